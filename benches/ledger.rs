@@ -0,0 +1,48 @@
+// a balance is looked up by the address of a key, a key hashes its encoding once when it is read
+// instead of on every lookup:
+// cargo bench --bench ledger
+
+use std::time::Instant;
+
+use lasagna::{account::AccountId, generate_keypair, ledger::Ledger, TRANSACTION_FEE};
+use rand::{thread_rng, Rng};
+use rsa::sha2::{Digest, Sha256};
+
+const ACCOUNTS: usize = 10_000;
+const KEYS: usize = 20;
+const LOOKUPS: usize = 10_000;
+
+fn main() {
+    let keys: Vec<_> = (0..KEYS).map(|_| generate_keypair().1).collect();
+    let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+    for key in &keys {
+        ledger.reward_winner(&AccountId::from(key), 1, 0).unwrap();
+    }
+    for _ in KEYS..ACCOUNTS {
+        ledger.reward_winner(&AccountId(thread_rng().gen()), 1, 0).unwrap();
+    }
+
+    let mut elapsed = Vec::new();
+    for interned in [false, true] {
+        let mut total = 0;
+        let start = Instant::now();
+        for key in keys.iter().cycle().take(LOOKUPS) {
+            let account = match interned {
+                false => AccountId(Sha256::digest(key.to_bytes()).into()),
+                true => AccountId::from(key),
+            };
+            total += ledger.get_balance(&account);
+        }
+        assert_eq!(total, LOOKUPS as u64);
+        let time = start.elapsed();
+        println!(
+            "{} lookups in a ledger of {} accounts, {}: {:?}",
+            LOOKUPS,
+            ACCOUNTS,
+            if interned { "address kept with the key" } else { "key hashed per lookup" },
+            time
+        );
+        elapsed.push(time);
+    }
+    println!("{:.0}x faster", elapsed[0].as_secs_f64() / elapsed[1].as_secs_f64());
+}
@@ -0,0 +1,50 @@
+// the lottery is drawn by every staker in every timeslot and checked again for every block:
+// cargo bench --bench lottery
+
+use std::time::Instant;
+
+use lasagna::difficulty::{self, initial_hardness};
+use num_bigint::BigUint;
+use rand::RngCore;
+
+const DRAWS: u32 = 100_000;
+
+// the lottery as it was written before, with the constants built on every call
+fn beats_as_written(draw_value: &BigUint, balance: u64, total_money: u64, hardness: &BigUint) -> bool {
+    let max_hash = BigUint::from(2u64).pow(256);
+    let balance = BigUint::from(balance);
+    let mult_factor = (hardness.clone() * total_money) + (balance * (max_hash.clone() - hardness.clone()));
+    draw_value * mult_factor > hardness * total_money * max_hash
+}
+
+fn main() {
+    let mut rng = rand::thread_rng();
+    let draws: Vec<_> = (0..DRAWS)
+        .map(|_| {
+            let mut bytes = [0; 32];
+            rng.fill_bytes(&mut bytes);
+            BigUint::from_bytes_be(&bytes)
+        })
+        .collect();
+    let hardness = initial_hardness();
+    let (balance, total_money) = (300, 3_000);
+
+    let start = Instant::now();
+    let old_wins = draws
+        .iter()
+        .filter(|draw| beats_as_written(draw, balance, total_money, &hardness))
+        .count();
+    let old = start.elapsed() / DRAWS;
+
+    let start = Instant::now();
+    let new_wins = draws
+        .iter()
+        .filter(|draw| difficulty::beats(draw, balance, total_money, &hardness))
+        .count();
+    let new = start.elapsed() / DRAWS;
+
+    assert_eq!(old_wins, new_wins);
+    println!("{DRAWS} draws, {new_wins} won");
+    println!("as written: {old:?} per draw");
+    println!("rearranged: {new:?} per draw");
+}
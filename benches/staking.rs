@@ -0,0 +1,46 @@
+// a producer checks whether it has won more often than once per timeslot,
+// the draw cache makes that one signature per timeslot instead of one per check:
+// cargo bench --bench staking
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use lasagna::{
+    blockchain::Blockchain, clock::ManualClock, draw::DrawCache, generate_keypair,
+    params::ChainParams,
+};
+
+const SLOTS: u64 = 20;
+const CHECKS_PER_SLOT: u64 = 10;
+
+fn main() {
+    let (sk, pk) = generate_keypair();
+    let clock = ManualClock::default();
+    let blockchain =
+        Blockchain::start_with_clock(vec![pk.clone()], ChainParams::default(), Arc::new(clock.clone()));
+
+    for cached in [false, true] {
+        let mut draws = DrawCache::default();
+        let mut signed = 0;
+        let start = Instant::now();
+        for _ in 0..SLOTS {
+            clock.advance_slots(1);
+            for _ in 0..CHECKS_PER_SLOT {
+                if !cached {
+                    signed += draws.signed();
+                    draws = DrawCache::default();
+                }
+                let draw = blockchain.get_draw(&sk, &mut draws);
+                blockchain.stake(&draw, &pk, 1);
+            }
+        }
+        signed += draws.signed();
+        let elapsed = start.elapsed() / (SLOTS * CHECKS_PER_SLOT) as u32;
+
+        let mode = if cached { "cached" } else { "uncached" };
+        println!(
+            "{mode}: {} signatures per timeslot, {elapsed:?} per check",
+            signed as f64 / SLOTS as f64
+        );
+    }
+}
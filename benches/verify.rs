@@ -0,0 +1,51 @@
+// compare with and without the parallel feature:
+// cargo bench --bench verify
+// cargo bench --bench verify --features parallel
+
+use std::time::Instant;
+
+use lasagna::{
+    block::{Block, BlockHash},
+    generate_keypair,
+    seeding_mechanism::SeedContent,
+    signatures::SignatureCache,
+    transaction::Transaction,
+    Timeslot,
+};
+
+const TRANSACTIONS: u64 = 500;
+const ROUNDS: u32 = 10;
+
+fn main() {
+    let (sk, pk) = generate_keypair();
+    let (_, receiver) = generate_keypair();
+    let transactions = (0..TRANSACTIONS)
+        .map(|amount| Transaction::new(pk.clone(), receiver.clone(), &sk, amount + 1, amount).unwrap())
+        .collect();
+    let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+    let block = Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk, transactions, [0; 32], &sk, seed);
+
+    // a cache that keeps nothing checks every signature
+    let uncached = SignatureCache::new(0);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert_eq!(block.verify_transactions(&uncached), Ok(()));
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+
+    let cache = SignatureCache::new(TRANSACTIONS as usize);
+    assert_eq!(block.verify_transactions(&cache), Ok(()));
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert_eq!(block.verify_transactions(&cache), Ok(()));
+    }
+    let cached = start.elapsed() / ROUNDS;
+
+    let mode = if cfg!(feature = "parallel") {
+        "parallel"
+    } else {
+        "serial"
+    };
+    println!("verifying {TRANSACTIONS} transaction signatures ({mode}): {elapsed:?}");
+    println!("verifying them again from the cache: {cached:?}");
+}
@@ -0,0 +1,18 @@
+#![no_main]
+
+use lasagna::block::{Block, BlockHeader};
+use libfuzzer_sys::fuzz_target;
+
+// a block that decodes has exactly one encoding, and the checks after decoding do not panic on it
+fuzz_target!(|data: &[u8]| {
+    if let Ok(block) = Block::from_bytes(data) {
+        assert_eq!(block.to_bytes(), data);
+        let header = BlockHeader::from_bytes(&block.header().to_bytes()).unwrap();
+        assert_eq!(header.hash, block.hash);
+        let _ = block.verify_signature();
+    }
+    if let Ok(header) = BlockHeader::from_bytes(data) {
+        assert_eq!(header.to_bytes(), data);
+    }
+    let _ = bincode::deserialize::<Block>(data);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use lasagna::draw::Draw;
+use libfuzzer_sys::fuzz_target;
+
+// a draw that decodes has exactly one encoding
+fuzz_target!(|data: &[u8]| {
+    if let Ok(draw) = Draw::from_bytes(data) {
+        assert_eq!(draw.to_bytes(), data);
+        let _ = draw.hash();
+    }
+    let _ = bincode::deserialize::<Draw>(data);
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use lasagna::transaction::{Transaction, UnsignedTransaction};
+use libfuzzer_sys::fuzz_target;
+
+// a transaction that decodes has exactly one encoding, and the checks after decoding do not panic on it
+fuzz_target!(|data: &[u8]| {
+    if let Ok(transaction) = Transaction::from_bytes(data) {
+        assert_eq!(transaction.to_bytes(), data);
+        let _ = transaction.verify_signature();
+        let _ = transaction.cost();
+    }
+    if let Ok(unsigned) = UnsignedTransaction::from_bytes(data) {
+        assert_eq!(unsigned.to_bytes(), data);
+    }
+    let _ = bincode::deserialize::<Transaction>(data);
+});
@@ -0,0 +1,88 @@
+// accounts are named by the hash of their public key, the key itself is only carried where a signature is checked
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{keys::PublicKey, Error};
+
+/// The SHA-256 of the encoding of a public key, written as hex
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AccountId(pub [u8; 32]);
+
+impl AccountId {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<&PublicKey> for AccountId {
+    fn from(key: &PublicKey) -> Self {
+        key.id()
+    }
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+// the full hash is noisy in debug output of ledgers, the address is what users see anyway
+impl fmt::Debug for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AccountId({self})")
+    }
+}
+
+impl FromStr for AccountId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Self)
+            .ok_or(Error::InvalidEncoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{thread_rng, Rng};
+    use rsa::sha2::{Digest, Sha256};
+
+    use super::*;
+
+    #[test]
+    fn addresses_round_trip_without_collisions() {
+        let mut rng = thread_rng();
+        let mut seen = HashSet::new();
+        for _ in 0..1_000 {
+            let id = AccountId(rng.gen());
+            let address = id.to_string();
+            assert_eq!(address.len(), 64);
+            assert_eq!(address.parse::<AccountId>(), Ok(id));
+            assert_eq!(address.to_uppercase().parse::<AccountId>(), Ok(id));
+            assert!(seen.insert(address));
+        }
+
+        let keys: Vec<_> = (0..3).map(|_| crate::generate_keypair().1).collect();
+        let ids: HashSet<_> = keys.iter().map(AccountId::from).collect();
+        assert_eq!(ids.len(), keys.len());
+        assert_eq!(AccountId::from(&keys[0]), AccountId::from(&keys[0].clone()));
+        // however the key was read, its address is the hash of its encoding
+        for key in keys {
+            let hashed = AccountId(Sha256::digest(key.to_bytes()).into());
+            assert_eq!(AccountId::from(&key), hashed);
+            assert_eq!(AccountId::from(&PublicKey::from_bytes(&key.to_bytes()).unwrap()), hashed);
+            assert_eq!(AccountId::from(&PublicKey::from_pem(&key.to_pem().unwrap()).unwrap()), hashed);
+        }
+
+        assert_eq!("".parse::<AccountId>(), Err(Error::InvalidEncoding));
+        assert_eq!("ab".parse::<AccountId>(), Err(Error::InvalidEncoding));
+        assert_eq!("zz".repeat(32).parse::<AccountId>(), Err(Error::InvalidEncoding));
+    }
+}
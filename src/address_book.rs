@@ -0,0 +1,199 @@
+// the nodes we have been connected to, kept in the data directory so a node that restarts can rejoin the
+// network through them when its seeds are gone. Operators can pass the addresses on as a plain list
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result, MAX_ADDRESS_BOOK};
+
+/// What we know of a node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub last_seen: u128, // when it was last our peer, 0 for an address that was imported
+    pub successes: u64, // dials that reached it
+    pub failures: u64, // dials that did not
+    pub banned_until: Option<u128>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressBook {
+    entries: BTreeMap<SocketAddr, AddressEntry>,
+}
+
+impl AddressBook {
+    /// The book written by `save`, an empty one if there is no file yet
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|_| Error::InvalidEncoding),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(_) => Err(Error::StorageError),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).expect("the entries are plain numbers");
+        std::fs::write(path, json).map_err(|_| Error::StorageError)
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddressEntry> {
+        self.entries.get(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The node is our peer now
+    pub fn seen(&mut self, addr: SocketAddr, now: u128) {
+        self.entry(addr).last_seen = now;
+    }
+
+    pub fn dialed(&mut self, addr: SocketAddr, now: u128) {
+        let entry = self.entry(addr);
+        entry.successes += 1;
+        entry.last_seen = now;
+    }
+
+    pub fn dial_failed(&mut self, addr: SocketAddr) {
+        self.entry(addr).failures += 1;
+    }
+
+    pub fn ban(&mut self, addr: SocketAddr, until: u128) {
+        self.entry(addr).banned_until = Some(until);
+    }
+
+    /// The nodes whose ban is still running, and until when
+    pub fn banned(&self, now: u128) -> Vec<(SocketAddr, u128)> {
+        self.entries
+            .iter()
+            .filter_map(|(addr, entry)| entry.banned_until.filter(|until| *until > now).map(|until| (*addr, until)))
+            .collect()
+    }
+
+    /// The nodes that are not banned, the most recently seen first and those that failed more often last on a tie
+    pub fn dialable(&self, now: u128) -> Vec<SocketAddr> {
+        let mut dialable: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.banned_until.is_none_or(|until| until <= now))
+            .collect();
+        dialable.sort_by_key(|(_, entry)| (std::cmp::Reverse(entry.last_seen), entry.failures));
+        dialable.into_iter().map(|(addr, _)| *addr).collect()
+    }
+
+    /// The addresses we would dial, one per line
+    pub fn export(&self, now: u128) -> String {
+        self.dialable(now).iter().map(|addr| format!("{addr}\n")).collect()
+    }
+
+    /// Adds the addresses of a list written by `export`, blank lines and lines starting with # are skipped.
+    /// It returns how many addresses were new, nothing is added if a line is not an address
+    pub fn import(&mut self, list: &str) -> Result<usize> {
+        let addrs = parse_list(list)?;
+        Ok(self.add(addrs))
+    }
+
+    /// Adds the addresses we did not know and returns how many there were
+    pub fn add(&mut self, addrs: impl IntoIterator<Item = SocketAddr>) -> usize {
+        let mut added = 0;
+        for addr in addrs {
+            if !self.entries.contains_key(&addr) {
+                self.entry(addr);
+                added += 1;
+            }
+        }
+        added
+    }
+
+    // the nodes seen longest ago are forgotten first, a running ban is kept
+    fn entry(&mut self, addr: SocketAddr) -> &mut AddressEntry {
+        if !self.entries.contains_key(&addr) && self.entries.len() >= MAX_ADDRESS_BOOK {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.banned_until.is_none())
+                .min_by_key(|(_, entry)| entry.last_seen)
+                .map(|(addr, _)| *addr);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.entry(addr).or_default()
+    }
+}
+
+/// The addresses of a list written by `AddressBook::export`
+pub fn parse_list(list: &str) -> Result<Vec<SocketAddr>> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse().map_err(|_| Error::InvalidEncoding))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn the_book_survives_a_round_trip_through_its_file() {
+        let path = std::env::temp_dir().join(format!("lasagna-address-book-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(AddressBook::load(&path).unwrap(), AddressBook::default());
+
+        let mut book = AddressBook::default();
+        book.dialed(addr(1), 10);
+        book.dial_failed(addr(2));
+        book.ban(addr(3), 1_000);
+        book.save(&path).unwrap();
+        let loaded = AddressBook::load(&path).unwrap();
+        assert_eq!(loaded, book);
+        assert_eq!(loaded.get(&addr(1)).unwrap().successes, 1);
+        assert_eq!(loaded.banned(500), vec![(addr(3), 1_000)]);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(AddressBook::load(&path), Err(Error::InvalidEncoding));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn recently_seen_nodes_are_dialed_first_and_banned_ones_not_at_all() {
+        let mut book = AddressBook::default();
+        book.seen(addr(1), 10);
+        book.seen(addr(2), 20);
+        book.add([addr(3)]);
+        book.dial_failed(addr(3));
+        book.add([addr(4)]);
+        book.ban(addr(2), 100);
+        assert_eq!(book.dialable(50), vec![addr(1), addr(4), addr(3)]);
+        // the ban is over
+        assert_eq!(book.dialable(100), vec![addr(2), addr(1), addr(4), addr(3)]);
+    }
+
+    #[test]
+    fn an_exported_list_imports_into_another_book() {
+        let mut book = AddressBook::default();
+        book.seen(addr(1), 10);
+        book.seen(addr(2), 20);
+        let list = book.export(0);
+        assert_eq!(list, "127.0.0.1:2\n127.0.0.1:1\n");
+
+        let mut other = AddressBook::default();
+        other.seen(addr(1), 5);
+        assert_eq!(other.import(&format!("# from a friend\n\n{list}")).unwrap(), 1);
+        assert_eq!(other.dialable(0), vec![addr(1), addr(2)]);
+        assert_eq!(other.import("127.0.0.1:3\nnot an address\n"), Err(Error::InvalidEncoding));
+        assert_eq!(other.len(), 2);
+    }
+}
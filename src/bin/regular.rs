@@ -1,12 +1,250 @@
-use lasagna::{client::ClientActor, ADDR, SEED_ADDR, WALLETS};
+use lasagna::{block_producer::ProductionPolicy, client::BLOCK_LOG_FILE, config::{self, ConfigFile}, keystore, node::NodeBuilder, peer_scores::PeerLimits, store::BlockLog, ClientMessage, ADDR, SEED_ADDR, WALLETS};
+
+use clap::Parser;
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Read the settings from this TOML file, the flags win over it
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+    /// What to log, a level like debug or a filter like lasagna::net=debug, it wins over RUST_LOG
+    #[arg(long)]
+    log_level: Option<String>,
+    /// The address to listen on, it is asked for if it is not given here or in the config file
+    #[arg(long)]
+    listen: Option<std::net::SocketAddr>,
+    /// Serve JSON-RPC on this address
+    #[cfg(feature = "rpc")]
+    #[arg(long)]
+    rpc: Option<std::net::SocketAddr>,
+    /// Serve subscriptions to new blocks, reorgs and the payments of accounts on ws://<address>
+    #[cfg(feature = "rpc")]
+    #[arg(long)]
+    ws: Option<std::net::SocketAddr>,
+    /// Serve Prometheus metrics on http://<address>/metrics
+    #[arg(long)]
+    metrics: Option<std::net::SocketAddr>,
+    /// The seed nodes to join through, comma separated and tried in order, the seed is asked for if none are given
+    #[arg(long, value_delimiter = ',')]
+    seeds: Vec<std::net::SocketAddr>,
+    /// Keep the blockchain and the address book here between runs
+    #[arg(long)]
+    data_dir: Option<std::path::PathBuf>,
+    /// Rebuild the chain in the data directory from its blocks alone before starting, when its ledger is suspect
+    #[arg(long)]
+    reindex: bool,
+    /// Read the private key from this file instead of deriving it from a seed phrase
+    #[arg(long)]
+    key_file: Option<std::path::PathBuf>,
+    /// The environment variable holding the passphrase of the key file, it is asked for if this is not given
+    #[arg(long)]
+    passphrase_env: Option<String>,
+    /// Stake with the accounts of these key files too, comma separated, they have the passphrase of the key file
+    #[arg(long, value_delimiter = ',')]
+    staking_key_files: Vec<std::path::PathBuf>,
+    /// Only produce blocks with transactions, unless the tip is max-empty-gap timeslots old
+    #[arg(long)]
+    skip_empty_blocks: bool,
+    /// The timeslots since the tip after which an empty block is produced anyway
+    #[arg(long, default_value_t = lasagna::MAX_EMPTY_GAP)]
+    max_empty_gap: u64,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print a config file with the default settings
+    PrintDefaultConfig,
+    /// Ask a running node for its status through its JSON-RPC endpoint
+    #[cfg(feature = "rpc")]
+    Status {
+        #[arg(long)]
+        rpc_addr: std::net::SocketAddr,
+    },
+    /// Pass the address book of a running node in or out as an address per line through its JSON-RPC endpoint
+    #[cfg(feature = "rpc")]
+    Peers {
+        #[command(subcommand)]
+        command: PeersCommand,
+    },
+}
+
+#[cfg(feature = "rpc")]
+#[derive(clap::Subcommand)]
+enum PeersCommand {
+    /// Print the address book
+    Export {
+        #[arg(long)]
+        rpc_addr: std::net::SocketAddr,
+    },
+    /// Add the addresses in the file to the address book
+    Import {
+        file: std::path::PathBuf,
+        #[arg(long)]
+        rpc_addr: std::net::SocketAddr,
+    },
+}
 
 #[tokio::main]
 async fn main() {
-    let _ = *ADDR;
-    let _ = *SEED_ADDR;
+    let args = Args::parse();
+    if let Err(e) = lasagna::logging::init(args.log_level.as_deref()) {
+        eprintln!("{e}");
+        std::process::exit(1)
+    }
+    match args.command {
+        Some(Command::PrintDefaultConfig) => {
+            print!("{}", config::sample());
+            return;
+        }
+        #[cfg(feature = "rpc")]
+        Some(Command::Status { rpc_addr }) => {
+            match lasagna::rpc::call(rpc_addr, "get_status", serde_json::json!([])).await {
+                Ok(Ok(status)) => print_status(&status),
+                Ok(Err(e)) => panic!("the node refused the request: {}", e.message),
+                Err(e) => panic!("unable to reach the node: {e}"),
+            }
+            return;
+        }
+        #[cfg(feature = "rpc")]
+        Some(Command::Peers { command }) => {
+            let result = match command {
+                PeersCommand::Export { rpc_addr } => {
+                    lasagna::rpc::call(rpc_addr, "export_peers", serde_json::json!([])).await
+                }
+                PeersCommand::Import { file, rpc_addr } => {
+                    let list = std::fs::read_to_string(&file)
+                        .unwrap_or_else(|e| panic!("unable to read {file:?}: {e}"));
+                    lasagna::rpc::call(rpc_addr, "import_peers", serde_json::json!([list])).await
+                }
+            };
+            match result {
+                Ok(Ok(serde_json::Value::String(list))) => print!("{list}"),
+                Ok(Ok(added)) => println!("Added {added} peers"),
+                Ok(Err(e)) => panic!("the node refused the request: {}", e.message),
+                Err(e) => panic!("unable to reach the node: {e}"),
+            }
+            return;
+        }
+        None => (),
+    }
+    let file = match args.config.as_deref() {
+        Some(path) => ConfigFile::load(path).unwrap_or_else(|e| {
+            eprintln!("{path:?}: {e}");
+            std::process::exit(1)
+        }),
+        None => ConfigFile::default(),
+    };
+    let key = args.key_file.or(file.key_file).map(|path| {
+        keystore::load(&path, || keystore::read_passphrase(args.passphrase_env.as_deref()))
+            .unwrap_or_else(|e| panic!("unable to read {path:?}: {e}"))
+    });
+    let staking_key_files = match args.staking_key_files.is_empty() {
+        true => file.staking_key_files,
+        false => args.staking_key_files,
+    };
+    let staking_keys: Vec<_> = staking_key_files
+        .iter()
+        .map(|path| {
+            keystore::load(path, || keystore::read_passphrase(args.passphrase_env.as_deref()))
+                .unwrap_or_else(|e| panic!("unable to read {path:?}: {e}"))
+        })
+        .collect();
+
+    let addr = args.listen.or(file.listen).unwrap_or_else(|| *ADDR);
+    let seeds = match (args.seeds.is_empty(), file.seeds.is_empty()) {
+        (false, _) => args.seeds,
+        (true, false) => file.seeds,
+        (true, true) => vec![*SEED_ADDR],
+    };
     let _ = *WALLETS;
 
-    ClientActor::run(*SEED_ADDR, *ADDR).await;
+    let key = match key {
+        Some(key) => key,
+        None => lasagna::cli::read_seedphrase_key().await,
+    };
+
+    let mut node = NodeBuilder::join_any(addr, seeds, key)
+        .production(ProductionPolicy {
+            produce_empty_blocks: !args.skip_empty_blocks,
+            max_empty_gap: args.max_empty_gap,
+        })
+        .buffer_limits(file.buffers)
+        .limits(PeerLimits {
+            upload_bytes_per_sec: file.upload_bytes_per_sec,
+            ..Default::default()
+        })
+        .staking_keys(staking_keys)
+        .relay_policy(file.policy)
+        .cli();
+    if let Some(depth) = file.max_reorg_depth {
+        node = node.max_reorg_depth(depth);
+    }
+    if let Some(depth) = file.prune_depth {
+        node = node.prune_depth(depth);
+    }
+    if let Some(dir) = args.data_dir.or(file.data_dir) {
+        let mut store = BlockLog::new(dir.join(BLOCK_LOG_FILE));
+        if args.reindex {
+            match store.reindex() {
+                Ok(Some(blockchain)) => println!("Reindexed the chain up to depth {}", blockchain.tip().depth),
+                Ok(None) => println!("There is no chain to reindex in {dir:?}"),
+                Err(e) => panic!("{e}"),
+            }
+        }
+        node = node.store(store).data_dir(dir);
+    }
+    #[cfg(feature = "rpc")]
+    if let Some(addr) = args.rpc.or(file.rpc) {
+        node = node.rpc(addr);
+    }
+    #[cfg(feature = "rpc")]
+    if let Some(addr) = args.ws.or(file.ws) {
+        node = node.ws(addr);
+    }
+    if let Some(addr) = args.metrics.or(file.metrics) {
+        node = node.metrics(addr);
+    }
+    let node = node.start().await.expect("unable to start the node");
+
+    // the progress of catching up with the network
+    let client_tx = node.sender().clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            let (reply, metrics) = tokio::sync::oneshot::channel();
+            if client_tx.send(ClientMessage::Metrics(reply)).await.is_err() {
+                break;
+            }
+            match metrics.await.ok().and_then(|metrics| metrics.sync) {
+                Some(progress) if progress.is_done() => break,
+                Some(progress) => println!("Syncing {:.0}%", progress.percent()),
+                None => (),
+            }
+        }
+    });
+
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl+C");
+    println!("Shutting down");
+    node.shutdown().await;
+}
 
-    tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+#[cfg(feature = "rpc")]
+fn print_status(status: &serde_json::Value) {
+    let uptime = status["uptime_secs"].as_u64().unwrap_or_default();
+    println!("Uptime:    {}h {}m {}s", uptime / 3600, uptime / 60 % 60, uptime % 60);
+    println!("Tip:       {} at depth {}", status["best_hash"].as_str().unwrap_or_default(), status["best_depth"]);
+    println!("Timeslot:  {}", status["timeslot"]);
+    match status["sync"]["progress"]["percent"].as_f64() {
+        Some(percent) if status["sync"]["state"] == "syncing" => println!("Sync:      syncing {percent:.0}%"),
+        _ => println!("Sync:      {}", status["sync"]["state"].as_str().unwrap_or_default()),
+    }
+    println!("Peers:     {}", status["peers"]);
+    println!("Mempool:   {} transactions", status["mempool_size"]);
+    println!("Orphans:   {} blocks", status["orphans"]);
+    let staking = if status["staking"] == true { "yes" } else { "no" };
+    println!("Staking:   {staking}, as {}", status["staking_address"].as_str().unwrap_or_default());
 }
@@ -1,24 +1,228 @@
-use lasagna::{client::ClientActor, ADDR, ROOTS, WALLETS};
-use rsa::{pkcs8::DecodePublicKey, RsaPublicKey};
+use lasagna::{block_producer::ProductionPolicy, client::BLOCK_LOG_FILE, config::{self, ConfigFile}, keystore, node::NodeBuilder, params::ChainParams, peer_scores::PeerLimits, store::BlockLog, ADDR, ROOTS, WALLETS};
+use lasagna::{genesis::GenesisManifest, keys};
+
+use clap::Parser;
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Read the settings from this TOML file, the flags win over it
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+    /// What to log, a level like debug or a filter like lasagna::net=debug, it wins over RUST_LOG
+    #[arg(long)]
+    log_level: Option<String>,
+    /// The address to listen on, it is asked for if it is not given here or in the config file
+    #[arg(long)]
+    listen: Option<std::net::SocketAddr>,
+    /// Serve JSON-RPC on this address
+    #[cfg(feature = "rpc")]
+    #[arg(long)]
+    rpc: Option<std::net::SocketAddr>,
+    /// Serve subscriptions to new blocks, reorgs and the payments of accounts on ws://<address>
+    #[cfg(feature = "rpc")]
+    #[arg(long)]
+    ws: Option<std::net::SocketAddr>,
+    /// Serve Prometheus metrics on http://<address>/metrics
+    #[arg(long)]
+    metrics: Option<std::net::SocketAddr>,
+    /// Keep the blockchain and the address book here between runs
+    #[arg(long)]
+    data_dir: Option<std::path::PathBuf>,
+    /// Rebuild the chain in the data directory from its blocks alone before starting, when its ledger is suspect
+    #[arg(long)]
+    reindex: bool,
+    /// Start the network of this manifest instead of reading the root accounts from the roots folder
+    #[arg(long)]
+    genesis: Option<std::path::PathBuf>,
+    /// Read the private key from this file instead of deriving it from a seed phrase
+    #[arg(long)]
+    key_file: Option<std::path::PathBuf>,
+    /// The environment variable holding the passphrase of the key file, it is asked for if this is not given
+    #[arg(long)]
+    passphrase_env: Option<String>,
+    /// Stake with the accounts of these key files too, comma separated, they have the passphrase of the key file
+    #[arg(long, value_delimiter = ',')]
+    staking_key_files: Vec<std::path::PathBuf>,
+    /// Only produce blocks with transactions, unless the tip is max-empty-gap timeslots old
+    #[arg(long)]
+    skip_empty_blocks: bool,
+    /// The timeslots since the tip after which an empty block is produced anyway
+    #[arg(long, default_value_t = lasagna::MAX_EMPTY_GAP)]
+    max_empty_gap: u64,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print a config file with the default settings
+    PrintDefaultConfig,
+    /// Print the hash of the genesis block of a manifest, every party computes the same one
+    Genesis {
+        manifest: std::path::PathBuf,
+        /// Write the genesis block in its canonical encoding to this file
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    /// Run a dev chain of a single node for testing applications, every draw wins and blocks are mined on demand
+    Dev {
+        /// The chain and the funded dev key are kept here
+        #[arg(long, default_value = "dev")]
+        data_dir: std::path::PathBuf,
+        #[arg(long, default_value = "127.0.0.1:9600")]
+        listen: std::net::SocketAddr,
+    },
+}
 
 #[tokio::main]
 async fn main() {
-    let _ = *ADDR;
+    let args = Args::parse();
+    if let Err(e) = lasagna::logging::init(args.log_level.as_deref()) {
+        eprintln!("{e}");
+        std::process::exit(1)
+    }
+    match args.command {
+        Some(Command::PrintDefaultConfig) => {
+            print!("{}", config::sample());
+            return;
+        }
+        Some(Command::Genesis { manifest, out }) => {
+            let genesis = GenesisManifest::load(&manifest)
+                .and_then(|manifest| manifest.genesis())
+                .unwrap_or_else(|e| {
+                    eprintln!("{manifest:?}: {e}");
+                    std::process::exit(1)
+                });
+            if let Some(out) = out {
+                std::fs::write(&out, genesis.block.to_bytes())
+                    .unwrap_or_else(|e| panic!("unable to write {out:?}: {e}"));
+            }
+            println!("{}", hex::encode(genesis.block.hash));
+            return;
+        }
+        Some(Command::Dev { data_dir, listen }) => {
+            let node = NodeBuilder::dev(listen, &data_dir)
+                .unwrap_or_else(|e| panic!("unable to start a dev node in {data_dir:?}: {e}"));
+            println!("The funded dev key is in {:?}", data_dir.join(lasagna::client::DEV_KEY_FILE));
+            #[cfg(feature = "rpc")]
+            let node = match args.rpc {
+                Some(addr) => node.rpc(addr),
+                None => node,
+            };
+            let node = node.start().await.expect("unable to start the node");
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to listen for Ctrl+C");
+            println!("Shutting down");
+            node.shutdown().await;
+            return;
+        }
+        None => (),
+    }
+    let file = match args.config.as_deref() {
+        Some(path) => ConfigFile::load(path).unwrap_or_else(|e| {
+            eprintln!("{path:?}: {e}");
+            std::process::exit(1)
+        }),
+        None => ConfigFile::default(),
+    };
+    let key = args.key_file.or(file.key_file).map(|path| {
+        keystore::load(&path, || keystore::read_passphrase(args.passphrase_env.as_deref()))
+            .unwrap_or_else(|e| panic!("unable to read {path:?}: {e}"))
+    });
+    let staking_key_files = match args.staking_key_files.is_empty() {
+        true => file.staking_key_files,
+        false => args.staking_key_files,
+    };
+    let staking_keys: Vec<_> = staking_key_files
+        .iter()
+        .map(|path| {
+            keystore::load(path, || keystore::read_passphrase(args.passphrase_env.as_deref()))
+                .unwrap_or_else(|e| panic!("unable to read {path:?}: {e}"))
+        })
+        .collect();
+
+    let addr = args.listen.or(file.listen).unwrap_or_else(|| *ADDR);
     let _ = *WALLETS;
     let _ = *ROOTS;
 
+    let genesis = args.genesis.map(|path| {
+        GenesisManifest::load(&path)
+            .and_then(|manifest| manifest.genesis())
+            .unwrap_or_else(|e| panic!("unable to read the manifest {path:?}: {e}"))
+    });
+
     // read the root accounts files in the path_to_root_accounts
-    let dir = std::fs::read_dir(ROOTS.clone()).unwrap();
     let mut root_accounts = Vec::new();
+    let dir = match genesis {
+        Some(_) => Vec::new(),
+        None => std::fs::read_dir(ROOTS.clone()).unwrap().collect(),
+    };
     for entry in dir {
         let entry = entry.unwrap();
         let path = entry.path();
-        let pem = std::fs::read_to_string(path).unwrap();
-        let public_key = RsaPublicKey::from_public_key_pem(&pem).unwrap();
+        // any encoding openssl writes is read, see `keys::import_public`
+        let bytes = std::fs::read(&path).unwrap();
+        let public_key = keys::import_public(&bytes)
+            .unwrap_or_else(|e| panic!("unable to read the root account {path:?}: {e}"));
         root_accounts.push(public_key);
     }
 
-    ClientActor::run_root(*ADDR, root_accounts).await;
+    let key = match key {
+        Some(key) => key,
+        None => lasagna::cli::read_seedphrase_key().await,
+    };
+
+    let mut node = NodeBuilder::root(addr, root_accounts, key)
+        .params(file.params.apply(ChainParams::default()))
+        .production(ProductionPolicy {
+            produce_empty_blocks: !args.skip_empty_blocks,
+            max_empty_gap: args.max_empty_gap,
+        })
+        .buffer_limits(file.buffers)
+        .limits(PeerLimits {
+            upload_bytes_per_sec: file.upload_bytes_per_sec,
+            ..Default::default()
+        })
+        .staking_keys(staking_keys)
+        .relay_policy(file.policy)
+        .cli();
+    if let Some(depth) = file.max_reorg_depth {
+        node = node.max_reorg_depth(depth);
+    }
+    if let Some(depth) = file.prune_depth {
+        node = node.prune_depth(depth);
+    }
+    if let Some(genesis) = genesis {
+        node = node.genesis(genesis);
+    }
+    if let Some(dir) = args.data_dir.or(file.data_dir) {
+        let mut store = BlockLog::new(dir.join(BLOCK_LOG_FILE));
+        if args.reindex {
+            match store.reindex() {
+                Ok(Some(blockchain)) => println!("Reindexed the chain up to depth {}", blockchain.tip().depth),
+                Ok(None) => println!("There is no chain to reindex in {dir:?}"),
+                Err(e) => panic!("{e}"),
+            }
+        }
+        node = node.store(store).data_dir(dir);
+    }
+    #[cfg(feature = "rpc")]
+    if let Some(addr) = args.rpc.or(file.rpc) {
+        node = node.rpc(addr);
+    }
+    #[cfg(feature = "rpc")]
+    if let Some(addr) = args.ws.or(file.ws) {
+        node = node.ws(addr);
+    }
+    if let Some(addr) = args.metrics.or(file.metrics) {
+        node = node.metrics(addr);
+    }
+    let node = node.start().await.expect("unable to start the node");
 
-    tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl+C");
+    println!("Shutting down");
+    node.shutdown().await;
 }
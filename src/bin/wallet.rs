@@ -0,0 +1,283 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use lasagna::{
+    account::AccountId,
+    generate_keypair_with_progress,
+    keys::{self, KeyFormat},
+    keystore,
+    transaction::{Transaction, UnsignedTransaction},
+    wallet::{self, WalletClient},
+    Timeslot, TRANSACTION_FEE,
+};
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+#[derive(Parser)]
+#[command(about = "Hold a key and send las without running a staking node")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a keypair, written to <NAME>.pem (public) and <NAME>.key.pem (private, encrypted with a passphrase)
+    Keygen {
+        name: String,
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        /// The environment variable holding the passphrase, it is asked for if this is not given
+        #[arg(long)]
+        passphrase_env: Option<String>,
+        /// Write the private key without encrypting it
+        #[arg(long, conflicts_with = "passphrase_env")]
+        plaintext: bool,
+        /// The size of the RSA key, at least 1024 and it must not be below the min_key_bits of the chain
+        #[arg(long, default_value_t = keys::DEFAULT_KEY_BITS)]
+        bits: usize,
+    },
+    /// Print the address of a key
+    Address { key: PathBuf },
+    /// Write a key in another encoding for other tools, any key file openssl writes is read
+    ExportKey {
+        key: PathBuf,
+        /// pkcs8-pem, pkcs8-der, pkcs1-pem or pkcs1-der, PKCS#8 stands for SPKI for a public key
+        #[arg(long)]
+        format: KeyFormat,
+        #[arg(long)]
+        out: PathBuf,
+        /// Write the private key unencrypted instead of the public key
+        #[arg(long)]
+        private: bool,
+        #[arg(long)]
+        passphrase_env: Option<String>,
+    },
+    /// Ask a node for the balance of an address or a public key
+    Balance {
+        account: String,
+        #[arg(long)]
+        node: SocketAddr,
+    },
+    /// Sign a transaction and submit it to a node
+    Send {
+        /// Public key pem of a receiver, repeat it with --amount to pay several in one transaction
+        #[arg(long, required = true)]
+        to: Vec<PathBuf>,
+        /// What the receiver in the same position is paid
+        #[arg(long, required = true)]
+        amount: Vec<u64>,
+        /// Transactions paying more per byte are included first
+        #[arg(long, default_value_t = TRANSACTION_FEE)]
+        fee: u64,
+        /// The number of transactions the sender has made before this one, see the get_nonce RPC,
+        /// or the nonce of a pending transaction to replace it with a higher fee
+        #[arg(long)]
+        nonce: u64,
+        /// The last timeslot in which the transaction may be included, it never expires without this
+        #[arg(long)]
+        valid_until: Option<Timeslot>,
+        /// Private key pem of the sender
+        #[arg(long)]
+        key: PathBuf,
+        /// The environment variable holding the passphrase of the key, it is asked for if the key is encrypted
+        #[arg(long)]
+        passphrase_env: Option<String>,
+        #[arg(long)]
+        node: SocketAddr,
+    },
+    /// Build a transaction without signing it, to be signed with sign-tx on the machine that holds the key
+    BuildTx {
+        /// Public key pem of the sender
+        #[arg(long)]
+        from: PathBuf,
+        /// Public key pem of a receiver, repeat it with --amount to pay several in one transaction
+        #[arg(long, required = true)]
+        to: Vec<PathBuf>,
+        /// What the receiver in the same position is paid
+        #[arg(long, required = true)]
+        amount: Vec<u64>,
+        #[arg(long, default_value_t = TRANSACTION_FEE)]
+        fee: u64,
+        #[arg(long)]
+        nonce: u64,
+        #[arg(long)]
+        valid_until: Option<Timeslot>,
+        /// Print base64 instead of hex
+        #[arg(long)]
+        base64: bool,
+    },
+    /// Sign a transaction built with build-tx, it needs no connection to a node
+    SignTx {
+        /// The unsigned transaction in hex, or base64 with --base64
+        transaction: String,
+        /// Private key pem of the sender
+        #[arg(long)]
+        key_file: PathBuf,
+        #[arg(long)]
+        passphrase_env: Option<String>,
+        /// Read and print base64 instead of hex
+        #[arg(long)]
+        base64: bool,
+    },
+    /// Submit a transaction signed with sign-tx to the JSON-RPC endpoint of a node
+    #[cfg(feature = "rpc")]
+    BroadcastTx {
+        /// The signed transaction in hex, or base64 with --base64
+        transaction: String,
+        #[arg(long)]
+        rpc: SocketAddr,
+        #[arg(long)]
+        base64: bool,
+    },
+}
+
+// the receivers paired with what they are paid
+fn read_outputs(to: &[PathBuf], amount: Vec<u64>) -> Vec<(lasagna::keys::PublicKey, u64)> {
+    if to.len() != amount.len() {
+        panic!("every --to needs an --amount");
+    }
+    to.iter()
+        .map(|to| wallet::read_public_key(to).expect("unable to read receiver key"))
+        .zip(amount)
+        .collect()
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Keygen {
+            name,
+            dir,
+            passphrase_env,
+            plaintext,
+            bits,
+        } => {
+            let passphrase = (!plaintext).then(|| {
+                keystore::read_passphrase(passphrase_env.as_deref())
+                    .unwrap_or_else(|e| panic!("unable to read the passphrase: {e}"))
+            });
+            let (sk, pk) = generate_keypair_with_progress(bits, move |attempts| {
+                let spinner = SPINNER[attempts as usize % SPINNER.len()];
+                eprint!("\r{spinner} generating a {bits} bit key, {attempts} attempts");
+            })
+            .await
+            .unwrap_or_else(|e| panic!("unable to generate the key: {e}"));
+            eprintln!();
+            wallet::write_keypair(&dir, &name, &sk, passphrase.as_deref().map(String::as_str))
+                .unwrap_or_else(|e| panic!("unable to write keypair: {e}"));
+            println!("{}", AccountId::from(&pk));
+        }
+        Command::Address { key } => {
+            let pk = wallet::read_public_key(&key).unwrap_or_else(|e| panic!("unable to read {key:?}: {e}"));
+            println!("{}", AccountId::from(&pk));
+        }
+        Command::ExportKey {
+            key,
+            format,
+            out,
+            private,
+            passphrase_env,
+        } => {
+            let written = match private {
+                true => wallet::read_private_key(&key, passphrase_env.as_deref())
+                    .and_then(|sk| keystore::save_as(&out, &sk, format)),
+                false => wallet::read_public_key(&key)
+                    .and_then(|pk| keys::export_public(&pk, format))
+                    .and_then(|bytes| std::fs::write(&out, bytes).map_err(|_| lasagna::Error::KeyFileError)),
+            };
+            written.unwrap_or_else(|e| panic!("unable to export {key:?}: {e}"));
+            println!("{format} written to {out:?}");
+        }
+        Command::Balance { account, node } => {
+            let account = wallet::read_account(&account).expect("unable to read account");
+            let mut client = WalletClient::connect(node)
+                .await
+                .expect("unable to connect to node");
+            let balance = client.get_balance(account).await.expect("no balance received");
+            println!("{balance} las");
+        }
+        Command::Send {
+            to,
+            amount,
+            fee,
+            nonce,
+            valid_until,
+            key,
+            passphrase_env,
+            node,
+        } => {
+            let sk = wallet::read_private_key(&key, passphrase_env.as_deref())
+                .unwrap_or_else(|e| panic!("unable to read {key:?}: {e}"));
+            let outputs = read_outputs(&to, amount);
+            let valid_until = valid_until.unwrap_or(Timeslot::MAX);
+            let transaction =
+                Transaction::with_outputs(sk.to_public_key(), outputs, &sk, fee, nonce, valid_until)
+                    .unwrap_or_else(|e| panic!("unable to sign the transaction: {e:?}"));
+            let mut client = WalletClient::connect(node)
+                .await
+                .expect("unable to connect to node");
+            client
+                .submit_transaction(transaction)
+                .await
+                .expect("unable to submit transaction");
+            println!("Transaction submitted with nonce {nonce}, it is processed when it is included in a new block");
+        }
+        Command::BuildTx {
+            from,
+            to,
+            amount,
+            fee,
+            nonce,
+            valid_until,
+            base64,
+        } => {
+            let from = wallet::read_public_key(&from).expect("unable to read sender key");
+            let outputs = read_outputs(&to, amount);
+            let unsigned = UnsignedTransaction::new(from, outputs, fee, nonce, valid_until.unwrap_or(Timeslot::MAX))
+                .unwrap_or_else(|e| panic!("unable to build the transaction: {e:?}"));
+            println!("{}", if base64 { unsigned.to_base64() } else { unsigned.to_hex() });
+        }
+        Command::SignTx {
+            transaction,
+            key_file,
+            passphrase_env,
+            base64,
+        } => {
+            let unsigned = match base64 {
+                true => UnsignedTransaction::from_base64(&transaction),
+                false => UnsignedTransaction::from_hex(&transaction),
+            }
+            .unwrap_or_else(|e| panic!("unable to read the unsigned transaction: {e}"));
+            let sk = wallet::read_private_key(&key_file, passphrase_env.as_deref())
+                .unwrap_or_else(|e| panic!("unable to read {key_file:?}: {e}"));
+            let signed = unsigned
+                .sign(&sk)
+                .unwrap_or_else(|e| panic!("unable to sign the transaction: {e}"));
+            println!("{}", if base64 { signed.to_base64() } else { signed.to_hex() });
+        }
+        #[cfg(feature = "rpc")]
+        Command::BroadcastTx {
+            transaction,
+            rpc,
+            base64,
+        } => {
+            let signed = match base64 {
+                true => Transaction::from_base64(&transaction),
+                false => Transaction::from_hex(&transaction),
+            }
+            .unwrap_or_else(|e| panic!("unable to read the signed transaction: {e}"));
+            let params = serde_json::json!([signed.to_hex()]);
+            match lasagna::rpc::call(rpc, "send_raw_transaction", params).await {
+                Ok(Ok(hash)) => println!("Transaction {} submitted", hash.as_str().unwrap_or_default()),
+                Ok(Err(e)) => match e.transaction_error() {
+                    Some(reason) => panic!("the node refused the transaction: {}", wallet::explain(&reason)),
+                    None => panic!("the node refused the transaction: {}", e.message),
+                },
+                Err(e) => panic!("unable to reach the node: {e}"),
+            }
+        }
+    }
+}
@@ -1,151 +1,664 @@
+use std::cmp::Ordering;
 use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
 
+use crate::account::AccountId;
+use crate::encoding::{serde_via_bytes, Reader, Writer};
+use crate::merkle::{self, MerkleProof};
 use crate::seeding_mechanism::SeedContent;
-use crate::PssSignature;
-use crate::{draw::Draw, transaction::Transaction, Timeslot};
-use rsa::RsaPrivateKey;
-use rsa::{
-    pkcs1::EncodeRsaPublicKey,
-    sha2::{Digest, Sha256},
-    RsaPublicKey,
+use crate::keys::{PublicKey, SecretKey, Signature};
+use crate::signatures::{SigError, SignatureCache, SIGNATURES};
+use crate::{
+    draw::Draw,
+    transaction::{Transaction, TxId, MIN_TRANSACTION_BYTES},
+    Error, Result, Timeslot,
 };
+use rsa::sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Block {
+/// The SHA-256 of the signed fields of a block header, it names the block
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The full hash, `Display` only shows its start
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl From<[u8; 32]> for BlockHash {
+    fn from(hash: [u8; 32]) -> Self {
+        Self(hash)
+    }
+}
+
+impl AsRef<[u8]> for BlockHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// the first 4 bytes tell the blocks of a node apart when reading logs and dumps
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0[..4]))
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlockHash({})", self.to_hex())
+    }
+}
+
+impl FromStr for BlockHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        hex::decode(s)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Self)
+            .ok_or(Error::InvalidEncoding)
+    }
+}
+
+/// The part of a block that is signed, it commits to the transactions through their merkle root
+/// and to the ledger after the block through its state root
+#[derive(Debug, Clone)]
+pub struct BlockHeader {
     pub(super) timeslot: Timeslot,
-    pub prev_hash: [u8; 32],
+    pub prev_hash: BlockHash,
     pub(super) depth: u64,
-    pub(super) transactions: Vec<Transaction>,
+    pub(super) transaction_count: u64,
+    pub(super) merkle_root: [u8; 32],
+    pub(super) state_root: [u8; 32],
     pub(super) draw: Draw,
-    pub(super) signature: PssSignature,
-    pub hash: [u8; 32],
+    pub(super) signature: Signature,
+    pub hash: BlockHash,
+}
+
+impl BlockHeader {
+    pub fn verify_signature(&self) -> bool {
+        self.hash_matches() && self.signed_by_winner()
+    }
+
+    /// Whether the hash is of the fields of the header, whoever signed it
+    pub fn hash_matches(&self) -> bool {
+        let hash = Self::hash_fields(
+            self.timeslot,
+            &self.prev_hash,
+            self.depth,
+            &self.draw,
+            self.transaction_count,
+            &self.merkle_root,
+            &self.state_root,
+        );
+        hash == self.hash
+    }
+
+    /// Whether the block is signed by the key that signed its draw. The reward goes to that key, so only the
+    /// winner of the draw may propose the block
+    pub fn signed_by_winner(&self) -> bool {
+        self.signature.verify(&self.draw.signed_by, self.hash.as_bytes()).is_ok()
+    }
+
+    // the seed is checked against the epoch seed of the chain when the block is added
+    pub(super) fn verify_winner(&self) -> bool {
+        self.draw.verify(&self.draw.seed, &self.prev_hash, self.timeslot)
+    }
+
+    /// Checks that the proof shows the transaction is in this block
+    pub fn verify_transaction(&self, transaction_hash: &[u8; 32], proof: &MerkleProof) -> bool {
+        proof.leaf_count == self.transaction_count
+            && proof.verify(transaction_hash, &self.merkle_root)
+    }
+
+    // the hashed preimage, written with the canonical encoding
+    fn hash_fields(
+        timeslot: Timeslot,
+        prev_hash: &BlockHash,
+        depth: u64,
+        draw: &Draw,
+        transaction_count: u64,
+        merkle_root: &[u8; 32],
+        state_root: &[u8; 32],
+    ) -> BlockHash {
+        let fields_bytes = Writer::new()
+            .timeslot(timeslot)
+            .block_hash(prev_hash)
+            .u64(depth)
+            .bytes(draw.signature.to_bytes())
+            .u64(transaction_count)
+            .hash(merkle_root)
+            .hash(state_root)
+            .finish();
+        BlockHash(Sha256::digest(fields_bytes).into())
+    }
+
+    pub(crate) fn encode(&self, writer: &mut Writer) {
+        writer
+            .timeslot(self.timeslot)
+            .block_hash(&self.prev_hash)
+            .u64(self.depth)
+            .u64(self.transaction_count)
+            .hash(&self.merkle_root)
+            .hash(&self.state_root);
+        self.draw.encode(writer);
+        writer.bytes(self.signature.to_bytes()).block_hash(&self.hash);
+    }
+
+    /// A header that can not be on any chain is refused before its signatures are checked: every block is
+    /// at a later timeslot than its parent so it is no deeper than its timeslot, its draw is for its own
+    /// parent and timeslot and the seed of the draw is from a block below it
+    pub(crate) fn decode(reader: &mut Reader) -> Result<Self> {
+        let header = Self {
+            timeslot: reader.timeslot()?,
+            prev_hash: reader.block_hash()?,
+            depth: reader.u64()?,
+            transaction_count: reader.u64()?,
+            merkle_root: reader.hash()?,
+            state_root: reader.hash()?,
+            draw: Draw::decode(reader)?,
+            signature: Signature(reader.signature()?),
+            hash: reader.block_hash()?,
+        };
+        let possible = header.depth <= header.timeslot.as_u64()
+            && header.timeslot < Timeslot::MAX
+            && header.draw.is_for(&header.prev_hash, header.timeslot)
+            && header.draw.seed.block_ptr.1 <= header.depth;
+        if !possible {
+            return Err(Error::InvalidEncoding);
+        }
+        Ok(header)
+    }
+
+    /// The canonical encoding, this is what is sent to light nodes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let header = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(header)
+    }
+
+    pub fn timeslot(&self) -> Timeslot {
+        self.timeslot
+    }
+
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+
+    pub fn transaction_count(&self) -> u64 {
+        self.transaction_count
+    }
+
+    pub fn merkle_root(&self) -> &[u8; 32] {
+        &self.merkle_root
+    }
+
+    /// The root of the ledger after this block, see `Ledger::state_root`
+    pub fn state_root(&self) -> &[u8; 32] {
+        &self.state_root
+    }
+
+    /// The account that won the timeslot and signed the block
+    pub fn winner(&self) -> &PublicKey {
+        &self.draw.signed_by
+    }
+
+    pub fn winner_id(&self) -> AccountId {
+        AccountId::from(&self.draw.signed_by)
+    }
+
+    /// The tiebreak between blocks at the same depth, the greater block is the better one.
+    /// The highest draw value wins and then the lexicographically smallest hash, so it is a total order
+    /// over blocks with different hashes and every node picks the same block from the same set
+    pub fn cmp_priority(&self, other: &BlockHeader) -> Ordering {
+        self.draw
+            .value
+            .cmp(&other.draw.value)
+            .then_with(|| other.hash.cmp(&self.hash))
+    }
+}
+
+serde_via_bytes!(BlockHeader);
+
+/// A header and the transactions it commits to, the body is empty when only the header is kept
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub(super) header: BlockHeader,
+    pub(super) transactions: Vec<Transaction>,
+}
+
+impl Deref for Block {
+    type Target = BlockHeader;
+
+    fn deref(&self) -> &BlockHeader {
+        &self.header
+    }
 }
 
 impl Block {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         timeslot: Timeslot,
-        prev_hash: [u8; 32],
+        prev_hash: BlockHash,
         depth: u64,
-        winner: RsaPublicKey,
+        winner: PublicKey,
         transactions: Vec<Transaction>,
-        sk: &RsaPrivateKey,
+        state_root: [u8; 32],
+        sk: &SecretKey,
         seed: SeedContent,
     ) -> Self {
-        let draw = Draw::new(timeslot, seed, winner.clone(), sk);
-        let fields_string =
-            Block::combine_fields_to_string(&timeslot, &prev_hash, depth, &draw, &transactions);
-        let mut hasher = Sha256::new();
-        hasher.update(fields_string.as_bytes());
-        let hash: [u8; 32] = hasher.finalize().into();
-        let signature = PssSignature::sign(sk, &hash).unwrap();
-        Self {
+        let draw = Draw::new(timeslot, prev_hash, seed, winner.clone(), sk);
+        let transaction_count = transactions.len() as u64;
+        let merkle_root = Self::merkle_root_of(&transactions);
+        let hash = BlockHeader::hash_fields(
             timeslot,
-            prev_hash,
+            &prev_hash,
             depth,
+            &draw,
+            transaction_count,
+            &merkle_root,
+            &state_root,
+        );
+        let signature = Signature::sign(sk, hash.as_bytes()).unwrap();
+        Self {
+            header: BlockHeader {
+                timeslot,
+                prev_hash,
+                depth,
+                transaction_count,
+                merkle_root,
+                state_root,
+                draw,
+                signature,
+                hash,
+            },
             transactions,
-            draw,
-            signature,
-            hash,
         }
     }
 
-    pub fn verify_signature(&self) -> bool {
-        let fields_string = Block::combine_fields_to_string(
-            &self.timeslot,
-            &self.prev_hash,
-            self.depth,
-            &self.draw,
-            &self.transactions,
-        );
-        let mut hasher = Sha256::new();
-        hasher.update(fields_string.as_bytes());
-        let hash: [u8; 32] = hasher.finalize().into();
-        hash == self.hash && self.signature.verify(&self.draw.signed_by, &hash).is_ok()
+    /// The genesis block of the network whose root accounts and params have the seed. It is not signed, so it is
+    /// the same whoever makes it and it is checked by making it again
+    pub(crate) fn genesis(seed: [u8; 32], winner: PublicKey, state_root: [u8; 32]) -> Self {
+        let prev_hash = BlockHash(seed);
+        let draw = Draw::unsigned(prev_hash, SeedContent::new((prev_hash, 0), seed), winner);
+        let merkle_root = Self::merkle_root_of(&[]);
+        let hash = BlockHeader::hash_fields(Timeslot::GENESIS, &prev_hash, 0, &draw, 0, &merkle_root, &state_root);
+        Self {
+            header: BlockHeader {
+                timeslot: Timeslot::GENESIS,
+                prev_hash,
+                depth: 0,
+                transaction_count: 0,
+                merkle_root,
+                state_root,
+                draw,
+                signature: Signature(Vec::new()),
+                hash,
+            },
+            transactions: Vec::new(),
+        }
     }
 
-    fn verify_winner(&self) -> bool {
-        if !self.draw.verify() {
-            return false;
-        }
-        if self.draw.timeslot != self.timeslot {
-            return false;
+    /// A block we only know the header of
+    pub fn from_header(header: BlockHeader) -> Self {
+        Self {
+            header,
+            transactions: Vec::new(),
         }
-        true
     }
 
-    fn verify_transactions(&self, previous_transactions: &HashSet<[u8; 32]>) -> bool {
-        self.transactions.iter().all(|t| {
-            t.verify_signature()
-                && !previous_transactions.contains(&t.hash)
-        })
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
     }
 
-    pub(super) fn verify_all(&self, previous_transactions: &HashSet<[u8; 32]>) -> bool {
-        let signature = self.verify_signature();
-        let transactions = self.verify_transactions(previous_transactions);
-        let winner = self.verify_winner();
-        signature && transactions && winner
+    /// The same block without its transactions
+    pub fn without_body(&self) -> Self {
+        Self::from_header(self.header.clone())
+    }
+
+    /// Whether we have the transactions of the block, otherwise only the header is known
+    pub fn has_body(&self) -> bool {
+        self.transactions.len() == self.tx_count()
+    }
+
+    /// The number of transactions the header commits to, also when we only have the header
+    pub fn tx_count(&self) -> usize {
+        self.transaction_count as usize
+    }
+
+    /// The length of `to_bytes`, counted without allocating the encoding. The block size limit, the mempool
+    /// and the sync batches all measure blocks and transactions with this
+    pub fn encoded_len(&self) -> usize {
+        let mut writer = Writer::counting();
+        self.encode(&mut writer);
+        writer.written()
+    }
+
+    /// The size of a block without transactions won with the draw, a transaction adds its encoding
+    /// without the version byte to this. The block is signed with the key of the draw,
+    /// so its signature is as long as the one of the draw
+    pub(crate) fn size_without_transactions(draw: &Draw) -> usize {
+        let mut writer = Writer::counting();
+        writer.u64(0).hash(&[0; 32]).u64(0).u64(0).hash(&[0; 32]).hash(&[0; 32]);
+        draw.encode(&mut writer);
+        writer
+            .bytes(draw.signature.to_bytes())
+            .hash(&[0; 32])
+            .len(0);
+        writer.written()
+    }
+
+    fn merkle_root_of(transactions: &[Transaction]) -> [u8; 32] {
+        let hashes: Vec<_> = transactions.iter().map(|t| t.hash).collect();
+        merkle::root(&hashes)
     }
 
-    pub(super) fn verify_genesis(&self, root_accounts: &Vec<RsaPublicKey>) -> bool {
-        let mut hasher = Sha256::new();
-        for ra in root_accounts.iter() {
-            hasher.update(ra.to_pkcs1_der().unwrap().as_bytes());
+    /// Verifies the header and that the transactions are the ones it commits to
+    pub fn verify_signature(&self) -> bool {
+        self.header.verify_signature()
+            && self.has_body()
+            && Self::merkle_root_of(&self.transactions) == self.merkle_root
+    }
+
+    /// Proves to someone with only the header that the transaction is in this block
+    pub fn prove_transaction(&self, transaction_hash: &[u8; 32]) -> Option<MerkleProof> {
+        let hashes: Vec<_> = self.transactions.iter().map(|t| t.hash).collect();
+        let index = hashes.iter().position(|hash| hash == transaction_hash)?;
+        MerkleProof::new(&hashes, index)
+    }
+
+    /// Verifies the signatures of the transactions that are not in the cache and adds those that hold.
+    /// It stops at the first that does not hold, the transactions are checked in parallel with the `parallel` feature
+    pub fn verify_transactions(&self, cache: &SignatureCache) -> std::result::Result<(), (TxId, SigError)> {
+        let verify = |t: &Transaction| cache.verify(t).map_err(|e| (t.id(), e));
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            self.transactions.par_iter().try_for_each(verify)
         }
+        #[cfg(not(feature = "parallel"))]
+        self.transactions.iter().try_for_each(verify)
+    }
 
-        let seed_hash: [u8; 32] = hasher.finalize().into();
-        self.transactions.is_empty() && self.verify_signature() && seed_hash == self.prev_hash
+    /// Verifies the signatures of all the transactions through the cache of the node
+    pub fn verify_transaction_signatures(&self) -> bool {
+        self.verify_transactions(&SIGNATURES).is_ok()
     }
 
-    // this should be replaced with a hashing function
-    fn combine_fields_to_string(
-        timeslot: &Timeslot,
-        prev_hash: &[u8; 32],
-        depth: u64,
-        draw: &Draw,
-        transactions: &Vec<Transaction>,
-    ) -> String {
-        // we can just use the hashes and the signatures of these to save a lot of space while preserving safety
-        let transactions =
-            hex::encode(bincode::serialize(&transactions.iter().map(|t| t.hash).collect::<Vec<_>>())
-                .unwrap());
-        let draw = hex::encode(draw.signature.to_bytes());
-        format!("{timeslot}{prev_hash:?}{depth}{draw}{transactions}")
+    pub(super) fn verify_all(&self, applied_transactions: &HashSet<TxId>) -> bool {
+        let body = self.header.hash_matches()
+            && self.has_body()
+            && Self::merkle_root_of(&self.transactions) == self.merkle_root;
+        let proposer = self.signed_by_winner();
+        let transactions = self
+            .transactions
+            .iter()
+            .all(|t| !applied_transactions.contains(&t.id()))
+            && self.verify_transaction_signatures();
+        let winner = self.verify_winner();
+        body && proposer && transactions && winner
     }
 
-    pub fn increment_timeslot(&mut self) {
-        self.timeslot += 1;
+    /// The genesis block points to the seed of the root accounts and chain params instead of a parent,
+    /// and it is the block `genesis` makes for its winner and state root
+    pub(super) fn verify_genesis(&self, genesis_seed: &[u8; 32]) -> bool {
+        self.transactions.is_empty()
+            && self.to_bytes() == Self::genesis(*genesis_seed, self.winner().clone(), *self.state_root()).to_bytes()
     }
 
-    // Tiebreak
-    pub(super) fn is_better_than(&self, other: &Block) -> bool {
-        // Tiebreak 1, earliest timeslot
-        if self.timeslot < other.timeslot {
-            return true;
-        } else if self.timeslot > other.timeslot {
-            return false;
+    /// The canonical encoding, this is what is sent on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.finish()
+    }
+
+    fn encode(&self, writer: &mut Writer) {
+        self.header.encode(writer);
+        writer.len(self.transactions.len());
+        for t in self.transactions.iter() {
+            t.encode(writer);
         }
-        // Tiebreak 2, most transactions
-        if self.transactions.len() > other.transactions.len() {
-            return true;
-        } else if self.transactions.len() < other.transactions.len() {
-            return false;
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let header = BlockHeader::decode(&mut reader)?;
+        let transactions = (0..reader.count(MIN_TRANSACTION_BYTES)?)
+            .map(|_| Transaction::decode(&mut reader))
+            .collect::<Result<Vec<_>>>()?;
+        reader.finish()?;
+        // the body is all the transactions of the header or none of them
+        if !transactions.is_empty() && transactions.len() as u64 != header.transaction_count {
+            return Err(Error::InvalidEncoding);
         }
+        Ok(Self {
+            header,
+            transactions,
+        })
+    }
 
-        // Tiebreak 3, lexicographically greatest hash
-        let self_hash = hex::encode(self.hash);
-        let other_hash = hex::encode(other.hash);
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
 
-        if let std::cmp::Ordering::Greater = self_hash.cmp(&other_hash) {
-            return true;
-        }
-        false
+    pub fn increment_timeslot(&mut self) {
+        self.header.timeslot += 1;
     }
 }
 
+serde_via_bytes!(Block);
+
 impl PartialEq for Block {
     fn eq(&self, other: &Self) -> bool {
         self.hash == other.hash
     }
 }
+
+#[cfg(test)]
+impl Block {
+    // the block signed again with another draw, as a winner reusing a draw from elsewhere would
+    pub(crate) fn with_draw(&self, draw: Draw, sk: &SecretKey) -> Self {
+        let mut block = self.clone();
+        let header = &mut block.header;
+        header.draw = draw;
+        header.hash = BlockHeader::hash_fields(
+            header.timeslot,
+            &header.prev_hash,
+            header.depth,
+            &header.draw,
+            header.transaction_count,
+            &header.merkle_root,
+            &header.state_root,
+        );
+        header.signature = Signature::sign(sk, header.hash.as_bytes()).unwrap();
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lazy_static::lazy_static;
+    use num_bigint::BigUint;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    lazy_static! {
+        static ref HEADER: BlockHeader = {
+            let (sk, pk) = crate::generate_keypair();
+            let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+            Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk, vec![], [0; 32], &sk, seed).header().clone()
+        };
+        static ref KEYS: (SecretKey, PublicKey) = crate::generate_keypair();
+    }
+
+    // a header that differs from the others only in what the tiebreak looks at, few values so there are ties
+    fn tiebreak_header() -> impl Strategy<Value = BlockHeader> {
+        (0u8..4, 0u8..4).prop_map(|(value, hash)| {
+            let mut header = HEADER.clone();
+            header.draw.value = BigUint::from(value);
+            header.hash = BlockHash([hash; 32]);
+            header
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn the_tiebreak_is_antisymmetric(a in tiebreak_header(), b in tiebreak_header()) {
+            prop_assert_eq!(a.cmp_priority(&b), b.cmp_priority(&a).reverse());
+            // only the same block ties
+            prop_assert_eq!(a.cmp_priority(&b).is_eq(), a.hash == b.hash && a.draw.value == b.draw.value);
+        }
+
+        #[test]
+        fn the_tiebreak_is_transitive(a in tiebreak_header(), b in tiebreak_header(), c in tiebreak_header()) {
+            if a.cmp_priority(&b).is_ge() && b.cmp_priority(&c).is_ge() {
+                prop_assert!(a.cmp_priority(&c).is_ge());
+            }
+            if a.cmp_priority(&b).is_gt() && b.cmp_priority(&c).is_gt() {
+                prop_assert!(a.cmp_priority(&c).is_gt());
+            }
+        }
+    }
+
+    // transactions signed as they are, with a random number of outputs and random fields
+    fn any_transaction() -> impl Strategy<Value = Transaction> {
+        (prop::collection::vec(any::<u64>(), 1..4), any::<u64>(), any::<u64>(), any::<u64>()).prop_map(
+            |(amounts, fee, nonce, valid_until)| {
+                let (sk, pk) = &*KEYS;
+                let outputs = amounts.into_iter().map(|amount| (pk.clone(), amount)).collect();
+                Transaction::sign(pk.clone(), outputs, sk, fee, nonce, Timeslot(valid_until))
+            },
+        )
+    }
+
+    proptest! {
+        // every case signs its transactions, so there are fewer of them
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn the_encoded_len_is_the_length_of_the_encoding(
+            transactions in prop::collection::vec(any_transaction(), 0..5),
+            timeslot in 1u64..1_000_000,
+        ) {
+            for t in transactions.iter() {
+                prop_assert_eq!(t.encoded_len(), t.to_bytes().len());
+            }
+            let (sk, pk) = &*KEYS;
+            let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+            let prev_hash = BlockHash([0; 32]);
+            let block = Block::new(Timeslot(timeslot), prev_hash, 1, pk.clone(), transactions, [0; 32], sk, seed);
+            prop_assert_eq!(block.encoded_len(), block.to_bytes().len());
+            prop_assert_eq!(block.without_body().encoded_len(), block.without_body().to_bytes().len());
+            prop_assert_eq!(block.tx_count(), block.transactions().len());
+        }
+    }
+
+    #[test]
+    fn the_higher_draw_and_then_the_smaller_hash_wins() {
+        let header = |value: u8, hash: u8| {
+            let mut header = HEADER.clone();
+            header.draw.value = BigUint::from(value);
+            header.hash = BlockHash([hash; 32]);
+            header
+        };
+        assert_eq!(header(2, 9).cmp_priority(&header(1, 0)), Ordering::Greater);
+        assert_eq!(header(1, 0).cmp_priority(&header(1, 9)), Ordering::Greater);
+        assert_eq!(header(1, 9).cmp_priority(&header(1, 0)), Ordering::Less);
+    }
+
+    #[test]
+    fn block_hashes_print_short_and_parse_in_full() {
+        let hash = BlockHash([0xab; 32]);
+        assert_eq!(hash.to_string(), "abababab");
+        assert_eq!(hash.to_hex().parse::<BlockHash>(), Ok(hash));
+        assert_eq!(hash.to_string().parse::<BlockHash>(), Err(Error::InvalidEncoding));
+        assert_eq!(serde_json::to_string(&hash).unwrap(), serde_json::to_string(&[0xab; 32]).unwrap());
+    }
+
+    #[test]
+    fn transaction_signatures_are_verified() {
+        let (sk, pk) = crate::generate_keypair();
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        let new_block = |transactions| {
+            Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk.clone(), transactions, [0; 32], &sk, seed.clone())
+        };
+        // the decision must be the same as verifying one by one, with or without the parallel feature
+        let serial = |block: &Block| block.transactions.iter().all(Transaction::verify_signature);
+
+        let (_, receiver) = crate::generate_keypair();
+        let transactions: Vec<_> = (1..=20)
+            .map(|amount| Transaction::new(pk.clone(), receiver.clone(), &sk, amount, amount - 1).unwrap())
+            .collect();
+        for block in [new_block(vec![]), new_block(transactions.clone())] {
+            assert!(block.verify_transaction_signatures());
+            assert!(serial(&block));
+            assert!(block.verify_all(&HashSet::new()));
+        }
+
+        for i in [0, 10, 19] {
+            let mut tampered = transactions.clone();
+            tampered[i].outputs[0].1 += 1;
+            let block = new_block(tampered);
+            // the block signature only covers the transaction hashes, so it is still valid
+            assert!(block.verify_signature());
+            assert!(!block.verify_transaction_signatures());
+            assert!(!serial(&block));
+            assert!(!block.verify_all(&HashSet::new()));
+        }
+    }
+
+    #[test]
+    fn headers_commit_to_the_transactions() {
+        let (sk, pk) = crate::generate_keypair();
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        let (_, receiver) = crate::generate_keypair();
+        let transactions: Vec<_> = (1..6)
+            .map(|amount| Transaction::new(pk.clone(), receiver.clone(), &sk, amount, amount - 1).unwrap())
+            .collect();
+        let block =
+            Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk.clone(), transactions.clone(), [0; 32], &sk, seed);
+        assert!(block.verify_signature());
+
+        for t in transactions.iter() {
+            let proof = block.prove_transaction(&t.hash).unwrap();
+            assert!(block.header().verify_transaction(&t.hash, &proof));
+        }
+        let (_, other) = crate::generate_keypair();
+        let outsider = Transaction::new(other, receiver.clone(), &sk, 1, 0).unwrap();
+        assert_eq!(block.prove_transaction(&outsider.hash), None);
+
+        // the header alone still verifies, but a block missing or swapping transactions does not
+        let header = BlockHeader::from_bytes(&block.header().to_bytes()).unwrap();
+        assert!(header.verify_signature());
+        let light = block.without_body();
+        assert!(!light.has_body());
+        assert!(!light.verify_signature());
+        assert!(light.header().verify_signature());
+        let mut swapped = block.clone();
+        swapped.transactions.swap(0, 1);
+        assert!(!swapped.verify_signature());
+        let mut replaced = block.clone();
+        replaced.transactions[0] = outsider;
+        assert!(!replaced.verify_signature());
+    }
+}
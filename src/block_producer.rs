@@ -0,0 +1,140 @@
+// the task that tries to win every timeslot, the blockchain actor builds the blocks and the wins are passed to the client
+
+use std::sync::Arc;
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    blockchain::Blockchain, blockchain_actor::BlockchainActorHandle, clock::Clock,
+    clock_watch::ClockWatch, ClientMessage, MAX_EMPTY_GAP,
+};
+
+/// Which won timeslots a node builds blocks in, other nodes accept empty blocks either way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProductionPolicy {
+    pub produce_empty_blocks: bool,
+    pub max_empty_gap: u64, // timeslots since the tip after which an empty block is produced anyway
+}
+
+impl Default for ProductionPolicy {
+    fn default() -> Self {
+        Self {
+            produce_empty_blocks: true,
+            max_empty_gap: MAX_EMPTY_GAP,
+        }
+    }
+}
+
+/// Wakes at every timeslot boundary of a chain and produces a block if we won the timeslot
+pub struct BlockProducer {
+    start_time: u128,
+    slot_length: u128,
+    clock: Arc<dyn Clock>,
+}
+
+impl BlockProducer {
+    /// Follows the timeslots of the chain, as read from its clock
+    pub fn new(blockchain: &Blockchain) -> Self {
+        Self {
+            start_time: blockchain.get_start_time(),
+            slot_length: blockchain.params().slot_length_ms as u128,
+            clock: blockchain.clock(),
+        }
+    }
+
+    /// Every won block is sent to the client to be broadcast, the task stops when the client is gone
+    pub fn spawn(
+        self,
+        blockchain: BlockchainActorHandle,
+        client: mpsc::Sender<ClientMessage>,
+    ) -> JoinHandle<()> {
+        // the watch starts from the timeslot we are in now, not when the task first runs
+        let mut slots = ClockWatch::start(self.start_time, self.slot_length, self.clock);
+        tokio::spawn(async move {
+            loop {
+                slots.wait_for_update().await;
+                let Some(block) = blockchain.produce().await else {
+                    continue;
+                };
+                if client.send(ClientMessage::Produced(block)).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "always_win"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        account::AccountId, block::Block, clock::ManualClock, params::ChainParams, Timeslot, BLOCK_REWARD, ROOT_AMOUNT,
+    };
+
+    async fn next_produced(rx: &mut mpsc::Receiver<ClientMessage>) -> Arc<Block> {
+        loop {
+            if let Some(ClientMessage::Produced(block)) = rx.recv().await {
+                return block;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_block_is_produced_once_per_slot() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let blockchain =
+            Blockchain::start_with_clock(vec![pk], ChainParams::default(), Arc::new(clock.clone()));
+        let (tx, mut rx) = mpsc::channel(100);
+        let producer = BlockProducer::new(&blockchain);
+        let handle = BlockchainActorHandle::new(blockchain, sk, tx.clone()).await;
+        producer.spawn(handle, tx);
+
+        for slot in 1..=5 {
+            clock.advance_slots(1);
+            let block = tokio::time::timeout(Duration::from_secs(5), next_produced(&mut rx))
+                .await
+                .expect("we win every timeslot");
+            assert_eq!(block.timeslot(), Timeslot(slot));
+            assert_eq!(block.depth(), slot);
+        }
+
+        // nothing more is produced while the clock stands still
+        let more = tokio::time::timeout(Duration::from_millis(100), next_produced(&mut rx)).await;
+        assert!(more.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_node_with_several_keys_produces_one_block_per_slot() {
+        let (sks, pks): (Vec<_>, Vec<_>) = (0..3).map(|_| crate::generate_keypair()).unzip();
+        let clock = ManualClock::default();
+        let blockchain =
+            Blockchain::start_with_clock(pks.clone(), ChainParams::default(), Arc::new(clock.clone()));
+        let (tx, mut rx) = mpsc::channel(100);
+        let producer = BlockProducer::new(&blockchain);
+        let handle = BlockchainActorHandle::with_keys(blockchain, sks, tx.clone()).await;
+        producer.spawn(handle.clone(), tx);
+
+        let mut winners = Vec::new();
+        for slot in 1..=6 {
+            clock.advance_slots(1);
+            let block = tokio::time::timeout(Duration::from_secs(5), next_produced(&mut rx))
+                .await
+                .expect("every key wins every timeslot");
+            assert_eq!(block.timeslot(), Timeslot(slot));
+            assert!(pks.contains(block.winner()));
+            winners.push(AccountId::from(block.winner()));
+        }
+        let more = tokio::time::timeout(Duration::from_millis(100), next_produced(&mut rx)).await;
+        assert!(more.is_err());
+
+        // each winner got the reward of its blocks
+        for pk in pks.iter() {
+            let account = AccountId::from(pk);
+            let blocks = winners.iter().filter(|winner| **winner == account).count() as u64;
+            assert_eq!(handle.get_balance(account).await, ROOT_AMOUNT + blocks * BLOCK_REWARD);
+        }
+    }
+}
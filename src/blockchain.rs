@@ -1,27 +1,49 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use rsa::pkcs8::der::zeroize::Zeroizing;
-use rsa::RsaPrivateKey;
-use rsa::{sha2::Sha256, RsaPublicKey};
+use rsa::sha2::Sha256;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::sync::broadcast;
 
-use crate::draw::Draw;
-use crate::seeding_mechanism::{self, SeedContent, MAX_SEED_AGE, MIN_SEED_AGE};
+use crate::account::AccountId;
+use crate::block_producer::ProductionPolicy;
+use crate::clock::{self, Clock, SkippingClock};
+use crate::difficulty;
+use crate::draw::{Draw, DrawCache};
+use crate::keys::{PublicKey, Scheme, SecretKey, SignatureScheme};
+use crate::events::{self, ChainEvent};
+use crate::watch::{AddressWatch, Watchers};
+use crate::history::AccountHistory;
+use crate::logging;
+use crate::mempool::Mempool;
+use crate::policy::RelayPolicy;
+use crate::seeding_mechanism::{self, epoch_of, SeedContent};
 use crate::Result;
 use crate::{
-    block::Block, is_winner, ledger::Ledger, transaction::Transaction, BLOCK_REWARD, ROOT_AMOUNT,
+    block::{Block, BlockHash, BlockHeader},
+    is_winner,
+    ledger::{Ledger, LedgerViolation, TxValidationError},
+    transaction::{Transaction, TxId},
+    CLOCK_SKEW_TOLERANCE, EQUIVOCATION_PENALTY, FINALITY_DEPTH, LEDGER_HISTORY_INTERVAL,
+    MAX_FUTURE_BLOCKS, MAX_MEMPOOL_AGE, MAX_MEMPOOL_BYTES, MAX_MEMPOOL_SIZE, MAX_ORPHANS, MAX_PARKED_SLOTS,
+    MAX_REORG_DEPTH, MAX_SNAPSHOT_BYTES, PRECHECKED_BLOCKS, SNAPSHOT_PART_BYTES,
 };
-use crate::{Timeslot, SLOT_LENGTH};
-use rsa::pkcs1::EncodeRsaPublicKey;
+use crate::params::ChainParams;
+use crate::Timeslot;
+use num_bigint::BigUint;
 use rsa::sha2::Digest;
 
-#[derive(Error, Debug, PartialEq)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum BlockchainError {
     #[error("Invalid signature")]
     InvalidSignature,
-    #[error("No parent was found to the block")]
-    OrphanBlock,
+    #[error("No parent was found to the block, the first missing ancestor is {}", hex::encode(.0))]
+    OrphanBlock(BlockHash),
     #[error("Invalid timeslot")]
     InvalidTimeslot,
     #[error("Best path not updated")]
@@ -31,7 +53,9 @@ pub enum BlockchainError {
     #[error("Invalid genesis block")]
     InvalidGenesisBlock,
     #[error("Invalid transaction")]
-    InvalidTransaction,
+    InvalidTransaction, // a block includes a transaction that can not be applied
+    #[error("Invalid transaction, {0}")]
+    RejectedTransaction(TxValidationError), // why a transaction was not added to the mempool
     #[error("Hash mismatch")]
     HashMismatch,
     #[error("Unable to verify block")]
@@ -44,6 +68,58 @@ pub enum BlockchainError {
     EmptyChain,
     #[error("Invalid seed")]
     InvalidSeed,
+    #[error("Duplicate transaction")]
+    DuplicateTransaction,
+    #[error("Mempool is full")]
+    MempoolFull,
+    #[error("A replacement must pay a higher fee than the transaction it replaces")]
+    ReplacementFeeTooLow,
+    #[error("Balance would overflow")]
+    BalanceOverflow,
+    #[error("Balance would underflow")]
+    BalanceUnderflow,
+    #[error("Transaction has not been processed")]
+    UnknownTransaction,
+    #[error("The block is not in the block tree")]
+    UnknownBlock,
+    #[error("Invalid depth")]
+    InvalidDepth,
+    #[error("Invalid transaction index")]
+    InvalidTransactionIndex,
+    #[error("The winner has equivocated and is penalized")]
+    EquivocatingWinner,
+    #[error("The block is from a future timeslot, it is retried when our clock catches up")]
+    FutureBlock,
+    #[error("The block would reorg past a final block, or deeper than the node allows without force_reorg")]
+    ReorgTooDeep,
+    #[error("The ledger breaks its invariants: {0:?}")]
+    LedgerAudit(Vec<LedgerViolation>),
+    #[error("The block has more transactions or bytes than the chain allows")]
+    BlockTooLarge,
+    #[error("The ledger does not have the state root the block commits to")]
+    StateRootMismatch,
+    #[error("The balance does not cover the fee and the smallest payment")]
+    NothingToSweep,
+    #[error("A transaction has expired before the timeslot of the block")]
+    ExpiredTransaction,
+    #[error("The sender of a transaction can not pay itself")]
+    SelfTransfer,
+    #[error("A transaction must pay a positive amount to every receiver")]
+    ZeroAmount,
+    #[error("The draw is not signed for the parent and timeslot of the block")]
+    DrawMismatch,
+    #[error("Blocks are only mined on demand on a dev chain")]
+    NotDevChain,
+    #[error("The transactions of the block have been pruned, only its header is kept")]
+    Pruned,
+    #[error("The block descends from another genesis block, the network or its start time differs")]
+    ForeignGenesis,
+    #[error("The block is not signed by the key that signed its draw")]
+    ProposerMismatch,
+    #[error("The block is signed with a key smaller than the chain allows")]
+    KeyTooSmall, // the winner or the sender of a transaction
+    #[error("The node does not relay transactions from or to these accounts")]
+    DeniedByPolicy, // by the relay policy of this node, a block may still include the transaction
 }
 
 impl<T> From<BlockchainError> for Result<T> {
@@ -58,17 +134,333 @@ impl From<BlockchainError> for crate::Error {
     }
 }
 
-pub type BlockPtr = ([u8; 32], u64);
+pub type BlockPtr = (BlockHash, u64);
+
+/// The head of the best path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TipRef {
+    pub hash: BlockHash,
+    pub depth: u64,
+}
+
+impl TipRef {
+    pub fn ptr(&self) -> BlockPtr {
+        (self.hash, self.depth)
+    }
+}
+
+impl From<BlockPtr> for TipRef {
+    fn from((hash, depth): BlockPtr) -> Self {
+        Self { hash, depth }
+    }
+}
+
+// the blocks `precheck_block` found to hold on their own, by their hash and signature as the signature is not
+// in the hash. It maps to whether the block also won its draw on its parent, the oldest are forgotten first
+#[derive(Debug, Clone, Default)]
+struct Prechecked(Arc<Mutex<PrecheckedBlocks>>);
+
+#[derive(Debug, Default)]
+struct PrecheckedBlocks {
+    won: HashMap<[u8; 32], bool>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl Prechecked {
+    fn key(block: &Block) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(block.hash.as_bytes());
+        hasher.update(block.signature.to_bytes());
+        hasher.finalize().into()
+    }
+
+    fn insert(&self, block: &Block, won: bool) {
+        let key = Self::key(block);
+        let mut blocks = self.0.lock().unwrap();
+        if blocks.won.insert(key, won).is_none() {
+            blocks.order.push_back(key);
+        }
+        while blocks.order.len() > PRECHECKED_BLOCKS {
+            let oldest = blocks.order.pop_front().expect("the order is not empty");
+            blocks.won.remove(&oldest);
+        }
+    }
+
+    fn get(&self, block: &Block) -> Option<bool> {
+        self.0.lock().unwrap().won.get(&Self::key(block)).copied()
+    }
+}
+
+// what a block is checked against before it is inserted, from the branch of its parent
+struct BranchState<'a> {
+    ledger: Ledger, // after the parent
+    stake_ledger: Cow<'a, Ledger>, // the lottery for the block is drawn with it
+    hardness: BigUint, // the draw of the block must beat it
+    fork_depth: u64, // where the branch leaves the best path
+}
+
+/// Why `verify_chain` refused a chain and the block where it went wrong.
+/// Violations of the whole chain, like a bad ledger, are pinned to the tip
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("block {} at depth {}: {violation}", hex::encode(block.0), block.1)]
+pub struct ChainVerifyReport {
+    pub block: BlockPtr,
+    pub violation: ChainViolation,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ChainViolation {
+    #[error("the timeslot is not after the timeslot of its parent")]
+    TimeslotOrder,
+    #[error("the block does not point to the block below it on the best path")]
+    HashLinkage,
+    #[error("the signatures of the block do not verify")]
+    BadSignature,
+    #[error("the winner did not win the timeslot")]
+    NotWinner,
+    #[error("the draw is not signed for the parent and timeslot of the block")]
+    DrawMismatch,
+    #[error("the block is not signed by the key that signed its draw")]
+    ProposerMismatch,
+    #[error("the winner or a sender has a key smaller than the chain allows")]
+    KeyTooSmall,
+    #[error("transaction {txid} can not be processed")]
+    InvalidTransaction { txid: TxId },
+    #[error("transaction {txid} expired before the timeslot of the block")]
+    ExpiredTransaction { txid: TxId },
+    #[error("the ledger has root {} but {} was expected", hex::encode(actual_root), hex::encode(expected_root))]
+    LedgerMismatch {
+        expected_root: [u8; 32],
+        actual_root: [u8; 32],
+    },
+    #[error("the genesis block is not the one of the root accounts")]
+    BadGenesis,
+    #[error("the block has more transactions or bytes than the chain allows")]
+    TooLarge,
+    #[error("the reward of the winner would overflow")]
+    RewardOverflow,
+    #[error("the best path does not lead from the tip to the checkpoint")]
+    BestPath,
+    #[error("the ledger breaks its invariants: {0:?}")]
+    LedgerAudit(Vec<LedgerViolation>),
+    #[error("the index of confirmed transactions does not match the best path")]
+    TransactionIndex,
+    #[error("the ledgers the next lotteries are drawn with do not match the best path")]
+    StakeLedgers,
+    #[error("the block does not have the weight of its branch")]
+    Weight,
+    #[error("the draw is not for the seed of its epoch on the best path")]
+    EpochSeed,
+    #[error("a ledger kept for balances at past depths does not have the state root of its block")]
+    LedgerHistory,
+}
+
+/// Why `reindex` could not rebuild a chain from its blocks
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ReindexError {
+    #[error("there is no block at depth 0")]
+    MissingGenesis,
+    #[error("the genesis block is not the one of the root accounts and params")]
+    BadGenesis,
+    #[error("block {} at depth {}: {reason}", hex::encode(block.0), block.1)]
+    InvalidBlock { block: BlockPtr, reason: BlockchainError },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    /// Waiting in the mempool to be included in a block
+    Pending,
+    /// Included in the block with this hash on the best path, the block itself is the first confirmation
+    Confirmed {
+        block: BlockHash,
+        confirmations: u64,
+    },
+    Unknown,
+}
+
+/// How many transactions and blocks that are not on the chain a node holds, a choice of the node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BufferLimits {
+    pub max_mempool_size: usize,
+    pub max_mempool_bytes: usize, // the sum of the `encoded_len` of the transactions in the mempool
+    pub max_orphans: usize, // blocks whose parent we do not have, more are dropped until their parent arrives
+    pub max_future_blocks: usize, // blocks held until our clock reaches their timeslot
+    pub max_mempool_age: u64, // timeslots a transaction waits in the mempool before `prune_mempool` drops it
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        Self {
+            max_mempool_size: MAX_MEMPOOL_SIZE,
+            max_mempool_bytes: MAX_MEMPOOL_BYTES,
+            max_orphans: MAX_ORPHANS,
+            max_future_blocks: MAX_FUTURE_BLOCKS,
+            max_mempool_age: MAX_MEMPOOL_AGE,
+        }
+    }
+}
+
+/// What a batch of `Blockchain::prune_mempool` did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolPruned {
+    pub dropped: usize,
+    pub finished: bool, // the batch reached the newest transaction, the next one starts from the oldest
+}
+
+/// Proof that a winner signed two different blocks in the same timeslot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Equivocation {
+    pub first: Arc<Block>,
+    pub second: Arc<Block>,
+}
+
+impl Equivocation {
+    /// Checks the proof without trusting whoever produced it
+    pub fn verify(&self) -> bool {
+        self.first.hash != self.second.hash
+            && self.first.winner() == self.second.winner()
+            && self.first.timeslot == self.second.timeslot
+            && self.first.header().verify_signature()
+            && self.second.header().verify_signature()
+    }
+}
+
+/// A block whose parent we do not have yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Orphan {
+    block: Arc<Block>,
+    received: u128, // when it arrived on our clock, its timeslot is checked against that when it is adopted
+}
+
+/// What a new node needs to start at the checkpoint of a chain instead of replaying it from genesis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub params: ChainParams,
+    pub root_accounts: Vec<PublicKey>,
+    pub start_time: u128,
+    pub headers: Vec<BlockHeader>, // the best path from the genesis block to the checkpoint
+    pub ledger: Ledger,            // the ledger after the checkpoint
+    pub stake_ledgers: Vec<Ledger>, // the ledgers after the blocks just below the checkpoint, oldest first
+}
+
+/// What a node needs to start an empty chain of a known network and sync into it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Genesis {
+    pub block: Block,
+    pub root_accounts: Vec<PublicKey>,
+    pub params: ChainParams,
+    pub start_time: u128,
+}
+
+impl Snapshot {
+    /// The ledger must have the state root of this header
+    pub fn checkpoint_header(&self) -> Option<&BlockHeader> {
+        self.headers.last()
+    }
+
+    /// The encoded snapshot in parts of at most part_bytes, `SnapshotParts` puts them together again
+    pub fn to_parts(&self, part_bytes: usize) -> Vec<Vec<u8>> {
+        let bytes = bincode::serialize(self).expect("a snapshot is plain data");
+        bytes.chunks(part_bytes).map(<[u8]>::to_vec).collect()
+    }
+}
+
+/// The parts of a snapshot that arrived so far, they are sent in order
+#[derive(Debug)]
+pub struct SnapshotParts {
+    parts: u32,
+    bytes: Vec<u8>,
+    received: u32,
+}
+
+impl SnapshotParts {
+    /// None if the parts would add up to more than `MAX_SNAPSHOT_BYTES`
+    pub fn new(parts: u32) -> Option<Self> {
+        (parts > 0 && parts as u64 * SNAPSHOT_PART_BYTES <= MAX_SNAPSHOT_BYTES).then_some(Self {
+            parts,
+            bytes: Vec::new(),
+            received: 0,
+        })
+    }
+
+    /// Adds the next part, the snapshot once it was the last one. A part out of order or larger than
+    /// `SNAPSHOT_PART_BYTES` is refused, and so are parts that do not decode to a snapshot
+    pub fn add(&mut self, part: u32, parts: u32, bytes: &[u8]) -> Result<Option<Snapshot>> {
+        if part != self.received || parts != self.parts || bytes.len() as u64 > SNAPSHOT_PART_BYTES {
+            return Err(crate::Error::InvalidEncoding);
+        }
+        self.bytes.extend_from_slice(bytes);
+        self.received += 1;
+        if self.received < self.parts {
+            return Ok(None);
+        }
+        bincode::deserialize(&self.bytes).map(Some).map_err(|_| crate::Error::InvalidEncoding)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blockchain {
-    pub(super) blocks: Vec<HashMap<[u8; 32], Block>>, // at index i all blocks at depth i exists in a map from their hash to the block
-    pub(super) best_path: Vec<BlockPtr>,              // best path
-    pub(super) ledger: Ledger, // this should follow the best_path_heads state
-    pub(super) root_accounts: Vec<RsaPublicKey>,
-    pub(super) orphans: HashMap<[u8; 32], Vec<Block>>, // maps from the parent that they have which is not in blocks
-    pub(super) transaction_buffer: HashSet<Transaction>,
+    blocks: Vec<HashMap<BlockHash, Arc<Block>>>, // at index i all blocks at depth i exists in a map from their hash to the block
+    best_path: Vec<BlockPtr>,                   // best path
+    ledger: Ledger,                             // this should follow the best_path_heads state
+    root_accounts: Vec<PublicKey>,
+    orphans: HashMap<BlockHash, Vec<Orphan>>, // maps from the parent that they have which is not in blocks
+    transaction_buffer: Mempool,
+    pending_ledger: Ledger, // the ledger with the buffered transactions applied
+    confirmed: HashMap<TxId, BlockPtr>, // the block on the best path that includes the transaction
+    history: AccountHistory, // the transfers of every account along the best path
+    seen_slots: HashMap<(AccountId, Timeslot), BlockPtr>, // the first block we got from a winner in a timeslot
+    epoch_seeds: HashMap<BlockPtr, [u8; 32]>, // the seed of the epoch after the block's, on paths where it ends its epoch
+    weights: HashMap<BlockPtr, BigUint>, // the weight of the branch up to the block, from the genesis block or the checkpoint of a snapshot
+    equivocations: Vec<Equivocation>,
+    penalized: HashMap<AccountId, Timeslot>, // maps to the timeslot of their latest equivocation
+    equivocation_penalty: u64,
+    future_blocks: BTreeMap<Timeslot, Vec<Arc<Block>>>, // blocks too far ahead of our clock, by their timeslot
+    clock_skew_tolerance: u64,
+    finality_depth: u64,
+    checkpoint: BlockPtr, // the deepest final block, every block in the tree descends from it
+    checkpoint_ledger: Ledger, // the ledger after the checkpoint block
+    stake_ledgers: BTreeMap<u64, Ledger>, // the ledgers after the blocks of the best path that lotteries are still drawn with
+    ledger_history: BTreeMap<u64, Ledger>, // the ledgers after every `history_interval`th block of the best path
+    history_interval: u64,
+    params: ChainParams,
+    prune_depth: Option<u64>, // final blocks this far below the tip are kept without their transactions
+    pruned_to: u64, // the transactions of the best path are gone up to this depth
     start_time: u128,
+    #[serde(skip)]
+    production: ProductionPolicy, // a choice of this node, not sent with the chain
+    #[serde(skip)]
+    buffer_limits: BufferLimits, // a choice of this node too
+    #[serde(skip, default = "default_max_reorg_depth")]
+    max_reorg_depth: u64, // and so is how deep a reorg it makes without `force_reorg`
+    #[serde(skip, default = "clock::system")]
+    clock: Arc<dyn Clock>,
+    #[serde(skip)]
+    events: Option<broadcast::Sender<ChainEvent>>, // created on the first subscription
+    #[serde(skip)]
+    relay_policy: RelayPolicy, // the accounts whose transactions this node does not take into its mempool
+    #[serde(skip)]
+    watchers: Watchers, // the accounts watched with `watch_address`
+    #[serde(skip)]
+    dev: Option<SkippingClock>, // on a dev chain every draw wins and the clock skips ahead to mine at once
+    #[serde(skip)]
+    prune_from: u64, // the insertion the next batch of `prune_mempool` starts from
+    #[serde(skip)]
+    prechecked: Prechecked, // shared by the copies, a block that holds does so on every copy of the chain
+}
+
+fn default_max_reorg_depth() -> u64 {
+    MAX_REORG_DEPTH
+}
+
+// why a header does not verify, when it hashes right the signature is of another key than the one of the draw
+fn signature_error(header: &BlockHeader) -> BlockchainError {
+    if header.hash_matches() && !header.signed_by_winner() {
+        BlockchainError::ProposerMismatch
+    } else {
+        BlockchainError::InvalidSignature
+    }
 }
 
 impl Blockchain {
@@ -76,635 +468,4460 @@ impl Blockchain {
         self.start_time
     }
 
-    fn produce_root_seed(root_accounts: &Vec<RsaPublicKey>) -> [u8; 32] {
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    // the start time is in it, chains started at different times disagree on every timeslot boundary
+    fn produce_root_seed(root_accounts: &[PublicKey], params: &ChainParams, start_time: u128) -> [u8; 32] {
         let mut hasher = Sha256::new();
         for ra in root_accounts.iter() {
-            hasher.update(ra.to_pkcs1_der().unwrap().as_bytes());
+            hasher.update(ra.to_bytes());
         }
+        hasher.update(params.to_bytes());
+        hasher.update(start_time.to_be_bytes());
+        hasher.update(Scheme::NAME);
 
         hasher.finalize().into()
     }
 
-    pub fn start(root_accounts: Vec<RsaPublicKey>, any_sk: &RsaPrivateKey) -> Self {
-        let seed_hash: [u8; 32] = Self::produce_root_seed(&root_accounts);
+    pub fn start(root_accounts: Vec<PublicKey>) -> Self {
+        Self::start_with_params(root_accounts, ChainParams::default())
+    }
 
-        let block = Block::new(
-            0,
-            seed_hash,
-            0,
-            root_accounts.first().unwrap().clone(),
-            Vec::new(),
-            any_sk,
-            SeedContent::new((seed_hash, 0), seed_hash),
-        );
+    pub fn start_with_params(root_accounts: Vec<PublicKey>, params: ChainParams) -> Self {
+        Self::start_with_clock(root_accounts, params, clock::system())
+    }
+
+    /// Starts the chain at the current time of the clock, which is used for every timeslot from then on
+    /// and is committed to by the genesis block
+    pub fn start_with_clock(root_accounts: Vec<PublicKey>, params: ChainParams, clock: Arc<dyn Clock>) -> Self {
+        let start_time = clock.now_millis();
+        let ledger = Self::root_ledger(&root_accounts, &params);
+        let block = Self::genesis_block(&root_accounts, &params, start_time);
+        Self::with_genesis(block, ledger, root_accounts, params, start_time, clock)
+    }
+
+    /// The genesis block of the network of the root accounts, params and start time, the first root account is
+    /// its winner. It needs no key, so everyone makes the same block, see `genesis::GenesisManifest`
+    pub fn genesis_block(root_accounts: &[PublicKey], params: &ChainParams, start_time: u128) -> Block {
+        let seed_hash = Self::produce_root_seed(root_accounts, params, start_time);
+        let ledger = Self::root_ledger(root_accounts, params);
+        let winner = root_accounts.first().expect("a network has root accounts").clone();
+        Block::genesis(seed_hash, winner, ledger.state_root())
+    }
+
+    /// An empty chain of the network with this genesis block, to sync into from peers
+    pub fn from_genesis(genesis: Block, root_accounts: Vec<PublicKey>, start_time: u128) -> Result<Self> {
+        Self::from_genesis_with_params(genesis, root_accounts, ChainParams::default(), start_time)
+    }
+
+    /// The genesis block must be the one `genesis_block` makes for the root accounts, params and start time,
+    /// so a node only takes the start time of a network along with a genesis block that commits to it
+    pub fn from_genesis_with_params(
+        genesis: Block,
+        root_accounts: Vec<PublicKey>,
+        params: ChainParams,
+        start_time: u128,
+    ) -> Result<Self> {
+        let seed_hash = Self::produce_root_seed(&root_accounts, &params, start_time);
+        let ledger = Self::root_ledger(&root_accounts, &params);
+        let valid = genesis.verify_genesis(&seed_hash)
+            && root_accounts.first() == Some(genesis.winner())
+            && *genesis.state_root() == ledger.state_root();
+        if !valid {
+            return BlockchainError::InvalidGenesisBlock.into();
+        }
+        Ok(Self::with_genesis(genesis, ledger, root_accounts, params, start_time, clock::system()))
+    }
+
+    /// The hash of the genesis block, the same on every node of the network
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.best_path[0].0
+    }
+
+    /// The genesis block and what it was made from, `from_genesis_with_params` starts a chain of the network from it
+    pub fn genesis(&self) -> Genesis {
+        let block = self.get_block(&self.best_path[0]).expect("unreachable");
+        Genesis {
+            block: Block::clone(block),
+            root_accounts: self.root_accounts.clone(),
+            params: self.params.clone(),
+            start_time: self.start_time,
+        }
+    }
+
+    // every root account starts with the root amount
+    fn root_ledger(root_accounts: &[PublicKey], params: &ChainParams) -> Ledger {
+        let root_ids: Vec<AccountId> = root_accounts.iter().map(AccountId::from).collect();
+        let mut ledger = Ledger::new(root_ids.clone(), params.transaction_fee).with_fee_sink(params.fee_sink);
+        for root_account in root_ids.iter() {
+            ledger
+                .reward_winner(root_account, params.root_amount, 0)
+                .expect("root allocations can not overflow");
+        }
+        ledger
+    }
+
+    fn with_genesis(
+        block: Block,
+        ledger: Ledger,
+        root_accounts: Vec<PublicKey>,
+        params: ChainParams,
+        start_time: u128,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         let hash = block.hash;
+        let seed_hash = block.prev_hash;
+        let seed = seeding_mechanism::next_seed(seed_hash.as_bytes(), 1, [&block.draw]);
+        let epoch_seeds = HashMap::from([((hash, 0), seed)]);
+        let history = AccountHistory::new(&block, &root_accounts, params.root_amount);
         let mut map = HashMap::new();
-        map.insert(hash, block.clone());
-        let mut ledger = Ledger::new(root_accounts.clone());
-        for root_account in root_accounts.iter() {
-            ledger.reward_winner(root_account, ROOT_AMOUNT);
-        }
+        map.insert(hash, Arc::new(block));
 
         let blocks = vec![map];
 
-        let _buffer_ledger = ledger.clone();
+        let pending_ledger = ledger.clone();
+        let checkpoint_ledger = ledger.clone();
+        let stake_ledgers = BTreeMap::from([(0, ledger.clone())]);
+        let ledger_history = BTreeMap::from([(0, ledger.clone())]);
 
         Self {
             blocks,
             best_path: vec![(hash, 0)],
+            weights: HashMap::from([((hash, 0), BigUint::from(0u64))]),
             ledger,
             root_accounts,
             orphans: HashMap::new(),
-            transaction_buffer: HashSet::new(),
-            start_time: crate::get_unix_timestamp(),
+            transaction_buffer: Mempool::new(MAX_MEMPOOL_SIZE, MAX_MEMPOOL_BYTES),
+            pending_ledger,
+            confirmed: HashMap::new(),
+            history,
+            seen_slots: HashMap::new(),
+            epoch_seeds,
+            equivocations: Vec::new(),
+            penalized: HashMap::new(),
+            equivocation_penalty: EQUIVOCATION_PENALTY,
+            future_blocks: BTreeMap::new(),
+            clock_skew_tolerance: CLOCK_SKEW_TOLERANCE,
+            finality_depth: FINALITY_DEPTH,
+            checkpoint: (hash, 0),
+            checkpoint_ledger,
+            stake_ledgers,
+            ledger_history,
+            history_interval: LEDGER_HISTORY_INTERVAL,
+            params,
+            prune_depth: None,
+            pruned_to: 0,
+            start_time,
+            production: ProductionPolicy::default(),
+            buffer_limits: BufferLimits::default(),
+            max_reorg_depth: MAX_REORG_DEPTH,
+            clock,
+            events: None,
+            relay_policy: RelayPolicy::default(),
+            watchers: Watchers::default(),
+            prechecked: Prechecked::default(),
+            dev: None,
+            prune_from: 0,
+        }
+    }
+
+    /// The headers of the best path up to the checkpoint and the ledger after it
+    pub fn export_snapshot(&self) -> Snapshot {
+        let headers = self.best_path[..=self.checkpoint.1 as usize]
+            .iter()
+            .map(|ptr| self.get_block(ptr).expect("unreachable").header().clone())
+            .collect();
+        Snapshot {
+            params: self.params.clone(),
+            root_accounts: self.root_accounts.clone(),
+            start_time: self.start_time,
+            headers,
+            ledger: self.checkpoint_ledger.clone(),
+            stake_ledgers: self
+                .stake_ledgers
+                .range(..self.checkpoint.1)
+                .map(|(_, ledger)| ledger.clone())
+                .collect(),
         }
     }
 
-    pub fn best_path_head(&self) -> &BlockPtr {
-        self.best_path.last().expect("unreachable")
+    /// Writes the blockchain to the file, the file is replaced in one step so it never holds half a chain
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let bytes = bincode::serialize(self).map_err(|_| crate::Error::InvalidEncoding)?;
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, bytes)
+            .and_then(|()| std::fs::rename(&partial, path))
+            .map_err(|_| crate::Error::StorageError)
+    }
+
+    /// Reads a blockchain written by `save`, it is not verified
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|_| crate::Error::StorageError)?;
+        bincode::deserialize(&bytes).map_err(|_| crate::Error::InvalidEncoding)
     }
 
-    fn check_seed(&self, block: &Block) -> bool {
-        let depth = block.depth as u64;
+    /// Starts at the checkpoint of the snapshot, only blocks building on it are accepted.
+    /// The blocks below it are kept without their transactions, so the ledger is trusted
+    /// because it has the state root that the checkpoint commits to
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self> {
+        let Snapshot {
+            params,
+            root_accounts,
+            start_time,
+            headers,
+            ledger,
+            stake_ledgers,
+        } = snapshot;
+        let Some(checkpoint) = headers.last() else {
+            return BlockchainError::EmptyChain.into();
+        };
+        if ledger.state_root() != *checkpoint.state_root() {
+            return BlockchainError::StateRootMismatch.into();
+        }
+        let checkpoint = (checkpoint.hash, checkpoint.depth);
 
-        let seed_age = || depth as u64 - block.draw.seed.block_ptr.1; // closure to evaluate lazily
-                                                                      // check that the seed is ok
-        if depth < MAX_SEED_AGE as _ && depth > 0 {
-            // if we are close to genesis we must have same seed as genesis block
-            if block.draw.seed != self.get_block(&self.best_path[0]).unwrap().draw.seed {
-                eprintln!("we do not have the same seed as the genesis block");
-                return false;
+        let genesis = Block::from_header(headers[0].clone());
+        if !genesis.verify_genesis(&Self::produce_root_seed(&root_accounts, &params, start_time)) {
+            return BlockchainError::InvalidGenesisBlock.into();
+        }
+        for (depth, header) in headers.iter().enumerate() {
+            if header.depth != depth as u64 {
+                return BlockchainError::InvalidDepth.into();
             }
-        } else if seed_age() > MIN_SEED_AGE && seed_age() <= MAX_SEED_AGE {
-            // in range, we must check if seed matches previous
-            let prev_block = self.get_block(&self.best_path[depth as usize - 1]).unwrap();
-            if block.draw.seed != prev_block.draw.seed {
-                eprintln!("block does not match previous at depth {}", depth);
-                eprintln!(
-                    "({},{}){}) seed_age = {}",
-                    hex::encode(block.draw.seed.block_ptr.0),
-                    block.draw.seed.block_ptr.1,
-                    hex::encode(block.draw.seed.seed),
-                    seed_age()
-                );
-                eprintln!(
-                    "prev ({},{}){})",
-                    hex::encode(prev_block.draw.seed.block_ptr.0),
-                    prev_block.draw.seed.block_ptr.1,
-                    hex::encode(prev_block.draw.seed.seed)
-                );
-                return false;
+            // the genesis block is not signed, it was checked above
+            if depth > 0 && (!header.verify_signature() || !header.verify_winner()) {
+                return BlockchainError::UnableToVerifyBlock.into();
             }
-        } else if seed_age() == MIN_SEED_AGE {
-            // on lower border, we must check if it is MIN_SEED_AGE back
-            // we must check that the hash of the draw MIN_SEED_AGE back is the seed
-            let old_block = self
-                .get_block(&self.best_path[(depth - (MIN_SEED_AGE)) as usize])
-                .unwrap();
-            if block.draw.seed
-                != SeedContent::new((old_block.hash, old_block.depth), old_block.draw.hash())
-            {
-                eprintln!("seed does not match MIN_SEED_AGE blocks back");
-                eprintln!(
-                    "comparing ({}) with ({}) at depth {}",
-                    block.draw.seed.block_ptr.1, old_block.depth, block.depth
-                );
-                return false;
+        }
+        for pair in headers.windows(2) {
+            if pair[1].prev_hash != pair[0].hash {
+                return BlockchainError::HashMismatch.into();
             }
-        } else {
-            // check genesis
-            let genesis_seed = Self::produce_root_seed(&self.root_accounts);
-            if !(depth == 0 && block.draw.seed.seed == genesis_seed) {
-                eprintln!(
-                    "out of range seed {} depth {} genesis_seed {} seed_age {}",
-                    hex::encode(block.draw.seed.seed),
-                    depth,
-                    hex::encode(genesis_seed),
-                    seed_age()
-                );
-                // out of range, this is always invalid (if not genesis)
-                return false;
+            if pair[1].timeslot <= pair[0].timeslot {
+                return BlockchainError::InvalidTimeslot.into();
             }
         }
-
-        true
-    }
-
-    /// Returns whether the new block extends the best path
-    pub fn add_block(&mut self, block: Block) -> Result<()> {
-        if !block.verify_signature() {
-            println!("signature invalid");
-            return BlockchainError::InvalidSignature.into();
+        // the lotteries just above the checkpoint are drawn with the ledgers below it
+        let lowest = (checkpoint.1 + 1).saturating_sub(params.stake_lookback);
+        if stake_ledgers.len() as u64 != checkpoint.1 - lowest {
+            return BlockchainError::StateRootMismatch.into();
+        }
+        let mut ledgers = BTreeMap::from([(checkpoint.1, ledger.clone())]);
+        for (depth, stake_ledger) in (lowest..).zip(stake_ledgers) {
+            if stake_ledger.state_root() != *headers[depth as usize].state_root() {
+                return BlockchainError::StateRootMismatch.into();
+            }
+            ledgers.insert(depth, stake_ledger);
         }
-        let depth = block.depth as usize;
 
-        let get_parent = |parent_hash: [u8; 32]| {
-            let map = self.blocks.get(depth - 1)?;
-            map.get(&parent_hash)
+        let best_path = headers.iter().map(|h| (h.hash, h.depth)).collect();
+        let blocks = headers
+            .into_iter()
+            .map(|header| HashMap::from([(header.hash, Arc::new(Block::from_header(header)))]))
+            .collect();
+        let mut blockchain = Self {
+            blocks,
+            best_path,
+            weights: HashMap::from([(checkpoint, BigUint::from(0u64))]), // the draws below it are not weighed
+            ledger: ledger.clone(),
+            root_accounts,
+            orphans: HashMap::new(),
+            transaction_buffer: Mempool::new(MAX_MEMPOOL_SIZE, MAX_MEMPOOL_BYTES),
+            pending_ledger: ledger.clone(),
+            confirmed: HashMap::new(),
+            history: AccountHistory::default(), // the transfers below the checkpoint are not known
+            seen_slots: HashMap::new(),
+            epoch_seeds: HashMap::new(),
+            equivocations: Vec::new(),
+            penalized: HashMap::new(),
+            equivocation_penalty: EQUIVOCATION_PENALTY,
+            future_blocks: BTreeMap::new(),
+            clock_skew_tolerance: CLOCK_SKEW_TOLERANCE,
+            finality_depth: FINALITY_DEPTH,
+            checkpoint,
+            checkpoint_ledger: ledger,
+            stake_ledgers: ledgers,
+            ledger_history: BTreeMap::new(), // the balances below the checkpoint are not known
+            history_interval: LEDGER_HISTORY_INTERVAL,
+            params,
+            prune_depth: None,
+            pruned_to: 0,
+            start_time,
+            production: ProductionPolicy::default(),
+            buffer_limits: BufferLimits::default(),
+            max_reorg_depth: MAX_REORG_DEPTH,
+            clock: clock::system(),
+            events: None,
+            relay_policy: RelayPolicy::default(),
+            watchers: Watchers::default(),
+            prechecked: Prechecked::default(),
+            dev: None,
+            prune_from: 0,
         };
+        // parents before children, every seed builds on the one below
+        for ptr in blockchain.best_path.clone() {
+            blockchain.record_epoch_seed(ptr);
+        }
+        blockchain.verify_seeds()?;
+        let violations = blockchain.audit_ledger();
+        if !violations.is_empty() {
+            return BlockchainError::LedgerAudit(violations).into();
+        }
+        Ok(blockchain)
+    }
 
-        let parent_hash = block.prev_hash;
-        let parent_block = get_parent(block.prev_hash);
-        let Some(parent_block) = parent_block else {
-            // the parent does not exist yet so we are an orphan
-            if let Some(orphans_of_prev) = self.orphans.get_mut(&block.prev_hash) {
-                orphans_of_prev.push(block);
-            } else {
-                self.orphans.insert(block.prev_hash, vec![block]);
-            }
-            println!(
-                "unable to find parent block, was looking for {:?}, best path head is {}",
-                &hex::encode(parent_hash)[0..5],
-                &hex::encode(self.best_path_head().0)[0..5]
-            );
-            return BlockchainError::OrphanBlock.into();
-        };
+    /// Rebuilds a chain from nothing but its blocks, for when the stored ledger and indexes can not be trusted.
+    /// The blocks are replayed through `add_block` by depth and then hash, so the same blocks always give the
+    /// same chain, and the first block that is refused stops the replay. Blocks that do not move the tip and
+    /// orphans are kept like when they arrived
+    pub fn reindex(
+        blocks: impl Iterator<Item = Block>,
+        params: ChainParams,
+        root_accounts: Vec<PublicKey>,
+        start_time: u128,
+    ) -> std::result::Result<Self, ReindexError> {
+        use BlockchainError::*;
+        let mut blocks: Vec<Block> = blocks.collect();
+        blocks.sort_by_key(|block| (block.depth, block.hash));
+        blocks.dedup_by_key(|block| block.hash);
+        let mut blocks = blocks.into_iter();
+        let genesis = blocks
+            .next()
+            .filter(|block| block.depth == 0)
+            .ok_or(ReindexError::MissingGenesis)?;
+        let mut blockchain = Self::from_genesis_with_params(genesis, root_accounts, params, start_time)
+            .map_err(|_| ReindexError::BadGenesis)?;
 
-        // we check the timeslot
-        if block.timeslot <= parent_block.timeslot || block.timeslot > self.calculate_timeslot() {
-            println!("signature timeslot mismatch");
-            dbg!(block.timeslot);
-            dbg!(parent_block.timeslot);
-            dbg!(self.calculate_timeslot());
-            return BlockchainError::InvalidTimeslot.into();
+        for block in blocks {
+            let ptr = (block.hash, block.depth);
+            let result = match block.has_body() {
+                true => blockchain.add_block(block),
+                false => blockchain.add_header(block.header().clone()),
+            };
+            let reason = match result {
+                Ok(()) => continue,
+                Err(crate::Error::BlockchainError(BestPathNotUpdated | OrphanBlock(_))) => continue,
+                Err(crate::Error::BlockchainError(reason)) => reason,
+                Err(_) => UnableToVerifyBlock,
+            };
+            return Err(ReindexError::InvalidBlock { block: ptr, reason });
         }
+        Ok(blockchain)
+    }
 
-        // check the seed
-        if !self.check_seed(&block) {
-            return BlockchainError::InvalidSeed.into();
-        }
+    /// Subscribe to the events emitted when the blockchain changes
+    pub fn subscribe(&mut self) -> broadcast::Receiver<ChainEvent> {
+        self.events.get_or_insert_with(events::channel).subscribe()
+    }
 
-        while depth >= self.blocks.len() {
-            // create empty hashmaps if the block is in the future, this will usually just be done once
-            self.blocks.push(HashMap::new());
+    /// Watch the payments to and from the account as they join and leave the best path
+    pub fn watch_address(&mut self, account: AccountId) -> AddressWatch {
+        self.watchers.watch(account)
+    }
+
+    fn emit(&self, event: ChainEvent) {
+        if let Some(ref events) = self.events {
+            let _ = events.send(event); // it is fine if nobody is listening
         }
+    }
 
-        // clone the stuff we need later
-        let block_hash = block.hash;
-        // we add ourself
-        self.blocks
-            .get_mut(depth)
-            .expect("unreachable")
-            .insert(block.hash, block.clone());
+    pub fn best_path_head(&self) -> TipRef {
+        let head = self.best_path.last().expect("invariant: the best path has at least the genesis block");
+        TipRef::from(*head)
+    }
 
-        // remove all transactions from the buffer that are in the block
-        for t in block.transactions.iter() {
-            self.transaction_buffer.remove(t);
-        }
+    /// The hash and depth of the head of the best path
+    pub fn tip(&self) -> TipRef {
+        self.best_path_head()
+    }
 
-        // we check if this is the new best path
-        let (old_best_path, old_depth) = *self.best_path_head();
+    pub fn block_at(&self, hash: &BlockHash, depth: u64) -> Option<&Arc<Block>> {
+        self.blocks
+            .get(depth as usize)
+            .and_then(|map| map.get(hash))
+    }
 
-        if depth > old_depth as _ {
-            // this is definetely the new best path
-            //self.best_path_head = (block_hash, depth as _);
+    fn latest_acceptable_timeslot(&self) -> Timeslot {
+        self.acceptable_timeslot_at(self.clock.now_millis())
+    }
 
-            // rollback if we changed branch
-            if old_best_path != parent_hash {
-                println!("rollback 1");
-                self.rollback((old_best_path, old_depth), (block_hash, depth as _));
-            } else {
-                self.proccess_transactions(&block.transactions, block.depth);
-                self.ledger
-                    .reward_winner(&block.draw.signed_by, BLOCK_REWARD);
-                self.best_path.push((block.hash, block.depth));
-            }
-        } else if depth == old_depth as usize {
-            //println!("equal depth");
-            let new_block = &block;
-            let curr_best_block = self.blocks[old_depth as usize].get(&old_best_path).unwrap();
+    // the latest timeslot a block that arrived at the time may have
+    fn acceptable_timeslot_at(&self, millis: u128) -> Timeslot {
+        crate::calculate_timeslot(millis, self.start_time, self.params.slot_length_ms as u128)
+            .saturating_add(self.clock_skew_tolerance)
+    }
 
-            if new_block.is_better_than(curr_best_block) {
-                //self.best_path_head = (block_hash, depth as _);
-                // we always have to rollback in this case
-                println!("rollback 2");
-                self.rollback((old_best_path, old_depth), (block_hash, depth as _));
-            }
+    /// Holds the block until its timeslot is acceptable, at most `max_future_blocks` of the buffer limits are held
+    fn hold_future_block(&mut self, block: Arc<Block>) -> Result<()> {
+        if self.future_block_count() >= self.buffer_limits.max_future_blocks {
+            return BlockchainError::InvalidTimeslot.into();
+        }
+        let held = self.future_blocks.entry(block.timeslot).or_default();
+        if !held.iter().any(|b| b.hash == block.hash) {
+            held.push(block);
         }
+        BlockchainError::FutureBlock.into()
+    }
 
-        // we check if we have any orphans, if we do we must add them after ourself
-        if let Some(orphans) = self.orphans.remove(&block_hash) {
-            for orphan in orphans {
-                println!(
-                    "Added orphan, result = {:?}",
-                    self.add_block(orphan.clone())
-                );
+    /// Adds the held blocks that our clock has caught up with
+    pub fn process_future_blocks(&mut self) {
+        let latest_timeslot = self.latest_acceptable_timeslot();
+        let Some(later) = latest_timeslot.checked_add(1) else {
+            return; // no block can be held
+        };
+        let still_future = self.future_blocks.split_off(&later);
+        let ready = std::mem::replace(&mut self.future_blocks, still_future);
+        for block in ready.into_values().flatten() {
+            let has_body = block.has_body();
+            let ptr = (block.hash, block.depth);
+            if let Err(error) = self.insert_block(block, has_body) {
+                let hash = hex::encode(ptr.0);
+                tracing::debug!(target: logging::BLOCKCHAIN, %hash, depth = ptr.1, %error, "held block not added");
             }
         }
+    }
 
-        // return whether the best_path has been updated
-        (old_best_path != self.best_path_head().0)
-            .then_some(())
-            .ok_or(crate::Error::BlockchainError(
-                BlockchainError::BestPathNotUpdated,
-            ))
+    pub fn future_block_count(&self) -> usize {
+        self.future_blocks.values().map(Vec::len).sum()
+    }
+
+    /// Sets how many timeslots ahead of our clock a block may be
+    pub fn set_clock_skew_tolerance(&mut self, timeslots: u64) {
+        self.clock_skew_tolerance = timeslots;
     }
 
-    fn calculate_timeslot(&self) -> Timeslot {
-        #[cfg(feature = "max_timeslot")]
-        return u64::MAX;
+    /// The equivocations we have seen, in the order they were detected
+    pub fn get_equivocations(&self) -> &[Equivocation] {
+        &self.equivocations
+    }
 
-        let now = crate::get_unix_timestamp();
-        let start = self.start_time;
-        let timeslot = (now - start) / SLOT_LENGTH;
-        timeslot as _
+    /// Sets for how many timeslots after an equivocation the winner's blocks are refused
+    pub fn set_equivocation_penalty(&mut self, timeslots: u64) {
+        self.equivocation_penalty = timeslots;
     }
 
-    pub fn add_transaction(&mut self, transaction: Transaction) -> bool {
-        if transaction.verify_signature() && self.ledger.is_transaction_possible(&transaction) {
-            self.transaction_buffer.insert(transaction);
-            true
-        } else {
-            println!("invalid transaction");
-            false
+    fn record_equivocation(&mut self, equivocation: Equivocation) {
+        let winner = equivocation.second.winner_id();
+        self.penalized.insert(winner, equivocation.second.timeslot);
+        self.equivocations.push(equivocation.clone());
+        self.emit(ChainEvent::Equivocation(equivocation));
+    }
+
+    // the parent may itself be an orphan we hold, then it is its missing ancestor we need
+    fn missing_ancestor(&self, parent_hash: BlockHash) -> BlockHash {
+        let mut missing = parent_hash;
+        while let Some(orphan) = self
+            .orphans
+            .values()
+            .flatten()
+            .find(|orphan| orphan.block.hash == missing)
+        {
+            missing = orphan.block.prev_hash;
         }
+        missing
     }
 
-    pub fn rollback(&mut self, from: ([u8; 32], u64), to: ([u8; 32], u64)) {
-        let get_block = |hash: &[u8; 32], depth: u64| {
-            self.blocks
-                .get(depth as usize)
-                .and_then(|m| m.get(hash))
-                .unwrap()
-        };
+    /// Finds a block in the tree without knowing its depth
+    pub fn block_by_hash(&self, hash: &BlockHash) -> Option<&Arc<Block>> {
+        self.blocks.iter().find_map(|map| map.get(hash))
+    }
 
-        let mut from_ptr = get_block(&from.0, from.1);
-        let mut to_ptr = get_block(&to.0, to.1);
-        let mut track_stack = Vec::new();
-        while from_ptr != to_ptr {
-            track_stack.push((to_ptr.hash, to_ptr.depth));
-            let popped = self.best_path.pop().unwrap();
-            assert_eq!(popped, (to_ptr.hash, to_ptr.depth));
-
-            if to_ptr.depth == 1 && from_ptr.depth == 1 && to_ptr.prev_hash == from_ptr.prev_hash {
-                self.ledger.rollback_reward(&to_ptr.draw.signed_by);
-                for t in from_ptr.transactions.iter() {
-                    self.ledger.rollback_transaction(t, from_ptr.depth);
-                    self.transaction_buffer.insert(t.clone()); // we have to readd the transactions to the buffer
-                }
-                break; // we have reached the genesis block
-            }
-            let (to_parent_hash, to_parent_depth) = (&to_ptr.prev_hash, to_ptr.depth - 1);
-            let old_to_ptr_depth = to_ptr.depth;
+    /// The block with its transactions, `Pruned` if only its header is kept
+    pub fn full_block_by_hash(&self, hash: &BlockHash) -> Result<&Arc<Block>> {
+        let block = self.block_by_hash(hash).ok_or(BlockchainError::UnknownBlock)?;
+        if !block.has_body() {
+            return BlockchainError::Pruned.into();
+        }
+        Ok(block)
+    }
 
-            to_ptr = get_block(to_parent_hash, to_parent_depth);
+    /// The block at this depth on the best path
+    pub fn best_path_block(&self, depth: u64) -> Option<&Arc<Block>> {
+        self.best_path
+            .get(depth as usize)
+            .and_then(|ptr| self.get_block(ptr))
+    }
 
-            if old_to_ptr_depth == from_ptr.depth {
-                // to_depth is always >= from_depth so we have to ensure that to goes back first
-                // we roll back the transactions on the from path
-                self.ledger.rollback_reward(&to_ptr.draw.signed_by);
-                for t in from_ptr.transactions.iter() {
-                    self.ledger.rollback_transaction(t, from_ptr.depth);
-                }
+    /// The hash of the block at this depth on the best path, also below the checkpoint of a snapshot
+    pub fn best_path_hash(&self, depth: u64) -> Option<BlockHash> {
+        self.best_path.get(depth as usize).map(|(hash, _)| *hash)
+    }
 
-                let (from_parent_hash, from_parent_depth) =
-                    (&from_ptr.prev_hash, from_ptr.depth - 1);
-                from_ptr = get_block(from_parent_hash, from_parent_depth);
-            }
-        }
+    /// Walks the best path from the tip to the genesis block
+    pub fn best_path_iter(&self) -> impl Iterator<Item = &Arc<Block>> {
+        self.best_path
+            .iter()
+            .rev()
+            .map(|ptr| self.get_block(ptr).expect("unreachable"))
+    }
 
-        // so now the track_stack should be the path from_ptr/to_ptr to the from/to hash
-        // so we perform the new transactions
-        while let Some((hash, depth)) = track_stack.pop() {
-            let block = get_block(&hash, depth);
-            for t in block.transactions.iter() {
-                self.ledger.process_transaction(t, block.depth);
-            }
-            self.ledger
-                .reward_winner(&block.draw.signed_by, BLOCK_REWARD);
-            self.best_path.push((hash, depth));
-        }
+    /// The ledger at the tip of the best path
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
     }
 
-    /// Simply checks if you've won
-    pub fn stake(&self, draw: Draw, wallet: &RsaPublicKey, depth: u64) -> bool {
-        is_winner(&self.ledger, draw, wallet, depth)
+    /// Every block in the tree, including the forks
+    pub fn tree_iter(&self) -> impl Iterator<Item = &Arc<Block>> {
+        self.blocks.iter().flat_map(HashMap::values)
     }
 
-    fn proccess_transactions(&mut self, transactions: &Vec<Transaction>, depth: u64) {
-        for t in transactions.iter() {
-            self.ledger.process_transaction(t, depth);
-        }
+    /// The blocks waiting for their parent
+    pub fn orphans_iter(&self) -> impl Iterator<Item = &Arc<Block>> {
+        self.orphans.values().flatten().map(|orphan| &orphan.block)
     }
 
-    pub fn get_balance(&self, account_sk: &RsaPublicKey) -> u64 {
-        self.ledger.map.get(account_sk).cloned().unwrap_or(0)
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.values().map(Vec::len).sum()
     }
 
-    fn verify_seeds(&self) -> Result<()> {
-        for ptr in self.best_path.iter() {
-            let block = self
-                .get_block(ptr)
-                .ok_or::<crate::Error>(BlockchainError::InvalidSeed.into())?;
-            if !self.check_seed(block) {
-                return BlockchainError::InvalidSeed.into();
-            }
-        }
+    /// Iterates the buffered transactions from the oldest to the newest
+    pub fn mempool_iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.transaction_buffer.iter()
+    }
 
-        Ok(())
+    /// Returns whether the new block extends the best path
+    pub fn add_block(&mut self, block: impl Into<Arc<Block>>) -> Result<()> {
+        let block: Arc<Block> = block.into();
+        let _span = tracing::debug_span!(
+            target: logging::BLOCKCHAIN,
+            "add_block",
+            hash = %hex::encode(block.hash),
+            depth = block.depth
+        )
+        .entered();
+        let verified = self.verify_block(&block);
+        self.emit_rejection((block.hash, block.depth), &verified);
+        verified?;
+        self.insert_block(block, true)
     }
 
-    /// Verifies that the entire blockchain follows the rules
-    pub fn verify_chain(&self) -> Result<()> {
-        if !self.check_best_path() {
-            println!("not best path");
-            return BlockchainError::InvalidBestPath.into();
+    /// Checks the signatures of the header, the draw and the transactions of a block that is not too large, and
+    /// whether the draw wins on its parent, without changing the chain. What holds is cached, so `add_block` does
+    /// not check it again
+    pub fn precheck_block(&self, block: &Block) {
+        if self.check_block_size(block).is_err() || self.check_key_sizes(block).is_err() {
+            return;
+        }
+        if !block.verify_signature() || !block.verify_winner() || !block.verify_transaction_signatures() {
+            return;
         }
+        // an orphan has no parent to draw against yet, its signatures are still cached
+        let won = block
+            .depth
+            .checked_sub(1)
+            .and_then(|parent_depth| self.get_block(&(block.prev_hash, parent_depth)))
+            .and_then(|parent| self.branch_state(parent))
+            .is_some_and(|branch| {
+                self.wins(&branch.stake_ledger, &block.draw, &block.draw.signed_by, block.depth, &branch.hardness)
+            });
+        self.prechecked.insert(block, won);
+    }
 
-        // there must be exactly 1 genesis block
-        let genesis_block = {
-            let mut blocks = self.blocks[0].values();
-            if blocks.len() == 1 {
-                (blocks.next().unwrap().hash, 0)
-            } else {
-                return BlockchainError::InvalidGenesisBlock.into();
+    fn verify_block(&self, block: &Block) -> Result<()> {
+        // the cheap checks first, an oversized block is not worth verifying
+        self.check_block_size(block)?;
+        self.check_key_sizes(block)?;
+        let started = Instant::now();
+        let signature = self.prechecked.get(block).is_some() || block.verify_signature();
+        let transactions = signature && block.verify_transaction_signatures();
+        self.emit(ChainEvent::BlockVerified(started.elapsed()));
+        if !signature {
+            return signature_error(block.header()).into();
+        }
+        if !transactions {
+            return BlockchainError::UnableToVerifyBlock.into();
+        }
+        if block.transactions.iter().any(|t| t.is_expired(block.timeslot)) {
+            return BlockchainError::ExpiredTransaction.into();
+        }
+        // a proposer could otherwise fill the block with payments that only burn fees
+        block.transactions.iter().try_for_each(Transaction::check_outputs)
+    }
+
+    // orphans, held blocks and blocks that are not on the best path are not rejected
+    fn emit_rejection(&self, block: BlockPtr, result: &Result<()>) {
+        use BlockchainError::*;
+        if let Err(crate::Error::BlockchainError(reason)) = result {
+            if !matches!(reason, OrphanBlock(_) | FutureBlock | BestPathNotUpdated) {
+                tracing::warn!(
+                    target: logging::BLOCKCHAIN,
+                    hash = %hex::encode(block.0),
+                    depth = block.1,
+                    ?reason,
+                    "block rejected"
+                );
+                self.emit(ChainEvent::BlockRejected {
+                    block,
+                    reason: reason.clone(),
+                });
             }
-        };
+        }
+    }
 
-        let get_parent_ptr = |ptr: &([u8; 32], u64)| {
-            (
-                self.blocks[ptr.1 as usize]
-                    .get(&ptr.0)
-                    .map(|b| b.prev_hash)
-                    .unwrap(),
-                ptr.1 - 1,
-            )
+    /// Follows the chain without the transactions, the header is checked like a block but only the
+    /// reward of the winner reaches the ledger. Returns whether the header extends the best path
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<()> {
+        let verified = if header.transaction_count() > self.params.max_block_transactions {
+            BlockchainError::BlockTooLarge.into()
+        } else if !header.draw.signed_by.has_bits(self.params.min_key_bits) {
+            BlockchainError::KeyTooSmall.into()
+        } else if !header.verify_signature() {
+            signature_error(&header).into()
+        } else if !header.verify_winner() {
+            BlockchainError::DrawMismatch.into()
+        } else {
+            Ok(())
         };
+        self.emit_rejection((header.hash, header.depth), &verified);
+        verified?;
+        // without the transactions our ledger can not have the state root of the block
+        self.insert_block(Arc::new(Block::from_header(header)), false)
+    }
 
-        let get_block = |ptr: &([u8; 32], u64)| self.blocks[ptr.1 as usize].get(&ptr.0).unwrap();
-
-        // we walk from the head, to the genesis block to get a verifiable path
-        let mut track_stack = Vec::new();
-        let mut walking_ptr = *self.best_path_head();
-        while walking_ptr != genesis_block {
-            track_stack.push(walking_ptr);
-            walking_ptr = get_parent_ptr(&walking_ptr);
+    // the byte size can only be checked when we have the transactions
+    fn check_block_size(&self, block: &Block) -> Result<()> {
+        if block.transaction_count() > self.params.max_block_transactions
+            || (block.has_body() && block.encoded_len() as u64 > self.params.max_block_bytes)
+        {
+            return BlockchainError::BlockTooLarge.into();
         }
-        // now the track_stack contains all on the best path except genesis
-        // we will also track a ledger to see if it matches the proposed ledger
-        // we then check the track_stack
-        let mut track_ledger = {
-            let mut l = Ledger::new(self.root_accounts.clone());
-            self.root_accounts
-                .iter()
-                .for_each(|acc| l.reward_winner(acc, ROOT_AMOUNT));
-            l
-        };
-        let previous_transactions = HashSet::new();
-        let mut prev_ptr = genesis_block;
-        let genesis_block = get_block(&genesis_block);
-        let mut prev_ts = genesis_block.timeslot;
-        while let Some((block_hash, depth)) = track_stack.pop() {
-            let block = get_block(&(block_hash, depth));
-            if block.timeslot <= prev_ts {
-                return BlockchainError::InvalidTimeslot.into();
-            }
-            prev_ts = block.timeslot;
+        Ok(())
+    }
 
-            if block.prev_hash != prev_ptr.0 {
-                println!("hash mishmatch");
-                return BlockchainError::HashMismatch.into();
-            }
-            if !block.verify_all(&previous_transactions) {
-                println!("block not verified");
-                return BlockchainError::UnableToVerifyBlock.into();
+    // the winner signs the draw and the block, and the senders their transactions
+    fn check_key_sizes(&self, block: &Block) -> Result<()> {
+        let min_bits = self.params.min_key_bits;
+        if !block.draw.signed_by.has_bits(min_bits) || block.transactions.iter().any(|t| !t.from.has_bits(min_bits)) {
+            return BlockchainError::KeyTooSmall.into();
+        }
+        Ok(())
+    }
+
+    // held blocks and orphans are inserted later, so their rejections are emitted here.
+    // The orphans waiting for the block are adopted after it with a queue instead of recursion, so a long chain
+    // of orphans can not overflow the stack. Parents are inserted before their children, so depths only go up
+    fn insert_block(&mut self, block: Arc<Block>, check_state_root: bool) -> Result<()> {
+        let old_tip = self.best_path_head().hash;
+        let ptr = (block.hash, block.depth);
+        let result = self.insert_into_tree(block, check_state_root, None);
+        self.emit_rejection(ptr, &result);
+        if self.get_block(&ptr).is_none() {
+            return result;
+        }
+
+        let mut adopted = VecDeque::from([ptr.0]);
+        while let Some(parent) = adopted.pop_front() {
+            for Orphan { block: orphan, received } in self.orphans.remove(&parent).unwrap_or_default() {
+                let orphan_ptr = (orphan.hash, orphan.depth);
+                let orphan_result = self.insert_into_tree(orphan, check_state_root, Some(received));
+                self.emit_rejection(orphan_ptr, &orphan_result);
+                tracing::debug!(
+                    target: logging::BLOCKCHAIN,
+                    hash = %hex::encode(orphan_ptr.0),
+                    depth = orphan_ptr.1,
+                    result = ?orphan_result,
+                    "orphan adopted"
+                );
+                if self.get_block(&orphan_ptr).is_some() {
+                    adopted.push_back(orphan_ptr.0);
+                }
             }
+        }
 
-            let winner = &block.draw.signed_by;
-            if !is_winner(&track_ledger, block.draw.clone(), winner, block.depth) {
-                println!("false winner");
-                return BlockchainError::FalseWinner.into();
+        // the orphans may have moved the tip even if the block itself did not
+        if old_tip != self.best_path_head().hash {
+            return Ok(());
+        }
+        result
+    }
+
+    // the block has been verified on its own, here it is checked against the tree.
+    // An adopted orphan is checked against the time it was received, not the time its parent arrived
+    fn insert_into_tree(&mut self, block: Arc<Block>, check_state_root: bool, received: Option<u128>) -> Result<()> {
+        // only the genesis block has depth 0, and it is never added
+        let Some(parent_depth) = block.depth.checked_sub(1) else {
+            return BlockchainError::InvalidDepth.into();
+        };
+        // clocks are never perfectly in sync so blocks a little ahead are accepted,
+        // those further ahead are held until our clock catches up
+        let adopted = received.is_some();
+        let received = received.unwrap_or_else(|| self.clock.now_millis());
+        let latest_timeslot = self.acceptable_timeslot_at(received);
+        if block.timeslot > latest_timeslot {
+            // an orphan that was too far ahead when it arrived is dropped instead of held again
+            if adopted {
+                return BlockchainError::InvalidTimeslot.into();
             }
+            return self.hold_future_block(block);
+        }
+        // every block is in a later timeslot than its parent, so no block can be deeper than the current timeslot
+        if block.depth > latest_timeslot.as_u64() {
+            return BlockchainError::InvalidDepth.into();
+        }
+        let depth = block.depth as usize;
 
-            // we process the transactions for the track ledger and they must all be valid
-            if !block
-                .transactions
-                .iter()
-                .all(|t| track_ledger.process_transaction(t, block.depth))
-            {
-                return BlockchainError::InvalidTransaction.into();
-            };
+        // blocks we already have are ignored, their orphans have already been adopted
+        if self.get_block(&(block.hash, block.depth)).is_some() {
+            return BlockchainError::BestPathNotUpdated.into();
+        }
+        // a block at or below the checkpoint can only be on a branch that forked off before it
+        if block.depth <= self.checkpoint.1 {
+            return BlockchainError::ReorgTooDeep.into();
+        }
+        // there is one genesis block, a child of another is of another network or start time and never adopted
+        if parent_depth == 0 && block.prev_hash != self.genesis_hash() {
+            return BlockchainError::ForeignGenesis.into();
+        }
+
+        let get_parent = |parent_hash: BlockHash| {
+            let map = self.blocks.get(parent_depth as usize)?;
+            map.get(&parent_hash)
+        };
 
-            track_ledger.reward_winner(winner, BLOCK_REWARD);
+        let parent_hash = block.prev_hash;
+        let parent_block = get_parent(block.prev_hash);
+        let Some(parent_block) = parent_block else {
+            // the parent does not exist yet so we are an orphan
+            self.emit(ChainEvent::BlockOrphaned((block.hash, block.depth)));
+            tracing::debug!(
+                target: logging::BLOCKCHAIN,
+                hash = %hex::encode(block.hash),
+                parent = %hex::encode(parent_hash),
+                tip = %hex::encode(self.best_path_head().hash),
+                "orphan held until its parent arrives"
+            );
+            // a full buffer drops the orphan, we still ask for its parents and get it again when we sync
+            if self.orphan_count() < self.buffer_limits.max_orphans {
+                self.orphans.entry(block.prev_hash).or_default().push(Orphan { block, received });
+            }
+            return BlockchainError::OrphanBlock(self.missing_ancestor(parent_hash)).into();
+        };
 
-            prev_ptr = (block_hash, depth);
+        // we check the timeslot
+        if block.timeslot <= parent_block.timeslot || block.timeslot > latest_timeslot {
+            tracing::debug!(
+                target: logging::BLOCKCHAIN,
+                timeslot = %block.timeslot,
+                parent_timeslot = %parent_block.timeslot,
+                %latest_timeslot,
+                "timeslot out of order"
+            );
+            return BlockchainError::InvalidTimeslot.into();
         }
 
-        // we then check the genesis block
-        if !genesis_block.transactions.is_empty()
-            || !genesis_block.verify_genesis(&self.root_accounts)
+        // the draw must be for the seed of its epoch on the path of the parent
+        if !self
+            .epoch_seed(parent_block, block.timeslot)
+            .is_some_and(|seed| block.draw.seed == seed)
         {
-            return BlockchainError::InvalidGenesisBlock.into();
+            return BlockchainError::InvalidSeed.into();
+        }
+        // and signed for this parent and timeslot, a lucky draw on another fork can not be used here
+        let prechecked = self.prechecked.get(&block);
+        if prechecked.is_none() && !block.draw.verify(&block.draw.seed, &block.prev_hash, block.timeslot) {
+            return BlockchainError::DrawMismatch.into();
+        }
+
+        // a winner that has signed two blocks in the same timeslot can not win again for a while
+        let winner = block.winner_id();
+        if let Some(equivocated_at) = self.penalized.get(&winner) {
+            if block.timeslot > *equivocated_at
+                && block.timeslot - *equivocated_at <= self.equivocation_penalty
+            {
+                return BlockchainError::EquivocatingWinner.into();
+            }
         }
 
-        if self.ledger != track_ledger {
-            dbg!("ledger mismatch {:#?}\n{:#?}", &self.ledger, track_ledger);
+        // everything is checked against the branch of the parent before anything is changed,
+        // so a refused block leaves no trace in the tree, the ledger or the mempool
+        let Some(branch) = self.branch_state(parent_block) else {
             return BlockchainError::InvalidLedger.into();
+        };
+        let won = prechecked == Some(true)
+            || self.wins(
+                &branch.stake_ledger,
+                &block.draw,
+                &block.draw.signed_by,
+                block.depth,
+                &branch.hardness,
+            );
+        if !won {
+            return BlockchainError::FalseWinner.into();
+        }
+        let fork_depth = branch.fork_depth;
+        let weight = self
+            .weights
+            .get(&(parent_hash, parent_depth))
+            .expect("invariant: every block above the checkpoint has a weight")
+            + difficulty::block_weight(&block.draw.value, &branch.hardness);
+        let mut next_ledger = branch.ledger;
+        if !block
+            .transactions
+            .iter()
+            .all(|t| next_ledger.process_transaction(t, block.depth))
+        {
+            return BlockchainError::InvalidTransaction.into();
+        }
+        next_ledger.reward_winner(&winner, self.params.block_reward, block.depth)?;
+        // without the transactions our ledger can not have the state root of the block
+        if check_state_root && next_ledger.state_root() != *block.state_root() {
+            return BlockchainError::StateRootMismatch.into();
         }
+        let TipRef { hash: old_best_path, depth: old_depth } = self.best_path_head();
 
-        Ok(())
-    }
+        let slot = (winner, block.timeslot);
+        if let Some(first) = self.seen_slots.get(&slot) {
+            let first = Arc::clone(self.get_block(first).expect("seen blocks are in the tree"));
+            self.record_equivocation(Equivocation {
+                first,
+                second: Arc::clone(&block),
+            });
+        } else {
+            self.seen_slots.insert(slot, (block.hash, block.depth));
+        }
 
-    /// checks that the best_path head is the correct one
-    pub fn check_best_path(&self) -> bool {
-        let max_depth = self.best_path_head().1 as usize;
-        if self.blocks.len() - 1 != max_depth {
-            println!(
-                "blocks len does not match depth {} vs {}",
-                self.blocks.len() - 1,
-                max_depth
-            );
-            return false;
+        // the parent is in the tree, so the block is at most one deeper than the deepest block
+        if depth == self.blocks.len() {
+            self.blocks.push(HashMap::new());
         }
-        let blocks_at_max_depth = self.blocks[max_depth].clone();
-        if blocks_at_max_depth.is_empty() {
-            println!("no blocks at max depth");
-            return false;
+
+        let block_hash = block.hash;
+        // we add ourself, the block is shared so this does not copy it
+        self.blocks
+            .get_mut(depth)
+            .expect("unreachable")
+            .insert(block.hash, Arc::clone(&block));
+        self.record_epoch_seed((block.hash, block.depth));
+        self.weights.insert((block.hash, block.depth), weight);
+        self.emit(ChainEvent::BlockAccepted(Arc::clone(&block)));
+
+        // remove all transactions from the buffer that are in the block
+        let mut removed = false;
+        for t in block.transactions.iter() {
+            removed |= self.transaction_buffer.remove(t).is_some();
         }
-        if blocks_at_max_depth.len() > 1 {
-            // check for tiebreak between all the blocks
-            let mut blocks = blocks_at_max_depth.values().collect::<Vec<_>>();
-            let mut greatest_block_so_far = blocks.pop().unwrap();
-            for block in blocks {
-                if !greatest_block_so_far.is_better_than(block) {
-                    greatest_block_so_far = block;
+
+        // we check if this is the new best path, the heaviest branch wins
+        let mut refused = false;
+        if self.cmp_branches(&(block_hash, depth as _), &(old_best_path, old_depth)).is_gt() {
+            // rollback if we changed branch
+            if old_best_path != parent_hash {
+                refused = !self.switch_branch((old_best_path, old_depth), (block_hash, depth as _), fork_depth);
+            } else {
+                self.ledger = next_ledger;
+                self.stake_ledgers.insert(block.depth, self.ledger.clone());
+                self.record_history(block.depth);
+                self.history.add_block(&block, self.params.block_reward);
+                self.watchers.connect_block(&block);
+                self.best_path.push((block.hash, block.depth));
+                self.emit(ChainEvent::BlockConnected(Arc::clone(&block)));
+                for t in block.transactions.iter() {
+                    self.confirmed.insert(t.id(), (block.hash, block.depth));
+                    self.emit(ChainEvent::TransactionConfirmed(t.hash));
                 }
             }
+        }
 
-            if &(greatest_block_so_far.hash, greatest_block_so_far.depth) != self.best_path_head() {
-                return false;
-            }
+        if old_best_path != self.best_path_head().hash {
+            self.emit(ChainEvent::NewTip(self.best_path_head().ptr()));
+            // transactions may have been spent on the new best path
+            self.purge_mempool();
+            self.advance_checkpoint();
+            self.watchers.new_tip(self.best_path_head().depth, self.checkpoint.1);
+        } else if removed {
+            // the pending ledger still has the removed transactions
+            self.purge_mempool();
+        }
+        // the block stays in the tree, `force_reorg` can still switch to its branch
+        if refused {
+            return BlockchainError::ReorgTooDeep.into();
         }
 
-        true
+        // return whether the best_path has been updated
+        (old_best_path != self.best_path_head().hash)
+            .then_some(())
+            .ok_or(crate::Error::BlockchainError(
+                BlockchainError::BestPathNotUpdated,
+            ))
     }
 
-    pub fn get_best_hash(&self) -> [u8; 32] {
-        self.best_path_head().0
+    // the fork choice, the branch with the greater weight up to its head is the better one and the tiebreak between
+    // the heads decides between branches of the same weight. Blocks below the checkpoint weigh nothing
+    fn cmp_branches(&self, a: &BlockPtr, b: &BlockPtr) -> std::cmp::Ordering {
+        self.weights.get(a).cmp(&self.weights.get(b)).then_with(|| {
+            match (self.get_block(a), self.get_block(b)) {
+                (Some(a), Some(b)) => a.cmp_priority(b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            }
+        })
     }
 
-    fn get_best_block(&self) -> &Block {
-        self.get_block(self.best_path_head()).expect("unreachable")
+    /// The weight of the branch up to the block, see `difficulty::block_weight`. Blocks below the checkpoint
+    /// have none, and a chain started from a snapshot weighs its blocks from the checkpoint
+    pub fn weight_of(&self, ptr: &BlockPtr) -> Option<&BigUint> {
+        self.weights.get(ptr)
     }
 
-    fn get_block(&self, ptr: &BlockPtr) -> Option<&Block> {
-        self.blocks
-            .get(ptr.1 as usize)
-            .and_then(|map| map.get(&ptr.0))
+    // switches to the branch of the new head unless it leaves the best path more than `max_reorg_depth` blocks below the tip
+    fn switch_branch(&mut self, from: BlockPtr, to: BlockPtr, fork_depth: u64) -> bool {
+        let depth = from.1 - fork_depth;
+        if depth > self.max_reorg_depth {
+            self.emit(ChainEvent::ReorgRefused {
+                old_tip: from,
+                new_tip: to,
+                depth,
+            });
+            return false;
+        }
+        self.rollback(from, to);
+        true
     }
 
-    fn get_parent(&self, block: &Block) -> Option<&Block> {
-        if block.depth == 0 {
-            return None;
+    /// Switches the best path to the branch of the block however deep the reorg is, for an operator who
+    /// has looked at a reorg the node refused. Blocks at or below the checkpoint are final and stay
+    pub fn force_reorg(&mut self, tip_hash: BlockHash) -> Result<()> {
+        let Some(tip) = self.block_by_hash(&tip_hash).map(|block| (block.hash, block.depth)) else {
+            return BlockchainError::UnknownBlock.into();
         };
-        let block_ptr = (block.prev_hash, block.depth - 1);
-        self.get_block(&block_ptr)
+        let old_tip = self.best_path_head().ptr();
+        if tip == old_tip || self.best_path.get(tip.1 as usize) == Some(&tip) {
+            return BlockchainError::BestPathNotUpdated.into();
+        }
+        self.rollback(old_tip, tip);
+        self.emit(ChainEvent::NewTip(tip));
+        self.purge_mempool();
+        self.advance_checkpoint();
+        self.watchers.new_tip(tip.1, self.checkpoint.1);
+        Ok(())
     }
 
-    fn get_next_seed(&self) -> SeedContent {
-        let best_block = self.get_best_block();
-        let seed_content = best_block.draw.seed.clone();
+    /// Sets how many blocks of the best path a reorg may roll back, a deeper one waits for `force_reorg`
+    pub fn set_max_reorg_depth(&mut self, depth: u64) {
+        self.max_reorg_depth = depth;
+    }
 
-        let seed_age = best_block.depth - seed_content.block_ptr.1;
-        if (seed_age >= MIN_SEED_AGE && seed_age < MAX_SEED_AGE) || best_block.depth < MIN_SEED_AGE
-        {
-            // use previous seed
-            return seed_content;
+    pub fn max_reorg_depth(&self) -> u64 {
+        self.max_reorg_depth
+    }
+
+    /// Sets whose transactions the node takes into its mempool, the buffered ones it no longer allows are dropped
+    pub fn set_relay_policy(&mut self, policy: RelayPolicy) {
+        self.relay_policy = policy;
+        let before = self.transaction_buffer.len();
+        self.transaction_buffer.retain(|t| self.relay_policy.allows(t));
+        if self.transaction_buffer.len() < before {
+            self.purge_mempool();
         }
+    }
 
-        // seed >= 100 so we pick a new seed
-        // we do this by walking 50 back from our best block
-        let block = self
-            .get_block(&self.best_path[(best_block.depth - MIN_SEED_AGE + 1) as usize])
-            .unwrap();
+    pub fn relay_policy(&self) -> &RelayPolicy {
+        &self.relay_policy
+    }
+
+    // the branch of the parent is replayed from where it leaves the best path, from the ledger we kept there
+    fn branch_state(&self, parent: &Block) -> Option<BranchState<'_>> {
+        let mut branch = Vec::new();
+        let mut ptr = (parent.hash, parent.depth);
+        while self.best_path.get(ptr.1 as usize) != Some(&ptr) {
+            let block = self.get_block(&ptr)?;
+            branch.push(block);
+            ptr = (block.prev_hash, ptr.1.checked_sub(1)?);
+        }
+        branch.reverse();
+        let fork_depth = ptr.1;
 
-        SeedContent {
-            block_ptr: (block.hash, block.depth),
-            seed: block.draw.hash(),
+        let depth = parent.depth + 1;
+        let stake_depth = depth.saturating_sub(self.params.stake_lookback);
+        let mut stake_ledger = match stake_depth <= fork_depth {
+            true => Some(Cow::Borrowed(self.stake_ledgers.get(&stake_depth)?)),
+            false => None,
+        };
+        let mut ledger = match fork_depth == self.best_path_head().depth {
+            true => self.ledger.clone(),
+            false => self.stake_ledgers.get(&fork_depth)?.clone(),
+        };
+        for block in branch.iter() {
+            for t in block.transactions.iter() {
+                ledger.process_transaction(t, block.depth);
+            }
+            ledger
+                .reward_winner(&block.winner_id(), self.params.block_reward, block.depth)
+                .ok()?;
+            if block.depth == stake_depth {
+                stake_ledger = Some(Cow::Owned(ledger.clone()));
+            }
         }
+
+        let timeslot_at = |d: u64| match d.checked_sub(fork_depth + 1) {
+            Some(i) => branch[i as usize].timeslot,
+            None => self.get_block(&self.best_path[d as usize]).expect("unreachable").timeslot,
+        };
+        Some(BranchState {
+            ledger,
+            stake_ledger: stake_ledger?,
+            hardness: difficulty::hardness_at(&self.params.hardness, timeslot_at, depth),
+            fork_depth,
+        })
     }
 
-    pub fn get_draw(&self, sk: &RsaPrivateKey) -> Draw {
-        Draw::new(
-            self.calculate_timeslot(),
-            self.get_next_seed(),
-            sk.to_public_key(),
-            sk,
+    pub(crate) fn calculate_timeslot(&self) -> Timeslot {
+        crate::calculate_timeslot(
+            self.clock.now_millis(),
+            self.start_time,
+            self.params.slot_length_ms as u128,
         )
     }
 
-    pub(crate) fn get_new_block(
-        &self,
-        prev_hash: [u8; 32],
-        draw: Draw,
-        sk: &RsaPrivateKey,
-    ) -> Block {
-        let mut checking_ledger = self.ledger.clone();
-        let new_depth = self.best_path_head().1 + 1;
-        let mut transactions_buffer: Vec<_> = self.transaction_buffer.clone().into_iter().collect();
-        // this could cause many transactions in the same block depth to only get a few valid in random order
-        transactions_buffer.retain(|t| checking_ledger.process_transaction(t, new_depth));
+    /// A chain received from a peer uses the system clock until it is given another
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
 
-        Block::new(
-            draw.timeslot,
-            prev_hash,
-            new_depth,
-            draw.signed_by.clone(),
-            transactions_buffer,
-            sk,
-            draw.seed,
-        )
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
     }
-}
 
-#[cfg(test)]
-impl Blockchain {
-    // Keeps mining until winning, since this is a test it will be fast
-    // A timeslot is only 0.001 ms when testing
-    fn produce_new_block_on_best_path(
-        &mut self,
-        sk: &RsaPrivateKey,
-        max_attempts: u64,
-    ) -> Result<()> {
-        let wallet = sk.to_public_key();
-        let mut attempts = 1;
-        let mut draw = self.get_draw(sk);
-        while !self.stake(draw.clone(), &wallet, self.best_path_head().1 + 1)
-            && attempts < max_attempts
-        {
-            draw = self.get_draw(sk);
-            attempts += 1;
+    /// Returns whether the transaction replaced a buffered one with the same sender and nonce.
+    /// It is checked against the pending ledger, so it must be affordable next to every buffered transaction.
+    /// A transaction after a nonce of its sender that has not arrived is parked until it does
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<bool> {
+        if self.transaction_buffer.contains(&transaction) || self.ledger.is_applied(&transaction.id()) {
+            return BlockchainError::DuplicateTransaction.into();
+        }
+        if !self.relay_policy.allows(&transaction) {
+            return BlockchainError::DeniedByPolicy.into();
+        }
+        // a block in the current timeslot may still include it
+        let timeslot = self.calculate_timeslot();
+        if transaction.is_expired(timeslot) {
+            let valid_until = transaction.valid_until();
+            return TxValidationError::Expired { valid_until, timeslot }.into();
+        }
+        if !transaction.from.has_bits(self.params.min_key_bits) {
+            let bits = transaction.from.bits().unwrap_or_default() as u64;
+            return TxValidationError::KeyTooSmall { bits, minimum: self.params.min_key_bits }.into();
+        }
+        let new_depth = self.best_path_head().depth + 1;
+        let from = transaction.from_id();
+        // a replacement is checked without the transaction it replaces
+        let replaced = self
+            .transaction_buffer
+            .get_by_nonce(&from, transaction.nonce)
+            .cloned();
+        let pending = match replaced {
+            Some(ref replaced) => Cow::Owned(self.apply_mempool(Some(replaced.hash)).0),
+            None => Cow::Borrowed(&self.pending_ledger),
+        };
+        let expected = pending.next_nonce(&from);
+        if transaction.nonce < expected {
+            return TxValidationError::NonceTooLow { expected, got: transaction.nonce }.into();
+        }
+        // what it can afford is only known once the transactions before it are in
+        let parked = transaction.nonce > expected;
+        if parked {
+            pending.validate_form(&transaction)?;
+        } else {
+            pending.validate_transaction(&transaction, new_depth)?;
+            // a replacement must leave room for the pending transactions of the sender after it
+            let room = replaced
+                .as_ref()
+                .and_then(Transaction::cost)
+                .map(|cost| cost.saturating_add(self.get_spendable_balance(&from)));
+            let cost = transaction.cost().expect("the transaction is valid");
+            if let Some(spendable) = room.filter(|room| cost > *room) {
+                return TxValidationError::InsufficientFunds { spendable, cost }.into();
+            }
         }
+        if self.transaction_buffer.is_full() {
+            // make room by dropping what can no longer be processed before evicting valid transactions
+            self.purge_mempool();
+        }
+        let evicts = self.transaction_buffer.is_full();
+        let replaced = self.transaction_buffer.insert(transaction.clone(), timeslot)?;
+        if parked {
+            self.transaction_buffer.park(transaction.hash, timeslot);
+        }
+        // a transaction that fills a gap lets the parked ones after it in
+        let fills_gap = !parked && self.transaction_buffer.get_by_nonce(&from, transaction.nonce + 1).is_some();
+        if replaced || evicts || fills_gap {
+            self.purge_mempool();
+        } else if !parked {
+            self.pending_ledger.process_transaction(&transaction, new_depth);
+        }
+        Ok(replaced)
+    }
 
-        self.add_block(self.get_new_block(self.get_best_hash(), draw, sk))
+    /// Removes the buffered transactions that have expired or are no longer possible against the ledger,
+    /// or that the sender can no longer pay for together with the other pending transactions.
+    /// Those after a nonce of their sender that has not arrived are parked, for `MAX_PARKED_SLOTS` timeslots.
+    /// The pending ledger is rebuilt from the ledger with the transactions that are kept
+    pub fn purge_mempool(&mut self) {
+        let timeslot = self.calculate_timeslot();
+        let (pending, mut keep) = self.apply_mempool(None);
+        let parked: Vec<_> = self
+            .transaction_buffer
+            .iter()
+            .filter(|t| !keep.contains(&t.hash) && !t.is_expired(timeslot))
+            .filter(|t| t.nonce > pending.next_nonce(&t.from_id()))
+            .map(|t| t.hash)
+            .collect();
+        for hash in keep.iter() {
+            self.transaction_buffer.unpark(hash);
+        }
+        for hash in parked {
+            self.transaction_buffer.park(hash, timeslot);
+            let since = self.transaction_buffer.parked_since(&hash).unwrap_or(timeslot);
+            if timeslot.since(since) < MAX_PARKED_SLOTS {
+                keep.insert(hash);
+            }
+        }
+        self.transaction_buffer.retain(|t| keep.contains(&t.hash));
+        self.pending_ledger = pending;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Drops what can no longer go in a block from the mempool: expired transactions, those whose nonce the ledger
+    /// has used, those the sender can no longer afford on the pending ledger and those that have waited more than
+    /// `max_mempool_age` timeslots. At most `batch` transactions are looked at, from where the last call stopped,
+    /// so the chain is not held up by a large mempool. The pending ledger is rebuilt if any were dropped
+    pub fn prune_mempool(&mut self, batch: usize) -> MempoolPruned {
+        let timeslot = self.calculate_timeslot();
+        let max_age = self.buffer_limits.max_mempool_age;
+        let new_depth = self.best_path_head().depth + 1;
+        let mut looked_at = 0;
+        let mut doomed = Vec::new();
+        for (insertion, t) in self.transaction_buffer.iter_from(self.prune_from).take(batch) {
+            looked_at += 1;
+            self.prune_from = insertion + 1;
+            let from = t.from_id();
+            let arrived = self.transaction_buffer.arrived(&t.hash).unwrap_or(timeslot);
+            // a transaction at the next nonce of the pending ledger is one that could not be applied
+            let unaffordable = t.nonce == self.pending_ledger.next_nonce(&from)
+                && self.pending_ledger.validate_transaction(t, new_depth).is_err();
+            if t.is_expired(timeslot)
+                || t.nonce < self.ledger.next_nonce(&from)
+                || unaffordable
+                || timeslot.since(arrived) > max_age
+            {
+                doomed.push(t.clone());
+            }
+        }
+        // the next round starts from the oldest again
+        let finished = looked_at < batch;
+        if finished {
+            self.prune_from = 0;
+        }
+        let before = self.transaction_buffer.len();
+        for t in doomed.iter() {
+            self.transaction_buffer.remove(t);
+        }
+        if !doomed.is_empty() {
+            // the transactions after a dropped one of the same sender may no longer fit either
+            self.purge_mempool();
+        }
+        let dropped = before - self.transaction_buffer.len();
+        if dropped > 0 {
+            self.emit(ChainEvent::MempoolPruned { dropped });
+        }
+        MempoolPruned { dropped, finished }
+    }
 
-    fn create_dummy_blockchain() -> (Blockchain, Vec<RsaPrivateKey>) {
-        let k1 = crate::cli::key_from_seedphrase(&Zeroizing::new(
-            "abstract gap pumpkin exchange crawl rapid grief glad private people popular harsh"
-                .into(),
-        ))
-        .unwrap();
-        let k2 = crate::cli::key_from_seedphrase(&Zeroizing::new(
-            "hole fall spin vote bracket relax dolphin trumpet trick elbow wise force".into(),
-        ))
-        .unwrap();
-        let k3 = crate::cli::key_from_seedphrase(&Zeroizing::new(
-            "shell peasant gorilla disorder state gate worth narrow afford liar pilot evil".into(),
-        ))
-        .unwrap();
-        let k4 = crate::cli::key_from_seedphrase(&Zeroizing::new(
-            "abstract gap pumpkin exchange crawl rapid grief glad private people popular harsh"
-                .into(),
-        ))
-        .unwrap();
+    // the ledger with the buffered transactions applied oldest first, except the one with the hash, and the
+    // hashes of those that could be applied. A transaction paid for by a younger one is applied after it
+    fn apply_mempool(&self, except: Option<[u8; 32]>) -> (Ledger, HashSet<[u8; 32]>) {
+        let new_depth = self.best_path_head().depth + 1;
+        let timeslot = self.calculate_timeslot();
+        let mut pending = self.ledger.clone();
+        let mut applied = HashSet::new();
+        let mut remaining: Vec<_> = self
+            .transaction_buffer
+            .iter()
+            .filter(|t| Some(t.hash) != except && !t.is_expired(timeslot))
+            .collect();
+        loop {
+            let before = applied.len();
+            remaining.retain(|t| {
+                if !pending.process_transaction(t, new_depth) {
+                    return true;
+                }
+                applied.insert(t.hash);
+                false
+            });
+            if applied.len() == before {
+                break;
+            }
+        }
+        (pending, applied)
+    }
 
-        let root_accounts = vec![
-            k1.to_public_key(),
-            k2.to_public_key(),
-            k3.to_public_key(),
-            k4.to_public_key(),
-        ];
+    /// The ledger at the tip with the buffered transactions applied, new transactions are checked against it
+    pub fn pending_ledger(&self) -> &Ledger {
+        &self.pending_ledger
+    }
 
-        let blockchain = Blockchain::start(root_accounts, &k1);
+    /// The nonce the next transaction of the account should have, after its pending transactions
+    pub fn next_nonce(&self, account: &AccountId) -> u64 {
+        self.pending_ledger.next_nonce(account)
+    }
 
-        (blockchain, vec![k1, k2, k3, k4])
+    /// The balance that can be spent in the next block after the pending transactions
+    pub fn get_spendable_balance(&self, account: &AccountId) -> u64 {
+        let new_depth = self.best_path_head().depth + 1;
+        self.pending_ledger.get_spendable_balance(account, new_depth)
     }
 
-    #[test]
-    fn produce_block() {
-        let (mut blockchain, keys) = create_dummy_blockchain();
+    /// A transaction emptying the account of `sk` into `to`, the pending transactions of the account are paid first
+    pub fn create_sweep(&self, sk: &SecretKey, to: PublicKey) -> Result<Transaction> {
+        let from = sk.to_public_key();
+        let account = AccountId::from(&from);
+        let balance = self.get_spendable_balance(&account);
+        Transaction::sweep_with_fee(from, to, sk, balance, self.params.transaction_fee, self.next_nonce(&account))
+    }
 
-        let max_attempts = 200;
+    pub fn get_transaction_status(&self, id: &TxId) -> TxStatus {
+        // the ledger knows whether it is applied, the index knows in which block
+        if let Some((block, depth)) = self.confirmed.get(id).filter(|_| self.ledger.is_applied(id)) {
+            return TxStatus::Confirmed {
+                block: *block,
+                confirmations: self.tip().depth - depth + 1,
+            };
+        }
+        if self.transaction_buffer.iter().any(|t| t.id() == *id) {
+            return TxStatus::Pending;
+        }
+        TxStatus::Unknown
+    }
 
-        assert_eq!(
-            blockchain.produce_new_block_on_best_path(&keys[0], max_attempts),
-            Ok(())
-        );
-        assert_eq!(blockchain.verify_chain(), Ok(()));
-        assert_eq!(blockchain.verify_seeds(), Ok(()));
+    pub fn mempool_len(&self) -> usize {
+        self.transaction_buffer.len()
     }
 
-    #[test]
-    fn produce_max_age_blocks() {
-        let (mut blockchain, keys) = create_dummy_blockchain();
+    pub fn set_max_mempool_size(&mut self, max_size: usize) {
+        self.buffer_limits.max_mempool_size = max_size;
+        self.transaction_buffer.set_max_size(max_size);
+        self.purge_mempool();
+    }
 
-        let max_attempts = 1000;
+    pub fn set_max_mempool_bytes(&mut self, max_bytes: usize) {
+        self.buffer_limits.max_mempool_bytes = max_bytes;
+        self.transaction_buffer.set_max_bytes(max_bytes);
+        self.purge_mempool();
+    }
 
-        for i in 0..(MAX_SEED_AGE + 2) {
-            eprintln!("iter i {i}");
-            assert_eq!(
-                blockchain.produce_new_block_on_best_path(&keys[0], max_attempts),
-                Ok(())
-            );
-            assert_eq!(blockchain.verify_chain(), Ok(()));
-            assert_eq!(blockchain.verify_seeds(), Ok(()));
+    /// Sets how many transactions and blocks that are not on the chain are held, the orphans and
+    /// future blocks already held are kept
+    pub fn set_buffer_limits(&mut self, limits: BufferLimits) {
+        self.set_max_mempool_size(limits.max_mempool_size);
+        self.set_max_mempool_bytes(limits.max_mempool_bytes);
+        self.buffer_limits = limits;
+    }
+
+    pub fn buffer_limits(&self) -> &BufferLimits {
+        &self.buffer_limits
+    }
+
+    /// Blocks more than `finality_depth` below the tip are final, the forks they have beaten are pruned
+    fn advance_checkpoint(&mut self) {
+        let Some(final_depth) = self.best_path_head().depth.checked_sub(self.finality_depth) else {
+            return;
+        };
+        let old_depth = self.checkpoint.1;
+        if final_depth <= old_depth {
+            return;
+        }
+
+        // the snapshot follows the best path up to the new checkpoint
+        for depth in old_depth + 1..=final_depth {
+            let ptr = self.best_path[depth as usize];
+            let block = self.blocks[depth as usize].get(&ptr.0).expect("unreachable");
+            for t in block.transactions.iter() {
+                self.checkpoint_ledger.process_transaction(t, depth);
+            }
+            self.checkpoint_ledger
+                .reward_winner(&block.winner_id(), self.params.block_reward, depth)
+                .expect("invariant: the total money supply fits in a u64");
+            // only the best path is kept at final depths
+            self.blocks[depth as usize].retain(|hash, _| *hash == ptr.0);
+        }
+        self.checkpoint = self.best_path[final_depth as usize];
+        self.prune_bodies();
+        let lowest = (final_depth + 1).saturating_sub(self.params.stake_lookback);
+        self.stake_ledgers = self.stake_ledgers.split_off(&lowest);
+
+        // and so are only the blocks above that descend from the checkpoint
+        for depth in (final_depth + 1) as usize..self.blocks.len() {
+            let (below, above) = self.blocks.split_at_mut(depth);
+            let parents = &below[depth - 1];
+            above[0].retain(|_, block| parents.contains_key(&block.prev_hash));
+        }
+        // a pruned fork may have been deeper than the best path
+        while self.blocks.last().is_some_and(HashMap::is_empty) {
+            self.blocks.pop();
         }
+        let blocks = &self.blocks;
+        self.seen_slots.retain(|_, (hash, depth)| {
+            blocks
+                .get(*depth as usize)
+                .is_some_and(|map| map.contains_key(hash))
+        });
+        self.epoch_seeds.retain(|(hash, depth), _| {
+            blocks
+                .get(*depth as usize)
+                .is_some_and(|map| map.contains_key(hash))
+        });
+        // no branch can leave the best path below the checkpoint, so the weights there are not compared anymore
+        self.weights.retain(|(hash, depth), _| {
+            *depth >= final_depth
+                && blocks
+                    .get(*depth as usize)
+                    .is_some_and(|map| map.contains_key(hash))
+        });
+        self.orphans.retain(|_, orphans| {
+            orphans.retain(|orphan| orphan.block.depth > final_depth);
+            !orphans.is_empty()
+        });
+    }
+
+    /// The deepest final block
+    pub fn checkpoint(&self) -> BlockPtr {
+        self.checkpoint
+    }
+
+    /// Sets how many blocks below the tip a block becomes final
+    pub fn set_finality_depth(&mut self, depth: u64) {
+        self.finality_depth = depth;
+        self.advance_checkpoint();
+    }
+
+    pub(crate) fn history(&self) -> &AccountHistory {
+        &self.history
+    }
+
+    /// Sets how many blocks apart the ledgers for balances at past depths are kept,
+    /// the kept ledgers that are not at a multiple of it are dropped
+    pub fn set_history_interval(&mut self, blocks: u64) {
+        self.history_interval = blocks.max(1);
+        let interval = self.history_interval;
+        self.ledger_history.retain(|depth, _| depth.is_multiple_of(interval));
+    }
+
+    // the ledger is the one after the block at this depth on the best path
+    fn record_history(&mut self, depth: u64) {
+        if depth.is_multiple_of(self.history_interval) {
+            self.ledger_history.insert(depth, self.ledger.clone());
+        }
+    }
+
+    /// A light chain keeps only the headers of final blocks
+    pub fn set_light(&mut self, light: bool) {
+        self.set_prune_depth(light.then_some(0));
+    }
+
+    pub fn is_light(&self) -> bool {
+        self.prune_depth.is_some()
+    }
+
+    /// Drops the transactions of the final blocks more than `depth` blocks below the tip, their headers stay.
+    /// The ledger, the history of the accounts and the checks of `verify_chain` start from the checkpoint,
+    /// so they do not need them. None keeps every block whole from now on
+    pub fn set_prune_depth(&mut self, depth: Option<u64>) {
+        self.prune_depth = depth;
+        self.prune_bodies();
+    }
+
+    pub fn prune_depth(&self) -> Option<u64> {
+        self.prune_depth
+    }
+
+    fn prune_bodies(&mut self) {
+        let Some(prune_depth) = self.prune_depth else {
+            return;
+        };
+        let Some(below) = self.best_path_head().depth.checked_sub(prune_depth) else {
+            return;
+        };
+        let prunable = below.min(self.checkpoint.1);
+        for depth in self.pruned_to + 1..=prunable {
+            let ptr = self.best_path[depth as usize];
+            let map = &mut self.blocks[depth as usize];
+            let header_only = Arc::new(map[&ptr.0].without_body());
+            map.insert(ptr.0, header_only);
+        }
+        self.pruned_to = self.pruned_to.max(prunable);
+    }
+
+    /// Sets whether we produce blocks without transactions, see `ProductionPolicy`
+    pub fn set_production_policy(&mut self, policy: ProductionPolicy) {
+        self.production = policy;
+    }
+
+    /// The number of blocks in the tree, including forks
+    pub fn block_count(&self) -> usize {
+        self.blocks.iter().map(HashMap::len).sum()
+    }
+
+    /// The depth of the deepest block in the tree, a fork that was refused or is lighter may be deeper than the tip
+    pub fn max_depth(&self) -> u64 {
+        self.blocks.iter().rposition(|map| !map.is_empty()).unwrap_or_default() as u64
+    }
+
+    /// Switches the best path from the `from` head to the `to` head.
+    /// Both heads are walked back to their common ancestor first, then the old branch is undone down to it
+    /// and the new branch is applied, the branches may have any lengths
+    pub fn rollback(&mut self, from: BlockPtr, to: BlockPtr) {
+        assert_eq!(from, self.best_path_head().ptr(), "the rollback starts at the head of the best path");
+        let _span = tracing::info_span!(
+            target: logging::BLOCKCHAIN,
+            "rollback",
+            from = %hex::encode(from.0),
+            from_depth = from.1,
+            to = %hex::encode(to.0),
+            to_depth = to.1
+        )
+        .entered();
+        let blocks = &self.blocks;
+        let get_block = |(hash, depth): BlockPtr| {
+            blocks
+                .get(depth as usize)
+                .and_then(|m| m.get(&hash))
+                .unwrap()
+        };
+        let parent = |ptr: BlockPtr| {
+            let block = get_block(ptr);
+            let depth = block
+                .depth
+                .checked_sub(1)
+                .expect("invariant: the branches share the genesis block");
+            (block.prev_hash, depth)
+        };
+
+        // the deeper head steps back until both are at the same block
+        let (mut undo, mut redo) = (Vec::new(), Vec::new());
+        let (mut from_ptr, mut to_ptr) = (from, to);
+        while from_ptr != to_ptr {
+            if from_ptr.1 >= to_ptr.1 {
+                undo.push(from_ptr);
+                from_ptr = parent(from_ptr);
+            } else {
+                redo.push(to_ptr);
+                to_ptr = parent(to_ptr);
+            }
+        }
+
+        // the old branch is the best path above the common ancestor, we undo it from its head
+        let timeslot = self.calculate_timeslot();
+        let mut rolled_back = 0;
+        for ptr in undo {
+            let block = get_block(ptr);
+            self.best_path.pop();
+            self.stake_ledgers.remove(&block.depth);
+            self.ledger_history.remove(&block.depth);
+            self.history.remove_block(block);
+            self.watchers.disconnect_block(block);
+            self.ledger
+                .rollback_reward(&block.winner_id(), self.params.block_reward, block.depth)
+                .expect(
+                    "invariant: the winner of a block on the best path has received its reward",
+                );
+            for t in block.transactions.iter().rev() {
+                self.ledger.rollback_transaction(t, block.depth).expect(
+                    "invariant: transactions on the best path have been applied to the ledger",
+                );
+                self.confirmed.remove(&t.id());
+                if self.relay_policy.allows(t) {
+                    let _ = self.transaction_buffer.insert(t.clone(), timeslot); // we have to readd the transactions to the buffer
+                }
+            }
+            self.emit(ChainEvent::BlockDisconnected(Arc::clone(block)));
+            rolled_back += 1;
+        }
+
+        // the new branch is applied from the common ancestor to its head
+        while let Some(ptr) = redo.pop() {
+            let block = get_block(ptr);
+            for t in block.transactions.iter() {
+                self.ledger.process_transaction(t, block.depth);
+                self.transaction_buffer.remove(t);
+            }
+            self.ledger
+                .reward_winner(&block.winner_id(), self.params.block_reward, block.depth)
+                .expect("invariant: the total money supply fits in a u64");
+            self.stake_ledgers.insert(block.depth, self.ledger.clone());
+            if block.depth.is_multiple_of(self.history_interval) {
+                self.ledger_history.insert(block.depth, self.ledger.clone());
+            }
+            self.history.add_block(block, self.params.block_reward);
+            self.watchers.connect_block(block);
+            self.best_path.push(ptr);
+            self.emit(ChainEvent::BlockConnected(Arc::clone(block)));
+            for t in block.transactions.iter() {
+                self.confirmed.insert(t.id(), ptr);
+                self.emit(ChainEvent::TransactionConfirmed(t.hash));
+            }
+        }
+
+        tracing::debug!(target: logging::BLOCKCHAIN, rolled_back, "switched branch");
+        self.emit(ChainEvent::Reorg {
+            old_tip: from,
+            new_tip: to,
+            depth: rolled_back,
+        });
+    }
+
+    /// Simply checks if you've won
+    pub fn stake(&self, draw: &Draw, wallet: &PublicKey, depth: u64) -> bool {
+        let Some(ledger) = self.stake_ledger(depth) else {
+            return false;
+        };
+        self.wins(ledger, draw, wallet, depth, &self.hardness_at(depth))
+    }
+
+    // the `always_win` feature at runtime on a dev chain
+    fn wins(&self, ledger: &Ledger, draw: &Draw, wallet: &PublicKey, depth: u64, hardness: &BigUint) -> bool {
+        self.dev.is_some() || is_winner(ledger, draw, wallet, depth, hardness)
+    }
+
+    /// Makes this the chain of a single dev node: every draw wins and `dev_mine` produces blocks at once.
+    /// It is a choice of the node that is not sent with the chain, see `NodeBuilder::dev`
+    pub fn set_dev_mode(&mut self) {
+        if self.dev.is_some() {
+            return;
+        }
+        let clock = SkippingClock::new(Arc::clone(&self.clock));
+        self.clock = Arc::new(clock.clone());
+        self.dev = Some(clock);
+    }
+
+    pub fn is_dev(&self) -> bool {
+        self.dev.is_some()
+    }
+
+    /// Produces the blocks on the tip of a dev chain at once, the clock skips to the next timeslot for each
+    pub fn dev_mine(&mut self, sk: &SecretKey, blocks: u64) -> Result<Vec<Arc<Block>>> {
+        let Some(clock) = self.dev.clone() else {
+            return BlockchainError::NotDevChain.into();
+        };
+        let mut mined = Vec::new();
+        for _ in 0..blocks {
+            let tip = self
+                .get_block(&self.best_path_head().ptr())
+                .expect("invariant: the head of the best path is in the block tree")
+                .timeslot;
+            let timeslot = self.calculate_timeslot();
+            if timeslot <= tip {
+                clock.skip((tip + 1 - timeslot) * self.params.slot_length_ms);
+            }
+            let timeslot = self.calculate_timeslot();
+            let prev_hash = self.get_best_hash();
+            let draw = Draw::new(timeslot, prev_hash, self.get_next_seed(timeslot), sk.to_public_key(), sk);
+            let block = Arc::new(self.get_new_block(prev_hash, draw, sk));
+            self.add_block(Arc::clone(&block))?;
+            mined.push(block);
+        }
+        Ok(mined)
+    }
+
+    /// The ledger the lottery for a block at this depth on the best path is drawn with.
+    /// It is `stake_lookback` blocks old, so money moved right before a timeslot does not change the odds
+    pub fn stake_ledger(&self, depth: u64) -> Option<&Ledger> {
+        self.stake_ledgers
+            .get(&depth.saturating_sub(self.params.stake_lookback))
+    }
+
+    /// The hardness a block at this depth on top of the best path must beat
+    pub fn hardness_at(&self, depth: u64) -> BigUint {
+        difficulty::hardness_at(
+            &self.params.hardness,
+            |d| {
+                self.get_block(&self.best_path[d as usize])
+                    .unwrap()
+                    .timeslot
+            },
+            depth,
+        )
+    }
+
+    pub fn get_balance(&self, account: &AccountId) -> u64 {
+        self.ledger.get_balance(account)
+    }
+
+    /// The balance of the account after the block at this depth on the best path.
+    /// The blocks since the nearest kept ledger below are replayed, so it is None above the tip
+    /// and where their transactions are gone, below the checkpoint of a light chain or a snapshot
+    pub fn get_balance_at(&self, account: &AccountId, depth: u64) -> Option<u64> {
+        if depth > self.best_path_head().depth {
+            return None;
+        }
+        if let Some(ledger) = self.stake_ledgers.get(&depth) {
+            return Some(ledger.get_balance(account));
+        }
+        let kept = self.ledger_history.range(..=depth).next_back();
+        let checkpoint = (self.checkpoint.1 <= depth).then_some((&self.checkpoint.1, &self.checkpoint_ledger));
+        let (&from, ledger) = kept.into_iter().chain(checkpoint).max_by_key(|(depth, _)| **depth)?;
+        let mut ledger = ledger.clone();
+        for depth in from + 1..=depth {
+            let block = self.best_path_block(depth)?;
+            if !block.has_body() {
+                return None;
+            }
+            for t in block.transactions.iter() {
+                ledger.process_transaction(t, depth);
+            }
+            ledger
+                .reward_winner(&block.winner_id(), self.params.block_reward, depth)
+                .ok()?;
+        }
+        Some(ledger.get_balance(account))
+    }
+
+    fn verify_seeds(&self) -> Result<()> {
+        match self.first_bad_seed() {
+            Some(_) => BlockchainError::InvalidSeed.into(),
+            None => Ok(()),
+        }
+    }
+
+    /// Audits the ledger and checks that it holds exactly the root allocations and block rewards minus the fees
+    pub fn audit_ledger(&self) -> Vec<LedgerViolation> {
+        let mut violations = self.ledger.audit();
+        let expected = (self.params.root_amount as u128 * self.root_accounts.len() as u128)
+            + (self.params.block_reward as u128 * self.ledger.rewarded_blocks() as u128);
+        let minted = self.ledger.minted();
+        if expected != minted as u128 {
+            violations.push(LedgerViolation::UnexpectedMinting {
+                expected: u64::try_from(expected).unwrap_or(u64::MAX),
+                minted,
+            });
+        }
+        violations
+    }
+
+    /// Whether the entire blockchain follows the rules
+    pub fn is_valid(&self) -> bool {
+        self.verify_chain().is_ok()
+    }
+
+    /// Verifies that the entire blockchain follows the rules, or reports the first rule it breaks
+    pub fn verify_chain(&self) -> std::result::Result<(), ChainVerifyReport> {
+        let tip = self.best_path_head().ptr();
+        let report = |block: BlockPtr, violation: ChainViolation| Err(ChainVerifyReport { block, violation });
+
+        let violations = self.audit_ledger();
+        if !violations.is_empty() {
+            return report(tip, ChainViolation::LedgerAudit(violations));
+        }
+        if !self.check_best_path() {
+            return report(tip, ChainViolation::BestPath);
+        }
+
+        // there must be exactly 1 genesis block
+        let genesis_block = {
+            let mut blocks = self.blocks[0].values();
+            if blocks.len() == 1 {
+                (blocks.next().unwrap().hash, 0)
+            } else {
+                return report(self.best_path[0], ChainViolation::BadGenesis);
+            }
+        };
+
+        let get_parent_ptr = |ptr: &BlockPtr| {
+            let block = self.get_block(ptr)?;
+            Some((block.prev_hash, block.depth.checked_sub(1)?))
+        };
+
+        let get_block = |ptr: &BlockPtr| self.blocks[ptr.1 as usize].get(&ptr.0).unwrap();
+
+        // we walk from the head to the checkpoint to get a verifiable path, everything below it is final
+        let mut track_stack = Vec::new();
+        let mut walking_ptr = self.best_path_head().ptr();
+        while walking_ptr != self.checkpoint {
+            if walking_ptr.1 <= self.checkpoint.1 {
+                return report(walking_ptr, ChainViolation::BestPath);
+            }
+            track_stack.push(walking_ptr);
+            let Some(parent_ptr) = get_parent_ptr(&walking_ptr) else {
+                return report(walking_ptr, ChainViolation::BestPath);
+            };
+            if self.get_block(&parent_ptr).is_none() {
+                return report(walking_ptr, ChainViolation::HashLinkage);
+            }
+            walking_ptr = parent_ptr;
+        }
+        // now the track_stack contains all on the best path above the checkpoint
+        // we will also track a ledger, starting from the snapshot, to see if it matches the proposed ledger
+        // we then check the track_stack
+        let mut track_ledger = self.checkpoint_ledger.clone();
+        let checkpoint_root = *get_block(&self.checkpoint).state_root();
+        if track_ledger.state_root() != checkpoint_root {
+            return report(
+                self.checkpoint,
+                ChainViolation::LedgerMismatch {
+                    expected_root: checkpoint_root,
+                    actual_root: track_ledger.state_root(),
+                },
+            );
+        }
+        let applied_transactions = HashSet::new();
+        let mut track_confirmed: HashMap<_, _> = self
+            .confirmed
+            .iter()
+            .filter(|(_, (_, depth))| *depth <= self.checkpoint.1)
+            .map(|(id, ptr)| (*id, *ptr))
+            .collect();
+        let mut prev_ptr = self.checkpoint;
+        let mut prev_ts = get_block(&self.checkpoint).timeslot;
+        // the timeslots at each depth, used for the hardness
+        let mut path_timeslots: Vec<_> = self.best_path[..=self.checkpoint.1 as usize]
+            .iter()
+            .map(|ptr| get_block(ptr).timeslot)
+            .collect();
+        // the ledgers the lotteries are drawn with, those below the checkpoint are final
+        let mut stake_ledgers: BTreeMap<_, _> = self
+            .stake_ledgers
+            .range(..=self.checkpoint.1)
+            .map(|(depth, ledger)| (*depth, ledger.clone()))
+            .collect();
+        let genesis_block = get_block(&genesis_block);
+        // the weights are summed from the checkpoint as the branch is walked up
+        let mut track_weight = match self.weights.get(&self.checkpoint) {
+            Some(weight) => weight.clone(),
+            None => return report(self.checkpoint, ChainViolation::Weight),
+        };
+        while let Some((block_hash, depth)) = track_stack.pop() {
+            let ptr = (block_hash, depth);
+            let block = get_block(&ptr);
+            if block.timeslot <= prev_ts {
+                return report(ptr, ChainViolation::TimeslotOrder);
+            }
+            prev_ts = block.timeslot;
+            let hardness = difficulty::hardness_at(
+                &self.params.hardness,
+                |d| path_timeslots[d as usize],
+                block.depth,
+            );
+            path_timeslots.push(block.timeslot);
+
+            if block.prev_hash != prev_ptr.0 {
+                return report(ptr, ChainViolation::HashLinkage);
+            }
+            if self.check_block_size(block).is_err() {
+                return report(ptr, ChainViolation::TooLarge);
+            }
+            if self.check_key_sizes(block).is_err() {
+                return report(ptr, ChainViolation::KeyTooSmall);
+            }
+            if !block.draw.verify(&block.draw.seed, &block.prev_hash, block.timeslot) {
+                return report(ptr, ChainViolation::DrawMismatch);
+            }
+            // blocks we only have the header of are checked as far as the header allows
+            let verified = if block.has_body() {
+                block.verify_all(&applied_transactions)
+            } else {
+                block.header().verify_signature() && block.verify_winner()
+            };
+            if !verified {
+                let violation = match signature_error(block.header()) {
+                    BlockchainError::ProposerMismatch => ChainViolation::ProposerMismatch,
+                    _ => ChainViolation::BadSignature,
+                };
+                return report(ptr, violation);
+            }
+
+            let winner = &block.draw.signed_by;
+            let stake_depth = block.depth.saturating_sub(self.params.stake_lookback);
+            if !stake_ledgers.get(&stake_depth).is_some_and(|stake_ledger| {
+                self.wins(stake_ledger, &block.draw, winner, block.depth, &hardness)
+            }) {
+                return report(ptr, ChainViolation::NotWinner);
+            }
+            // the weight comes from the draw, so it is only summed once the draw holds
+            track_weight += difficulty::block_weight(&block.draw.value, &hardness);
+            if self.weights.get(&ptr) != Some(&track_weight) {
+                return report(ptr, ChainViolation::Weight);
+            }
+
+            if let Some(t) = block.transactions.iter().find(|t| t.is_expired(block.timeslot)) {
+                return report(ptr, ChainViolation::ExpiredTransaction { txid: t.id() });
+            }
+            // we process the transactions for the track ledger and they must all be valid
+            if let Some(t) = block
+                .transactions
+                .iter()
+                .find(|t| !track_ledger.process_transaction(t, block.depth))
+            {
+                return report(ptr, ChainViolation::InvalidTransaction { txid: t.id() });
+            };
+
+            if track_ledger
+                .reward_winner(&block.winner_id(), self.params.block_reward, block.depth)
+                .is_err()
+            {
+                return report(ptr, ChainViolation::RewardOverflow);
+            }
+            if block.has_body() && track_ledger.state_root() != *block.state_root() {
+                return report(
+                    ptr,
+                    ChainViolation::LedgerMismatch {
+                        expected_root: *block.state_root(),
+                        actual_root: track_ledger.state_root(),
+                    },
+                );
+            }
+            for t in block.transactions.iter() {
+                track_confirmed.insert(t.id(), (block_hash, depth));
+            }
+            stake_ledgers.insert(depth, track_ledger.clone());
+
+            prev_ptr = ptr;
+        }
+
+        // we then check the genesis block
+        if !genesis_block.transactions.is_empty()
+            || !genesis_block
+                .verify_genesis(&Self::produce_root_seed(&self.root_accounts, &self.params, self.start_time))
+        {
+            return report((genesis_block.hash, 0), ChainViolation::BadGenesis);
+        }
+
+        // the roots cover everything in the ledgers, so they differ exactly when the ledgers do
+        let (expected_root, actual_root) = (track_ledger.state_root(), self.ledger.state_root());
+        if expected_root != actual_root {
+            return report(
+                tip,
+                ChainViolation::LedgerMismatch {
+                    expected_root,
+                    actual_root,
+                },
+            );
+        }
+
+        if self.confirmed != track_confirmed {
+            return report(tip, ChainViolation::TransactionIndex);
+        }
+        if self.stake_ledgers != stake_ledgers {
+            return report(tip, ChainViolation::StakeLedgers);
+        }
+        if let Some(ptr) = self.first_bad_seed() {
+            return report(ptr, ChainViolation::EpochSeed);
+        }
+        for (depth, ledger) in self.ledger_history.iter() {
+            let Some(block) = self.best_path_block(*depth) else {
+                return report(tip, ChainViolation::LedgerHistory);
+            };
+            if ledger.state_root() != *block.state_root() {
+                return report((block.hash, *depth), ChainViolation::LedgerHistory);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// checks that the best_path head is the correct one, it must outweigh every other block in the tree
+    /// except those on branches that leave the best path deeper than a reorg may go
+    pub fn check_best_path(&self) -> bool {
+        let Some(tip) = self.best_path.last() else {
+            tracing::warn!(target: logging::BLOCKCHAIN, "no best path");
+            return false;
+        };
+        if self.get_block(tip).is_none() || !self.weights.contains_key(tip) {
+            let tip = hex::encode(tip.0);
+            tracing::warn!(target: logging::BLOCKCHAIN, %tip, "the head of the best path is not weighed");
+            return false;
+        }
+        self.blocks
+            .iter()
+            .flat_map(HashMap::values)
+            .map(|block| (block.hash, block.depth))
+            .filter(|ptr| {
+                // where the branch of the block leaves the best path, a refused reorg waits for `force_reorg`
+                let mut fork = *ptr;
+                while self.best_path.get(fork.1 as usize) != Some(&fork) {
+                    match self.get_block(&fork) {
+                        Some(block) if fork.1 > 0 => fork = (block.prev_hash, fork.1 - 1),
+                        _ => return true,
+                    }
+                }
+                tip.1 - fork.1 <= self.max_reorg_depth
+            })
+            .all(|ptr| ptr == *tip || self.cmp_branches(tip, &ptr).is_gt())
+    }
+
+    pub fn get_best_hash(&self) -> BlockHash {
+        self.best_path_head().hash
+    }
+
+    fn get_best_block(&self) -> &Arc<Block> {
+        self.get_block(&self.best_path_head().ptr()).expect("unreachable")
+    }
+
+    fn get_block(&self, ptr: &BlockPtr) -> Option<&Arc<Block>> {
+        self.block_at(&ptr.0, ptr.1)
+    }
+
+    fn get_parent(&self, block: &Block) -> Option<&Arc<Block>> {
+        let block_ptr = (block.prev_hash, block.depth.checked_sub(1)?);
+        self.get_block(&block_ptr)
+    }
+
+    /// The seed of the draws in the timeslot on top of the best path
+    fn get_next_seed(&self, timeslot: Timeslot) -> SeedContent {
+        self.epoch_seed(self.get_best_block(), timeslot)
+            .expect("invariant: the blocks in the tree have epoch seeds")
+    }
+
+    /// The seed of the epoch of the timeslot for a block on top of the parent
+    pub(crate) fn epoch_seed(&self, parent: &Block, timeslot: Timeslot) -> Option<SeedContent> {
+        let epoch = epoch_of(timeslot);
+        if epoch == 0 {
+            return Some(self.get_block(&self.best_path[0])?.draw.seed.clone());
+        }
+        // the last block before the epoch fixes its seed
+        let mut boundary = parent;
+        while epoch_of(boundary.timeslot) >= epoch {
+            boundary = self.get_parent(boundary)?;
+        }
+        let seed = self.seed_of_epoch(boundary, epoch)?;
+        Some(SeedContent::new((boundary.hash, boundary.depth), seed))
+    }
+
+    // the seed of an epoch on a path where the boundary is the last block before it
+    fn seed_of_epoch(&self, boundary: &Block, epoch: u64) -> Option<[u8; 32]> {
+        let mut seed = *self.epoch_seeds.get(&(boundary.hash, boundary.depth))?;
+        // the epochs without blocks only hash the seed before them
+        for empty in epoch_of(boundary.timeslot) + 2..=epoch {
+            seed = seeding_mechanism::next_seed(&seed, empty, []);
+        }
+        Some(seed)
+    }
+
+    // the seed of the epoch after the one of the block, if it is the last block of its epoch
+    fn record_epoch_seed(&mut self, ptr: BlockPtr) {
+        let Some(block) = self.get_block(&ptr) else {
+            return;
+        };
+        let epoch = epoch_of(block.timeslot);
+        let mut draws = vec![&block.draw];
+        let mut below = self.get_parent(block);
+        while let Some(parent) = below.filter(|parent| epoch_of(parent.timeslot) == epoch) {
+            draws.push(&parent.draw);
+            below = self.get_parent(parent);
+        }
+        // the first epoch has no block below it and uses the seed of the root accounts
+        let seed = match below {
+            Some(boundary) => self.seed_of_epoch(boundary, epoch),
+            None => self.get_block(&self.best_path[0]).map(|genesis| genesis.draw.seed.seed),
+        };
+        if let Some(seed) = seed {
+            let next = seeding_mechanism::next_seed(&seed, epoch + 1, draws.into_iter().rev());
+            self.epoch_seeds.insert(ptr, next);
+        }
+    }
+
+    // the epoch seeds of the best path worked out again from the genesis block, returns the first block
+    // whose draw or cached seed does not match
+    fn first_bad_seed(&self) -> Option<BlockPtr> {
+        let genesis = self.get_block(&self.best_path[0])?;
+        let mut seed = genesis.draw.seed.clone();
+        let mut epoch = 0;
+        let mut draws = Vec::new();
+        let mut last = genesis;
+        for ptr in self.best_path.iter() {
+            let Some(block) = self.get_block(ptr) else {
+                return Some(*ptr);
+            };
+            let block_epoch = epoch_of(block.timeslot);
+            if block_epoch > epoch {
+                let mut next = seeding_mechanism::next_seed(&seed.seed, epoch + 1, draws.drain(..));
+                for empty in epoch + 2..=block_epoch {
+                    next = seeding_mechanism::next_seed(&next, empty, []);
+                }
+                seed = SeedContent::new((last.hash, last.depth), next);
+                epoch = block_epoch;
+            }
+            draws.push(&block.draw);
+            last = block;
+
+            let after = seeding_mechanism::next_seed(&seed.seed, epoch + 1, draws.iter().copied());
+            if block.draw.seed != seed || self.epoch_seeds.get(ptr) != Some(&after) {
+                return Some(*ptr);
+            }
+        }
+        None
+    }
+
+    /// The draw for the current timeslot on the tip, the last one in the cache is reused if neither has changed
+    pub fn get_draw(&self, sk: &SecretKey, draws: &mut DrawCache) -> Draw {
+        let timeslot = self.calculate_timeslot();
+        let vk = sk.to_public_key();
+        draws.get_or_sign(timeslot, self.get_best_hash(), &vk, || {
+            Draw::new(timeslot, self.get_best_hash(), self.get_next_seed(timeslot), vk.clone(), sk)
+        })
+    }
+
+    /// Draws for the current timeslot and builds a signed block on the tip if we won.
+    /// None if we lost, the tip is already from this timeslot or the block would be empty and we skip those
+    pub fn try_produce(&self, sk: &SecretKey, draws: &mut DrawCache) -> Option<Block> {
+        self.try_produce_any(std::slice::from_ref(sk), std::slice::from_mut(draws))
+    }
+
+    /// Draws with every key, each with its own cache, and builds one block with the best winning draw,
+    /// the one that weighs the most. A node staking with several accounts competes with itself otherwise
+    pub fn try_produce_any(&self, keys: &[SecretKey], draws: &mut [DrawCache]) -> Option<Block> {
+        assert_eq!(keys.len(), draws.len(), "every key has its own draw cache");
+        let TipRef { hash: tip_hash, depth: tip_depth } = self.best_path_head();
+        let tip = self.get_block(&(tip_hash, tip_depth))?;
+        let (draw, sk) = keys
+            .iter()
+            .zip(draws.iter_mut())
+            .map(|(sk, draws)| (self.get_draw(sk, draws), sk))
+            .filter(|(draw, sk)| {
+                draw.timeslot > tip.timeslot && self.stake(draw, &sk.to_public_key(), tip_depth + 1)
+            })
+            .max_by(|(a, _), (b, _)| a.value.cmp(&b.value))?;
+        // the chain still advances now and then when nothing is sent
+        let gap = draw.timeslot - tip.timeslot;
+        let block = self.get_new_block(tip_hash, draw, sk);
+        let skipped = !self.production.produce_empty_blocks && gap < self.production.max_empty_gap;
+        (!skipped || block.transaction_count() > 0).then_some(block)
+    }
+
+    pub(crate) fn get_new_block(
+        &self,
+        prev_hash: BlockHash,
+        draw: Draw,
+        sk: &SecretKey,
+    ) -> Block {
+        let mut checking_ledger = self.ledger.clone();
+        let new_depth = self.best_path_head().depth + 1;
+        // the best paying transactions are picked first in the canonical order of the mempool, of each sender only
+        // those from its next nonce on without a gap. A transaction that is not yet possible may be paid for by one
+        // picked after it, or follow one of its sender with a lower fee, so we go over the rest again until nothing more fits
+        let mut remaining: Vec<_> = self
+            .transaction_buffer
+            .iter_ready(|from| self.ledger.next_nonce(from))
+            .collect();
+        let mut transactions_buffer = Vec::new();
+        let mut size = Block::size_without_transactions(&draw) as u64;
+        loop {
+            let before = transactions_buffer.len();
+            remaining.retain(|t| {
+                // the version byte is written once for the whole block
+                let transaction_size = t.encoded_len() as u64 - 1;
+                if transactions_buffer.len() as u64 >= self.params.max_block_transactions
+                    || size + transaction_size > self.params.max_block_bytes
+                    || t.is_expired(draw.timeslot)
+                    || !checking_ledger.process_transaction(t, new_depth)
+                {
+                    return true;
+                }
+                size += transaction_size;
+                transactions_buffer.push((*t).clone());
+                false
+            });
+            if transactions_buffer.len() == before {
+                break;
+            }
+        }
+        let winner = AccountId::from(&draw.signed_by);
+        // a reward that overflows makes the block invalid anyway
+        let _ = checking_ledger.reward_winner(&winner, self.params.block_reward, new_depth);
+
+        Block::new(
+            draw.timeslot,
+            prev_hash,
+            new_depth,
+            draw.signed_by.clone(),
+            transactions_buffer,
+            checking_ledger.state_root(),
+            sk,
+            draw.seed,
+        )
+    }
+}
+
+#[cfg(test)]
+impl Blockchain {
+    // Keeps mining until winning, the clock is moved a timeslot for every attempt
+    pub(crate) fn produce_new_block_on_best_path(
+        &mut self,
+        clock: &crate::clock::ManualClock,
+        sk: &SecretKey,
+        max_attempts: u64,
+    ) -> Result<()> {
+        let wallet = sk.to_public_key();
+        let mut attempts = 1;
+        let mut draws = DrawCache::default();
+        clock.advance_slots(1);
+        let mut draw = self.get_draw(sk, &mut draws);
+        while !self.stake(&draw, &wallet, self.best_path_head().depth + 1)
+            && attempts < max_attempts
+        {
+            clock.advance_slots(1);
+            draw = self.get_draw(sk, &mut draws);
+            attempts += 1;
+        }
+
+        self.add_block(self.get_new_block(self.get_best_hash(), draw, sk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::ledger::verify_balance_proof;
+    use crate::params::FeeSink;
+    use crate::seeding_mechanism::SEED_EPOCH_SLOTS;
+    use crate::{BLOCK_REWARD, ROOT_AMOUNT, TRANSACTION_FEE};
+
+    fn create_dummy_blockchain() -> (Blockchain, Vec<SecretKey>, ManualClock) {
+        let k1 = crate::cli::key_from_seedphrase(&Zeroizing::new(
+            "abstract gap pumpkin exchange crawl rapid grief glad private people popular harsh"
+                .into(),
+        ))
+        .unwrap();
+        let k2 = crate::cli::key_from_seedphrase(&Zeroizing::new(
+            "hole fall spin vote bracket relax dolphin trumpet trick elbow wise force".into(),
+        ))
+        .unwrap();
+        let k3 = crate::cli::key_from_seedphrase(&Zeroizing::new(
+            "shell peasant gorilla disorder state gate worth narrow afford liar pilot evil".into(),
+        ))
+        .unwrap();
+        let k4 = crate::cli::key_from_seedphrase(&Zeroizing::new(
+            "abstract gap pumpkin exchange crawl rapid grief glad private people popular harsh"
+                .into(),
+        ))
+        .unwrap();
+
+        let root_accounts = vec![
+            k1.to_public_key(),
+            k2.to_public_key(),
+            k3.to_public_key(),
+            k4.to_public_key(),
+        ];
+
+        let clock = ManualClock::default();
+        let blockchain = Blockchain::start_with_clock(root_accounts, ChainParams::default(), Arc::new(clock.clone()));
+
+        (blockchain, vec![k1, k2, k3, k4], clock)
+    }
+
+    // moves the clock a timeslot before every draw, so the draw is in a later timeslot than the best block
+    fn winning_draw(
+        blockchain: &Blockchain,
+        clock: &ManualClock,
+        sk: &SecretKey,
+        depth: u64,
+    ) -> Draw {
+        let mut draws = DrawCache::default();
+        loop {
+            clock.advance_slots(1);
+            let draw = blockchain.get_draw(sk, &mut draws);
+            if blockchain.stake(&draw, &sk.to_public_key(), depth) {
+                break draw;
+            }
+        }
+    }
+
+    // a winning draw of each key, the draw whose block wins the tiebreak first
+    fn ranked_draws<'a, const N: usize>(
+        blockchain: &Blockchain,
+        clock: &ManualClock,
+        keys: [&'a SecretKey; N],
+        depth: u64,
+    ) -> [(Draw, &'a SecretKey); N] {
+        let mut draws = keys.map(|sk| (winning_draw(blockchain, clock, sk, depth), sk));
+        draws.sort_by(|(a, _), (b, _)| b.value.cmp(&a.value));
+        draws
+    }
+
+    #[test]
+    fn produce_block() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+
+        let max_attempts = 200;
+
+        assert_eq!(
+            blockchain.produce_new_block_on_best_path(&clock, &keys[0], max_attempts),
+            Ok(())
+        );
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+        assert_eq!(blockchain.verify_seeds(), Ok(()));
+    }
+
+    #[test]
+    fn a_node_staking_with_several_keys_produces_with_the_best_draw() {
+        let (sks, pks): (Vec<_>, Vec<_>) = (0..3).map(|_| crate::generate_keypair()).unzip();
+        let clock = ManualClock::default();
+        // without hardness every key wins every timeslot
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(pks.clone(), params, Arc::new(clock.clone()));
+        let mut draws: Vec<_> = sks.iter().map(|_| DrawCache::default()).collect();
+        let mut won = HashMap::new();
+        for _ in 0..12 {
+            clock.advance_slots(difficulty::TARGET_SLOTS_PER_BLOCK);
+            let best = sks
+                .iter()
+                .map(|sk| blockchain.get_draw(sk, &mut DrawCache::default()))
+                .max_by(|a, b| a.value.cmp(&b.value))
+                .unwrap();
+            let block = blockchain.try_produce_any(&sks, &mut draws).unwrap();
+            assert_eq!(block.draw.value, best.value);
+            assert_eq!(block.winner(), &best.signed_by);
+            *won.entry(AccountId::from(block.winner())).or_insert(0) += 1;
+            assert_eq!(blockchain.add_block(block), Ok(()));
+            // the one block of the timeslot is on the tip, so no key produces another
+            assert!(blockchain.try_produce_any(&sks, &mut draws).is_none());
+        }
+
+        // the rewards went to the keys that were picked
+        assert_eq!(won.values().sum::<u64>(), 12);
+        for pk in pks.iter() {
+            let account = AccountId::from(pk);
+            let blocks = won.get(&account).copied().unwrap_or(0);
+            assert_eq!(blockchain.get_balance(&account), ROOT_AMOUNT + blocks * BLOCK_REWARD);
+        }
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn produce_max_age_blocks() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+
+        let max_attempts = 1000;
+        const BLOCKS: u64 = 102;
+
+        for _ in 0..BLOCKS {
+            assert_eq!(
+                blockchain.produce_new_block_on_best_path(&clock, &keys[0], max_attempts),
+                Ok(())
+            );
+            assert_eq!(blockchain.verify_chain(), Ok(()));
+            assert_eq!(blockchain.verify_seeds(), Ok(()));
+        }
+
+        let tip = blockchain.tip();
+        assert_eq!(tip.depth, BLOCKS);
+        assert_eq!(blockchain.best_path_iter().next().unwrap().hash, tip.hash);
+        assert_eq!(blockchain.best_path_iter().count() as u64, tip.depth + 1);
+        assert!(blockchain
+            .best_path_iter()
+            .all(|b| blockchain.block_at(&b.hash, b.depth()).is_some()));
+    }
+
+    #[test]
+    fn try_produce_builds_one_block_per_timeslot() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut draws = DrawCache::default();
+        let block = loop {
+            clock.advance_slots(1);
+            if let Some(block) = blockchain.try_produce(&keys[0], &mut draws) {
+                break block;
+            }
+        };
+        assert_eq!(block.prev_hash, blockchain.get_best_hash());
+        assert_eq!(blockchain.add_block(block), Ok(()));
+        assert!(blockchain.try_produce(&keys[0], &mut draws).is_none());
+    }
+
+    #[test]
+    fn empty_blocks_are_skipped_until_the_tip_is_old() {
+        let (blockchain, sk, clock) = create_winning_blockchain();
+        let mut draws = DrawCache::default();
+        clock.advance_slots(1);
+        let empty = blockchain.try_produce(&sk, &mut draws).unwrap();
+        assert_eq!(empty.transaction_count(), 0);
+
+        let mut skipping = blockchain.clone();
+        skipping.set_production_policy(ProductionPolicy {
+            produce_empty_blocks: false,
+            max_empty_gap: 3,
+        });
+        assert!(skipping.try_produce(&sk, &mut draws).is_none());
+        // others may still produce them
+        assert_eq!(skipping.add_block(empty), Ok(()));
+
+        // a block with a transaction is produced in the next timeslot
+        let (_, receiver) = crate::generate_keypair();
+        let payment = Transaction::new(sk.to_public_key(), receiver, &sk, 10, 0).unwrap();
+        assert_eq!(skipping.add_transaction(payment), Ok(false));
+        clock.advance_slots(1);
+        let block = skipping.try_produce(&sk, &mut draws).unwrap();
+        assert_eq!(block.transaction_count(), 1);
+        assert_eq!(skipping.add_block(block), Ok(()));
+
+        // with nothing to include the tip must be max_empty_gap timeslots old
+        clock.advance_slots(2);
+        assert!(skipping.try_produce(&sk, &mut draws).is_none());
+        clock.advance_slots(1);
+        let block = skipping.try_produce(&sk, &mut draws).unwrap();
+        assert_eq!(block.transaction_count(), 0);
+        assert_eq!(block.timeslot(), Timeslot(5));
+    }
+
+    #[test]
+    fn draws_are_signed_once_per_timeslot_and_tip() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut draws = DrawCache::default();
+        clock.advance_slots(1);
+        let first = blockchain.get_draw(&keys[0], &mut draws);
+        for _ in 0..10 {
+            let again = blockchain.get_draw(&keys[0], &mut draws);
+            assert_eq!(again.hash(), first.hash());
+            let won = blockchain.stake(&first, &keys[0].to_public_key(), 1);
+            assert_eq!(blockchain.try_produce(&keys[0], &mut draws).is_some(), won);
+        }
+        assert_eq!(draws.signed(), 1);
+
+        // another key, timeslot or tip is a new draw
+        blockchain.get_draw(&keys[1], &mut draws);
+        assert_eq!(draws.signed(), 2);
+        clock.advance_slots(1);
+        blockchain.get_draw(&keys[1], &mut draws);
+        assert_eq!(draws.signed(), 3);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        blockchain.get_draw(&keys[1], &mut draws);
+        assert_eq!(draws.signed(), 4);
+    }
+
+    #[test]
+    fn mempool_is_capped() {
+        let (mut blockchain, keys, _) = create_dummy_blockchain();
+        let max_size = 5;
+        blockchain.set_max_mempool_size(max_size);
+
+        let (from, to) = (&keys[0], keys[1].to_public_key());
+        for nonce in 0..(max_size * 2) as u64 {
+            let t = Transaction::new(from.to_public_key(), to.clone(), from, 1, nonce).unwrap();
+            assert_eq!(blockchain.add_transaction(t.clone()), Ok(false));
+            assert!(blockchain.mempool_len() <= max_size);
+            assert_eq!(
+                blockchain.add_transaction(t),
+                BlockchainError::DuplicateTransaction.into()
+            );
+        }
+        assert_eq!(blockchain.mempool_len(), max_size);
+        assert_eq!(blockchain.mempool_iter().count(), max_size);
+    }
+
+    #[test]
+    fn pending_spends_limit_new_transactions() {
+        let (mut blockchain, keys, _) = create_dummy_blockchain();
+        let (_, to) = crate::generate_keypair();
+        let (sk, from) = (&keys[1], keys[1].to_public_key());
+        let from_id = AccountId::from(&from);
+        let balance = blockchain.get_balance(&from_id);
+        assert_eq!(blockchain.get_spendable_balance(&from_id), balance);
+
+        let most = Transaction::with_fee(from.clone(), to.clone(), sk, balance - 10, 1, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(most), Ok(false));
+        assert_eq!(blockchain.get_spendable_balance(&from_id), 9);
+        assert_eq!(blockchain.get_balance(&from_id), balance);
+
+        let overspend = Transaction::with_fee(from.clone(), to.clone(), sk, 20, 1, 1).unwrap();
+        assert_eq!(
+            blockchain.add_transaction(overspend),
+            TxValidationError::InsufficientFunds { spendable: 9, cost: 21 }.into()
+        );
+        let rest = Transaction::with_fee(from.clone(), to.clone(), sk, 8, 1, 1).unwrap();
+        assert_eq!(blockchain.add_transaction(rest), Ok(false));
+        assert_eq!(blockchain.get_spendable_balance(&from_id), 0);
+
+        // a replacement only has to fit next to the other pending transactions
+        let too_much = Transaction::with_fee(from.clone(), to.clone(), sk, balance - 10, 2, 0).unwrap();
+        assert_eq!(
+            blockchain.add_transaction(too_much),
+            TxValidationError::InsufficientFunds { spendable: balance - 9, cost: balance - 8 }.into()
+        );
+        let replacement = Transaction::with_fee(from.clone(), to.clone(), sk, balance - 11, 2, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(replacement), Ok(true));
+        assert_eq!(blockchain.mempool_len(), 2);
+    }
+
+    #[test]
+    fn overspends_across_the_mempool_are_refused() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let (sk, from) = (&keys[1], keys[1].to_public_key());
+        let from_id = AccountId::from(&from);
+        let receivers: Vec<_> = (0..3).map(|_| crate::generate_keypair()).collect();
+        // each of them is affordable on its own, but not all three together
+        let payments: Vec<_> = receivers
+            .iter()
+            .enumerate()
+            .map(|(nonce, (_, to))| {
+                Transaction::with_fee(from.clone(), to.clone(), sk, 100, TRANSACTION_FEE, nonce as u64).unwrap()
+            })
+            .collect();
+        assert_eq!(blockchain.add_transaction(payments[0].clone()), Ok(false));
+        assert_eq!(blockchain.add_transaction(payments[1].clone()), Ok(false));
+        let cost = 100 + TRANSACTION_FEE;
+        let left = ROOT_AMOUNT - 2 * cost;
+        assert_eq!(
+            blockchain.add_transaction(payments[2].clone()),
+            TxValidationError::InsufficientFunds { spendable: left, cost }.into()
+        );
+        assert_eq!(blockchain.mempool_len(), 2);
+        assert_eq!(blockchain.pending_ledger().get_balance(&from_id), left);
+        assert_eq!(blockchain.get_balance(&from_id), ROOT_AMOUNT);
+
+        // money that is on its way can be spent next to the transaction paying it
+        let (receiver_sk, receiver) = &receivers[0];
+        let onward = Transaction::new(receiver.clone(), keys[2].to_public_key(), receiver_sk, 50, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(onward), Ok(false));
+
+        // the pending ledger is rebuilt when the transactions are confirmed
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        assert_eq!(blockchain.best_path_block(1).unwrap().transactions().len(), 3);
+        assert_eq!(blockchain.mempool_len(), 0);
+        assert_eq!(blockchain.pending_ledger(), blockchain.ledger());
+        assert_eq!(blockchain.get_balance(&from_id), left);
+        assert_eq!(
+            blockchain.add_transaction(payments[2].clone()),
+            TxValidationError::InsufficientFunds { spendable: left, cost }.into()
+        );
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn sweeps_empty_the_account() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let (_, to) = crate::generate_keypair();
+        let (sk, from) = (&keys[1], keys[1].to_public_key());
+        let from_id = AccountId::from(&from);
+
+        let payment = Transaction::with_fee(from.clone(), to.clone(), sk, 40, 2, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment), Ok(false));
+        let sweep = blockchain.create_sweep(sk, to.clone()).unwrap();
+        assert_eq!(sweep.amount(), ROOT_AMOUNT - 42 - TRANSACTION_FEE);
+        assert_eq!(blockchain.add_transaction(sweep), Ok(false));
+        assert_eq!(
+            blockchain.create_sweep(sk, to.clone()),
+            BlockchainError::NothingToSweep.into()
+        );
+
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        assert_eq!(blockchain.mempool_len(), 0);
+        assert_eq!(blockchain.get_balance(&from_id), 0);
+        assert_eq!(blockchain.get_balance(&AccountId::from(&to)), ROOT_AMOUNT - 2 - TRANSACTION_FEE);
+
+        // the smallest payment is the fee, so a sweep needs twice the fee
+        let sweep = Transaction::sweep(from.clone(), to.clone(), sk, 2 * TRANSACTION_FEE, 2).unwrap();
+        assert_eq!((sweep.amount(), sweep.fee()), (TRANSACTION_FEE, TRANSACTION_FEE));
+        assert_eq!(
+            Transaction::sweep(from, to, sk, 2 * TRANSACTION_FEE - 1, 2),
+            BlockchainError::NothingToSweep.into()
+        );
+    }
+
+    #[test]
+    fn sequential_payments_fit_in_one_block_in_order() {
+        let (mut blockchain, keys, _) = create_dummy_blockchain();
+        let mut other = blockchain.clone();
+        let from = &keys[1];
+        let payments: Vec<_> = (0..3)
+            .map(|nonce| {
+                let (_, to) = crate::generate_keypair();
+                Transaction::with_fee(from.to_public_key(), to, from, 10, TRANSACTION_FEE, nonce).unwrap()
+            })
+            .collect();
+        for t in payments.iter() {
+            assert_eq!(blockchain.add_transaction(t.clone()), Ok(false));
+        }
+        // another node that got them in another order builds the same list
+        for t in payments.iter().rev() {
+            assert_eq!(other.add_transaction(t.clone()), Ok(false));
+        }
+
+        let draw = blockchain.get_draw(&keys[0], &mut DrawCache::default());
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw.clone(), &keys[0]);
+        let other_block = other.get_new_block(other.get_best_hash(), draw, &keys[0]);
+        let included: Vec<_> = block.transactions().iter().map(|t| t.hash).collect();
+        let expected: Vec<_> = payments.iter().map(|t| t.hash).collect();
+        assert_eq!(included, expected);
+        let other_included: Vec<_> = other_block.transactions().iter().map(|t| t.hash).collect();
+        assert_eq!(other_included, expected);
+        assert_eq!(block.merkle_root(), other_block.merkle_root());
+    }
+
+    #[test]
+    fn blocks_over_the_limits_are_refused() {
+        let (mut blockchain, keys, _) = create_dummy_blockchain();
+        blockchain.params.max_block_transactions = 2;
+        let from = &keys[1];
+        let payments: Vec<_> = (0..3)
+            .map(|nonce| {
+                let (_, to) = crate::generate_keypair();
+                Transaction::with_fee(from.to_public_key(), to, from, 10, TRANSACTION_FEE, nonce).unwrap()
+            })
+            .collect();
+        for t in payments.iter() {
+            assert_eq!(blockchain.add_transaction(t.clone()), Ok(false));
+        }
+
+        let draw = blockchain.get_draw(&keys[0], &mut DrawCache::default());
+        let empty = Block::new(
+            draw.timeslot,
+            blockchain.get_best_hash(),
+            1,
+            keys[0].to_public_key(),
+            vec![],
+            [0; 32],
+            &keys[0],
+            draw.seed.clone(),
+        );
+        assert_eq!(Block::size_without_transactions(&draw), empty.encoded_len());
+
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw.clone(), &keys[0]);
+        assert_eq!(block.transactions().len(), 2);
+        let too_many = Block::new(
+            draw.timeslot,
+            blockchain.get_best_hash(),
+            1,
+            keys[0].to_public_key(),
+            payments,
+            [0; 32],
+            &keys[0],
+            draw.seed.clone(),
+        );
+        assert_eq!(
+            blockchain.add_block(too_many),
+            BlockchainError::BlockTooLarge.into()
+        );
+
+        // one byte less and only one of them fits
+        blockchain.params.max_block_bytes = block.encoded_len() as u64 - 1;
+        let smaller = blockchain.get_new_block(blockchain.get_best_hash(), draw, &keys[0]);
+        assert_eq!(smaller.transactions().len(), 1);
+        assert!(smaller.encoded_len() as u64 <= blockchain.params.max_block_bytes);
+        assert_eq!(
+            blockchain.add_block(block),
+            BlockchainError::BlockTooLarge.into()
+        );
+    }
+
+    #[test]
+    fn new_blocks_take_the_highest_fees_first() {
+        let (mut blockchain, keys, _) = create_dummy_blockchain();
+        let (_, to) = crate::generate_keypair();
+        // the senders with their fees by nonce
+        let senders = [(&keys[0], vec![1]), (&keys[1], vec![4]), (&keys[2], vec![2, 3])];
+        let transactions: Vec<_> = senders
+            .iter()
+            .flat_map(|(from, fees)| {
+                fees.iter().enumerate().map(|(nonce, &fee)| {
+                    Transaction::with_fee(from.to_public_key(), to.clone(), from, 10, fee, nonce as u64).unwrap()
+                })
+            })
+            .collect();
+        for t in transactions.iter() {
+            assert_eq!(blockchain.add_transaction(t.clone()), Ok(false));
+        }
+
+        // bumping the fee of the cheapest transaction moves it to the front
+        let bumped = Transaction::with_fee(keys[0].to_public_key(), to.clone(), &keys[0], 10, 5, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(bumped.clone()), Ok(true));
+        assert_eq!(blockchain.mempool_len(), transactions.len());
+
+        // a transaction paying more than the one before it of its sender still follows it
+        let draw = blockchain.get_draw(&keys[0], &mut DrawCache::default());
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw, &keys[0]);
+        let included: Vec<_> = block.transactions().iter().map(|t| t.hash).collect();
+        let expected = [
+            bumped.hash,
+            transactions[1].hash,
+            transactions[2].hash,
+            transactions[3].hash,
+        ];
+        assert_eq!(included, expected);
+    }
+
+    #[test]
+    fn added_blocks_are_shared() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut events = blockchain.subscribe();
+
+        let (from, to) = (&keys[1], keys[2].to_public_key());
+        for amount in 1..=10 {
+            let t = Transaction::new(from.to_public_key(), to.clone(), from, amount, amount - 1).unwrap();
+            assert_eq!(blockchain.add_transaction(t), Ok(false));
+        }
+
+        let draw = winning_draw(&blockchain, &clock, &keys[0], 1);
+        let block = Arc::new(blockchain.get_new_block(blockchain.get_best_hash(), draw, &keys[0]));
+        assert_eq!(block.transactions().len(), 10);
+        assert_eq!(blockchain.add_block(Arc::clone(&block)), Ok(()));
+
+        // the tree, the best path and the event all point to the block we added
+        let stored = blockchain.block_at(&block.hash, 1).unwrap();
+        assert!(Arc::ptr_eq(stored, &block));
+        assert!(Arc::ptr_eq(
+            blockchain.best_path_iter().next().unwrap(),
+            &block
+        ));
+        assert!(std::ptr::eq(
+            stored.transactions().as_ptr(),
+            block.transactions().as_ptr()
+        ));
+        let accepted = std::iter::from_fn(|| events.try_recv().ok())
+            .find_map(|event| match event {
+                ChainEvent::BlockAccepted(b) => Some(b),
+                _ => None,
+            })
+            .unwrap();
+        assert!(Arc::ptr_eq(&accepted, &block));
+
+        // a copy of the chain shares its blocks
+        let copy = blockchain.clone();
+        assert!(Arc::ptr_eq(copy.block_at(&block.hash, 1).unwrap(), &block));
+        assert_eq!(copy.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn blocks_with_impossible_depths_are_rejected() {
+        let (mut blockchain, keys, _) = create_dummy_blockchain();
+        let genesis_hash = blockchain.get_best_hash();
+        let seed = blockchain.get_draw(&keys[0], &mut DrawCache::default()).seed;
+        let crafted = |prev_hash, depth, seed: &SeedContent| {
+            let winner = keys[0].to_public_key();
+            Block::new(Timeslot(1), prev_hash, depth, winner, vec![], [0; 32], &keys[0], seed.clone())
+        };
+
+        for depth in [0, u64::MAX] {
+            for prev_hash in [genesis_hash, BlockHash([7; 32])] {
+                assert_eq!(
+                    blockchain.add_block(crafted(prev_hash, depth, &seed)),
+                    BlockchainError::InvalidDepth.into()
+                );
+            }
+        }
+
+        // a seed from a block deeper than the block itself
+        let future_seed = SeedContent::new((genesis_hash, u64::MAX), seed.seed);
+        assert_eq!(
+            blockchain.add_block(crafted(genesis_hash, 1, &future_seed)),
+            BlockchainError::InvalidSeed.into()
+        );
+
+        assert_eq!(blockchain.tip(), TipRef { hash: genesis_hash, depth: 0 });
+        assert_eq!(blockchain.orphan_count(), 0);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn expired_transactions_are_refused() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let (_, to) = crate::generate_keypair();
+        let from = keys[1].to_public_key();
+        let expiring =
+            Transaction::with_expiry(from.clone(), to.clone(), &keys[1], 10, TRANSACTION_FEE, 0, Timeslot(5)).unwrap();
+        let lasting = Transaction::new(keys[2].to_public_key(), to.clone(), &keys[2], 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(expiring.clone()), Ok(false));
+        assert_eq!(blockchain.add_transaction(lasting), Ok(false));
+
+        let genesis_hash = blockchain.get_best_hash();
+        let seed = blockchain.get_draw(&keys[0], &mut DrawCache::default()).seed;
+        let including = |timeslot| {
+            let winner = keys[0].to_public_key();
+            let transactions = vec![expiring.clone()];
+            Block::new(timeslot, genesis_hash, 1, winner, transactions, [0; 32], &keys[0], seed.clone())
+        };
+        assert_eq!(blockchain.verify_block(&including(Timeslot(5))), Ok(()));
+
+        clock.advance_slots(6);
+        assert_eq!(blockchain.calculate_timeslot(), Timeslot(6));
+        assert_eq!(
+            blockchain.add_block(including(Timeslot(6))),
+            BlockchainError::ExpiredTransaction.into()
+        );
+        blockchain.purge_mempool();
+        assert_eq!(blockchain.mempool_len(), 1);
+        assert_eq!(
+            blockchain.add_transaction(expiring),
+            TxValidationError::Expired { valid_until: Timeslot(5), timeslot: Timeslot(6) }.into()
+        );
+        assert_eq!(blockchain.tip(), TipRef { hash: genesis_hash, depth: 0 });
+    }
+
+    #[test]
+    fn the_mempool_is_pruned_in_batches() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        blockchain.set_buffer_limits(BufferLimits {
+            max_mempool_age: 10,
+            ..Default::default()
+        });
+        let mut events = blockchain.subscribe();
+        let (_, to) = crate::generate_keypair();
+        let expiring = Transaction::with_expiry(
+            keys[1].to_public_key(),
+            to.clone(),
+            &keys[1],
+            10,
+            TRANSACTION_FEE,
+            0,
+            Timeslot(5),
+        )
+        .unwrap();
+        assert_eq!(blockchain.add_transaction(expiring), Ok(false));
+        for nonce in 0..3 {
+            let t = Transaction::new(keys[2].to_public_key(), to.clone(), &keys[2], 10, nonce).unwrap();
+            assert_eq!(blockchain.add_transaction(t), Ok(false));
+        }
+
+        clock.advance_slots(6);
+        let pruned = blockchain.prune_mempool(2);
+        assert_eq!(pruned, MempoolPruned { dropped: 1, finished: false });
+        assert_eq!(blockchain.mempool_len(), 3);
+        // the next batches go on from where the first stopped, until they run out
+        assert_eq!(blockchain.prune_mempool(2), MempoolPruned { dropped: 0, finished: false });
+        assert_eq!(blockchain.prune_mempool(2), MempoolPruned { dropped: 0, finished: true });
+
+        // the others have waited too long
+        clock.advance_slots(5);
+        assert_eq!(blockchain.prune_mempool(10), MempoolPruned { dropped: 3, finished: true });
+        assert_eq!(blockchain.mempool_len(), 0);
+        assert_eq!(blockchain.next_nonce(&AccountId::from(&keys[2].to_public_key())), 0);
+        let dropped: usize = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|e| match e {
+                ChainEvent::MempoolPruned { dropped } => dropped,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(dropped, 4);
+    }
+
+    #[test]
+    fn transaction_status_follows_the_best_path() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let genesis_hash = blockchain.get_best_hash();
+
+        // the empty block has the higher draw so it wins the tiebreak at depth 1
+        let [(empty_draw, empty_sk), (with_t_draw, with_t_sk)] = ranked_draws(&blockchain, &clock, [&keys[1], &keys[0]], 1);
+        let empty = blockchain.get_new_block(genesis_hash, empty_draw, empty_sk);
+
+        let (_, receiver) = crate::generate_keypair();
+        let t = Transaction::new(keys[2].to_public_key(), receiver, &keys[2], 10, 0).unwrap();
+        let id = t.id();
+        assert_eq!(blockchain.get_transaction_status(&id), TxStatus::Unknown);
+        assert_eq!(blockchain.add_transaction(t), Ok(false));
+        assert_eq!(blockchain.get_transaction_status(&id), TxStatus::Pending);
+
+        let with_t = blockchain.get_new_block(genesis_hash, with_t_draw, with_t_sk);
+        assert_eq!(with_t.transactions().len(), 1);
+        assert_eq!(blockchain.add_block(with_t.clone()), Ok(()));
+        assert_eq!(
+            blockchain.get_transaction_status(&id),
+            TxStatus::Confirmed {
+                block: with_t.hash,
+                confirmations: 1
+            }
+        );
+
+        let child = blockchain.get_new_block(
+            with_t.hash,
+            winning_draw(&blockchain, &clock, &keys[0], 2),
+            &keys[0],
+        );
+
+        // switching to the empty branch abandons the transaction
+        assert_eq!(blockchain.add_block(empty), Ok(()));
+        assert_eq!(blockchain.get_transaction_status(&id), TxStatus::Pending);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        // and growing the old branch confirms it again
+        assert_eq!(blockchain.add_block(child), Ok(()));
+        assert_eq!(
+            blockchain.get_transaction_status(&id),
+            TxStatus::Confirmed {
+                block: with_t.hash,
+                confirmations: 2
+            }
+        );
+        assert_eq!(blockchain.mempool_len(), 0);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn equivocating_winners_are_penalized() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut events = blockchain.subscribe();
+        let genesis_hash = blockchain.get_best_hash();
+
+        // the same draw is used for two different blocks
+        let draw = winning_draw(&blockchain, &clock, &keys[0], 1);
+        let first = blockchain.get_new_block(genesis_hash, draw.clone(), &keys[0]);
+        let (_, receiver) = crate::generate_keypair();
+        let t = Transaction::new(keys[2].to_public_key(), receiver, &keys[2], 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(t), Ok(false));
+        let second = blockchain.get_new_block(genesis_hash, draw, &keys[0]);
+        assert_ne!(first.hash, second.hash);
+
+        assert_eq!(blockchain.add_block(first.clone()), Ok(()));
+        assert!(blockchain.get_equivocations().is_empty());
+        // the draws are the same, so the smaller hash decides which block is the tip
+        let expected = match second.cmp_priority(&first) {
+            std::cmp::Ordering::Greater => Ok(()),
+            _ => BlockchainError::BestPathNotUpdated.into(),
+        };
+        assert_eq!(blockchain.add_block(second.clone()), expected);
+
+        let [equivocation] = blockchain.get_equivocations() else {
+            panic!("expected one equivocation");
+        };
+        assert_eq!(equivocation.first.hash, first.hash);
+        assert_eq!(equivocation.second.hash, second.hash);
+        assert!(equivocation.verify());
+        let reported = std::iter::from_fn(|| events.try_recv().ok())
+            .any(|e| matches!(e, ChainEvent::Equivocation(e) if e.second.hash == second.hash));
+        assert!(reported);
+
+        // the equivocating winner can not extend the chain, but others can
+        let penalized = blockchain.get_new_block(
+            blockchain.get_best_hash(),
+            winning_draw(&blockchain, &clock, &keys[0], 2),
+            &keys[0],
+        );
+        assert_eq!(
+            blockchain.add_block(penalized),
+            BlockchainError::EquivocatingWinner.into()
+        );
+        let honest = blockchain.get_new_block(
+            blockchain.get_best_hash(),
+            winning_draw(&blockchain, &clock, &keys[1], 2),
+            &keys[1],
+        );
+        assert_eq!(blockchain.add_block(honest), Ok(()));
+
+        // until the penalty has run out
+        clock.advance_slots(EQUIVOCATION_PENALTY);
+        let forgiven = blockchain.get_new_block(
+            blockchain.get_best_hash(),
+            winning_draw(&blockchain, &clock, &keys[0], 3),
+            &keys[0],
+        );
+        assert_eq!(blockchain.add_block(forgiven), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn blocks_from_the_future_are_held() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let genesis_hash = blockchain.get_best_hash();
+
+        // a block made by a node whose clock is ahead of ours by more than the tolerance
+        clock.advance_slots(CLOCK_SKEW_TOLERANCE);
+        let draw = winning_draw(&blockchain, &clock, &keys[0], 1);
+        let block = blockchain.get_new_block(genesis_hash, draw, &keys[0]);
+        clock.set(blockchain.get_start_time() as u64);
+
+        assert_eq!(
+            blockchain.add_block(block.clone()),
+            BlockchainError::FutureBlock.into()
+        );
+        assert_eq!(blockchain.future_block_count(), 1);
+
+        // a timeslot before the tolerance reaches the block it is still held
+        clock.advance_slots(block.timeslot.as_u64() - CLOCK_SKEW_TOLERANCE - 1);
+        blockchain.process_future_blocks();
+        assert_eq!(blockchain.future_block_count(), 1);
+        assert_eq!(blockchain.get_best_hash(), genesis_hash);
+
+        clock.advance_slots(1);
+        blockchain.process_future_blocks();
+        assert_eq!(blockchain.future_block_count(), 0);
+        assert_eq!(blockchain.get_best_hash(), block.hash);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn reorgs_past_finality_are_refused() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        blockchain.set_finality_depth(10);
+        let genesis_hash = blockchain.get_best_hash();
+
+        // a longer branch that was built in private from the genesis block
+        let mut alternate = blockchain.clone();
+        for _ in 0..100 {
+            assert_eq!(
+                alternate.produce_new_block_on_best_path(&clock, &keys[1], 1000),
+                Ok(())
+            );
+        }
+        let mut alternate: Vec<_> = alternate.best_path_iter().cloned().collect();
+        alternate.pop(); // the genesis block
+
+        // a fork that loses the tiebreak at depth 1
+        let [(draw, sk), (fork_draw, fork_sk)] = ranked_draws(&blockchain, &clock, [&keys[0], &keys[2]], 1);
+        let first = blockchain.get_new_block(genesis_hash, draw, sk);
+        let fork = blockchain.get_new_block(genesis_hash, fork_draw, fork_sk);
+        assert_eq!(blockchain.add_block(first), Ok(()));
+        assert_eq!(
+            blockchain.add_block(fork),
+            BlockchainError::BestPathNotUpdated.into()
+        );
+        assert_eq!(blockchain.block_count(), 3);
+
+        for _ in 1..30 {
+            assert_eq!(
+                blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000),
+                Ok(())
+            );
+        }
+        assert_eq!(blockchain.checkpoint().1, 20);
+        // the fork has been pruned
+        assert_eq!(blockchain.block_count(), 31);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        let tip = blockchain.tip().hash;
+        // the orphans are held, so they all miss the refused block below the first of them
+        let mut missing = None;
+        for block in alternate.into_iter().rev() {
+            let expected = if block.depth() <= 20 {
+                BlockchainError::ReorgTooDeep
+            } else {
+                BlockchainError::OrphanBlock(*missing.get_or_insert(block.prev_hash))
+            };
+            assert_eq!(blockchain.add_block(block), expected.into());
+        }
+        assert_eq!(blockchain.tip(), TipRef { hash: tip, depth: 30 });
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    // a branch built in private from the genesis block, from its first block up
+    fn private_branch(blockchain: &Blockchain, clock: &ManualClock, sk: &SecretKey, length: usize) -> Vec<Arc<Block>> {
+        let mut private = blockchain.clone();
+        for _ in 0..length {
+            assert_eq!(private.produce_new_block_on_best_path(clock, sk, 1000), Ok(()));
+        }
+        let mut branch: Vec<_> = private.best_path_iter().cloned().collect();
+        branch.pop(); // the genesis block
+        branch.reverse();
+        branch
+    }
+
+    #[test]
+    fn shallow_reorgs_are_made() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let branch = private_branch(&blockchain, &clock, &keys[1], 4);
+        for _ in 0..3 {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+
+        for block in branch.iter().take(2) {
+            assert_eq!(blockchain.add_block(block.clone()), BlockchainError::BestPathNotUpdated.into());
+        }
+        let _ = blockchain.add_block(branch[2].clone()); // it may be the heavier one
+        assert_eq!(blockchain.add_block(branch[3].clone()), Ok(()));
+        assert_eq!(blockchain.tip(), TipRef { hash: branch[3].hash, depth: 4 });
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn deep_reorgs_wait_for_force_reorg() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let branch = private_branch(&blockchain, &clock, &keys[1], 51);
+        for _ in 0..50 {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        let tip = blockchain.tip().hash;
+        let mut events = blockchain.subscribe();
+
+        for block in branch.iter().take(49) {
+            assert_eq!(blockchain.add_block(block.clone()), BlockchainError::BestPathNotUpdated.into());
+        }
+        let _ = blockchain.add_block(branch[49].clone()); // refused too if it is the heavier one
+        let branch_tip = branch[50].hash;
+        assert_eq!(blockchain.add_block(branch[50].clone()), BlockchainError::ReorgTooDeep.into());
+        // the branch is kept but the tip is not moved
+        assert_eq!(blockchain.tip(), TipRef { hash: tip, depth: 50 });
+        assert_eq!(blockchain.block_count(), 1 + 50 + 51);
+        assert!(blockchain.block_by_hash(&branch_tip).is_some());
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        let mut refused = None;
+        while let Ok(event) = events.try_recv() {
+            if let ChainEvent::ReorgRefused { new_tip, depth, .. } = event {
+                refused = Some((new_tip, depth));
+            }
+        }
+        assert_eq!(refused, Some(((branch_tip, 51), 50)));
+
+        assert_eq!(blockchain.force_reorg(BlockHash([0; 32])), BlockchainError::UnknownBlock.into());
+        assert_eq!(blockchain.force_reorg(branch_tip), Ok(()));
+        assert_eq!(blockchain.tip(), TipRef { hash: branch_tip, depth: 51 });
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+        assert_eq!(blockchain.force_reorg(branch_tip), BlockchainError::BestPathNotUpdated.into());
+    }
+
+    #[test]
+    fn pruned_forks_deeper_than_the_tip_leave_no_empty_depths() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let branch = private_branch(&blockchain, &clock, &keys[1], 6);
+        blockchain.set_max_reorg_depth(0);
+        blockchain.set_finality_depth(2);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        let tip = blockchain.tip();
+
+        // the branch can not replace the block on the best path, so it is kept above the tip
+        for block in branch {
+            let _ = blockchain.add_block(block);
+        }
+        assert_eq!(blockchain.tip(), tip);
+        assert_eq!(blockchain.max_depth(), 6);
+        assert!(blockchain.check_best_path());
+
+        // the branch leaves the best path below the checkpoint once the tip is at depth 3
+        for _ in 0..2 {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        assert_eq!(blockchain.block_count(), 4);
+        assert_eq!(blockchain.max_depth(), 3);
+        assert_eq!(blockchain.blocks.len(), 4);
+        assert!(blockchain.check_best_path());
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn rejected_future_blocks_leave_no_residue() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        let now = clock.now_millis() as u64;
+        blockchain.set_buffer_limits(BufferLimits {
+            max_future_blocks: 0,
+            ..Default::default()
+        });
+
+        // a block too far ahead with no room to hold it, on the tip and on a parent we do not have
+        clock.advance_slots(CLOCK_SKEW_TOLERANCE);
+        let draw = winning_draw(&blockchain, &clock, &keys[0], 2);
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw, &keys[0]);
+        let winner = keys[0].to_public_key();
+        let seed = block.draw.seed.clone();
+        let orphan = Block::new(block.timeslot, BlockHash([7; 32]), 5, winner, vec![], [0; 32], &keys[0], seed);
+        clock.set(now);
+        assert_eq!(blockchain.add_block(block), BlockchainError::InvalidTimeslot.into());
+        assert_eq!(blockchain.add_block(orphan), BlockchainError::InvalidTimeslot.into());
+
+        assert_eq!(blockchain.future_block_count(), 0);
+        assert_eq!(blockchain.block_count(), 2);
+        assert_eq!(blockchain.blocks.len(), 2);
+        assert_eq!(blockchain.max_depth(), 1);
+        assert!(blockchain.check_best_path());
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn a_watched_payment_is_confirmed_as_the_chain_grows_and_unconfirmed_in_a_reorg() {
+        use crate::watch::AddressEvent;
+
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let branch = private_branch(&blockchain, &clock, &keys[1], 6);
+        let (_, payee) = crate::generate_keypair();
+        let mut incoming = blockchain.watch_address(AccountId::from(&payee));
+        let mut outgoing = blockchain.watch_address(AccountId::from(&keys[2].to_public_key()));
+
+        let payment = Transaction::new(keys[2].to_public_key(), payee, &keys[2], 50, 0).unwrap();
+        let txid = payment.id();
+        assert_eq!(blockchain.add_transaction(payment), Ok(false));
+        for _ in 0..4 {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        // the branch without the payment takes over once we accept it, however heavy it is
+        blockchain.set_max_reorg_depth(0);
+        for block in branch.iter() {
+            let _ = blockchain.add_block(block.clone());
+        }
+        assert_eq!(blockchain.force_reorg(branch[5].hash), Ok(()));
+
+        let confirmed = |confirmations| AddressEvent::IncomingConfirmed {
+            txid,
+            amount: 50,
+            confirmations,
+        };
+        let events: Vec<_> = std::iter::from_fn(|| incoming.try_recv()).collect();
+        assert_eq!(
+            events,
+            vec![confirmed(1), confirmed(2), confirmed(3), confirmed(4), AddressEvent::Unconfirmed { txid }]
+        );
+        let events: Vec<_> = std::iter::from_fn(|| outgoing.try_recv()).collect();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[0],
+            AddressEvent::OutgoingConfirmed {
+                txid,
+                amount: 50,
+                confirmations: 1
+            }
+        );
+        assert_eq!(events[4], AddressEvent::Unconfirmed { txid });
+    }
+
+    #[test]
+    fn the_heavier_of_two_forks_of_the_same_depth_wins() {
+        let (base, keys, clock) = create_dummy_blockchain();
+        let genesis_hash = base.get_best_hash();
+        let on = |parent: &Block| {
+            let mut chain = base.clone();
+            assert_eq!(chain.add_block(parent.clone()), Ok(()));
+            chain
+        };
+        let [(heavy_draw, heavy_sk), _, (light_draw, light_sk)] =
+            ranked_draws(&base, &clock, [&keys[0], &keys[1], &keys[2]], 1);
+        let heavy_first = base.get_new_block(genesis_hash, heavy_draw, heavy_sk);
+        let light_first = base.get_new_block(genesis_hash, light_draw, light_sk);
+
+        let mut heavy = on(&heavy_first);
+        let [_, _, (draw, sk)] = ranked_draws(&heavy, &clock, [&keys[0], &keys[1], &keys[2]], 2);
+        let heavy_second = heavy.get_new_block(heavy_first.hash, draw, sk);
+        assert_eq!(heavy.add_block(heavy_second.clone()), Ok(()));
+        let heavy_weight = heavy.weight_of(&(heavy_second.hash, 2)).unwrap().clone();
+
+        // a second block that wins the tiebreak against the head of the heavy fork but not the weight of its branch
+        let light = on(&light_first);
+        let light_second = (0..1000)
+            .find_map(|_| {
+                let block = light.get_new_block(light_first.hash, winning_draw(&light, &clock, &keys[0], 2), &keys[0]);
+                let mut chain = light.clone();
+                assert_eq!(chain.add_block(block.clone()), Ok(()));
+                let lighter = chain.weight_of(&(block.hash, 2)).unwrap() < &heavy_weight;
+                (lighter && block.cmp_priority(&heavy_second).is_gt()).then_some(block)
+            })
+            .expect("a lighter fork with the better head");
+
+        for order in [
+            [&heavy_first, &heavy_second, &light_first, &light_second],
+            [&light_first, &light_second, &heavy_first, &heavy_second],
+        ] {
+            let mut blockchain = base.clone();
+            for block in order {
+                let _ = blockchain.add_block(block.clone());
+            }
+            assert_eq!(blockchain.tip(), TipRef { hash: heavy_second.hash, depth: 2 });
+            assert_eq!(blockchain.verify_chain(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn verify_chain_reports_ledger_violations() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        assert_eq!(
+            blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000),
+            Ok(())
+        );
+        assert_eq!(blockchain.audit_ledger(), vec![]);
+
+        let minted = blockchain.ledger.minted();
+        blockchain.ledger.minted += 1;
+        blockchain.ledger.total_money += 1;
+        let winner = AccountId::from(&keys[0].to_public_key());
+        *blockchain.ledger.map.get_mut(&winner).unwrap() += 1;
+        assert_eq!(
+            blockchain.verify_chain(),
+            Err(ChainVerifyReport {
+                block: blockchain.best_path_head().ptr(),
+                violation: ChainViolation::LedgerAudit(vec![LedgerViolation::UnexpectedMinting {
+                    expected: minted,
+                    minted: minted + 1
+                }])
+            })
+        );
+        assert!(!blockchain.is_valid());
+    }
+
+    #[test]
+    fn verify_chain_pins_the_offending_block() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        for _ in 0..3 {
+            assert_eq!(
+                blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000),
+                Ok(())
+            );
+        }
+        assert!(blockchain.is_valid());
+
+        // swaps in a changed block under the hash of the original
+        let verify_with = |ptr: BlockPtr, change: &dyn Fn(&mut Block)| {
+            let mut chain = blockchain.clone();
+            let mut block = (*chain.blocks[ptr.1 as usize][&ptr.0]).clone();
+            change(&mut block);
+            chain.blocks[ptr.1 as usize].insert(ptr.0, Arc::new(block));
+            chain.verify_chain()
+        };
+        let report = |block: BlockPtr, violation: ChainViolation| Err(ChainVerifyReport { block, violation });
+
+        let ptr = blockchain.best_path[2];
+        assert_eq!(
+            verify_with(ptr, &|block| block.header.timeslot = Timeslot::GENESIS),
+            report(ptr, ChainViolation::TimeslotOrder)
+        );
+        assert_eq!(
+            verify_with(ptr, &|block| block.header.prev_hash = BlockHash([7; 32])),
+            report(ptr, ChainViolation::HashLinkage)
+        );
+        assert_eq!(
+            verify_with(ptr, &|block| block.header.state_root = [7; 32]),
+            report(ptr, ChainViolation::BadSignature)
+        );
+        let other_draw = blockchain.best_path_block(1).unwrap().draw.clone();
+        assert_eq!(
+            verify_with(ptr, &|block| block.header.draw = other_draw.clone()),
+            report(ptr, ChainViolation::DrawMismatch)
+        );
+        assert_eq!(
+            verify_with(ptr, &|block| *block = block.with_draw(block.draw.clone(), &keys[1])),
+            report(ptr, ChainViolation::ProposerMismatch)
+        );
+        let genesis = blockchain.best_path[0];
+        assert_eq!(
+            verify_with(genesis, &|block| block.header.prev_hash = BlockHash([7; 32])),
+            report(genesis, ChainViolation::BadGenesis)
+        );
+
+        let expected_root = blockchain.ledger.state_root();
+        blockchain.ledger.nonces.insert(AccountId([7; 32]), 1);
+        assert_eq!(
+            blockchain.verify_chain(),
+            report(
+                blockchain.best_path_head().ptr(),
+                ChainViolation::LedgerMismatch {
+                    expected_root,
+                    actual_root: blockchain.ledger.state_root(),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn orphans_report_their_missing_ancestor() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut behind = blockchain.clone();
+        let mut other = blockchain.clone();
+        for _ in 0..6 {
+            assert_eq!(
+                blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000),
+                Ok(())
+            );
+        }
+
+        // the node missed 5 blocks, it asks for the missing ancestor until it connects
+        let mut next = Arc::clone(blockchain.best_path_block(6).unwrap());
+        let mut requests = 0;
+        while let Err(crate::Error::BlockchainError(BlockchainError::OrphanBlock(missing))) =
+            behind.add_block(next)
+        {
+            next = Arc::clone(blockchain.block_by_hash(&missing).unwrap());
+            requests += 1;
+        }
+        assert_eq!(requests, 5);
+        assert_eq!(behind.tip(), blockchain.tip());
+        assert_eq!(behind.orphan_count(), 0);
+
+        // held orphans are skipped, it is the block below them that is missing
+        let block = |depth| Arc::clone(blockchain.best_path_block(depth).unwrap());
+        let missing = block(4).hash;
+        assert_eq!(
+            other.add_block(block(5)),
+            BlockchainError::OrphanBlock(missing).into()
+        );
+        assert_eq!(
+            other.add_block(block(6)),
+            BlockchainError::OrphanBlock(missing).into()
+        );
+    }
+
+    #[test]
+    fn pruned_chains_drop_the_transactions_of_old_final_blocks() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut full = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        full.set_finality_depth(20);
+        for nonce in 0..500 {
+            let payment = Transaction::new(pk.clone(), receiver.clone(), &sk, 1, nonce).unwrap();
+            assert_eq!(full.add_transaction(payment), Ok(false));
+            clock.advance_slots(difficulty::TARGET_SLOTS_PER_BLOCK - 1);
+            assert_eq!(full.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        }
+        assert_eq!(full.tip().depth, 500);
+        assert!(full.best_path_iter().all(|block| block.has_body()));
+
+        let mut pruned = full.clone();
+        pruned.set_prune_depth(Some(50));
+        assert_eq!(pruned.pruned_to, 450);
+        let size = |chain: &Blockchain| bincode::serialized_size(chain).unwrap();
+        assert!(size(&pruned) < size(&full));
+
+        // the blocks above the prune depth keep their transactions, the ones below only their headers
+        let old = full.best_path_block(100).unwrap().hash;
+        let recent = full.best_path_block(460).unwrap().hash;
+        let full_block = |hash| pruned.full_block_by_hash(hash).map(|block| block.transactions().len());
+        assert_eq!(full_block(&old), BlockchainError::Pruned.into());
+        assert_eq!(full_block(&recent), Ok(1));
+        assert_eq!(full_block(&BlockHash([0; 32])), BlockchainError::UnknownBlock.into());
+        assert_eq!(pruned.block_by_hash(&old).unwrap().hash, old);
+
+        // it verifies from the checkpoint ledger and answers the same balances
+        assert_eq!(pruned.verify_chain(), Ok(()));
+        let accounts = [AccountId::from(&pk), AccountId::from(&receiver)];
+        for account in &accounts {
+            assert_eq!(pruned.get_balance(account), full.get_balance(account));
+        }
+        assert_eq!(pruned.get_balance(&accounts[1]), 500);
+
+        // and keeps pruning as the chain grows
+        for _ in 0..10 {
+            clock.advance_slots(difficulty::TARGET_SLOTS_PER_BLOCK - 1);
+            assert_eq!(pruned.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        }
+        assert_eq!(pruned.pruned_to, 460);
+        assert!(!pruned.best_path_block(460).unwrap().has_body());
+        assert!(pruned.best_path_block(461).unwrap().has_body());
+        assert_eq!(pruned.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn light_chains_follow_a_full_chain() {
+        let (mut full, keys, clock) = create_dummy_blockchain();
+        full.set_finality_depth(5);
+        let mut light = full.clone();
+        light.set_light(true);
+        let mut headers_only = full.clone();
+
+        let (_, receiver) = crate::generate_keypair();
+        let payment = Transaction::new(keys[0].to_public_key(), receiver.clone(), &keys[0], 10, 0).unwrap();
+        assert_eq!(full.add_transaction(payment.clone()), Ok(false));
+        for _ in 0..12 {
+            assert_eq!(full.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+            let block = Arc::clone(full.best_path_block(full.tip().depth).unwrap());
+            assert_eq!(light.add_block(Arc::clone(&block)), Ok(()));
+            assert_eq!(headers_only.add_header(block.header().clone()), Ok(()));
+        }
+        assert_eq!(light.tip(), full.tip());
+        assert_eq!(headers_only.tip(), full.tip());
+
+        // the light chain has the full ledger but only the headers of final blocks
+        assert_eq!(light.ledger(), full.ledger());
+        assert_eq!(light.verify_chain(), Ok(()));
+        let (_, checkpoint) = light.checkpoint();
+        assert_eq!(checkpoint, 7);
+        let paid_in = full.best_path_block(1).unwrap();
+        assert_eq!(paid_in.transactions().len(), 1);
+        assert!(!light.best_path_block(1).unwrap().has_body());
+        assert!(light
+            .best_path_iter()
+            .take_while(|block| block.depth() > checkpoint)
+            .all(|block| block.has_body()));
+
+        // a wallet with only the headers can still check the payment
+        let proof = paid_in.prove_transaction(&payment.hash).unwrap();
+        let header = headers_only.best_path_block(1).unwrap().header();
+        assert!(!headers_only.best_path_block(1).unwrap().has_body());
+        assert!(header.verify_transaction(&payment.hash, &proof));
+
+        // and the balances a full node proves against the state root of the tip
+        let tip = headers_only.best_path_block(full.tip().depth).unwrap().header();
+        let (_, stranger) = crate::generate_keypair();
+        let accounts = keys.iter().map(SecretKey::to_public_key).chain([receiver, stranger]);
+        for account in accounts.map(|pk| AccountId::from(&pk)) {
+            let proof = full.ledger().prove_balance(&account);
+            assert_eq!(verify_balance_proof(tip.state_root(), &proof), Some((account, full.get_balance(&account))));
+        }
+    }
+
+    // with the `parallel` feature the signatures of a block are checked on other threads, where they are not counted
+    #[cfg(not(feature = "parallel"))]
+    #[test]
+    fn a_signature_is_checked_at_most_once() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let (_, receiver) = crate::generate_keypair();
+        let payment = Transaction::new(keys[1].to_public_key(), receiver, &keys[1], 10, 0).unwrap();
+        let checks = || crate::transaction::SIGNATURE_CHECKS.with(std::cell::Cell::get);
+        let before = checks();
+        assert_eq!(blockchain.add_transaction(payment.clone()), Ok(false));
+        assert_eq!(checks(), before + 1);
+
+        for _ in 0..3 {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        assert_eq!(blockchain.best_path_block(1).unwrap().transactions().len(), 1);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+        assert!(crate::signatures::SIGNATURES.contains(&payment.id()));
+        assert_eq!(checks(), before + 1);
+    }
+
+    #[test]
+    fn a_snapshot_is_put_together_from_its_parts_in_order() {
+        let (mut a, keys, clock) = create_dummy_blockchain();
+        a.set_finality_depth(5);
+        for _ in 0..10 {
+            assert_eq!(
+                a.produce_new_block_on_best_path(&clock, &keys[0], 1000),
+                Ok(())
+            );
+        }
+        let snapshot = a.export_snapshot();
+        // parts much smaller than the frame limit, so there are many of them
+        let parts = snapshot.to_parts(1_000);
+        let count = parts.len() as u32;
+        assert!(count > 2);
+
+        let mut assembly = SnapshotParts::new(count).unwrap();
+        let (last, rest) = parts.split_last().unwrap();
+        for (part, bytes) in (0..).zip(rest) {
+            assert!(matches!(assembly.add(part, count, bytes), Ok(None)));
+        }
+        let assembled = assembly.add(count - 1, count, last).unwrap().unwrap();
+        assert_eq!(
+            assembled.checkpoint_header().unwrap().hash,
+            snapshot.checkpoint_header().unwrap().hash
+        );
+        assert!(Blockchain::from_snapshot(assembled).is_ok());
+
+        // a part out of order or one larger than a part may be is refused
+        let mut assembly = SnapshotParts::new(count).unwrap();
+        assert!(assembly.add(1, count, &parts[1]).is_err());
+        let oversized = vec![0; SNAPSHOT_PART_BYTES as usize + 1];
+        assert!(SnapshotParts::new(count)
+            .unwrap()
+            .add(0, count, &oversized)
+            .is_err());
+        // and so are more parts than a snapshot may have
+        assert!(
+            SnapshotParts::new((MAX_SNAPSHOT_BYTES / SNAPSHOT_PART_BYTES) as u32 + 1).is_none()
+        );
+        assert!(SnapshotParts::new(0).is_none());
+    }
+
+    #[test]
+    fn new_nodes_start_from_a_snapshot() {
+        let (mut a, keys, clock) = create_dummy_blockchain();
+        a.set_finality_depth(5);
+        let (_, receiver) = crate::generate_keypair();
+        let payment = Transaction::new(keys[0].to_public_key(), receiver, &keys[0], 10, 0).unwrap();
+        assert_eq!(a.add_transaction(payment), Ok(false));
+        for _ in 0..10 {
+            assert_eq!(a.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+
+        let snapshot = a.export_snapshot();
+        assert_eq!(snapshot.checkpoint_header().unwrap().depth(), a.checkpoint().1);
+        let mut b = Blockchain::from_snapshot(snapshot.clone()).unwrap();
+        b.set_clock(a.clock());
+        b.set_finality_depth(5);
+        assert_eq!(b.checkpoint(), a.checkpoint());
+        assert_eq!(b.verify_chain(), Ok(()));
+
+        // b catches up from the checkpoint and then follows a
+        for depth in a.checkpoint().1 + 1..=a.tip().depth {
+            let block = Arc::clone(a.best_path_block(depth).unwrap());
+            assert_eq!(b.add_block(block), Ok(()));
+        }
+        for _ in 0..10 {
+            assert_eq!(a.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+            let block = Arc::clone(a.best_path_block(a.tip().depth).unwrap());
+            assert_eq!(b.add_block(block), Ok(()));
+        }
+        assert_eq!(b.tip(), a.tip());
+        assert_eq!(b.ledger(), a.ledger());
+        assert_eq!(b.verify_chain(), Ok(()));
+
+        let mut tampered = snapshot.clone();
+        let winner = AccountId::from(&keys[0].to_public_key());
+        tampered.ledger.reward_winner(&winner, 1, 0).unwrap();
+        assert_eq!(
+            Blockchain::from_snapshot(tampered).err(),
+            Some(BlockchainError::StateRootMismatch.into())
+        );
+        let mut tampered = snapshot.clone();
+        tampered.stake_ledgers[0].reward_winner(&winner, 1, 0).unwrap();
+        assert_eq!(
+            Blockchain::from_snapshot(tampered).err(),
+            Some(BlockchainError::StateRootMismatch.into())
+        );
+        let mut unlinked = snapshot;
+        unlinked.headers.remove(1);
+        assert!(Blockchain::from_snapshot(unlinked).is_err());
+
+        // a block that does not commit to the ledger after it is refused
+        let draw = winning_draw(&a, &clock, &keys[0], a.tip().depth + 1);
+        let block = a.get_new_block(a.get_best_hash(), draw.clone(), &keys[0]);
+        let wrong_root = Block::new(
+            draw.timeslot,
+            a.get_best_hash(),
+            a.tip().depth + 1,
+            keys[0].to_public_key(),
+            block.transactions().to_vec(),
+            [0; 32],
+            &keys[0],
+            draw.seed,
+        );
+        assert_eq!(a.add_block(wrong_root), BlockchainError::StateRootMismatch.into());
+        assert_eq!(a.add_block(block), Ok(()));
+    }
+
+    #[test]
+    fn the_start_time_is_part_of_genesis() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        // the same root accounts and params, started a few timeslots apart
+        let mut earlier = Blockchain::start_with_clock(vec![pk.clone()], params.clone(), Arc::new(clock.clone()));
+        clock.advance_slots(3);
+        let mut later = Blockchain::start_with_clock(vec![pk.clone()], params.clone(), Arc::new(clock.clone()));
+        assert_ne!(earlier.genesis_hash(), later.genesis_hash());
+        assert_eq!(later.verify_chain(), Ok(()));
+
+        // their blocks are refused by each other instead of being held as orphans
+        assert_eq!(later.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let block = Arc::clone(later.best_path_block(1).unwrap());
+        assert_eq!(earlier.add_block(block), BlockchainError::ForeignGenesis.into());
+        assert_eq!(earlier.orphan_count(), 0);
+        assert_eq!(earlier.tip().depth, 0);
+
+        // and a node only takes a start time along with a genesis block that commits to it
+        let Genesis { block, root_accounts, params, start_time } = later.genesis();
+        assert_eq!(
+            Blockchain::from_genesis_with_params(block.clone(), root_accounts.clone(), params.clone(), start_time + 1)
+                .map(|_| ()),
+            BlockchainError::InvalidGenesisBlock.into()
+        );
+        let joined = Blockchain::from_genesis_with_params(block, root_accounts, params, start_time).unwrap();
+        assert_eq!(joined.get_start_time(), later.get_start_time());
+        let mut snapshot = later.export_snapshot();
+        snapshot.start_time = earlier.get_start_time();
+        assert_eq!(
+            Blockchain::from_snapshot(snapshot).map(|_| ()),
+            BlockchainError::InvalidGenesisBlock.into()
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn rejected_blocks_are_logged_with_their_reason() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params.clone(), Arc::new(clock.clone()));
+        clock.advance_slots(1);
+        let mut other = Blockchain::start_with_clock(vec![pk], params, Arc::new(clock.clone()));
+        assert_eq!(other.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let block = Arc::clone(other.best_path_block(1).unwrap());
+        let hash = hex::encode(block.hash);
+
+        assert_eq!(blockchain.add_block(block), BlockchainError::ForeignGenesis.into());
+        assert!(logs_contain("block rejected"));
+        assert!(logs_contain("reason=ForeignGenesis"));
+        assert!(logs_contain(&format!("hash={hash}")));
+    }
+
+    #[test]
+    fn chain_params_are_part_of_genesis() {
+        let (_, pk) = crate::generate_keypair();
+        let start = |params: ChainParams| {
+            Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(ManualClock::default()))
+        };
+        let genesis_seed = |blockchain: &Blockchain| blockchain.best_path_block(0).unwrap().prev_hash;
+
+        let mainnet = start(ChainParams::default());
+        let testnet_params = ChainParams {
+            root_amount: 1_000_000,
+            block_reward: 5,
+            ..ChainParams::default()
+        };
+        let testnet = start(testnet_params.clone());
+
+        assert_eq!(genesis_seed(&mainnet), genesis_seed(&start(ChainParams::default())));
+        assert_ne!(genesis_seed(&mainnet), genesis_seed(&testnet));
+        assert_eq!(testnet.params(), &testnet_params);
+        assert_eq!(testnet.get_balance(&AccountId::from(&pk)), 1_000_000);
+        assert_eq!(testnet.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn reorg_event_on_fork() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut events = blockchain.subscribe();
+        let genesis_hash = blockchain.get_best_hash();
+
+        let [(draw, sk), (worse_draw, worse_sk)] = ranked_draws(&blockchain, &clock, [&keys[0], &keys[1]], 1);
+        let better = blockchain.get_new_block(genesis_hash, draw, sk);
+        let worse = blockchain.get_new_block(genesis_hash, worse_draw, worse_sk);
+        assert!(better.cmp_priority(&worse).is_gt());
+        let balance = blockchain.get_balance(&better.winner_id());
+
+        assert_eq!(blockchain.add_block(worse.clone()), Ok(()));
+        assert_eq!(blockchain.add_block(better.clone()), Ok(()));
+        assert_eq!(blockchain.tip(), TipRef { hash: better.hash, depth: 1 });
+        assert_eq!(blockchain.orphan_count(), 0);
+        assert_eq!(
+            blockchain.ledger().get_balance(&better.winner_id()),
+            balance + BLOCK_REWARD
+        );
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        let mut reorgs = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let ChainEvent::Reorg {
+                old_tip,
+                new_tip,
+                depth,
+            } = event
+            {
+                reorgs.push((old_tip, new_tip, depth));
+            }
+        }
+        assert_eq!(reorgs, vec![((worse.hash, 1), (better.hash, 1), 1)]);
+    }
+
+    #[test]
+    fn transfers_do_not_change_the_next_lottery() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let lookback = blockchain.params().stake_lookback;
+        for _ in 0..lookback {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        let (sender, receiver) = (keys[2].to_public_key(), keys[1].to_public_key());
+        let next_depth = blockchain.tip().depth + 2;
+        let draws: Vec<_> = (0..100)
+            .map(|timeslot| {
+                let timeslot = Timeslot(1_000 + timeslot);
+                let seed = blockchain.get_next_seed(timeslot);
+                Draw::new(timeslot, blockchain.get_best_hash(), seed, receiver.clone(), &keys[1])
+            })
+            .collect();
+        let odds = |blockchain: &Blockchain| {
+            draws
+                .iter()
+                .map(|draw| blockchain.stake(draw, &receiver, next_depth))
+                .collect::<Vec<_>>()
+        };
+        let before = odds(&blockchain);
+        assert!(before.contains(&true));
+
+        // the receiver gets almost all of the money of the sender in the block before the draw
+        let payment = Transaction::new(sender.clone(), receiver.clone(), &keys[2], ROOT_AMOUNT - 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment), Ok(false));
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        assert_eq!(blockchain.tip().depth + 1, next_depth);
+        assert_eq!(blockchain.get_balance(&AccountId::from(&receiver)), 2 * ROOT_AMOUNT - 10);
+        assert_eq!(odds(&blockchain), before);
+        let stake_ledger = blockchain.stake_ledger(next_depth).unwrap();
+        assert_eq!(stake_ledger.get_balance(&AccountId::from(&receiver)), ROOT_AMOUNT);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        // the transfer counts once the lottery has moved past it
+        for _ in 0..lookback {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        let stake_ledger = blockchain.stake_ledger(blockchain.tip().depth + 1).unwrap();
+        assert_eq!(stake_ledger.get_balance(&AccountId::from(&receiver)), 2 * ROOT_AMOUNT - 10);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn payment_to_several_receivers_is_undone_by_a_fork() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let genesis_hash = blockchain.get_best_hash();
+        // the block without the payment wins the tiebreak
+        let [(draw, sk), (worse_draw, worse_sk)] = ranked_draws(&blockchain, &clock, [&keys[0], &keys[1]], 1);
+        let better = blockchain.get_new_block(genesis_hash, draw, sk);
+
+        let receivers: Vec<_> = (0..3).map(|_| crate::generate_keypair().1).collect();
+        let sender = keys[2].to_public_key();
+        let outputs = receivers.iter().cloned().zip([10, 20, 30]).collect();
+        let payment = Transaction::with_outputs(sender.clone(), outputs, &keys[2], TRANSACTION_FEE, 0, Timeslot::MAX).unwrap();
+        assert_eq!(blockchain.add_transaction(payment.clone()), Ok(false));
+        let worse = blockchain.get_new_block(genesis_hash, worse_draw, worse_sk);
+        assert_eq!(worse.transactions.len(), 1);
+        assert!(better.cmp_priority(&worse).is_gt());
+
+        assert_eq!(blockchain.add_block(worse), Ok(()));
+        assert_eq!(
+            blockchain.get_balance(&AccountId::from(&sender)),
+            ROOT_AMOUNT - 60 - TRANSACTION_FEE
+        );
+        for (receiver, amount) in receivers.iter().zip([10, 20, 30]) {
+            assert_eq!(blockchain.get_balance(&AccountId::from(receiver)), amount);
+        }
+        assert_eq!(blockchain.mempool_len(), 0);
+
+        // the payment is taken back as a whole and waits for the next block
+        assert_eq!(blockchain.add_block(better.clone()), Ok(()));
+        assert_eq!(blockchain.tip(), TipRef { hash: better.hash, depth: 1 });
+        assert_eq!(blockchain.get_balance(&AccountId::from(&sender)), ROOT_AMOUNT);
+        for receiver in receivers.iter() {
+            assert_eq!(blockchain.get_balance(&AccountId::from(receiver)), 0);
+        }
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+        assert_eq!(blockchain.mempool_iter().map(|t| t.hash).collect::<Vec<_>>(), vec![payment.hash]);
+    }
+
+    #[test]
+    fn balances_at_past_depths_follow_the_best_path() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        blockchain.set_history_interval(2);
+        let sender = keys[2].to_public_key();
+        let pay = |blockchain: &mut Blockchain, amount, nonce| {
+            let (_, to) = crate::generate_keypair();
+            let payment = Transaction::new(sender.clone(), to, &keys[2], amount, nonce).unwrap();
+            assert_eq!(blockchain.add_transaction(payment.clone()), Ok(false));
+            payment
+        };
+        let first = pay(&mut blockchain, 10, 0);
+        let first_to = AccountId::from(&first.outputs()[0].0);
+        blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000).unwrap();
+        blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000).unwrap();
+
+        // a fork from depth 2 that grows longer than the best path
+        let mut fork = blockchain.clone();
+        let second = pay(&mut blockchain, 5, 1);
+        let second_to = AccountId::from(&second.outputs()[0].0);
+        blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000).unwrap();
+        blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000).unwrap();
+        let sent = |amount| ROOT_AMOUNT - amount - TRANSACTION_FEE;
+        let sender = AccountId::from(&sender);
+        assert_eq!(blockchain.get_balance_at(&sender, 0), Some(ROOT_AMOUNT));
+        assert_eq!(blockchain.get_balance_at(&first_to, 0), Some(0));
+        assert_eq!(blockchain.get_balance_at(&first_to, 1), Some(10));
+        assert_eq!(blockchain.get_balance_at(&sender, 2), Some(sent(10)));
+        assert_eq!(blockchain.get_balance_at(&second_to, 2), Some(0));
+        assert_eq!(blockchain.get_balance_at(&second_to, 3), Some(5));
+        assert_eq!(blockchain.get_balance_at(&sender, 4), Some(sent(15) - TRANSACTION_FEE));
+        assert_eq!(blockchain.get_balance_at(&sender, 5), None);
+
+        for _ in 0..3 {
+            fork.produce_new_block_on_best_path(&clock, &keys[1], 1000).unwrap();
+        }
+        // the fork only wins for sure once it is longer
+        for depth in 3..=4 {
+            let _ = blockchain.add_block(Block::clone(fork.best_path_block(depth).unwrap()));
+        }
+        assert_eq!(blockchain.add_block(Block::clone(fork.best_path_block(5).unwrap())), Ok(()));
+        assert_eq!(blockchain.tip(), fork.tip());
+        assert_eq!(blockchain.get_balance_at(&second_to, 3), Some(0));
+        assert_eq!(blockchain.get_balance_at(&sender, 4), Some(sent(10)));
+        // the answers are the ones of a node that only saw the fork
+        let winners = keys.iter().map(|sk| AccountId::from(&sk.to_public_key()));
+        for account in winners.chain([sender, first_to, second_to]) {
+            for depth in 0..=5 {
+                assert_eq!(blockchain.get_balance_at(&account, depth), fork.get_balance_at(&account, depth));
+            }
+        }
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        // past the lookback only the kept ledgers at even depths are left to replay from
+        blockchain.stake_ledgers.clear();
+        assert_eq!(blockchain.get_balance_at(&first_to, 3), Some(10));
+        let producer = AccountId::from(&keys[1].to_public_key());
+        assert_eq!(blockchain.get_balance_at(&producer, 5), fork.get_balance_at(&producer, 5));
+    }
+
+    #[test]
+    fn orphans_are_checked_against_the_timeslot_they_arrived_in() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let mut delayed = blockchain.clone();
+        let mut rewound = blockchain.clone();
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        clock.advance_slots(3);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        let parent = Arc::clone(blockchain.best_path_block(1).unwrap());
+        let child = Arc::clone(blockchain.best_path_block(2).unwrap());
+        assert!(child.timeslot > parent.timeslot + CLOCK_SKEW_TOLERANCE);
+
+        // the parent arrives several timeslots after its child
+        for chain in [&mut delayed, &mut rewound] {
+            assert_eq!(chain.add_block(Arc::clone(&child)), BlockchainError::OrphanBlock(parent.hash).into());
+        }
+        clock.advance_slots(5);
+        assert_eq!(delayed.add_block(Arc::clone(&parent)), Ok(()));
+        assert_eq!(delayed.tip(), blockchain.tip());
+
+        // a clock that is set back before the parent arrives does not send the child back to wait
+        let behind = ManualClock::new((parent.timeslot.as_u64() as u128 * crate::SLOT_LENGTH) as u64);
+        rewound.set_clock(Arc::new(behind));
+        assert!(child.timeslot > rewound.latest_acceptable_timeslot());
+        assert_eq!(rewound.add_block(parent), Ok(()));
+        assert_eq!(rewound.tip(), blockchain.tip());
+        assert_eq!(rewound.orphan_count(), 0);
+        assert_eq!(rewound.future_block_count(), 0);
+    }
+
+    #[test]
+    fn long_chains_of_orphans_are_adopted_without_recursion() {
+        // a thousand recursive adoptions already overflow the stack of a test thread
+        #[cfg(not(feature = "heavy_test"))]
+        const ORPHANS: u64 = 1_000;
+        #[cfg(feature = "heavy_test")]
+        const ORPHANS: u64 = 10_000;
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        // without hardness every draw wins, and a block every target number of timeslots keeps it that way
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut source = Blockchain::start_with_clock(vec![pk], params, Arc::new(clock.clone()));
+        let mut blockchain = source.clone();
+        for _ in 0..=ORPHANS {
+            clock.advance_slots(difficulty::TARGET_SLOTS_PER_BLOCK - 1);
+            assert_eq!(source.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        }
+
+        // the first block is withheld and the rest arrive newest first
+        let first = Arc::clone(source.best_path_block(1).unwrap());
+        for depth in (2..=ORPHANS + 1).rev() {
+            let block = Arc::clone(source.best_path_block(depth).unwrap());
+            let parent = block.prev_hash;
+            assert_eq!(blockchain.add_block(block), BlockchainError::OrphanBlock(parent).into());
+        }
+        assert_eq!(blockchain.orphan_count(), ORPHANS as usize);
+
+        assert_eq!(blockchain.add_block(first), Ok(()));
+        assert_eq!(blockchain.tip(), source.tip());
+        assert_eq!(blockchain.orphan_count(), 0);
+        assert_eq!(blockchain.checkpoint(), source.checkpoint());
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn reorgs_between_branches_of_any_lengths_leave_the_ledger_of_the_new_branch() {
+        // blocks in the tree before the fork, then the lengths of the losing and the winning branch
+        for (shared, losing, winning) in [(0, 3, 5), (1, 3, 5), (0, 1, 4), (2, 4, 5)] {
+            let (sk_a, pk_a) = crate::generate_keypair();
+            let (sk_b, pk_b) = crate::generate_keypair();
+            let (a, b) = (AccountId::from(&pk_a), AccountId::from(&pk_b));
+            let clock = ManualClock::default();
+            let params = ChainParams {
+                hardness: BigUint::from(0u8),
+                ..Default::default()
+            };
+            let mut blockchain =
+                Blockchain::start_with_clock(vec![pk_a.clone(), pk_b.clone()], params, Arc::new(clock.clone()));
+            let produce = |blockchain: &mut Blockchain, sk: &SecretKey| {
+                let mut draws = DrawCache::default();
+                loop {
+                    clock.advance_slots(1);
+                    if let Some(block) = blockchain.try_produce(sk, &mut draws) {
+                        assert_eq!(blockchain.add_block(block), Ok(()));
+                        return;
+                    }
+                }
+            };
+            for _ in 0..shared {
+                produce(&mut blockchain, &sk_a);
+            }
+            let mut winner = blockchain.clone();
+            // the losing branch has a payment, so its transactions are undone as well as its rewards
+            let payment = Transaction::new(pk_a.clone(), pk_b.clone(), &sk_a, 10, 0).unwrap();
+            assert_eq!(blockchain.add_transaction(payment), Ok(false));
+            for _ in 0..losing {
+                produce(&mut blockchain, &sk_a);
+            }
+            for _ in 0..winning {
+                produce(&mut winner, &sk_b);
+            }
+            let loser = blockchain.clone();
+
+            for depth in shared + 1..=shared + winning {
+                let _ = blockchain.add_block(Block::clone(winner.best_path_block(depth).unwrap()));
+            }
+            assert_eq!(blockchain.tip(), winner.tip());
+            assert_eq!(blockchain.verify_chain(), Ok(()));
+            assert_eq!(blockchain.get_balance(&a), ROOT_AMOUNT + shared * BLOCK_REWARD);
+            assert_eq!(blockchain.get_balance(&b), ROOT_AMOUNT + winning * BLOCK_REWARD);
+            assert_eq!(blockchain.mempool_len(), 1);
+
+            // straight from the longer head to the shorter one and back
+            let short_tip = (loser.tip().hash, loser.tip().depth);
+            let long_tip = (winner.tip().hash, winner.tip().depth);
+            blockchain.rollback(long_tip, short_tip);
+            assert_eq!(blockchain.ledger().state_root(), loser.ledger().state_root());
+            assert_eq!(blockchain.get_balance(&b), ROOT_AMOUNT + 10);
+            assert_eq!(
+                blockchain.get_balance(&a),
+                ROOT_AMOUNT + (shared + losing) * BLOCK_REWARD - 10 - TRANSACTION_FEE
+            );
+            blockchain.rollback(short_tip, long_tip);
+            assert_eq!(blockchain.ledger().state_root(), winner.ledger().state_root());
+            assert_eq!(blockchain.verify_chain(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn fees_are_burned_or_paid_to_the_proposer_to_the_unit_across_reorgs() {
+        for fee_sink in [FeeSink::Burn, FeeSink::ToProposer] {
+            let (sk_a, pk_a) = crate::generate_keypair();
+            let (sk_b, pk_b) = crate::generate_keypair();
+            let (a, b) = (AccountId::from(&pk_a), AccountId::from(&pk_b));
+            let clock = ManualClock::default();
+            let params = ChainParams {
+                hardness: BigUint::from(0u8),
+                fee_sink,
+                ..Default::default()
+            };
+            let mut blockchain =
+                Blockchain::start_with_clock(vec![pk_a.clone(), pk_b.clone()], params, Arc::new(clock.clone()));
+            let produce = |blockchain: &mut Blockchain, sk: &SecretKey| {
+                let mut draws = DrawCache::default();
+                loop {
+                    clock.advance_slots(1);
+                    if let Some(block) = blockchain.try_produce(sk, &mut draws) {
+                        assert_eq!(blockchain.add_block(block), Ok(()));
+                        return;
+                    }
+                }
+            };
+            // each branch has a payment by its proposer with another fee
+            let (fee_a, fee_b) = (TRANSACTION_FEE + 3, TRANSACTION_FEE + 7);
+            let mut winner = blockchain.clone();
+            let payment = Transaction::with_fee(pk_a.clone(), pk_b.clone(), &sk_a, 10, fee_a, 0).unwrap();
+            assert_eq!(blockchain.add_transaction(payment), Ok(false));
+            produce(&mut blockchain, &sk_a);
+            let payment = Transaction::with_fee(pk_b.clone(), pk_a.clone(), &sk_b, 20, fee_b, 0).unwrap();
+            assert_eq!(winner.add_transaction(payment), Ok(false));
+            for _ in 0..3 {
+                produce(&mut winner, &sk_b);
+            }
+            let loser = blockchain.clone();
+
+            let supply = |blockchain: &Blockchain, rewards: u64, fee: u64| {
+                let ledger = blockchain.ledger();
+                let burned = match fee_sink {
+                    FeeSink::Burn => fee,
+                    FeeSink::ToProposer => 0,
+                };
+                assert_eq!(ledger.audit(), vec![]);
+                assert_eq!(ledger.fees_paid(), fee);
+                assert_eq!(ledger.total_burned(), burned);
+                assert_eq!(ledger.minted(), 2 * ROOT_AMOUNT + rewards * BLOCK_REWARD);
+                assert_eq!(ledger.circulating_supply(), ledger.minted() - burned);
+                assert_eq!(blockchain.get_balance(&a) + blockchain.get_balance(&b), ledger.circulating_supply());
+                assert_eq!(blockchain.verify_chain(), Ok(()));
+            };
+            supply(&loser, 1, fee_a);
+            let kept_fee = |fee| match fee_sink {
+                FeeSink::Burn => 0,
+                FeeSink::ToProposer => fee,
+            };
+            assert_eq!(
+                loser.get_balance(&a),
+                ROOT_AMOUNT + BLOCK_REWARD - 10 - fee_a + kept_fee(fee_a)
+            );
+
+            for depth in 1..=3 {
+                let _ = blockchain.add_block(Block::clone(winner.best_path_block(depth).unwrap()));
+            }
+            assert_eq!(blockchain.tip(), winner.tip());
+            supply(&blockchain, 3, fee_b);
+            assert_eq!(blockchain.get_balance(&a), ROOT_AMOUNT + 20);
+            assert_eq!(
+                blockchain.get_balance(&b),
+                ROOT_AMOUNT + 3 * BLOCK_REWARD - 20 - fee_b + kept_fee(fee_b)
+            );
+            assert_eq!(blockchain.stats().fees, fee_b);
+
+            // back to the shorter branch and forward again
+            let (short_tip, long_tip) = ((loser.tip().hash, 1), (winner.tip().hash, 3));
+            blockchain.rollback(long_tip, short_tip);
+            assert_eq!(blockchain.ledger(), loser.ledger());
+            blockchain.rollback(short_tip, long_tip);
+            assert_eq!(blockchain.ledger(), winner.ledger());
+            supply(&blockchain, 3, fee_b);
+        }
+    }
+
+    // a chain where every draw wins
+    fn create_winning_blockchain() -> (Blockchain, SecretKey, ManualClock) {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let blockchain = Blockchain::start_with_clock(vec![pk], params, Arc::new(clock.clone()));
+        (blockchain, sk, clock)
+    }
+
+    #[test]
+    fn epoch_seeds_change_exactly_at_epoch_boundaries() {
+        let (mut blockchain, sk, clock) = create_winning_blockchain();
+        let epoch = Timeslot(SEED_EPOCH_SLOTS);
+        let genesis_seed = blockchain.get_next_seed(Timeslot(1));
+        let first_before = blockchain.get_next_seed(epoch);
+
+        // a block in the first epoch only changes the seed of the epoch after it
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        assert_eq!(blockchain.get_next_seed(Timeslot(2)), genesis_seed);
+        assert_ne!(blockchain.get_next_seed(epoch), first_before);
+
+        // the last block before the boundary
+        clock.advance_slots(SEED_EPOCH_SLOTS - 3);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let boundary = Arc::clone(blockchain.best_path_block(2).unwrap());
+        assert_eq!(boundary.timeslot, Timeslot(SEED_EPOCH_SLOTS - 1));
+        let seeds: Vec<_> = (SEED_EPOCH_SLOTS - 1..=2 * SEED_EPOCH_SLOTS)
+            .map(|timeslot| blockchain.get_next_seed(Timeslot(timeslot)))
+            .collect();
+        assert_eq!(seeds[0], genesis_seed);
+        let first = seeds[1].clone();
+        assert_eq!(first.block_ptr, (boundary.hash, boundary.depth));
+        assert!(seeds[1..=SEED_EPOCH_SLOTS as usize].iter().all(|seed| *seed == first));
+        assert_ne!(seeds[SEED_EPOCH_SLOTS as usize + 1], first);
+
+        // a block in the epoch does not change its seed
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let block = blockchain.best_path_block(3).unwrap();
+        assert_eq!(block.timeslot, epoch);
+        assert_eq!(block.draw.seed, first);
+        assert_eq!(blockchain.get_next_seed(epoch.next()), first);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn draws_for_the_seed_of_another_epoch_are_rejected() {
+        let (mut blockchain, sk, clock) = create_winning_blockchain();
+        let pk = sk.to_public_key();
+        clock.advance_slots(SEED_EPOCH_SLOTS - 2);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        clock.advance_slots(1);
+        let timeslot = blockchain.calculate_timeslot();
+        assert_eq!(epoch_of(timeslot), 1);
+
+        // the seed the previous block was drawn with
+        let stale = blockchain.best_path_block(1).unwrap().draw.seed.clone();
+        let stale_draw = Draw::new(timeslot, blockchain.get_best_hash(), stale, pk.clone(), &sk);
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), stale_draw, &sk);
+        assert_eq!(blockchain.add_block(Arc::new(block)), BlockchainError::InvalidSeed.into());
+
+        // the seed of the next epoch
+        let early = blockchain.get_next_seed(Timeslot(2 * SEED_EPOCH_SLOTS));
+        let early_draw = Draw::new(timeslot, blockchain.get_best_hash(), early, pk.clone(), &sk);
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), early_draw, &sk);
+        assert_eq!(blockchain.add_block(Arc::new(block)), BlockchainError::InvalidSeed.into());
+
+        let seed = blockchain.get_next_seed(timeslot);
+        let draw = Draw::new(timeslot, blockchain.get_best_hash(), seed, pk, &sk);
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw, &sk);
+        assert_eq!(blockchain.add_block(Arc::new(block)), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        // a seed cached wrong is found when the chain is verified
+        let tip = blockchain.best_path_head().ptr();
+        blockchain.epoch_seeds.insert(tip, [0; 32]);
+        assert_eq!(
+            blockchain.verify_chain(),
+            Err(ChainVerifyReport { block: tip, violation: ChainViolation::EpochSeed })
+        );
+    }
+
+    #[test]
+    fn draws_grafted_onto_another_parent_are_rejected() {
+        let (mut blockchain, sk, clock) = create_winning_blockchain();
+        let genesis_hash = blockchain.get_best_hash();
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        clock.advance_slots(1);
+
+        // the winning draw on the tip, reused by its winner for a sibling of the tip in the same timeslot
+        let draw = blockchain.get_draw(&sk, &mut DrawCache::default());
+        let on_tip = blockchain.get_new_block(blockchain.get_best_hash(), draw.clone(), &sk);
+        let winner = sk.to_public_key();
+        let sibling = Block::new(draw.timeslot, genesis_hash, 1, winner, vec![], [0; 32], &sk, draw.seed.clone());
+        let grafted = sibling.with_draw(on_tip.draw.clone(), &sk);
+        assert!(grafted.verify_signature());
+        assert_eq!(blockchain.add_block(grafted), BlockchainError::DrawMismatch.into());
+        assert_eq!(blockchain.block_count(), 2);
+
+        assert_eq!(blockchain.add_block(on_tip), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[cfg(not(feature = "ed25519"))]
+    #[test]
+    fn signers_with_keys_below_the_minimum_are_rejected() {
+        let small_sk = SecretKey::generate_with_bits(1024).unwrap();
+        let sk = SecretKey::generate_with_bits(2048).unwrap();
+        let (_, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            min_key_bits: 2048,
+            ..Default::default()
+        };
+        let root_accounts = vec![small_sk.to_public_key(), sk.to_public_key()];
+        let mut blockchain = Blockchain::start_with_clock(root_accounts, params, Arc::new(clock.clone()));
+        clock.advance_slots(1);
+
+        let draw = blockchain.get_draw(&small_sk, &mut DrawCache::default());
+        let small = blockchain.get_new_block(blockchain.get_best_hash(), draw, &small_sk);
+        assert_eq!(blockchain.add_block(small.clone()), BlockchainError::KeyTooSmall.into());
+        assert_eq!(blockchain.add_header(small.header().clone()), BlockchainError::KeyTooSmall.into());
+        let transaction = Transaction::new(small_sk.to_public_key(), receiver, &small_sk, 10, 0).unwrap();
+        assert_eq!(
+            blockchain.add_transaction(transaction),
+            TxValidationError::KeyTooSmall { bits: 1024, minimum: 2048 }.into()
+        );
+        assert_eq!(blockchain.block_count(), 1);
+
+        let draw = blockchain.get_draw(&sk, &mut DrawCache::default());
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw, &sk);
+        assert_eq!(blockchain.add_block(block), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        // a chain that allowed the small key is not the chain of the larger minimum
+        blockchain.params.min_key_bits = 1024;
+        clock.advance_slots(1);
+        let draw = blockchain.get_draw(&small_sk, &mut DrawCache::default());
+        let small = blockchain.get_new_block(blockchain.get_best_hash(), draw, &small_sk);
+        assert_eq!(blockchain.add_block(small), Ok(()));
+        blockchain.params.min_key_bits = 2048;
+        assert_eq!(
+            blockchain.verify_chain(),
+            Err(ChainVerifyReport {
+                block: blockchain.best_path_head().ptr(),
+                violation: ChainViolation::KeyTooSmall
+            })
+        );
+    }
+
+    #[test]
+    fn blocks_signed_by_another_key_than_their_draw_are_rejected() {
+        let (mut blockchain, sk, clock) = create_winning_blockchain();
+        let (other_sk, _) = crate::generate_keypair();
+        clock.advance_slots(1);
+
+        // the winning draw of `sk` in a block that `other_sk` signs to take it over
+        let draw = blockchain.get_draw(&sk, &mut DrawCache::default());
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw.clone(), &sk);
+        let taken = block.with_draw(draw, &other_sk);
+        assert!(taken.hash_matches() && !taken.verify_signature());
+        assert!(!taken.verify_all(&HashSet::new()));
+        assert_eq!(blockchain.add_block(taken.clone()), BlockchainError::ProposerMismatch.into());
+        assert_eq!(blockchain.add_header(taken.header().clone()), BlockchainError::ProposerMismatch.into());
+        assert_eq!(blockchain.block_count(), 1);
+
+        assert!(block.verify_all(&HashSet::new()));
+        assert_eq!(blockchain.add_block(block), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn prechecked_blocks_are_known_by_their_signature_too() {
+        let (mut blockchain, sk, clock) = create_winning_blockchain();
+        let (other_sk, _) = crate::generate_keypair();
+        clock.advance_slots(1);
+        let draw = blockchain.get_draw(&sk, &mut DrawCache::default());
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw.clone(), &sk);
+        let taken = block.with_draw(draw, &other_sk);
+
+        blockchain.precheck_block(&block);
+        blockchain.precheck_block(&taken);
+        assert_eq!(blockchain.prechecked.get(&block), Some(true));
+        // the same hash signed by another key is checked again in full
+        assert_eq!(blockchain.prechecked.get(&taken), None);
+        assert_eq!(
+            blockchain.add_block(taken),
+            BlockchainError::ProposerMismatch.into()
+        );
+        assert_eq!(blockchain.add_block(block), Ok(()));
+    }
+
+    #[test]
+    fn payments_to_the_sender_are_refused_in_blocks() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let sender = keys[2].to_public_key();
+        let to_self = Transaction::sign(sender.clone(), vec![(sender, 10)], &keys[2], TRANSACTION_FEE, 0, Timeslot::MAX);
+        assert_eq!(blockchain.add_transaction(to_self.clone()), TxValidationError::SelfTransfer.into());
+        assert_eq!(blockchain.mempool_len(), 0);
+
+        // a proposer includes it anyway and commits to the ledger without it
+        let draw = winning_draw(&blockchain, &clock, &keys[0], 1);
+        let block = blockchain.get_new_block(blockchain.get_best_hash(), draw.clone(), &keys[0]);
+        let hostile = Block::new(
+            draw.timeslot,
+            blockchain.get_best_hash(),
+            1,
+            keys[0].to_public_key(),
+            vec![to_self],
+            *block.state_root(),
+            &keys[0],
+            draw.seed,
+        );
+        assert_eq!(blockchain.add_block(hostile), BlockchainError::SelfTransfer.into());
+        assert_eq!(blockchain.add_block(block), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn chains_from_the_same_genesis_follow_the_same_blocks() {
+        let (mut blockchain, keys, clock) = create_dummy_blockchain();
+        let genesis = blockchain.genesis();
+        let mut joined =
+            Blockchain::from_genesis(genesis.block.clone(), genesis.root_accounts.clone(), genesis.start_time)
+                .unwrap();
+        assert_eq!(joined.tip(), blockchain.tip());
+        assert_eq!(joined.get_start_time(), blockchain.get_start_time());
+
+        for _ in 0..3 {
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &keys[0], 1000), Ok(()));
+        }
+        for depth in 1..=3 {
+            let block = Arc::clone(blockchain.best_path_block(depth).unwrap());
+            assert_eq!(joined.add_block(block), Ok(()));
+        }
+        assert_eq!(joined.tip(), blockchain.tip());
+        let winner = AccountId::from(&keys[0].to_public_key());
+        assert_eq!(joined.get_balance(&winner), blockchain.get_balance(&winner));
+        assert_eq!(joined.verify_chain(), Ok(()));
+
+        // the genesis block of other root accounts or params is another network
+        let mut other_roots = genesis.root_accounts.clone();
+        other_roots.pop();
+        assert_eq!(
+            Blockchain::from_genesis(genesis.block.clone(), other_roots, genesis.start_time).map(|_| ()),
+            BlockchainError::InvalidGenesisBlock.into()
+        );
+        let params = ChainParams {
+            root_amount: ROOT_AMOUNT + 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            Blockchain::from_genesis_with_params(genesis.block.clone(), genesis.root_accounts.clone(), params, 0)
+                .map(|_| ()),
+            BlockchainError::InvalidGenesisBlock.into()
+        );
+        // the genesis block is not signed, so a signed one is refused whoever signed it
+        let signed = Block::new(
+            Timeslot::GENESIS,
+            genesis.block.prev_hash,
+            0,
+            genesis.block.winner().clone(),
+            Vec::new(),
+            *genesis.block.state_root(),
+            &keys[0],
+            genesis.block.draw.seed.clone(),
+        );
+        assert_eq!(
+            Blockchain::from_genesis(signed, genesis.root_accounts.clone(), genesis.start_time).map(|_| ()),
+            BlockchainError::InvalidGenesisBlock.into()
+        );
+        let not_genesis = Block::clone(blockchain.best_path_block(1).unwrap());
+        assert_eq!(
+            Blockchain::from_genesis(not_genesis, genesis.root_accounts, genesis.start_time).map(|_| ()),
+            BlockchainError::InvalidGenesisBlock.into()
+        );
+    }
+
+    #[test]
+    fn refused_blocks_leave_the_chain_unchanged() {
+        let (sk, pk) = crate::generate_keypair();
+        let (receiver_sk, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        // without hardness every draw wins, so the transactions are what is refused
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let first = Arc::clone(blockchain.best_path_block(1).unwrap());
+        let payment = Transaction::new(pk.clone(), receiver.clone(), &sk, 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment), Ok(false));
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+
+        let pending = Transaction::new(pk.clone(), receiver.clone(), &sk, 20, 1).unwrap();
+        assert_eq!(blockchain.add_transaction(pending.clone()), Ok(false));
+        let before = bincode::serialize(&blockchain).unwrap();
+        // the root commits to the ledger with the invalid transaction skipped, as a node that skipped it would
+        let block_on = |blockchain: &Blockchain, parent: &Block, transactions: Vec<Transaction>| {
+            clock.advance_slots(1);
+            let timeslot = blockchain.calculate_timeslot();
+            let mut ledger = blockchain.stake_ledgers[&parent.depth].clone();
+            for t in transactions.iter() {
+                ledger.process_transaction(t, parent.depth + 1);
+            }
+            ledger.reward_winner(&AccountId::from(&pk), BLOCK_REWARD, parent.depth + 1).unwrap();
+            let seed = blockchain.epoch_seed(parent, timeslot).unwrap();
+            Block::new(timeslot, parent.hash, parent.depth + 1, pk.clone(), transactions, ledger.state_root(), &sk, seed)
+        };
+
+        // more than the sender has, next to a transaction of the mempool
+        let overspend = Transaction::new(pk.clone(), receiver.clone(), &sk, 10 * ROOT_AMOUNT, 2).unwrap();
+        let tip = Arc::clone(blockchain.best_path_block(2).unwrap());
+        let block = block_on(&blockchain, &tip, vec![pending.clone(), overspend]);
+        assert_eq!(blockchain.add_block(block), BlockchainError::InvalidTransaction.into());
+        assert_eq!(bincode::serialize(&blockchain).unwrap(), before);
+
+        // the receiver only has money on the best path, so spending it on a fork off the first block is refused
+        let spend = Transaction::new(receiver.clone(), pk.clone(), &receiver_sk, 5, 0).unwrap();
+        let fork = block_on(&blockchain, &first, vec![spend.clone()]);
+        assert_eq!(blockchain.add_block(fork), BlockchainError::InvalidTransaction.into());
+        assert_eq!(bincode::serialize(&blockchain).unwrap(), before);
+        // while on the tip it is fine
+        let block = block_on(&blockchain, &tip, vec![spend]);
+        assert_eq!(blockchain.add_block(block), Ok(()));
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+        assert!(blockchain.mempool_iter().any(|t| t.hash == pending.hash));
+
+        // a block whose winner did not win is refused before its transactions are looked at
+        let impossible = ChainParams {
+            hardness: difficulty::max_hash(),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], impossible, Arc::new(clock.clone()));
+        let genesis = Arc::clone(blockchain.best_path_block(0).unwrap());
+        let before = bincode::serialize(&blockchain).unwrap();
+        let block = block_on(&blockchain, &genesis, vec![]);
+        assert_eq!(blockchain.add_block(block), BlockchainError::FalseWinner.into());
+        assert_eq!(bincode::serialize(&blockchain).unwrap(), before);
+    }
+
+    #[test]
+    fn transactions_after_a_gap_wait_for_it() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        let sender = AccountId::from(&pk);
+        let pay = |amount, nonce| Transaction::new(pk.clone(), receiver.clone(), &sk, amount, nonce).unwrap();
+
+        // the second transaction is parked and left out of blocks until the first arrives
+        let (first, second) = (pay(10, 0), pay(20, 1));
+        assert_eq!(blockchain.add_transaction(second.clone()), Ok(false));
+        assert_eq!(blockchain.next_nonce(&sender), 0);
+        assert_eq!(blockchain.transaction_buffer.parked_len(), 1);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        assert_eq!(blockchain.best_path_block(1).unwrap().transaction_count(), 0);
+
+        assert_eq!(blockchain.add_transaction(first.clone()), Ok(false));
+        assert_eq!(blockchain.next_nonce(&sender), 2);
+        assert_eq!(blockchain.transaction_buffer.parked_len(), 0);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let included: Vec<_> = blockchain.best_path_block(2).unwrap().transactions().iter().map(|t| t.hash).collect();
+        assert_eq!(included, [first.hash, second.hash]);
+        assert_eq!(blockchain.mempool_len(), 0);
+        let stale = TxValidationError::NonceTooLow { expected: 2, got: 1 };
+        assert_eq!(blockchain.add_transaction(pay(5, 1)), stale.into());
+
+        // a gap that is not filled drops the transactions after it
+        assert_eq!(blockchain.add_transaction(pay(30, 3)), Ok(false));
+        clock.advance_slots(MAX_PARKED_SLOTS - 1);
+        blockchain.purge_mempool();
+        assert_eq!(blockchain.mempool_len(), 1);
+        clock.advance_slots(1);
+        blockchain.purge_mempool();
+        assert_eq!(blockchain.mempool_len(), 0);
+
+        // a block must have the transactions of a sender in nonce order
+        let tip = Arc::clone(blockchain.best_path_block(2).unwrap());
+        let swapped = vec![pay(2, 3), pay(1, 2)];
+        clock.advance_slots(1);
+        let timeslot = blockchain.calculate_timeslot();
+        let mut ledger = blockchain.stake_ledgers[&tip.depth].clone();
+        for t in swapped.iter() {
+            ledger.process_transaction(t, tip.depth + 1);
+        }
+        ledger.reward_winner(&sender, BLOCK_REWARD, tip.depth + 1).unwrap();
+        let seed = blockchain.epoch_seed(&tip, timeslot).unwrap();
+        let block = Block::new(timeslot, tip.hash, tip.depth + 1, pk.clone(), swapped, ledger.state_root(), &sk, seed);
+        assert_eq!(blockchain.add_block(block), BlockchainError::InvalidTransaction.into());
+        assert_eq!(blockchain.tip(), TipRef { hash: tip.hash, depth: 2 });
+    }
+    #[test]
+    fn reindex_rebuilds_a_chain_whose_stored_ledger_is_corrupted() {
+        use crate::store::{BlockLog, ChainStore};
+        let (sk_a, pk_a) = crate::generate_keypair();
+        let (sk_b, pk_b) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain =
+            Blockchain::start_with_clock(vec![pk_a.clone(), pk_b.clone()], params, Arc::new(clock.clone()));
+        let produce = |blockchain: &mut Blockchain, sk: &SecretKey| {
+            let mut draws = DrawCache::default();
+            loop {
+                clock.advance_slots(1);
+                if let Some(block) = blockchain.try_produce(sk, &mut draws) {
+                    let block = Arc::new(block);
+                    assert_eq!(blockchain.add_block(block.clone()), Ok(()));
+                    return block;
+                }
+            }
+        };
+        for nonce in 0..4 {
+            let payment = Transaction::new(pk_a.clone(), pk_b.clone(), &sk_a, 10, nonce).unwrap();
+            assert_eq!(blockchain.add_transaction(payment), Ok(false));
+            produce(&mut blockchain, &sk_a);
+        }
+        // a branch that does not end up on the best path
+        let mut side = blockchain.clone();
+        let forked = produce(&mut side, &sk_b);
+        produce(&mut blockchain, &sk_a);
+        let _ = blockchain.add_block(forked);
+        produce(&mut blockchain, &sk_a);
+
+        // the ledger is corrupted on its way to the disk, and a block is appended after it
+        let path = std::env::temp_dir().join(format!("lasagna-reindex-{}.log", uuid::Uuid::new_v4()));
+        let mut log = BlockLog::new(&path);
+        let mut corrupted = blockchain.clone();
+        *corrupted.ledger.map.get_mut(&AccountId::from(&pk_b)).unwrap() += 1;
+        log.compact(&corrupted).unwrap();
+        log.append(&produce(&mut blockchain, &sk_a)).unwrap();
+        assert!(BlockLog::new(&path).load().unwrap().unwrap().verify_chain().is_err());
+
+        let reindexed = BlockLog::new(&path).reindex().unwrap().unwrap();
+        assert_eq!(reindexed.verify_chain(), Ok(()));
+        assert_eq!(reindexed.tip(), blockchain.tip());
+        assert_eq!(reindexed.block_count(), blockchain.block_count());
+        assert_eq!(reindexed.ledger().state_root(), blockchain.ledger().state_root());
+        // the log now holds the rebuilt chain
+        let stored = BlockLog::new(&path).load().unwrap().unwrap();
+        assert_eq!(stored.verify_chain(), Ok(()));
+        assert_eq!(stored.tip(), blockchain.tip());
+
+        // the order the blocks come in does not matter
+        let (genesis, blocks) = BlockLog::new(&path).blocks().unwrap().unwrap();
+        let reindex = |blocks: Vec<Block>| {
+            let genesis = genesis.clone();
+            Blockchain::reindex(blocks.into_iter(), genesis.params, genesis.root_accounts, genesis.start_time)
+        };
+        let reversed = reindex(blocks.iter().rev().cloned().collect()).unwrap();
+        assert_eq!(reversed.tip(), blockchain.tip());
+
+        // a refused block is named with the reason
+        let mut tampered = blocks.clone();
+        let bad = tampered.iter_mut().find(|block| block.depth == 3).unwrap();
+        bad.header.timeslot += 1;
+        let bad = (bad.hash, bad.depth);
+        assert_eq!(
+            reindex(tampered).err(),
+            Some(ReindexError::InvalidBlock {
+                block: bad,
+                reason: BlockchainError::InvalidSignature,
+            })
+        );
+        let without_genesis = blocks.into_iter().filter(|block| block.depth > 0).collect();
+        assert_eq!(reindex(without_genesis).err(), Some(ReindexError::MissingGenesis));
+        let _ = std::fs::remove_file(&path);
     }
 }
 
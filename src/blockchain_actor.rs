@@ -1,105 +1,148 @@
 use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
 
-use rsa::{RsaPrivateKey, RsaPublicKey};
+use crate::keys::SecretKey;
 use tokio::sync::{
+    broadcast::error::RecvError,
     mpsc::Sender,
     oneshot,
 };
 
 use crate::{
-    block::Block, blockchain::Blockchain, clock_watch::ClockWatch, transaction::Transaction, ClientMessage
+    account::AccountId,
+    block::{Block, BlockHash},
+    blockchain::{Blockchain, Genesis, MempoolPruned, Snapshot, TipRef},
+    draw::DrawCache,
+    logging,
+    metrics::NodeMetrics,
+    sync::SyncProgress,
+    transaction::Transaction,
+    ClientMessage, Timeslot, SYNC_BATCH_BYTES,
 };
 
+// the actor is the only one that writes to the blockchain, the handles read it without going through the actor.
+// Signatures are checked and blocks signed under a read lock, so the write lock is only held to commit a change
 struct BlockchainActor {
     sending_channel: tokio::sync::mpsc::Sender<ClientMessage>,
-    blockchain: Blockchain,
-    account: RsaPublicKey,
-    account_sk: RsaPrivateKey,
+    blockchain: Arc<RwLock<Blockchain>>,
+    keys: Vec<SecretKey>, // the accounts we stake with, the first is the key of the node
+    draws: Vec<DrawCache>, // one for each key, a draw is only signed again when it changes
+    produced_in: Option<Timeslot>, // we never sign two blocks in a timeslot, not even with two of our keys
 }
 
 impl BlockchainActor {
     fn run(
-        blockchain: Blockchain,
-        account: RsaPublicKey,
-        account_sk: RsaPrivateKey,
+        blockchain: Arc<RwLock<Blockchain>>,
+        keys: Vec<SecretKey>,
         sending_channel: tokio::sync::mpsc::Sender<ClientMessage>,
     ) -> Self {
         Self {
             sending_channel,
             blockchain,
-            account,
-            account_sk,
+            draws: keys.iter().map(|_| DrawCache::default()).collect(),
+            keys,
+            produced_in: None,
+        }
+    }
+
+    // this happens every timeslot, so held blocks are added as soon as possible
+    fn produce(&mut self) -> Option<Arc<Block>> {
+        self.blockchain.write().unwrap().process_future_blocks();
+        // a reorg can move the tip below a block we produced in this timeslot
+        let timeslot = self.blockchain.read().unwrap().calculate_timeslot();
+        if self.produced_in.is_some_and(|produced_in| produced_in >= timeslot) {
+            return None;
+        }
+        // nothing else writes, so the tip is still the parent of the block when it is added
+        let block = self.blockchain.read().unwrap().try_produce_any(&self.keys, &mut self.draws);
+        let block = Arc::new(block?);
+        self.produced_in = Some(block.timeslot());
+        // the client is notified through the chain events
+        let added = self.blockchain.write().unwrap().add_block(Arc::clone(&block));
+        match added {
+            Ok(()) => Some(block),
+            Err(error) => {
+                let hash = hex::encode(block.hash);
+                let depth = block.depth;
+                tracing::warn!(target: logging::BLOCKCHAIN, %hash, depth, %error, "produced block not added");
+                None
+            }
         }
     }
 
     async fn handle_message(&mut self, msg: BlockchainActorMessage) {
         use BlockchainActorMessage::*;
         match msg {
-            AddTransaction(t) => {
-                self.blockchain.add_transaction(t.clone());
-            }
-            AddBlock(b) => {
-                if let Err(e) = self.blockchain.add_block(b) {
-                    println!("Error when adding block: {:?}", e)
+            AddTransaction(t, callback) => {
+                let txid = t.id();
+                let result = self.blockchain.write().unwrap().add_transaction(t);
+                match result {
+                    Ok(replaced) => tracing::debug!(target: logging::MEMPOOL, %txid, replaced, "transaction added"),
+                    Err(ref error) => tracing::debug!(target: logging::MEMPOOL, %txid, %error, "transaction refused"),
                 }
+                let _ = callback.send(result);
+            }
+            AddBlock(b, callback) => {
+                // the expensive part is done while the handles can still read
+                self.blockchain.read().unwrap().precheck_block(&b);
+                let result = self.blockchain.write().unwrap().add_block(*b);
+                // the client may not care about the outcome
+                let _ = callback.send(result);
             }
             CheckBalance(pk) => {
-                let balance = self.blockchain.get_balance(&pk);
+                let balance = self.blockchain.read().unwrap().get_balance(&pk);
                 self.sending_channel
                     .send(ClientMessage::BalanceOf(pk, balance))
                     .await
                     .unwrap();
             }
             Stake => {
-                let draw = self.blockchain.get_draw(&self.account_sk);
-                let prev_hash = self.blockchain.get_best_hash();
-                let new_depth = self.blockchain.best_path_head().1 + 1;
-                if self.blockchain.stake(draw.clone(), &self.account, new_depth) {
-                    let block = self
-                        .blockchain
-                        .get_new_block(prev_hash, draw.clone(), &self.account_sk);
-                    match self.blockchain.add_block(block.clone()) {
-                        Ok(_) => {
-                            self.sending_channel
-                            .send(ClientMessage::Won(block.clone()))
-                            .await
-                            .unwrap();
-                        },
-                        Err(e) => println!("Error when adding block: {:?}", e),
-                    } 
-                } else {
-                    //println!("lost a stake whomp whomp");
-                }
+                self.produce();
+            }
+            Produce(callback) => {
+                // the producer may have stopped
+                let _ = callback.send(self.produce());
             }
-            BlockchainCopy(callback) => {
-                callback.send(self.blockchain.clone()).unwrap();
+            PruneMempool(batch, callback) => {
+                let _ = callback.send(self.blockchain.write().unwrap().prune_mempool(batch));
             }
-            GetStartTime(callback) => {
-                callback.send(self.blockchain.get_start_time()).unwrap();
+            DevMine(blocks, callback) => {
+                let _ = callback.send(self.blockchain.write().unwrap().dev_mine(&self.keys[0], blocks));
+            }
+            #[cfg(feature = "rpc")]
+            Rpc(method, callback) => {
+                // the rpc server may have given up on the request
+                let _ = callback.send(crate::rpc::answer(&mut self.blockchain.write().unwrap(), method));
             }
         }
     }
 }
 
 enum BlockchainActorMessage {
-    AddTransaction(Transaction),
-    AddBlock(Block),
-    CheckBalance(RsaPublicKey),
+    AddTransaction(Transaction, oneshot::Sender<crate::Result<bool>>),
+    AddBlock(Box<Block>, oneshot::Sender<crate::Result<()>>),
+    CheckBalance(AccountId),
     Stake,
-    BlockchainCopy(oneshot::Sender<Blockchain>),
-    GetStartTime(oneshot::Sender<u128>),
+    Produce(oneshot::Sender<Option<Arc<Block>>>),
+    PruneMempool(usize, oneshot::Sender<MempoolPruned>),
+    DevMine(u64, oneshot::Sender<crate::Result<Vec<Arc<Block>>>>),
+    #[cfg(feature = "rpc")]
+    Rpc(crate::rpc::Method, oneshot::Sender<crate::rpc::RpcResult>),
 }
 
 impl Debug for BlockchainActorMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use BlockchainActorMessage::*;
         match self {
-            AddTransaction(_) => write!(f, "AddTransaction"),
-            AddBlock(_) => write!(f, "AddBlock"),
+            AddTransaction(_, _) => write!(f, "AddTransaction"),
+            AddBlock(_, _) => write!(f, "AddBlock"),
             CheckBalance(_) => write!(f, "CheckBalance"),
             Stake => write!(f, "Stake"),
-            BlockchainCopy(_) => write!(f, "BlockchainCopy"),
-            GetStartTime(_) => write!(f, "GetStartTime"),
+            Produce(_) => write!(f, "Produce"),
+            PruneMempool(_, _) => write!(f, "PruneMempool"),
+            DevMine(_, _) => write!(f, "DevMine"),
+            #[cfg(feature = "rpc")]
+            Rpc(_, _) => write!(f, "Rpc"),
         }
     }
 }
@@ -107,61 +150,87 @@ impl Debug for BlockchainActorMessage {
 #[derive(Clone)]
 pub struct BlockchainActorHandle {
     sender: tokio::sync::mpsc::Sender<BlockchainActorMessage>,
+    blockchain: Arc<RwLock<Blockchain>>, // read directly, the actor holds the write lock only to commit a change
 }
 
 impl BlockchainActorHandle {
     pub async fn new(
         blockchain: Blockchain,
-        account: RsaPublicKey,
-        account_sk: RsaPrivateKey,
+        account_sk: SecretKey,
+        client_tx: Sender<ClientMessage>,
+    ) -> Self {
+        Self::with_keys(blockchain, vec![account_sk], client_tx).await
+    }
+
+    /// Stakes with every key, producing at most one block a timeslot with the best winning draw among them.
+    /// The first key mines the blocks of a dev chain
+    pub async fn with_keys(
+        mut blockchain: Blockchain,
+        keys: Vec<SecretKey>,
         client_tx: Sender<ClientMessage>,
     ) -> Self {
+        assert!(!keys.is_empty(), "a node stakes with at least its own key");
         let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
-        let start_time = blockchain.get_start_time();
+        let mut events = blockchain.subscribe();
 
-        let mut actor = BlockchainActor::run(blockchain, account, account_sk, client_tx.clone());
+        let blockchain = Arc::new(RwLock::new(blockchain));
+        let mut actor = BlockchainActor::run(Arc::clone(&blockchain), keys, client_tx.clone());
         tokio::spawn(async move {
             while let Some(msg) = receiver.recv().await {
                 actor.handle_message(msg).await
             }
         });
 
-        // start a task that sends stake messages at beginning of timeslot, to keep checking if we won
-        {
-            let sender = sender.clone();
-            tokio::spawn(async move {
-                let mut clock = ClockWatch::start(start_time);
-                loop {
-                    clock.wait_for_update().await;
-                    sender.send(BlockchainActorMessage::Stake).await.unwrap();
+        // forward the chain events to the client
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => client_tx.send(ClientMessage::Chain(event)).await.unwrap(),
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(target: logging::BLOCKCHAIN, missed, "the client missed chain events");
+                        client_tx.send(ClientMessage::ChainEventsLagged(missed)).await.unwrap();
+                    }
+                    Err(RecvError::Closed) => break,
                 }
-            });
-        }
+            }
+        });
 
-        Self { sender }
+        Self { sender, blockchain }
     }
 
-    pub async fn add_transaction(&self, transaction: Transaction) {
+    /// Reads the chain without waiting for the actor, a block that is being added is either all in or not at all
+    pub fn read<T>(&self, f: impl FnOnce(&Blockchain) -> T) -> T {
+        f(&self.blockchain.read().unwrap())
+    }
+
+    /// Whether the transaction replaced one in the mempool, or why it was refused
+    pub async fn add_transaction(&self, transaction: Transaction) -> crate::Result<bool> {
+        let (tx, rx) = oneshot::channel();
         self.sender
-            .send(BlockchainActorMessage::AddTransaction(transaction))
+            .send(BlockchainActorMessage::AddTransaction(transaction, tx))
             .await
             .unwrap();
+        rx.await.unwrap()
     }
 
-    pub async fn add_block(&self, block: Block) {
+    /// An orphan tells which ancestor is missing in its error
+    pub async fn add_block(&self, block: Block) -> crate::Result<()> {
+        let (tx, rx) = oneshot::channel();
         self.sender
-            .send(BlockchainActorMessage::AddBlock(block))
+            .send(BlockchainActorMessage::AddBlock(Box::new(block), tx))
             .await
             .unwrap();
+        rx.await.unwrap()
     }
 
-    pub async fn check_balance(&self, account: RsaPublicKey) {
+    pub async fn check_balance(&self, account: AccountId) {
         self.sender
             .send(BlockchainActorMessage::CheckBalance(account))
             .await
             .unwrap();
     }
 
+    /// Adds a block if we won the current timeslot, the block is not broadcast
     pub async fn stake(&self) {
         self.sender
             .send(BlockchainActorMessage::Stake)
@@ -169,21 +238,191 @@ impl BlockchainActorHandle {
             .unwrap();
     }
 
+    /// Adds a block if we won the current timeslot and returns it
+    pub async fn produce(&self) -> Option<Arc<Block>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(BlockchainActorMessage::Produce(tx))
+            .await
+            .unwrap();
+        rx.await.unwrap()
+    }
+
     pub async fn get_blockchain_copy(&self) -> Blockchain {
+        self.read(Blockchain::clone)
+    }
+
+    pub async fn get_snapshot(&self) -> Snapshot {
+        self.read(Blockchain::export_snapshot)
+    }
+
+    pub async fn get_genesis(&self) -> Genesis {
+        self.read(Blockchain::genesis)
+    }
+
+    /// Fills in the gauges that are read from the chain
+    pub async fn sample_metrics(&self, mut metrics: NodeMetrics) -> NodeMetrics {
+        self.read(|blockchain| metrics.sample(blockchain));
+        metrics
+    }
+
+    pub async fn get_balance(&self, account: AccountId) -> u64 {
+        self.read(|blockchain| blockchain.get_balance(&account))
+    }
+
+    /// The nonce of the next transaction of the account, counting those in the mempool
+    pub async fn get_next_nonce(&self, account: AccountId) -> u64 {
+        self.read(|blockchain| blockchain.next_nonce(&account))
+    }
+
+    /// Any block in the tree, not only those on the best path
+    pub async fn get_block(&self, hash: BlockHash) -> Option<Arc<Block>> {
+        self.read(|blockchain| blockchain.block_by_hash(&hash).cloned())
+    }
+
+    /// Hashes of our best path for a peer to find the last block we share, see `Blockchain::locator`
+    pub async fn get_locator(&self) -> Vec<BlockHash> {
+        self.read(Blockchain::locator)
+    }
+
+    /// The hash and depth of the head of the best path
+    pub async fn get_tip(&self) -> TipRef {
+        self.read(Blockchain::tip)
+    }
+
+    /// The next blocks for a peer that is behind, see `Blockchain::sync_batch`
+    pub async fn get_sync_batch(
+        &self,
+        locator: Vec<BlockHash>,
+        max: usize,
+    ) -> Option<(SyncProgress, Vec<Block>)> {
+        self.read(|blockchain| blockchain.sync_batch(&locator, max, SYNC_BATCH_BYTES))
+    }
+
+    /// Drops up to a batch of transactions that can no longer go in a block, see `Blockchain::prune_mempool`
+    pub async fn prune_mempool(&self, batch: usize) -> MempoolPruned {
         let (tx, rx) = oneshot::channel();
         self.sender
-            .send(BlockchainActorMessage::BlockchainCopy(tx))
+            .send(BlockchainActorMessage::PruneMempool(batch, tx))
             .await
             .unwrap();
         rx.await.unwrap()
     }
 
-    pub async fn get_start_time(&self) -> u128 {
+    /// Mines the blocks at once on a dev chain, they are not broadcast
+    pub async fn dev_mine(&self, blocks: u64) -> crate::Result<Vec<Arc<Block>>> {
         let (tx, rx) = oneshot::channel();
         self.sender
-            .send(BlockchainActorMessage::GetStartTime(tx))
+            .send(BlockchainActorMessage::DevMine(blocks, tx))
             .await
             .unwrap();
         rx.await.unwrap()
     }
+
+    #[cfg(feature = "rpc")]
+    pub async fn rpc(&self, method: crate::rpc::Method) -> crate::rpc::RpcResult {
+        if method.writes() {
+            let (tx, rx) = oneshot::channel();
+            self.sender
+                .send(BlockchainActorMessage::Rpc(method, tx))
+                .await
+                .unwrap();
+            return rx.await.unwrap();
+        }
+        self.read(|blockchain| crate::rpc::answer_read(blockchain, method))
+    }
+
+    pub async fn get_start_time(&self) -> u128 {
+        self.read(Blockchain::get_start_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{clock::ManualClock, params::ChainParams, BLOCK_REWARD, ROOT_AMOUNT};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_client_is_told_when_it_misses_chain_events() {
+        let (sk, pk) = crate::generate_keypair();
+        let mut blockchain = Blockchain::start(vec![pk]);
+        blockchain.set_dev_mode();
+        // the client takes one message and then reads nothing while the blocks are mined
+        let (tx, mut rx) = mpsc::channel(1);
+        let handle = BlockchainActorHandle::new(blockchain, sk, tx).await;
+        let mined = handle
+            .dev_mine(crate::events::EVENT_CHANNEL_CAPACITY as u64)
+            .await
+            .unwrap();
+        assert_eq!(mined.len(), crate::events::EVENT_CHANNEL_CAPACITY);
+
+        let lagged = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(ClientMessage::ChainEventsLagged(missed)) = rx.recv().await {
+                    break missed;
+                }
+            }
+        });
+        assert!(lagged.await.unwrap() > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_are_never_torn_and_do_not_wait_for_verification() {
+        let (sk, pk) = crate::generate_keypair();
+        let miner = AccountId::from(&pk);
+        let clock = ManualClock::default();
+        let mut source = Blockchain::start_with_clock(vec![pk], ChainParams::default(), Arc::new(clock.clone()));
+        let mut blockchain = source.clone();
+        source.set_dev_mode();
+        blockchain.set_dev_mode();
+        let blocks = source.dev_mine(&sk, 40).unwrap();
+        clock.advance_slots(40);
+
+        let (tx, _rx) = mpsc::channel(100);
+        let handle = BlockchainActorHandle::new(blockchain, sk, tx).await;
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let handle = handle.clone();
+                let done = Arc::clone(&done);
+                std::thread::spawn(move || {
+                    let mut depths = Vec::new();
+                    loop {
+                        let depth = handle.read(|blockchain| {
+                            // the tip, its block and the ledger are always of the same chain
+                            let TipRef { hash, depth } = blockchain.tip();
+                            assert_eq!(blockchain.block_by_hash(&hash).map(|b| b.depth), Some(depth));
+                            assert_eq!(blockchain.best_path_block(depth).map(|b| b.hash), Some(hash));
+                            assert_eq!(blockchain.get_balance(&miner), ROOT_AMOUNT + depth * BLOCK_REWARD);
+                            depth
+                        });
+                        depths.push(depth);
+                        if done.load(Ordering::Relaxed) {
+                            return depths;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for block in blocks {
+            handle.add_block(Block::clone(&block)).await.unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        let mut between = 0;
+        for reader in readers {
+            let depths = reader.join().unwrap();
+            // every read sees the blocks added before it, in the order they were added
+            assert!(depths.windows(2).all(|pair| pair[0] <= pair[1]), "{depths:?}");
+            between += depths.iter().filter(|&&depth| depth > 0 && depth < 40).count();
+        }
+        // the reads went on while the blocks were being verified and added
+        assert!(between > 0);
+        assert_eq!(handle.get_tip().await.depth, 40);
+    }
 }
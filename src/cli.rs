@@ -2,17 +2,12 @@ use std::{path::PathBuf, time::Duration};
 
 use arrayref::array_ref;
 use bip39::{Language, Mnemonic, Seed};
-use rand::SeedableRng;
-use rand_chacha::ChaCha20Rng;
-use rsa::{
-    pkcs8::{der::zeroize::Zeroizing, DecodePublicKey},
-    RsaPrivateKey, RsaPublicKey,
-};
+use rsa::pkcs8::der::zeroize::Zeroizing;
 
 use tokio::io::AsyncBufReadExt;
 use tokio::{io::BufReader, sync::mpsc::Sender};
 
-use crate::{transaction::Transaction, CLIMessage, ClientMessage, Error, Result, WALLETS};
+use crate::{account::AccountId, keys::{self, PublicKey, SecretKey}, transaction::Transaction, CLIMessage, ClientMessage, Error, Result, WALLETS};
 
 pub(crate) async fn read_line() -> String {
     let mut line = String::new();
@@ -40,9 +35,26 @@ async fn read_input() -> Result<CLIMessage> {
                 .map(CLIMessage::PostTransaction)
         }
         "balance" => {
-            let public_key =
-                read_public_key_pem(&tokens.next().ok_or(Error::CLIError)?, WALLETS.clone())?;
-            Ok(CLIMessage::CheckBalance(public_key))
+            let token = tokens.next().ok_or(Error::CLIError)?;
+            // an address or the name of a pem in the wallets directory
+            let account = match token.parse() {
+                Ok(account) => account,
+                Err(_) => AccountId::from(&read_public_key_pem(&token, WALLETS.clone())?),
+            };
+            Ok(CLIMessage::CheckBalance(account))
+        }
+        "dump" => {
+            let dir = tokens.next().ok_or(Error::CLIError)?;
+            Ok(CLIMessage::Dump(PathBuf::from(dir)))
+        }
+        "peers" => {
+            let action = tokens.next().ok_or(Error::CLIError)?;
+            let file = PathBuf::from(tokens.next().ok_or(Error::CLIError)?);
+            match action.as_str() {
+                "export" => Ok(CLIMessage::ExportPeers(file)),
+                "import" => Ok(CLIMessage::ImportPeers(file)),
+                _ => Err(Error::CLIError),
+            }
         }
         _ => Err(Error::CLIError),
     }
@@ -64,29 +76,35 @@ async fn read_transaction(tokens: &mut impl Iterator<Item = String>) -> Result<C
 
 #[derive(Clone, Debug)]
 pub struct CliPreTransaction {
-    to: RsaPublicKey,
+    to: PublicKey,
     amount: u64,
 }
 
 impl CliPreTransaction {
-    pub(super) fn to_transaction(self, sk: &RsaPrivateKey) -> Transaction {
-        Transaction::new(sk.to_public_key(), self.to, sk, self.amount)
+    pub(super) fn to_transaction(self, sk: &SecretKey, nonce: u64) -> Result<Transaction> {
+        Transaction::new(sk.to_public_key(), self.to, sk, self.amount, nonce)
     }
 }
 
-fn read_public_key_pem(name: &str, wallets_dir: PathBuf) -> Result<RsaPublicKey> {
+fn read_public_key_pem(name: &str, wallets_dir: PathBuf) -> Result<PublicKey> {
     let dir = wallets_dir.join(format!("{name}.pem"));
-    let pem = std::fs::read_to_string(dir).map_err(|_| Error::CLIError)?;
-    RsaPublicKey::from_public_key_pem(&pem).map_err(|_| Error::CLIError)
+    let bytes = std::fs::read(dir).map_err(|_| Error::CLIError)?;
+    keys::import_public(&bytes)
 }
 
-pub fn key_from_seedphrase(seedphrase: &Zeroizing<String>) -> Result<RsaPrivateKey> {
+pub fn key_from_seedphrase(seedphrase: &Zeroizing<String>) -> Result<SecretKey> {
     Mnemonic::validate(seedphrase, Language::English).map_err(|_| Error::CLIError)?;
     let mnemonic = Mnemonic::from_phrase(seedphrase.as_str(), Language::English).unwrap();
     let seed = Seed::new(&mnemonic, "");
     let seed_array = *array_ref!(seed.as_bytes(), 0, 32);
-    let mut rng = ChaCha20Rng::from_seed(seed_array);
-    RsaPrivateKey::new(&mut rng, 2048).map_err(|_| Error::CLIError)
+    Ok(SecretKey::from_seed(seed_array))
+}
+
+/// Asks for a seed phrase on stdin and derives the key from it
+pub async fn read_seedphrase_key() -> SecretKey {
+    println!("Please enter your seed phrase:");
+    let seed_phrase = Zeroizing::new(read_line().await);
+    key_from_seedphrase(&seed_phrase).expect("key from seedphrase failed")
 }
 
 // a function to run the command line interface as a separate task
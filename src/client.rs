@@ -1,151 +1,984 @@
 // we create a client, this is where we combine the network with the core and the cli and handle the messages passed between these actors
 
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use rsa::{
-    pkcs8::der::zeroize::Zeroizing, RsaPrivateKey,
-    RsaPublicKey,
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
 };
-use tokio::sync::mpsc;
 
 use crate::{
-    blockchain::Blockchain,
+    account::AccountId,
+    address_book::AddressBook,
+    block::{Block, BlockHash},
+    block_producer::{BlockProducer, ProductionPolicy},
+    blockchain::{Blockchain, BlockchainError, BufferLimits, Genesis, Snapshot, SnapshotParts},
     blockchain_actor::BlockchainActorHandle,
+    clock_watch::ClockWatch,
+    events::ChainEvent,
+    keys::{PublicKey, SecretKey},
+    export::DumpFormat,
+    ledger::TxValidationError,
+    logging,
+    metrics::{NodeMetrics, NodeStatus},
     network_actor::NetworkHandle,
-    CLIMessage, ClientMessage, ExternalMessage,
+    params::ChainParams,
+    peer_scores::{self, Misbehavior, PeerLimits, PeerScores, Verdict},
+    policy::RelayPolicy,
+    requests::{Requests, Wanted},
+    store::SharedStore,
+    sync::SyncProgress,
+    transaction::Transaction,
+    pippi::handshake::{ChainId, Handshake, HandshakeError},
+    CLIMessage, ClientMessage, Error, ExternalMessage, ADDRESS_BOOK_FLUSH, BLOCK_LOG_COMPACTION,
+    MEMPOOL_PRUNE_BATCH, REQUEST_CHECK, SYNC_BATCH_SIZE,
 };
 
+pub const CHAIN_FILE: &str = "chain.bin";
+pub const BLOCK_LOG_FILE: &str = "blocks.log"; // the chain and the blocks accepted since, see `BlockLog`
+pub const ADDRESS_BOOK_FILE: &str = "peers.json"; // see `AddressBook`
+pub const DUMP_FILE: &str = "chain.json";
+pub const FORKS_FILE: &str = "forks.dot"; // render it with `dot -Tsvg forks.dot -o forks.svg`
+pub const DEV_KEY_FILE: &str = "dev.key.pem"; // the funded key of a dev node, unencrypted
+
+/// How a node treats its peers and where it keeps its state between runs
+#[derive(Debug, Clone, Default)]
+pub struct NodeConfig {
+    pub limits: PeerLimits,
+    pub data_dir: Option<PathBuf>, // the chain and the address book are read from here on start and written on shutdown
+    pub production: ProductionPolicy,
+    pub params: ChainParams, // of the chain a root node starts, a joining node takes those of the network
+    pub buffers: BufferLimits, // given to every chain we start
+    pub max_reorg_depth: Option<u64>, // deeper reorgs wait for `force_reorg`, `MAX_REORG_DEPTH` if none
+    pub relay_policy: RelayPolicy, // the accounts whose transactions the node neither takes in nor passes on
+    pub store: Option<SharedStore>, // the accepted blocks are appended to it, and the chain is read from it on start
+    pub genesis: Option<Genesis>, // the chain a root node starts instead of one of its root accounts and params starting now
+    pub dev: bool, // every draw wins and blocks are mined on demand, see `NodeBuilder::dev`
+    pub prune_depth: Option<u64>, // final blocks this far below the tip are kept without their transactions
+    pub staking_keys: Vec<SecretKey>, // the other accounts the node stakes with besides its own key
+}
+
+// a sync we asked for, until a batch reaches the tip of the peer
+#[derive(Debug)]
+struct SyncSession {
+    last_applied: Option<BlockHash>, // we ask another peer from here when the batches stop
+    span: tracing::Span, // the events of the session are in it
+}
+
+impl SyncSession {
+    fn start() -> Self {
+        Self {
+            last_applied: None,
+            span: tracing::info_span!(target: logging::SYNC, "sync"),
+        }
+    }
+}
+
+/// A running client, it keeps running until it is shut down
+pub struct ClientHandle {
+    sender: mpsc::Sender<ClientMessage>,
+    events: broadcast::Sender<ChainEvent>,
+    terminated: JoinHandle<()>,
+}
+
+impl ClientHandle {
+    /// The channel to the client
+    pub fn sender(&self) -> &mpsc::Sender<ClientMessage> {
+        &self.sender
+    }
+
+    /// The events of the chain the client is on, they continue on the chain of a snapshot it moves to
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// The channel the chain events are passed on in, for the servers that subscribe per connection
+    pub(crate) fn events(&self) -> &broadcast::Sender<ChainEvent> {
+        &self.events
+    }
+
+    /// Asks the client to stop, it stops producing, leaves the network and flushes its state
+    pub async fn shutdown(&self) {
+        // the client may already have stopped
+        let _ = self.sender.send(ClientMessage::Shutdown).await;
+    }
+
+    /// Resolves when the client has stopped
+    pub async fn await_terminated(self) {
+        self.terminated.await.expect("the client panicked");
+    }
+}
+
 pub struct ClientActor {
-    priv_key: RsaPrivateKey,
+    priv_key: SecretKey,
     network: NetworkHandle,
     blockchain: Option<BlockchainActorHandle>,
+    producer: Option<JoinHandle<()>>,
+    mempool_cleanup: Option<JoinHandle<()>>, // prunes the mempool of the chain every timeslot
     tx: mpsc::Sender<ClientMessage>,
+    requests: Requests, // the missing blocks and the sync we asked peers for
+    metrics: NodeMetrics, // the counters, the gauges are read when the metrics are asked for
+    peers: PeerScores,
+    data_dir: Option<PathBuf>,
+    joining: Option<BlockHash>, // the genesis block we started an empty chain with, until the snapshot of the network replaces it
+    snapshot_parts: Option<(SocketAddr, SnapshotParts)>, // the parts of the snapshot a peer is sending us
+    refused: HashMap<SocketAddr, HandshakeError>, // the nodes we refused to peer with and why
+    production: ProductionPolicy, // given to every chain we start producing on
+    buffers: BufferLimits, // given to every chain we start
+    max_reorg_depth: Option<u64>, // and so is this if it is set
+    relay_policy: RelayPolicy, // and the relay policy, set again with the `policy_set` RPC
+    dev: bool, // and dev mode
+    prune_depth: Option<u64>, // and how far below the tip the transactions are dropped, if they are
+    staking_keys: Vec<SecretKey>,
+    sync: Option<SyncSession>,
+    events: broadcast::Sender<ChainEvent>, // the chain events passed on to the subscribers of the handle
+    store: Option<SharedStore>,
+    appended: u64, // blocks appended to the store since it was compacted
+    started: Instant,
 }
 
 impl ClientActor {
-    pub async fn run_root(addr: SocketAddr, root_accounts: Vec<RsaPublicKey>) {
-        println!("Please enter your seed phrase:");
-        let seed_phrase = Zeroizing::new(crate::cli::read_line().await);
-        let sk = crate::cli::key_from_seedphrase(&seed_phrase).expect("key from seedphrase failed");
-
+    /// Starts a root node without the CLI, it continues the chain in the data directory if there is one
+    pub async fn start_root(
+        addr: SocketAddr,
+        root_accounts: Vec<PublicKey>,
+        sk: SecretKey,
+        config: NodeConfig,
+    ) -> ClientHandle {
         let (tx, rx) = mpsc::channel(100);
-        let network = NetworkHandle::new(addr, addr, tx.clone());
-
-        let blockchain = Blockchain::start(root_accounts, &sk.clone());
-
-        let blockchain_handle =
-            BlockchainActorHandle::new(blockchain, sk.to_public_key(), sk.clone(), tx.clone()).await;
-
-        crate::cli::run_cli(tx.clone());
-        ClientActor::read_messages(
-            Self {
-                priv_key: sk,
-                network,
-                blockchain: Some(blockchain_handle),
-                tx: tx.clone(),
+        let blockchain = match stored_chain(&config) {
+            Some(blockchain) => blockchain,
+            None => match config.genesis.clone() {
+                Some(Genesis { block, root_accounts, params, start_time }) => {
+                    Blockchain::from_genesis_with_params(block, root_accounts, params, start_time)
+                        .expect("invariant: the genesis block is made from the root accounts and params")
+                }
+                None => Blockchain::start_with_params(root_accounts, config.params.clone()),
             },
-            rx,
-        );
+        };
+        let handshake = handshake_of(Some(&blockchain));
+        let network = NetworkHandle::new(
+            vec![],
+            addr,
+            handshake,
+            address_book_of(&config),
+            config.limits.max_frame_bytes,
+            config.limits.upload_bytes_per_sec,
+            tx.clone(),
+        )
+        .await;
+
+        let mut client = Self::new(sk, network, tx.clone(), config);
+        client.start_blockchain(blockchain).await;
+        client.read_messages(rx)
     }
 
-    pub async fn run(seed_addr: SocketAddr, addr: SocketAddr) {
-        println!("Please enter your seed phrase:");
-        let seed_phrase = Zeroizing::new(crate::cli::read_line().await);
-        let sk = crate::cli::key_from_seedphrase(&seed_phrase).expect("key from seedphrase failed");
+    /// Starts a node that joins the network of the seed node without the CLI
+    pub async fn start(
+        seed_addr: SocketAddr,
+        addr: SocketAddr,
+        sk: SecretKey,
+        config: NodeConfig,
+    ) -> ClientHandle {
+        Self::start_with_seeds(vec![seed_addr], addr, sk, config).await
+    }
 
+    /// Starts a node that joins the network through the first of the seeds that answers without the CLI,
+    /// the seeds are dialed again whenever the node loses every peer
+    pub async fn start_with_seeds(
+        seeds: Vec<SocketAddr>,
+        addr: SocketAddr,
+        sk: SecretKey,
+        config: NodeConfig,
+    ) -> ClientHandle {
         let (tx, rx) = mpsc::channel(100);
-        let network = NetworkHandle::new(seed_addr, addr, tx.clone());
-        network
-            .request_bootstrap()
-            .await
-            .expect("unable to send Request Bootstrap message");
+        let stored = stored_chain(&config);
+        // without a chain we are let in by any network, and the seed node tells us its genesis block
+        let handshake = handshake_of(stored.as_ref());
+        let network = NetworkHandle::new(
+            seeds,
+            addr,
+            handshake,
+            address_book_of(&config),
+            config.limits.max_frame_bytes,
+            config.limits.upload_bytes_per_sec,
+            tx.clone(),
+        )
+        .await;
 
-        crate::cli::run_cli(tx.clone());
+        let mut client = Self::new(sk, network, tx.clone(), config);
+        match stored {
+            // the blocks we missed while we were gone are streamed to us by the seed node
+            Some(blockchain) => {
+                client.start_blockchain(blockchain).await;
+                client.request_sync().await;
+            }
+            // we start an empty chain of the network, then move to the checkpoint of the seed node
+            // instead of replaying its whole history
+            None => client
+                .network
+                .request_genesis()
+                .await
+                .expect("unable to send Request Genesis message"),
+        }
+        client.read_messages(rx)
+    }
 
-        ClientActor::read_messages(
-            Self {
-                priv_key: sk,
-                network,
-                blockchain: None,
-                tx,
-            },
-            rx,
-        );
+    fn new(
+        priv_key: SecretKey,
+        network: NetworkHandle,
+        tx: mpsc::Sender<ClientMessage>,
+        config: NodeConfig,
+    ) -> Self {
+        Self {
+            priv_key,
+            network,
+            blockchain: None,
+            producer: None,
+            mempool_cleanup: None,
+            tx,
+            requests: Requests::default(),
+            metrics: NodeMetrics::default(),
+            peers: PeerScores::new(config.limits),
+            data_dir: config.data_dir,
+            joining: None,
+            snapshot_parts: None,
+            refused: HashMap::new(),
+            production: config.production,
+            buffers: config.buffers,
+            max_reorg_depth: config.max_reorg_depth,
+            relay_policy: config.relay_policy,
+            dev: config.dev,
+            prune_depth: config.prune_depth,
+            staking_keys: config.staking_keys,
+            sync: None,
+            events: crate::events::channel(),
+            store: config.store,
+            appended: 0,
+            started: Instant::now(),
+        }
     }
 
-    fn read_messages(self, mut rx: mpsc::Receiver<ClientMessage>) {
-        tokio::spawn(async move {
+    fn read_messages(self, mut rx: mpsc::Receiver<ClientMessage>) -> ClientHandle {
+        let sender = self.tx.clone();
+        let events = self.events.clone();
+        if self.data_dir.is_some() {
+            save_address_book_every(ADDRESS_BOOK_FLUSH, sender.clone());
+        }
+        retry_requests_every(REQUEST_CHECK, sender.clone());
+        let terminated = tokio::spawn(async move {
             let mut client = self;
             while let Some(msg) = rx.recv().await {
+                let stop = matches!(msg, ClientMessage::Shutdown);
                 client.handle_message(msg).await;
+                if stop {
+                    break;
+                }
             }
         });
+        ClientHandle {
+            sender,
+            events,
+            terminated,
+        }
     }
 
     async fn handle_message(&mut self, msg: ClientMessage) {
         match msg {
-            ClientMessage::Won(block) => {
-                //println!("We won a block");
-                self.network.broadcast_block(block).await.unwrap();
+            ClientMessage::Chain(event) => self.handle_chain_event(event).await,
+            // the blocks accepted among the missed events were never appended, the chain as it is now has them all
+            ClientMessage::ChainEventsLagged(missed) => {
+                if let (Some(_), Some(blockchain)) = (&self.store, self.blockchain.clone()) {
+                    tracing::warn!(target: logging::STORE, missed, "compacting the block log after missed chain events");
+                    self.compact(&blockchain.get_blockchain_copy().await);
+                }
+            }
+            // we only broadcast the blocks we have won, the rest are relayed by the network
+            ClientMessage::Produced(block) => {
+                self.network
+                    .broadcast_block(Block::clone(&block))
+                    .await
+                    .unwrap();
             }
             ClientMessage::BalanceOf(_wallet, balance) => {
                 println!("Wallet has {} las", balance);
             }
-            ClientMessage::External(ext_msg) => self.handle_external_message(ext_msg).await,
+            ClientMessage::External(from, ext_msg) => {
+                if self.admit(from, &ext_msg).await {
+                    self.handle_external_message(from, *ext_msg).await
+                }
+            }
+            ClientMessage::Malformed(from) => self.punish(from, Misbehavior::Malformed).await,
+            ClientMessage::BannedPeers(reply) => {
+                let _ = reply.send(self.peers.banned(crate::get_unix_timestamp()));
+            }
+            ClientMessage::PeerRefused(peer, reason) => {
+                tracing::warn!(target: logging::NET, %peer, %reason, "refused to peer");
+                self.refused.insert(peer, reason);
+            }
+            ClientMessage::RefusedPeers(reply) => {
+                let mut refused: Vec<_> = self.refused.clone().into_iter().collect();
+                refused.sort_by_key(|(peer, _)| *peer);
+                let _ = reply.send(refused);
+            }
             ClientMessage::CLI(cli_msg) => self.handle_cli_message(cli_msg).await,
+            #[cfg(feature = "rpc")]
+            ClientMessage::Rpc(call) => self.handle_rpc_call(call).await,
+            ClientMessage::Metrics(reply) => {
+                let _ = reply.send(self.read_metrics().await);
+            }
+            ClientMessage::Status(reply) => {
+                let _ = reply.send(self.status().await);
+            }
+            ClientMessage::RetryRequests => self.retry_requests().await,
+            // what we asked the seed before may never have reached it
+            ClientMessage::Reconnected(seed) => {
+                tracing::info!(target: logging::NET, peer = %seed, "reconnected");
+                if self.blockchain.is_none() {
+                    let _ = self.network.request_genesis().await;
+                } else if self.joining.is_some() {
+                    let _ = self.network.request_snapshot().await;
+                } else {
+                    self.request_sync().await;
+                }
+            }
+            ClientMessage::Connection(reply) => {
+                if let Ok(state) = self.network.connection_state().await {
+                    let _ = reply.send(state);
+                }
+            }
+            ClientMessage::SaveAddressBook => self.save_address_book().await,
+            ClientMessage::SubmitTransaction(transaction, reply) => {
+                let _ = reply.send(self.submit_transaction(transaction).await);
+            }
+            ClientMessage::GetBalance(account, reply) => {
+                let balance = match self.blockchain {
+                    Some(ref blockchain) => Some(blockchain.get_balance(account).await),
+                    None => None,
+                };
+                let _ = reply.send(balance);
+            }
+            ClientMessage::BestTip(reply) => {
+                let tip = match self.blockchain {
+                    Some(ref blockchain) => Some(blockchain.get_tip().await),
+                    None => None,
+                };
+                let _ = reply.send(tip);
+            }
+            ClientMessage::Shutdown => self.shutdown().await,
             ClientMessage::Ping => println!("Ping"),
         }
     }
 
-    async fn handle_external_message(&mut self, ext_msg: crate::ExternalMessage) {
+    // a block we are producing is finished by the blockchain actor, so the flushed chain has it
+    async fn shutdown(&mut self) {
+        if let Some(producer) = self.producer.take() {
+            producer.abort();
+        }
+        if let Some(cleanup) = self.mempool_cleanup.take() {
+            cleanup.abort();
+        }
+        let book = self.network.shutdown().await.unwrap_or_default();
+        if let Some(dir) = self.data_dir.clone() {
+            match self.flush(&dir, &book).await {
+                Ok(()) => tracing::info!(target: logging::STORE, dir = %dir.display(), "state written"),
+                Err(error) => {
+                    tracing::error!(target: logging::STORE, dir = %dir.display(), %error, "unable to write state")
+                }
+            }
+        }
+    }
+
+    // with a store the chain is compacted into it instead, so the next start does not replay the log
+    async fn flush(&self, dir: &Path, book: &AddressBook) -> crate::Result<()> {
+        std::fs::create_dir_all(dir).map_err(|_| Error::StorageError)?;
+        if let Some(ref blockchain) = self.blockchain {
+            let blockchain = blockchain.get_blockchain_copy().await;
+            match self.store {
+                Some(ref store) => store.lock().unwrap().compact(&blockchain)?,
+                None => blockchain.save(&dir.join(CHAIN_FILE))?,
+            }
+        }
+        book.save(&dir.join(ADDRESS_BOOK_FILE))
+    }
+
+    // so the nodes we learned of are not lost if we crash
+    async fn save_address_book(&self) {
+        let (Some(dir), Ok(book)) = (self.data_dir.as_deref(), self.network.address_book().await) else {
+            return;
+        };
+        let saved = std::fs::create_dir_all(dir)
+            .map_err(|_| Error::StorageError)
+            .and_then(|_| book.save(&dir.join(ADDRESS_BOOK_FILE)));
+        if let Err(error) = saved {
+            tracing::warn!(target: logging::STORE, dir = %dir.display(), %error, "unable to write the address book");
+        }
+    }
+
+    // whether the message is within the rate limits of the peer that sent it
+    async fn admit(&mut self, from: SocketAddr, ext_msg: &ExternalMessage) -> bool {
+        // a blockchain we asked for is large and only handled once
+        let awaited = match ext_msg {
+            ExternalMessage::Bootstrap(_) | ExternalMessage::Genesis(_) => self.blockchain.is_none(),
+            ExternalMessage::Snapshot(_) | ExternalMessage::SnapshotPart { .. } => {
+                self.blockchain.is_none() || self.joining.is_some()
+            }
+            ExternalMessage::SyncBatch(_, _) => self.sync.is_some(),
+            _ => false,
+        };
+        if awaited {
+            return true;
+        }
+        let bytes = bincode::serialized_size(ext_msg).unwrap_or(u64::MAX);
+        match self.peers.admit(from, bytes, crate::get_unix_timestamp()) {
+            Verdict::Handle => true,
+            Verdict::Drop => false,
+            Verdict::Ban(until) => {
+                self.ban(from, until).await;
+                false
+            }
+        }
+    }
+
+    async fn punish(&mut self, peer: SocketAddr, misbehavior: Misbehavior) {
+        if let Some(until) = self.peers.record(peer, misbehavior, crate::get_unix_timestamp()) {
+            self.ban(peer, until).await;
+        }
+    }
+
+    async fn ban(&self, peer: SocketAddr, until: u128) {
+        tracing::warn!(target: logging::NET, %peer, until, "banning a misbehaving peer");
+        self.network.ban(peer, until).await.unwrap();
+    }
+
+    async fn handle_chain_event(&mut self, event: ChainEvent) {
+        self.metrics.observe(&event);
+        // nobody may be subscribed
+        let _ = self.events.send(event.clone());
+        if let ChainEvent::BlockAccepted(ref block) = event {
+            self.store_block(block).await;
+        }
+        match event {
+            ChainEvent::Reorg {
+                old_tip,
+                new_tip,
+                depth,
+            } => {
+                tracing::info!(
+                    target: logging::BLOCKCHAIN,
+                    depth,
+                    old_tip = %hex::encode(old_tip.0),
+                    new_tip = %hex::encode(new_tip.0),
+                    "reorg"
+                );
+            }
+            ChainEvent::ReorgRefused {
+                old_tip,
+                new_tip,
+                depth,
+            } => {
+                tracing::error!(
+                    target: logging::BLOCKCHAIN,
+                    depth,
+                    old_tip = %hex::encode(old_tip.0),
+                    new_tip = %hex::encode(new_tip.0),
+                    "ALERT: refused a deep reorg, force_reorg the new tip to accept it"
+                );
+            }
+            ChainEvent::Equivocation(equivocation) => {
+                tracing::warn!(
+                    target: logging::BLOCKCHAIN,
+                    winner = %equivocation.second.winner_id(),
+                    timeslot = %equivocation.second.timeslot(),
+                    "equivocation"
+                );
+            }
+            ChainEvent::NewTip((_, depth)) => self.network.set_best_depth(depth).await.unwrap_or(()),
+            _ => (),
+        }
+    }
+
+    // a joining node moves from its empty chain to the snapshot of the network
+    async fn start_from_snapshot(&mut self, sender: SocketAddr, snapshot: Snapshot) {
+        if self.blockchain.is_some() && self.joining.is_none() {
+            return;
+        }
+        // the snapshot must be of the network we started the empty chain of
+        let genesis = snapshot.headers.first().map(|header| header.hash);
+        if self.joining.is_some() && genesis != self.joining {
+            tracing::warn!(target: logging::NET, peer = %sender, "received a snapshot of another network");
+            return;
+        }
+        match Blockchain::from_snapshot(snapshot) {
+            Ok(blockchain) => {
+                let depth = blockchain.checkpoint().1;
+                tracing::info!(target: logging::BLOCKCHAIN, depth, "started from a snapshot");
+                self.joining = None;
+                self.start_blockchain(blockchain).await;
+                // the peer has moved on while the snapshot was on its way
+                self.request_sync().await;
+            }
+            Err(error) => {
+                tracing::warn!(target: logging::NET, peer = %sender, %error, "received an invalid snapshot")
+            }
+        }
+    }
+
+    async fn handle_external_message(&mut self, sender: SocketAddr, ext_msg: ExternalMessage) {
         match ext_msg {
             ExternalMessage::Bootstrap(blockchain) => {
-                println!("Blockchain bootstrapped");
+                tracing::info!(target: logging::NET, peer = %sender, "blockchain bootstrapped");
+                if self.blockchain.is_none() {
+                    if let Err(error) = blockchain.verify_chain() {
+                        let peer = sender;
+                        return tracing::warn!(target: logging::NET, %peer, %error, "received an invalid blockchain");
+                    }
+                    self.start_blockchain(blockchain).await;
+                }
+            }
+            ExternalMessage::Genesis(genesis) => {
                 if self.blockchain.is_none() {
-                    if let Err(e) = blockchain.verify_chain() { println!("Received invalid blockchain {e:?}"); return };
-                    let account_sk = self.priv_key.clone();
-                    let blockchain = BlockchainActorHandle::new(
-                        blockchain,
-                        account_sk.to_public_key(),
-                        account_sk,
-                        self.tx.clone(),
-                    ).await;
-                    self.blockchain = Some(blockchain);
-                }
-            }
-            ExternalMessage::BootstrapReqFrom(from) => {
-                println!("Sending bootstrap to {from:?}");
+                    let Genesis { block, root_accounts, params, start_time } = *genesis;
+                    let hash = block.hash;
+                    match Blockchain::from_genesis_with_params(block, root_accounts, params, start_time) {
+                        Ok(blockchain) => {
+                            let genesis = hex::encode(hash);
+                            tracing::info!(target: logging::BLOCKCHAIN, %genesis, "started from the genesis block");
+                            self.start_blockchain(blockchain).await;
+                            self.joining = Some(hash);
+                            self.network
+                                .request_snapshot()
+                                .await
+                                .expect("unable to send Request Snapshot message");
+                        }
+                        Err(error) => {
+                            tracing::warn!(target: logging::NET, peer = %sender, %error, "received a bad genesis block")
+                        }
+                    }
+                }
+            }
+            ExternalMessage::Snapshot(snapshot) => self.start_from_snapshot(sender, *snapshot).await,
+            ExternalMessage::SnapshotPart { part, parts, bytes } => {
+                if self.blockchain.is_some() && self.joining.is_none() {
+                    return;
+                }
+                // the parts of one peer are put together, the first part starts over
+                if part == 0 {
+                    self.snapshot_parts = SnapshotParts::new(parts).map(|assembly| (sender, assembly));
+                }
+                let Some((_, assembly)) = self.snapshot_parts.as_mut().filter(|(from, _)| *from == sender) else {
+                    return;
+                };
+                match assembly.add(part, parts, &bytes) {
+                    Ok(None) => (),
+                    Ok(Some(snapshot)) => {
+                        self.snapshot_parts = None;
+                        self.start_from_snapshot(sender, snapshot).await;
+                    }
+                    Err(_) => {
+                        self.snapshot_parts = None;
+                        self.punish(sender, Misbehavior::Malformed).await;
+                    }
+                }
+            }
+            ExternalMessage::GenesisReqFrom(_) => {
+                if let Some(ref blockchain_handle) = self.blockchain {
+                    self.network
+                        .send_genesis_to(sender, blockchain_handle.get_genesis().await)
+                        .await
+                        .unwrap();
+                }
+            }
+            ExternalMessage::SnapshotReqFrom(_) => {
+                if let Some(ref blockchain_handle) = self.blockchain {
+                    self.network
+                        .send_snapshot_to(sender, blockchain_handle.get_snapshot().await)
+                        .await
+                        .unwrap();
+                    tracing::debug!(target: logging::NET, peer = %sender, "sent a snapshot");
+                }
+            }
+            ExternalMessage::BootstrapReqFrom(_) => {
                 if let Some(ref blockchain_handle) = self.blockchain {
                     self.network
                         .send_bootstraping_message_to(
-                            from,
+                            sender,
                             blockchain_handle.get_blockchain_copy().await,
                         )
                         .await
                         .unwrap();
-                    println!("Sent bootstrap to {from:?}");
+                    tracing::debug!(target: logging::NET, peer = %sender, "sent a bootstrap");
                 }
             }
-            ExternalMessage::BroadcastBlock(block) => {
+            ExternalMessage::BalanceReqFrom(_, account) => {
                 if let Some(ref blockchain_handle) = self.blockchain {
-                    blockchain_handle.add_block(block).await;
+                    let balance = blockchain_handle.get_balance(account).await;
+                    self.network
+                        .send_balance_to(sender, account, balance)
+                        .await
+                        .unwrap();
+                }
+            }
+            ExternalMessage::Balance(_, _) => (), // only wallets are interested in these
+            ExternalMessage::BroadcastBlock(block) | ExternalMessage::RequestedBlock(block) => {
+                if let Some(blockchain_handle) = self.blockchain.clone() {
+                    let hash = block.hash;
+                    // a flooded block is relayed by the network, one we asked for is announced to our other peers
+                    let asked = self.requests.is_requested(&Wanted::Block(hash)).then(|| block.clone());
+                    match blockchain_handle.add_block(block).await {
+                        // we walk back from the orphan until we reach our chain
+                        Err(Error::BlockchainError(BlockchainError::OrphanBlock(missing))) => {
+                            let now = crate::get_unix_timestamp();
+                            if self.requests.request_parent(sender, &hash, missing, now) {
+                                self.network.request_block(sender, missing).await.unwrap();
+                            }
+                        }
+                        result => {
+                            self.requests.received(&Wanted::Block(hash));
+                            match result {
+                                Ok(()) => {
+                                    if let Some(block) = asked {
+                                        self.network.relay_block(block, sender).await.unwrap();
+                                    }
+                                }
+                                Err(Error::BlockchainError(reason)) if peer_scores::is_invalid_block(&reason) => {
+                                    self.punish(sender, Misbehavior::InvalidBlock).await;
+                                }
+                                Err(_) => (),
+                            }
+                        }
+                    }
                 }
             },
-            ExternalMessage::BroadcastTransaction(t) => {
+            // the network only passes on the first announce of a block, we ask the announcer for it unless we have it
+            ExternalMessage::TipAnnounce { hash, depth, timeslot } => {
+                if let Some(ref blockchain_handle) = self.blockchain {
+                    if blockchain_handle.get_block(hash).await.is_some() {
+                        return;
+                    }
+                    let now = crate::get_unix_timestamp();
+                    if self.requests.request(Wanted::Block(hash), &[sender], now).is_some() {
+                        let (peer, block) = (sender, hex::encode(hash));
+                        tracing::debug!(target: logging::NET, %peer, %block, depth, %timeslot, "fetching a block");
+                        self.network.request_block(sender, hash).await.unwrap();
+                    }
+                }
+            }
+            ExternalMessage::BlockReqFrom(_, hash) => {
                 if let Some(ref blockchain_handle) = self.blockchain {
-                    blockchain_handle.add_transaction(t).await;
+                    if let Some(block) = blockchain_handle.get_block(hash).await {
+                        self.network
+                            .send_block_to(sender, Block::clone(&block))
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+            ExternalMessage::SyncReqFrom(_, locator) => {
+                if let Some(blockchain_handle) = self.blockchain.clone() {
+                    let network = self.network.clone();
+                    // the batches are streamed while we handle other messages
+                    tokio::spawn(async move {
+                        let mut locator = locator;
+                        while let Some((progress, blocks)) =
+                            blockchain_handle.get_sync_batch(locator, SYNC_BATCH_SIZE).await
+                        {
+                            let next = blocks.last().map(|block| vec![block.hash]);
+                            if network.send_sync_batch_to(sender, progress, blocks).await.is_err() {
+                                break;
+                            }
+                            match next {
+                                Some(next) if !progress.is_done() => locator = next,
+                                _ => break,
+                            }
+                        }
+                    });
+                }
+            }
+            ExternalMessage::SyncBatch(progress, blocks) => self.apply_sync_batch(sender, progress, blocks).await,
+            ExternalMessage::BroadcastTransaction(t) => {
+                if let Some(blockchain_handle) = self.blockchain.clone() {
+                    let result = blockchain_handle.add_transaction(t).await;
+                    // a transaction can become impossible on another fork, but only the sender can forge a signature
+                    if result == TxValidationError::BadSignature.into() {
+                        self.punish(sender, Misbehavior::InvalidTransaction).await;
+                    }
                 }
             },
         }
     }
 
+    // a crash after this returns does not lose the block, the log is rewritten as the chain every so often
+    async fn store_block(&mut self, block: &Block) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        if let Err(error) = store.lock().unwrap().append(block) {
+            let hash = hex::encode(block.hash);
+            tracing::error!(target: logging::STORE, %hash, %error, "unable to append the block to the store");
+        }
+        self.appended += 1;
+        if self.appended >= BLOCK_LOG_COMPACTION {
+            if let Some(blockchain) = self.blockchain.clone() {
+                self.compact(&blockchain.get_blockchain_copy().await);
+            }
+        }
+    }
+
+    fn compact(&mut self, blockchain: &Blockchain) {
+        let Some(ref store) = self.store else {
+            return;
+        };
+        match store.lock().unwrap().compact(blockchain) {
+            Ok(()) => self.appended = 0,
+            Err(error) => tracing::error!(target: logging::STORE, %error, "unable to compact the store"),
+        }
+    }
+
+    // the blockchain we start producing on once we have one, it replaces the one we had
+    async fn start_blockchain(&mut self, mut blockchain: Blockchain) {
+        // the blocks appended from now on are replayed on this chain
+        self.compact(&blockchain);
+        if let Some(producer) = self.producer.take() {
+            producer.abort();
+        }
+        if let Some(cleanup) = self.mempool_cleanup.take() {
+            cleanup.abort();
+        }
+        // the missing parents we asked for were for the old chain
+        self.requests = Requests::default();
+        self.network
+            .set_chain(chain_id(&blockchain), blockchain.tip().depth)
+            .await
+            .unwrap();
+        self.network.set_relay_policy(self.relay_policy.clone()).await.unwrap();
+        blockchain.set_production_policy(self.production.clone());
+        blockchain.set_buffer_limits(self.buffers.clone());
+        if let Some(depth) = self.max_reorg_depth {
+            blockchain.set_max_reorg_depth(depth);
+        }
+        blockchain.set_relay_policy(self.relay_policy.clone());
+        if self.prune_depth.is_some() {
+            blockchain.set_prune_depth(self.prune_depth);
+        }
+        // before the producer reads the clock, which skips ahead on a dev chain
+        if self.dev {
+            blockchain.set_dev_mode();
+        }
+        let mut keys = vec![self.priv_key.clone()];
+        keys.extend(self.staking_keys.iter().cloned());
+        let producer = BlockProducer::new(&blockchain);
+        let slots = ClockWatch::start(
+            blockchain.get_start_time(),
+            blockchain.params().slot_length_ms as u128,
+            blockchain.clock(),
+        );
+        let blockchain = BlockchainActorHandle::with_keys(blockchain, keys, self.tx.clone()).await;
+        self.producer = Some(producer.spawn(blockchain.clone(), self.tx.clone()));
+        self.mempool_cleanup = Some(prune_mempool_every_slot(slots, blockchain.clone()));
+        self.blockchain = Some(blockchain);
+    }
+
+    // asks the seed node for the blocks above our best path, what we asked before may never have reached it
+    async fn request_sync(&mut self) {
+        if self.blockchain.is_none() {
+            return;
+        }
+        self.sync.get_or_insert_with(SyncSession::start);
+        let seed = self.network.connection_state().await.ok().and_then(|state| state.seed);
+        let mut peers: Vec<_> = seed.into_iter().collect();
+        peers.extend(self.network.peers().await.unwrap_or_default());
+        self.requests.received(&Wanted::Sync);
+        // without peers the network asks the seed node or whoever it reaches
+        let peer = self.requests.request(Wanted::Sync, &peers, crate::get_unix_timestamp());
+        self.send_sync_request(peer).await;
+    }
+
+    // asks the node for the blocks above our best path, from the last block we applied if a sync stalled
+    async fn send_sync_request(&self, peer: Option<SocketAddr>) {
+        let (Some(blockchain), Some(session)) = (&self.blockchain, &self.sync) else {
+            return;
+        };
+        let mut locator = blockchain.get_locator().await;
+        if let Some(hash) = session.last_applied {
+            locator.insert(0, hash);
+        }
+        self.network.request_sync(peer, locator).await.unwrap();
+    }
+
+    // asks other peers for what was not sent in time
+    async fn retry_requests(&mut self) {
+        let peers = self.network.peers().await.unwrap_or_default();
+        for (wanted, peer) in self.requests.expired(crate::get_unix_timestamp(), &peers) {
+            match wanted {
+                Wanted::Block(hash) => self.network.request_block(peer, hash).await.unwrap(),
+                Wanted::Sync if self.sync.is_some() => {
+                    if let Some(ref session) = self.sync {
+                        session
+                            .span
+                            .in_scope(|| tracing::info!(target: logging::SYNC, %peer, "stalled, asking another peer"));
+                    }
+                    self.send_sync_request(Some(peer)).await;
+                }
+                Wanted::Sync => self.requests.received(&Wanted::Sync),
+            }
+        }
+    }
+
+    async fn apply_sync_batch(&mut self, sender: SocketAddr, progress: SyncProgress, blocks: Vec<Block>) {
+        let Some(blockchain_handle) = self.blockchain.clone() else {
+            return;
+        };
+        // the events of the batch are in the span of the session
+        let Some(span) = self.sync.as_ref().map(|session| session.span.clone()) else {
+            return;
+        };
+        let mut last_applied = None;
+        for block in blocks {
+            let hash = block.hash;
+            match blockchain_handle.add_block(block).await {
+                Err(Error::BlockchainError(reason)) if peer_scores::is_invalid_block(&reason) => {
+                    let hash = hex::encode(hash);
+                    span.in_scope(|| {
+                        tracing::warn!(target: logging::SYNC, peer = %sender, %hash, ?reason, "invalid block")
+                    });
+                    self.sync = None;
+                    self.requests.received(&Wanted::Sync);
+                    self.punish(sender, Misbehavior::InvalidBlock).await;
+                    return;
+                }
+                // blocks we already had are not applied again
+                _ => last_applied = Some(hash),
+            }
+        }
+        self.metrics.sync = Some(progress);
+        let Some(session) = self.sync.as_mut() else {
+            return;
+        };
+        session.last_applied = last_applied.or(session.last_applied);
+        if progress.is_done() {
+            let depth = progress.current_depth;
+            span.in_scope(|| tracing::info!(target: logging::SYNC, peer = %sender, depth, "synced"));
+            self.sync = None;
+            self.requests.received(&Wanted::Sync);
+        } else {
+            // the peer is still streaming its batches
+            self.requests.renew(&Wanted::Sync, crate::get_unix_timestamp());
+        }
+    }
+
+    // a transaction from the application the node is embedded in, it is flooded like those from the CLI
+    async fn submit_transaction(&mut self, transaction: Transaction) -> crate::Result<()> {
+        let blockchain = self.blockchain.as_ref().ok_or(Error::NotReady)?;
+        blockchain.add_transaction(transaction.clone()).await?;
+        self.network.broadcast_transaction(transaction).await
+    }
+
+    // the counters with the gauges of the network and the chain read now
+    async fn read_metrics(&self) -> NodeMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.peers_connected = self.network.peer_count().await.unwrap_or(0);
+        metrics.gossip = self.network.gossip_stats().await.unwrap_or_default();
+        if let Some(ref blockchain) = self.blockchain {
+            metrics = blockchain.sample_metrics(metrics).await;
+        }
+        metrics
+    }
+
+    async fn status(&self) -> NodeStatus {
+        NodeStatus {
+            uptime: self.started.elapsed(),
+            metrics: self.read_metrics().await,
+            syncing: self.sync.is_some(),
+            staking: self.producer.is_some(),
+            staking_address: AccountId::from(&self.priv_key.to_public_key()),
+        }
+    }
+
+    #[cfg(feature = "rpc")]
+    async fn handle_rpc_call(&mut self, call: crate::rpc::Call) {
+        // the status and the address book are also answered before we have a chain
+        let rejected = |e: Error| crate::rpc::RpcError::new(crate::rpc::REJECTED, e.to_string());
+        match call.method {
+            crate::rpc::Method::GetStatus => {
+                let _ = call.reply.send(Ok(crate::rpc::status_to_json(&self.status().await)));
+                return;
+            }
+            crate::rpc::Method::ExportPeers => {
+                let list = self.network.address_book().await.map(|book| book.export(crate::get_unix_timestamp()));
+                let _ = call.reply.send(list.map(serde_json::Value::String).map_err(rejected));
+                return;
+            }
+            crate::rpc::Method::ImportPeers(addrs) => {
+                let added = self.network.import_peers(addrs).await;
+                let _ = call.reply.send(added.map(|added| serde_json::json!(added)).map_err(rejected));
+                return;
+            }
+            crate::rpc::Method::PolicyGet => {
+                let _ = call.reply.send(Ok(serde_json::json!(self.relay_policy)));
+                return;
+            }
+            // the chains we start from now on take it too, and the chain we have takes it from the blockchain actor
+            crate::rpc::Method::PolicySet(ref policy) => {
+                self.relay_policy = policy.clone();
+                if let Err(e) = self.network.set_relay_policy(policy.clone()).await {
+                    let _ = call.reply.send(Err(rejected(e)));
+                    return;
+                }
+                if self.blockchain.is_none() {
+                    let _ = call.reply.send(Ok(serde_json::json!(self.relay_policy)));
+                    return;
+                }
+            }
+            // a node that is not in dev mode does not have the method
+            crate::rpc::Method::DevMine(_) if !self.dev => {
+                let missing = crate::rpc::RpcError::new(crate::rpc::METHOD_NOT_FOUND, "method not found");
+                let _ = call.reply.send(Err(missing));
+                return;
+            }
+            _ => (),
+        }
+        let Some(ref blockchain) = self.blockchain else {
+            let _ = call.reply.send(Err(crate::rpc::RpcError::not_ready()));
+            return;
+        };
+        let submitted = match call.method {
+            crate::rpc::Method::SendRawTransaction(ref t) => Some(t.as_ref().clone()),
+            _ => None,
+        };
+        if let crate::rpc::Method::DevMine(blocks) = call.method {
+            let mined = blockchain.dev_mine(blocks).await;
+            // the mined blocks reach the peers like the ones we win
+            for block in mined.iter().flatten() {
+                self.network.broadcast_block(Block::clone(block)).await.unwrap();
+            }
+            let mined = mined.map(|mined| mined.iter().map(|block| hex::encode(block.hash)).collect());
+            let _ = call.reply.send(mined.map_err(rejected));
+            return;
+        }
+        let result = blockchain.rpc(call.method).await;
+        // accepted transactions are passed on to the network like the ones from the CLI
+        if let (Ok(_), Some(transaction)) = (&result, submitted) {
+            self.network.broadcast_transaction(transaction).await.unwrap();
+        }
+        let _ = call.reply.send(result);
+    }
+
     async fn handle_cli_message(&mut self, cli_msg: CLIMessage) {
         match cli_msg {
             CLIMessage::PostTransaction(transaction) => {
                 if let Some(ref blockchain) = self.blockchain {
-                    let transaction = transaction.to_transaction(&self.priv_key);
+                    let nonce = blockchain.get_next_nonce(AccountId::from(&self.priv_key.to_public_key())).await;
+                    let transaction = match transaction.to_transaction(&self.priv_key, nonce) {
+                        Ok(transaction) => transaction,
+                        Err(e) => return println!("Unable to send, {e:?}"),
+                    };
                     self.network.broadcast_transaction(transaction.clone()).await.unwrap();
-                    blockchain.add_transaction(transaction).await;
+                    // the blockchain actor tells why a transaction is refused
+                    let _ = blockchain.add_transaction(transaction).await;
                 }
             },
             CLIMessage::CheckBalance(wallet) => {
@@ -155,6 +988,752 @@ impl ClientActor {
                     println!("Blockchain not initialized yet");
                 }
             }
+            CLIMessage::Dump(dir) => {
+                let Some(ref blockchain) = self.blockchain else {
+                    println!("Blockchain not initialized yet");
+                    return;
+                };
+                let chain = blockchain.get_blockchain_copy().await;
+                let written = [(DUMP_FILE, DumpFormat::Json), (FORKS_FILE, DumpFormat::Dot)]
+                    .into_iter()
+                    .try_for_each(|(file, format)| std::fs::write(dir.join(file), chain.dump(format)));
+                match written {
+                    Ok(()) => println!("Wrote {DUMP_FILE} and {FORKS_FILE} to {}", dir.display()),
+                    Err(e) => println!("Unable to write the dump: {e}"),
+                }
+            }
+            CLIMessage::ExportPeers(file) => {
+                let Ok(book) = self.network.address_book().await else {
+                    return println!("The network has stopped");
+                };
+                let list = book.export(crate::get_unix_timestamp());
+                match std::fs::write(&file, &list) {
+                    Ok(()) => println!("Wrote {} peers to {}", list.lines().count(), file.display()),
+                    Err(e) => println!("Unable to write the peers: {e}"),
+                }
+            }
+            CLIMessage::ImportPeers(file) => {
+                let addrs = match std::fs::read_to_string(&file) {
+                    Ok(list) => crate::address_book::parse_list(&list),
+                    Err(_) => Err(Error::StorageError),
+                };
+                match addrs {
+                    Ok(addrs) => match self.network.import_peers(addrs).await {
+                        Ok(added) => println!("Added {added} peers from {}", file.display()),
+                        Err(e) => println!("Unable to import the peers: {e}"),
+                    },
+                    Err(e) => println!("Unable to read the peers in {}: {e}", file.display()),
+                }
+            }
+        }
+    }
+}
+
+fn chain_id(blockchain: &Blockchain) -> ChainId {
+    ChainId {
+        genesis: blockchain.genesis_hash(),
+        params: blockchain.params().hash(),
+    }
+}
+
+// asks the client to write its address book until it stops
+fn save_address_book_every(millis: u64, tx: mpsc::Sender<ClientMessage>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            if tx.send(ClientMessage::SaveAddressBook).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// goes through the whole mempool once every timeslot, in batches so blocks and transactions are not held up
+fn prune_mempool_every_slot(mut slots: ClockWatch, blockchain: BlockchainActorHandle) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            slots.wait_for_update().await;
+            while !blockchain.prune_mempool(MEMPOOL_PRUNE_BATCH).await.finished {
+                tokio::task::yield_now().await;
+            }
+        }
+    })
+}
+
+// the requests that were not answered in time are asked of other peers
+fn retry_requests_every(millis: u64, tx: mpsc::Sender<ClientMessage>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            if tx.send(ClientMessage::RetryRequests).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// what we tell the nodes we make contact with before we start on the chain
+fn handshake_of(blockchain: Option<&Blockchain>) -> Handshake {
+    match blockchain {
+        Some(blockchain) => Handshake::new(Some(chain_id(blockchain)), blockchain.tip().depth),
+        None => Handshake::new(None, 0),
+    }
+}
+
+// the chain in the store with the blocks appended to it, or the chain we flushed to the data directory when we
+// last shut down, if there is one and it is valid
+fn stored_chain(config: &NodeConfig) -> Option<Blockchain> {
+    let Some(ref store) = config.store else {
+        return config.data_dir.as_deref().and_then(read_chain);
+    };
+    match store.lock().unwrap().load() {
+        Ok(Some(blockchain)) => verified(blockchain, "the store"),
+        Ok(None) => config.data_dir.as_deref().and_then(read_chain),
+        Err(error) => {
+            tracing::error!(target: logging::STORE, %error, "unable to read the store");
+            None
+        }
+    }
+}
+
+fn read_chain(dir: &Path) -> Option<Blockchain> {
+    let path = dir.join(CHAIN_FILE);
+    if !path.exists() {
+        return None;
+    }
+    match Blockchain::load(&path) {
+        Ok(blockchain) => verified(blockchain, &format!("{path:?}")),
+        Err(error) => {
+            let path = path.display();
+            tracing::error!(target: logging::STORE, %path, %error, "unable to read the stored blockchain");
+            None
+        }
+    }
+}
+
+fn verified(blockchain: Blockchain, source: &str) -> Option<Blockchain> {
+    if let Err(error) = blockchain.verify_chain() {
+        tracing::error!(target: logging::STORE, source, %error, "the stored blockchain is invalid");
+        return None;
+    }
+    tracing::info!(target: logging::STORE, source, depth = blockchain.tip().depth, "blockchain read");
+    Some(blockchain)
+}
+
+// the nodes we were connected to in the last run, dialed before the seeds
+fn address_book_of(config: &NodeConfig) -> AddressBook {
+    let Some(ref dir) = config.data_dir else {
+        return AddressBook::default();
+    };
+    AddressBook::load(&dir.join(ADDRESS_BOOK_FILE)).unwrap_or_else(|error| {
+        tracing::warn!(target: logging::STORE, %error, "unable to read the address book");
+        AddressBook::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::oneshot,
+    };
+
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        params::ChainParams,
+        pippi::{Message, MessageContent},
+        store::{BlockLog, ChainStore},
+        sync::SyncProgress,
+    };
+
+    async fn ask<T>(
+        client: &ClientHandle,
+        message: impl FnOnce(oneshot::Sender<T>) -> ClientMessage,
+    ) -> T {
+        let (tx, rx) = oneshot::channel();
+        client.sender().send(message(tx)).await.unwrap();
+        rx.await.unwrap()
+    }
+
+    // makes contact like a node that claims the address from and then sends bytes that are not messages, returns the
+    // address we connected from and whether we get disconnected
+    async fn send_garbage(to: SocketAddr, from: SocketAddr, messages: usize) -> (SocketAddr, bool) {
+        let mut stream = loop {
+            match TcpStream::connect(to).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await, // the node may not listen yet
+            }
+        };
+        Message::new_direct_message(&from, MessageContent::Contact(Handshake::new(None, 0)))
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        let connection = stream.local_addr().unwrap();
+        let garbage = [0xff; 16];
+        for _ in 0..messages {
+            stream
+                .write_all(&(garbage.len() as u64).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&garbage).await.unwrap();
+        }
+        // the node replies to our contact, then closes the connection
+        let closed = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut buf = [0; 1024];
+            while let Ok(1..) = stream.read(&mut buf).await {}
+        });
+        (connection, closed.await.is_ok())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hostile_peers_are_banned_while_honest_peers_stay_synced() {
+        // the nodes win many of the millisecond timeslots in tests, more blocks than they would send in real timeslots
+        let limits = PeerLimits {
+            messages_per_sec: 10_000,
+            message_burst: 10_000,
+            ..Default::default()
+        };
+        // both nodes produce blocks, each sends its own blocks in order so neither has to ask for parents
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let (honest_sk, honest_pk) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9541".parse().unwrap();
+        let config = NodeConfig {
+            limits,
+            ..Default::default()
+        };
+        let root =
+            ClientActor::start_root(root_addr, vec![root_pk, honest_pk], root_sk, config.clone())
+                .await;
+        let honest_addr: SocketAddr = "127.0.0.1:9542".parse().unwrap();
+        let honest = ClientActor::start(root_addr, honest_addr, honest_sk, config).await;
+
+        // the hostile node claims the address of the honest one, the ban lands on its own connection
+        let (hostile, closed) = send_garbage(root_addr, honest_addr, 4).await;
+        assert!(closed);
+        let banned = ask(&root, ClientMessage::BannedPeers).await;
+        assert_eq!(
+            banned.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+            vec![hostile]
+        );
+
+        // the root keeps following the chain of the honest node
+        let mut height = 0;
+        while height == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            height = ask(&honest, ClientMessage::Metrics).await.chain_height;
+        }
+        let synced = tokio::time::timeout(Duration::from_secs(30), async {
+            while ask(&root, ClientMessage::Metrics).await.chain_height < height {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(synced.await.is_ok());
+        assert_eq!(ask(&root, ClientMessage::BannedPeers).await.len(), 1);
+    }
+
+    // makes contact like a node and then announces a message of 100 MB, returns the address we connected from and
+    // whether we get disconnected
+    async fn send_oversized(to: SocketAddr, from: SocketAddr) -> (SocketAddr, bool) {
+        let mut stream = TcpStream::connect(to).await.unwrap();
+        let connection = stream.local_addr().unwrap();
+        Message::new_direct_message(&from, MessageContent::Contact(Handshake::new(None, 0)))
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        stream
+            .write_all(&100_000_000u64.to_be_bytes())
+            .await
+            .unwrap();
+        // the node does not wait for the rest of the body
+        let _ = stream.write_all(&[0; 1024]).await;
+        let closed = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut buf = [0; 1024];
+            while let Ok(1..) = stream.read(&mut buf).await {}
+        });
+        (connection, closed.await.is_ok())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn oversized_frames_drop_the_connection() {
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9553".parse().unwrap();
+        // the frame counts as a malformed message, and one of them gets the node banned here
+        let limits = PeerLimits {
+            malformed_score: crate::BAN_SCORE,
+            ..Default::default()
+        };
+        let config = NodeConfig {
+            limits,
+            ..Default::default()
+        };
+        let root = ClientActor::start_root(root_addr, vec![root_pk], root_sk, config).await;
+
+        // the ban is on the connection the frame came on, not on the address the node claims
+        let claimed: SocketAddr = "127.0.0.1:9554".parse().unwrap();
+        let (hostile, closed) = send_oversized(root_addr, claimed).await;
+        assert!(closed);
+        let banned = ask(&root, ClientMessage::BannedPeers).await;
+        assert_eq!(
+            banned.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+            vec![hostile]
+        );
+        root.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nodes_of_different_root_accounts_refuse_to_peer() {
+        let data_dir = std::env::temp_dir().join(format!("lasagna-handshake-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let (other_sk, other_pk) = crate::generate_keypair();
+        // the other node continues a chain of its own root account
+        Blockchain::start(vec![other_pk]).save(&data_dir.join(CHAIN_FILE)).unwrap();
+        let config = NodeConfig {
+            data_dir: Some(data_dir.clone()),
+            ..Default::default()
+        };
+
+        let root_addr: SocketAddr = "127.0.0.1:9549".parse().unwrap();
+        let other_addr: SocketAddr = "127.0.0.1:9550".parse().unwrap();
+        let root = ClientActor::start_root(root_addr, vec![root_pk], root_sk, NodeConfig::default()).await;
+        let other = ClientActor::start(root_addr, other_addr, other_sk, config).await;
+
+        // both sides see the other handshake and tell why they refuse
+        let refused = tokio::time::timeout(Duration::from_secs(10), async {
+            for (node, peer) in [(&root, other_addr), (&other, root_addr)] {
+                while ask(node, ClientMessage::RefusedPeers).await != vec![(peer, HandshakeError::Genesis)] {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        });
+        assert!(refused.await.is_ok());
+        for node in [&root, &other] {
+            assert_eq!(ask(node, ClientMessage::Metrics).await.peers_connected, 0);
+        }
+        root.shutdown().await;
+        other.shutdown().await;
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn an_empty_node_catches_up_in_batches() {
+        let dirs = ["ahead", "behind"]
+            .map(|name| std::env::temp_dir().join(format!("lasagna-sync-{name}-{}", std::process::id())));
+        for dir in dirs.iter() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        // the chain of the node that is ahead was made in the past, with a block every ten timeslots
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let clock = ManualClock::new(crate::get_unix_timestamp() as u64 - 20_000);
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut chain = Blockchain::start_with_clock(vec![root_pk.clone()], params, Arc::new(clock.clone()));
+        chain.save(&dirs[1].join(CHAIN_FILE)).unwrap();
+        for _ in 0..1000 {
+            clock.advance_slots(9);
+            assert_eq!(chain.produce_new_block_on_best_path(&clock, &root_sk, 1), Ok(()));
+        }
+        chain.save(&dirs[0].join(CHAIN_FILE)).unwrap();
+
+        // the node that is ahead stops producing, so the target stays at its tip
+        let ahead_config = NodeConfig {
+            data_dir: Some(dirs[0].clone()),
+            production: ProductionPolicy {
+                produce_empty_blocks: false,
+                max_empty_gap: u64::MAX,
+            },
+            ..Default::default()
+        };
+        let behind_config = NodeConfig {
+            data_dir: Some(dirs[1].clone()),
+            ..Default::default()
+        };
+        let ahead_addr: SocketAddr = "127.0.0.1:9551".parse().unwrap();
+        let ahead = ClientActor::start_root(ahead_addr, vec![root_pk], root_sk, ahead_config).await;
+        let (sk, _) = crate::generate_keypair();
+        let behind = ClientActor::start(ahead_addr, "127.0.0.1:9552".parse().unwrap(), sk, behind_config).await;
+
+        let mut seen: Vec<SyncProgress> = Vec::new();
+        let synced = tokio::time::timeout(Duration::from_secs(60), async {
+            loop {
+                let metrics = ask(&behind, ClientMessage::Metrics).await;
+                if let Some(progress) = metrics.sync {
+                    if seen.last() != Some(&progress) {
+                        seen.push(progress);
+                    }
+                    if progress.is_done() && metrics.chain_height == 1000 {
+                        break;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        assert!(synced.await.is_ok(), "stopped at {:?}", seen.last());
+        assert!(seen.len() > 1, "{seen:?}");
+        assert!(seen.windows(2).all(|pair| pair[0].current_depth < pair[1].current_depth), "{seen:?}");
+        assert_eq!(
+            seen.last(),
+            Some(&SyncProgress {
+                current_depth: 1000,
+                target_depth: 1000
+            })
+        );
+        for node in [ahead, behind] {
+            node.shutdown().await;
+        }
+        for dir in dirs {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn flooded_blocks_are_sent_once_per_peer() {
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9546".parse().unwrap();
+        let root = ClientActor::start_root(root_addr, vec![root_pk], root_sk, NodeConfig::default()).await;
+        let mut nodes = vec![root];
+        for addr in ["127.0.0.1:9547", "127.0.0.1:9548"] {
+            let (sk, _) = crate::generate_keypair();
+            nodes.push(ClientActor::start(root_addr, addr.parse().unwrap(), sk, NodeConfig::default()).await);
+        }
+
+        // the peer walk connects the two nodes, then every block travels around a cycle
+        let connected = tokio::time::timeout(Duration::from_secs(30), async {
+            for node in nodes.iter() {
+                while ask(node, ClientMessage::Metrics).await.peers_connected < 2 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        });
+        assert!(connected.await.is_ok());
+        let height = ask(&nodes[0], ClientMessage::Metrics).await.chain_height + 20;
+        let synced = tokio::time::timeout(Duration::from_secs(30), async {
+            for node in nodes.iter() {
+                while ask(node, ClientMessage::Metrics).await.chain_height < height {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        });
+        assert!(synced.await.is_ok());
+
+        let mut sent = 0;
+        let mut duplicates = 0;
+        for node in nodes.iter() {
+            let gossip = ask(node, ClientMessage::Metrics).await.gossip;
+            sent += gossip.blocks_sent;
+            duplicates += gossip.duplicate_blocks;
+        }
+        // only the root produces, and each node sends a block at most once to each of its two peers
+        let produced = ask(&nodes[0], ClientMessage::Metrics).await.chain_height;
+        assert!(sent <= produced * 3 * 2, "{sent} sends of {produced} blocks");
+        assert!(duplicates > 0);
+        for node in nodes {
+            node.shutdown().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn announced_blocks_cross_each_connection_once() {
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9570".parse().unwrap();
+        let root = ClientActor::start_root(root_addr, vec![root_pk], root_sk, NodeConfig::default()).await;
+        let mut nodes = vec![root];
+        for addr in ["127.0.0.1:9571", "127.0.0.1:9572"] {
+            let (sk, _) = crate::generate_keypair();
+            nodes.push(ClientActor::start(root_addr, addr.parse().unwrap(), sk, NodeConfig::default()).await);
+        }
+
+        let connected = tokio::time::timeout(Duration::from_secs(30), async {
+            for node in nodes.iter() {
+                while ask(node, ClientMessage::Metrics).await.peers_connected < 2 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        });
+        assert!(connected.await.is_ok());
+        let height = ask(&nodes[0], ClientMessage::Metrics).await.chain_height + 20;
+        let synced = tokio::time::timeout(Duration::from_secs(30), async {
+            for node in nodes.iter() {
+                while ask(node, ClientMessage::Metrics).await.chain_height < height {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        });
+        assert!(synced.await.is_ok());
+
+        // only the root produces, the others are announced each block by both of their peers and fetch it once
+        let produced = ask(&nodes[0], ClientMessage::Metrics).await.chain_height;
+        let mut hits = 0;
+        for node in nodes.iter() {
+            let gossip = ask(node, ClientMessage::Metrics).await.gossip;
+            assert!(gossip.blocks_fetched <= produced, "{} fetches of {produced} blocks", gossip.blocks_fetched);
+            hits += gossip.announce_hits;
+        }
+        assert_eq!(ask(&nodes[0], ClientMessage::Metrics).await.gossip.blocks_fetched, 0);
+        assert!(hits > 0);
+        for node in nodes {
+            node.shutdown().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_node_says_goodbye_and_continues_its_chain_after_a_restart() {
+        let data_dir = std::env::temp_dir().join(format!("lasagna-shutdown-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let config = NodeConfig {
+            data_dir: Some(data_dir.clone()),
+            ..Default::default()
+        };
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let (node_sk, _) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9544".parse().unwrap();
+        let node_addr: SocketAddr = "127.0.0.1:9545".parse().unwrap();
+        let root = ClientActor::start_root(root_addr, vec![root_pk.clone()], root_sk.clone(), config.clone()).await;
+        let node = ClientActor::start(root_addr, node_addr, node_sk, NodeConfig::default()).await;
+
+        let joined = tokio::time::timeout(Duration::from_secs(10), async {
+            while ask(&node, ClientMessage::Metrics).await.chain_height == 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(joined.await.is_ok());
+        let height = ask(&root, ClientMessage::Metrics).await.chain_height;
+        root.shutdown().await;
+        root.await_terminated().await;
+
+        // the node hears it at once, a silent node is only dropped after several missed heartbeats
+        let left = tokio::time::timeout(Duration::from_secs(2), async {
+            while ask(&node, ClientMessage::Metrics).await.peers_connected > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(left.await.is_ok());
+        let book = AddressBook::load(&data_dir.join(ADDRESS_BOOK_FILE)).unwrap();
+        assert_eq!(book.dialable(crate::get_unix_timestamp()), vec![node_addr]);
+        let stored = Blockchain::load(&data_dir.join(CHAIN_FILE)).unwrap();
+        assert!(stored.is_valid());
+        assert!(stored.tip().depth >= height);
+
+        // the listener was stopped, so the root can start again on its address
+        let root = ClientActor::start_root(root_addr, vec![root_pk], root_sk, config).await;
+        assert!(ask(&root, ClientMessage::Metrics).await.chain_height >= stored.tip().depth);
+        root.shutdown().await;
+        root.await_terminated().await;
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_accepted_blocks_survive_a_crash_in_the_block_log() {
+        let path = std::env::temp_dir().join(format!("lasagna-store-{}.log", std::process::id()));
+        let copy = path.with_extension("crashed");
+        let _ = std::fs::remove_file(&path);
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9555".parse().unwrap();
+        let config = NodeConfig {
+            store: Some(BlockLog::new(&path).shared()),
+            ..Default::default()
+        };
+        let root = ClientActor::start_root(root_addr, vec![root_pk.clone()], root_sk.clone(), config).await;
+
+        // the log is read as a crash would leave it, from a copy so the node keeps appending to its own
+        let recovered = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                std::fs::copy(&path, &copy).unwrap();
+                if let Ok(Some(blockchain)) = BlockLog::new(&copy).load() {
+                    if blockchain.tip().depth >= 3 {
+                        break blockchain;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        let recovered = recovered.await.unwrap();
+        assert!(recovered.is_valid());
+        root.shutdown().await;
+        root.await_terminated().await;
+
+        // without a data directory nothing is flushed, the chain is replayed from the log
+        let config = NodeConfig {
+            store: Some(BlockLog::new(&path).shared()),
+            ..Default::default()
+        };
+        let restarted_addr: SocketAddr = "127.0.0.1:9556".parse().unwrap();
+        let root = ClientActor::start_root(restarted_addr, vec![root_pk], root_sk, config).await;
+        assert!(ask(&root, ClientMessage::BestTip).await.unwrap().depth >= recovered.tip().depth);
+        root.shutdown().await;
+        root.await_terminated().await;
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&copy);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missed_chain_events_compact_the_block_log() {
+        let path =
+            std::env::temp_dir().join(format!("lasagna-store-lagged-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let config = NodeConfig {
+            store: Some(BlockLog::new(&path).shared()),
+            ..Default::default()
+        };
+        let root = ClientActor::start_root(
+            "127.0.0.1:9560".parse().unwrap(),
+            vec![root_pk],
+            root_sk,
+            config,
+        )
+        .await;
+        let produced = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let tip = ask(&root, ClientMessage::BestTip).await.unwrap();
+                if tip.depth >= 3 {
+                    break tip;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        let tip = produced.await.unwrap();
+
+        // as if every block so far had been among the missed events
+        std::fs::remove_file(&path).unwrap();
+        root.sender()
+            .send(ClientMessage::ChainEventsLagged(1))
+            .await
+            .unwrap();
+        let compacted = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(Some(blockchain)) = BlockLog::new(&path).load() {
+                    break blockchain;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(compacted.await.unwrap().tip().depth >= tip.depth);
+        root.shutdown().await;
+        root.await_terminated().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_node_joins_through_the_next_seed_when_the_first_is_down() {
+        let limits = PeerLimits {
+            messages_per_sec: 10_000,
+            message_burst: 10_000,
+            ..Default::default()
+        };
+        let config = NodeConfig {
+            limits,
+            ..Default::default()
+        };
+        // the second seed has stake, so the chain goes on without the root
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let (seed_sk, seed_pk) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9557".parse().unwrap();
+        let seed_addr: SocketAddr = "127.0.0.1:9558".parse().unwrap();
+        let root = ClientActor::start_root(root_addr, vec![root_pk, seed_pk], root_sk, config.clone()).await;
+        let seed = ClientActor::start(root_addr, seed_addr, seed_sk, config.clone()).await;
+        while ask(&seed, ClientMessage::Metrics).await.chain_height == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        root.shutdown().await;
+        root.await_terminated().await;
+
+        let (node_sk, _) = crate::generate_keypair();
+        let node =
+            ClientActor::start_with_seeds(vec![root_addr, seed_addr], "127.0.0.1:9559".parse().unwrap(), node_sk, config)
+                .await;
+        let state = ask(&node, ClientMessage::Connection).await;
+        assert_eq!(state.seed, Some(seed_addr));
+        assert_eq!(state.failed_dials, 0);
+
+        let height = ask(&seed, ClientMessage::Metrics).await.chain_height;
+        let synced = tokio::time::timeout(Duration::from_secs(30), async {
+            while ask(&node, ClientMessage::Metrics).await.chain_height < height {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(synced.await.is_ok());
+        for client in [node, seed] {
+            client.shutdown().await;
+            client.await_terminated().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_node_rejoins_through_its_address_book_when_its_seed_is_gone() {
+        let data_dir = std::env::temp_dir().join(format!("lasagna-address-book-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let config = NodeConfig {
+            limits: PeerLimits {
+                messages_per_sec: 10_000,
+                message_burst: 10_000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let node_config = NodeConfig {
+            data_dir: Some(data_dir.clone()),
+            ..config.clone()
+        };
+        let (root_sk, root_pk) = crate::generate_keypair();
+        let (node_sk, _) = crate::generate_keypair();
+        let root_addr: SocketAddr = "127.0.0.1:9562".parse().unwrap();
+        let node_addr: SocketAddr = "127.0.0.1:9563".parse().unwrap();
+        let peer_addrs: Vec<SocketAddr> = vec!["127.0.0.1:9564".parse().unwrap(), "127.0.0.1:9565".parse().unwrap()];
+        let root = ClientActor::start_root(root_addr, vec![root_pk], root_sk, config.clone()).await;
+        let node = ClientActor::start(root_addr, node_addr, node_sk.clone(), node_config.clone()).await;
+        while ask(&node, ClientMessage::BestTip).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // the peers join through the node, so it learns of them
+        let mut peers = Vec::new();
+        for addr in peer_addrs.iter() {
+            let (sk, _) = crate::generate_keypair();
+            peers.push(ClientActor::start(node_addr, *addr, sk, config.clone()).await);
+        }
+        let learned = tokio::time::timeout(Duration::from_secs(10), async {
+            while ask(&node, ClientMessage::Connection).await.peers < 3 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(learned.await.is_ok());
+        for client in [node, root] {
+            client.shutdown().await;
+            client.await_terminated().await;
+        }
+        let book = AddressBook::load(&data_dir.join(ADDRESS_BOOK_FILE)).unwrap();
+        let known = book.dialable(crate::get_unix_timestamp());
+        assert!(peer_addrs.iter().all(|addr| known.contains(addr)));
+
+        // the only seed is gone, the node dials the peers it knew instead
+        let node = ClientActor::start(root_addr, node_addr, node_sk, node_config).await;
+        let state = ask(&node, ClientMessage::Connection).await;
+        assert!(state.seed.is_some_and(|seed| peer_addrs.contains(&seed)));
+        assert_eq!(state.failed_dials, 0);
+        let rejoined = tokio::time::timeout(Duration::from_secs(10), async {
+            while ask(&node, ClientMessage::Connection).await.peers < 2 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        assert!(rejoined.await.is_ok());
+
+        node.shutdown().await;
+        node.await_terminated().await;
+        let book = AddressBook::load(&data_dir.join(ADDRESS_BOOK_FILE)).unwrap();
+        assert!(peer_addrs.iter().all(|addr| book.get(addr).unwrap().successes == 1));
+        for client in peers {
+            client.shutdown().await;
+            client.await_terminated().await;
         }
+        let _ = std::fs::remove_dir_all(&data_dir);
     }
 }
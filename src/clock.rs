@@ -0,0 +1,82 @@
+// the time used by the blockchain, so tests can decide which timeslot it is instead of waiting for it
+
+use std::fmt::Debug;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::SLOT_LENGTH;
+
+pub trait Clock: Debug + Send + Sync {
+    /// Milliseconds since the unix epoch
+    fn now_millis(&self) -> u128;
+}
+
+/// The time of the machine we are running on
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u128 {
+        crate::get_unix_timestamp()
+    }
+}
+
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when it is told to, clones share the same time
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    pub fn new(now_millis: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(now_millis)))
+    }
+
+    pub fn set(&self, now_millis: u64) {
+        self.0.store(now_millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.0.fetch_add(millis, Ordering::SeqCst);
+    }
+
+    pub fn advance_slots(&self, slots: u64) {
+        self.advance(slots * SLOT_LENGTH as u64);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u128 {
+        self.0.load(Ordering::SeqCst) as u128
+    }
+}
+
+/// Another clock moved ahead by what a dev chain skipped to produce its blocks at once, clones share the skip
+#[derive(Debug, Clone)]
+pub struct SkippingClock {
+    base: Arc<dyn Clock>,
+    skipped: Arc<AtomicU64>, // milliseconds
+}
+
+impl SkippingClock {
+    pub fn new(base: Arc<dyn Clock>) -> Self {
+        Self {
+            base,
+            skipped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn skip(&self, millis: u64) {
+        self.skipped.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SkippingClock {
+    fn now_millis(&self) -> u128 {
+        self.base.now_millis() + self.skipped.load(Ordering::SeqCst) as u128
+    }
+}
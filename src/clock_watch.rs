@@ -1,40 +1,45 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::sync::watch;
 
-use crate::{calculate_timeslot, get_unix_timestamp, SLOT_LENGTH};
+use crate::{calculate_timeslot, clock::Clock, Timeslot};
 
 /// A tokio sync watch that sends a timeslot when a new timeslot is reached
 #[derive(Clone)]
 pub struct ClockWatch {
-    receiver: watch::Receiver<u64>,
+    receiver: watch::Receiver<Timeslot>,
 }
 
 impl ClockWatch {
-    pub fn start(starttime: u128) -> Self {
-        let (sender, receiver) = watch::channel(calculate_timeslot(starttime));
-       
+    /// The timeslots are read from the clock, a clock that is moved by hand is noticed within a timeslot
+    pub fn start(starttime: u128, slot_length: u128, clock: Arc<dyn Clock>) -> Self {
+        let mut last_timeslot = calculate_timeslot(clock.now_millis(), starttime, slot_length);
+        let (sender, receiver) = watch::channel(last_timeslot);
+
         // start the clock
         tokio::spawn(async move {
             loop {
-                let last_timeslot = calculate_timeslot(starttime);
-                let next_timeslot_start = starttime + (last_timeslot as u128 + 1) * SLOT_LENGTH;
-                let time_to_sleep = next_timeslot_start - get_unix_timestamp();
+                let next_timeslot_start = starttime + (last_timeslot.as_u64() as u128 + 1) * slot_length;
+                let time_to_sleep = next_timeslot_start
+                    .saturating_sub(clock.now_millis())
+                    .min(slot_length);
                 tokio::time::sleep(Duration::from_millis(time_to_sleep as u64)).await;
-                let current_timeslot = calculate_timeslot(starttime);
+                let current_timeslot = calculate_timeslot(clock.now_millis(), starttime, slot_length);
                 if current_timeslot != last_timeslot {
-                    sender.send(current_timeslot).unwrap();
+                    last_timeslot = current_timeslot;
+                    if sender.send(current_timeslot).is_err() {
+                        break; // nobody is watching anymore
+                    }
                 }
             }
         });
 
-        Self {
-            receiver
-        }
+        Self { receiver }
     }
 
-    pub async fn wait_for_update(&mut self) -> u64 {
+    pub async fn wait_for_update(&mut self) -> Timeslot {
         self.receiver.changed().await.unwrap();
         *(self.receiver.borrow())
     }
-} 
\ No newline at end of file
+}
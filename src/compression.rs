@@ -0,0 +1,43 @@
+// blocks carry a 2048-bit RSA key for every transaction, so they are large but repetitive. They are deflated on the
+// wire and on disk, always the bytes of a whole message or record after it is signed and hashed, so the bytes that
+// consensus sees never change
+
+const LEVEL: u8 = 6; // the default of zlib, most of the gain of the highest level for a fraction of the time
+
+/// Deflates the bytes
+pub fn deflate(bytes: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(bytes, LEVEL)
+}
+
+/// Inflates the bytes, none if they were not deflated or would inflate to more than max_bytes
+pub fn inflate(bytes: &[u8], max_bytes: usize) -> Option<Vec<u8>> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(bytes, max_bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::{Block, BlockHash},
+        seeding_mechanism::SeedContent, transaction::Transaction,
+        Timeslot,
+    };
+
+    #[test]
+    fn blocks_shrink_and_inflate_to_the_same_bytes() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let transactions = (0..20)
+            .map(|nonce| Transaction::new(pk.clone(), receiver.clone(), &sk, 10, nonce).unwrap())
+            .collect();
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        let block = Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk, transactions, [0; 32], &sk, seed);
+        let bytes = block.to_bytes();
+
+        let deflated = deflate(&bytes);
+        assert!(deflated.len() < bytes.len());
+        assert_eq!(inflate(&deflated, bytes.len()), Some(bytes.clone()));
+        // a bomb is stopped at the limit
+        assert_eq!(inflate(&deflated, bytes.len() - 1), None);
+    }
+}
@@ -0,0 +1,235 @@
+// the settings a node reads from a TOML file, so the binaries do not need a flag for every setting
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::AccountId,
+    blockchain::BufferLimits,
+    keys,
+    params::{ChainParams, FeeSink},
+    policy::RelayPolicy,
+    Error, Result,
+};
+
+/// What a node reads from its `--config` file. Every key may be left out and the flags of the binaries win over the file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub listen: Option<SocketAddr>,
+    pub seeds: Vec<SocketAddr>, // tried in order by a joining node
+    pub data_dir: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub staking_key_files: Vec<PathBuf>, // the other accounts the node stakes with, with the passphrase of the key file
+    pub rpc: Option<SocketAddr>,
+    pub ws: Option<SocketAddr>, // served with the rpc feature like the JSON-RPC endpoint
+    pub metrics: Option<SocketAddr>, // the metrics are only served if this is set
+    pub upload_bytes_per_sec: Option<u64>, // what the node sends each peer at most, as fast as it reads without this
+    pub max_reorg_depth: Option<u64>, // a deeper reorg waits for the force_reorg RPC
+    pub prune_depth: Option<u64>, // final blocks this far below the tip are kept without their transactions
+    pub buffers: BufferLimits,
+    pub policy: RelayPolicy, // whose transactions the node neither takes in nor passes on, it may be changed with the policy_set RPC
+    pub params: ParamsOverride, // for the chain of a root node, a joining node takes those of the network
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("unable to read {path:?}, {e}")))?;
+        Self::parse(&text)
+    }
+
+    /// The error names the line and the key that are wrong
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        config.params.validate()?;
+        Ok(config)
+    }
+}
+
+/// The params that a test network changes, the rest are the defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParamsOverride {
+    pub root_amount: Option<u64>,
+    pub block_reward: Option<u64>,
+    pub transaction_fee: Option<u64>,
+    pub slot_length_ms: Option<u64>,
+    #[serde(with = "hex_hardness")]
+    pub hardness: Option<BigUint>, // written in hex
+    pub max_block_transactions: Option<u64>,
+    pub max_block_bytes: Option<u64>,
+    pub stake_lookback: Option<u64>,
+    pub fee_sink: Option<FeeSink>, // "burn" or "to_proposer"
+    pub min_key_bits: Option<u64>, // at least 1024, only a test network should go below 2048
+}
+
+impl ParamsOverride {
+    pub fn apply(&self, params: ChainParams) -> ChainParams {
+        ChainParams {
+            root_amount: self.root_amount.unwrap_or(params.root_amount),
+            block_reward: self.block_reward.unwrap_or(params.block_reward),
+            transaction_fee: self.transaction_fee.unwrap_or(params.transaction_fee),
+            slot_length_ms: self.slot_length_ms.unwrap_or(params.slot_length_ms),
+            hardness: self.hardness.clone().unwrap_or(params.hardness),
+            max_block_transactions: self.max_block_transactions.unwrap_or(params.max_block_transactions),
+            max_block_bytes: self.max_block_bytes.unwrap_or(params.max_block_bytes),
+            stake_lookback: self.stake_lookback.unwrap_or(params.stake_lookback),
+            fee_sink: self.fee_sink.unwrap_or(params.fee_sink),
+            min_key_bits: self.min_key_bits.unwrap_or(params.min_key_bits),
+        }
+    }
+
+    /// The params that no chain can run with
+    pub fn validate(&self) -> Result<()> {
+        match self.min_key_bits {
+            Some(bits) if bits < keys::MIN_KEY_BITS as u64 => Err(Error::InvalidConfig(format!(
+                "min_key_bits must be at least {}, not {bits}",
+                keys::MIN_KEY_BITS
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+mod hex_hardness {
+    use num_bigint::BigUint;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hardness: &Option<BigUint>, serializer: S) -> Result<S::Ok, S::Error> {
+        match hardness {
+            Some(hardness) => serializer.serialize_str(&hardness.to_str_radix(16)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BigUint>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| BigUint::parse_bytes(hex.as_bytes(), 16).ok_or_else(|| D::Error::custom("expected a hex number")))
+            .transpose()
+    }
+}
+
+/// A config file with every key, the keys without a default are commented out
+pub fn sample() -> String {
+    let buffers = toml::to_string(&BufferLimits::default()).expect("the limits are plain numbers");
+    let params = ChainParams::default();
+    format!(
+        r#"# the address the node listens on, it is asked for if it is not here or given with --listen
+# listen = "127.0.0.1:8080"
+# the seed nodes a joining node tries in order
+# seeds = ["127.0.0.1:8081", "127.0.0.1:8082"]
+# data_dir = "./data"
+# key_file = "./node.key"
+# stake with these accounts too, at most one block a timeslot is produced with the best draw of all the keys
+# staking_key_files = ["./second.key", "./third.key"]
+# rpc = "127.0.0.1:8545"
+# ws = "127.0.0.1:8546"
+# metrics = "127.0.0.1:9100"
+# the most bytes per second sent to each peer, so a peer that syncs leaves room for the blocks sent to the rest
+# upload_bytes_per_sec = 100000
+# the most blocks of the best path a reorg rolls back, a deeper one is refused until it is forced
+# max_reorg_depth = {}
+# keep only the headers of the final blocks this many blocks below the tip, every block is kept whole without it
+# prune_depth = 1000
+
+[buffers]
+{buffers}
+# the node neither takes into its mempool nor passes on the transactions from or to these accounts,
+# the blocks that include them are accepted like any other
+[policy]
+# deny = ["{}"]
+# with an allow list the transactions from or to any account that is not in it are refused too
+# allow = ["{}"]
+# the params of the chain a root node starts, change them for a test network
+[params]
+# root_amount = {}
+# block_reward = {}
+# transaction_fee = {}
+# slot_length_ms = {}
+# hardness = "{}"
+# max_block_transactions = {}
+# max_block_bytes = {}
+# stake_lookback = {}
+# the fees of a block are burned or given to its winner, "burn" or "to_proposer"
+# fee_sink = "{}"
+# the smallest RSA key that may sign a block or a transaction, at least 1024
+# min_key_bits = {}
+"#,
+        crate::MAX_REORG_DEPTH,
+        AccountId([0; 32]),
+        AccountId([0xff; 32]),
+        params.root_amount,
+        params.block_reward,
+        params.transaction_fee,
+        params.slot_length_ms,
+        params.hardness.to_str_radix(16),
+        params.max_block_transactions,
+        params.max_block_bytes,
+        params.stake_lookback,
+        match params.fee_sink {
+            FeeSink::Burn => "burn",
+            FeeSink::ToProposer => "to_proposer",
+        },
+        params.min_key_bits,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sample_is_the_default_and_every_key_in_it_is_read() {
+        let sample = sample();
+        assert_eq!(ConfigFile::parse(&sample), Ok(ConfigFile::default()));
+
+        let uncommented: String = sample
+            .lines()
+            .map(|line| line.strip_prefix("# ").filter(|line| line.contains(" = ")).unwrap_or(line))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let config = ConfigFile::parse(&uncommented).unwrap();
+        assert_eq!(config.listen, Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!(config.seeds.len(), 2);
+        assert_eq!(config.key_file, Some(PathBuf::from("./node.key")));
+        assert_eq!(config.staking_key_files.len(), 2);
+        assert_eq!(config.upload_bytes_per_sec, Some(100_000));
+        assert_eq!(config.max_reorg_depth, Some(crate::MAX_REORG_DEPTH));
+        assert_eq!(config.prune_depth, Some(1000));
+        assert_eq!(config.policy.deny, [AccountId([0; 32])].into());
+        assert_eq!(config.policy.allow, Some([AccountId([0xff; 32])].into()));
+        assert_eq!(config.params.apply(ChainParams::default()), ChainParams::default());
+    }
+
+    #[test]
+    fn invalid_configs_name_the_line_and_the_key() {
+        let wrong_type = "listen = \"127.0.0.1:8080\"\n\n[buffers]\nmax_orphans = \"many\"\n";
+        let Err(Error::InvalidConfig(e)) = ConfigFile::parse(wrong_type) else {
+            panic!("the config was read");
+        };
+        assert!(e.contains("line 4"), "{e}");
+        assert!(e.contains("max_orphans"), "{e}");
+
+        let unknown = "listen = \"127.0.0.1:8080\"\nsed = []\n";
+        let Err(Error::InvalidConfig(e)) = ConfigFile::parse(unknown) else {
+            panic!("the config was read");
+        };
+        assert!(e.contains("line 2") && e.contains("unknown field `sed`"), "{e}");
+
+        let hardness = "[params]\nhardness = \"not hex\"\n";
+        assert!(ConfigFile::parse(hardness).is_err());
+        let config = ConfigFile::parse("[params]\nhardness = \"ff\"\nblock_reward = 5\n").unwrap();
+        let params = config.params.apply(ChainParams::default());
+        assert_eq!(params.hardness, BigUint::from(255u8));
+        assert_eq!(params.block_reward, 5);
+
+        let Err(Error::InvalidConfig(e)) = ConfigFile::parse("[params]\nmin_key_bits = 512\n") else {
+            panic!("the config was read");
+        };
+        assert!(e.contains("min_key_bits"), "{e}");
+    }
+}
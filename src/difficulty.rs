@@ -0,0 +1,176 @@
+use lazy_static::lazy_static;
+use num_bigint::BigUint;
+
+use crate::Timeslot;
+
+lazy_static! {
+    /// 2^256, one more than the largest draw
+    static ref MAX_HASH: BigUint = BigUint::from(1u64) << 256;
+    static ref INITIAL_HARDNESS: BigUint = BigUint::from(10421u64) * BigUint::from(10u64).pow(73);
+}
+
+/// The hardness is recomputed every EPOCH_LENGTH blocks
+pub const EPOCH_LENGTH: u64 = 50;
+/// The network aims for a block every TARGET_SLOTS_PER_BLOCK timeslots
+pub const TARGET_SLOTS_PER_BLOCK: u64 = 10;
+/// The most the easiness can be multiplied or divided by in a single retarget
+const MAX_ADJUSTMENT: u64 = 4;
+/// A draw that beats the hardness by everything there is adds this share of the weight of its block on top
+pub const QUALITY_SHARE: u64 = 1_000;
+/// The weights are fixed point numbers with this many fractions of a draw
+const WEIGHT_PRECISION: u64 = 1 << 32;
+
+pub fn max_hash() -> BigUint {
+    MAX_HASH.clone()
+}
+
+/// The entire network has a total 10% chance of beating this at a given timeslot
+pub fn initial_hardness() -> BigUint {
+    INITIAL_HARDNESS.clone()
+}
+
+/// Whether the draw wins for an account with `balance` of the `total_money`.
+/// The draw value in [0, 2^256) is mapped to [0, h + c(2^256 - h)), where h is the hardness and c = balance / total_money
+/// is the share of the money we have, by multiplying it with (h + c(2^256 - h)) / 2^256. It wins if the mapped value
+/// is above h. Multiplying both sides by 2^256 and total_money keeps the comparison exact in integers:
+/// draw * (h * total_money + balance * (2^256 - h)) > h * total_money * 2^256,
+/// where the multiplication by 2^256 is a shift, so a draw costs a single multiplication of two large numbers
+pub fn beats(draw_value: &BigUint, balance: u64, total_money: u64, hardness: &BigUint) -> bool {
+    let scaled_hardness = hardness * total_money;
+    let mult_factor = &scaled_hardness + (&*MAX_HASH - hardness) * balance;
+    draw_value * mult_factor > scaled_hardness << 256
+}
+
+/// Computes the hardness of the next epoch from one that used `slots` timeslots for `blocks` blocks.
+/// The chance of winning is roughly proportional to 2^256 - hardness, so we scale that by how far we are from the target
+pub fn retarget(hardness: &BigUint, slots: u64, blocks: u64) -> BigUint {
+    let max_hash = &*MAX_HASH;
+    let target = (blocks * TARGET_SLOTS_PER_BLOCK).max(1);
+    let slots = slots.clamp((target / MAX_ADJUSTMENT).max(1), target * MAX_ADJUSTMENT);
+
+    let easiness = (max_hash - hardness) * slots / target;
+    let easiness = easiness.clamp(BigUint::from(1u64), max_hash - 1u64);
+    max_hash - easiness
+}
+
+/// The hardness a block at the given depth must beat.
+/// `timeslot_at` gives the timeslot of the block at a depth on the path leading to it
+pub fn hardness_at(
+    initial: &BigUint,
+    timeslot_at: impl Fn(u64) -> Timeslot,
+    depth: u64,
+) -> BigUint {
+    let mut hardness = initial.clone();
+    for epoch in 1..=(depth / EPOCH_LENGTH) {
+        let start = timeslot_at((epoch - 1) * EPOCH_LENGTH);
+        let end = timeslot_at(epoch * EPOCH_LENGTH - 1);
+        hardness = retarget(&hardness, end.since(start), EPOCH_LENGTH - 1);
+    }
+    hardness
+}
+
+/// The weight a block adds to its branch, the fork choice goes to the heaviest branch.
+/// It is the number of draws it takes on average to beat the hardness, so a block of a harder epoch weighs more,
+/// plus up to a `QUALITY_SHARE`th of that for how far the draw beats the hardness. Forks of the same depth are
+/// decided by the draws of all their blocks, and a fork still needs more than `QUALITY_SHARE` blocks of better
+/// draws to outweigh a deeper one of the same hardness
+pub fn block_weight(draw_value: &BigUint, hardness: &BigUint) -> BigUint {
+    let max_hash = &*MAX_HASH;
+    let easiness = match hardness < max_hash {
+        true => max_hash - hardness,
+        false => BigUint::from(1u64),
+    };
+    let expected = max_hash * WEIGHT_PRECISION / &easiness;
+    let beaten_by = match draw_value > hardness {
+        true => (draw_value - hardness).min(easiness.clone()),
+        false => BigUint::from(0u64),
+    };
+    &expected + &expected * beaten_by / (easiness * QUALITY_SHARE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_epochs_get_harder() {
+        // a block in every timeslot is much faster than the target
+        let fast = hardness_at(&initial_hardness(), Timeslot, EPOCH_LENGTH);
+        assert!(fast > initial_hardness());
+
+        let faster = hardness_at(&initial_hardness(), Timeslot, 2 * EPOCH_LENGTH);
+        assert!(faster > fast);
+        assert!(faster < max_hash());
+    }
+
+    #[test]
+    fn slow_epochs_get_easier() {
+        let slow = hardness_at(&initial_hardness(), |depth| Timeslot(depth * 4 * TARGET_SLOTS_PER_BLOCK), EPOCH_LENGTH);
+        assert!(slow < initial_hardness());
+    }
+
+    #[test]
+    fn on_target_epochs_keep_hardness() {
+        let hardness = hardness_at(
+            &initial_hardness(),
+            |depth| Timeslot(depth * TARGET_SLOTS_PER_BLOCK),
+            3 * EPOCH_LENGTH,
+        );
+        assert_eq!(hardness, initial_hardness());
+    }
+
+    #[test]
+    fn better_draws_and_harder_epochs_weigh_more() {
+        let hardness = initial_hardness();
+        let just_won = block_weight(&(&hardness + 1u64), &hardness);
+        let better = block_weight(&(&hardness + (max_hash() - &hardness) / 2u64), &hardness);
+        let best = block_weight(&(max_hash() - 1u64), &hardness);
+        assert!(just_won < better && better < best);
+        // the draw adds at most a `QUALITY_SHARE`th
+        assert!(best * QUALITY_SHARE <= &just_won * (QUALITY_SHARE + 1));
+
+        let harder = hardness_at(&hardness, Timeslot, EPOCH_LENGTH);
+        assert!(block_weight(&(&harder + 1u64), &harder) > just_won);
+        // without hardness a block weighs one draw
+        assert_eq!(block_weight(&BigUint::from(0u64), &BigUint::from(0u64)), BigUint::from(WEIGHT_PRECISION));
+    }
+
+    #[test]
+    fn first_epoch_uses_initial_hardness() {
+        assert_eq!(hardness_at(&initial_hardness(), Timeslot, EPOCH_LENGTH - 1), initial_hardness());
+    }
+
+    // the lottery as it was written before it was rearranged
+    fn beats_as_written(draw_value: &BigUint, balance: u64, total_money: u64, hardness: &BigUint) -> bool {
+        let max_hash = BigUint::from(2u64).pow(256);
+        let balance = BigUint::from(balance);
+        let mult_factor = (hardness.clone() * total_money) + (balance * (max_hash.clone() - hardness.clone()));
+        draw_value * mult_factor > hardness * total_money * max_hash
+    }
+
+    #[test]
+    fn the_constants_are_what_they_were() {
+        assert_eq!(max_hash(), BigUint::from(2u64).pow(256));
+        assert_eq!(initial_hardness(), BigUint::from(10421u64) * BigUint::from(10u64).pow(73));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn the_rearranged_lottery_picks_the_same_winners(
+            draw in proptest::prelude::any::<[u8; 32]>(),
+            hardness in proptest::prelude::any::<[u8; 32]>(),
+            balance in proptest::prelude::any::<u64>(),
+            total_money in proptest::prelude::any::<u64>(),
+            near in proptest::prelude::any::<u32>(),
+        ) {
+            let hardness = BigUint::from_bytes_be(&hardness);
+            // random draws and draws right above the hardness, where the balance decides
+            for draw in [BigUint::from_bytes_be(&draw), (&hardness + near).min(max_hash() - 1u64)] {
+                proptest::prop_assert_eq!(
+                    beats(&draw, balance, total_money, &hardness),
+                    beats_as_written(&draw, balance, total_money, &hardness)
+                );
+            }
+        }
+    }
+}
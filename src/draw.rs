@@ -1,56 +1,123 @@
 use num_bigint::BigUint;
-use rsa::{sha2::{Digest, Sha256}, RsaPrivateKey, RsaPublicKey};
-use serde::{Deserialize, Serialize};
-use crate::{seeding_mechanism::SeedContent, Pkcs1v15Signature, Timeslot};
+use rsa::sha2::{Digest, Sha256};
+use crate::encoding::{serde_via_bytes, Reader, Writer, MAX_DRAW_VALUE_BYTES};
+use crate::keys::{PublicKey, SecretKey, UniqueSignature};
+use crate::{block::BlockHash, seeding_mechanism::SeedContent, Error, Result, Timeslot};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Draw {
     pub(super) value: BigUint,
     pub(super) timeslot: Timeslot,
-    pub(super) signature: Pkcs1v15Signature,
-    pub(super) signed_by: RsaPublicKey,
+    pub(super) prev_hash: BlockHash, // the parent of the block the draw is for
+    pub(super) signature: UniqueSignature,
+    pub(super) signed_by: PublicKey,
     pub(super) seed: SeedContent,
 }
 
 impl Draw {
     pub fn new(
         timeslot: Timeslot,
+        prev_hash: BlockHash,
         seed: SeedContent,
-        vk: RsaPublicKey,
-        sk: &RsaPrivateKey,
+        vk: PublicKey,
+        sk: &SecretKey,
     ) -> Self {
-        let data = format!("Lottery{timeslot}");
-        let mut hasher = Sha256::new();
-        hasher.update(data.as_bytes());
-        hasher.update(seed.seed);
-        let hash: [u8; 32] = hasher.finalize().into();
-        let signature = Pkcs1v15Signature::sign(sk, &hash).unwrap();
+        let hash = Self::lottery_hash(timeslot, &prev_hash, &seed);
+        let signature = UniqueSignature::sign(sk, &hash).unwrap();
 
         let mut hasher = Sha256::new();
         hasher.update(signature.to_bytes());
         let signature_hash: [u8; 32] = hasher.finalize().into();
         let value = BigUint::from_bytes_be(&signature_hash);
 
-        let _rsa_vk: RsaPublicKey = vk.clone();
-
         Self {
             value,
             timeslot,
+            prev_hash,
             signature,
             signed_by: vk,
             seed,
         }
     }
 
-    pub fn verify(&self) -> bool {
-        let vk = &self.signed_by;
-        let timeslot = self.timeslot;
+    /// The draw of the genesis block, it is not signed so it is the same whoever makes the block
+    pub(crate) fn unsigned(prev_hash: BlockHash, seed: SeedContent, vk: PublicKey) -> Self {
+        let signature = UniqueSignature(Vec::new());
+        let signature_hash: [u8; 32] = Sha256::digest(signature.to_bytes()).into();
+        Self {
+            value: BigUint::from_bytes_be(&signature_hash),
+            timeslot: Timeslot::GENESIS,
+            prev_hash,
+            signature,
+            signed_by: vk,
+            seed,
+        }
+    }
+
+    // what the draw signs, so a draw on one parent says nothing about another
+    fn lottery_hash(timeslot: Timeslot, prev_hash: &BlockHash, seed: &SeedContent) -> [u8; 32] {
         let data = format!("Lottery{timeslot}");
         let mut hasher = Sha256::new();
         hasher.update(data.as_bytes());
-        hasher.update(self.seed.seed);
-        let hash: [u8; 32] = hasher.finalize().into();
-        self.signature.verify(vk, &hash).is_ok()
+        hasher.update(prev_hash);
+        hasher.update(seed.seed);
+        hasher.finalize().into()
+    }
+
+    /// Whether the draw is for a block on this parent in this timeslot
+    pub fn is_for(&self, prev_hash: &BlockHash, timeslot: Timeslot) -> bool {
+        self.prev_hash == *prev_hash && self.timeslot == timeslot
+    }
+
+    /// Checks that the draw is signed for the seed of its epoch and for the parent and timeslot of its block
+    pub fn verify(&self, seed: &SeedContent, prev_hash: &BlockHash, timeslot: Timeslot) -> bool {
+        if self.seed != *seed || !self.is_for(prev_hash, timeslot) {
+            return false;
+        }
+        let hash = Self::lottery_hash(self.timeslot, &self.prev_hash, &self.seed);
+        self.signature.verify(&self.signed_by, &hash).is_ok()
+    }
+
+    pub(crate) fn encode(&self, writer: &mut Writer) {
+        writer
+            .bytes(&self.value.to_bytes_be())
+            .timeslot(self.timeslot)
+            .block_hash(&self.prev_hash)
+            .bytes(self.signature.to_bytes())
+            .key(&self.signed_by)
+            .block_hash(&self.seed.block_ptr.0)
+            .u64(self.seed.block_ptr.1)
+            .hash(&self.seed.seed);
+    }
+
+    pub(crate) fn decode(reader: &mut Reader) -> Result<Self> {
+        let value = reader.bytes_at_most(MAX_DRAW_VALUE_BYTES)?;
+        // the bytes are those of `to_bytes_be`, so a value has a single encoding
+        if value.is_empty() || (value.len() > 1 && value[0] == 0) {
+            return Err(Error::InvalidEncoding);
+        }
+        Ok(Self {
+            value: BigUint::from_bytes_be(value),
+            timeslot: reader.timeslot()?,
+            prev_hash: reader.block_hash()?,
+            signature: UniqueSignature(reader.signature()?),
+            signed_by: reader.key()?,
+            seed: SeedContent::new((reader.block_hash()?, reader.u64()?), reader.hash()?),
+        })
+    }
+
+    /// The canonical encoding, the same layout as in a block header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let draw = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(draw)
     }
 
     pub fn hash(&self) -> [u8; 32] {
@@ -58,4 +125,39 @@ impl Draw {
         hasher.update(self.signature.0.clone());
         hasher.finalize().into()
     }
+}
+
+serde_via_bytes!(Draw);
+
+/// The last draw of a producer. A draw only changes with the timeslot and the tip it builds on,
+/// so staking again in the same timeslot does not sign again
+#[derive(Debug, Default)]
+pub struct DrawCache {
+    last: Option<(Timeslot, BlockHash, Draw)>, // and the hash of the tip
+    signed: u64,
+}
+
+impl DrawCache {
+    pub(crate) fn get_or_sign(
+        &mut self,
+        timeslot: Timeslot,
+        tip: BlockHash,
+        vk: &PublicKey,
+        sign: impl FnOnce() -> Draw,
+    ) -> Draw {
+        if let Some((last_timeslot, last_tip, ref draw)) = self.last {
+            if last_timeslot == timeslot && last_tip == tip && draw.signed_by == *vk {
+                return draw.clone();
+            }
+        }
+        let draw = sign();
+        self.signed += 1;
+        self.last = Some((timeslot, tip, draw.clone()));
+        draw
+    }
+
+    /// The number of draws that have been signed
+    pub fn signed(&self) -> u64 {
+        self.signed
+    }
 }
\ No newline at end of file
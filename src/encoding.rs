@@ -0,0 +1,574 @@
+// the canonical byte layout of blocks and transactions, this is what is sent on the wire and what is hashed and signed
+// so it must not depend on which serde backend a node is built with
+
+use crate::{block::BlockHash, keys::PublicKey, Error, Result, Timeslot};
+
+/// The first byte of every encoding, bump this when the layout changes
+pub const FORMAT_VERSION: u8 = 7;
+
+const MAX_KEY_BYTES: usize = 1_024; // an RSA key of 4096 bits is 526 bytes, larger keys are refused before they are parsed
+const MAX_SIGNATURE_BYTES: usize = 1_024; // an RSA signature of 4096 bits is 512 bytes
+pub(crate) const MAX_DRAW_VALUE_BYTES: usize = 32; // a draw value is a SHA-256, so no BigUint math is done on larger numbers
+
+/// Integers are fixed width big endian, variable length fields are prefixed with their length as a u32
+pub(crate) struct Writer {
+    buf: Option<Vec<u8>>, // none when only the length is counted
+    written: usize,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        let mut writer = Self {
+            buf: Some(Vec::new()),
+            written: 0,
+        };
+        writer.put(&[FORMAT_VERSION]);
+        writer
+    }
+
+    /// A writer that only counts the bytes, so the length of an encoding is known without allocating it
+    pub(crate) fn counting() -> Self {
+        let mut writer = Self { buf: None, written: 0 };
+        writer.put(&[FORMAT_VERSION]);
+        writer
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        self.written += bytes.len();
+        if let Some(buf) = self.buf.as_mut() {
+            buf.extend_from_slice(bytes);
+        }
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.put(&value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn len(&mut self, len: usize) -> &mut Self {
+        let len = u32::try_from(len).expect("fields are never longer than u32::MAX");
+        self.put(&len.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn hash(&mut self, hash: &[u8; 32]) -> &mut Self {
+        self.put(hash);
+        self
+    }
+
+    pub(crate) fn block_hash(&mut self, hash: &BlockHash) -> &mut Self {
+        self.hash(hash.as_bytes())
+    }
+
+    pub(crate) fn timeslot(&mut self, timeslot: Timeslot) -> &mut Self {
+        self.u64(timeslot.as_u64())
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.len(bytes.len());
+        self.put(bytes);
+        self
+    }
+
+    /// Keys are written as the bytes of their scheme, the PKCS#1 DER encoding for RSA
+    pub(crate) fn key(&mut self, key: &PublicKey) -> &mut Self {
+        self.bytes(&key.to_bytes())
+    }
+
+    /// The number of bytes written so far, the version byte included
+    pub(crate) fn written(&self) -> usize {
+        self.written
+    }
+
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        self.written = 0;
+        self.buf.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Fails if the bytes are not of the version we know
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Self> {
+        match bytes.split_first() {
+            Some((&FORMAT_VERSION, rest)) => Ok(Self(rest)),
+            Some((version, _)) => Err(Error::UnsupportedFormatVersion(*version)),
+            None => Err(Error::InvalidEncoding),
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(Error::InvalidEncoding);
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn len(&mut self) -> Result<usize> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    /// The number of items that follow, refused if the bytes left can not hold that many of at least `min_bytes`
+    pub(crate) fn count(&mut self, min_bytes: usize) -> Result<usize> {
+        let count = self.len()?;
+        if count.saturating_mul(min_bytes) > self.0.len() {
+            return Err(Error::InvalidEncoding);
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn hash(&mut self) -> Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into().unwrap())
+    }
+
+    pub(crate) fn block_hash(&mut self) -> Result<BlockHash> {
+        self.hash().map(BlockHash)
+    }
+
+    pub(crate) fn timeslot(&mut self) -> Result<Timeslot> {
+        self.u64().map(Timeslot)
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    /// Bytes that are refused if they are longer than `max`
+    pub(crate) fn bytes_at_most(&mut self, max: usize) -> Result<&'a [u8]> {
+        let bytes = self.bytes()?;
+        if bytes.len() > max {
+            return Err(Error::InvalidEncoding);
+        }
+        Ok(bytes)
+    }
+
+    pub(crate) fn key(&mut self) -> Result<PublicKey> {
+        PublicKey::from_bytes(self.bytes_at_most(MAX_KEY_BYTES)?)
+    }
+
+    pub(crate) fn signature(&mut self) -> Result<Vec<u8>> {
+        Ok(self.bytes_at_most(MAX_SIGNATURE_BYTES)?.to_vec())
+    }
+
+    /// Fails if there are bytes left, so every value has exactly one encoding
+    pub(crate) fn finish(self) -> Result<()> {
+        self.0
+            .is_empty()
+            .then_some(())
+            .ok_or(Error::InvalidEncoding)
+    }
+}
+
+/// Implements serde for a type through its canonical encoding, so every serde backend carries the same bytes
+macro_rules! serde_via_bytes {
+    ($type:ty) => {
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                serializer.serialize_bytes(&self.to_bytes())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> std::result::Result<Self, D::Error> {
+                let bytes = <Vec<u8>>::deserialize(deserializer)?;
+                Self::from_bytes(&bytes).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+pub(crate) use serde_via_bytes;
+
+/// Implements hex and base64 text for a type through its canonical encoding, to carry it between machines by hand
+macro_rules! text_via_bytes {
+    ($type:ty) => {
+        impl $type {
+            pub fn to_hex(&self) -> String {
+                hex::encode(self.to_bytes())
+            }
+
+            pub fn from_hex(text: &str) -> crate::Result<Self> {
+                let bytes = hex::decode(text.trim()).map_err(|_| crate::Error::InvalidEncoding)?;
+                Self::from_bytes(&bytes)
+            }
+
+            pub fn to_base64(&self) -> String {
+                use base64::Engine;
+                base64::engine::general_purpose::STANDARD.encode(self.to_bytes())
+            }
+
+            pub fn from_base64(text: &str) -> crate::Result<Self> {
+                use base64::Engine;
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(text.trim())
+                    .map_err(|_| crate::Error::InvalidEncoding)?;
+                Self::from_bytes(&bytes)
+            }
+        }
+    };
+}
+
+pub(crate) use text_via_bytes;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::Block,
+        blockchain::BlockchainError,
+        draw::Draw,
+        seeding_mechanism::SeedContent,
+        transaction::{Transaction, UnsignedTransaction},
+    };
+
+    // encodings written by the current version of the format, these must keep decoding
+    #[cfg(not(feature = "ed25519"))]
+    const GOLDEN_TRANSACTION: &str = include_str!("../testdata/transaction_v7.hex");
+    #[cfg(not(feature = "ed25519"))]
+    const GOLDEN_BLOCK: &str = include_str!("../testdata/block_v7.hex");
+    // the first version had no fee or nonce in transactions
+    const V1_TRANSACTION: &str = include_str!("../testdata/transaction_v1.hex");
+    const V1_BLOCK: &str = include_str!("../testdata/block_v1.hex");
+    // the second version signed the transaction hashes of a block instead of their merkle root
+    const V2_TRANSACTION: &str = include_str!("../testdata/transaction_v2.hex");
+    const V2_BLOCK: &str = include_str!("../testdata/block_v2.hex");
+    // the third version had no state root in block headers
+    const V3_TRANSACTION: &str = include_str!("../testdata/transaction_v3.hex");
+    const V3_BLOCK: &str = include_str!("../testdata/block_v3.hex");
+    // the fourth version had no expiry in transactions
+    const V4_TRANSACTION: &str = include_str!("../testdata/transaction_v4.hex");
+    const V4_BLOCK: &str = include_str!("../testdata/block_v4.hex");
+    // the fifth version paid a single receiver per transaction
+    const V5_TRANSACTION: &str = include_str!("../testdata/transaction_v5.hex");
+    const V5_BLOCK: &str = include_str!("../testdata/block_v5.hex");
+    // the sixth version had draws that were not bound to the parent of their block
+    const V6_TRANSACTION: &str = include_str!("../testdata/transaction_v6.hex");
+    const V6_BLOCK: &str = include_str!("../testdata/block_v6.hex");
+
+    #[test]
+    fn unknown_versions_are_refused() {
+        assert_eq!(
+            Reader::new(&[FORMAT_VERSION + 1, 0]).err(),
+            Some(Error::UnsupportedFormatVersion(FORMAT_VERSION + 1))
+        );
+        assert_eq!(Reader::new(&[]).err(), Some(Error::InvalidEncoding));
+    }
+
+    #[test]
+    fn first_version_is_refused() {
+        let transaction = hex::decode(V1_TRANSACTION.trim()).unwrap();
+        assert_eq!(
+            Transaction::from_bytes(&transaction).err(),
+            Some(Error::UnsupportedFormatVersion(1))
+        );
+        let block = hex::decode(V1_BLOCK.trim()).unwrap();
+        assert_eq!(
+            Block::from_bytes(&block).err(),
+            Some(Error::UnsupportedFormatVersion(1))
+        );
+    }
+
+    #[test]
+    fn second_version_is_refused() {
+        let transaction = hex::decode(V2_TRANSACTION.trim()).unwrap();
+        assert_eq!(
+            Transaction::from_bytes(&transaction).err(),
+            Some(Error::UnsupportedFormatVersion(2))
+        );
+        let block = hex::decode(V2_BLOCK.trim()).unwrap();
+        assert_eq!(
+            Block::from_bytes(&block).err(),
+            Some(Error::UnsupportedFormatVersion(2))
+        );
+    }
+
+    #[test]
+    fn third_version_is_refused() {
+        let transaction = hex::decode(V3_TRANSACTION.trim()).unwrap();
+        assert_eq!(
+            Transaction::from_bytes(&transaction).err(),
+            Some(Error::UnsupportedFormatVersion(3))
+        );
+        let block = hex::decode(V3_BLOCK.trim()).unwrap();
+        assert_eq!(
+            Block::from_bytes(&block).err(),
+            Some(Error::UnsupportedFormatVersion(3))
+        );
+    }
+
+    #[test]
+    fn fourth_version_is_refused() {
+        let transaction = hex::decode(V4_TRANSACTION.trim()).unwrap();
+        assert_eq!(
+            Transaction::from_bytes(&transaction).err(),
+            Some(Error::UnsupportedFormatVersion(4))
+        );
+        let block = hex::decode(V4_BLOCK.trim()).unwrap();
+        assert_eq!(
+            Block::from_bytes(&block).err(),
+            Some(Error::UnsupportedFormatVersion(4))
+        );
+    }
+
+    #[test]
+    fn fifth_version_is_refused() {
+        let transaction = hex::decode(V5_TRANSACTION.trim()).unwrap();
+        assert_eq!(
+            Transaction::from_bytes(&transaction).err(),
+            Some(Error::UnsupportedFormatVersion(5))
+        );
+        let block = hex::decode(V5_BLOCK.trim()).unwrap();
+        assert_eq!(
+            Block::from_bytes(&block).err(),
+            Some(Error::UnsupportedFormatVersion(5))
+        );
+    }
+
+    #[test]
+    fn sixth_version_is_refused() {
+        let transaction = hex::decode(V6_TRANSACTION.trim()).unwrap();
+        assert_eq!(
+            Transaction::from_bytes(&transaction).err(),
+            Some(Error::UnsupportedFormatVersion(6))
+        );
+        let block = hex::decode(V6_BLOCK.trim()).unwrap();
+        assert_eq!(
+            Block::from_bytes(&block).err(),
+            Some(Error::UnsupportedFormatVersion(6))
+        );
+    }
+
+    #[test]
+    fn truncated_and_trailing_bytes_are_refused() {
+        let bytes = Writer::new().u64(7).bytes(&[1, 2, 3]).finish();
+
+        let mut reader = Reader::new(&bytes).unwrap();
+        assert_eq!(reader.u64(), Ok(7));
+        assert_eq!(reader.bytes(), Ok(&[1u8, 2, 3][..]));
+        assert_eq!(reader.finish(), Ok(()));
+
+        let mut reader = Reader::new(&bytes[..bytes.len() - 1]).unwrap();
+        reader.u64().unwrap();
+        assert_eq!(reader.bytes(), Err(Error::InvalidEncoding));
+
+        let mut longer = bytes.clone();
+        longer.push(0);
+        let mut reader = Reader::new(&longer).unwrap();
+        reader.u64().unwrap();
+        reader.bytes().unwrap();
+        assert_eq!(reader.finish(), Err(Error::InvalidEncoding));
+    }
+
+    #[test]
+    fn impossible_counts_and_huge_keys_are_refused_before_decoding() {
+        // a count of items that do not fit in the bytes left
+        let bytes = Writer::new().len(u32::MAX as usize).u64(1).finish();
+        assert_eq!(Reader::new(&bytes).unwrap().count(1), Err(Error::InvalidEncoding));
+        let bytes = Writer::new().len(2).u64(1).finish();
+        assert_eq!(Reader::new(&bytes).unwrap().count(4), Ok(2));
+        assert_eq!(Reader::new(&bytes).unwrap().count(5), Err(Error::InvalidEncoding));
+
+        let bytes = Writer::new().bytes(&[0; MAX_KEY_BYTES + 1]).finish();
+        assert_eq!(Reader::new(&bytes).unwrap().key().err(), Some(Error::InvalidEncoding));
+        let (_, pk) = crate::generate_keypair();
+        let bytes = Writer::new().key(&pk).finish();
+        assert_eq!(Reader::new(&bytes).unwrap().key(), Ok(pk));
+    }
+
+    #[test]
+    fn transactions_round_trip() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        assert_eq!(
+            Transaction::new(from.clone(), to.clone(), &sk, 0, 0).map(|_| ()),
+            BlockchainError::ZeroAmount.into()
+        );
+        for amount in [1, 42, u64::MAX] {
+            let t = Transaction::new(from.clone(), to.clone(), &sk, amount, 0).unwrap();
+            let bytes = t.to_bytes();
+            assert_eq!(bytes[0], FORMAT_VERSION);
+
+            let decoded = Transaction::from_bytes(&bytes).unwrap();
+            assert!(decoded.verify_signature());
+            assert_eq!(decoded.hash, t.hash);
+            assert_eq!(decoded.amount(), amount);
+            assert_eq!(decoded.fee, t.fee);
+            assert_eq!(decoded.nonce, t.nonce);
+            assert_eq!(decoded.valid_until, t.valid_until);
+            assert_eq!(decoded.to_bytes(), bytes);
+
+            let through_serde: Transaction =
+                bincode::deserialize(&bincode::serialize(&t).unwrap()).unwrap();
+            assert_eq!(through_serde.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn unsigned_transactions_are_signed_like_the_constructors_sign() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let outputs = vec![(to.clone(), 42)];
+        assert_eq!(
+            UnsignedTransaction::new(from.clone(), vec![(to.clone(), 0)], 1, 9, Timeslot(11)).err(),
+            Some(Error::BlockchainError(BlockchainError::ZeroAmount))
+        );
+        let unsigned = UnsignedTransaction::new(from.clone(), outputs.clone(), 2, 9, Timeslot(11)).unwrap();
+        let bytes = unsigned.to_signing_bytes();
+        assert_eq!(bytes[0], FORMAT_VERSION);
+
+        // carried to the offline machine and back as text
+        let carried = UnsignedTransaction::from_hex(&unsigned.to_hex()).unwrap();
+        assert_eq!(carried.to_signing_bytes(), bytes);
+        let carried = UnsignedTransaction::from_base64(&unsigned.to_base64()).unwrap();
+        assert_eq!(carried, unsigned);
+        let signed = carried.sign(&sk).unwrap();
+        let signed = Transaction::from_base64(&signed.to_base64()).unwrap();
+        let signed = Transaction::from_hex(&signed.to_hex()).unwrap();
+        assert!(signed.verify_signature());
+
+        // the signed transaction has the layout of one signed on the spot
+        let on_the_spot = Transaction::with_outputs(from, outputs, &sk, 2, 9, Timeslot(11)).unwrap();
+        assert_eq!(on_the_spot.unsigned(), unsigned);
+        assert_eq!(signed.unsigned(), unsigned);
+        assert!(signed.to_bytes().starts_with(&bytes));
+        assert_eq!(signed.to_bytes().len(), on_the_spot.to_bytes().len());
+
+        let (other_sk, _) = crate::generate_keypair();
+        assert_eq!(unsigned.sign(&other_sk).err(), Some(Error::WrongKey));
+        assert_eq!(UnsignedTransaction::from_hex("not hex").err(), Some(Error::InvalidEncoding));
+        assert!(UnsignedTransaction::from_bytes(&signed.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        for n in [0, 1, 5] {
+            let transactions = (1..=n)
+                .map(|amount| Transaction::new(pk.clone(), receiver.clone(), &sk, amount, amount - 1).unwrap())
+                .collect();
+            let seed = SeedContent::new((BlockHash([n as u8; 32]), n), [7; 32]);
+            let block = Block::new(
+                Timeslot(n + 1),
+                BlockHash([9; 32]),
+                n,
+                pk.clone(),
+                transactions,
+                [n as u8; 32],
+                &sk,
+                seed.clone(),
+            );
+            let bytes = block.to_bytes();
+
+            let decoded = Block::from_bytes(&bytes).unwrap();
+            assert!(decoded.verify_signature());
+            assert!(decoded.draw.verify(&seed, &BlockHash([9; 32]), Timeslot(n + 1)));
+            assert_eq!(decoded.hash, block.hash);
+            assert_eq!(decoded.state_root(), &[n as u8; 32]);
+            assert_eq!(decoded.transactions.len(), n as usize);
+            assert_eq!(decoded.draw.value, block.draw.value);
+            assert_eq!(decoded.to_bytes(), bytes);
+
+            assert_eq!(
+                Block::from_bytes(&bytes[..bytes.len() - 1]).err(),
+                Some(Error::InvalidEncoding)
+            );
+        }
+    }
+
+    #[test]
+    fn impossible_blocks_and_draws_are_refused_before_any_signature_is_checked() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let transactions = vec![Transaction::new(pk.clone(), receiver, &sk, 1, 0).unwrap()];
+        let seed = SeedContent::new((BlockHash([0; 32]), 1), [7; 32]);
+        let block = Block::new(Timeslot(2), BlockHash([9; 32]), 2, pk, transactions, [0; 32], &sk, seed);
+        assert!(Block::from_bytes(&block.to_bytes()).is_ok());
+        assert_eq!(Draw::from_bytes(&block.draw.to_bytes()).unwrap().value, block.draw.value);
+
+        let changed = |change: fn(&mut Block)| {
+            let mut block = block.clone();
+            change(&mut block);
+            block.to_bytes()
+        };
+        let refused: [fn(&mut Block); 8] = [
+            |block| block.header.depth = 3, // deeper than its timeslot
+            |block| block.header.depth = u64::MAX,
+            |block| {
+                block.header.timeslot = Timeslot::MAX;
+                block.header.draw.timeslot = Timeslot::MAX;
+            },
+            |block| block.header.draw.timeslot = Timeslot(3),
+            |block| block.header.draw.prev_hash = BlockHash([3; 32]), // a draw for another parent
+            |block| block.header.draw.seed.block_ptr.1 = 3,
+            |block| block.header.signature = crate::keys::Signature(vec![0; MAX_SIGNATURE_BYTES + 1]),
+            |block| block.header.transaction_count = 2, // a body with fewer transactions than its header
+        ];
+        for change in refused {
+            assert_eq!(Block::from_bytes(&changed(change)).err(), Some(Error::InvalidEncoding));
+        }
+
+        // a draw value is a SHA-256, a larger number is not read
+        let mut draw = block.draw.clone();
+        draw.value = num_bigint::BigUint::from_bytes_be(&[1; MAX_DRAW_VALUE_BYTES + 1]);
+        assert_eq!(Draw::from_bytes(&draw.to_bytes()).err(), Some(Error::InvalidEncoding));
+        let bytes = changed(|block| block.header.draw.value = num_bigint::BigUint::from_bytes_be(&[1; 1_000]));
+        assert_eq!(Block::from_bytes(&bytes).err(), Some(Error::InvalidEncoding));
+        // nor is a value with a leading zero, which would give the draw a second encoding
+        draw.value = num_bigint::BigUint::from(5u8);
+        let bytes = draw.to_bytes();
+        assert!(Draw::from_bytes(&bytes).is_ok());
+        let mut padded = Writer::new().bytes(&[0, 5]).finish();
+        padded.extend_from_slice(&bytes[1 + 4 + 1..]);
+        assert_eq!(Draw::from_bytes(&padded).err(), Some(Error::InvalidEncoding));
+    }
+
+    // the goldens were written with RSA keys
+    #[test]
+    #[cfg(not(feature = "ed25519"))]
+    fn golden_transaction_decodes() {
+        let bytes = hex::decode(GOLDEN_TRANSACTION.trim()).unwrap();
+        let t = Transaction::from_bytes(&bytes).unwrap();
+        assert!(t.verify_signature());
+        assert_eq!(t.outputs.len(), 2);
+        assert_eq!(t.amount(), 42);
+        assert_eq!(t.fee, 2);
+        assert_eq!(t.nonce, 9);
+        assert_eq!(t.valid_until, Timeslot(11));
+        assert_eq!(t.to_bytes(), bytes);
+    }
+
+    #[test]
+    #[cfg(not(feature = "ed25519"))]
+    fn golden_block_decodes() {
+        let bytes = hex::decode(GOLDEN_BLOCK.trim()).unwrap();
+        let block = Block::from_bytes(&bytes).unwrap();
+        assert!(block.verify_signature());
+        assert!(block.draw.verify(&block.draw.seed, &BlockHash([1; 32]), Timeslot(7)));
+        assert_eq!(block.timeslot, Timeslot(7));
+        assert_eq!(block.prev_hash, BlockHash([1; 32]));
+        assert_eq!(block.depth, 3);
+        assert_eq!(block.state_root(), &[2; 32]);
+        assert_eq!(block.transactions.len(), 1);
+        assert!(block.transactions[0].verify_signature());
+        assert_eq!(block.to_bytes(), bytes);
+
+        let header = crate::block::BlockHeader::from_bytes(&block.header().to_bytes()).unwrap();
+        assert!(header.verify_signature());
+        assert_eq!(header.hash, block.hash);
+        assert_eq!(header.transaction_count(), 1);
+    }
+}
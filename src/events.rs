@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::block::Block;
+use crate::blockchain::{BlockPtr, BlockchainError, Equivocation};
+
+/// How many events a slow subscriber can lag behind before it starts missing them
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Events emitted by the blockchain as it changes
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    /// The block was added to the block tree, it is not necessarily on the best path
+    BlockAccepted(Arc<Block>),
+    /// The block breaks a rule of the chain, orphans and blocks from the future are not rejected
+    BlockRejected {
+        block: BlockPtr,
+        reason: BlockchainError,
+    },
+    /// The signatures of a block and its transactions were checked in this time
+    BlockVerified(Duration),
+    /// The parent of the block is unknown, so it is kept until the parent arrives
+    BlockOrphaned(BlockPtr),
+    /// The best path switched branch, `depth` is the number of blocks that were rolled back
+    Reorg {
+        old_tip: BlockPtr,
+        new_tip: BlockPtr,
+        depth: u64,
+    },
+    /// A branch overtook the best path but leaves it more than `max_reorg_depth` blocks below the tip,
+    /// so the node kept its tip. `Blockchain::force_reorg` switches to the branch
+    ReorgRefused {
+        old_tip: BlockPtr,
+        new_tip: BlockPtr,
+        depth: u64,
+    },
+    /// The block joined the best path, either on top of it or as part of a reorg
+    BlockConnected(Arc<Block>),
+    /// The block left the best path in a reorg, its transactions are back in the mempool
+    BlockDisconnected(Arc<Block>),
+    /// The head of the best path changed, either by extension or by a reorg
+    NewTip(BlockPtr),
+    /// The transaction with this hash was included in a block on the best path
+    TransactionConfirmed([u8; 32]),
+    /// A winner signed two different blocks in the same timeslot and is penalized for a while
+    Equivocation(Equivocation),
+    /// The mempool cleanup dropped this many transactions that could no longer go in a block
+    MempoolPruned { dropped: usize },
+}
+
+pub(crate) fn channel() -> broadcast::Sender<ChainEvent> {
+    broadcast::channel(EVENT_CHANNEL_CAPACITY).0
+}
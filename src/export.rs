@@ -0,0 +1,229 @@
+// dumps of a chain for explorers and for diffing the views of two nodes.
+// the output only depends on the chain, blocks are ordered by depth and hash and accounts by address
+
+use std::io::{self, Write};
+
+use crate::{block::Block, blockchain::{Blockchain, TipRef}};
+
+/// What the `dump` RPC method and CLI command write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Json,
+    Dot,
+}
+
+// the first 8 bytes are enough to tell blocks and accounts apart when reading a dump
+fn short(bytes: &[u8]) -> String {
+    hex::encode(&bytes[..8])
+}
+
+fn ordered<'a>(blocks: impl Iterator<Item = &'a std::sync::Arc<Block>>) -> Vec<&'a Block> {
+    let mut blocks: Vec<&Block> = blocks.map(|block| block.as_ref()).collect();
+    blocks.sort_by_key(|block| (block.depth(), block.hash));
+    blocks
+}
+
+impl Blockchain {
+    /// Writes the params, the best path, the orphans and the ledger as a JSON document
+    pub fn export_json(&self, w: &mut impl Write) -> io::Result<()> {
+        let params = self.params();
+        let TipRef { hash: tip_hash, depth: tip_depth } = self.tip();
+        let (checkpoint_hash, checkpoint_depth) = self.checkpoint();
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"params\": {{")?;
+        writeln!(w, "    \"root_amount\": {},", params.root_amount)?;
+        writeln!(w, "    \"block_reward\": {},", params.block_reward)?;
+        writeln!(w, "    \"transaction_fee\": {},", params.transaction_fee)?;
+        writeln!(w, "    \"slot_length_ms\": {},", params.slot_length_ms)?;
+        writeln!(w, "    \"hardness\": \"{}\",", params.hardness.to_str_radix(16))?;
+        writeln!(w, "    \"max_block_transactions\": {},", params.max_block_transactions)?;
+        writeln!(w, "    \"max_block_bytes\": {},", params.max_block_bytes)?;
+        writeln!(w, "    \"stake_lookback\": {},", params.stake_lookback)?;
+        writeln!(w, "    \"min_key_bits\": {}", params.min_key_bits)?;
+        writeln!(w, "  }},")?;
+        let genesis = self.best_path_block(0).expect("the genesis block is on the best path");
+        writeln!(w, "  \"genesis\": \"{}\",", hex::encode(genesis.hash))?;
+        writeln!(w, "  \"tip\": {{ \"hash\": \"{}\", \"depth\": {tip_depth} }},", hex::encode(tip_hash))?;
+        writeln!(
+            w,
+            "  \"checkpoint\": {{ \"hash\": \"{}\", \"depth\": {checkpoint_depth} }},",
+            hex::encode(checkpoint_hash)
+        )?;
+
+        // from the genesis block to the tip
+        let mut best_path: Vec<_> = self.best_path_iter().collect();
+        best_path.reverse();
+        writeln!(w, "  \"best_path\": [")?;
+        for (i, block) in best_path.iter().enumerate() {
+            let transactions: Vec<_> = block
+                .transactions()
+                .iter()
+                .map(|t| format!("\"{}\"", t.id()))
+                .collect();
+            let comma = if i + 1 < best_path.len() { "," } else { "" };
+            writeln!(
+                w,
+                "    {{ \"hash\": \"{}\", \"depth\": {}, \"timeslot\": {}, \"proposer\": \"{}\", \"transactions\": [{}] }}{comma}",
+                hex::encode(block.hash),
+                block.depth(),
+                block.timeslot(),
+                short(block.winner_id().as_bytes()),
+                transactions.join(", "),
+            )?;
+        }
+        writeln!(w, "  ],")?;
+
+        let orphans = ordered(self.orphans_iter());
+        writeln!(w, "  \"orphans\": {{")?;
+        writeln!(w, "    \"count\": {},", orphans.len())?;
+        writeln!(w, "    \"blocks\": [")?;
+        for (i, block) in orphans.iter().enumerate() {
+            let comma = if i + 1 < orphans.len() { "," } else { "" };
+            writeln!(
+                w,
+                "      {{ \"hash\": \"{}\", \"depth\": {}, \"parent\": \"{}\" }}{comma}",
+                hex::encode(block.hash),
+                block.depth(),
+                hex::encode(block.prev_hash),
+            )?;
+        }
+        writeln!(w, "    ]")?;
+        writeln!(w, "  }},")?;
+
+        let mut balances: Vec<_> = self.ledger().map.iter().collect();
+        balances.sort();
+        writeln!(w, "  \"ledger\": {{")?;
+        for (i, (account, balance)) in balances.iter().enumerate() {
+            let comma = if i + 1 < balances.len() { "," } else { "" };
+            writeln!(w, "    \"{}\": {balance}{comma}", short(account.as_bytes()))?;
+        }
+        writeln!(w, "  }}")?;
+        writeln!(w, "}}")
+    }
+
+    /// Writes every block in the tree as a Graphviz graph, the best path is drawn in bold
+    pub fn export_forks_dot(&self, w: &mut impl Write) -> io::Result<()> {
+        let on_best_path = |block: &Block| {
+            self.best_path_block(block.depth())
+                .is_some_and(|best| best.hash == block.hash)
+        };
+        writeln!(w, "digraph forks {{")?;
+        writeln!(w, "  rankdir=LR;")?;
+        writeln!(w, "  node [shape=box, fontname=monospace];")?;
+        let blocks = ordered(self.tree_iter());
+        for block in blocks.iter() {
+            let style = if on_best_path(block) {
+                ", style=\"bold,filled\", fillcolor=lightblue"
+            } else {
+                ""
+            };
+            writeln!(
+                w,
+                "  \"{}\" [label=\"{}\\ndepth {} timeslot {}\\n{} transactions\"{style}];",
+                hex::encode(block.hash),
+                short(block.hash.as_bytes()),
+                block.depth(),
+                block.timeslot(),
+                block.transaction_count(),
+            )?;
+        }
+        // the genesis block has no parent in the tree
+        for block in blocks.iter().filter(|block| block.depth() > 0) {
+            let style = if on_best_path(block) { " [style=bold]" } else { "" };
+            writeln!(
+                w,
+                "  \"{}\" -> \"{}\"{style};",
+                hex::encode(block.prev_hash),
+                hex::encode(block.hash)
+            )?;
+        }
+        writeln!(w, "}}")
+    }
+
+    /// The dump in the format, as written by `export_json` or `export_forks_dot`
+    pub fn dump(&self, format: DumpFormat) -> String {
+        let mut out = Vec::new();
+        match format {
+            DumpFormat::Json => self.export_json(&mut out),
+            DumpFormat::Dot => self.export_forks_dot(&mut out),
+        }
+        .expect("writing to a vec can not fail");
+        String::from_utf8(out).expect("the dumps are ascii")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        account::AccountId, block::BlockHash, clock::ManualClock, draw::DrawCache, params::ChainParams,
+        seeding_mechanism::SeedContent, transaction::Transaction,
+    };
+
+    #[test]
+    fn dumps_show_the_best_path_and_the_forks() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let mut blockchain =
+            Blockchain::start_with_clock(vec![pk.clone()], ChainParams::default(), Arc::new(clock.clone()));
+        let (_, receiver) = crate::generate_keypair();
+        let payment = Transaction::new(pk.clone(), receiver.clone(), &sk, 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment.clone()), Ok(false));
+
+        let mut draws = DrawCache::default();
+        // a fork is checked against the ledger of its branch, so it is built on the chain as it was after the first block
+        let mut at_first = None;
+        while blockchain.tip().depth < 5 {
+            clock.advance_slots(1);
+            if let Some(block) = blockchain.try_produce(&sk, &mut draws) {
+                assert_eq!(blockchain.add_block(block), Ok(()));
+            }
+            if blockchain.tip().depth == 1 && at_first.is_none() {
+                at_first = Some(blockchain.clone());
+            }
+        }
+        // a losing fork off the first block and an orphan
+        let at_first = at_first.unwrap();
+        let first = Arc::clone(blockchain.best_path_block(1).unwrap());
+        let mut fork_draws = DrawCache::default();
+        let fork = loop {
+            clock.advance_slots(1);
+            if let Some(fork) = at_first.try_produce(&sk, &mut fork_draws) {
+                break fork;
+            }
+        };
+        let timeslot = fork.timeslot();
+        assert!(blockchain.add_block(fork.clone()).is_err());
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        let orphan = Block::new(timeslot, BlockHash([9; 32]), 4, pk.clone(), vec![], [0; 32], &sk, seed);
+        assert!(blockchain.add_block(orphan.clone()).is_err());
+
+        let json: serde_json::Value = serde_json::from_str(&blockchain.dump(DumpFormat::Json)).unwrap();
+        assert_eq!(json["params"]["block_reward"], blockchain.params().block_reward);
+        assert_eq!(json["tip"]["depth"], 5);
+        let best_path = json["best_path"].as_array().unwrap();
+        assert_eq!(best_path.len(), 6);
+        assert_eq!(json["genesis"], best_path[0]["hash"]);
+        assert_eq!(best_path[1]["transactions"][0], payment.id().to_string());
+        assert_eq!(json["orphans"]["count"], 1);
+        assert_eq!(json["orphans"]["blocks"][0]["parent"], hex::encode([9; 32]));
+        let short_receiver = short(AccountId::from(&receiver).as_bytes());
+        assert_eq!(json["ledger"][short_receiver], 10);
+        // the same chain always gives the same dump
+        assert_eq!(blockchain.dump(DumpFormat::Json), blockchain.clone().dump(DumpFormat::Json));
+
+        let dot = blockchain.dump(DumpFormat::Dot);
+        assert!(dot.starts_with("digraph forks {"));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", hex::encode(first.hash), hex::encode(fork.hash))));
+        let second = blockchain.best_path_block(2).unwrap();
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [style=bold];",
+            hex::encode(first.hash),
+            hex::encode(second.hash)
+        )));
+        assert_eq!(dot.matches(" -> ").count(), 6);
+        assert!(!dot.contains(&hex::encode(orphan.hash)));
+    }
+}
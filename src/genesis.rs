@@ -0,0 +1,129 @@
+// a network is launched from a manifest every party reads before the launch: the root accounts, the params and
+// the start time. The genesis block is made from the manifest alone and is not signed, so everyone who has it
+// computes the same block and hash without any key and can confirm the hash before the first node starts
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blockchain::{Blockchain, Genesis},
+    config::ParamsOverride,
+    keys::{self, PublicKey},
+    params::ChainParams,
+    Error, Result,
+};
+
+/// The TOML file a network is launched from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisManifest {
+    pub start_time: u64, // milliseconds since the unix epoch at which timeslot 0 starts
+    pub root_accounts: Vec<String>, // public keys in PEM, the order is part of the genesis and the first is its winner
+    #[serde(default)]
+    pub params: ParamsOverride, // the defaults for those that are left out
+}
+
+impl GenesisManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("unable to read {path:?}, {e}")))?;
+        Self::parse(&text)
+    }
+
+    /// The error names the line and the key that are wrong
+    pub fn parse(text: &str) -> Result<Self> {
+        let manifest: Self = toml::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        manifest.params.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn root_accounts(&self) -> Result<Vec<PublicKey>> {
+        if self.root_accounts.is_empty() {
+            return Err(Error::InvalidConfig("the manifest has no root accounts".into()));
+        }
+        self.root_accounts
+            .iter()
+            .map(|pem| keys::import_public(pem.as_bytes()))
+            .collect()
+    }
+
+    pub fn params(&self) -> ChainParams {
+        self.params.apply(ChainParams::default())
+    }
+
+    /// The genesis block and what it is made from, `Blockchain::from_genesis_with_params` starts the chain of it
+    pub fn genesis(&self) -> Result<Genesis> {
+        let root_accounts = self.root_accounts()?;
+        let params = self.params();
+        // they could never produce a block
+        if let Some(small) = root_accounts.iter().find(|pk| !pk.has_bits(params.min_key_bits)) {
+            return Err(Error::InvalidConfig(format!(
+                "the key of root account {} is smaller than min_key_bits",
+                small.id()
+            )));
+        }
+        let start_time = self.start_time as u128;
+        Ok(Genesis {
+            block: Blockchain::genesis_block(&root_accounts, &params, start_time),
+            root_accounts,
+            params,
+            start_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "ed25519"))]
+    fn manifest(root_accounts: &[&str]) -> String {
+        let root_accounts: Vec<_> = root_accounts.iter().map(|pem| format!("\"\"\"\n{pem}\"\"\"")).collect();
+        format!(
+            "start_time = 1767225600000\nroot_accounts = [{}]\n\n[params]\nblock_reward = 5\n",
+            root_accounts.join(", ")
+        )
+    }
+
+    #[cfg(not(feature = "ed25519"))]
+    #[test]
+    fn the_same_manifest_makes_the_same_genesis_block() {
+        let fixture = include_str!("../tests/fixtures/keys/public.spki.pem");
+        let (_, pk) = crate::generate_keypair();
+        let generated = keys::export_public(&pk, keys::KeyFormat::Pkcs8Pem).unwrap();
+        let generated = String::from_utf8(generated).unwrap();
+        let text = manifest(&[fixture, &generated]);
+
+        let first = GenesisManifest::parse(&text).unwrap().genesis().unwrap();
+        let second = GenesisManifest::parse(&text).unwrap().genesis().unwrap();
+        assert_eq!(first.block.to_bytes(), second.block.to_bytes());
+        assert_eq!(first.params.block_reward, 5);
+        assert_eq!(first.start_time, 1767225600000);
+
+        // it is the genesis block of a chain started from the root accounts and params at the start time
+        let Genesis { block, root_accounts, params, start_time } = first;
+        let clock = std::sync::Arc::new(crate::clock::ManualClock::new(start_time as u64));
+        let started = Blockchain::start_with_clock(root_accounts.clone(), params.clone(), clock);
+        assert_eq!(started.genesis_hash(), block.hash);
+        let blockchain = Blockchain::from_genesis_with_params(block.clone(), root_accounts, params, start_time).unwrap();
+        assert_eq!(blockchain.genesis_hash(), block.hash);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+
+        // the order of the root accounts is part of it
+        let swapped = GenesisManifest::parse(&manifest(&[&generated, fixture])).unwrap();
+        assert_ne!(swapped.genesis().unwrap().block.hash, block.hash);
+        // and so is the start time
+        let later = GenesisManifest::parse(&text.replace("1767225600000", "1767225601000")).unwrap();
+        assert_ne!(later.genesis().unwrap().block.hash, block.hash);
+    }
+
+    #[test]
+    fn a_manifest_without_root_accounts_or_with_unknown_keys_is_refused() {
+        let empty = GenesisManifest::parse("start_time = 0\nroot_accounts = []\n").unwrap();
+        assert!(matches!(empty.genesis(), Err(Error::InvalidConfig(_))));
+        let not_a_key = GenesisManifest::parse("start_time = 0\nroot_accounts = [\"not a key\"]\n").unwrap();
+        assert!(matches!(not_a_key.genesis(), Err(Error::KeyImport(_))));
+        assert!(GenesisManifest::parse("start_time = 0\nroot_accounts = []\nseed = 1\n").is_err());
+    }
+}
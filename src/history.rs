@@ -0,0 +1,277 @@
+// the transfers of every account along the best path, for wallets and accounting.
+// the index follows the best path block by block, so a query does not scan the chain
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::AccountId,
+    block::{Block, BlockHash},
+    blockchain::Blockchain,
+    keys::PublicKey,
+    transaction::TxId,
+    Timeslot,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Incoming => "in",
+            Direction::Outgoing => "out",
+        }
+    }
+}
+
+/// A transfer to or from an account in a block of the best path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub depth: u64,
+    pub block: BlockHash,
+    pub timeslot: Timeslot,
+    pub transaction: Option<TxId>, // none for a reward
+    pub counterparty: Option<AccountId>, // none for a reward
+    pub direction: Direction,
+    pub amount: u64,
+    pub fee: u64, // paid by the sender, it is on the entry of the first output of a transaction
+    pub reward: bool, // the reward of a block, the root allocations are the rewards of the genesis block
+}
+
+/// The entries of every account in the order of the best path
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountHistory {
+    entries: HashMap<AccountId, Vec<HistoryEntry>>,
+}
+
+impl AccountHistory {
+    /// Starts with the root allocations of the genesis block
+    pub fn new(genesis: &Block, root_accounts: &[PublicKey], root_amount: u64) -> Self {
+        let mut history = Self::default();
+        for account in root_accounts {
+            history.push(AccountId::from(account), reward_entry(genesis, root_amount));
+        }
+        history
+    }
+
+    /// Adds the transfers of the block on top of the best path
+    pub fn add_block(&mut self, block: &Block, reward: u64) {
+        for t in block.transactions() {
+            let mut fee = t.fee();
+            for (to, amount) in t.outputs() {
+                let entry = |counterparty, direction, fee| HistoryEntry {
+                    depth: block.depth(),
+                    block: block.hash,
+                    timeslot: block.timeslot(),
+                    transaction: Some(t.id()),
+                    counterparty: Some(counterparty),
+                    direction,
+                    amount: *amount,
+                    fee,
+                    reward: false,
+                };
+                let to = AccountId::from(to);
+                self.push(t.from_id(), entry(to, Direction::Outgoing, fee));
+                self.push(to, entry(t.from_id(), Direction::Incoming, 0));
+                fee = 0;
+            }
+        }
+        self.push(block.winner_id(), reward_entry(block, reward));
+    }
+
+    /// Removes the transfers of the block at the top of the best path when it is rolled back
+    pub fn remove_block(&mut self, block: &Block) {
+        let mut accounts = vec![block.winner_id()];
+        for t in block.transactions() {
+            accounts.push(t.from_id());
+            accounts.extend(t.outputs().iter().map(|(to, _)| AccountId::from(to)));
+        }
+        for account in accounts {
+            let Some(entries) = self.entries.get_mut(&account) else {
+                continue;
+            };
+            while entries.last().is_some_and(|entry| entry.depth == block.depth()) {
+                entries.pop();
+            }
+            if entries.is_empty() {
+                self.entries.remove(&account);
+            }
+        }
+    }
+
+    pub fn get(&self, account: &AccountId) -> &[HistoryEntry] {
+        self.entries.get(account).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn push(&mut self, account: AccountId, entry: HistoryEntry) {
+        self.entries.entry(account).or_default().push(entry);
+    }
+}
+
+fn reward_entry(block: &Block, amount: u64) -> HistoryEntry {
+    HistoryEntry {
+        depth: block.depth(),
+        block: block.hash,
+        timeslot: block.timeslot(),
+        transaction: None,
+        counterparty: None,
+        direction: Direction::Incoming,
+        amount,
+        fee: 0,
+        reward: true,
+    }
+}
+
+fn optional(value: Option<impl ToString>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+impl Blockchain {
+    /// Every transfer to and from the account along the best path, oldest first.
+    /// A chain started from a snapshot only knows the transfers above its checkpoint
+    pub fn account_history(&self, account: &AccountId) -> Vec<HistoryEntry> {
+        self.history().get(account).to_vec()
+    }
+
+    /// Writes the history of the account with a header line, the missing fields of a reward are empty
+    pub fn export_history_csv(&self, account: &AccountId, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "depth,block,timeslot,transaction,counterparty,direction,amount,fee,reward")?;
+        for entry in self.history().get(account) {
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{},{},{}",
+                entry.depth,
+                hex::encode(entry.block),
+                entry.timeslot,
+                optional(entry.transaction),
+                optional(entry.counterparty),
+                entry.direction.as_str(),
+                entry.amount,
+                entry.fee,
+                entry.reward,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the history of the account as a JSON array, the missing fields of a reward are null
+    pub fn export_history_json(&self, account: &AccountId, w: &mut impl Write) -> io::Result<()> {
+        let quoted = |value: Option<String>| match value {
+            Some(value) => format!("\"{value}\""),
+            None => "null".to_string(),
+        };
+        let entries = self.history().get(account);
+        writeln!(w, "[")?;
+        for (i, entry) in entries.iter().enumerate() {
+            let comma = if i + 1 < entries.len() { "," } else { "" };
+            writeln!(
+                w,
+                "  {{ \"depth\": {}, \"block\": \"{}\", \"timeslot\": {}, \"transaction\": {}, \"counterparty\": {}, \"direction\": \"{}\", \"amount\": {}, \"fee\": {}, \"reward\": {} }}{comma}",
+                entry.depth,
+                hex::encode(entry.block),
+                entry.timeslot,
+                quoted(entry.transaction.map(|id| id.to_string())),
+                quoted(entry.counterparty.map(|id| id.to_string())),
+                entry.direction.as_str(),
+                entry.amount,
+                entry.fee,
+                entry.reward,
+            )?;
+        }
+        writeln!(w, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{clock::ManualClock, draw::DrawCache, keys::SecretKey, params::ChainParams, transaction::Transaction};
+
+    // every draw wins, so a block is produced in every timeslot
+    fn produce(blockchain: &mut Blockchain, clock: &ManualClock, sk: &SecretKey) -> Block {
+        let mut draws = DrawCache::default();
+        loop {
+            clock.advance_slots(1);
+            if let Some(block) = blockchain.try_produce(sk, &mut draws) {
+                assert_eq!(blockchain.add_block(block.clone()), Ok(()));
+                return block;
+            }
+        }
+    }
+
+    #[test]
+    fn history_follows_rewards_payments_and_reorgs() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, bob) = crate::generate_keypair();
+        let (me, bob_id) = (AccountId::from(&pk), AccountId::from(&bob));
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain =
+            Blockchain::start_with_clock(vec![pk.clone()], params.clone(), Arc::new(clock.clone()));
+        let mut fork = blockchain.clone();
+        let allocation = reward_entry(blockchain.best_path_block(0).unwrap(), params.root_amount);
+        assert_eq!(allocation.block, blockchain.genesis_hash());
+        assert_eq!(blockchain.account_history(&me), vec![allocation]);
+
+        let payment = Transaction::new(pk.clone(), bob.clone(), &sk, 30, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment.clone()), Ok(false));
+        let block = produce(&mut blockchain, &clock, &sk);
+
+        let sent = HistoryEntry {
+            depth: 1,
+            block: block.hash,
+            timeslot: block.timeslot(),
+            transaction: Some(payment.id()),
+            counterparty: Some(bob_id),
+            direction: Direction::Outgoing,
+            amount: 30,
+            fee: payment.fee(),
+            reward: false,
+        };
+        let received = HistoryEntry {
+            counterparty: Some(me),
+            direction: Direction::Incoming,
+            fee: 0,
+            ..sent.clone()
+        };
+        let history = blockchain.account_history(&me);
+        assert_eq!(history[1..], [sent, reward_entry(&block, params.block_reward)]);
+        assert_eq!(blockchain.account_history(&bob_id), vec![received]);
+
+        let mut csv = Vec::new();
+        blockchain.export_history_csv(&bob_id, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().ends_with(&format!(",{me},in,30,0,false")));
+        let mut json = Vec::new();
+        blockchain.export_history_json(&me, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+        assert_eq!(json[0]["counterparty"], serde_json::Value::Null);
+        assert_eq!(json[1]["fee"], payment.fee());
+
+        // a longer branch without the payment takes over
+        let forked: Vec<_> = (0..2).map(|_| produce(&mut fork, &clock, &sk)).collect();
+        for block in forked.iter() {
+            let _ = blockchain.add_block(block.clone());
+        }
+        assert_eq!(blockchain.tip(), fork.tip());
+        assert!(blockchain.account_history(&bob_id).is_empty());
+        let rewards: Vec<_> = forked.iter().map(|block| reward_entry(block, params.block_reward)).collect();
+        assert_eq!(blockchain.account_history(&me)[1..], rewards);
+        assert_eq!(blockchain.account_history(&me), fork.account_history(&me));
+    }
+}
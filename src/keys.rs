@@ -0,0 +1,742 @@
+// the signature scheme of the chain. RSA is the default and Ed25519 can be chosen with the `ed25519` feature,
+// the rest of the crate only sees `PublicKey`, `SecretKey` and the signatures they make.
+// keys made by other tools are imported in any of the encodings openssl writes
+
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use pkcs8::der::pem::{self, LineEnding};
+use rand::{thread_rng, CryptoRng, RngCore};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+use rsa::sha2::{Digest, Sha256};
+
+use crate::{
+    account::AccountId,
+    encoding::serde_via_bytes,
+    Error, Result,
+};
+
+/// The smallest RSA key that is made or that a chain may accept, only a test network should go below 2048
+pub const MIN_KEY_BITS: usize = 1024;
+/// The size of the RSA keys that are made when no size is asked for, and the minimum of a chain by default.
+/// The `small_key` feature halves it for tests, the minimum is in the params so such a chain is a chain of its own
+#[cfg(not(feature = "small_key"))]
+pub const DEFAULT_KEY_BITS: usize = 2048;
+#[cfg(feature = "small_key")]
+pub const DEFAULT_KEY_BITS: usize = MIN_KEY_BITS;
+
+pub trait SignatureScheme {
+    /// Hashed into the genesis seed, so chains with different schemes never accept each other's blocks
+    const NAME: &'static str;
+    type SigningKey: Clone;
+    type VerifyingKey: Clone + PartialEq + Eq + Hash + fmt::Debug;
+
+    /// A key of `bits`, a scheme whose keys all have the same size ignores it
+    fn generate<R: CryptoRng + RngCore>(bits: usize, rng: &mut R) -> Option<Self::SigningKey>;
+    /// The same seed always gives the same key, used for seedphrases
+    fn from_seed(seed: [u8; 32]) -> Self::SigningKey;
+    fn verifying_key(sk: &Self::SigningKey) -> Self::VerifyingKey;
+    /// The size of the key, `None` for a scheme whose keys all have the same size
+    fn key_bits(vk: &Self::VerifyingKey) -> Option<usize>;
+    fn sign(sk: &Self::SigningKey, data: &[u8]) -> Option<Vec<u8>>;
+    /// The same key and data always give the same signature, the draws are the hash of one
+    fn sign_unique(sk: &Self::SigningKey, data: &[u8]) -> Option<Vec<u8>>;
+    fn verify(vk: &Self::VerifyingKey, data: &[u8], signature: &[u8]) -> bool;
+    fn verify_unique(vk: &Self::VerifyingKey, data: &[u8], signature: &[u8]) -> bool;
+    /// The bytes a key is encoded with and that its address is the hash of
+    fn key_to_bytes(vk: &Self::VerifyingKey) -> Vec<u8>;
+    fn key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey>;
+    fn key_to_pem(vk: &Self::VerifyingKey) -> Option<String>;
+    fn key_from_pem(pem: &str) -> Option<Self::VerifyingKey>;
+    fn secret_to_pem(sk: &Self::SigningKey) -> Option<Zeroizing<String>>;
+    fn secret_from_pem(pem: &str) -> Option<Self::SigningKey>;
+    /// The PKCS#8 DER of the key, the keystore encrypts it
+    fn secret_to_der(sk: &Self::SigningKey) -> Option<Zeroizing<Vec<u8>>>;
+    fn secret_from_der(der: &[u8]) -> Option<Self::SigningKey>;
+    /// The SubjectPublicKeyInfo DER of the key, what a `PUBLIC KEY` pem holds
+    fn key_to_spki(vk: &Self::VerifyingKey) -> Option<Vec<u8>>;
+    fn key_from_spki(der: &[u8]) -> Option<Self::VerifyingKey>;
+    /// The PKCS#1 DER of the key, only RSA keys have one
+    fn key_to_pkcs1(_vk: &Self::VerifyingKey) -> Option<Vec<u8>> {
+        None
+    }
+    fn key_from_pkcs1(_der: &[u8]) -> Option<Self::VerifyingKey> {
+        None
+    }
+    fn secret_to_pkcs1(_sk: &Self::SigningKey) -> Option<Zeroizing<Vec<u8>>> {
+        None
+    }
+    fn secret_from_pkcs1(_der: &[u8]) -> Option<Self::SigningKey> {
+        None
+    }
+}
+
+/// RSA with PSS signatures, the draws use PKCS#1 v1.5 because it is deterministic
+pub struct Rsa;
+
+impl SignatureScheme for Rsa {
+    const NAME: &'static str = "rsa";
+    type SigningKey = rsa::RsaPrivateKey;
+    type VerifyingKey = rsa::RsaPublicKey;
+
+    fn generate<R: CryptoRng + RngCore>(bits: usize, rng: &mut R) -> Option<Self::SigningKey> {
+        rsa::RsaPrivateKey::new(rng, bits).ok()
+    }
+
+    fn from_seed(seed: [u8; 32]) -> Self::SigningKey {
+        use rand::SeedableRng;
+        let mut rng = rand_chacha::ChaCha20Rng::from_seed(seed);
+        rsa::RsaPrivateKey::new(&mut rng, 2048).unwrap()
+    }
+
+    fn verifying_key(sk: &Self::SigningKey) -> Self::VerifyingKey {
+        sk.to_public_key()
+    }
+
+    fn key_bits(vk: &Self::VerifyingKey) -> Option<usize> {
+        use rsa::traits::PublicKeyParts;
+        Some(vk.n().bits())
+    }
+
+    fn sign(sk: &Self::SigningKey, data: &[u8]) -> Option<Vec<u8>> {
+        sk.sign_with_rng(&mut thread_rng(), rsa::Pss::new::<rsa::sha2::Sha256>(), data)
+            .ok()
+    }
+
+    fn sign_unique(sk: &Self::SigningKey, data: &[u8]) -> Option<Vec<u8>> {
+        sk.sign(rsa::Pkcs1v15Sign::new::<rsa::sha2::Sha256>(), data).ok()
+    }
+
+    fn verify(vk: &Self::VerifyingKey, data: &[u8], signature: &[u8]) -> bool {
+        vk.verify(rsa::Pss::new::<rsa::sha2::Sha256>(), data, signature)
+            .is_ok()
+    }
+
+    fn verify_unique(vk: &Self::VerifyingKey, data: &[u8], signature: &[u8]) -> bool {
+        vk.verify(rsa::Pkcs1v15Sign::new::<rsa::sha2::Sha256>(), data, signature)
+            .is_ok()
+    }
+
+    fn key_to_bytes(vk: &Self::VerifyingKey) -> Vec<u8> {
+        use rsa::pkcs1::EncodeRsaPublicKey;
+        vk.to_pkcs1_der().unwrap().as_bytes().to_vec()
+    }
+
+    fn key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey> {
+        use rsa::pkcs1::DecodeRsaPublicKey;
+        rsa::RsaPublicKey::from_pkcs1_der(bytes).ok()
+    }
+
+    fn key_to_pem(vk: &Self::VerifyingKey) -> Option<String> {
+        use rsa::pkcs8::{EncodePublicKey, LineEnding};
+        vk.to_public_key_pem(LineEnding::LF).ok()
+    }
+
+    fn key_from_pem(pem: &str) -> Option<Self::VerifyingKey> {
+        use rsa::pkcs8::DecodePublicKey;
+        rsa::RsaPublicKey::from_public_key_pem(pem).ok()
+    }
+
+    fn secret_to_pem(sk: &Self::SigningKey) -> Option<Zeroizing<String>> {
+        use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+        sk.to_pkcs8_pem(LineEnding::LF).ok()
+    }
+
+    fn secret_from_pem(pem: &str) -> Option<Self::SigningKey> {
+        use rsa::pkcs8::DecodePrivateKey;
+        rsa::RsaPrivateKey::from_pkcs8_pem(pem).ok()
+    }
+
+    fn secret_to_der(sk: &Self::SigningKey) -> Option<Zeroizing<Vec<u8>>> {
+        use rsa::pkcs8::EncodePrivateKey;
+        sk.to_pkcs8_der().ok().map(|der| der.to_bytes())
+    }
+
+    fn secret_from_der(der: &[u8]) -> Option<Self::SigningKey> {
+        use rsa::pkcs8::DecodePrivateKey;
+        rsa::RsaPrivateKey::from_pkcs8_der(der).ok()
+    }
+
+    fn key_to_spki(vk: &Self::VerifyingKey) -> Option<Vec<u8>> {
+        use rsa::pkcs8::EncodePublicKey;
+        vk.to_public_key_der().ok().map(|der| der.as_bytes().to_vec())
+    }
+
+    fn key_from_spki(der: &[u8]) -> Option<Self::VerifyingKey> {
+        use rsa::pkcs8::DecodePublicKey;
+        rsa::RsaPublicKey::from_public_key_der(der).ok()
+    }
+
+    fn key_to_pkcs1(vk: &Self::VerifyingKey) -> Option<Vec<u8>> {
+        Some(Self::key_to_bytes(vk))
+    }
+
+    fn key_from_pkcs1(der: &[u8]) -> Option<Self::VerifyingKey> {
+        Self::key_from_bytes(der)
+    }
+
+    fn secret_to_pkcs1(sk: &Self::SigningKey) -> Option<Zeroizing<Vec<u8>>> {
+        use rsa::pkcs1::EncodeRsaPrivateKey;
+        sk.to_pkcs1_der().ok().map(|der| der.to_bytes())
+    }
+
+    fn secret_from_pkcs1(der: &[u8]) -> Option<Self::SigningKey> {
+        use rsa::pkcs1::DecodeRsaPrivateKey;
+        rsa::RsaPrivateKey::from_pkcs1_der(der).ok()
+    }
+}
+
+/// Ed25519, its signatures are deterministic so draws use them as they are
+#[cfg(feature = "ed25519")]
+pub struct Ed25519;
+
+#[cfg(feature = "ed25519")]
+impl SignatureScheme for Ed25519 {
+    const NAME: &'static str = "ed25519";
+    type SigningKey = ed25519_dalek::SigningKey;
+    type VerifyingKey = ed25519_dalek::VerifyingKey;
+
+    fn generate<R: CryptoRng + RngCore>(_bits: usize, rng: &mut R) -> Option<Self::SigningKey> {
+        let mut seed = [0; 32];
+        rng.fill_bytes(&mut seed);
+        Some(ed25519_dalek::SigningKey::from_bytes(&seed))
+    }
+
+    fn from_seed(seed: [u8; 32]) -> Self::SigningKey {
+        ed25519_dalek::SigningKey::from_bytes(&seed)
+    }
+
+    fn verifying_key(sk: &Self::SigningKey) -> Self::VerifyingKey {
+        sk.verifying_key()
+    }
+
+    fn key_bits(_vk: &Self::VerifyingKey) -> Option<usize> {
+        None
+    }
+
+    fn sign(sk: &Self::SigningKey, data: &[u8]) -> Option<Vec<u8>> {
+        use ed25519_dalek::Signer;
+        Some(sk.sign(data).to_vec())
+    }
+
+    fn sign_unique(sk: &Self::SigningKey, data: &[u8]) -> Option<Vec<u8>> {
+        Self::sign(sk, data)
+    }
+
+    fn verify(vk: &Self::VerifyingKey, data: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature)
+            .is_ok_and(|signature| vk.verify_strict(data, &signature).is_ok())
+    }
+
+    fn verify_unique(vk: &Self::VerifyingKey, data: &[u8], signature: &[u8]) -> bool {
+        Self::verify(vk, data, signature)
+    }
+
+    fn key_to_bytes(vk: &Self::VerifyingKey) -> Vec<u8> {
+        vk.to_bytes().to_vec()
+    }
+
+    fn key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey> {
+        ed25519_dalek::VerifyingKey::from_bytes(bytes.try_into().ok()?).ok()
+    }
+
+    fn key_to_pem(vk: &Self::VerifyingKey) -> Option<String> {
+        use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePublicKey};
+        vk.to_public_key_pem(LineEnding::LF).ok()
+    }
+
+    fn key_from_pem(pem: &str) -> Option<Self::VerifyingKey> {
+        use ed25519_dalek::pkcs8::DecodePublicKey;
+        ed25519_dalek::VerifyingKey::from_public_key_pem(pem).ok()
+    }
+
+    fn secret_to_pem(sk: &Self::SigningKey) -> Option<Zeroizing<String>> {
+        use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey};
+        sk.to_pkcs8_pem(LineEnding::LF).ok()
+    }
+
+    fn secret_from_pem(pem: &str) -> Option<Self::SigningKey> {
+        use ed25519_dalek::pkcs8::DecodePrivateKey;
+        ed25519_dalek::SigningKey::from_pkcs8_pem(pem).ok()
+    }
+
+    fn secret_to_der(sk: &Self::SigningKey) -> Option<Zeroizing<Vec<u8>>> {
+        use ed25519_dalek::pkcs8::EncodePrivateKey;
+        sk.to_pkcs8_der().ok().map(|der| der.to_bytes())
+    }
+
+    fn secret_from_der(der: &[u8]) -> Option<Self::SigningKey> {
+        use ed25519_dalek::pkcs8::DecodePrivateKey;
+        ed25519_dalek::SigningKey::from_pkcs8_der(der).ok()
+    }
+
+    fn key_to_spki(vk: &Self::VerifyingKey) -> Option<Vec<u8>> {
+        use ed25519_dalek::pkcs8::EncodePublicKey;
+        vk.to_public_key_der().ok().map(|der| der.as_bytes().to_vec())
+    }
+
+    fn key_from_spki(der: &[u8]) -> Option<Self::VerifyingKey> {
+        use ed25519_dalek::pkcs8::DecodePublicKey;
+        ed25519_dalek::VerifyingKey::from_public_key_der(der).ok()
+    }
+}
+
+/// The scheme of this build, every node on a network must use the same
+#[cfg(not(feature = "ed25519"))]
+pub type Scheme = Rsa;
+#[cfg(feature = "ed25519")]
+pub type Scheme = Ed25519;
+
+/// A key knows its address, balances are looked up by it so often that it is only hashed once
+#[derive(Clone, Debug)]
+pub struct PublicKey(<Scheme as SignatureScheme>::VerifyingKey, AccountId);
+
+impl PublicKey {
+    fn new(key: <Scheme as SignatureScheme>::VerifyingKey) -> Self {
+        let id = AccountId(Sha256::digest(Scheme::key_to_bytes(&key)).into());
+        Self(key, id)
+    }
+
+    /// The address of the key, the SHA-256 of its encoding
+    pub fn id(&self) -> AccountId {
+        self.1
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        Scheme::key_to_bytes(&self.0)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Scheme::key_from_bytes(bytes)
+            .map(Self::new)
+            .ok_or(Error::InvalidEncoding)
+    }
+
+    pub fn to_pem(&self) -> Result<String> {
+        Scheme::key_to_pem(&self.0).ok_or(Error::InvalidPem)
+    }
+
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        Scheme::key_from_pem(pem).map(Self::new).ok_or(Error::InvalidPem)
+    }
+
+    /// The size of the key, `None` for a scheme whose keys all have the same size
+    pub fn bits(&self) -> Option<usize> {
+        Scheme::key_bits(&self.0)
+    }
+
+    /// Whether the key is at least `min_bits`, a key of a scheme with one size always is
+    pub fn has_bits(&self, min_bits: u64) -> bool {
+        self.bits().is_none_or(|bits| bits as u64 >= min_bits)
+    }
+}
+
+// two keys with the same encoding are the same key, comparing the hashes is cheaper than comparing the keys
+impl PartialEq for PublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.1 == other.1
+    }
+}
+
+impl Eq for PublicKey {}
+
+impl Hash for PublicKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.1.hash(state)
+    }
+}
+
+serde_via_bytes!(PublicKey);
+
+#[derive(Clone)]
+pub struct SecretKey(<Scheme as SignatureScheme>::SigningKey);
+
+impl SecretKey {
+    pub fn generate() -> Self {
+        Self::generate_with_bits(DEFAULT_KEY_BITS).expect("the default size is a valid size")
+    }
+
+    /// A key of `bits`, it must be at least `MIN_KEY_BITS`
+    pub fn generate_with_bits(bits: usize) -> Result<Self> {
+        Self::generate_with_rng(bits, &mut thread_rng())
+    }
+
+    pub(crate) fn generate_with_rng<R: CryptoRng + RngCore>(bits: usize, rng: &mut R) -> Result<Self> {
+        if bits < MIN_KEY_BITS {
+            return Err(Error::KeyTooSmall(bits));
+        }
+        Scheme::generate(bits, rng).map(Self).ok_or(Error::KeyTooSmall(bits))
+    }
+
+    /// Like `generate_with_bits`, with `progress` called with the number of attempts so far
+    pub fn generate_with_progress(bits: usize, progress: impl FnMut(u64)) -> Result<Self> {
+        let mut rng = CountingRng {
+            rng: thread_rng(),
+            draws: 0,
+            progress,
+        };
+        Self::generate_with_rng(bits, &mut rng)
+    }
+
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self(Scheme::from_seed(seed))
+    }
+
+    pub fn to_public_key(&self) -> PublicKey {
+        PublicKey::new(Scheme::verifying_key(&self.0))
+    }
+
+    pub fn to_pem(&self) -> Result<Zeroizing<String>> {
+        Scheme::secret_to_pem(&self.0).ok_or(Error::InvalidPem)
+    }
+
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        Scheme::secret_from_pem(pem).map(Self).ok_or(Error::InvalidPem)
+    }
+
+    pub fn to_der(&self) -> Result<Zeroizing<Vec<u8>>> {
+        Scheme::secret_to_der(&self.0).ok_or(Error::InvalidEncoding)
+    }
+
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        Scheme::secret_from_der(der).map(Self).ok_or(Error::InvalidEncoding)
+    }
+}
+
+// passes the randomness through and counts the draws of bytes, an RSA key draws once for each candidate prime
+struct CountingRng<R, F> {
+    rng: R,
+    draws: u64,
+    progress: F,
+}
+
+impl<R: RngCore, F: FnMut(u64)> RngCore for CountingRng<R, F> {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.draws += 1;
+        (self.progress)(self.draws);
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<R: CryptoRng, F> CryptoRng for CountingRng<R, F> {}
+
+// the secret itself is never printed
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey({:?})", self.to_public_key())
+    }
+}
+
+/// The encodings of a key file, openssl writes all four
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    Pkcs8Pem, // a public key is a SubjectPublicKeyInfo, as `openssl pkey -pubout` writes it
+    Pkcs8Der,
+    Pkcs1Pem, // `-traditional` or `-RSAPublicKey_out`, only RSA keys have it
+    Pkcs1Der,
+}
+
+impl KeyFormat {
+    pub fn is_pem(self) -> bool {
+        matches!(self, KeyFormat::Pkcs8Pem | KeyFormat::Pkcs1Pem)
+    }
+
+    pub fn is_pkcs1(self) -> bool {
+        matches!(self, KeyFormat::Pkcs1Pem | KeyFormat::Pkcs1Der)
+    }
+
+    // the label of a pem in the format
+    fn label(self, private: bool) -> &'static str {
+        match (self.is_pkcs1(), private) {
+            (false, false) => "PUBLIC KEY",
+            (false, true) => "PRIVATE KEY",
+            (true, false) => "RSA PUBLIC KEY",
+            (true, true) => "RSA PRIVATE KEY",
+        }
+    }
+
+    // what the format is called for a public or a private key
+    fn name(self, private: bool) -> &'static str {
+        match (self, private) {
+            (KeyFormat::Pkcs8Pem, false) => "SPKI PEM",
+            (KeyFormat::Pkcs8Der, false) => "SPKI DER",
+            (KeyFormat::Pkcs8Pem, true) => "PKCS#8 PEM",
+            (KeyFormat::Pkcs8Der, true) => "PKCS#8 DER",
+            (KeyFormat::Pkcs1Pem, _) => "PKCS#1 PEM",
+            (KeyFormat::Pkcs1Der, _) => "PKCS#1 DER",
+        }
+    }
+}
+
+impl FromStr for KeyFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pkcs8-pem" => Ok(KeyFormat::Pkcs8Pem),
+            "pkcs8-der" => Ok(KeyFormat::Pkcs8Der),
+            "pkcs1-pem" => Ok(KeyFormat::Pkcs1Pem),
+            "pkcs1-der" => Ok(KeyFormat::Pkcs1Der),
+            _ => Err(Error::InvalidEncoding),
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyFormat::Pkcs8Pem => write!(f, "pkcs8-pem"),
+            KeyFormat::Pkcs8Der => write!(f, "pkcs8-der"),
+            KeyFormat::Pkcs1Pem => write!(f, "pkcs1-pem"),
+            KeyFormat::Pkcs1Der => write!(f, "pkcs1-der"),
+        }
+    }
+}
+
+/// A key that is in none of the formats that were tried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyImportError {
+    pub private: bool,
+    pub attempted: Vec<KeyFormat>,
+}
+
+impl fmt::Display for KeyImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.private { "private" } else { "public" };
+        let names: Vec<_> = self.attempted.iter().map(|format| format.name(self.private)).collect();
+        write!(f, "not a {kind} key in {}", names.join(" or "))
+    }
+}
+
+impl std::error::Error for KeyImportError {}
+
+/// Reads a public key in any of the `KeyFormat`s, PEM is told from DER by the bytes and PKCS#1 from PKCS#8
+/// by the label of a pem or by trying both on DER
+pub fn import_public(bytes: &[u8]) -> Result<PublicKey> {
+    import(bytes, false, |der, format| match format.is_pkcs1() {
+        true => Scheme::key_from_pkcs1(der),
+        false => Scheme::key_from_spki(der),
+    })
+    .map(PublicKey::new)
+}
+
+/// Reads a private key in any of the `KeyFormat`s, it must not be encrypted
+pub fn import_private(bytes: &[u8]) -> Result<SecretKey> {
+    import(bytes, true, |der, format| match format.is_pkcs1() {
+        true => Scheme::secret_from_pkcs1(der),
+        false => Scheme::secret_from_der(der),
+    })
+    .map(SecretKey)
+}
+
+fn import<K>(bytes: &[u8], private: bool, read: impl Fn(&[u8], KeyFormat) -> Option<K>) -> Result<K> {
+    let bytes = bytes.trim_ascii_start();
+    let attempted = match bytes.starts_with(b"-----BEGIN ") {
+        true => [KeyFormat::Pkcs8Pem, KeyFormat::Pkcs1Pem],
+        false => [KeyFormat::Pkcs8Der, KeyFormat::Pkcs1Der],
+    };
+    for format in attempted {
+        let key = match format.is_pem() {
+            true => pem::decode_vec(bytes)
+                .ok()
+                .filter(|(label, _)| *label == format.label(private))
+                .and_then(|(_, der)| read(&Zeroizing::new(der), format)),
+            false => read(bytes, format),
+        };
+        if let Some(key) = key {
+            return Ok(key);
+        }
+    }
+    Err(Error::KeyImport(KeyImportError {
+        private,
+        attempted: attempted.to_vec(),
+    }))
+}
+
+/// The public key in the format, an Ed25519 key has no PKCS#1 encoding
+pub fn export_public(pk: &PublicKey, format: KeyFormat) -> Result<Vec<u8>> {
+    let der = match format.is_pkcs1() {
+        true => Scheme::key_to_pkcs1(&pk.0),
+        false => Scheme::key_to_spki(&pk.0),
+    }
+    .ok_or(Error::InvalidEncoding)?;
+    if !format.is_pem() {
+        return Ok(der);
+    }
+    pem::encode_string(format.label(false), LineEnding::LF, &der)
+        .map(String::into_bytes)
+        .map_err(|_| Error::InvalidEncoding)
+}
+
+/// The private key in the format, unencrypted
+pub fn export_private(sk: &SecretKey, format: KeyFormat) -> Result<Zeroizing<Vec<u8>>> {
+    let der = match format.is_pkcs1() {
+        true => Scheme::secret_to_pkcs1(&sk.0),
+        false => Scheme::secret_to_der(&sk.0),
+    }
+    .ok_or(Error::InvalidEncoding)?;
+    if !format.is_pem() {
+        return Ok(der);
+    }
+    pem::encode_string(format.label(true), LineEnding::LF, &der)
+        .map(|pem| Zeroizing::new(pem.into_bytes()))
+        .map_err(|_| Error::InvalidEncoding)
+}
+
+/// A signature of a block or a transaction
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Signature(pub(crate) Vec<u8>);
+
+impl Signature {
+    pub fn sign(sk: &SecretKey, hashed_data: &[u8]) -> Result<Signature> {
+        Scheme::sign(&sk.0, hashed_data)
+            .map(Signature)
+            .ok_or(Error::SignatureError)
+    }
+
+    pub fn verify(&self, vk: &PublicKey, hashed_data: &[u8]) -> Result<()> {
+        Scheme::verify(&vk.0, hashed_data, &self.0)
+            .then_some(())
+            .ok_or(Error::SignatureError)
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A signature that is the only valid one of the key for the data, so a draw can not be ground
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct UniqueSignature(pub(crate) Vec<u8>);
+
+impl UniqueSignature {
+    pub fn sign(sk: &SecretKey, hashed_data: &[u8]) -> Result<UniqueSignature> {
+        Scheme::sign_unique(&sk.0, hashed_data)
+            .map(UniqueSignature)
+            .ok_or(Error::SignatureError)
+    }
+
+    pub fn verify(&self, vk: &PublicKey, hashed_data: &[u8]) -> Result<()> {
+        Scheme::verify_unique(&vk.0, hashed_data, &self.0)
+            .then_some(())
+            .ok_or(Error::SignatureError)
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_and_signatures_round_trip() {
+        let sk = SecretKey::generate();
+        let pk = sk.to_public_key();
+        assert_eq!(PublicKey::from_bytes(&pk.to_bytes()), Ok(pk.clone()));
+        assert_eq!(PublicKey::from_pem(&pk.to_pem().unwrap()), Ok(pk.clone()));
+        let read = SecretKey::from_pem(&sk.to_pem().unwrap()).unwrap();
+        assert_eq!(read.to_public_key(), pk);
+        assert_eq!(SecretKey::from_seed([7; 32]).to_public_key(), SecretKey::from_seed([7; 32]).to_public_key());
+
+        let data = [1; 32];
+        let signature = Signature::sign(&sk, &data).unwrap();
+        assert_eq!(signature.verify(&pk, &data), Ok(()));
+        assert_eq!(signature.verify(&pk, &[2; 32]), Err(Error::SignatureError));
+        let other = SecretKey::generate().to_public_key();
+        assert_eq!(signature.verify(&other, &data), Err(Error::SignatureError));
+
+        let unique = UniqueSignature::sign(&sk, &data).unwrap();
+        assert_eq!(unique.to_bytes(), UniqueSignature::sign(&sk, &data).unwrap().to_bytes());
+        assert_eq!(unique.verify(&pk, &data), Ok(()));
+        assert_eq!(unique.verify(&pk, &[2; 32]), Err(Error::SignatureError));
+    }
+
+    #[tokio::test]
+    async fn keys_are_made_in_the_size_asked_for_with_progress() {
+        assert_eq!(SecretKey::generate_with_bits(MIN_KEY_BITS - 1).unwrap_err(), Error::KeyTooSmall(1023));
+
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let reported = attempts.clone();
+        let (sk, pk) = crate::generate_keypair_with_progress(MIN_KEY_BITS, move |attempt| {
+            reported.store(attempt, std::sync::atomic::Ordering::Relaxed)
+        })
+        .await
+        .unwrap();
+        assert_eq!(sk.to_public_key(), pk);
+        assert!(attempts.load(std::sync::atomic::Ordering::Relaxed) > 0);
+        #[cfg(not(feature = "ed25519"))]
+        {
+            assert_eq!(pk.bits(), Some(MIN_KEY_BITS));
+            assert!(pk.has_bits(1024) && !pk.has_bits(2048));
+        }
+    }
+
+    #[cfg(not(feature = "ed25519"))]
+    const FORMATS: [KeyFormat; 4] = [KeyFormat::Pkcs8Pem, KeyFormat::Pkcs8Der, KeyFormat::Pkcs1Pem, KeyFormat::Pkcs1Der];
+
+    // written by openssl from one key with `genpkey`, `pkcs8 -topk8`, `rsa -traditional`, `rsa -pubout`
+    // and `rsa -RSAPublicKey_out`, the DER ones with `-outform DER`
+    #[cfg(not(feature = "ed25519"))]
+    #[test]
+    fn keys_made_by_openssl_import_in_every_format() {
+        let public: [&[u8]; 4] = [
+            include_bytes!("../tests/fixtures/keys/public.spki.pem"),
+            include_bytes!("../tests/fixtures/keys/public.spki.der"),
+            include_bytes!("../tests/fixtures/keys/public.pkcs1.pem"),
+            include_bytes!("../tests/fixtures/keys/public.pkcs1.der"),
+        ];
+        let private: [&[u8]; 4] = [
+            include_bytes!("../tests/fixtures/keys/private.pkcs8.pem"),
+            include_bytes!("../tests/fixtures/keys/private.pkcs8.der"),
+            include_bytes!("../tests/fixtures/keys/private.pkcs1.pem"),
+            include_bytes!("../tests/fixtures/keys/private.pkcs1.der"),
+        ];
+        let pk = import_public(public[0]).unwrap();
+        for (format, (public, private)) in FORMATS.into_iter().zip(public.into_iter().zip(private)) {
+            assert_eq!(import_public(public), Ok(pk.clone()), "{format}");
+            assert_eq!(import_private(private).unwrap().to_public_key(), pk, "{format}");
+            // a public key has one encoding in each format, so what we export is what openssl wrote
+            assert_eq!(export_public(&pk, format).unwrap(), public, "{format}");
+        }
+    }
+
+    #[cfg(not(feature = "ed25519"))]
+    #[test]
+    fn an_unreadable_key_names_the_formats_that_were_tried() {
+        let sk = SecretKey::generate();
+        for format in FORMATS {
+            let exported = export_private(&sk, format).unwrap();
+            assert_eq!(import_private(&exported).unwrap().to_public_key(), sk.to_public_key());
+        }
+
+        let private_pem = export_private(&sk, KeyFormat::Pkcs1Pem).unwrap();
+        let e = import_public(&private_pem).unwrap_err();
+        assert_eq!(
+            e,
+            Error::KeyImport(KeyImportError {
+                private: false,
+                attempted: vec![KeyFormat::Pkcs8Pem, KeyFormat::Pkcs1Pem],
+            })
+        );
+        assert_eq!(e.to_string(), "Unable to import the key, not a public key in SPKI PEM or PKCS#1 PEM");
+        let e = import_private(&[0x30, 0x03, 0x02, 0x01, 0x00]).unwrap_err();
+        assert_eq!(e.to_string(), "Unable to import the key, not a private key in PKCS#8 DER or PKCS#1 DER");
+    }
+}
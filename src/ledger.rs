@@ -1,19 +1,97 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use rsa::RsaPublicKey;
+use rsa::sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::{transaction::Transaction, BLOCK_REWARD, TRANSACTION_FEE};
+use crate::{
+    account::AccountId,
+    blockchain::BlockchainError,
+    encoding::{serde_via_bytes, text_via_bytes, Reader, Writer},
+    merkle::{self, MerkleProof},
+    params::FeeSink,
+    signatures::SIGNATURES,
+    transaction::{Transaction, TxId},
+    Result, Timeslot, REWARD_MATURITY,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Ledger {
-    pub(super) map: HashMap<RsaPublicKey, u64>,
-    pub(super) previous_transactions: HashSet<[u8; 32]>,
-    pub(super) published_accounts: HashMap<RsaPublicKey, u64>, // maps to depth of publish
+    pub(super) map: HashMap<AccountId, u64>,
+    pub(super) applied_transactions: HashSet<TxId>, // a transaction is only applied once
+    pub(super) nonces: HashMap<AccountId, u64>, // the nonce of the next transaction of each sender, senders start at 0
+    pub(super) published_accounts: HashMap<AccountId, u64>, // maps to depth of publish
+    pub(super) rewards: HashMap<AccountId, BTreeMap<u64, u64>>, // maps to the rewards received at each depth
+    pub(super) transaction_fee: u64, // the lowest fee a transaction can pay
+    pub(super) fee_sink: FeeSink, // where the fees of a block go
+    pub(super) total_money: u64, // the sum of the balances
+    pub(super) minted: u64, // every reward including the root allocations
+    pub(super) rewarded_blocks: u64, // number of rewards given at a depth above 0
+    pub(super) fees_paid: u64, // by every applied transaction, wherever the fees went
+    pub(super) total_burned: u64, // fees taken from the sender and given to nobody
+    pub(super) unclaimed_fees: u64, // paid in the block being applied, its winner gets them with the reward
+}
+
+/// An invariant of the ledger that does not hold, see `Ledger::audit`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerViolation {
+    /// Empty accounts should have been removed
+    ZeroBalance(AccountId),
+    /// The maintained total is not the sum of the balances
+    CachedTotalMismatch { cached: u64, actual: u64 },
+    /// The balances do not add up to what has been minted minus what has been burned
+    SupplyMismatch { expected: u64, actual: u64 },
+    /// More or less has been minted than the root allocations and block rewards
+    UnexpectedMinting { expected: u64, minted: u64 },
+}
+
+/// Why a transaction is refused, see `Ledger::validate_transaction`. It is serialized with the reason as a tag
+/// so the wallets that submit through JSON-RPC can tell the user what to change
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum TxValidationError {
+    #[error("the signature is not made with the key of the sender")]
+    BadSignature,
+    #[error("the transaction pays no receivers")]
+    NoReceivers,
+    #[error("the sender can not pay itself")]
+    SelfTransfer,
+    #[error("every receiver must be paid at least {minimum}")]
+    OutputTooSmall { minimum: u64 },
+    #[error("the fee of {fee} is below the minimum fee of {minimum}")]
+    FeeTooLow { fee: u64, minimum: u64 },
+    #[error("the amounts and the fee add up to more than a balance can hold")]
+    Overflow,
+    #[error("the sender has no balance")]
+    UnknownSender,
+    #[error("the sender can spend {spendable} but the transaction costs {cost}")]
+    InsufficientFunds { spendable: u64, cost: u64 },
+    #[error("the transaction has already been applied")]
+    AlreadyApplied,
+    #[error("the sender has already used nonce {got}, its next nonce is {expected}")]
+    NonceTooLow { expected: u64, got: u64 },
+    #[error("the next nonce of the sender is {expected}, not {got}")]
+    NonceGap { expected: u64, got: u64 },
+    #[error("the transaction was valid until timeslot {valid_until} and it is timeslot {timeslot}")]
+    Expired { valid_until: Timeslot, timeslot: Timeslot },
+    #[error("the key of the sender has {bits} bits, the chain only allows keys of {minimum} bits and more")]
+    KeyTooSmall { bits: u64, minimum: u64 },
+}
+
+impl<T> From<TxValidationError> for Result<T> {
+    fn from(value: TxValidationError) -> Self {
+        Err(value.into())
+    }
+}
+
+impl From<TxValidationError> for crate::Error {
+    fn from(value: TxValidationError) -> Self {
+        BlockchainError::RejectedTransaction(value).into()
+    }
 }
 
 impl Ledger {
-    pub fn new(root_accounts: Vec<RsaPublicKey>) -> Self {
+    pub fn new(root_accounts: Vec<AccountId>, transaction_fee: u64) -> Self {
         let mut published_accounts = HashMap::new();
         for acc in root_accounts {
             published_accounts.insert(acc, 0);
@@ -21,127 +99,942 @@ impl Ledger {
 
         Self {
             map: HashMap::new(),
-            previous_transactions: HashSet::new(),
+            applied_transactions: HashSet::new(),
+            nonces: HashMap::new(),
             published_accounts,
+            rewards: HashMap::new(),
+            transaction_fee,
+            fee_sink: FeeSink::default(),
+            total_money: 0,
+            minted: 0,
+            rewarded_blocks: 0,
+            fees_paid: 0,
+            total_burned: 0,
+            unclaimed_fees: 0,
         }
     }
 
-    pub fn add_acount_if_absent(&mut self, account: &RsaPublicKey) {
+    pub fn with_fee_sink(mut self, fee_sink: FeeSink) -> Self {
+        self.fee_sink = fee_sink;
+        self
+    }
+
+    pub fn add_acount_if_absent(&mut self, account: &AccountId) {
         if !self.map.contains_key(account) {
-            self.map.insert(account.clone(), 0);
+            self.map.insert(*account, 0);
         }
     }
 
-    pub fn get_balance(&self, account: &RsaPublicKey) -> u64 {
+    pub fn get_balance(&self, account: &AccountId) -> u64 {
         *self.map.get(account).unwrap_or(&0)
     }
 
-    pub fn reward_winner(&mut self, winner: &RsaPublicKey, amount: u64) {
-        self.add_acount_if_absent(winner);
-        let balance = self.map.get_mut(winner).unwrap();
-        *balance += amount;
+    /// The nonce the next transaction of the account must have, the number of its transactions so far
+    pub fn next_nonce(&self, account: &AccountId) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+
+    // empty accounts are removed, so ledgers with the same balances are equal no matter how they got there
+    fn set_balance(&mut self, account: &AccountId, balance: u64) {
+        if balance == 0 {
+            self.map.remove(account);
+        } else {
+            self.map.insert(*account, balance);
+        }
+    }
+
+    /// The part of the balance that can be spent in a block at the given depth.
+    /// Rewards received at depth D can first be spent at depth D + REWARD_MATURITY
+    pub fn get_spendable_balance(&self, account: &AccountId, depth: u64) -> u64 {
+        let immature: u64 = self
+            .rewards
+            .get(account)
+            .map(|rewards| {
+                rewards
+                    .range((depth + 1).saturating_sub(REWARD_MATURITY)..)
+                    .map(|(_, amount)| amount)
+                    .sum()
+            })
+            .unwrap_or(0);
+        self.get_balance(account).saturating_sub(immature)
+    }
+
+    /// Rewards at depth 0 are the root allocations, these are spendable immediately.
+    /// The winner also gets the fees of the transactions applied since the last reward if they go to the proposer,
+    /// they mature with the reward
+    pub fn reward_winner(&mut self, winner: &AccountId, amount: u64, depth: u64) -> Result<()> {
+        let Some(received) = amount.checked_add(self.unclaimed_fees) else {
+            return BlockchainError::BalanceOverflow.into();
+        };
+        let Some(new_balance) = self.get_balance(winner).checked_add(received) else {
+            return BlockchainError::BalanceOverflow.into();
+        };
+        let (Some(total_money), Some(minted)) = (
+            self.total_money.checked_add(received),
+            self.minted.checked_add(amount),
+        ) else {
+            return BlockchainError::BalanceOverflow.into();
+        };
+        self.set_balance(winner, new_balance);
+        self.total_money = total_money;
+        self.minted = minted;
+        self.unclaimed_fees = 0;
+        if depth > 0 {
+            self.rewarded_blocks += 1;
+            *self
+                .rewards
+                .entry(*winner)
+                .or_default()
+                .entry(depth)
+                .or_default() += received;
+        }
+        Ok(())
     }
 
-    /// Panics if the transaction has been added previously
+    /// Returns false without changing the ledger if the transaction is invalid or has been added previously
     /// depth is the depth of the block where this happens
     pub fn process_transaction(&mut self, transaction: &Transaction, depth: u64) -> bool {
-        if !transaction.verify_signature() {
+        if self.validate_transaction(transaction, depth).is_err() {
             return false;
-        };
-        if transaction.amount < TRANSACTION_FEE {
+        }
+        let from = &transaction.from_id();
+        let cost = transaction.cost().expect("the transaction is valid");
+        let Some(new_from_balance) = self.get_balance(from).checked_sub(cost) else {
             return false;
         };
-        let from: &RsaPublicKey = &transaction.from;
-        let to: &RsaPublicKey = &transaction.to;
-        let amount = transaction.amount;
-        self.add_acount_if_absent(from);
-        self.add_acount_if_absent(to);
-
-        let from_balance = self.map.get_mut(from).unwrap();
-
-        if *from_balance < amount + TRANSACTION_FEE {
-            return false;
+        // the balances after the transaction, a receiver might be paid by several outputs
+        let mut balances = HashMap::from([(*from, new_from_balance)]);
+        for (to, amount) in transaction.outputs.iter() {
+            let to = AccountId::from(to);
+            let balance = balances.get(&to).copied().unwrap_or_else(|| self.get_balance(&to));
+            let Some(balance) = balance.checked_add(*amount) else {
+                return false;
+            };
+            balances.insert(to, balance);
         }
 
-        if !self.previous_transactions.insert(transaction.hash) {
+        if !self.applied_transactions.insert(transaction.id()) {
             return false;
         }
+        self.nonces.insert(*from, transaction.nonce + 1);
 
-        if amount < 1 {
-            return false; 
+        for (account, balance) in balances {
+            self.set_balance(&account, balance);
+        }
+        self.total_money -= transaction.fee;
+        self.fees_paid += transaction.fee;
+        match self.fee_sink {
+            FeeSink::Burn => self.total_burned += transaction.fee,
+            FeeSink::ToProposer => self.unclaimed_fees += transaction.fee,
         }
 
-        *from_balance -= amount + TRANSACTION_FEE;
-        let to_balance = self.map.get_mut(to).unwrap();
-
-        *to_balance += amount;
-
-        // check if this is an account publication
-        if self.published_accounts.contains_key(to) {
-            self.published_accounts.insert(to.clone(), depth);
+        // check if this is an account publication, an account is published by the first payment to it
+        for (to, _) in transaction.outputs.iter() {
+            self.published_accounts.entry(AccountId::from(to)).or_insert(depth);
         }
 
         true
     }
 
+    /// Whether the transaction has been applied and not rolled back
+    pub fn is_applied(&self, id: &TxId) -> bool {
+        self.applied_transactions.contains(id)
+    }
+
     /// Reverse the transaction
-    /// panics if the transaction was not performed
-    pub fn rollback_transaction(&mut self, transaction: &Transaction, depth: u64) {
-        let from: &RsaPublicKey = &transaction.from;
-        let to: &RsaPublicKey = &transaction.to;
-        let amount = transaction.amount;
+    /// fails without changing the ledger if the transaction was not performed
+    pub fn rollback_transaction(&mut self, transaction: &Transaction, depth: u64) -> Result<()> {
+        let from = &transaction.from_id();
 
-        assert!(self.previous_transactions.remove(&transaction.hash));
+        // the transactions of a sender are rolled back from its last one
+        if !self.applied_transactions.contains(&transaction.id())
+            || transaction.nonce.checked_add(1) != Some(self.next_nonce(from))
+        {
+            return BlockchainError::UnknownTransaction.into();
+        }
+        // the fee is taken back from the winner when its reward is rolled back
+        let fees = match self.fee_sink {
+            FeeSink::Burn => self.total_burned,
+            FeeSink::ToProposer => self.unclaimed_fees,
+        };
+        let Some(fees) = fees.checked_sub(transaction.fee) else {
+            return BlockchainError::BalanceUnderflow.into();
+        };
 
-        let from_balance = self.map.get_mut(from).unwrap();
+        // the balances before the transaction, a receiver might have been paid by several outputs
+        let mut balances = HashMap::new();
+        for (to, amount) in transaction.outputs.iter() {
+            let to = AccountId::from(to);
+            let balance = balances.get(&to).copied().unwrap_or_else(|| self.get_balance(&to));
+            let Some(balance) = balance.checked_sub(*amount) else {
+                return BlockchainError::BalanceUnderflow.into();
+            };
+            balances.insert(to, balance);
+        }
+        let from_balance = balances.get(from).copied().unwrap_or_else(|| self.get_balance(from));
+        let Some(new_from_balance) = transaction
+            .cost()
+            .and_then(|cost| from_balance.checked_add(cost))
+        else {
+            return BlockchainError::BalanceOverflow.into();
+        };
+        balances.insert(*from, new_from_balance);
 
-        *from_balance += amount + TRANSACTION_FEE;
-        let to_balance = self.map.get_mut(to).unwrap();
-        *to_balance -= amount;
+        self.applied_transactions.remove(&transaction.id());
+        match transaction.nonce {
+            0 => self.nonces.remove(from),
+            nonce => self.nonces.insert(*from, nonce),
+        };
+        for (account, balance) in balances {
+            self.set_balance(&account, balance);
+        }
+        self.total_money += transaction.fee;
+        self.fees_paid -= transaction.fee;
+        match self.fee_sink {
+            FeeSink::Burn => self.total_burned = fees,
+            FeeSink::ToProposer => self.unclaimed_fees = fees,
+        }
 
         // check if this transaction is an account publication
         // if it is we remove it from the publications
-        if let Some(published_at) = self.published_accounts.get(to) {
-            let published_at = *published_at;
-            if published_at == depth {
-                self.published_accounts.remove(to);
+        for (to, _) in transaction.outputs.iter() {
+            let to = AccountId::from(to);
+            if self.published_accounts.get(&to) == Some(&depth) {
+                self.published_accounts.remove(&to);
             }
         }
+
+        Ok(())
+    }
+
+    /// What can be checked without the balance of the sender: the signature, the outputs and the fee.
+    /// Every output pays at least the minimum fee, so outputs can not be used to spam dust, and nothing is paid
+    /// to the sender
+    pub fn validate_form(&self, transaction: &Transaction) -> std::result::Result<(), TxValidationError> {
+        if SIGNATURES.verify(transaction).is_err() {
+            return Err(TxValidationError::BadSignature);
+        }
+        if transaction.outputs.is_empty() {
+            return Err(TxValidationError::NoReceivers);
+        }
+        let sender = transaction.from_id();
+        if transaction.outputs.iter().any(|(to, _)| AccountId::from(to) == sender) {
+            return Err(TxValidationError::SelfTransfer);
+        }
+        let minimum = self.transaction_fee.max(1);
+        if transaction.outputs.iter().any(|(_, amount)| *amount < minimum) {
+            return Err(TxValidationError::OutputTooSmall { minimum });
+        }
+        if transaction.fee < self.transaction_fee {
+            return Err(TxValidationError::FeeTooLow {
+                fee: transaction.fee,
+                minimum: self.transaction_fee,
+            });
+        }
+        if transaction.cost().is_none() {
+            return Err(TxValidationError::Overflow);
+        }
+        Ok(())
     }
 
-    /// TODO maintain this in a variable instead
-    pub fn get_total_money_in_ledger(&self) -> u64 {
-        self.map.values().sum()
+    /// The sum of the balances, the stake of an account in the lottery is its share of it.
+    /// Burned fees are not in it, and neither are the fees of a block until its winner is rewarded
+    pub fn circulating_supply(&self) -> u64 {
+        self.total_money
     }
 
-    pub(super) fn rollback_reward(&mut self, winner: &RsaPublicKey) {
-        self.add_acount_if_absent(winner);
-        let balance = self.map.get_mut(winner).unwrap();
-        *balance -= BLOCK_REWARD;
+    /// Everything that has been minted, the root allocations and the block rewards
+    pub fn minted(&self) -> u64 {
+        self.minted
     }
-    
-    pub(crate) fn is_transaction_possible(&self, transaction: &Transaction) -> bool {
-        if !transaction.verify_signature() {
-            return false;
+
+    /// The number of block rewards that have been given
+    pub fn rewarded_blocks(&self) -> u64 {
+        self.rewarded_blocks
+    }
+
+    /// The fees paid by the applied transactions, burned or given to the winners
+    pub fn fees_paid(&self) -> u64 {
+        self.fees_paid
+    }
+
+    /// The fees that left the supply, always zero when they go to the proposer
+    pub fn total_burned(&self) -> u64 {
+        self.total_burned
+    }
+
+    /// Checks the invariants that every change to the ledger keeps, an empty result means the ledger is consistent
+    pub fn audit(&self) -> Vec<LedgerViolation> {
+        let mut violations: Vec<_> = self
+            .map
+            .iter()
+            .filter(|(_, balance)| **balance == 0)
+            .map(|(account, _)| LedgerViolation::ZeroBalance(*account))
+            .collect();
+
+        // summed wider so a corrupted map can not overflow
+        let actual = self.map.values().map(|b| *b as u128).sum::<u128>();
+        let actual = u64::try_from(actual).unwrap_or(u64::MAX);
+        if actual != self.total_money {
+            violations.push(LedgerViolation::CachedTotalMismatch {
+                cached: self.total_money,
+                actual,
+            });
+        }
+        let expected = self
+            .minted
+            .saturating_sub(self.total_burned)
+            .saturating_sub(self.unclaimed_fees);
+        if actual != expected {
+            violations.push(LedgerViolation::SupplyMismatch { expected, actual });
+        }
+        violations
+    }
+
+    /// Commits to everything that decides what can happen next, blocks commit to the root after them.
+    /// Ledgers with the same contents have the same root no matter the order they were built in.
+    /// The balances are the leaves of a merkle tree so a single balance can be proven, see `prove_balance`
+    pub fn state_root(&self) -> [u8; 32] {
+        let leaves = balance_leaves(&self.sorted_balances());
+        state_root_of(&merkle::root(&leaves), leaves.len() as u64, &self.rest_root())
+    }
+
+    /// Proves the balance of the account against the state root, an account without a balance is proven by the
+    /// balances on either side of where it would be. The same ledger always gives the same proof
+    pub fn prove_balance(&self, account: &AccountId) -> BalanceProof {
+        let balances = self.sorted_balances();
+        let leaves = balance_leaves(&balances);
+        let entry = |index: usize| BalanceEntry {
+            account: balances[index].0,
+            balance: balances[index].1,
+            path: MerkleProof::new(&leaves, index).expect("the index is in range"),
         };
-        if transaction.amount < TRANSACTION_FEE {
-            return false;
+        let entries = match balances.binary_search_by_key(account, |(account, _)| *account) {
+            Ok(index) => vec![entry(index)],
+            Err(index) => {
+                let below = index.checked_sub(1);
+                let above = (index < balances.len()).then_some(index);
+                below.into_iter().chain(above).map(entry).collect()
+            }
         };
-        let from: &RsaPublicKey = &transaction.from;
-        let amount = transaction.amount;
+        BalanceProof {
+            account: *account,
+            entries,
+            rest: self.rest_root(),
+        }
+    }
+
+    fn sorted_balances(&self) -> Vec<(AccountId, u64)> {
+        let mut balances: Vec<_> = self.map.iter().map(|(account, balance)| (*account, *balance)).collect();
+        balances.sort();
+        balances
+    }
+
+    // what the state root commits to besides the balances
+    fn rest_root(&self) -> [u8; 32] {
+        let mut writer = Writer::new();
+        let mut applied_transactions: Vec<_> = self.applied_transactions.iter().collect();
+        applied_transactions.sort();
+        writer.len(applied_transactions.len());
+        for id in applied_transactions {
+            writer.hash(&id.0);
+        }
+        let mut nonces: Vec<_> = self.nonces.iter().collect();
+        nonces.sort();
+        writer.len(nonces.len());
+        for (account, nonce) in nonces {
+            writer.hash(account.as_bytes()).u64(*nonce);
+        }
+        let mut published_accounts: Vec<_> = self.published_accounts.iter().collect();
+        published_accounts.sort();
+        writer.len(published_accounts.len());
+        for (account, depth) in published_accounts {
+            writer.hash(account.as_bytes()).u64(*depth);
+        }
+        let mut rewards: Vec<_> = self.rewards.iter().collect();
+        rewards.sort_by_key(|(account, _)| **account);
+        writer.len(rewards.len());
+        for (account, rewards) in rewards {
+            writer.hash(account.as_bytes()).len(rewards.len());
+            for (depth, amount) in rewards {
+                writer.u64(*depth).u64(*amount);
+            }
+        }
+        writer
+            .u64(self.transaction_fee)
+            .u64(self.fee_sink as u64)
+            .u64(self.total_money)
+            .u64(self.minted)
+            .u64(self.rewarded_blocks)
+            .u64(self.fees_paid)
+            .u64(self.total_burned)
+            .u64(self.unclaimed_fees);
+        Sha256::digest(writer.finish()).into()
+    }
 
-        let Some(from_balance) = self.map.get(from) else {
-            return false; // if the account does not exist it can't have enough money to pay the fee
+    /// depth is the depth of the block that gave the reward, the fees it gave with the reward are unclaimed again
+    /// fails without changing the ledger if the winner does not have the reward
+    pub(super) fn rollback_reward(
+        &mut self,
+        winner: &AccountId,
+        amount: u64,
+        depth: u64,
+    ) -> Result<()> {
+        let received = match depth {
+            0 => amount,
+            _ => self
+                .rewards
+                .get(winner)
+                .and_then(|rewards| rewards.get(&depth))
+                .copied()
+                .unwrap_or(amount),
+        };
+        let (Some(fees), Some(new_balance)) = (
+            received.checked_sub(amount),
+            self.get_balance(winner).checked_sub(received),
+        ) else {
+            return BlockchainError::BalanceUnderflow.into();
+        };
+        let rewarded_blocks = match depth {
+            0 => Some(self.rewarded_blocks),
+            _ => self.rewarded_blocks.checked_sub(1),
         };
+        let (Some(minted), Some(rewarded_blocks)) = (self.minted.checked_sub(amount), rewarded_blocks)
+        else {
+            return BlockchainError::BalanceUnderflow.into();
+        };
+        self.set_balance(winner, new_balance);
+        self.total_money -= received;
+        self.minted = minted;
+        self.rewarded_blocks = rewarded_blocks;
+        self.unclaimed_fees += fees;
+        if let Some(rewards) = self.rewards.get_mut(winner) {
+            rewards.remove(&depth);
+            if rewards.is_empty() {
+                self.rewards.remove(winner);
+            }
+        }
+        Ok(())
+    }
 
-        if *from_balance < amount + TRANSACTION_FEE {
-            return false;
+    /// Whether the transaction can be applied next, and why not.
+    /// depth is the depth of the block the transaction would be included in
+    pub fn validate_transaction(
+        &self,
+        transaction: &Transaction,
+        depth: u64,
+    ) -> std::result::Result<(), TxValidationError> {
+        self.validate_form(transaction)?;
+        let from = &transaction.from_id();
+        // if the account does not exist it can't have enough money to pay the fee
+        if !self.map.contains_key(from) {
+            return Err(TxValidationError::UnknownSender);
+        };
+        if self.applied_transactions.contains(&transaction.id()) {
+            return Err(TxValidationError::AlreadyApplied); // if we have already proccessed this
+        }
+        // the transactions of a sender are applied in nonce order
+        let expected = self.next_nonce(from);
+        if transaction.nonce < expected {
+            return Err(TxValidationError::NonceTooLow { expected, got: transaction.nonce });
+        }
+        if transaction.nonce > expected {
+            return Err(TxValidationError::NonceGap { expected, got: transaction.nonce });
         }
+        let cost = transaction.cost().expect("the form is checked");
+        let spendable = self.get_spendable_balance(from, depth);
+        if spendable < cost {
+            return Err(TxValidationError::InsufficientFunds { spendable, cost });
+        }
+        Ok(())
+    }
+
+}
+
+/// A balance and its place among the sorted balances of a ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceEntry {
+    pub account: AccountId,
+    pub balance: u64,
+    pub path: MerkleProof,
+}
+
+/// Shows the balance of an account to someone who only has the state root, see `Ledger::prove_balance`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceProof {
+    pub account: AccountId,
+    pub entries: Vec<BalanceEntry>, // the entry of the account, or the entries next to where it would be
+    pub rest: [u8; 32], // the hash of what the state root commits to besides the balances
+}
+
+// an account, a balance, an index, a length and the number of siblings
+const MIN_ENTRY_BYTES: usize = 32 + 8 + 8 + 8 + 4;
+
+impl BalanceProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.hash(self.account.as_bytes()).hash(&self.rest).len(self.entries.len());
+        for entry in &self.entries {
+            writer
+                .hash(entry.account.as_bytes())
+                .u64(entry.balance)
+                .u64(entry.path.index)
+                .u64(entry.path.leaf_count)
+                .len(entry.path.siblings.len());
+            for sibling in &entry.path.siblings {
+                writer.hash(sibling);
+            }
+        }
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let account = AccountId(reader.hash()?);
+        let rest = reader.hash()?;
+        let entries = (0..reader.count(MIN_ENTRY_BYTES)?)
+            .map(|_| {
+                let account = AccountId(reader.hash()?);
+                let balance = reader.u64()?;
+                let (index, leaf_count) = (reader.u64()?, reader.u64()?);
+                let siblings = (0..reader.count(32)?).map(|_| reader.hash()).collect::<Result<_>>()?;
+                Ok(BalanceEntry {
+                    account,
+                    balance,
+                    path: MerkleProof {
+                        index,
+                        leaf_count,
+                        siblings,
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+        reader.finish()?;
+        Ok(Self { account, entries, rest })
+    }
+}
+
+serde_via_bytes!(BalanceProof);
+text_via_bytes!(BalanceProof);
 
-        if self.previous_transactions.contains(&transaction.hash) {
-            return false; // if we have already proccessed this
+/// The account and its balance if the proof holds for the state root, a light client trusts the root of a
+/// header it has verified and nothing else
+pub fn verify_balance_proof(state_root: &[u8; 32], proof: &BalanceProof) -> Option<(AccountId, u64)> {
+    // every entry must lead to the same root of the same number of balances
+    let mut balance_root = merkle::root(&[]);
+    let mut count = 0;
+    for (i, entry) in proof.entries.iter().enumerate() {
+        let root = entry.path.root_of(&balance_leaf(&entry.account, entry.balance))?;
+        if i > 0 && (root != balance_root || entry.path.leaf_count != count) {
+            return None;
         }
+        balance_root = root;
+        count = entry.path.leaf_count;
+    }
+    if state_root_of(&balance_root, count, &proof.rest) != *state_root {
+        return None;
+    }
+    let account = proof.account;
+    let balance = match proof.entries.as_slice() {
+        [entry] if entry.account == account => entry.balance,
+        // there is no balance between the neighbours, or before the first or after the last one
+        [] => 0,
+        [above] if account < above.account && above.path.index == 0 => 0,
+        [below] if below.account < account && below.path.index + 1 == count => 0,
+        [below, above] if below.account < account && account < above.account => {
+            (below.path.index + 1 == above.path.index).then_some(0)?
+        }
+        _ => return None,
+    };
+    Some((account, balance))
+}
 
-        true
+fn balance_leaves(balances: &[(AccountId, u64)]) -> Vec<[u8; 32]> {
+    balances.iter().map(|(account, balance)| balance_leaf(account, *balance)).collect()
+}
+
+fn balance_leaf(account: &AccountId, balance: u64) -> [u8; 32] {
+    Sha256::digest(Writer::new().hash(account.as_bytes()).u64(balance).finish()).into()
+}
+
+// the number of balances is committed to, so a proof can not claim an entry is the last one
+fn state_root_of(balance_root: &[u8; 32], balance_count: u64, rest: &[u8; 32]) -> [u8; 32] {
+    Sha256::digest(Writer::new().hash(balance_root).u64(balance_count).hash(rest).finish()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BLOCK_REWARD, TRANSACTION_FEE};
+
+    #[test]
+    fn rewards_mature() {
+        let (sk, winner) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        let reward_depth = 1;
+        ledger
+            .reward_winner(&AccountId::from(&winner), BLOCK_REWARD, reward_depth)
+            .unwrap();
+
+        let t = Transaction::new(winner.clone(), receiver.clone(), &sk, 10, 0).unwrap();
+        let immature = TxValidationError::InsufficientFunds { spendable: 0, cost: 10 + TRANSACTION_FEE };
+        assert_eq!(ledger.validate_transaction(&t, reward_depth + 1), Err(immature));
+        assert!(!ledger.process_transaction(&t, reward_depth + 1));
+        assert!(!ledger.process_transaction(&t, reward_depth + REWARD_MATURITY - 1));
+
+        assert_eq!(ledger.validate_transaction(&t, reward_depth + REWARD_MATURITY), Ok(()));
+        assert!(ledger.process_transaction(&t, reward_depth + REWARD_MATURITY));
+        assert_eq!(ledger.get_balance(&AccountId::from(&receiver)), 10);
+    }
+
+    #[test]
+    fn audit_finds_corrupted_balances() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 1).unwrap();
+        let t = Transaction::with_fee(from.clone(), to.clone(), &sk, 10, 3, 0).unwrap();
+        assert!(ledger.process_transaction(&t, 2));
+        assert_eq!(ledger.audit(), vec![]);
+        assert_eq!(ledger.minted(), 2 * BLOCK_REWARD);
+        assert_eq!(ledger.rewarded_blocks(), 1);
+        assert_eq!(ledger.total_burned(), 3);
+        assert_eq!(ledger.circulating_supply(), 2 * BLOCK_REWARD - 3);
+
+        let total = ledger.circulating_supply();
+        ledger.map.insert(AccountId::from(&to), 11);
+        assert_eq!(
+            ledger.audit(),
+            vec![
+                LedgerViolation::CachedTotalMismatch {
+                    cached: total,
+                    actual: total + 1
+                },
+                LedgerViolation::SupplyMismatch {
+                    expected: total,
+                    actual: total + 1
+                },
+            ]
+        );
+
+        ledger.map.insert(AccountId::from(&to), 10);
+        assert_eq!(ledger.audit(), vec![]);
+        let (_, empty) = crate::generate_keypair();
+        ledger.map.insert(AccountId::from(&empty), 0);
+        assert_eq!(ledger.audit(), vec![LedgerViolation::ZeroBalance(AccountId::from(&empty))]);
+    }
+
+    #[test]
+    fn applied_transactions_are_not_replayed_until_rolled_back() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+        let before = ledger.clone();
+        let t = Transaction::new(from.clone(), to.clone(), &sk, 10, 0).unwrap();
+        assert!(!ledger.is_applied(&t.id()));
+        assert!(ledger.process_transaction(&t, 1));
+        assert!(ledger.is_applied(&t.id()));
+        assert!(!ledger.process_transaction(&t, 1));
+        assert_eq!(ledger.get_balance(&AccountId::from(&to)), 10);
+
+        // the applied set is part of the ledger, so a ledger that missed it differs
+        let mut diverged = ledger.clone();
+        diverged.applied_transactions.clear();
+        assert_ne!(diverged, ledger);
+        assert_ne!(diverged.state_root(), ledger.state_root());
+
+        ledger.rollback_transaction(&t, 1).unwrap();
+        assert!(!ledger.is_applied(&t.id()));
+        assert_eq!(ledger, before);
+        assert!(ledger.process_transaction(&t, 1));
+        assert_eq!(ledger.get_balance(&AccountId::from(&to)), 10);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rollback_reward_of_empty_account_is_refused() {
+        let (_, account) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        assert_eq!(
+            ledger.rollback_reward(&AccountId::from(&account), BLOCK_REWARD, 1),
+            BlockchainError::BalanceUnderflow.into()
+        );
+        assert_eq!(ledger.get_balance(&AccountId::from(&account)), 0);
+        assert_eq!(ledger, Ledger::new(vec![], TRANSACTION_FEE));
+    }
+
+    #[test]
+    fn rollback_restores_the_ledger() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+        let before = ledger.clone();
+
+        // the receiver is new and the sender is emptied
+        let t = Transaction::new(from.clone(), to.clone(), &sk, BLOCK_REWARD - TRANSACTION_FEE, 0).unwrap();
+        assert!(ledger.process_transaction(&t, 1));
+        ledger.reward_winner(&AccountId::from(&to), BLOCK_REWARD, 1).unwrap();
+        assert_eq!(ledger.get_balance(&AccountId::from(&from)), 0);
+
+        ledger.rollback_reward(&AccountId::from(&to), BLOCK_REWARD, 1).unwrap();
+        ledger.rollback_transaction(&t, 1).unwrap();
+        assert_eq!(ledger, before);
+    }
+
+    #[test]
+    fn state_root_follows_the_contents() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let (from_id, to_id) = (AccountId::from(&from), AccountId::from(&to));
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&from_id, BLOCK_REWARD, 0).unwrap();
+        ledger.reward_winner(&to_id, BLOCK_REWARD, 1).unwrap();
+        let mut reversed = Ledger::new(vec![], TRANSACTION_FEE);
+        reversed.reward_winner(&to_id, BLOCK_REWARD, 1).unwrap();
+        reversed.reward_winner(&from_id, BLOCK_REWARD, 0).unwrap();
+        assert_eq!(ledger.state_root(), reversed.state_root());
+
+        let root = ledger.state_root();
+        let t = Transaction::new(from.clone(), to.clone(), &sk, 10, 0).unwrap();
+        assert!(ledger.process_transaction(&t, 2));
+        assert_ne!(ledger.state_root(), root);
+        ledger.rollback_transaction(&t, 2).unwrap();
+        assert_eq!(ledger.state_root(), root);
+
+        // the same balance with a reward at another depth matures at another time
+        ledger.rewards.get_mut(&to_id).unwrap().insert(2, BLOCK_REWARD);
+        ledger.rewards.get_mut(&to_id).unwrap().remove(&1);
+        assert_ne!(ledger.state_root(), root);
+    }
+
+    // balances at every odd account, so there are accounts before, between and after them
+    fn proven_ledger() -> Ledger {
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        for i in [1u8, 3, 5] {
+            ledger.reward_winner(&AccountId([i; 32]), BLOCK_REWARD * i as u64, 0).unwrap();
+        }
+        ledger
+    }
+
+    #[test]
+    fn balances_are_proven_against_the_state_root() {
+        let ledger = proven_ledger();
+        let root = ledger.state_root();
+        for i in 0u8..8 {
+            let account = AccountId([i; 32]);
+            let proof = ledger.prove_balance(&account);
+            assert_eq!(proof, ledger.prove_balance(&account));
+            assert_eq!(BalanceProof::from_bytes(&proof.to_bytes()), Ok(proof.clone()));
+            let balance = ledger.get_balance(&account);
+            assert_eq!(verify_balance_proof(&root, &proof), Some((account, balance)));
+            assert_eq!(balance, if [1, 3, 5].contains(&i) { BLOCK_REWARD * i as u64 } else { 0 });
+        }
+
+        // a proof is only good for the ledger it was made from
+        let proof = ledger.prove_balance(&AccountId([3; 32]));
+        let mut changed = ledger.clone();
+        changed.reward_winner(&AccountId([7; 32]), BLOCK_REWARD, 1).unwrap();
+        assert_eq!(verify_balance_proof(&changed.state_root(), &proof), None);
+        // and for the account it was made for
+        let mut other = proof.clone();
+        other.account = AccountId([1; 32]);
+        assert_eq!(verify_balance_proof(&root, &other), None);
+        // the neighbours of an absent account must be next to each other
+        let mut gap = ledger.prove_balance(&AccountId([2; 32]));
+        gap.entries[1] = ledger.prove_balance(&AccountId([5; 32])).entries.remove(0);
+        gap.account = AccountId([4; 32]);
+        assert_eq!(verify_balance_proof(&root, &gap), None);
+
+        let empty = Ledger::new(vec![], TRANSACTION_FEE);
+        let account = AccountId([1; 32]);
+        let proof = empty.prove_balance(&account);
+        assert_eq!(verify_balance_proof(&empty.state_root(), &proof), Some((account, 0)));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn altered_proofs_do_not_vouch_for_their_account(
+            i in 0u8..8,
+            position in proptest::prelude::any::<proptest::sample::Index>(),
+            flip in 1u8..=255,
+        ) {
+            let ledger = proven_ledger();
+            let account = AccountId([i; 32]);
+            let mut bytes = ledger.prove_balance(&account).to_bytes();
+            let position = position.index(bytes.len());
+            bytes[position] ^= flip;
+            // another account may be shown to have no balance, but never this one
+            if let Ok(altered) = BalanceProof::from_bytes(&bytes) {
+                let verified = verify_balance_proof(&ledger.state_root(), &altered);
+                proptest::prop_assert!(verified.is_none_or(|(proven, _)| proven != account));
+            }
+        }
+    }
+
+    #[test]
+    fn nonces_are_used_in_order() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let from_id = AccountId::from(&from);
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&from_id, BLOCK_REWARD, 0).unwrap();
+        assert_eq!(ledger.next_nonce(&from_id), 0);
+
+        // a nonce after the next one waits for the gap to be filled
+        let second = Transaction::with_fee(from.clone(), to.clone(), &sk, 10, 3, 1).unwrap();
+        assert_eq!(
+            ledger.validate_transaction(&second, 1),
+            Err(TxValidationError::NonceGap { expected: 0, got: 1 })
+        );
+        assert!(!ledger.process_transaction(&second, 1));
+
+        let t = Transaction::with_fee(from.clone(), to.clone(), &sk, 10, 3, 0).unwrap();
+        assert!(ledger.process_transaction(&t, 1));
+        assert_eq!(ledger.get_balance(&from_id), BLOCK_REWARD - 10 - 3);
+        assert_eq!(ledger.next_nonce(&from_id), 1);
+
+        let same_nonce = Transaction::with_fee(from.clone(), to.clone(), &sk, 10, 4, 0).unwrap();
+        assert_eq!(
+            ledger.validate_transaction(&same_nonce, 1),
+            Err(TxValidationError::NonceTooLow { expected: 1, got: 0 })
+        );
+        assert!(!ledger.process_transaction(&same_nonce, 1));
+        assert!(ledger.process_transaction(&second, 1));
+
+        // the last transaction of the sender is rolled back first, and frees its nonce again
+        assert_eq!(ledger.rollback_transaction(&t, 1), BlockchainError::UnknownTransaction.into());
+        ledger.rollback_transaction(&second, 1).unwrap();
+        ledger.rollback_transaction(&t, 1).unwrap();
+        assert_eq!(ledger.next_nonce(&from_id), 0);
+        assert!(ledger.process_transaction(&same_nonce, 1));
+
+        let below_minimum = Transaction::with_fee(from.clone(), to.clone(), &sk, 10, 0, 1).unwrap();
+        assert_eq!(
+            ledger.validate_transaction(&below_minimum, 1),
+            Err(TxValidationError::FeeTooLow { fee: 0, minimum: TRANSACTION_FEE })
+        );
+        assert!(!ledger.process_transaction(&below_minimum, 1));
+    }
+
+    #[test]
+    fn outputs_are_paid_together() {
+        let (sk, from) = crate::generate_keypair();
+        let receivers: Vec<_> = (0..3).map(|_| crate::generate_keypair().1).collect();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+        let before = ledger.clone();
+
+        let outputs = receivers.iter().cloned().zip([10, 20, 5]).collect();
+        let t = Transaction::with_outputs(from.clone(), outputs, &sk, 2, 0, Timeslot::MAX).unwrap();
+        assert_eq!(t.cost(), Some(37));
+        assert!(ledger.process_transaction(&t, 1));
+        assert_eq!(ledger.get_balance(&AccountId::from(&from)), BLOCK_REWARD - 37);
+        for (receiver, amount) in receivers.iter().zip([10, 20, 5]) {
+            assert_eq!(ledger.get_balance(&AccountId::from(receiver)), amount);
+        }
+        assert_eq!(ledger.total_burned(), 2);
+        assert_eq!(ledger.audit(), vec![]);
+
+        // one output too many and nothing is paid
+        let outputs = receivers.iter().cloned().zip([10, 10, BLOCK_REWARD]).collect();
+        let unaffordable = Transaction::with_outputs(from.clone(), outputs, &sk, 2, 1, Timeslot::MAX).unwrap();
+        let paid = ledger.clone();
+        let cost = 20 + BLOCK_REWARD + 2;
+        assert_eq!(
+            ledger.validate_transaction(&unaffordable, 1),
+            Err(TxValidationError::InsufficientFunds { spendable: BLOCK_REWARD - 37, cost })
+        );
+        assert!(!ledger.process_transaction(&unaffordable, 1));
+        assert_eq!(ledger, paid);
+
+        assert_eq!(
+            Transaction::with_outputs(from.clone(), vec![], &sk, 2, 3, Timeslot::MAX).map(|_| ()),
+            BlockchainError::ZeroAmount.into()
+        );
+        let empty = Transaction::sign(from.clone(), vec![], &sk, 2, 1, Timeslot::MAX);
+        assert_eq!(ledger.validate_transaction(&empty, 1), Err(TxValidationError::NoReceivers));
+        assert!(!ledger.process_transaction(&empty, 1));
+
+        ledger.rollback_transaction(&t, 1).unwrap();
+        assert_eq!(ledger, before);
+    }
+
+    #[test]
+    fn payments_to_the_sender_or_of_nothing_are_refused() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+        let before = ledger.clone();
+
+        assert_eq!(
+            Transaction::new(from.clone(), from.clone(), &sk, 10, 0).map(|_| ()),
+            BlockchainError::SelfTransfer.into()
+        );
+        assert_eq!(
+            Transaction::new(from.clone(), to.clone(), &sk, 0, 0).map(|_| ()),
+            BlockchainError::ZeroAmount.into()
+        );
+        let outputs = vec![(to.clone(), 10), (from.clone(), 10)];
+        assert_eq!(
+            Transaction::with_outputs(from.clone(), outputs.clone(), &sk, 2, 1, Timeslot::MAX).map(|_| ()),
+            BlockchainError::SelfTransfer.into()
+        );
+
+        // signed anyway, as a hostile node could
+        let to_self = Transaction::sign(from.clone(), vec![(from.clone(), 10)], &sk, 2, 0, Timeslot::MAX);
+        let partly_to_self = Transaction::sign(from.clone(), outputs, &sk, 2, 0, Timeslot::MAX);
+        let nothing = Transaction::sign(from.clone(), vec![(to.clone(), 0)], &sk, 2, 0, Timeslot::MAX);
+        let too_small = TxValidationError::OutputTooSmall { minimum: TRANSACTION_FEE };
+        let refused = [
+            (to_self, TxValidationError::SelfTransfer),
+            (partly_to_self, TxValidationError::SelfTransfer),
+            (nothing, too_small),
+        ];
+        for (t, reason) in refused {
+            assert!(t.verify_signature());
+            assert_eq!(ledger.validate_transaction(&t, 1), Err(reason));
+            assert!(!ledger.process_transaction(&t, 1));
+        }
+        assert_eq!(ledger, before);
+    }
+
+    #[test]
+    fn refused_transactions_say_why() {
+        let (sk, from) = crate::generate_keypair();
+        let (other_sk, to) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+
+        let forged = Transaction::sign(from.clone(), vec![(to.clone(), 10)], &other_sk, 2, 0, Timeslot::MAX);
+        assert_eq!(ledger.validate_transaction(&forged, 1), Err(TxValidationError::BadSignature));
+        let outputs = vec![(to.clone(), u64::MAX), (crate::generate_keypair().1, 1)];
+        let overflowing = Transaction::sign(from.clone(), outputs, &sk, 2, 0, Timeslot::MAX);
+        assert_eq!(ledger.validate_transaction(&overflowing, 1), Err(TxValidationError::Overflow));
+        let penniless = Transaction::new(to.clone(), from.clone(), &other_sk, 10, 0).unwrap();
+        assert_eq!(ledger.validate_transaction(&penniless, 1), Err(TxValidationError::UnknownSender));
+
+        let t = Transaction::new(from.clone(), to.clone(), &sk, 10, 0).unwrap();
+        assert_eq!(ledger.validate_transaction(&t, 1), Ok(()));
+        assert!(ledger.process_transaction(&t, 1));
+        assert_eq!(ledger.validate_transaction(&t, 1), Err(TxValidationError::AlreadyApplied));
+    }
+
+    #[test]
+    fn rollback_of_unprocessed_transaction_is_refused() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut ledger = Ledger::new(vec![], TRANSACTION_FEE);
+        ledger.reward_winner(&AccountId::from(&from), BLOCK_REWARD, 0).unwrap();
+        let t = Transaction::new(from.clone(), to.clone(), &sk, 10, 0).unwrap();
+        assert_eq!(
+            ledger.rollback_transaction(&t, 1),
+            BlockchainError::UnknownTransaction.into()
+        );
+        assert_eq!(ledger.get_balance(&AccountId::from(&from)), BLOCK_REWARD);
+        assert_eq!(ledger.get_balance(&AccountId::from(&to)), 0);
+    }
+}
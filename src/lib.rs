@@ -2,47 +2,125 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
-use block::Block;
-use blockchain::{Blockchain, BlockchainError};
+use account::AccountId;
+use block::{Block, BlockHash};
+use blockchain::{Blockchain, BlockchainError, Genesis, Snapshot};
 
 use cli::CliPreTransaction;
 use draw::Draw;
+use events::ChainEvent;
 use ledger::Ledger;
 use num_bigint::BigUint;
-use rand::thread_rng;
-
-use rsa::{
-    sha2::Sha256,
-    RsaPrivateKey, RsaPublicKey,
-};
+use pippi::handshake::HandshakeError;
+use keys::{PublicKey, SecretKey};
 use seeding_mechanism::MIN_SEED_AGE;
+use sync::SyncProgress;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use transaction::Transaction;
+pub mod account;
+pub mod address_book;
+pub mod clock;
 pub mod clock_watch;
 pub mod block;
+pub mod block_producer;
 pub mod blockchain;
 pub mod blockchain_actor;
 pub mod cli;
 pub mod client;
+pub mod compression;
+pub mod config;
+pub mod difficulty;
 pub mod draw;
+pub mod encoding;
+pub mod events;
+pub mod export;
+pub mod genesis;
+pub mod history;
+pub mod keys;
+pub mod keystore;
 pub mod ledger;
+pub mod logging;
+pub mod mempool;
+pub mod merkle;
+pub mod metrics;
 pub mod network_actor;
+pub mod node;
+pub mod params;
+pub mod peer_scores;
 pub mod pippi;
+pub mod policy;
+mod requests;
+#[cfg(feature = "rpc")]
+pub mod rpc;
 pub mod transaction;
+pub mod wallet;
+pub mod watch;
+#[cfg(feature = "rpc")]
+pub mod ws;
 pub mod seeding_mechanism;
+pub mod signatures;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub mod stats;
+pub mod store;
+pub mod sync;
+pub mod timeslot;
 
 pub const TRANSACTION_FEE: u64 = 1;
 pub const BLOCK_REWARD: u64 = 50;
 pub const ROOT_AMOUNT: u64 = 300;
+pub const REWARD_MATURITY: u64 = 10; // number of blocks before a block reward can be spent
+pub const MAX_MEMPOOL_SIZE: usize = 10_000;
+pub const MAX_MEMPOOL_BYTES: usize = 10_000_000; // the sum of the `encoded_len` of the transactions in the mempool
+pub const MAX_PARKED_SLOTS: u64 = 60; // timeslots a transaction waits for a missing nonce of its sender before it is dropped
+pub const MAX_MEMPOOL_AGE: u64 = 360; // timeslots a transaction waits in the mempool before it is dropped
+pub const MEMPOOL_PRUNE_BATCH: usize = 500; // transactions looked at in a batch of the mempool cleanup, between them the chain is free
+pub const MAX_BLOCK_TRANSACTIONS: u64 = 1_000;
+pub const SIGNATURE_CACHE_SIZE: usize = 100_000; // ids of transactions whose signature is known to hold
+pub const PRECHECKED_BLOCKS: usize = 256; // blocks whose header and draw are known to hold, see `Blockchain::precheck_block`
+pub const MAX_BLOCK_BYTES: u64 = 1_000_000; // a transaction is around 900 bytes with 2048 bit keys
+pub const CLOCK_SKEW_TOLERANCE: u64 = 1; // number of timeslots a block may be ahead of our clock
+pub const MAX_FUTURE_BLOCKS: usize = 100; // blocks further ahead are held until their timeslot, at most this many
+pub const MAX_ORPHANS: usize = 1_000; // blocks whose parent we do not have are held until it arrives, at most this many
+pub const FINALITY_DEPTH: u64 = 100; // number of blocks below the tip at which a block is final
+pub const MAX_REORG_DEPTH: u64 = 30; // number of blocks of the best path a reorg may roll back before an operator has to accept it
+pub const LEDGER_HISTORY_INTERVAL: u64 = 100; // number of blocks between the ledgers kept for balances at past depths
+pub const STAKE_LOOKBACK: u64 = 10; // number of blocks back whose ledger decides the lottery for a block
+pub const EQUIVOCATION_PENALTY: u64 = 1_000; // number of timeslots in which an equivocating winner can not win
+pub const MAX_PARENT_WALK: u64 = 100; // number of missing ancestors of an orphan we ask for before giving up
+pub const MAX_PEER_REQUESTS: usize = 10; // number of unanswered block and sync requests we have with a peer
+pub const BLOCK_REQUEST_TIMEOUT: u128 = 10_000; // milliseconds before an unanswered block request is asked of another peer
+pub const REQUEST_ATTEMPTS: usize = 3; // number of times a missing block is asked for before we give up on it
+pub const REQUEST_CHECK: u64 = 1_000; // milliseconds between the checks for requests that were not answered in time
+pub const BAN_SCORE: u64 = 100; // misbehavior points at which a peer is banned
+pub const BAN_COOLDOWN: u128 = 600_000; // milliseconds a banned peer is refused
+pub const MAX_TRACKED_PEERS: usize = 10_000; // well behaved peers are forgotten when we keep score of more than this
+pub const MAX_FRAME_BYTES: u64 = 8_000_000; // largest message a peer may send, its length is checked before it is read
+pub const MAX_EMPTY_GAP: u64 = 30; // timeslots since the tip after which a node that skips empty blocks produces one anyway
+pub const SYNC_BATCH_SIZE: usize = 100; // blocks in a batch of a sync
+pub const SYNC_BATCH_BYTES: u64 = 4_000_000; // a batch has fewer blocks if they are large, so it stays below the frame limit
+pub const SNAPSHOT_PART_BYTES: u64 = 4_000_000; // a snapshot is sent in parts of this size, so each stays below the frame limit
+pub const MAX_SNAPSHOT_BYTES: u64 = 1_000_000_000; // largest snapshot a joining node puts together from its parts
+pub const SYNC_TIMEOUT: u128 = 10_000; // milliseconds without a batch before a sync is asked of another peer
+pub const BLOCK_LOG_COMPACTION: u64 = 1_000; // blocks appended to the block log before it is rewritten as the chain
+pub const RECONNECT_CHECK: u64 = 1_000; // milliseconds between the checks that a node that joined through seeds still has a peer
+pub const RECONNECT_BACKOFF: u128 = 500; // milliseconds before the seeds are dialed again, doubled after every round that fails
+pub const MAX_RECONNECT_BACKOFF: u128 = 60_000; // the longest we wait before dialing the seeds again
+pub const DIAL_TIMEOUT: u64 = 5_000; // milliseconds before we give up on a seed that does not answer
+pub const REDIAL_PEERS: usize = 8; // addresses of the address book dialed before the seeds
+pub const MAX_ADDRESS_BOOK: usize = 1_000; // addresses kept between runs, the ones seen longest ago are forgotten first
+pub const ADDRESS_BOOK_FLUSH: u64 = 60_000; // milliseconds between the writes of the address book to the data directory
 #[cfg(not(test))]
-pub const SLOT_LENGTH: u128 = 10_000_000;
+pub const SLOT_LENGTH: u128 = 10_000; // milliseconds
 #[cfg(test)]
-pub const SLOT_LENGTH: u128 = 1; // 0.001 millisecond for testing
+pub const SLOT_LENGTH: u128 = 1; // 1 millisecond for testing
+pub const DEV_SLOT_LENGTH: u64 = 100; // milliseconds, of the chain of `NodeBuilder::dev`
 
-pub(crate) type Timeslot = u64;
+pub use timeslot::Timeslot;
 
 use lazy_static::lazy_static;
 
@@ -84,51 +162,53 @@ lazy_static! {
     };
 }
 
-pub fn generate_keypair() -> (RsaPrivateKey, RsaPublicKey) {
-    let mut rng = thread_rng();
-
-    #[cfg(not(feature = "small_key"))]
-    const BITS: usize = 2048;
-    #[cfg(feature = "small_key")]
-    const BITS: usize = 1024;
-
-    let sk = RsaPrivateKey::new(&mut rng, BITS).unwrap();
+pub fn generate_keypair() -> (SecretKey, PublicKey) {
+    let sk = SecretKey::generate();
     let pk = sk.to_public_key();
     (sk, pk)
 }
 
-fn is_winner(ledger: &Ledger, draw: Draw, wallet: &RsaPublicKey, depth: u64) -> bool {
+/// Makes a keypair of `bits` on a blocking thread, `progress` is called there with the number of attempts so far.
+/// An attempt is a candidate prime of an RSA key, so a wallet can show that it is still working
+pub async fn generate_keypair_with_progress(
+    bits: usize,
+    progress: impl FnMut(u64) + Send + 'static,
+) -> Result<(SecretKey, PublicKey)> {
+    let sk = tokio::task::spawn_blocking(move || SecretKey::generate_with_progress(bits, progress))
+        .await
+        .expect("generating a key does not panic")?;
+    let pk = sk.to_public_key();
+    Ok((sk, pk))
+}
+
+fn is_winner(
+    ledger: &Ledger,
+    draw: &Draw,
+    wallet: &PublicKey,
+    depth: u64,
+    hardness: &BigUint,
+) -> bool {
     #[cfg(feature = "always_win")]
     return true;
 
-    let Some(account_published_at) = ledger.published_accounts.get(wallet) else  {
-        println!("account not published");
+    let account = AccountId::from(wallet);
+    let Some(account_published_at) = ledger.published_accounts.get(&account) else  {
+        tracing::debug!(target: logging::BLOCKCHAIN, %account, "the winner is not published");
         return false // account has not been published
     };
 
-    let account_age = depth - account_published_at;
+    let Some(account_age) = depth.checked_sub(*account_published_at) else {
+        tracing::debug!(target: logging::BLOCKCHAIN, %account, depth, "the winner was published after the draw");
+        return false
+    };
 
     if account_age < MIN_SEED_AGE && *account_published_at != 0 { 
-        println!("account too young");
+        tracing::debug!(target: logging::BLOCKCHAIN, %account, account_age, "the winner is too young");
         return false
      }
 
-    let balance = BigUint::from(ledger.get_balance(wallet));
-    let total_money = ledger.get_total_money_in_ledger();
-
-    let max_hash = BigUint::from(2u64).pow(256);
-
-    // the entire network has a total 10% chance of beating this at a given timeslot
-    let hardness = BigUint::from(10421u64) * (BigUint::from(10u64).pow(73));
-
-    // we must map the draw value which is in [0, 2^256] to [0, h + c(2^256 - h)] where h is hardness and c is the ratio of money we have
-    // we can map this by multiplying the draw with (h + c(2^256 - h))/(2^256)
-    // we can describe c as balance/total_money. Therefore we can multiply total_money to the hardness and write the multiplication factor as:
-    let mult_factor =
-        (hardness.clone() * total_money) + (balance * (max_hash.clone() - hardness.clone()));
-
     // We win if we have a good draw and a big enough fraction of the money
-    draw.value.clone() * mult_factor > hardness * total_money * max_hash.clone()
+    difficulty::beats(&draw.value, ledger.get_balance(&account), ledger.circulating_supply(), hardness)
 }
 
 pub fn get_unix_timestamp() -> u128 {
@@ -136,79 +216,67 @@ pub fn get_unix_timestamp() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_micros()
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Pkcs1v15Signature(Vec<u8>);
-
-impl Pkcs1v15Signature {
-    pub fn sign(sk: &RsaPrivateKey, hashed_data: &[u8]) -> Result<Pkcs1v15Signature> {
-        sk.sign(rsa::Pkcs1v15Sign::new::<Sha256>(), hashed_data)
-            .map(Pkcs1v15Signature)
-            .map_err(|_| Error::Pkcs1v15Error)
-    }
-
-    pub fn verify(&self, vk: &RsaPublicKey, hashed_data: &[u8]) -> Result<()> {
-        vk.verify(rsa::Pkcs1v15Sign::new::<Sha256>(), hashed_data, &self.0)
-            .map_err(|_| Error::Pkcs1v15Error)
-    }
-
-    pub fn to_bytes(&self) -> &[u8] {
-        &self.0
-    }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PssSignature(Vec<u8>);
-
-impl PssSignature {
-    pub fn sign(sk: &RsaPrivateKey, hashed_data: &[u8]) -> Result<PssSignature> {
-        sk.sign_with_rng(&mut thread_rng(), rsa::Pss::new::<Sha256>(), hashed_data)
-            .map(PssSignature)
-            .map_err(|_| Error::PssError)
-    }
-
-    pub fn verify(&self, vk: &RsaPublicKey, hashed_data: &[u8]) -> Result<()> {
-        vk.verify(rsa::Pss::new::<Sha256>(), hashed_data, &self.0)
-            .map_err(|_| Error::PssError)
-    }
-
-    pub fn to_bytes(&self) -> &[u8] {
-        &self.0
-    }
+        .as_millis()
 }
 
 // messages to the client
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum ClientMessage {
-    Won(Block),
-    BalanceOf(RsaPublicKey, u64),
-    External(ExternalMessage),
+    Chain(ChainEvent),
+    ChainEventsLagged(u64), // the client missed this many chain events, blocks among them are not in the block log
+    Produced(Arc<Block>), // a block we won, to be broadcast
+    BalanceOf(AccountId, u64),
+    External(SocketAddr, Box<ExternalMessage>), // and who sent it
     CLI(CLIMessage),
+    #[cfg(feature = "rpc")]
+    Rpc(rpc::Call),
+    Metrics(tokio::sync::oneshot::Sender<metrics::NodeMetrics>), // a scrape of the metrics endpoint
+    Status(tokio::sync::oneshot::Sender<metrics::NodeStatus>),
+    Malformed(SocketAddr), // a peer sent bytes that are not a message, or a frame over the limit
+    BannedPeers(tokio::sync::oneshot::Sender<Vec<(SocketAddr, u128)>>), // and until when they are banned
+    PeerRefused(SocketAddr, HandshakeError), // the node is not on our network or speaks no version we speak
+    RefusedPeers(tokio::sync::oneshot::Sender<Vec<(SocketAddr, HandshakeError)>>), // and why, the last reason for each
+    RetryRequests, // asks other peers for the blocks and batches that were not sent in time
+    Reconnected(SocketAddr), // we had lost every peer and joined the network through this seed again
+    Connection(tokio::sync::oneshot::Sender<network_actor::ConnectionState>),
+    SaveAddressBook, // written to the data directory now and then, not only on shutdown
+    SubmitTransaction(Transaction, tokio::sync::oneshot::Sender<Result<()>>), // added to the mempool and flooded, or why not
+    GetBalance(AccountId, tokio::sync::oneshot::Sender<Option<u64>>), // none before we have a chain
+    BestTip(tokio::sync::oneshot::Sender<Option<blockchain::TipRef>>), // none before we have a chain
+    Shutdown, // the client stops after flushing its state
     Ping,
 }
 
-/// Messages received on the network
+/// Messages received on the network. The address in a request is the one the node says it listens on, it is only
+/// kept so older nodes can read the messages. A reply goes back on the connection the request came on
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ExternalMessage {
     Bootstrap(Blockchain), // if we need a blockchain to start off on we take this one
     BootstrapReqFrom(SocketAddr), // someone needs a blockchain
+    Snapshot(Box<Snapshot>), // if we need a blockchain we can also start at its checkpoint
+    SnapshotReqFrom(SocketAddr), // someone needs a blockchain but not its history
+    Genesis(Box<Genesis>), // the genesis block of the network, a new node starts an empty chain with it
+    GenesisReqFrom(SocketAddr), // someone is joining the network
+    BalanceReqFrom(SocketAddr, AccountId), // a wallet wants to know the balance of an account
+    Balance(AccountId, u64), // reply to a balance request
     BroadcastTransaction(Transaction),
     BroadcastBlock(Block), // a won block
-}
-
-impl From<ExternalMessage> for ClientMessage {
-    fn from(value: ExternalMessage) -> Self {
-        ClientMessage::External(value)
-    }
+    BlockReqFrom(SocketAddr, BlockHash), // someone is missing the parent of an orphan
+    RequestedBlock(Block), // reply to a block request, it is not flooded
+    SyncReqFrom(SocketAddr, Vec<BlockHash>), // someone is behind, with the locator of its best path
+    SyncBatch(SyncProgress, Vec<Block>), // the next blocks of a sync, applied in order
+    TipAnnounce { hash: BlockHash, depth: u64, timeslot: Timeslot }, // we have the block, ask us for it if you do not
+    SnapshotPart { part: u32, parts: u32, bytes: Vec<u8> }, // the encoded snapshot in parts, sent in order
 }
 
 // messages from the CLI to the client
 #[derive(Clone, Debug)]
 pub enum CLIMessage {
     PostTransaction(CliPreTransaction),
-    CheckBalance(RsaPublicKey),
+    CheckBalance(AccountId),
+    Dump(PathBuf), // the folder the chain and its block tree are written to
+    ExportPeers(PathBuf), // the file the address book is written to, an address per line
+    ImportPeers(PathBuf), // a file written by an export, its addresses are added to the address book
 }
 
 impl From<CLIMessage> for ClientMessage {
@@ -225,22 +293,47 @@ pub enum Error {
     CLIError,
     #[error("Invalid pem")]
     InvalidPem,
-    #[error("pkcs1v15 error")]
-    Pkcs1v15Error,
-    #[error("pss error")]
-    PssError,
+    #[error("A key must have at least {} bits, not {0}", keys::MIN_KEY_BITS)]
+    KeyTooSmall(usize),
+    #[error("Unable to import the key, {0}")]
+    KeyImport(keys::KeyImportError), // with the formats that were tried
+    #[error("Invalid signature")]
+    SignatureError,
+    #[error("Invalid encoding")]
+    InvalidEncoding,
+    #[error("Unsupported format version {0}")]
+    UnsupportedFormatVersion(u8),
+    #[error("Unable to read or write the data directory")]
+    StorageError,
+    #[error("Unable to read or write the key file")]
+    KeyFileError,
+    #[error("Wrong passphrase for the key file")]
+    WrongPassphrase,
+    #[error("The key file is corrupted")]
+    CorruptKeyFile,
+    #[error("No passphrase was given for the key file")]
+    MissingPassphrase,
+    #[error("The key is not the key of the sender")]
+    WrongKey,
+    #[error("The node has no blockchain yet")]
+    NotReady,
+    #[error("The node has stopped")]
+    NodeStopped,
+    #[error("Invalid config, {0}")]
+    InvalidConfig(String), // with the line and key that are wrong
     #[error("Internal Blockchain error")]
     BlockchainError(BlockchainError),
+    #[error("Unable to reindex the chain, {0}")]
+    Reindex(blockchain::ReindexError),
 }
 
-pub(crate) fn calculate_timeslot(start_time: u128) -> Timeslot {
+pub(crate) fn calculate_timeslot(now: u128, start_time: u128, slot_length: u128) -> Timeslot {
     #[cfg(feature = "max_timeslot")]
-    return u64::MAX;
+    return Timeslot::MAX;
 
-    let now = crate::get_unix_timestamp();
     let start = start_time;
-    let timeslot = (now - start) / SLOT_LENGTH;
-    timeslot as _
+    let timeslot = now.saturating_sub(start) / slot_length;
+    Timeslot(timeslot as _)
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
@@ -0,0 +1,37 @@
+// the diagnostics of the node are tracing events with the hash, depth, peer or txid they are about as fields,
+// under a target for each component so they can be filtered like `RUST_LOG=lasagna::net=debug`. The library
+// never installs a subscriber, an application embedding a node sees the events with its own
+
+use tracing_subscriber::EnvFilter;
+
+use crate::{Error, Result};
+
+/// Adding and verifying blocks and switching branches
+pub const BLOCKCHAIN: &str = "lasagna::blockchain";
+/// Peers, bans and what is sent to them
+pub const NET: &str = "lasagna::net";
+/// Transactions offered to the mempool
+pub const MEMPOOL: &str = "lasagna::mempool";
+/// Sync sessions with other nodes
+pub const SYNC: &str = "lasagna::sync";
+/// The chain and the address book in the data directory
+pub const STORE: &str = "lasagna::store";
+/// The RPC, subscription and metrics servers
+pub const RPC: &str = "lasagna::rpc";
+
+/// The level the binaries log at when neither `--log-level` nor `RUST_LOG` is given
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Installs the subscriber of the binaries, printing the events to stdout. The level is a filter like that of
+/// `RUST_LOG`, which is read when there is none, and `DEFAULT_LOG_LEVEL` is used when neither is given
+pub fn init(level: Option<&str>) -> Result<()> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)
+            .map_err(|e| Error::InvalidConfig(format!("invalid log level {level:?}, {e}")))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL)),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .try_init()
+        .map_err(|e| Error::InvalidConfig(e.to_string()))
+}
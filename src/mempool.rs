@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountId;
+use crate::blockchain::BlockchainError;
+use crate::transaction::Transaction;
+use crate::{Result, Timeslot};
+
+/// Bounded buffer of transactions waiting to be included in a block, in number and in the sum of their `encoded_len`.
+/// When full the oldest transactions are evicted to make room for the new one.
+/// A transaction with the same sender and nonce as a buffered one replaces it if it pays a higher fee.
+/// The transactions of a sender are kept in nonce order, those after a missing nonce are parked until it arrives
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mempool {
+    transactions: HashMap<[u8; 32], (u64, Transaction)>, // maps from hash to (insertion number, transaction)
+    order: BTreeMap<u64, [u8; 32]>,                      // insertion number to hash, the first entry is the oldest
+    by_sender: HashMap<AccountId, BTreeMap<u64, [u8; 32]>>, // the hashes of each sender by nonce
+    parked: HashMap<[u8; 32], Timeslot>,                 // waiting for a missing nonce since the timeslot
+    arrived: HashMap<[u8; 32], Timeslot>,                // the timeslot each transaction was inserted in
+    next_insertion: u64,
+    max_size: usize,
+    bytes: usize, // the encoded length of the buffered transactions
+    max_bytes: usize,
+}
+
+impl Mempool {
+    pub fn new(max_size: usize, max_bytes: usize) -> Self {
+        Self {
+            transactions: HashMap::new(),
+            order: BTreeMap::new(),
+            by_sender: HashMap::new(),
+            parked: HashMap::new(),
+            arrived: HashMap::new(),
+            next_insertion: 0,
+            max_size,
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.max_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Lowering the max size evicts the oldest transactions until we are within it
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        while self.len() > self.max_size {
+            self.evict_oldest();
+        }
+    }
+
+    /// The sum of the `encoded_len` of the buffered transactions
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Lowering the max bytes evicts the oldest transactions until we are within it
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        while self.bytes > self.max_bytes {
+            self.evict_oldest();
+        }
+    }
+
+    pub fn contains(&self, transaction: &Transaction) -> bool {
+        self.transactions.contains_key(&transaction.hash)
+    }
+
+    /// The buffered transaction that a transaction with this sender and nonce would replace
+    pub fn get_by_nonce(&self, from: &AccountId, nonce: u64) -> Option<&Transaction> {
+        let hash = self.by_sender.get(from)?.get(&nonce)?;
+        self.transactions.get(hash).map(|(_, t)| t)
+    }
+
+    /// The transactions of the sender from the next nonce on without a gap, these can go in the next block in order
+    pub fn ready_from(&self, from: &AccountId, next_nonce: u64) -> impl Iterator<Item = &Transaction> {
+        let nonces = self.by_sender.get(from).map(|nonces| nonces.range(next_nonce..));
+        nonces
+            .into_iter()
+            .flatten()
+            .zip(next_nonce..)
+            .take_while(|((nonce, _), expected)| *nonce == expected)
+            .map(|((_, hash), _)| &self.transactions.get(hash).expect("unreachable").1)
+    }
+
+    /// Marks the transaction as waiting for a missing nonce of its sender, from the timeslot unless it already was
+    pub fn park(&mut self, hash: [u8; 32], timeslot: Timeslot) {
+        if self.transactions.contains_key(&hash) {
+            self.parked.entry(hash).or_insert(timeslot);
+        }
+    }
+
+    pub fn unpark(&mut self, hash: &[u8; 32]) {
+        self.parked.remove(hash);
+    }
+
+    /// The timeslot since which the transaction has waited for a missing nonce, none if it is not parked
+    pub fn parked_since(&self, hash: &[u8; 32]) -> Option<Timeslot> {
+        self.parked.get(hash).copied()
+    }
+
+    pub fn parked_len(&self) -> usize {
+        self.parked.len()
+    }
+
+    /// What the buffered transactions of the account would take from it, amounts and fees
+    pub fn pending_spend(&self, account: &AccountId) -> u64 {
+        self.transactions
+            .values()
+            .filter(|(_, t)| t.from_id() == *account)
+            .fold(0, |total, (_, t)| {
+                total.saturating_add(t.cost().unwrap_or(u64::MAX))
+            })
+    }
+
+    /// Inserts the transaction that arrived in the timeslot, evicting the oldest one if the mempool is full.
+    /// Returns whether it replaced a transaction with the same sender and nonce
+    pub fn insert(&mut self, transaction: Transaction, timeslot: Timeslot) -> Result<bool> {
+        if self.contains(&transaction) {
+            return BlockchainError::DuplicateTransaction.into();
+        }
+        let len = transaction.encoded_len();
+        if self.max_size == 0 || len > self.max_bytes {
+            return BlockchainError::MempoolFull.into();
+        }
+
+        let replaced = match self.get_by_nonce(&transaction.from_id(), transaction.nonce) {
+            Some(buffered) => {
+                if transaction.fee <= buffered.fee {
+                    return BlockchainError::ReplacementFeeTooLow.into();
+                }
+                let buffered = buffered.clone();
+                self.remove(&buffered);
+                true
+            }
+            None => false,
+        };
+        while self.is_full() || self.bytes + len > self.max_bytes {
+            self.evict_oldest();
+        }
+
+        let insertion = self.next_insertion;
+        self.next_insertion += 1;
+        self.order.insert(insertion, transaction.hash);
+        self.by_sender
+            .entry(transaction.from_id())
+            .or_default()
+            .insert(transaction.nonce, transaction.hash);
+        self.arrived.insert(transaction.hash, timeslot);
+        self.bytes += len;
+        self.transactions
+            .insert(transaction.hash, (insertion, transaction));
+        Ok(replaced)
+    }
+
+    pub fn remove(&mut self, transaction: &Transaction) -> Option<Transaction> {
+        let (insertion, transaction) = self.transactions.remove(&transaction.hash)?;
+        self.order.remove(&insertion);
+        self.forget(&transaction);
+        Some(transaction)
+    }
+
+    /// Keeps only the transactions for which the predicate holds
+    pub fn retain(&mut self, mut f: impl FnMut(&Transaction) -> bool) {
+        let order = &mut self.order;
+        let mut removed = Vec::new();
+        self.transactions.retain(|_, (insertion, t)| {
+            let keep = f(t);
+            if !keep {
+                order.remove(insertion);
+                removed.push(t.clone());
+            }
+            keep
+        });
+        for transaction in removed {
+            self.forget(&transaction);
+        }
+    }
+
+    /// The timeslot the transaction was inserted in
+    pub fn arrived(&self, hash: &[u8; 32]) -> Option<Timeslot> {
+        self.arrived.get(hash).copied()
+    }
+
+    /// Iterates from the transaction with the insertion number on to the newest, with their insertion numbers
+    pub fn iter_from(&self, insertion: u64) -> impl Iterator<Item = (u64, &Transaction)> {
+        self.order
+            .range(insertion..)
+            .map(|(insertion, hash)| (*insertion, &self.transactions.get(hash).expect("unreachable").1))
+    }
+
+    /// Iterates from the oldest to the newest transaction
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.order
+            .values()
+            .map(|hash| &self.transactions.get(hash).expect("unreachable").1)
+    }
+
+    /// Iterates from the highest to the lowest fee per byte, ties are broken by the address of the sender and then the nonce.
+    /// The order only depends on the transactions, so nodes with the same transactions agree on it
+    pub fn iter_by_priority(&self) -> impl Iterator<Item = &Transaction> {
+        let mut prioritized: Vec<_> = self
+            .transactions
+            .values()
+            .map(|(_, t)| (t.fee as u128, t.encoded_len() as u128, t.from_id(), t))
+            .collect();
+        // a/b > c/d is compared as a*d > c*b so we do not lose precision
+        prioritized.sort_by(|(fee_a, size_a, from_a, a), (fee_b, size_b, from_b, b)| {
+            (fee_b * size_a)
+                .cmp(&(fee_a * size_b))
+                .then(from_a.cmp(from_b))
+                .then(a.nonce.cmp(&b.nonce))
+        });
+        prioritized.into_iter().map(|(_, _, _, t)| t)
+    }
+
+    /// Like `iter_by_priority`, but of each sender only the transactions from its next nonce on without a gap
+    pub fn iter_ready(&self, next_nonce: impl Fn(&AccountId) -> u64) -> impl Iterator<Item = &Transaction> {
+        let ready: HashSet<[u8; 32]> = self
+            .by_sender
+            .keys()
+            .flat_map(|from| self.ready_from(from, next_nonce(from)))
+            .map(|t| t.hash)
+            .collect();
+        self.iter_by_priority().filter(move |t| ready.contains(&t.hash))
+    }
+
+    fn evict_oldest(&mut self) -> Option<Transaction> {
+        let (_, hash) = self.order.pop_first()?;
+        let (_, transaction) = self.transactions.remove(&hash)?;
+        self.forget(&transaction);
+        Some(transaction)
+    }
+
+    // drops the indexes of a transaction that has been removed
+    fn forget(&mut self, transaction: &Transaction) {
+        let from = transaction.from_id();
+        if let Some(nonces) = self.by_sender.get_mut(&from) {
+            nonces.remove(&transaction.nonce);
+            if nonces.is_empty() {
+                self.by_sender.remove(&from);
+            }
+        }
+        self.parked.remove(&transaction.hash);
+        self.arrived.remove(&transaction.hash);
+        self.bytes -= transaction.encoded_len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TRANSACTION_FEE;
+
+    #[test]
+    fn higher_fees_come_first() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut mempool = Mempool::new(10, crate::MAX_MEMPOOL_BYTES);
+        let fees = [2, 5, 1, 5, 3];
+        let transactions: Vec<_> = fees
+            .iter()
+            .enumerate()
+            .map(|(nonce, &fee)| {
+                Transaction::with_fee(from.clone(), to.clone(), &sk, 10, fee, nonce as u64).unwrap()
+            })
+            .collect();
+        for t in transactions.iter() {
+            assert_eq!(mempool.insert(t.clone(), Timeslot(0)), Ok(false));
+        }
+
+        // the two transactions paying 5 are ordered by nonce
+        let order: Vec<_> = mempool.iter_by_priority().map(|t| t.hash).collect();
+        let expected: Vec<_> = [1, 3, 4, 0, 2]
+            .iter()
+            .map(|&i| transactions[i].hash)
+            .collect();
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn higher_fee_replaces_same_nonce() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let mut mempool = Mempool::new(10, crate::MAX_MEMPOOL_BYTES);
+        let original = Transaction::with_fee(from.clone(), to.clone(), &sk, 10, TRANSACTION_FEE, 7).unwrap();
+        assert_eq!(mempool.insert(original.clone(), Timeslot(0)), Ok(false));
+
+        let same_fee = Transaction::with_fee(from.clone(), to.clone(), &sk, 20, TRANSACTION_FEE, 7).unwrap();
+        assert_eq!(
+            mempool.insert(same_fee, Timeslot(0)),
+            BlockchainError::ReplacementFeeTooLow.into()
+        );
+
+        let other_nonce =
+            Transaction::with_fee(from.clone(), to.clone(), &sk, 10, TRANSACTION_FEE, 8).unwrap();
+        assert_eq!(mempool.insert(other_nonce, Timeslot(0)), Ok(false));
+
+        let replacement =
+            Transaction::with_fee(from.clone(), to.clone(), &sk, 10, TRANSACTION_FEE + 1, 7).unwrap();
+        assert_eq!(mempool.insert(replacement.clone(), Timeslot(0)), Ok(true));
+        assert_eq!(mempool.len(), 2);
+        assert!(!mempool.contains(&original));
+        assert!(mempool.contains(&replacement));
+
+        // removing a transaction frees its nonce
+        mempool.remove(&replacement);
+        assert_eq!(mempool.insert(original, Timeslot(0)), Ok(false));
+    }
+
+    #[test]
+    fn the_mempool_is_capped_in_bytes() {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let transactions: Vec<_> = (0..4)
+            .map(|nonce| Transaction::new(from.clone(), to.clone(), &sk, 10, nonce).unwrap())
+            .collect();
+        let len = transactions[0].encoded_len();
+        let mut mempool = Mempool::new(10, 3 * len);
+        for t in transactions.iter() {
+            assert_eq!(mempool.insert(t.clone(), Timeslot(0)), Ok(false));
+            assert!(mempool.bytes() <= mempool.max_bytes());
+        }
+        // the oldest made room for the fourth
+        assert_eq!(mempool.len(), 3);
+        assert_eq!(mempool.bytes(), 3 * len);
+        assert!(!mempool.contains(&transactions[0]));
+
+        mempool.set_max_bytes(len);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&transactions[3]));
+        mempool.remove(&transactions[3]);
+        assert_eq!(mempool.bytes(), 0);
+
+        mempool.set_max_bytes(len - 1);
+        assert_eq!(mempool.insert(transactions[0].clone(), Timeslot(0)), BlockchainError::MempoolFull.into());
+    }
+}
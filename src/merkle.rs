@@ -0,0 +1,153 @@
+// a merkle tree over the transaction hashes of a block, the header commits to its root
+// so a transaction can be shown to be in a block without the rest of the block
+// leaves and inner nodes are hashed with different prefixes, and an odd node is carried up as it is,
+// so no two lists of hashes have the same root
+
+use rsa::sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+fn leaf(hash: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([LEAF])
+        .chain_update(hash)
+        .finalize()
+        .into()
+}
+
+fn node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([NODE])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// The root of the hashes in order, a block without transactions has the zero root
+pub fn root(hashes: &[[u8; 32]]) -> [u8; 32] {
+    let mut level: Vec<_> = hashes.iter().map(leaf).collect();
+    if level.is_empty() {
+        return [0; 32];
+    }
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// The siblings on the way from a leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: u64,
+    pub leaf_count: u64,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Proves that the hash at the index is in the list, None if the index is out of range
+    pub fn new(hashes: &[[u8; 32]], index: usize) -> Option<Self> {
+        if index >= hashes.len() {
+            return None;
+        }
+        let mut level: Vec<_> = hashes.iter().map(leaf).collect();
+        let mut position = index;
+        let mut siblings = Vec::new();
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+        Some(Self {
+            index: index as u64,
+            leaf_count: hashes.len() as u64,
+            siblings,
+        })
+    }
+
+    /// Checks that the hash is at the index of a list with this root
+    pub fn verify(&self, hash: &[u8; 32], root: &[u8; 32]) -> bool {
+        self.root_of(hash).is_some_and(|computed| computed == *root)
+    }
+
+    /// The root of the list if the hash is at the index, None if the siblings do not fit the index and length
+    pub fn root_of(&self, hash: &[u8; 32]) -> Option<[u8; 32]> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut current = leaf(hash);
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+        while width > 1 {
+            // the last node of an odd level has no sibling
+            if position ^ 1 < width {
+                let sibling = siblings.next()?;
+                current = if position.is_multiple_of(2) {
+                    node(&current, sibling)
+                } else {
+                    node(sibling, &current)
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none().then_some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(n: u8) -> Vec<[u8; 32]> {
+        (0..n).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn every_leaf_can_be_proven() {
+        for n in 1..=9 {
+            let hashes = hashes(n);
+            let root = root(&hashes);
+            for (i, hash) in hashes.iter().enumerate() {
+                let proof = MerkleProof::new(&hashes, i).unwrap();
+                assert!(proof.verify(hash, &root));
+                assert!(!proof.verify(&[99; 32], &root));
+
+                let mut moved = proof.clone();
+                moved.index = (moved.index + 1) % n as u64;
+                assert!(n == 1 || !moved.verify(hash, &root));
+            }
+            assert_eq!(MerkleProof::new(&hashes, n as usize), None);
+        }
+    }
+
+    #[test]
+    fn roots_commit_to_order_and_length() {
+        let three = hashes(3);
+        let mut swapped = three.clone();
+        swapped.swap(0, 1);
+        // an odd node is not duplicated, so repeating the last hash changes the root
+        let mut repeated = three.clone();
+        repeated.push(three[2]);
+
+        assert_ne!(root(&three), root(&swapped));
+        assert_ne!(root(&three), root(&repeated));
+        assert_ne!(root(&[]), root(&hashes(1)));
+    }
+}
@@ -0,0 +1,311 @@
+// what an operator wants to watch on a running node. The counters follow the chain events the client receives
+// and the gauges are read from the chain and the network when the metrics are asked for.
+// they are served in the Prometheus text format on an optional HTTP endpoint
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    account::AccountId,
+    block::BlockHash,
+    blockchain::{Blockchain, BlockchainError},
+    events::ChainEvent,
+    logging,
+    pippi::seen_items::GossipStats,
+    sync::SyncProgress,
+    ClientMessage, Error, Timeslot,
+};
+
+const MAX_HEADER_LINES: usize = 64;
+
+/// The metrics of a node at the time they were read
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeMetrics {
+    pub chain_height: u64, // the depth of the tip
+    pub best_hash: BlockHash, // the hash of the tip
+    pub timeslot: Timeslot, // the current timeslot of the chain
+    pub reorgs: u64,
+    pub orphan_pool_size: usize,
+    pub mempool_size: usize,
+    pub peers_connected: usize,
+    pub blocks_accepted: u64,
+    pub blocks_rejected: BTreeMap<&'static str, u64>, // by the reason they were rejected
+    pub transactions_processed: u64, // confirmed on the best path, again if they are confirmed after a reorg
+    pub transactions_pruned: u64, // dropped from the mempool by the cleanup
+    pub block_verifications: u64,
+    pub block_verification_time: Duration, // the sum of the verifications
+    pub gossip: GossipStats, // blocks and transactions sent to peers and the copies we dropped
+    pub sync: Option<SyncProgress>, // of the last batch we applied, none if we never synced
+}
+
+impl NodeMetrics {
+    /// Counts the event
+    pub fn observe(&mut self, event: &ChainEvent) {
+        match event {
+            ChainEvent::BlockAccepted(_) => self.blocks_accepted += 1,
+            ChainEvent::BlockRejected { reason, .. } => {
+                *self.blocks_rejected.entry(rejection_label(reason)).or_default() += 1
+            }
+            ChainEvent::BlockVerified(took) => {
+                self.block_verifications += 1;
+                self.block_verification_time += *took;
+            }
+            ChainEvent::Reorg { .. } => self.reorgs += 1,
+            ChainEvent::NewTip((_, depth)) => self.chain_height = *depth,
+            ChainEvent::TransactionConfirmed(_) => self.transactions_processed += 1,
+            ChainEvent::MempoolPruned { dropped } => self.transactions_pruned += *dropped as u64,
+            ChainEvent::BlockOrphaned(_)
+            | ChainEvent::BlockConnected(_)
+            | ChainEvent::BlockDisconnected(_)
+            | ChainEvent::ReorgRefused { .. } // counted with the rejections
+            | ChainEvent::Equivocation(_) => (),
+        }
+    }
+
+    /// Reads the gauges of the chain
+    pub fn sample(&mut self, blockchain: &Blockchain) {
+        let tip = blockchain.tip();
+        self.chain_height = tip.depth;
+        self.best_hash = tip.hash;
+        self.timeslot = blockchain.calculate_timeslot();
+        self.orphan_pool_size = blockchain.orphan_count();
+        self.mempool_size = blockchain.mempool_len();
+    }
+
+    /// The Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            writeln!(out, "# HELP lasagna_{name} {help}").unwrap();
+            writeln!(out, "# TYPE lasagna_{name} {kind}").unwrap();
+            writeln!(out, "lasagna_{name} {value}").unwrap();
+        };
+        metric("chain_height", "gauge", "The depth of the tip of the best path", &self.chain_height);
+        metric("reorgs_total", "counter", "Times the best path switched branch", &self.reorgs);
+        metric("orphan_pool_size", "gauge", "Blocks waiting for their parent", &self.orphan_pool_size);
+        metric("mempool_size", "gauge", "Transactions waiting to be included", &self.mempool_size);
+        metric("peers_connected", "gauge", "Open connections to other nodes", &self.peers_connected);
+        metric("blocks_accepted_total", "counter", "Blocks added to the block tree", &self.blocks_accepted);
+        metric(
+            "transactions_processed_total",
+            "counter",
+            "Transactions confirmed on the best path",
+            &self.transactions_processed,
+        );
+        metric(
+            "transactions_pruned_total",
+            "counter",
+            "Transactions the mempool cleanup dropped",
+            &self.transactions_pruned,
+        );
+        let sync = self.sync.unwrap_or_default();
+        metric("sync_current_depth", "gauge", "The depth the sync from a peer has reached", &sync.current_depth);
+        metric("sync_target_depth", "gauge", "The tip of the peer we sync from", &sync.target_depth);
+
+        writeln!(out, "# HELP lasagna_blocks_rejected_total Blocks that broke a rule of the chain").unwrap();
+        writeln!(out, "# TYPE lasagna_blocks_rejected_total counter").unwrap();
+        for (reason, count) in self.blocks_rejected.iter() {
+            writeln!(out, "lasagna_blocks_rejected_total{{reason=\"{reason}\"}} {count}").unwrap();
+        }
+
+        writeln!(out, "# HELP lasagna_gossip_sent_total Blocks and transactions sent to peers").unwrap();
+        writeln!(out, "# TYPE lasagna_gossip_sent_total counter").unwrap();
+        writeln!(out, "lasagna_gossip_sent_total{{kind=\"block\"}} {}", self.gossip.blocks_sent).unwrap();
+        writeln!(out, "lasagna_gossip_sent_total{{kind=\"transaction\"}} {}", self.gossip.transactions_sent).unwrap();
+        writeln!(out, "# HELP lasagna_gossip_duplicates_total Flooded blocks and transactions dropped because we had seen them").unwrap();
+        writeln!(out, "# TYPE lasagna_gossip_duplicates_total counter").unwrap();
+        writeln!(out, "lasagna_gossip_duplicates_total{{kind=\"block\"}} {}", self.gossip.duplicate_blocks).unwrap();
+        writeln!(
+            out,
+            "lasagna_gossip_duplicates_total{{kind=\"transaction\"}} {}",
+            self.gossip.duplicate_transactions
+        )
+        .unwrap();
+        writeln!(out, "# HELP lasagna_gossip_dropped_total Flooded blocks and transactions dropped from the full queue of a slow peer").unwrap();
+        writeln!(out, "# TYPE lasagna_gossip_dropped_total counter").unwrap();
+        writeln!(out, "lasagna_gossip_dropped_total{{kind=\"block\"}} {}", self.gossip.blocks_dropped).unwrap();
+        writeln!(
+            out,
+            "lasagna_gossip_dropped_total{{kind=\"transaction\"}} {}",
+            self.gossip.transactions_dropped
+        )
+        .unwrap();
+        writeln!(out, "# HELP lasagna_gossip_announce_hits_total Announced blocks we had seen, so we did not ask for them").unwrap();
+        writeln!(out, "# TYPE lasagna_gossip_announce_hits_total counter").unwrap();
+        writeln!(out, "lasagna_gossip_announce_hits_total {}", self.gossip.announce_hits).unwrap();
+        writeln!(out, "# HELP lasagna_gossip_blocks_fetched_total Blocks peers sent us because we asked for them").unwrap();
+        writeln!(out, "# TYPE lasagna_gossip_blocks_fetched_total counter").unwrap();
+        writeln!(out, "lasagna_gossip_blocks_fetched_total {}", self.gossip.blocks_fetched).unwrap();
+
+        writeln!(out, "# HELP lasagna_block_verification_seconds Time spent checking the signatures of blocks").unwrap();
+        writeln!(out, "# TYPE lasagna_block_verification_seconds summary").unwrap();
+        writeln!(
+            out,
+            "lasagna_block_verification_seconds_sum {}",
+            self.block_verification_time.as_secs_f64()
+        )
+        .unwrap();
+        writeln!(out, "lasagna_block_verification_seconds_count {}", self.block_verifications).unwrap();
+        out
+    }
+}
+
+/// What a node tells about itself, its metrics and what the client knows beyond them
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStatus {
+    pub uptime: Duration,
+    pub metrics: NodeMetrics,
+    pub syncing: bool, // a sync we asked for has not reached the tip of the peer yet
+    pub staking: bool, // a producer runs on the chain
+    pub staking_address: AccountId, // of the key the node produces blocks with
+}
+
+fn rejection_label(reason: &BlockchainError) -> &'static str {
+    use BlockchainError::*;
+    match reason {
+        InvalidSignature => "invalid_signature",
+        UnableToVerifyBlock => "unverified_transactions",
+        InvalidTimeslot => "invalid_timeslot",
+        InvalidDepth => "invalid_depth",
+        InvalidSeed => "invalid_seed",
+        DrawMismatch => "draw_mismatch",
+        ProposerMismatch => "proposer_mismatch",
+        KeyTooSmall => "key_too_small",
+        FalseWinner => "false_winner",
+        EquivocatingWinner => "equivocating_winner",
+        ReorgTooDeep => "reorg_too_deep",
+        BlockTooLarge => "too_large",
+        StateRootMismatch => "state_root_mismatch",
+        ExpiredTransaction => "expired_transaction",
+        _ => "other",
+    }
+}
+
+/// Starts serving the metrics on `GET /metrics` and returns the address that was bound
+pub async fn serve(
+    addr: SocketAddr,
+    client: mpsc::Sender<ClientMessage>,
+) -> crate::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|_| Error::NetworkError)?;
+    let addr = listener.local_addr().map_err(|_| Error::NetworkError)?;
+    tracing::info!(target: logging::RPC, url = %format!("http://{addr}/metrics"), "serving metrics");
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(error) = handle_connection(stream, client).await {
+                    tracing::debug!(target: logging::RPC, ?error, "metrics connection failed");
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    client: mpsc::Sender<ClientMessage>,
+) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    for _ in 0..MAX_HEADER_LINES {
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    if (parts.next(), parts.next()) != (Some("GET"), Some("/metrics")) {
+        let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        return stream.get_mut().write_all(response.as_bytes()).await;
+    }
+
+    let (reply, rx) = oneshot::channel();
+    let body = match client.send(ClientMessage::Metrics(reply)).await {
+        Ok(()) => rx.await.unwrap_or_default().to_prometheus(),
+        Err(_) => NodeMetrics::default().to_prometheus(),
+    };
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.get_mut().write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        block::Block, clock::ManualClock, draw::DrawCache, params::ChainParams, seeding_mechanism::SeedContent,
+        transaction::Transaction,
+    };
+
+    #[test]
+    fn counters_follow_the_chain() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let mut blockchain =
+            Blockchain::start_with_clock(vec![pk.clone()], ChainParams::default(), Arc::new(clock.clone()));
+        let mut events = blockchain.subscribe();
+        let mut metrics = NodeMetrics::default();
+
+        let (_, receiver) = crate::generate_keypair();
+        let payment = Transaction::new(pk.clone(), receiver, &sk, 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment), Ok(false));
+        metrics.sample(&blockchain);
+        assert_eq!(metrics.mempool_size, 1);
+
+        let mut draws = DrawCache::default();
+        let mut produced = 0;
+        while produced < 3 {
+            clock.advance_slots(1);
+            if let Some(block) = blockchain.try_produce(&sk, &mut draws) {
+                assert_eq!(blockchain.add_block(block), Ok(()));
+                produced += 1;
+            }
+        }
+        // only the genesis block can be at depth 0
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        let at_genesis = Block::new(Timeslot(1), blockchain.get_best_hash(), 0, pk, vec![], [0; 32], &sk, seed);
+        assert!(blockchain.add_block(at_genesis).is_err());
+
+        while let Ok(event) = events.try_recv() {
+            metrics.observe(&event);
+        }
+        assert_eq!(metrics.chain_height, 3);
+        assert_eq!(metrics.blocks_accepted, 3);
+        assert_eq!(metrics.transactions_processed, 1);
+        assert_eq!(metrics.block_verifications, 4);
+        assert!(metrics.block_verification_time > Duration::ZERO);
+        assert_eq!(metrics.blocks_rejected, BTreeMap::from([("invalid_depth", 1)]));
+        assert_eq!(metrics.reorgs, 0);
+
+        metrics.sample(&blockchain);
+        assert_eq!(metrics.mempool_size, 0);
+        let text = metrics.to_prometheus();
+        assert!(text.contains("lasagna_chain_height 3\n"));
+        assert!(text.contains("lasagna_blocks_rejected_total{reason=\"invalid_depth\"} 1\n"));
+        assert!(text.contains("lasagna_block_verification_seconds_count 4\n"));
+    }
+}
@@ -1,33 +1,160 @@
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use crate::{
-    block::Block,
-    blockchain::Blockchain,
-    pippi::{message_handling::DefaultMessageHandlingStrategy, peer::Peer, PippiError},
+    account::AccountId,
+    address_book::AddressBook,
+    block::{Block, BlockHash},
+    blockchain::{Blockchain, Genesis, Snapshot},
+    logging,
+    policy,
+    sync::SyncProgress,
+    pippi::{
+        handshake::{ChainId, Handshake},
+        message_handling::DefaultMessageHandlingStrategy,
+        peer::Peer,
+        seen_items::GossipStats,
+        Message, MessageContent, PippiError, SNAPSHOT_PARTS_VERSION,
+    },
     transaction::Transaction,
-    ExternalMessage, Error,
+    ExternalMessage, Error, DIAL_TIMEOUT, MAX_RECONNECT_BACKOFF, RECONNECT_BACKOFF, RECONNECT_CHECK, REDIAL_PEERS,
+    SNAPSHOT_PART_BYTES,
 };
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{mpsc::Sender, oneshot};
 
 use crate::ClientMessage;
 
+/// How a node is connected to the network, a root node has no seeds and is never reconnecting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionState {
+    pub seed: Option<SocketAddr>, // the seed or node of the address book we last joined through, none if none has answered yet
+    pub peers: usize,
+    pub failed_dials: u32, // rounds of the seeds that failed since we last had a peer
+}
+
 struct NetworkActor {
-    seed_addr: SocketAddr,
+    seeds: Vec<SocketAddr>, // tried in order when we have no peers, empty for the seed node of the network
+    seed_addr: SocketAddr,  // the seed we last joined through, we ask it for what a new node needs
+    joined: bool,
+    failed_dials: u32,
+    next_dial: u128, // no seed is dialed before this time
+    book: AddressBook, // the nodes we have been connected to, the client writes it to the data directory
     peer: Peer<DefaultMessageHandlingStrategy>,
+    client_tx: Sender<ClientMessage>,
 }
 
 impl NetworkActor {
-    /// If we are the seed node set seed_addr = addr
+    /// Without seeds we are the seed node. A node whose seeds are all down still starts, it dials them again later
     async fn new(
-        seed_addr: SocketAddr,
+        seeds: Vec<SocketAddr>,
         addr: SocketAddr,
+        handshake: Handshake,
+        book: AddressBook,
+        max_frame_bytes: u64,
+        upload_bytes_per_sec: Option<u64>,
         sending_channel: Sender<ClientMessage>,
     ) -> Result<Self, PippiError> {
-        let peer = Peer::new(addr, sending_channel)?;
-        if addr != seed_addr {
-            peer.join_network(&seed_addr).await?;
+        let peer = Peer::new(addr, sending_channel.clone(), handshake, max_frame_bytes, upload_bytes_per_sec)?;
+        // the bans of the last run are not over because we restarted
+        for (banned, until) in book.banned(crate::get_unix_timestamp()) {
+            peer.ban(banned, until).await;
+        }
+        let mut actor = Self {
+            seed_addr: seeds.first().copied().unwrap_or(addr),
+            seeds,
+            joined: false,
+            failed_dials: 0,
+            next_dial: 0,
+            book,
+            peer,
+            client_tx: sending_channel,
+        };
+        if !actor.seeds.is_empty() {
+            actor.dial_seeds().await;
+        }
+        Ok(actor)
+    }
+
+    // the nodes of the address book are dialed before the seeds, so we rejoin where we left even if the seeds
+    // are gone. The seeds are tried in order and we join through the first that answers, after a round where
+    // none of them did we wait twice as long as after the round before
+    async fn dial_seeds(&mut self) -> bool {
+        if let Some(known) = self.dial_known_peers().await {
+            tracing::info!(target: logging::NET, peer = %known, "rejoined the network");
+            return self.joined_through(known);
+        }
+        for seed in self.seeds.clone() {
+            if self.dial(seed).await {
+                tracing::info!(target: logging::NET, peer = %seed, "joined the network");
+                return self.joined_through(seed);
+            }
+            tracing::warn!(target: logging::NET, peer = %seed, "unable to reach the seed");
+        }
+        let backoff = RECONNECT_BACKOFF << self.failed_dials.min(16);
+        self.failed_dials += 1;
+        self.next_dial = crate::get_unix_timestamp() + backoff.min(MAX_RECONNECT_BACKOFF);
+        false
+    }
+
+    // every node we reach becomes a peer, we ask the first for what a new node needs
+    async fn dial_known_peers(&mut self) -> Option<SocketAddr> {
+        let known: Vec<_> = self
+            .book
+            .dialable(crate::get_unix_timestamp())
+            .into_iter()
+            .filter(|addr| *addr != self.peer.address && !self.seeds.contains(addr))
+            .take(REDIAL_PEERS)
+            .collect();
+        let mut first = None;
+        for addr in known {
+            if self.dial(addr).await {
+                first = first.or(Some(addr));
+            }
+        }
+        first
+    }
+
+    // whether the node let us join the network through it, the address book keeps count
+    async fn dial(&mut self, addr: SocketAddr) -> bool {
+        let dial = tokio::time::timeout(Duration::from_millis(DIAL_TIMEOUT), self.peer.join_network(&addr));
+        let joined = matches!(dial.await, Ok(Ok(())));
+        if joined {
+            self.book.dialed(addr, crate::get_unix_timestamp());
+        } else {
+            self.book.dial_failed(addr);
+        }
+        joined
+    }
+
+    fn joined_through(&mut self, addr: SocketAddr) -> bool {
+        self.seed_addr = addr;
+        self.joined = true;
+        self.failed_dials = 0;
+        true
+    }
+
+    // the address book hears of our peers, the ones we learned on the peer walk too
+    async fn record_peers(&mut self) {
+        let now = crate::get_unix_timestamp();
+        for peer in self.peer.dialable_peers().await {
+            self.book.seen(peer, now);
+        }
+    }
+
+    // peers that stop sending heartbeats are dropped, when the last one is gone we dial the seeds again
+    async fn check_connection(&mut self) {
+        self.record_peers().await;
+        if self.seeds.is_empty() || !self.peer.get_peerset().await.is_empty() {
+            self.failed_dials = 0;
+            return;
+        }
+        if crate::get_unix_timestamp() < self.next_dial {
+            return;
+        }
+        if self.dial_seeds().await {
+            // the client may have stopped
+            let _ = self.client_tx.send(ClientMessage::Reconnected(self.seed_addr)).await;
         }
-        Ok(Self { seed_addr, peer })
     }
 
     async fn handle_message(&mut self, msg: NetworkActorMessage) {
@@ -41,30 +168,164 @@ impl NetworkActor {
                     )
                     .await;
             }
-            BroadcastBlock(block) => {
+            RequestSnapshot => {
                 self.peer
-                    .flood(ExternalMessage::BroadcastBlock(block))
+                    .send_direct_client_message(
+                        self.seed_addr,
+                        ExternalMessage::SnapshotReqFrom(self.peer.address),
+                    )
                     .await;
             }
+            RequestGenesis => {
+                self.peer
+                    .send_direct_client_message(
+                        self.seed_addr,
+                        ExternalMessage::GenesisReqFrom(self.peer.address),
+                    )
+                    .await;
+            }
+            BroadcastBlock(block, except) => {
+                self.peer.announce(&block, except).await;
+            }
             Bootstrap(to, blockchain) => {
                 self.peer
                     .send_direct_client_message(to, ExternalMessage::Bootstrap(blockchain))
                     .await;
             }
+            // a snapshot in one message is larger than the frame limit on a long chain, the nodes that put one
+            // together get it in parts
+            Snapshot(to, snapshot) => {
+                let in_parts = self.peer.protocol_version(&to).await.is_some_and(|v| v >= SNAPSHOT_PARTS_VERSION);
+                if in_parts {
+                    let parts = snapshot.to_parts(SNAPSHOT_PART_BYTES as usize);
+                    let count = parts.len() as u32;
+                    for (part, bytes) in (0..).zip(parts) {
+                        let message = ExternalMessage::SnapshotPart { part, parts: count, bytes };
+                        self.peer.send_direct_client_message(to, message).await;
+                    }
+                } else {
+                    self.peer
+                        .send_direct_client_message(to, ExternalMessage::Snapshot(snapshot))
+                        .await;
+                }
+            }
+            Genesis(to, genesis) => {
+                self.peer
+                    .send_direct_client_message(to, ExternalMessage::Genesis(genesis))
+                    .await;
+            }
             BroadcastTransaction(t) => {
                 self.peer
                     .flood(ExternalMessage::BroadcastTransaction(t))
                     .await;
             }
+            Balance(to, account, balance) => {
+                self.peer
+                    .send_direct_client_message(to, ExternalMessage::Balance(account, balance))
+                    .await;
+            }
+            RequestBlock(from, hash) => {
+                let request = Message::new_direct_message(
+                    &self.peer.address,
+                    MessageContent::App(ExternalMessage::BlockReqFrom(self.peer.address, hash)),
+                );
+                // the block may have been flooded to us by a node we are not connected to, then we ask our peers
+                if self.peer.send_to(&request, &from).await.is_err() {
+                    self.peer.broadcast_to_peerset(request).await;
+                }
+            }
+            RequestSync(from, locator) => {
+                let request = Message::new_direct_message(
+                    &self.peer.address,
+                    MessageContent::App(ExternalMessage::SyncReqFrom(self.peer.address, locator)),
+                );
+                // the node may have gone, then any peer that is ahead can serve us
+                if self.peer.send_to(&request, &from.unwrap_or(self.seed_addr)).await.is_err() {
+                    self.peer.broadcast_to_peerset(request).await;
+                }
+            }
+            SyncBatch(to, progress, blocks) => {
+                self.peer
+                    .send_direct_client_message(to, ExternalMessage::SyncBatch(progress, blocks))
+                    .await;
+            }
+            SendBlock(to, block) => {
+                self.peer
+                    .send_direct_client_message(to, ExternalMessage::RequestedBlock(block))
+                    .await;
+            }
+            PeerCount(callback) => {
+                let _ = callback.send(self.peer.connections_len().await);
+            }
+            Peers(callback) => {
+                let mut peers: Vec<_> = self.peer.get_peerset().await.into_iter().collect();
+                peers.sort();
+                let _ = callback.send(peers);
+            }
+            Gossip(callback) => {
+                let _ = callback.send(self.peer.gossip_stats());
+            }
+            Ban(addr, until) => {
+                self.book.ban(addr, until);
+                self.peer.ban(addr, until).await;
+            }
+            Chain(chain, depth) => {
+                self.peer.set_chain(chain);
+                self.peer.set_best_depth(depth);
+            }
+            BestDepth(depth) => self.peer.set_best_depth(depth),
+            RelayPolicy(policy) => self.peer.set_relay_policy(policy),
+            CheckConnection => self.check_connection().await,
+            Connection(callback) => {
+                let _ = callback.send(ConnectionState {
+                    seed: self.joined.then_some(self.seed_addr),
+                    peers: self.peer.get_peerset().await.len(),
+                    failed_dials: self.failed_dials,
+                });
+            }
+            AddressBook(callback) => {
+                self.record_peers().await;
+                let _ = callback.send(self.book.clone());
+            }
+            ImportPeers(addrs, callback) => {
+                let own = self.peer.address;
+                let _ = callback.send(self.book.add(addrs.into_iter().filter(|addr| *addr != own)));
+            }
+            Shutdown(callback) => {
+                self.record_peers().await;
+                self.peer.shutdown().await;
+                let _ = callback.send(std::mem::take(&mut self.book));
+            }
         }
     }
 }
 
 enum NetworkActorMessage {
     RequestBootstrap,
-    BroadcastBlock(Block),
+    RequestSnapshot,
+    RequestGenesis,
+    BroadcastBlock(Block, Option<SocketAddr>), // announced to our peers except the one it came from
     BroadcastTransaction(Transaction),
     Bootstrap(SocketAddr, Blockchain),
+    Snapshot(SocketAddr, Box<Snapshot>),
+    Genesis(SocketAddr, Box<Genesis>),
+    Balance(SocketAddr, AccountId, u64),
+    RequestBlock(SocketAddr, BlockHash), // asks the node for the block, or our peers if we can not reach it
+    SendBlock(SocketAddr, Block),
+    RequestSync(Option<SocketAddr>, Vec<BlockHash>), // asks the node, the seed node if none, for the blocks above the locator, or our peers if it is gone
+    SyncBatch(SocketAddr, SyncProgress, Vec<Block>),
+    PeerCount(oneshot::Sender<usize>),
+    Peers(oneshot::Sender<Vec<SocketAddr>>),
+    Gossip(oneshot::Sender<GossipStats>),
+    Ban(SocketAddr, u128), // until then
+    Chain(ChainId, u64), // the network we follow and our best depth, for the handshakes from now on
+    BestDepth(u64),
+    RelayPolicy(policy::RelayPolicy), // the transactions we neither flood nor pass on from now on
+    CheckConnection, // dials the seeds if we have lost every peer
+    Connection(oneshot::Sender<ConnectionState>),
+    AddressBook(oneshot::Sender<AddressBook>), // with our peers seen now
+    ImportPeers(Vec<SocketAddr>, oneshot::Sender<usize>), // replies with how many of the addresses were new
+    Shutdown(oneshot::Sender<AddressBook>), // replies with the address book, our last peers seen now
 }
 
 #[derive(Clone)]
@@ -73,13 +334,41 @@ pub struct NetworkHandle {
 }
 
 impl NetworkHandle {
-    /// If we are creating the network set seed_addr = addr
-    pub fn new(seed_addr: SocketAddr, addr: SocketAddr, client_tx: Sender<ClientMessage>) -> Self {
+    /// If we are creating the network there are no seeds, otherwise they are tried in order until one answers
+    /// and again whenever we lose every peer, after the nodes of the address book. The handshake is what we
+    /// tell the seed node.
+    /// Peers that send a message longer than max_frame_bytes are disconnected, and we send each peer at most
+    /// upload_bytes_per_sec if it is set
+    pub async fn new(
+        seeds: Vec<SocketAddr>,
+        addr: SocketAddr,
+        handshake: Handshake,
+        book: AddressBook,
+        max_frame_bytes: u64,
+        upload_bytes_per_sec: Option<u64>,
+        client_tx: Sender<ClientMessage>,
+    ) -> Self {
         let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
+        let mut actor = NetworkActor::new(seeds, addr, handshake, book, max_frame_bytes, upload_bytes_per_sec, client_tx)
+            .await
+            .unwrap();
+        let monitor = sender.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(RECONNECT_CHECK)).await;
+                // the network has stopped
+                if monitor.send(NetworkActorMessage::CheckConnection).await.is_err() {
+                    break;
+                }
+            }
+        });
         tokio::spawn(async move {
-            let mut actor = NetworkActor::new(seed_addr, addr, client_tx).await.unwrap();
             while let Some(msg) = receiver.recv().await {
-                actor.handle_message(msg).await
+                let stop = matches!(msg, NetworkActorMessage::Shutdown(_));
+                actor.handle_message(msg).await;
+                if stop {
+                    break;
+                }
             }
         });
 
@@ -93,9 +382,33 @@ impl NetworkHandle {
             .map_err(|_| Error::NetworkError)
     }
 
+    /// Asks the seed node for a snapshot instead of its whole blockchain
+    pub async fn request_snapshot(&self) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::RequestSnapshot)
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// Asks the seed node for the genesis block of the network
+    pub async fn request_genesis(&self) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::RequestGenesis)
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
     pub async fn broadcast_block(&self, block: Block) -> crate::Result<()> {
         self.sender
-            .send(NetworkActorMessage::BroadcastBlock(block))
+            .send(NetworkActorMessage::BroadcastBlock(block, None))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// Announces a block we asked the node for to our other peers
+    pub async fn relay_block(&self, block: Block, from: SocketAddr) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::BroadcastBlock(block, Some(from)))
             .await
             .map_err(|_| Error::NetworkError)
     }
@@ -111,6 +424,169 @@ impl NetworkHandle {
             .map_err(|_| Error::NetworkError)
     }
 
+    pub async fn send_snapshot_to(&self, to: SocketAddr, snapshot: Snapshot) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::Snapshot(to, Box::new(snapshot)))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    pub async fn send_genesis_to(&self, to: SocketAddr, genesis: Genesis) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::Genesis(to, Box::new(genesis)))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    pub async fn send_balance_to(
+        &self,
+        to: SocketAddr,
+        account: AccountId,
+        balance: u64,
+    ) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::Balance(to, account, balance))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    pub async fn request_block(&self, from: SocketAddr, hash: BlockHash) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::RequestBlock(from, hash))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    pub async fn send_block_to(&self, to: SocketAddr, block: Block) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::SendBlock(to, block))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// Asks for the blocks of the best path of the node, or of the seed node if none, above the last block of the
+    /// locator we share
+    pub async fn request_sync(&self, from: Option<SocketAddr>, locator: Vec<BlockHash>) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::RequestSync(from, locator))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    pub async fn send_sync_batch_to(
+        &self,
+        to: SocketAddr,
+        progress: SyncProgress,
+        blocks: Vec<Block>,
+    ) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::SyncBatch(to, progress, blocks))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// The number of open connections to other nodes
+    pub async fn peer_count(&self) -> crate::Result<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::PeerCount(tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
+    /// The nodes we are connected to
+    pub async fn peers(&self) -> crate::Result<Vec<SocketAddr>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::Peers(tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
+    /// How many blocks and transactions we flooded and how many copies we had already seen
+    pub async fn gossip_stats(&self) -> crate::Result<GossipStats> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::Gossip(tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
+    /// Disconnects the node and refuses it until the time in milliseconds
+    pub async fn ban(&self, addr: SocketAddr, until: u128) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::Ban(addr, until))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// Tells the nodes we make contact with from now on that we follow the network at this depth
+    pub async fn set_chain(&self, chain: ChainId, depth: u64) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::Chain(chain, depth))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    pub async fn set_best_depth(&self, depth: u64) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::BestDepth(depth))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// The transactions of the accounts the policy denies are neither flooded nor passed on from now on
+    pub async fn set_relay_policy(&self, policy: policy::RelayPolicy) -> crate::Result<()> {
+        self.sender
+            .send(NetworkActorMessage::RelayPolicy(policy))
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+
+    /// The seed we joined through and how many peers we have
+    pub async fn connection_state(&self) -> crate::Result<ConnectionState> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::Connection(tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
+    /// The nodes we have been connected to, for the client to write to the data directory
+    pub async fn address_book(&self) -> crate::Result<AddressBook> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::AddressBook(tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
+    /// Adds the addresses to the address book, they are dialed the next time we have no peers.
+    /// Returns how many of them were new
+    pub async fn import_peers(&self, addrs: Vec<SocketAddr>) -> crate::Result<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::ImportPeers(addrs, tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
+    /// Says goodbye to the nodes we are connected to and stops the network, returns the address book
+    pub async fn shutdown(&self) -> crate::Result<AddressBook> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(NetworkActorMessage::Shutdown(tx))
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        rx.await.map_err(|_| Error::NetworkError)
+    }
+
     pub async fn broadcast_transaction(
         &self,
         transaction: crate::transaction::Transaction,
@@ -0,0 +1,301 @@
+// a node embedded in another tokio application, the binaries are built on this too
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::{
+    account::AccountId,
+    block_producer::ProductionPolicy,
+    blockchain::{BufferLimits, Genesis, TipRef},
+    client::{ClientActor, ClientHandle, NodeConfig, DEV_KEY_FILE},
+    events::ChainEvent,
+    keys::{PublicKey, SecretKey},
+    keystore,
+    metrics::NodeStatus,
+    network_actor::ConnectionState,
+    params::ChainParams,
+    peer_scores::PeerLimits,
+    policy::RelayPolicy,
+    store::ChainStore,
+    transaction::Transaction,
+    ClientMessage, Error, Result, DEV_SLOT_LENGTH,
+};
+
+/// Starts a node, either the root node of a new network or one that joins the network of a seed node
+///
+/// ```
+/// use lasagna::{account::AccountId, generate_keypair, node::NodeBuilder, transaction::Transaction};
+///
+/// # #[tokio::main]
+/// # async fn main() -> lasagna::Result<()> {
+/// let (sk, pk) = generate_keypair();
+/// let (_, bob) = generate_keypair();
+/// // a new network where we hold all the money
+/// let node = NodeBuilder::root("127.0.0.1:9560".parse().unwrap(), vec![pk.clone()], sk.clone())
+///     .start()
+///     .await?;
+/// let mut events = node.subscribe_events();
+/// assert_eq!(node.get_balance(AccountId::from(&pk)).await?, lasagna::ROOT_AMOUNT);
+///
+/// let transaction = Transaction::new(pk, bob, &sk, 50, 0)?;
+/// let hash = node.submit_transaction(transaction).await?;
+/// println!("{} is pending at depth {}", hex::encode(hash), node.best_tip().await?.depth);
+///
+/// node.shutdown().await;
+/// // the events end with the node
+/// while events.recv().await.is_ok() {}
+/// # Ok(())
+/// # }
+/// ```
+pub struct NodeBuilder {
+    addr: SocketAddr,
+    key: SecretKey,
+    seeds: Option<Vec<SocketAddr>>, // none for a root node
+    root_accounts: Vec<PublicKey>,
+    config: NodeConfig,
+    cli: bool,
+    #[cfg(feature = "rpc")]
+    rpc: Option<SocketAddr>,
+    #[cfg(feature = "rpc")]
+    ws: Option<SocketAddr>,
+    metrics: Option<SocketAddr>,
+}
+
+impl NodeBuilder {
+    /// A node that joins the network of the seed node, it starts from a snapshot unless it has a stored chain
+    pub fn join(addr: SocketAddr, seed: SocketAddr, key: SecretKey) -> Self {
+        Self::join_any(addr, vec![seed], key)
+    }
+
+    /// A node that joins through the first of the seeds that answers, they are tried again in order
+    /// whenever the node loses every peer
+    pub fn join_any(addr: SocketAddr, seeds: Vec<SocketAddr>, key: SecretKey) -> Self {
+        Self::new(addr, Some(seeds), Vec::new(), key)
+    }
+
+    /// The first node of a new network, the root accounts share the money of the genesis block
+    pub fn root(addr: SocketAddr, root_accounts: Vec<PublicKey>, key: SecretKey) -> Self {
+        Self::new(addr, None, root_accounts, key)
+    }
+
+    /// The node of a dev chain of its own, to test applications against and never for a real network.
+    /// Every draw wins, the timeslots are `DEV_SLOT_LENGTH` long and the `dev_mine` RPC mines blocks at once.
+    /// The one funded account is the key of the node, written unencrypted to `DEV_KEY_FILE` in the data directory
+    pub fn dev(addr: SocketAddr, data_dir: impl Into<PathBuf>) -> Result<Self> {
+        let data_dir = data_dir.into();
+        let path = data_dir.join(DEV_KEY_FILE);
+        // a dev node that restarts continues its chain with the same account
+        let key = if path.exists() {
+            keystore::load(&path, || Err(Error::MissingPassphrase))?
+        } else {
+            std::fs::create_dir_all(&data_dir).map_err(|_| Error::StorageError)?;
+            let key = SecretKey::generate();
+            keystore::save_plaintext(&path, &key)?;
+            key
+        };
+        let params = ChainParams {
+            slot_length_ms: DEV_SLOT_LENGTH,
+            ..Default::default()
+        };
+        let mut node = Self::root(addr, vec![key.to_public_key()], key)
+            .params(params)
+            .data_dir(data_dir)
+            .production(ProductionPolicy {
+                produce_empty_blocks: false,
+                ..Default::default()
+            });
+        node.config.dev = true;
+        Ok(node)
+    }
+
+    fn new(addr: SocketAddr, seeds: Option<Vec<SocketAddr>>, root_accounts: Vec<PublicKey>, key: SecretKey) -> Self {
+        Self {
+            addr,
+            key,
+            seeds,
+            root_accounts,
+            config: NodeConfig::default(),
+            cli: false,
+            #[cfg(feature = "rpc")]
+            rpc: None,
+            #[cfg(feature = "rpc")]
+            ws: None,
+            metrics: None,
+        }
+    }
+
+    /// Continue from the chain stored here, and store the chain and the address book here on shutdown
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Append every accepted block to the store and continue from the chain in it, see `BlockLog`
+    pub fn store(mut self, store: impl ChainStore + 'static) -> Self {
+        self.config.store = Some(Arc::new(Mutex::new(store)));
+        self
+    }
+
+    /// The params of the chain a root node starts, a joining node takes those of the network
+    pub fn params(mut self, params: ChainParams) -> Self {
+        self.config.params = params;
+        self
+    }
+
+    /// The network a root node starts, from a `GenesisManifest`, instead of one of its root accounts and params
+    /// starting now
+    pub fn genesis(mut self, genesis: Genesis) -> Self {
+        self.config.genesis = Some(genesis);
+        self
+    }
+
+    /// How many transactions and blocks that are not on the chain the node holds
+    pub fn buffer_limits(mut self, limits: BufferLimits) -> Self {
+        self.config.buffers = limits;
+        self
+    }
+
+    /// How many blocks of the best path a reorg may roll back, a deeper one waits for `Blockchain::force_reorg`
+    pub fn max_reorg_depth(mut self, depth: u64) -> Self {
+        self.config.max_reorg_depth = Some(depth);
+        self
+    }
+
+    /// The accounts whose transactions the node neither takes into its mempool nor passes on, see `RelayPolicy`
+    pub fn relay_policy(mut self, policy: RelayPolicy) -> Self {
+        self.config.relay_policy = policy;
+        self
+    }
+
+    /// Stake with these accounts too, the node produces at most one block a timeslot with the best draw of its keys
+    pub fn staking_keys(mut self, keys: Vec<SecretKey>) -> Self {
+        self.config.staking_keys = keys;
+        self
+    }
+
+    /// Keep only the headers of the final blocks more than `depth` blocks below the tip, see `Blockchain::set_prune_depth`
+    pub fn prune_depth(mut self, depth: u64) -> Self {
+        self.config.prune_depth = Some(depth);
+        self
+    }
+
+    pub fn limits(mut self, limits: PeerLimits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    pub fn production(mut self, production: ProductionPolicy) -> Self {
+        self.config.production = production;
+        self
+    }
+
+    /// Read commands from stdin, see the README for the commands
+    pub fn cli(mut self) -> Self {
+        self.cli = true;
+        self
+    }
+
+    /// Serve JSON-RPC on this address
+    #[cfg(feature = "rpc")]
+    pub fn rpc(mut self, addr: SocketAddr) -> Self {
+        self.rpc = Some(addr);
+        self
+    }
+
+    /// Serve subscriptions to new blocks, reorgs and the payments of accounts on ws://<address>
+    #[cfg(feature = "rpc")]
+    pub fn ws(mut self, addr: SocketAddr) -> Self {
+        self.ws = Some(addr);
+        self
+    }
+
+    /// Serve Prometheus metrics on http://<address>/metrics
+    pub fn metrics(mut self, addr: SocketAddr) -> Self {
+        self.metrics = Some(addr);
+        self
+    }
+
+    /// Starts the node on the current runtime, it runs until it is shut down
+    pub async fn start(self) -> Result<NodeHandle> {
+        let client = match self.seeds {
+            Some(seeds) => ClientActor::start_with_seeds(seeds, self.addr, self.key, self.config).await,
+            None => ClientActor::start_root(self.addr, self.root_accounts, self.key, self.config).await,
+        };
+        if self.cli {
+            crate::cli::run_cli(client.sender().clone());
+        }
+        #[cfg(feature = "rpc")]
+        if let Some(addr) = self.rpc {
+            crate::rpc::serve(addr, client.sender().clone()).await?;
+        }
+        #[cfg(feature = "rpc")]
+        if let Some(addr) = self.ws {
+            crate::ws::serve(addr, client.events().clone()).await?;
+        }
+        if let Some(addr) = self.metrics {
+            crate::metrics::serve(addr, client.sender().clone()).await?;
+        }
+        Ok(NodeHandle { client })
+    }
+}
+
+/// A running node, it keeps running until it is shut down
+pub struct NodeHandle {
+    client: ClientHandle,
+}
+
+impl NodeHandle {
+    /// The channel to the client, for the messages that have no method here
+    pub fn sender(&self) -> &mpsc::Sender<ClientMessage> {
+        self.client.sender()
+    }
+
+    /// Adds a signed transaction to the mempool and floods it, it returns the hash of the transaction or why it was refused
+    pub async fn submit_transaction(&self, transaction: Transaction) -> Result<[u8; 32]> {
+        let hash = transaction.hash();
+        self.ask(|reply| ClientMessage::SubmitTransaction(transaction, reply)).await??;
+        Ok(hash)
+    }
+
+    /// The balance of the account at the tip
+    pub async fn get_balance(&self, account: AccountId) -> Result<u64> {
+        self.ask(|reply| ClientMessage::GetBalance(account, reply))
+            .await?
+            .ok_or(Error::NotReady)
+    }
+
+    /// The hash and depth of the head of the best path
+    pub async fn best_tip(&self) -> Result<TipRef> {
+        self.ask(ClientMessage::BestTip).await?.ok_or(Error::NotReady)
+    }
+
+    /// How long the node runs, its tip, sync, peers and buffers and whether it produces blocks
+    pub async fn status(&self) -> Result<NodeStatus> {
+        self.ask(ClientMessage::Status).await
+    }
+
+    /// The seed the node joined through and how many peers it has
+    pub async fn connection_state(&self) -> Result<ConnectionState> {
+        self.ask(ClientMessage::Connection).await
+    }
+
+    /// The events of the chain the node is on, see `ChainEvent`
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ChainEvent> {
+        self.client.subscribe_events()
+    }
+
+    /// Stops the node and resolves when it has stopped, with a data directory its state is written first
+    pub async fn shutdown(self) {
+        self.client.shutdown().await;
+        self.client.await_terminated().await;
+    }
+
+    async fn ask<T>(&self, message: impl FnOnce(oneshot::Sender<T>) -> ClientMessage) -> Result<T> {
+        let (reply, rx) = oneshot::channel();
+        self.sender().send(message(reply)).await.map_err(|_| Error::NodeStopped)?;
+        rx.await.map_err(|_| Error::NodeStopped)
+    }
+}
@@ -0,0 +1,76 @@
+// the consensus values of a network, a testnet can run with other economics than the defaults without recompiling
+
+use num_bigint::BigUint;
+use rsa::sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    difficulty, encoding::Writer, keys, BLOCK_REWARD, MAX_BLOCK_BYTES, MAX_BLOCK_TRANSACTIONS,
+    ROOT_AMOUNT, SLOT_LENGTH, STAKE_LOOKBACK, TRANSACTION_FEE,
+};
+
+/// Every node on a network must use the same params, they are hashed into the genesis seed
+/// so nodes with different params are on different chains from the start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainParams {
+    pub root_amount: u64,
+    pub block_reward: u64,
+    pub transaction_fee: u64, // the minimum fee of a transaction
+    pub slot_length_ms: u64,
+    pub hardness: BigUint, // the hardness of the first epoch, it is retargeted from there
+    pub max_block_transactions: u64,
+    pub max_block_bytes: u64, // the size of the canonical encoding of a block
+    pub stake_lookback: u64, // the lottery for a block uses the ledger this many blocks before it, at least 1
+    pub fee_sink: FeeSink,
+    pub min_key_bits: u64, // the smallest RSA key that signs a block or a transaction, keys of one size are not checked
+}
+
+/// Where the fees of the transactions in a block go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeSink {
+    /// Nobody gets them, the circulating supply shrinks by the fees
+    #[default]
+    Burn,
+    /// The winner of the block gets them with its reward
+    ToProposer,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self {
+            root_amount: ROOT_AMOUNT,
+            block_reward: BLOCK_REWARD,
+            transaction_fee: TRANSACTION_FEE,
+            slot_length_ms: SLOT_LENGTH as u64,
+            hardness: difficulty::initial_hardness(),
+            max_block_transactions: MAX_BLOCK_TRANSACTIONS,
+            max_block_bytes: MAX_BLOCK_BYTES,
+            stake_lookback: STAKE_LOOKBACK,
+            fee_sink: FeeSink::default(),
+            min_key_bits: keys::DEFAULT_KEY_BITS as u64,
+        }
+    }
+}
+
+impl ChainParams {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        Writer::new()
+            .u64(self.root_amount)
+            .u64(self.block_reward)
+            .u64(self.transaction_fee)
+            .u64(self.slot_length_ms)
+            .bytes(&self.hardness.to_bytes_be())
+            .u64(self.max_block_transactions)
+            .u64(self.max_block_bytes)
+            .u64(self.stake_lookback)
+            .u64(self.fee_sink as u64)
+            .u64(self.min_key_bits)
+            .finish()
+    }
+
+    /// The SHA-256 of the params, peers compare it before they exchange blocks
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.to_bytes()).into()
+    }
+}
@@ -0,0 +1,296 @@
+// every invalid block or transaction a peer sends costs us a signature check, so we keep score of how peers behave.
+// inbound messages are rate limited with token buckets and a peer is banned for a while once its misbehavior adds up
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::{blockchain::BlockchainError, BAN_COOLDOWN, BAN_SCORE, MAX_FRAME_BYTES, MAX_TRACKED_PEERS};
+
+/// How much a peer may send and how much misbehavior we take before banning it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerLimits {
+    pub messages_per_sec: u64,
+    pub message_burst: u64,
+    pub bytes_per_sec: u64,
+    pub byte_burst: u64,
+    pub ban_score: u64,
+    pub ban_cooldown: u128, // milliseconds
+    pub score_decay: u64, // points forgiven per second
+    pub invalid_block_score: u64,
+    pub invalid_transaction_score: u64,
+    pub malformed_score: u64,
+    pub rate_limited_score: u64, // for every message that is dropped
+    pub max_frame_bytes: u64, // a larger message is not read and the connection is dropped
+    pub upload_bytes_per_sec: Option<u64>, // what we send each peer at most, none for as fast as it reads
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: 20,
+            message_burst: 100,
+            bytes_per_sec: 1_000_000,
+            byte_burst: 10_000_000,
+            ban_score: BAN_SCORE,
+            ban_cooldown: BAN_COOLDOWN,
+            score_decay: 1,
+            invalid_block_score: 50,
+            invalid_transaction_score: 10,
+            malformed_score: 25,
+            rate_limited_score: 1,
+            max_frame_bytes: MAX_FRAME_BYTES,
+            upload_bytes_per_sec: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    InvalidBlock,
+    InvalidTransaction,
+    Malformed, // bytes that are not a message, or a frame over the limit
+    RateLimited,
+}
+
+/// What to do with a message from a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Handle,
+    Drop,
+    Ban(u128), // the peer is banned until then and must be disconnected
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerStats {
+    pub messages: u64,
+    pub bytes: u64,
+    pub invalid_blocks: u64,
+    pub invalid_transactions: u64,
+    pub malformed_messages: u64,
+    pub rate_limited: u64,
+    pub score: u64,
+    pub banned_until: Option<u128>,
+    message_tokens: TokenBucket,
+    byte_tokens: TokenBucket,
+    last_decay: u128,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct TokenBucket {
+    tokens: u64,
+    refilled_at: u128,
+}
+
+impl TokenBucket {
+    fn full(burst: u64, now: u128) -> Self {
+        Self {
+            tokens: burst,
+            refilled_at: now,
+        }
+    }
+
+    fn take(&mut self, amount: u64, per_sec: u64, burst: u64, now: u128) -> bool {
+        let elapsed = now.saturating_sub(self.refilled_at);
+        let refill = (elapsed * per_sec as u128 / 1_000) as u64;
+        // the time of a partial token is kept for the next refill
+        if refill > 0 {
+            self.tokens = self.tokens.saturating_add(refill);
+            if self.tokens >= burst {
+                self.tokens = burst;
+                self.refilled_at = now;
+            } else {
+                self.refilled_at += refill as u128 * 1_000 / per_sec as u128;
+            }
+        }
+        if self.tokens < amount {
+            return false;
+        }
+        self.tokens -= amount;
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PeerScores {
+    limits: PeerLimits,
+    peers: HashMap<SocketAddr, PeerStats>,
+}
+
+impl PeerScores {
+    pub(crate) fn new(limits: PeerLimits) -> Self {
+        Self {
+            limits,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Whether a message of this many bytes from the peer should be handled, dropped messages count against it
+    pub(crate) fn admit(&mut self, peer: SocketAddr, bytes: u64, now: u128) -> Verdict {
+        if self.is_banned(&peer, now) {
+            return Verdict::Drop;
+        }
+        let limits = &self.limits;
+        let stats = stats_mut(&mut self.peers, limits, peer, now);
+        stats.messages += 1;
+        stats.bytes += bytes;
+        let within_limits = stats
+            .message_tokens
+            .take(1, limits.messages_per_sec, limits.message_burst, now)
+            && stats
+                .byte_tokens
+                .take(bytes, limits.bytes_per_sec, limits.byte_burst, now);
+        if within_limits {
+            return Verdict::Handle;
+        }
+        match self.record(peer, Misbehavior::RateLimited, now) {
+            Some(until) => Verdict::Ban(until),
+            None => Verdict::Drop,
+        }
+    }
+
+    /// Counts the misbehavior and returns until when the peer is banned if this was too much
+    pub(crate) fn record(&mut self, peer: SocketAddr, misbehavior: Misbehavior, now: u128) -> Option<u128> {
+        if self.is_banned(&peer, now) {
+            return None;
+        }
+        let limits = &self.limits;
+        let stats = stats_mut(&mut self.peers, limits, peer, now);
+        let points = match misbehavior {
+            Misbehavior::InvalidBlock => {
+                stats.invalid_blocks += 1;
+                limits.invalid_block_score
+            }
+            Misbehavior::InvalidTransaction => {
+                stats.invalid_transactions += 1;
+                limits.invalid_transaction_score
+            }
+            Misbehavior::Malformed => {
+                stats.malformed_messages += 1;
+                limits.malformed_score
+            }
+            Misbehavior::RateLimited => {
+                stats.rate_limited += 1;
+                limits.rate_limited_score
+            }
+        };
+        stats.score = stats.score.saturating_add(points);
+        if stats.score < limits.ban_score {
+            return None;
+        }
+        let until = now + limits.ban_cooldown;
+        stats.banned_until = Some(until);
+        stats.score = 0;
+        Some(until)
+    }
+
+    pub(crate) fn is_banned(&self, peer: &SocketAddr, now: u128) -> bool {
+        self.peers
+            .get(peer)
+            .and_then(|stats| stats.banned_until)
+            .is_some_and(|until| until > now)
+    }
+
+    /// The peers that are banned and until when
+    pub(crate) fn banned(&self, now: u128) -> Vec<(SocketAddr, u128)> {
+        let mut banned: Vec<_> = self
+            .peers
+            .iter()
+            .filter_map(|(peer, stats)| Some((*peer, stats.banned_until?)))
+            .filter(|(_, until)| *until > now)
+            .collect();
+        banned.sort();
+        banned
+    }
+}
+
+fn stats_mut<'a>(
+    peers: &'a mut HashMap<SocketAddr, PeerStats>,
+    limits: &PeerLimits,
+    peer: SocketAddr,
+    now: u128,
+) -> &'a mut PeerStats {
+    // anyone can claim an address, so we forget the well behaved peers before the map grows too large
+    if peers.len() >= MAX_TRACKED_PEERS && !peers.contains_key(&peer) {
+        peers.retain(|_, stats| stats.score > 0 || stats.banned_until.is_some_and(|until| until > now));
+    }
+    let stats = peers.entry(peer).or_insert_with(|| PeerStats {
+        message_tokens: TokenBucket::full(limits.message_burst, now),
+        byte_tokens: TokenBucket::full(limits.byte_burst, now),
+        last_decay: now,
+        ..Default::default()
+    });
+    let forgiven = (now.saturating_sub(stats.last_decay) / 1_000) as u64 * limits.score_decay;
+    if forgiven > 0 {
+        stats.score = stats.score.saturating_sub(forgiven);
+        stats.last_decay = now;
+    }
+    stats
+}
+
+/// Whether the sender of a block we refused must have known it was invalid.
+/// Orphans, blocks from the future and blocks on forks we do not follow are sent by honest peers too
+pub(crate) fn is_invalid_block(reason: &BlockchainError) -> bool {
+    use BlockchainError::*;
+    matches!(
+        reason,
+        InvalidSignature
+            | InvalidGenesisBlock
+            | InvalidTransaction
+            | ExpiredTransaction
+            | HashMismatch
+            | UnableToVerifyBlock
+            | FalseWinner
+            | InvalidSeed
+            | DrawMismatch
+            | ProposerMismatch
+            | KeyTooSmall
+            | InvalidDepth
+            | BlockTooLarge
+            | StateRootMismatch
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misbehaving_peers_are_limited_and_banned() {
+        let honest: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let hostile: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let limits = PeerLimits {
+            messages_per_sec: 10,
+            message_burst: 10,
+            ..Default::default()
+        };
+        let mut scores = PeerScores::new(limits.clone());
+
+        for _ in 0..10 {
+            assert_eq!(scores.admit(hostile, 100, 0), Verdict::Handle);
+        }
+        assert_eq!(scores.admit(hostile, 100, 0), Verdict::Drop);
+        // a tenth of a second later there is room for one more
+        assert_eq!(scores.admit(hostile, 100, 100), Verdict::Handle);
+        assert_eq!(scores.admit(hostile, 100, 100), Verdict::Drop);
+        assert_eq!(scores.admit(honest, limits.byte_burst + 1, 100), Verdict::Drop);
+
+        assert_eq!(scores.record(hostile, Misbehavior::InvalidBlock, 100), None);
+        assert_eq!(scores.record(hostile, Misbehavior::Malformed, 100), None);
+        let until = 100 + limits.ban_cooldown;
+        assert_eq!(scores.record(hostile, Misbehavior::Malformed, 100), Some(until));
+        assert_eq!(scores.admit(hostile, 100, 200), Verdict::Drop);
+        assert_eq!(scores.banned(200), vec![(hostile, until)]);
+
+        let stats = &scores.peers[&hostile];
+        assert_eq!((stats.invalid_blocks, stats.malformed_messages, stats.rate_limited), (1, 2, 2));
+        assert_eq!(stats.messages, 13);
+
+        // the ban runs out and the score is forgiven over time
+        assert!(scores.banned(until).is_empty());
+        assert_eq!(scores.admit(hostile, 100, until), Verdict::Handle);
+        assert_eq!(scores.record(honest, Misbehavior::InvalidTransaction, 0), None);
+        assert_eq!(scores.peers[&honest].score, 11);
+        scores.admit(honest, 0, 5_000);
+        assert_eq!(scores.peers[&honest].score, 7);
+    }
+}
@@ -47,14 +47,14 @@ impl ConnectionMap {
         self.inner.read().await.keys().copied().collect()
     }
 
-    pub(crate) async fn shutdown(&self) -> crate::pippi::Result<()> {
+    pub(crate) async fn shutdown(&self) {
         let keys = self.keys().await;
         for key in keys {
             let (reading_actor, writing_actor) = self.remove(&key).await.unwrap();
-            reading_actor.kill().await?;
-            writing_actor.kill().await?;
+            // the actors of a connection that broke are already gone
+            reading_actor.kill().await.unwrap_or(());
+            writing_actor.kill().await.unwrap_or(());
         }
-        Ok(())
     }
 
     // this is better than combining the other functions since this maintains the lock through the process
@@ -0,0 +1,110 @@
+// the first message on a connection says which network a node is on and which versions of the protocol it speaks,
+// so nodes of different networks never exchange blocks
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::BlockHash,
+    pippi::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION},
+};
+
+/// The network a node follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainId {
+    pub genesis: BlockHash, // the hash of the genesis block
+    pub params: [u8; 32],  // the hash of the chain params
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32, // the newest version we speak
+    pub min_protocol_version: u32, // the oldest version we still speak
+    pub chain: Option<ChainId>, // none for wallets and nodes that are joining and have no chain yet
+    pub best_depth: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("the peer is on a network with another genesis block")]
+    Genesis,
+    #[error("the peer runs with other chain params")]
+    Params,
+    #[error("no common protocol version, we speak {ours:?} and the peer {theirs:?}")]
+    Version { ours: (u32, u32), theirs: (u32, u32) },
+}
+
+impl Handshake {
+    pub fn new(chain: Option<ChainId>, best_depth: u64) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            chain,
+            best_depth,
+        }
+    }
+
+    /// The newest version both we and the peer speak, or why we can not be peers
+    pub fn negotiate(&self, theirs: &Handshake) -> Result<u32, HandshakeError> {
+        if let (Some(ours), Some(their_chain)) = (&self.chain, &theirs.chain) {
+            if ours.genesis != their_chain.genesis {
+                return Err(HandshakeError::Genesis);
+            }
+            if ours.params != their_chain.params {
+                return Err(HandshakeError::Params);
+            }
+        }
+        let version = self.protocol_version.min(theirs.protocol_version);
+        if version < self.min_protocol_version.max(theirs.min_protocol_version) {
+            return Err(HandshakeError::Version {
+                ours: (self.min_protocol_version, self.protocol_version),
+                theirs: (theirs.min_protocol_version, theirs.protocol_version),
+            });
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speaking(min_protocol_version: u32, protocol_version: u32, chain: Option<ChainId>) -> Handshake {
+        Handshake {
+            protocol_version,
+            min_protocol_version,
+            chain,
+            best_depth: 0,
+        }
+    }
+
+    #[test]
+    fn peers_agree_on_the_newest_common_version_of_the_same_network() {
+        let chain = ChainId {
+            genesis: BlockHash([1; 32]),
+            params: [2; 32],
+        };
+        let ours = speaking(1, 3, Some(chain));
+        assert_eq!(ours.negotiate(&speaking(2, 5, Some(chain))), Ok(3));
+        assert_eq!(ours.negotiate(&speaking(1, 2, Some(chain))), Ok(2));
+        // nodes without a chain yet are let in to join
+        assert_eq!(ours.negotiate(&speaking(1, 3, None)), Ok(3));
+
+        assert_eq!(
+            ours.negotiate(&speaking(4, 5, Some(chain))),
+            Err(HandshakeError::Version {
+                ours: (1, 3),
+                theirs: (4, 5)
+            })
+        );
+        let other_genesis = ChainId {
+            genesis: BlockHash([3; 32]),
+            ..chain
+        };
+        assert_eq!(ours.negotiate(&speaking(1, 3, Some(other_genesis))), Err(HandshakeError::Genesis));
+        let other_params = ChainId {
+            params: [3; 32],
+            ..chain
+        };
+        assert_eq!(ours.negotiate(&speaking(1, 3, Some(other_params))), Err(HandshakeError::Params));
+    }
+}
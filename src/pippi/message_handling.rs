@@ -1,15 +1,21 @@
 use crate::pippi::peer::Peer;
+use crate::pippi::seen_items::Item;
 use crate::pippi::Message;
 use crate::pippi::MessageContent;
 use crate::pippi::Result;
 use crate::pippi::MAX_PEERS;
+use crate::logging::NET;
+use crate::ExternalMessage;
 use rand::Rng;
 use std::collections::HashSet;
+use std::net::SocketAddr;
 
 pub trait MessageHandlingStrategy: Clone + Send + Sync + 'static {
+    /// Handles a message that arrived on the connection to via
     fn handle_message(
         peer: &Peer<impl MessageHandlingStrategy>,
         message: Message,
+        via: SocketAddr,
     ) -> impl std::future::Future<Output = Result<()>> + Send;
 }
 
@@ -20,10 +26,25 @@ impl MessageHandlingStrategy for DefaultMessageHandlingStrategy {
     async fn handle_message(
         peer: &Peer<impl MessageHandlingStrategy>,
         message: Message,
+        via: SocketAddr,
     ) -> Result<()> {
-        let from = message.from;
+        // the address a message declares is whatever the sender wrote, so it is held to the connection it came on
+        let from = via;
         use crate::pippi::MessageContent::*;
 
+        if peer.is_banned(&from).await {
+            return Ok(());
+        }
+
+        // the same block or transaction can be flooded by several nodes, each under its own uuid
+        if let (Some(_), App(app_message)) = (message.uuid, &message.content) {
+            if let Some(item) = Item::of(app_message) {
+                if peer.seen.lock().unwrap().see(item) {
+                    return Ok(());
+                }
+            }
+        }
+
         if let Some(id) = message.uuid {
             if peer.flooding_set.contains(&id).await {
                 return Ok(());
@@ -31,11 +52,25 @@ impl MessageHandlingStrategy for DefaultMessageHandlingStrategy {
             peer.flooding_set.add(id).await;
         }
 
+        // a block announced by several peers is only asked for from the first
+        match message.content {
+            App(ExternalMessage::TipAnnounce { hash, .. }) if peer.seen.lock().unwrap().announced(hash) => {
+                return Ok(());
+            }
+            App(ExternalMessage::RequestedBlock(_)) => peer.seen.lock().unwrap().stats.blocks_fetched += 1,
+            _ => (),
+        }
+
         match message.content {
             App(ref app_message) => {
-                peer.app_message(app_message.clone()).await;
+                peer.app_message(from, app_message.clone()).await;
+            }
+            Contact(ref theirs) | ContactReply(ref theirs) => {
+                if !peer.greet(from, theirs).await {
+                    peer.disconnect(&from).await;
+                    return Ok(());
+                }
             }
-            Contact => (),
             AddMe => {
                 let mut inner_peerset = peer.peerset.inner.write().await; // we need to lock it here to avoid race conditions
                 let number_of_peers = inner_peerset.len();
@@ -46,7 +81,7 @@ impl MessageHandlingStrategy for DefaultMessageHandlingStrategy {
 
                     let message = Message::new_direct_message(
                         &peer.address,
-                        MessageContent::IDroppedYou(from),
+                        MessageContent::IDroppedYou(peer.listening_address(&from).await),
                     );
                     peer.send_to(&message, &random_peer).await.unwrap();
 
@@ -78,7 +113,7 @@ impl MessageHandlingStrategy for DefaultMessageHandlingStrategy {
             PeersetRelayRequest { origin, counter } => {
                 if counter == 0 {
                     peer.establish_contact(&origin).await.unwrap();
-                    let peerset = peer.peerset.get_copy().await;
+                    let peerset = peer.dialable_peers().await;
                     let message = Message::new_direct_message(
                         &peer.address,
                         MessageContent::PeersetResponse(peerset),
@@ -133,10 +168,11 @@ impl MessageHandlingStrategy for DefaultMessageHandlingStrategy {
             Heartbeat => {
                 peer.heartbeat.update(from).await;
             }
+            Goodbye => peer.disconnect(&from).await,
         }
 
         if message.is_flood() {
-            peer.broadcast_to_peerset(message).await;
+            peer.relay(message, via).await;
         }
 
         Ok(())
@@ -150,11 +186,9 @@ impl MessageHandlingStrategy for LoggingMessageHandlingStrategy {
     async fn handle_message(
         peer: &Peer<impl MessageHandlingStrategy>,
         message: Message,
+        via: SocketAddr,
     ) -> Result<()> {
-        println!(
-            "{} got {:?} from {}",
-            peer.address, message.content, message.from
-        );
-        DefaultMessageHandlingStrategy::handle_message(peer, message).await
+        tracing::info!(target: NET, peer = %via, node = %peer.address, content = ?message.content, "received a message");
+        DefaultMessageHandlingStrategy::handle_message(peer, message, via).await
     }
 }
@@ -1,29 +1,45 @@
-use std::{collections::HashSet, net::SocketAddr};
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
 
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use uuid::Uuid;
 
 use crate::ExternalMessage;
+use handshake::Handshake;
 
 pub(crate) mod connectionmap;
 pub(crate) mod flooding_set_actor;
+pub mod handshake;
 pub(crate) mod heartbeat;
 pub mod message_handling;
 pub mod network_analysis;
 pub mod peer;
 pub(crate) mod peerset;
 pub(crate) mod reading_actor;
+pub mod seen_items;
 pub(crate) mod writing_actor;
 
 const MAX_PEERS: usize = 10;
 const PEER_WALK_DEPTH: u32 = 30;
 const MESSAGE_LIFETIME: u128 = 2_000;
+const MAX_SEEN_ITEMS: usize = 100_000; // blocks and transactions we remember having seen
 
 const THROTTLE_MESSAGES: u32 = 30; // we only handle 10 messages per second per connection
 const THROTTLE_PERIOD: u128 = 1_000;
 
 const MAX_CONNECTIONS: usize = 1000; // max connections we can have in our connection-map
 
+const OUTBOUND_QUEUE: usize = 64; // messages waiting to be written to a connection, when full flooded ones are dropped
+const DEGRADED_AFTER: Duration = Duration::from_secs(2); // a peer whose queue stays full this long is degraded
+const STALLED_AFTER: Duration = Duration::from_secs(30); // and we disconnect it when its queue stays full this long
+
+const PROTOCOL_VERSION: u32 = 4; // the version of the messages we send
+const MIN_PROTOCOL_VERSION: u32 = 1; // the oldest version of a peer we still understand
+const DEFLATE_VERSION: u32 = 2; // from this version a node reads deflated frames
+const ANNOUNCE_VERSION: u32 = 3; // from this version a node is sent a `TipAnnounce` of a block instead of the block
+pub(crate) const SNAPSHOT_PARTS_VERSION: u32 = 4; // from this version a node is sent a snapshot in parts
+const DEFLATED: u64 = 1 << 63; // set on the length of a frame whose body is deflated
+
 #[derive(Debug, thiserror::Error)]
 pub enum PippiError {
     #[error("IO error: {0}")]
@@ -38,6 +54,12 @@ pub enum PippiError {
     RecvActorError(#[from] tokio::sync::oneshot::error::RecvError),
     #[error("Not found")]
     NotFound,
+    #[error("The queue of the connection is full of messages that matter more")]
+    QueueFull,
+    #[error("Frame of {0} bytes is over the limit")]
+    FrameTooLarge(u64),
+    #[error("Frame does not inflate to a message within the limit")]
+    Inflate,
     #[error("Heartbeat actor error")]
     HeartbeatError,
     #[error("actor send error")]
@@ -55,7 +77,8 @@ pub(crate) struct BootstrapData {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum MessageContent {
-    Contact,
+    Contact(Handshake), // the first message on a connection, and again when a connection is reused
+    ContactReply(Handshake), // so the node that made contact can check us too
     AddMe,
     IDroppedYou(SocketAddr),
     AddMeAccepted(Option<SocketAddr>),
@@ -63,6 +86,7 @@ pub(crate) enum MessageContent {
     PeersetResponse(HashSet<SocketAddr>),
     App(ExternalMessage),
     Heartbeat,
+    Goodbye, // the node is shutting down and closes the connection
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +124,62 @@ impl Message {
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
         Ok(bincode::serialize(&self)?)
     }
+
+    pub(crate) fn content(&self) -> &MessageContent {
+        &self.content
+    }
+
+    /// Whether the message carries many blocks, these are deflated for the nodes that read deflated frames
+    pub(crate) fn is_bulk(&self) -> bool {
+        matches!(
+            self.content,
+            MessageContent::App(
+                ExternalMessage::SyncBatch(..)
+                    | ExternalMessage::Bootstrap(_)
+                    | ExternalMessage::Snapshot(_)
+                    | ExternalMessage::SnapshotPart { .. }
+            )
+        )
+    }
+
+    /// Reads a message prefixed with its length as 8 bytes, a longer one is refused before its body is read.
+    /// A deflated body may not inflate to more than the limit either
+    pub(crate) async fn read_from(reader: &mut (impl AsyncRead + Unpin), max_bytes: u64) -> Result<Self> {
+        let mut length_buf = [0; 8];
+        reader.read_exact(&mut length_buf).await?;
+        let length = u64::from_be_bytes(length_buf);
+        let deflated = length & DEFLATED != 0;
+        let length = length & !DEFLATED;
+        if length > max_bytes {
+            return Err(PippiError::FrameTooLarge(length));
+        }
+        let mut buf = vec![0; length as usize];
+        reader.read_exact(&mut buf).await?;
+        if deflated {
+            buf = crate::compression::inflate(&buf, max_bytes as usize).ok_or(PippiError::Inflate)?;
+        }
+        Self::from_bytes(&buf)
+    }
+
+    /// The message prefixed with its length as 8 bytes, with the body deflated and the highest bit of the length set if asked
+    pub(crate) fn to_frame(&self, deflate: bool) -> Result<Vec<u8>> {
+        let mut bytes = self.to_bytes()?;
+        let mut length = bytes.len() as u64;
+        if deflate {
+            bytes = crate::compression::deflate(&bytes);
+            length = bytes.len() as u64 | DEFLATED;
+        }
+        let mut frame = Vec::with_capacity(8 + bytes.len());
+        frame.extend(length.to_be_bytes());
+        frame.extend(bytes);
+        Ok(frame)
+    }
+
+    /// Writes the message prefixed with its length as 8 bytes
+    pub(crate) async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        writer.write_all(&self.to_frame(false)?).await?;
+        Ok(())
+    }
 }
 
 fn get_unix_time() -> u128 {
@@ -108,3 +188,87 @@ fn get_unix_time() -> u128 {
         .unwrap()
         .as_millis()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_over_the_limit_are_refused_before_their_body_is_read() {
+        let message = Message::new_direct_message(&"127.0.0.1:1".parse().unwrap(), MessageContent::Heartbeat);
+        let mut frame = Vec::new();
+        message.write_to(&mut frame).await.unwrap();
+        let length = frame.len() as u64 - 8;
+        assert!(Message::read_from(&mut frame.as_slice(), length).await.is_ok());
+        assert!(matches!(
+            Message::read_from(&mut frame.as_slice(), length - 1).await,
+            Err(PippiError::FrameTooLarge(too_long)) if too_long == length
+        ));
+
+        // only the length of 100 MB arrives, reading the body would fail at the end of the bytes instead
+        let huge = 100_000_000u64.to_be_bytes();
+        assert!(matches!(
+            Message::read_from(&mut huge.as_slice(), 4_000_000).await,
+            Err(PippiError::FrameTooLarge(100_000_000))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sync_batches_round_trip_deflated() {
+        use crate::{
+            block::{Block, BlockHash},
+            seeding_mechanism::SeedContent,
+            sync::SyncProgress,
+            transaction::Transaction,
+            Timeslot,
+        };
+
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let blocks: Vec<_> = (0..5)
+            .map(|depth| {
+                let transactions = (0..10)
+                    .map(|nonce| Transaction::new(pk.clone(), receiver.clone(), &sk, 10, depth * 10 + nonce).unwrap())
+                    .collect();
+                let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+                let timeslot = Timeslot(depth + 1);
+                Block::new(timeslot, BlockHash([0; 32]), depth + 1, pk.clone(), transactions, [0; 32], &sk, seed)
+            })
+            .collect();
+        let progress = SyncProgress {
+            current_depth: 5,
+            target_depth: 5,
+        };
+        let batch = Message::new_direct_message(
+            &"127.0.0.1:1".parse().unwrap(),
+            MessageContent::App(ExternalMessage::SyncBatch(progress, blocks.clone())),
+        );
+        assert!(batch.is_bulk());
+
+        let raw = batch.to_frame(false).unwrap();
+        let deflated = batch.to_frame(true).unwrap();
+        assert!(deflated.len() < raw.len());
+        // the blocks arrive with the bytes they were signed and hashed with
+        for frame in [&raw, &deflated] {
+            let read = Message::read_from(&mut frame.as_slice(), crate::MAX_FRAME_BYTES).await.unwrap();
+            let MessageContent::App(ExternalMessage::SyncBatch(_, read)) = read.content else {
+                panic!("expected the batch");
+            };
+            assert!(read.iter().zip(blocks.iter()).all(|(a, b)| a.to_bytes() == b.to_bytes()));
+        }
+
+        // the limit holds for what a frame inflates to, and a body that does not inflate leaves the stream at the
+        // next message
+        let body_limit = deflated.len() as u64 - 8;
+        assert!(matches!(
+            Message::read_from(&mut deflated.as_slice(), body_limit).await,
+            Err(PippiError::Inflate)
+        ));
+        let mut garbage = (4u64 | DEFLATED).to_be_bytes().to_vec();
+        garbage.extend([0xff; 4]);
+        garbage.extend(&raw);
+        let mut stream = garbage.as_slice();
+        assert!(matches!(Message::read_from(&mut stream, crate::MAX_FRAME_BYTES).await, Err(PippiError::Inflate)));
+        assert!(Message::read_from(&mut stream, crate::MAX_FRAME_BYTES).await.is_ok());
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::{HashMap, VecDeque};
 
+use crate::logging::NET;
+
 pub struct NetworkGraph {
     edges: HashMap<String, Vec<String>>, // String is id
 }
@@ -39,10 +41,7 @@ fn distance_to_each_other_node(
         }
     }
     if distance.len() != graph.len() {
-        println!("Distance at {} is in-complete {}", node, distance.len());
-        if distance.len() == 1 {
-            println!("1 at {}", node);
-        }
+        tracing::debug!(target: NET, node, reached = distance.len(), nodes = graph.len(), "the graph is not connected");
     }
     distance
 }
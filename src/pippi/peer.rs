@@ -1,24 +1,36 @@
-use std::{collections::HashSet, marker::PhantomData, net::SocketAddr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use crate::ClientMessage;
 use crate::{
+    block::Block,
     pippi::{
         connectionmap::ConnectionMap,
         flooding_set_actor::FloodingSetHandle,
+        handshake::{ChainId, Handshake},
         heartbeat::HeartbeatHandle,
         message_handling::{DefaultMessageHandlingStrategy, MessageHandlingStrategy},
         peerset::Peerset,
         reading_actor::ReadingActorHandle,
-        writing_actor::WritingActorHandle,
-        Message, MessageContent, PippiError, Result, MAX_PEERS, PEER_WALK_DEPTH, THROTTLE_MESSAGES,
-        THROTTLE_PERIOD,
+        seen_items::{GossipStats, Item, SeenItems},
+        writing_actor::{Priority, WritingActorHandle},
+        Message, MessageContent, PippiError, Result, ANNOUNCE_VERSION, DEFLATE_VERSION, MAX_PEERS, MAX_SEEN_ITEMS,
+        PEER_WALK_DEPTH, STALLED_AFTER, THROTTLE_MESSAGES, THROTTLE_PERIOD,
     },
+    logging::NET,
+    policy::RelayPolicy,
     ExternalMessage,
 };
 use rand::Rng;
 use tokio::{
     net::TcpStream,
-    sync::mpsc,
+    sync::{mpsc, RwLock},
+    task::{AbortHandle, JoinHandle},
 };
 
 #[derive(Clone)]
@@ -27,7 +39,16 @@ pub struct Peer<M: MessageHandlingStrategy = DefaultMessageHandlingStrategy> {
     pub(crate) peerset: Peerset,
     pub(crate) connections: ConnectionMap,
     pub(crate) flooding_set: FloodingSetHandle,
+    pub(crate) seen: Arc<Mutex<SeenItems>>, // the blocks and transactions that were flooded to us or by us
     pub(crate) heartbeat: HeartbeatHandle,
+    banned: Arc<RwLock<HashMap<SocketAddr, u128>>>, // maps to the time the ban is over
+    handshake: Arc<Mutex<Handshake>>, // what we tell the nodes we make contact with
+    relay_policy: Arc<Mutex<RelayPolicy>>, // the transactions we neither flood nor pass on
+    versions: Arc<RwLock<HashMap<SocketAddr, u32>>>, // the protocol version agreed on with each node we are connected to
+    listening: Arc<RwLock<HashMap<SocketAddr, SocketAddr>>>, // where each node that connected to us says it listens
+    tasks: Arc<Mutex<Vec<AbortHandle>>>, // the listener, the peer walk and the heartbeat, stopped on shutdown
+    max_frame_bytes: u64, // longer messages are not read and the connection is dropped
+    upload_bytes_per_sec: Option<u64>, // what we send each node, so one that syncs from us leaves room for the rest
     app_channel: mpsc::Sender<ClientMessage>,
     message_handling: PhantomData<M>,
 }
@@ -39,20 +60,38 @@ where
     pub(crate) fn new_no_startup(
         addr: SocketAddr,
         app_channel: mpsc::Sender<ClientMessage>,
+        handshake: Handshake,
+        max_frame_bytes: u64,
+        upload_bytes_per_sec: Option<u64>,
     ) -> Self {
         Self {
             address: addr,
             peerset: Peerset::empty(),
             connections: ConnectionMap::new(),
             flooding_set: FloodingSetHandle::new(),
+            seen: Arc::new(Mutex::new(SeenItems::new(MAX_SEEN_ITEMS))),
             heartbeat: HeartbeatHandle::new(),
+            banned: Arc::new(RwLock::new(HashMap::new())),
+            handshake: Arc::new(Mutex::new(handshake)),
+            relay_policy: Arc::new(Mutex::new(RelayPolicy::default())),
+            versions: Arc::new(RwLock::new(HashMap::new())),
+            listening: Arc::new(RwLock::new(HashMap::new())),
+            tasks: Arc::new(Mutex::new(Vec::new())),
+            max_frame_bytes,
+            upload_bytes_per_sec,
             app_channel,
             message_handling: PhantomData,
         }
     }
 
-    pub fn new(addr: SocketAddr, app_channel: mpsc::Sender<ClientMessage>) -> Result<Self> {
-        let peer = Peer::new_no_startup(addr, app_channel);
+    pub fn new(
+        addr: SocketAddr,
+        app_channel: mpsc::Sender<ClientMessage>,
+        handshake: Handshake,
+        max_frame_bytes: u64,
+        upload_bytes_per_sec: Option<u64>,
+    ) -> Result<Self> {
+        let peer = Peer::new_no_startup(addr, app_channel, handshake, max_frame_bytes, upload_bytes_per_sec);
         peer.listen_for_connections()?;
         peer.run_peer_walk();
         peer.run_heartbeat_protocol();
@@ -63,26 +102,95 @@ where
         self.peerset.get_copy().await
     }
 
+    /// The address to dial the node of a connection at. A node that connected to us is known by the address it
+    /// connected from, which it cannot choose, but it is dialed where it says it listens
+    pub(crate) async fn listening_address(&self, addr: &SocketAddr) -> SocketAddr {
+        self.listening.read().await.get(addr).copied().unwrap_or(*addr)
+    }
+
+    /// Our peers at the addresses they can be dialed at
+    pub async fn dialable_peers(&self) -> HashSet<SocketAddr> {
+        let listening = self.listening.read().await;
+        let peerset = self.peerset.get_copy().await;
+        peerset.iter().map(|addr| listening.get(addr).copied().unwrap_or(*addr)).collect()
+    }
+
     pub(crate) async fn connections_len(&self) -> usize {
         self.connections.len().await
     }
 
+    fn handshake(&self) -> Handshake {
+        self.handshake.lock().unwrap().clone()
+    }
+
+    /// The network we follow, told to the nodes we make contact with from now on
+    pub fn set_chain(&self, chain: ChainId) {
+        self.handshake.lock().unwrap().chain = Some(chain);
+    }
+
+    pub fn set_best_depth(&self, depth: u64) {
+        self.handshake.lock().unwrap().best_depth = depth;
+    }
+
+    pub fn set_relay_policy(&self, policy: RelayPolicy) {
+        *self.relay_policy.lock().unwrap() = policy;
+    }
+
+    // only the transactions of the accounts the policy denies are held back, blocks always go on
+    fn relays(&self, message: &ExternalMessage) -> bool {
+        match message {
+            ExternalMessage::BroadcastTransaction(t) => self.relay_policy.lock().unwrap().allows(t),
+            _ => true,
+        }
+    }
+
+    /// The protocol version agreed on with the node
+    pub async fn protocol_version(&self, addr: &SocketAddr) -> Option<u32> {
+        self.versions.read().await.get(addr).copied()
+    }
+
+    /// Whether the node is on our network and speaks a version we speak, the client is told why it is not
+    pub(crate) async fn greet(&self, from: SocketAddr, theirs: &Handshake) -> bool {
+        match self.handshake().negotiate(theirs) {
+            Ok(version) => {
+                self.versions.write().await.insert(from, version);
+                true
+            }
+            Err(reason) => {
+                let from = self.listening_address(&from).await;
+                // the client is gone when we are shutting down
+                let _ = self.app_channel.send(ClientMessage::PeerRefused(from, reason)).await;
+                false
+            }
+        }
+    }
+
+    fn keep<T>(&self, task: JoinHandle<T>) {
+        self.tasks.lock().unwrap().push(task.abort_handle());
+    }
+
     pub(crate) fn listen_for_connections(&self) -> Result<()> {
+        // bound before we return, so peers can connect as soon as we are started
+        let socket = match self.address {
+            SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+        };
+        socket.set_reuseaddr(true)?;
+        socket.bind(self.address)?;
+        let listener = socket.listen(1024)?;
         let peer = self.clone();
-        tokio::spawn(async move {
-            let listener = tokio::net::TcpListener::bind(peer.address)
-                .await
-                .expect("unable to create listener");
+        let task = tokio::spawn(async move {
             loop {
                 // if someone is trying to spam us connections we don't allow them to ddos us
                 while peer.connections_len().await > crate::pippi::MAX_CONNECTIONS {
                     tokio::time::sleep(Duration::from_millis(1000)).await;
                 }
-                if let Ok((stream, _)) = listener.accept().await {
-                    peer.handle_stream(stream);
+                if let Ok((stream, remote)) = listener.accept().await {
+                    peer.handle_stream(stream, remote);
                 };
             }
         });
+        self.keep(task);
 
         Ok(())
     }
@@ -98,11 +206,13 @@ where
             .await
     }
 
-    pub(crate) fn handle_stream(&self, stream: tokio::net::TcpStream) {
+    /// Reads the contact of a node that connected to us from remote. The connection, and with it every score and
+    /// ban, is known by the address the node connected from, the address it writes in its messages is its own choice
+    pub(crate) fn handle_stream(&self, stream: tokio::net::TcpStream, remote: SocketAddr) {
         let peer = self.clone();
         tokio::spawn(async move {
             let (reader, writer) = stream.into_split();
-            let reader = ReadingActorHandle::new(reader);
+            let reader = ReadingActorHandle::new(reader, peer.max_frame_bytes);
             let message = reader.read().await;
 
             let Ok(message) = message else {
@@ -113,21 +223,35 @@ where
                 return;
             };
 
-            let from = &message.from;
+            let from = &remote;
             match message.content {
-                MessageContent::Contact => {
-                    let writer = WritingActorHandle::new(writer);
+                MessageContent::Contact(_) if peer.is_banned(from).await || peer.is_banned(&message.from).await => {
+                    reader
+                        .kill()
+                        .await
+                        .expect("error while trying to kill reader");
+                }
+                MessageContent::Contact(ref theirs) => {
+                    let writer = WritingActorHandle::new(writer, peer.upload_bytes_per_sec);
+                    let reply = Message::new_direct_message(
+                        &peer.address,
+                        MessageContent::ContactReply(peer.handshake()),
+                    );
+                    // a refused node gets our handshake too, so it knows why
+                    writer.send_message(reply, false).await.unwrap_or(());
+                    peer.listening.write().await.insert(*from, message.from);
+                    if !peer.greet(*from, theirs).await {
+                        peer.listening.write().await.remove(from);
+                        reader.kill().await.unwrap_or(());
+                        writer.kill().await.unwrap_or(());
+                        return;
+                    }
                     peer.add_connection(*from, reader.clone(), writer).await;
-                    peer.handle_connection(reader);
+                    peer.handle_connection(reader, *from);
                     peer.heartbeat.update(*from).await;
                 }
                 _ => {
-                    println!(
-                        "Contact expected but {} received {:?} from {}",
-                        peer.address.port(),
-                        message.content,
-                        from.port()
-                    );
+                    tracing::warn!(target: NET, peer = %from, content = ?message.content, "a connection did not start with a handshake");
                     reader
                         .kill()
                         .await
@@ -137,7 +261,8 @@ where
         });
     }
 
-    pub(crate) fn handle_connection(&self, reader: ReadingActorHandle) {
+    /// Reads the messages of the connection to the node at addr
+    pub(crate) fn handle_connection(&self, reader: ReadingActorHandle, addr: SocketAddr) {
         let peer = self.clone();
         tokio::spawn(async move {
             let mut messages_since_reset = 0;
@@ -148,8 +273,25 @@ where
                     Err(PippiError::ReadingActorError) => {
                         break;
                     }
+                    Err(PippiError::Serde(_) | PippiError::Inflate) => {
+                        peer.app_channel
+                            .send(ClientMessage::Malformed(addr))
+                            .await
+                            .unwrap();
+                        continue;
+                    }
+                    // the body was not read, so the stream is not at a message anymore
+                    Err(PippiError::FrameTooLarge(length)) => {
+                        tracing::warn!(target: NET, peer = %addr, length, "disconnecting a peer that sent an oversized frame");
+                        peer.app_channel
+                            .send(ClientMessage::Malformed(addr))
+                            .await
+                            .unwrap();
+                        peer.disconnect(&addr).await;
+                        break;
+                    }
                     Err(e) => {
-                        println!("Error: {e}");
+                        tracing::debug!(target: NET, peer = %addr, error = %e, "unable to read a message");
                         continue;
                     }
                     Ok(msg) => msg,
@@ -171,7 +313,7 @@ where
                     last_reset = crate::pippi::get_unix_time();
                 }
 
-                match M::handle_message(&peer, msg).await {
+                match M::handle_message(&peer, msg, addr).await {
                     Ok(_) => (),
                     Err(PippiError::NotFound) => (), // this occurs when handling a message where we dropped the peer
                     Err(e) => panic!("{e}"),
@@ -184,13 +326,13 @@ where
         if self.connections.get(to).await.is_none() {
             let stream = TcpStream::connect(to).await?;
             let (reader, writer) = stream.into_split();
-            let writer = WritingActorHandle::new(writer);
-            let reader = ReadingActorHandle::new(reader);
-            self.handle_connection(reader.clone());
+            let writer = WritingActorHandle::new(writer, self.upload_bytes_per_sec);
+            let reader = ReadingActorHandle::new(reader, self.max_frame_bytes);
+            self.handle_connection(reader.clone(), *to);
             self.connections.insert(*to, (reader, writer)).await;
         };
         self.send_to(
-            &Message::new_direct_message(&self.address, MessageContent::Contact),
+            &Message::new_direct_message(&self.address, MessageContent::Contact(self.handshake())),
             to,
         )
         .await?;
@@ -203,24 +345,126 @@ where
         self.peerset.add_peer(*seed_node).await;
 
         let message = Message::new_direct_message(&self.address, MessageContent::AddMe);
-        self.send_to(&message, seed_node).await?;
+        match self.send_to(&message, seed_node).await {
+            // the handshake of the seed node may already have been refused, the client is told why
+            Err(PippiError::NotFound) => Ok(()),
+            result => result,
+        }
+    }
 
-        Ok(())
+    /// Drops the connection to the node and refuses it until the ban is over
+    pub async fn ban(&self, addr: SocketAddr, until: u128) {
+        {
+            let mut banned = self.banned.write().await;
+            let now = crate::pippi::get_unix_time();
+            banned.retain(|_, until| *until > now);
+            banned.insert(addr, until);
+        }
+        self.disconnect(&addr).await;
+    }
+
+    /// Drops the connection to the node and removes it from our peerset
+    pub(crate) async fn disconnect(&self, addr: &SocketAddr) {
+        self.peerset.remove(addr).await;
+        self.versions.write().await.remove(addr);
+        self.listening.write().await.remove(addr);
+        if let Some((r, w)) = self.connections.remove(addr).await {
+            r.kill().await.unwrap_or(());
+            w.kill().await.unwrap_or(());
+        }
     }
 
-    pub async fn app_message(&self, app_message: ExternalMessage) {
-        self.app_channel.send(app_message.into()).await.unwrap();
+    pub(crate) async fn is_banned(&self, addr: &SocketAddr) -> bool {
+        self.banned
+            .read()
+            .await
+            .get(addr)
+            .is_some_and(|until| *until > crate::pippi::get_unix_time())
+    }
+
+    pub async fn app_message(&self, from: SocketAddr, app_message: ExternalMessage) {
+        self.app_channel
+            .send(ClientMessage::External(from, Box::new(app_message)))
+            .await
+            .unwrap();
     }
 
     pub(crate) async fn send_to(&self, message: &Message, to: &SocketAddr) -> Result<()> {
         if let Some((_, writer)) = self.connections.get(to).await {
-            writer.send_message(message.clone()).await?;
+            let deflate = self.deflates(message, to).await;
+            writer.send_message(message.clone(), deflate).await?;
             Ok(())
         } else {
             Err(PippiError::NotFound)
         }
     }
 
+    /// Like `send_to`, but never waits for a node that does not take its messages. When the queue of its connection
+    /// is full the least important queued message is dropped, a transaction before a block, or this one if all of
+    /// them matter more. The node hears of a block from its other peers or asks for it when it sees a block
+    /// building on it. A node whose queue stays full for `STALLED_AFTER` is disconnected
+    pub(crate) async fn offer_to(&self, message: &Message, to: &SocketAddr) -> Result<()> {
+        let Some((_, writer)) = self.connections.get(to).await else {
+            return Err(PippiError::NotFound);
+        };
+        let deflate = self.deflates(message, to).await;
+        let offered = writer.offer(message.clone(), deflate);
+        let dropped = match &offered {
+            Ok(dropped) => *dropped,
+            Err(PippiError::QueueFull) => Some(Priority::of(message)),
+            Err(_) => None,
+        };
+        if let Some(priority) = dropped {
+            self.seen.lock().unwrap().dropped(priority);
+        }
+        if let Some(full) = writer.full_for().filter(|full| *full >= STALLED_AFTER) {
+            tracing::warn!(target: NET, peer = %to, ?full, "disconnecting a peer that does not take its messages");
+            self.disconnect(to).await;
+        }
+        offered.map(|_| ())
+    }
+
+    /// The nodes whose queue has been full for `DEGRADED_AFTER`, they miss flooded transactions and blocks
+    pub async fn degraded_peers(&self) -> Vec<SocketAddr> {
+        let mut degraded = Vec::new();
+        for addr in self.connections.keys().await {
+            if let Some((_, writer)) = self.connections.get(&addr).await {
+                if writer.is_degraded() {
+                    degraded.push(addr);
+                }
+            }
+        }
+        degraded
+    }
+
+    // the blocks of a sync are deflated after they are signed and hashed, for the nodes that read deflated frames
+    async fn deflates(&self, message: &Message, to: &SocketAddr) -> bool {
+        message.is_bulk() && self.protocol_version(to).await.is_some_and(|version| version >= DEFLATE_VERSION)
+    }
+
+    /// Passes a flooded message on to our peers, except the one we got it from.
+    /// A transaction the relay policy denies is not passed on
+    pub(crate) async fn relay(&self, message: Message, via: SocketAddr) {
+        let item = match message.content() {
+            MessageContent::App(app_message) if !self.relays(app_message) => return,
+            MessageContent::App(app_message) => Item::of(app_message),
+            _ => None,
+        };
+        let mut sent = 0;
+        for to in self.peerset.get_copy().await {
+            if to != via && self.offer_to(&message, &to).await.is_ok() {
+                sent += 1;
+            }
+        }
+        if let Some(item) = item {
+            self.seen.lock().unwrap().sent(item, sent);
+        }
+    }
+
+    pub fn gossip_stats(&self) -> GossipStats {
+        self.seen.lock().unwrap().stats.clone()
+    }
+
     pub(crate) async fn broadcast_to_peerset(&self, msg: Message) {
         let peer = self.clone();
         let peerset = peer.peerset.get_copy().await;
@@ -231,7 +475,7 @@ where
 
     pub(crate) fn run_peer_walk(&self) {
         let peer = self.clone();
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             loop {
                 tokio::time::sleep(Duration::from_secs(1)).await;
                 let inner_peerset = peer.peerset.inner.read().await;
@@ -259,60 +503,238 @@ where
                 drop(inner_peerset);
             }
         });
+        self.keep(task);
     }
 
     /// Used for flooding blockchain messages, such as transaction or block
     pub async fn flood(&self, message: ExternalMessage) {
+        if !self.relays(&message) {
+            return;
+        }
+        // so the copies our peers pass on are not handled again
+        let item = Item::of(&message);
+        if let Some(item) = item {
+            self.seen.lock().unwrap().see(item);
+        }
         let message = Message::new_flood_message(&self.address, MessageContent::App(message));
         let peers = self.peerset.get_copy().await;
+        let mut sent = 0;
         for to in peers {
-            self.send_to(&message, &to)
-                .await
-                .unwrap_or_else(|_| println!("unable to flood message"));
+            match self.offer_to(&message, &to).await {
+                Ok(()) => sent += 1,
+                Err(e) => tracing::debug!(target: NET, peer = %to, error = %e, "unable to flood a message"),
+            }
+        }
+        if let Some(item) = item {
+            self.seen.lock().unwrap().sent(item, sent);
         }
     }
 
+    /// Tells our peers except one of a block we have. Those that speak `ANNOUNCE_VERSION` get a `TipAnnounce` and
+    /// ask us for the block if they have not seen it, so a block crosses a connection at most once. Older ones get
+    /// the block flooded to them
+    pub async fn announce(&self, block: &Block, except: Option<SocketAddr>) {
+        let item = Item::Block(block.hash);
+        {
+            let mut seen = self.seen.lock().unwrap();
+            if !seen.contains(&item) {
+                seen.see(item);
+            }
+        }
+        let announce = ExternalMessage::TipAnnounce {
+            hash: block.hash,
+            depth: block.depth(),
+            timeslot: block.timeslot(),
+        };
+        let announce = Message::new_direct_message(&self.address, MessageContent::App(announce));
+        let flood = Message::new_flood_message(
+            &self.address,
+            MessageContent::App(ExternalMessage::BroadcastBlock(block.clone())),
+        );
+        let mut sent = 0;
+        for to in self.peerset.get_copy().await {
+            if Some(to) == except {
+                continue;
+            }
+            let announces = self.protocol_version(&to).await.is_some_and(|version| version >= ANNOUNCE_VERSION);
+            let message = if announces { &announce } else { &flood };
+            if self.offer_to(message, &to).await.is_ok() {
+                sent += 1;
+            }
+        }
+        self.seen.lock().unwrap().sent(item, sent);
+    }
+
     /// Used for sending a direct blockchain message such as a bootstrap
     pub async fn send_direct_client_message(&self, to: SocketAddr, message: ExternalMessage) {
         let message = Message::new_direct_message(&self.address, MessageContent::App(message));
         self.send_to(&message, &to)
             .await
-            .unwrap_or_else(|_| println!("unable to send direct client message"));
+            .unwrap_or_else(|e| tracing::debug!(target: NET, peer = %to, error = %e, "unable to send a direct message"));
     }
 
     fn run_heartbeat_protocol(&self) {
         {
             let peer = self.clone();
-            tokio::spawn(async move {
+            let task = tokio::spawn(async move {
                 loop {
                     tokio::time::sleep(Duration::from_millis(1_000)).await;
                     let deads = peer.heartbeat.take_dead().await;
                     for dead in deads {
-                        peer.peerset.remove(&dead).await;
-                        if let Some((r, w)) = peer.connections.remove(&dead).await {
-                            r.kill().await.unwrap_or(());
-                            w.kill().await.unwrap_or(());
-                        };
+                        peer.disconnect(&dead).await;
                     }
                 }
             });
+            self.keep(task);
         }
 
         let peer = self.clone();
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             loop {
                 tokio::time::sleep(Duration::from_millis(crate::pippi::heartbeat::HEARTBEAT)).await;
                 let message = Message::new_direct_message(&peer.address, MessageContent::Heartbeat);
                 peer.broadcast_to_peerset(message).await;
             }
         });
+        self.keep(task);
     }
 
+    /// Stops listening and says goodbye to the nodes we are connected to before closing the connections
     pub async fn shutdown(&self) {
-        self.connections.shutdown().await.unwrap();
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        // a writer sends what it was given before it is killed
+        let goodbye = Message::new_direct_message(&self.address, MessageContent::Goodbye);
+        for addr in self.connections.keys().await {
+            self.send_to(&goodbye, &addr).await.unwrap_or(());
+        }
+        self.connections.shutdown().await;
         let peerset = self.peerset.get_copy().await;
         for peer in peerset {
             self.peerset.remove(&peer).await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        block::BlockHash,
+        pippi::{DEGRADED_AFTER, OUTBOUND_QUEUE},
+        seeding_mechanism::SeedContent,
+        transaction::Transaction,
+        Timeslot,
+    };
+
+    // a connection of the peer to a node, the node gets the other end of it
+    async fn connect(peer: &Peer) -> (SocketAddr, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (connected, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (reader, writer) = connected.unwrap().into_split();
+        let reader = ReadingActorHandle::new(reader, crate::MAX_FRAME_BYTES);
+        peer.connections
+            .insert(addr, (reader, WritingActorHandle::new(writer, None)))
+            .await;
+        peer.peerset.add_peer(addr).await;
+        (addr, accepted.unwrap().0)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_node_is_known_by_the_address_it_connects_from() {
+        let (app_tx, mut app_rx) = mpsc::channel(16);
+        let address = "127.0.0.1:1".parse().unwrap();
+        let peer: Peer = Peer::new_no_startup(
+            address,
+            app_tx,
+            Handshake::new(None, 0),
+            crate::MAX_FRAME_BYTES,
+            None,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (connected, accepted) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let (accepted, remote) = accepted.unwrap();
+        peer.handle_stream(accepted, remote);
+
+        // the node claims to be another one, what it sends is still held against its own connection
+        let mut stream = connected.unwrap();
+        let connection = stream.local_addr().unwrap();
+        let claimed: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        Message::new_direct_message(&claimed, MessageContent::Contact(Handshake::new(None, 0)))
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        let (sk, pk) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let t = Transaction::new(pk, to, &sk, 1, 0).unwrap();
+        let flooded = MessageContent::App(ExternalMessage::BroadcastTransaction(t));
+        Message::new_flood_message(&claimed, flooded)
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        let Some(ClientMessage::External(from, _)) = app_rx.recv().await else {
+            panic!("expected the transaction");
+        };
+        assert_eq!(from, connection);
+        assert_eq!(peer.connections.keys().await, vec![connection]);
+        // it is dialed where it says it listens
+        assert_eq!(peer.listening_address(&connection).await, claimed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_stalled_peer_does_not_hold_up_blocks_to_the_others() {
+        let (app_tx, _app_rx) = mpsc::channel(16);
+        let address = "127.0.0.1:1".parse().unwrap();
+        let peer: Peer = Peer::new_no_startup(address, app_tx, Handshake::new(None, 0), crate::MAX_FRAME_BYTES, None);
+        // the stalled node never reads what we send it
+        let (stalled, _stalled) = connect(&peer).await;
+        let (_, mut healthy) = connect(&peer).await;
+
+        let (sk, pk) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let t = Transaction::new(pk.clone(), to, &sk, 1, 0).unwrap();
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        // about a megabyte, so the socket buffers of the stalled connection fill up
+        let large = Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk.clone(), vec![t.clone(); 1_000], [0; 32], &sk, seed.clone());
+        let block = Block::new(Timeslot(2), BlockHash([0; 32]), 1, pk, vec![], [0; 32], &sk, seed);
+
+        let hash = block.hash;
+        let received = tokio::spawn(async move {
+            loop {
+                let message = Message::read_from(&mut healthy, crate::MAX_FRAME_BYTES).await.unwrap();
+                if let MessageContent::App(ExternalMessage::BroadcastBlock(b)) = message.content {
+                    if b.hash == hash {
+                        return;
+                    }
+                }
+            }
+        });
+
+        // a slot of the chain, none of the floods waits for the stalled node
+        let slot = Duration::from_millis(10_000);
+        let flooded = tokio::time::timeout(slot, async {
+            for _ in 0..20 {
+                peer.flood(ExternalMessage::BroadcastBlock(large.clone())).await;
+            }
+            for _ in 0..2 * OUTBOUND_QUEUE {
+                peer.flood(ExternalMessage::BroadcastTransaction(t.clone())).await;
+            }
+            peer.flood(ExternalMessage::BroadcastBlock(block)).await;
+        });
+        assert!(flooded.await.is_ok());
+        assert!(matches!(tokio::time::timeout(slot, received).await, Ok(Ok(()))));
+
+        let gossip = peer.gossip_stats();
+        assert!(gossip.transactions_dropped > 0, "{gossip:?}");
+        // the healthy node took everything, so only the stalled one is degraded
+        tokio::time::sleep(DEGRADED_AFTER).await;
+        assert_eq!(peer.degraded_peers().await, vec![stalled]);
+    }
+}
@@ -1,6 +1,5 @@
 use crate::pippi::{Message, PippiError, Result};
 use tokio::{
-    io::AsyncReadExt,
     net::tcp::OwnedReadHalf,
     sync::{mpsc, oneshot},
 };
@@ -8,14 +7,20 @@ use tokio::{
 struct ReadingActor {
     reader: OwnedReadHalf,
     receiver: tokio::sync::mpsc::Receiver<ReadingActorMessage>,
+    max_frame_bytes: u64,
 }
 
 impl ReadingActor {
     fn new(
         receiver: tokio::sync::mpsc::Receiver<ReadingActorMessage>,
         reader: OwnedReadHalf,
+        max_frame_bytes: u64,
     ) -> Self {
-        Self { reader, receiver }
+        Self {
+            reader,
+            receiver,
+            max_frame_bytes,
+        }
     }
 
     async fn run(mut actor: ReadingActor) -> Result<()> {
@@ -25,30 +30,24 @@ impl ReadingActor {
         Ok(())
     }
 
-    async fn read(reader: &mut OwnedReadHalf) -> Result<Message> {
-        let mut length_buf = [0; 8];
+    async fn read(reader: &mut OwnedReadHalf, max_frame_bytes: u64) -> Result<Message> {
         reader
             .readable()
             .await
             .map_err(|_| PippiError::WritingActorError)?;
-        reader
-            .read_exact(&mut length_buf)
-            .await
-            .map_err(|_| PippiError::ReadingActorError)?;
-        let length = u64::from_be_bytes(length_buf);
-        let mut buf = vec![0; length as usize];
-        let n = reader
-            .read_exact(&mut buf)
-            .await
-            .map_err(|_| PippiError::ReadingActorError)?;
-        let message = Message::from_bytes(&buf[..n]).map_err(|_| PippiError::ReadingActorError)?;
-        Ok(message)
+        // a message that can not be decoded leaves the stream at the next message, anything else ends it
+        Message::read_from(reader, max_frame_bytes).await.map_err(|e| match e {
+            PippiError::Serde(e) => PippiError::Serde(e),
+            PippiError::Inflate => PippiError::Inflate,
+            PippiError::FrameTooLarge(length) => PippiError::FrameTooLarge(length),
+            _ => PippiError::ReadingActorError,
+        })
     }
 
     async fn handle_message(&mut self, msg: ReadingActorMessage) -> Result<()> {
         match msg {
             ReadingActorMessage::Read { reply_to } => {
-                let msg = Self::read(&mut self.reader).await;
+                let msg = Self::read(&mut self.reader, self.max_frame_bytes).await;
                 reply_to
                     .send(msg)
                     .map_err(|_| PippiError::WritingActorError)?;
@@ -75,9 +74,10 @@ pub struct ReadingActorHandle {
 }
 
 impl ReadingActorHandle {
-    pub fn new(reader: OwnedReadHalf) -> Self {
+    /// Messages longer than max_frame_bytes are refused without reading them
+    pub fn new(reader: OwnedReadHalf, max_frame_bytes: u64) -> Self {
         let (sender, receiver) = mpsc::channel(64);
-        let actor = ReadingActor::new(receiver, reader);
+        let actor = ReadingActor::new(receiver, reader, max_frame_bytes);
         tokio::spawn(ReadingActor::run(actor));
         Self { sender }
     }
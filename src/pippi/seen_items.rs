@@ -0,0 +1,144 @@
+// blocks and transactions are flooded, so in a network with cycles the same item arrives from several peers.
+// we remember the items we have seen and only hand an item on the first time it arrives
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{block::BlockHash, pippi::writing_actor::Priority, transaction::TxId, ExternalMessage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Item {
+    Block(BlockHash),
+    Transaction(TxId),
+}
+
+impl Item {
+    /// The item a flooded message carries, other messages are only deduplicated by their uuid
+    pub(crate) fn of(message: &ExternalMessage) -> Option<Self> {
+        match message {
+            ExternalMessage::BroadcastBlock(block) => Some(Item::Block(block.hash)),
+            ExternalMessage::BroadcastTransaction(t) => Some(Item::Transaction(t.id())),
+            _ => None,
+        }
+    }
+}
+
+/// How many blocks and transactions we sent to peers and how many copies we dropped because we had seen them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GossipStats {
+    pub blocks_sent: u64,
+    pub transactions_sent: u64,
+    pub duplicate_blocks: u64,
+    pub duplicate_transactions: u64,
+    pub blocks_dropped: u64, // left out of the full queue of a slow peer
+    pub transactions_dropped: u64,
+    pub announce_hits: u64, // announced blocks we had seen, so we did not ask for them
+    pub blocks_fetched: u64, // blocks that peers sent us because we asked for them
+}
+
+/// The most recently seen items, the one seen longest ago is forgotten when there are too many
+pub(crate) struct SeenItems {
+    capacity: usize,
+    stamps: HashMap<Item, u64>,
+    order: VecDeque<(Item, u64)>, // oldest first, an entry is stale if the item was seen again since
+    next_stamp: u64,
+    pub(crate) stats: GossipStats,
+}
+
+impl SeenItems {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            stamps: HashMap::new(),
+            order: VecDeque::new(),
+            next_stamp: 0,
+            stats: GossipStats::default(),
+        }
+    }
+
+    /// Marks the item as seen and returns whether it was seen before, which is counted as a duplicate
+    pub(crate) fn see(&mut self, item: Item) -> bool {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        let seen = self.stamps.insert(item, stamp).is_some();
+        self.order.push_back((item, stamp));
+        if seen {
+            match item {
+                Item::Block(_) => self.stats.duplicate_blocks += 1,
+                Item::Transaction(_) => self.stats.duplicate_transactions += 1,
+            }
+        }
+
+        while self.stamps.len() > self.capacity {
+            let Some((oldest, oldest_stamp)) = self.order.pop_front() else {
+                break;
+            };
+            if self.stamps.get(&oldest) == Some(&oldest_stamp) {
+                self.stamps.remove(&oldest);
+            }
+        }
+        // items that keep arriving leave stale entries behind, they are dropped before the queue grows too long
+        if self.order.len() > 2 * self.capacity {
+            let stamps = &self.stamps;
+            self.order.retain(|(item, stamp)| stamps.get(item) == Some(stamp));
+        }
+        seen
+    }
+
+    /// Marks the announced block as seen and returns whether it was seen before, then it is not asked for.
+    /// A block announced again is counted as a duplicate like a block flooded again
+    pub(crate) fn announced(&mut self, hash: BlockHash) -> bool {
+        let seen = self.see(Item::Block(hash));
+        if seen {
+            self.stats.announce_hits += 1;
+        }
+        seen
+    }
+
+    /// Whether the item is among the ones seen most recently
+    pub(crate) fn contains(&self, item: &Item) -> bool {
+        self.stamps.contains_key(item)
+    }
+
+    /// Counts that the item was sent to this many peers
+    pub(crate) fn sent(&mut self, item: Item, peers: u64) {
+        match item {
+            Item::Block(_) => self.stats.blocks_sent += peers,
+            Item::Transaction(_) => self.stats.transactions_sent += peers,
+        }
+    }
+
+    /// Counts a flooded message that was dropped from the full queue of a peer
+    pub(crate) fn dropped(&mut self, priority: Priority) {
+        match priority {
+            Priority::Transaction => self.stats.transactions_dropped += 1,
+            Priority::Block => self.stats.blocks_dropped += 1,
+            Priority::Critical => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_item_seen_longest_ago_is_forgotten() {
+        let mut seen = SeenItems::new(2);
+        let (a, b, c) = (Item::Block(BlockHash([1; 32])), Item::Block(BlockHash([2; 32])), Item::Transaction(TxId([3; 32])));
+        assert!(!seen.see(a));
+        assert!(!seen.see(b));
+        assert!(seen.see(a));
+        // b was seen longest ago
+        assert!(!seen.see(c));
+        assert!(seen.see(a));
+        assert!(!seen.see(b));
+        assert!(!seen.stamps.contains_key(&c));
+
+        for _ in 0..10 {
+            seen.see(b);
+        }
+        assert!(seen.order.len() <= 4);
+        assert_eq!(seen.stats.duplicate_blocks, 12);
+        assert_eq!(seen.stats.duplicate_transactions, 0);
+    }
+}
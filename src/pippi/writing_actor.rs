@@ -1,78 +1,386 @@
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf, sync::mpsc};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::pippi::{Message, PippiError, Result};
+use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf, sync::Notify, time::Instant};
+
+use crate::{
+    pippi::{Message, MessageContent, PippiError, Result, DEGRADED_AFTER, OUTBOUND_QUEUE},
+    ExternalMessage,
+};
+
+/// How much a message matters to the peer, when its queue is full the least important message is dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    Transaction, // a flooded transaction, the peer gets it from the block that includes it
+    Block,       // a flooded or announced block, the peer asks for it when it sees a block building on it
+    Critical,    // everything else, these are never dropped
+}
+
+impl Priority {
+    pub(crate) fn of(message: &Message) -> Self {
+        match message.content {
+            MessageContent::App(ExternalMessage::BroadcastTransaction(_)) => Priority::Transaction,
+            MessageContent::App(ExternalMessage::BroadcastBlock(_) | ExternalMessage::TipAnnounce { .. }) => {
+                Priority::Block
+            }
+            _ => Priority::Critical,
+        }
+    }
+}
+
+struct Queued {
+    message: Message,
+    deflate: bool,
+    priority: Priority,
+}
+
+/// The messages waiting to be written to a connection, oldest first
+struct Queue {
+    items: VecDeque<Queued>,
+    capacity: usize,
+    full_since: Option<Instant>, // when an offer first found the queue full, until the writer drains it to half
+    closed: bool,                // killed, what is queued is still written but nothing more is taken
+}
+
+impl Queue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity,
+            full_since: None,
+            closed: false,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.items.len() >= self.capacity
+    }
+
+    // returns the priority of the queued message that was dropped, the offered message is refused when everything
+    // queued matters more than it
+    fn offer(&mut self, queued: Queued) -> Result<Option<Priority>> {
+        if !self.is_full() {
+            self.items.push_back(queued);
+            return Ok(None);
+        }
+        self.full_since.get_or_insert_with(Instant::now);
+        // the least important queued message, the oldest of them
+        let dropped = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.priority < Priority::Critical)
+            .min_by_key(|(i, item)| (item.priority, *i))
+            .map(|(i, item)| (i, item.priority));
+        match dropped {
+            Some((i, priority)) if priority <= queued.priority => {
+                self.items.remove(i);
+                self.items.push_back(queued);
+                Ok(Some(priority))
+            }
+            _ => Err(PippiError::QueueFull),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        let queued = self.items.pop_front()?;
+        if self.items.len() <= self.capacity / 2 {
+            self.full_since = None;
+        }
+        Some(queued)
+    }
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    queued: Notify, // wakes the writer
+    room: Notify,   // wakes the senders that wait for room in the queue
+}
+
+impl Shared {
+    // returns whether the queue was open
+    fn close(&self) -> bool {
+        let was_open = !std::mem::replace(&mut self.queue.lock().unwrap().closed, true);
+        self.queued.notify_one();
+        self.room.notify_waiters();
+        was_open
+    }
+}
+
+// closes the queue when the last handle is dropped, so the writer stops and the connection is closed
+struct Open(Arc<Shared>);
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
 
 struct WritingActor {
     writer: OwnedWriteHalf,
-    receiver: tokio::sync::mpsc::Receiver<WritingActorMessage>,
+    shared: Arc<Shared>,
+    throttle: Option<Throttle>,
 }
 
 impl WritingActor {
-    fn new(receiver: mpsc::Receiver<WritingActorMessage>, write: OwnedWriteHalf) -> Self {
+    fn new(shared: Arc<Shared>, write: OwnedWriteHalf, throttle: Option<Throttle>) -> Self {
         Self {
             writer: write,
-            receiver,
+            shared,
+            throttle,
         }
     }
 
     async fn run(mut actor: WritingActor) {
-        while let Some(msg) = actor.receiver.recv().await {
-            match actor.handle_message(msg).await {
-                Ok(()) => (),
-                Err(_) => (),
-            }
+        while let Some(queued) = actor.next().await {
+            // a connection that broke is dropped by the heartbeat
+            let _ = actor.write(queued).await;
         }
     }
 
-    async fn handle_message(&mut self, msg: WritingActorMessage) -> Result<()> {
-        match msg {
-            WritingActorMessage::Write { message } => {
-                let bytes = message.to_bytes()?;
-                self.writer.writable().await?;
-                let length = bytes.len() as u64; // 8 bytes
-                let length_bytes = length.to_be_bytes();
-                self.writer.write_all(&length_bytes).await?;
-                self.writer.write_all(&bytes).await?;
-                Ok(())
-            }
-            WritingActorMessage::Kill => {
-                self.receiver.close();
-                Ok(())
+    // the oldest queued message, none once the queue is closed and empty
+    async fn next(&self) -> Option<Queued> {
+        loop {
+            let queued = self.shared.queued.notified();
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if let Some(next) = queue.pop() {
+                    self.shared.room.notify_one();
+                    return Some(next);
+                }
+                if queue.closed {
+                    return None;
+                }
             }
+            queued.await;
+        }
+    }
+
+    async fn write(&mut self, queued: Queued) -> Result<()> {
+        let frame = queued.message.to_frame(queued.deflate)?;
+        if let Some(throttle) = self.throttle.as_mut() {
+            throttle.pace(frame.len() as u64).await;
         }
+        self.writer.writable().await?;
+        self.writer.write_all(&frame).await?;
+        Ok(())
     }
 }
 
-enum WritingActorMessage {
-    Write { message: Message },
-    Kill,
+/// Spaces the frames written to a connection so they average at most bytes_per_sec
+#[derive(Debug)]
+struct Throttle {
+    bytes_per_sec: u64,
+    paid_until: Instant, // when the frames written so far would have been sent at the cap
 }
 
-#[derive(Debug, Clone)]
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            paid_until: Instant::now(),
+        }
+    }
+
+    /// Waits until the frames written before are paid for, then books the next one
+    async fn pace(&mut self, bytes: u64) {
+        tokio::time::sleep_until(self.paid_until).await;
+        let nanos = bytes as u128 * 1_000_000_000 / self.bytes_per_sec as u128;
+        // an idle connection does not save up for a burst
+        self.paid_until = self.paid_until.max(Instant::now()) + Duration::from_nanos(nanos as u64);
+    }
+}
+
+/// The outbound queue of a connection and the task that writes it, so a slow peer never holds up the others
+#[derive(Clone)]
 pub struct WritingActorHandle {
-    sender: tokio::sync::mpsc::Sender<WritingActorMessage>,
+    shared: Arc<Shared>,
+    _open: Arc<Open>,
+}
+
+impl std::fmt::Debug for WritingActorHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let queue = self.shared.queue.lock().unwrap();
+        f.debug_struct("WritingActorHandle")
+            .field("queued", &queue.items.len())
+            .field("full_since", &queue.full_since)
+            .finish()
+    }
 }
 
 impl WritingActorHandle {
-    pub fn new(writer: OwnedWriteHalf) -> Self {
-        let (sender, receiver) = mpsc::channel(64);
-        let actor = WritingActor::new(receiver, writer);
+    /// Without a cap the frames are written as fast as the connection takes them
+    pub fn new(writer: OwnedWriteHalf, bytes_per_sec: Option<u64>) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::new(OUTBOUND_QUEUE)),
+            queued: Notify::new(),
+            room: Notify::new(),
+        });
+        let actor = WritingActor::new(shared.clone(), writer, bytes_per_sec.map(Throttle::new));
         tokio::spawn(WritingActor::run(actor));
-        Self { sender }
+        Self {
+            _open: Arc::new(Open(shared.clone())),
+            shared,
+        }
+    }
+
+    /// Waits for room in the queue of the connection
+    pub async fn send_message(&self, msg: Message, deflate: bool) -> Result<()> {
+        let priority = Priority::of(&msg);
+        let mut queued = Some(Queued {
+            message: msg,
+            deflate,
+            priority,
+        });
+        loop {
+            let room = self.shared.room.notified();
+            tokio::pin!(room);
+            // registered before we look, so a kill while we wait wakes us
+            room.as_mut().enable();
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if queue.closed {
+                    return Err(PippiError::WritingActorError);
+                }
+                if !queue.is_full() {
+                    queue.items.push_back(queued.take().expect("invariant: queued once"));
+                    self.shared.queued.notify_one();
+                    return Ok(());
+                }
+            }
+            room.await;
+        }
+    }
+
+    /// Queues the message without waiting. When the queue is full the least important message is dropped,
+    /// transactions before blocks and the oldest first, and its priority is returned. When everything queued
+    /// matters more than the message it is dropped itself and `QueueFull` is returned
+    pub(crate) fn offer(&self, msg: Message, deflate: bool) -> Result<Option<Priority>> {
+        let priority = Priority::of(&msg);
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.closed {
+            return Err(PippiError::WritingActorError);
+        }
+        let offered = queue.offer(Queued {
+            message: msg,
+            deflate,
+            priority,
+        });
+        self.shared.queued.notify_one();
+        offered
+    }
+
+    /// How long the queue has been full, none if the writer has drained it to half since
+    pub(crate) fn full_for(&self) -> Option<Duration> {
+        let queue = self.shared.queue.lock().unwrap();
+        queue.full_since.map(|since| since.elapsed())
     }
 
-    pub async fn send_message(&self, msg: Message) -> Result<()> {
-        let msg = WritingActorMessage::Write { message: msg };
-        self.sender
-            .send(msg)
-            .await
-            .map_err(|_| PippiError::WritingActorError)
+    /// A peer whose queue has been full for `DEGRADED_AFTER` is not taking our messages as fast as we send them
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.full_for().is_some_and(|full| full >= DEGRADED_AFTER)
     }
 
+    /// What is queued is still written, after that the writer stops
     pub async fn kill(&self) -> Result<()> {
-        self.sender
-            .send(WritingActorMessage::Kill)
-            .await
-            .map_err(|_| PippiError::WritingActorError)
+        if self.shared.close() {
+            Ok(())
+        } else {
+            Err(PippiError::WritingActorError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, net::SocketAddr};
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        block::{Block, BlockHash},
+        seeding_mechanism::SeedContent,
+        transaction::Transaction,
+        Timeslot,
+    };
+
+    #[tokio::test]
+    async fn the_cap_on_a_connection_is_respected_under_load() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (connected, accepted) = tokio::join!(TcpStream::connect(listener.local_addr().unwrap()), listener.accept());
+        let (_read_half, write_half) = connected.unwrap().into_split();
+        let (mut incoming, _) = accepted.unwrap();
+        let writer = WritingActorHandle::new(write_half, Some(100_000));
+
+        // frames of about 10 KB, 200 KB queued at once
+        let peers: HashSet<SocketAddr> = (0..1_000u32).map(|i| SocketAddr::from((i.to_be_bytes(), 8080))).collect();
+        let message = Message::new_direct_message(&"127.0.0.1:1".parse().unwrap(), MessageContent::PeersetResponse(peers));
+        let frame_bytes = message.to_frame(false).unwrap().len() as u64;
+        let frames = 200_000 / frame_bytes;
+        let started = Instant::now();
+        for _ in 0..frames {
+            writer.send_message(message.clone(), false).await.unwrap();
+        }
+        for _ in 0..frames {
+            Message::read_from(&mut incoming, crate::MAX_FRAME_BYTES).await.unwrap();
+        }
+
+        // the first frame goes out at once and the rest at the cap
+        let elapsed = started.elapsed().as_secs_f64();
+        let expected = ((frames - 1) * frame_bytes) as f64 / 100_000.0;
+        assert!(elapsed >= expected * 0.9, "{elapsed}s where the cap takes {expected}s");
+        assert!(elapsed <= expected * 1.5 + 0.5, "{elapsed}s where the cap takes {expected}s");
+    }
+
+    fn queued(content: MessageContent) -> Queued {
+        let message = Message::new_flood_message(&"127.0.0.1:1".parse().unwrap(), content);
+        Queued {
+            priority: Priority::of(&message),
+            message,
+            deflate: false,
+        }
+    }
+
+    #[test]
+    fn a_full_queue_drops_transactions_before_blocks() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        let t = Transaction::new(pk.clone(), to, &sk, 1, 0).unwrap();
+        let seed = SeedContent::new((BlockHash([0; 32]), 0), [0; 32]);
+        let b = Block::new(Timeslot(1), BlockHash([0; 32]), 1, pk, vec![], [0; 32], &sk, seed);
+        let transaction = || queued(MessageContent::App(ExternalMessage::BroadcastTransaction(t.clone())));
+        let block = || queued(MessageContent::App(ExternalMessage::BroadcastBlock(b.clone())));
+        let critical = || queued(MessageContent::Heartbeat);
+        let priorities = |queue: &Queue| queue.items.iter().map(|item| item.priority).collect::<Vec<_>>();
+
+        let mut queue = Queue::new(3);
+        for item in [block(), transaction(), critical()] {
+            assert!(matches!(queue.offer(item), Ok(None)));
+        }
+        assert!(queue.full_since.is_none());
+
+        assert!(matches!(queue.offer(block()), Ok(Some(Priority::Transaction))));
+        assert!(queue.full_since.is_some());
+        // a transaction does not push out a block
+        assert!(matches!(queue.offer(transaction()), Err(PippiError::QueueFull)));
+        assert_eq!(priorities(&queue), [Priority::Block, Priority::Critical, Priority::Block]);
+        // the oldest block goes first
+        assert!(matches!(queue.offer(critical()), Ok(Some(Priority::Block))));
+        assert_eq!(priorities(&queue), [Priority::Critical, Priority::Block, Priority::Critical]);
+        assert!(matches!(queue.offer(critical()), Ok(Some(Priority::Block))));
+        // nothing that matters more is dropped
+        assert!(matches!(queue.offer(critical()), Err(PippiError::QueueFull)));
+        assert!(matches!(queue.offer(block()), Err(PippiError::QueueFull)));
+        assert_eq!(priorities(&queue), [Priority::Critical; 3]);
+
+        // the queue is no longer full once the writer has drained it to half
+        queue.pop();
+        assert!(queue.full_since.is_some());
+        queue.pop();
+        assert!(queue.full_since.is_none());
     }
 }
@@ -0,0 +1,156 @@
+// what a node passes on for others, apart from consensus. An operator may refuse the transactions of some accounts,
+// they are not taken into the mempool or relayed, but the blocks that include them are accepted like any other
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{account::AccountId, transaction::Transaction};
+
+/// The accounts whose transactions the node does not take in or pass on, written as hex addresses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayPolicy {
+    #[serde(with = "hex_accounts")]
+    pub deny: BTreeSet<AccountId>, // a transaction from or to any of them is refused
+    #[serde(with = "hex_allow")]
+    pub allow: Option<BTreeSet<AccountId>>, // if there is one, a transaction from or to anyone else is refused
+}
+
+impl RelayPolicy {
+    /// Whether the sender and every receiver of the transaction may be relayed for
+    pub fn allows(&self, transaction: &Transaction) -> bool {
+        let receivers = transaction.outputs().iter().map(|(to, _)| to.id());
+        std::iter::once(transaction.from_id()).chain(receivers).all(|account| {
+            !self.deny.contains(&account) && self.allow.as_ref().is_none_or(|allow| allow.contains(&account))
+        })
+    }
+}
+
+mod hex_accounts {
+    use std::collections::BTreeSet;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::account::AccountId;
+
+    pub fn serialize<S: Serializer>(accounts: &BTreeSet<AccountId>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(accounts.iter().map(AccountId::to_string))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeSet<AccountId>, D::Error> {
+        parse(Vec::deserialize(deserializer)?)
+    }
+
+    pub(super) fn parse<E: Error>(addresses: Vec<String>) -> Result<BTreeSet<AccountId>, E> {
+        addresses
+            .iter()
+            .map(|address| address.parse().map_err(|_| E::custom("expected a hex address")))
+            .collect()
+    }
+}
+
+mod hex_allow {
+    use std::collections::BTreeSet;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::account::AccountId;
+
+    pub fn serialize<S: Serializer>(accounts: &Option<BTreeSet<AccountId>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match accounts {
+            Some(accounts) => super::hex_accounts::serialize(accounts, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BTreeSet<AccountId>>, D::Error> {
+        Option::<Vec<String>>::deserialize(deserializer)?
+            .map(super::hex_accounts::parse)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        blockchain::{Blockchain, BlockchainError},
+        clock::ManualClock,
+        draw::DrawCache,
+        params::ChainParams,
+        Error,
+    };
+
+    #[test]
+    fn denied_senders_are_refused_at_submission_but_not_in_blocks() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        let transaction = Transaction::new(pk.clone(), receiver.clone(), &sk, 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(transaction.clone()), Ok(false));
+
+        // the pending transaction is dropped when its sender is denied, and it is not taken again
+        let policy = RelayPolicy {
+            deny: BTreeSet::from([pk.id()]),
+            allow: None,
+        };
+        blockchain.set_relay_policy(policy.clone());
+        assert_eq!(blockchain.relay_policy(), &policy);
+        assert_eq!(blockchain.mempool_iter().count(), 0);
+        assert_eq!(
+            blockchain.add_transaction(transaction.clone()),
+            Err(Error::BlockchainError(BlockchainError::DeniedByPolicy))
+        );
+
+        // a block with the transaction is still valid, the policy is not consensus
+        clock.advance_slots(1);
+        let mut other = blockchain.clone();
+        other.set_relay_policy(RelayPolicy::default());
+        assert_eq!(other.add_transaction(transaction.clone()), Ok(false));
+        let block = other.try_produce(&sk, &mut DrawCache::default()).unwrap();
+        assert_eq!(block.transactions(), std::slice::from_ref(&transaction));
+        assert_eq!(blockchain.add_block(block), Ok(()));
+        assert_eq!(blockchain.get_balance(&receiver.id()), 10);
+        assert_eq!(blockchain.verify_chain(), Ok(()));
+    }
+
+    #[test]
+    fn allow_lists_refuse_everyone_else_and_lists_are_read_as_hex() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let transaction = Transaction::new(pk.clone(), receiver.clone(), &sk, 10, 0).unwrap();
+        assert!(RelayPolicy::default().allows(&transaction));
+        let deny_receiver = RelayPolicy {
+            deny: BTreeSet::from([receiver.id()]),
+            allow: None,
+        };
+        assert!(!deny_receiver.allows(&transaction));
+        let allow_sender = RelayPolicy {
+            deny: BTreeSet::new(),
+            allow: Some(BTreeSet::from([pk.id()])),
+        };
+        assert!(!allow_sender.allows(&transaction));
+        let allow_both = RelayPolicy {
+            deny: BTreeSet::new(),
+            allow: Some(BTreeSet::from([pk.id(), receiver.id()])),
+        };
+        assert!(allow_both.allows(&transaction));
+
+        let json = serde_json::to_value(&allow_both).unwrap();
+        assert_eq!(json["allow"][0].as_str().map(str::len), Some(64));
+        assert_eq!(serde_json::from_value::<RelayPolicy>(json).unwrap(), allow_both);
+        let toml = format!("deny = [\"{}\"]\n", receiver.id());
+        assert_eq!(toml::from_str::<RelayPolicy>(&toml), Ok(deny_receiver));
+        assert!(toml::from_str::<RelayPolicy>("deny = [\"not hex\"]\n").is_err());
+    }
+}
@@ -0,0 +1,299 @@
+// the blocks and syncs we asked peers for and have not got yet. A block several orphans are missing is asked for
+// once, a peer has a bounded number of our requests at a time and what a peer does not answer in time is asked of
+// another. An orphan starts a walk back towards our chain, we ask for its missing parent and if that is an orphan
+// too we ask for the next one, until a block connects. The walks are bounded so a peer can not keep us asking forever
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::{
+    block::BlockHash, BLOCK_REQUEST_TIMEOUT, MAX_PARENT_WALK, MAX_PEER_REQUESTS, REQUEST_ATTEMPTS, SYNC_TIMEOUT,
+};
+
+/// What we asked a peer for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Wanted {
+    Block(BlockHash),
+    Sync, // the batches above our best path, there is one sync at a time
+}
+
+impl Wanted {
+    fn timeout(&self) -> u128 {
+        match self {
+            Wanted::Block(_) => BLOCK_REQUEST_TIMEOUT,
+            Wanted::Sync => SYNC_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Requests {
+    outstanding: HashMap<Wanted, Request>,
+}
+
+#[derive(Debug)]
+struct Request {
+    peer: SocketAddr,
+    tried: Vec<SocketAddr>, // the peers that did not answer in time, since every peer was last tried
+    attempts: usize,
+    steps: u64, // the number of blocks the walk has gone back
+    deadline: u128,
+}
+
+impl Requests {
+    /// Whether the missing block should be requested from the peer that sent us the orphan.
+    /// An orphan that we asked for continues the walk that asked for it, and a block that is asked for already
+    /// is not asked for again
+    pub(crate) fn request_parent(
+        &mut self,
+        peer: SocketAddr,
+        orphan: &BlockHash,
+        missing: BlockHash,
+        now: u128,
+    ) -> bool {
+        let steps = self
+            .outstanding
+            .remove(&Wanted::Block(*orphan))
+            .map_or(1, |request| request.steps + 1);
+        let wanted = Wanted::Block(missing);
+        if steps > MAX_PARENT_WALK || self.outstanding.contains_key(&wanted) || !self.has_room(&peer) {
+            return false;
+        }
+        self.insert(wanted, peer, steps, now);
+        true
+    }
+
+    /// The first of the peers with room for another of our requests, none if it is asked for already
+    pub(crate) fn request(&mut self, wanted: Wanted, peers: &[SocketAddr], now: u128) -> Option<SocketAddr> {
+        if self.outstanding.contains_key(&wanted) {
+            return None;
+        }
+        let peer = *peers.iter().find(|peer| self.has_room(peer))?;
+        self.insert(wanted, peer, 0, now);
+        Some(peer)
+    }
+
+    /// The peer is still answering, like a sync that streams its batches
+    pub(crate) fn renew(&mut self, wanted: &Wanted, now: u128) {
+        if let Some(request) = self.outstanding.get_mut(wanted) {
+            request.deadline = now + wanted.timeout();
+        }
+    }
+
+    /// Whether we are waiting for a peer to send it
+    pub(crate) fn is_requested(&self, wanted: &Wanted) -> bool {
+        self.outstanding.contains_key(wanted)
+    }
+
+    /// We got what we asked for, or no longer want it
+    pub(crate) fn received(&mut self, wanted: &Wanted) {
+        self.outstanding.remove(wanted);
+    }
+
+    /// The requests that were not answered in time and the peers to ask now, one that has not been asked yet if
+    /// one of the peers has room. A block is given up on after `REQUEST_ATTEMPTS` attempts, a sync is asked for
+    /// until it is done
+    pub(crate) fn expired(&mut self, now: u128, peers: &[SocketAddr]) -> Vec<(Wanted, SocketAddr)> {
+        let expired: Vec<_> = self
+            .outstanding
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(wanted, _)| *wanted)
+            .collect();
+        let mut retries = Vec::new();
+        for wanted in expired {
+            let mut request = self.outstanding.remove(&wanted).expect("invariant: the request is outstanding");
+            if matches!(wanted, Wanted::Block(_)) && request.attempts >= REQUEST_ATTEMPTS {
+                continue;
+            }
+            request.tried.push(request.peer);
+            let untried = |peer: &&SocketAddr| !request.tried.contains(peer) && self.has_room(peer);
+            // when every peer has been tried we go round again, the peer that did not answer if no other can be asked
+            let peer = match peers.iter().find(untried) {
+                Some(peer) => *peer,
+                None => {
+                    request.tried.clear();
+                    peers.iter().find(|peer| self.has_room(peer)).copied().unwrap_or(request.peer)
+                }
+            };
+            request.peer = peer;
+            request.attempts += 1;
+            request.deadline = now + wanted.timeout();
+            self.outstanding.insert(wanted, request);
+            retries.push((wanted, peer));
+        }
+        retries
+    }
+
+    fn has_room(&self, peer: &SocketAddr) -> bool {
+        self.outstanding_for(peer) < MAX_PEER_REQUESTS
+    }
+
+    fn outstanding_for(&self, peer: &SocketAddr) -> usize {
+        self.outstanding
+            .values()
+            .filter(|request| request.peer == *peer)
+            .count()
+    }
+
+    fn insert(&mut self, wanted: Wanted, peer: SocketAddr, steps: u64, now: u128) {
+        self.outstanding.insert(
+            wanted,
+            Request {
+                peer,
+                tried: Vec::new(),
+                attempts: 1,
+                steps,
+                deadline: now + wanted.timeout(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        blockchain::{Blockchain, BlockchainError},
+        clock::ManualClock,
+        params::ChainParams,
+        Error,
+    };
+
+    fn hash(i: u64) -> BlockHash {
+        let mut hash = [0; 32];
+        hash[..8].copy_from_slice(&i.to_be_bytes());
+        BlockHash(hash)
+    }
+
+    #[test]
+    fn walks_and_requests_per_peer_are_bounded() {
+        let peer: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let mut requests = Requests::default();
+
+        // a walk from block i asks for block i + 1
+        let start = 1_000;
+        assert!(requests.request_parent(peer, &hash(0), hash(1), 0));
+        assert!(!requests.request_parent(other, &hash(0), hash(1), 0));
+        for i in 1..MAX_PARENT_WALK {
+            assert!(requests.request_parent(peer, &hash(i), hash(i + 1), 0));
+        }
+        assert!(!requests.request_parent(peer, &hash(MAX_PARENT_WALK), hash(start), 0));
+        assert_eq!(requests.outstanding_for(&peer), 0);
+
+        for i in 0..MAX_PEER_REQUESTS as u64 {
+            assert!(requests.request_parent(peer, &hash(start + 2 * i), hash(start + 2 * i + 1), 0));
+        }
+        assert!(!requests.request_parent(peer, &hash(start - 1), hash(start - 2), 0));
+        assert!(requests.request_parent(other, &hash(start - 1), hash(start - 2), 0));
+
+        requests.received(&Wanted::Block(hash(start + 1)));
+        assert!(requests.request_parent(peer, &hash(start - 3), hash(start - 4), 0));
+        // a sync goes to the first peer with room
+        assert_eq!(requests.request(Wanted::Sync, &[peer, other], 0), Some(other));
+        assert_eq!(requests.request(Wanted::Sync, &[peer, other], 0), None);
+    }
+
+    #[test]
+    fn orphans_of_the_same_parent_ask_for_it_once() {
+        let peer: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let mut requests = Requests::default();
+        assert!(requests.request_parent(peer, &hash(1), hash(0), 0));
+        assert!(!requests.request_parent(peer, &hash(2), hash(0), 0));
+        assert!(!requests.request_parent(other, &hash(3), hash(0), 0));
+        assert_eq!(requests.outstanding.len(), 1);
+    }
+
+    #[test]
+    fn unanswered_requests_go_to_another_peer_until_they_are_given_up() {
+        let peers: Vec<SocketAddr> = ["127.0.0.1:8080", "127.0.0.1:8081"].map(|peer| peer.parse().unwrap()).into();
+        let mut requests = Requests::default();
+        assert!(requests.request_parent(peers[0], &hash(1), hash(0), 0));
+        assert_eq!(requests.request(Wanted::Sync, &peers, 0), Some(peers[0]));
+        assert_eq!(requests.expired(BLOCK_REQUEST_TIMEOUT - 1, &peers), vec![]);
+
+        let mut now = BLOCK_REQUEST_TIMEOUT.max(SYNC_TIMEOUT);
+        let mut retries = requests.expired(now, &peers);
+        retries.sort_by_key(|(wanted, _)| *wanted == Wanted::Sync);
+        assert_eq!(retries, vec![(Wanted::Block(hash(0)), peers[1]), (Wanted::Sync, peers[1])]);
+
+        // a streaming sync is not asked again while its batches arrive
+        now += SYNC_TIMEOUT;
+        requests.renew(&Wanted::Sync, now - 1);
+        assert_eq!(requests.expired(now, &peers), vec![(Wanted::Block(hash(0)), peers[0])]);
+        for _ in 0..REQUEST_ATTEMPTS {
+            now += SYNC_TIMEOUT.max(BLOCK_REQUEST_TIMEOUT);
+            let retries = requests.expired(now, &peers);
+            assert!(retries.contains(&(Wanted::Sync, peers[0])) || retries.contains(&(Wanted::Sync, peers[1])));
+        }
+        // the block was given up on, the sync goes on
+        assert!(!requests.outstanding.contains_key(&Wanted::Block(hash(0))));
+        assert!(requests.outstanding.contains_key(&Wanted::Sync));
+    }
+
+    #[test]
+    fn a_node_converges_through_a_peer_that_drops_every_other_request() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        // without hardness every draw wins
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut node = Blockchain::start_with_clock(vec![pk], params, Arc::new(clock.clone()));
+        let mut source = node.clone();
+        for _ in 0..20 {
+            assert_eq!(source.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        }
+
+        let flaky: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let silent: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let mut requests = Requests::default();
+        let (mut asked, mut asked_silent) = (0, 0);
+        let mut answers = vec![(flaky, Arc::clone(source.best_path_block(20).unwrap()))];
+        let mut now = 0;
+        while node.tip() != source.tip() {
+            assert!(now < 100 * BLOCK_REQUEST_TIMEOUT, "the node did not converge");
+            // the answers arrive within a timeout of the request
+            let mut sent = Vec::new();
+            for (sender, block) in answers.drain(..) {
+                let hash = block.hash;
+                match node.add_block(block) {
+                    Err(Error::BlockchainError(BlockchainError::OrphanBlock(missing))) => {
+                        if requests.request_parent(sender, &hash, missing, now) {
+                            sent.push((sender, missing));
+                        }
+                    }
+                    _ => requests.received(&Wanted::Block(hash)),
+                }
+            }
+            for (wanted, peer) in requests.expired(now, &[flaky, silent]) {
+                let Wanted::Block(hash) = wanted else {
+                    unreachable!("the node does not sync");
+                };
+                sent.push((peer, hash));
+            }
+            for (peer, hash) in sent {
+                if peer == silent {
+                    asked_silent += 1;
+                    continue;
+                }
+                asked += 1;
+                if asked % 2 == 0 {
+                    continue;
+                }
+                let block = source.block_by_hash(&hash).expect("the node asks for blocks of the source");
+                answers.push((flaky, Arc::clone(block)));
+            }
+            now += BLOCK_REQUEST_TIMEOUT;
+        }
+        assert!(asked_silent > 0);
+        assert_eq!(node.verify_chain(), Ok(()));
+    }
+}
@@ -0,0 +1,495 @@
+// a JSON-RPC 2.0 endpoint over HTTP, so explorers and wallets can query a running node
+// requests are parsed here and passed to the client, which answers them with the blockchain actor
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    account::AccountId,
+    block::{Block, BlockHash},
+    blockchain::{Blockchain, BlockchainError, TipRef},
+    export::DumpFormat,
+    keys::PublicKey,
+    ledger::TxValidationError,
+    logging,
+    metrics::NodeStatus,
+    policy::RelayPolicy,
+    stats::ChainStats,
+    transaction::Transaction,
+    ClientMessage, Error,
+};
+
+const MAX_HEADER_LINES: usize = 64;
+const MAX_HEADER_BYTES: u64 = 16 * 1024; // the request line and all headers
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+pub(crate) const PARSE_ERROR: i64 = -32700;
+pub(crate) const INVALID_REQUEST: i64 = -32600;
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+/// The node refused the request, e.g. an invalid transaction
+pub(crate) const REJECTED: i64 = -32000;
+/// The node has not received a blockchain yet
+const NOT_READY: i64 = -32001;
+/// The ledger refused a transaction, the data of the error says why
+pub const REJECTED_TRANSACTION: i64 = -32002;
+/// The transactions of the block were pruned, the data of the error is its header
+pub const PRUNED: i64 = -32003;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>, // the reason of a refused transaction, see `TxValidationError`, or the header of a pruned block
+}
+
+impl RpcError {
+    pub(crate) fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn rejected(e: Error) -> Self {
+        match e {
+            Error::BlockchainError(BlockchainError::RejectedTransaction(reason)) => Self {
+                code: REJECTED_TRANSACTION,
+                message: reason.to_string(),
+                data: Some(serde_json::to_value(reason).expect("the reasons are plain numbers")),
+            },
+            e => Self::new(REJECTED, e.to_string()),
+        }
+    }
+
+    /// Why the ledger refused the transaction, if that is what the node said
+    pub fn transaction_error(&self) -> Option<TxValidationError> {
+        serde_json::from_value(self.data.clone()?).ok()
+    }
+
+    pub(crate) fn not_ready() -> Self {
+        Self::new(NOT_READY, "the node has not received a blockchain yet")
+    }
+}
+
+pub type RpcResult = std::result::Result<Value, RpcError>;
+
+#[derive(Debug)]
+pub enum Method {
+    GetBalance(AccountId),
+    GetNonce(AccountId),
+    GetTip,
+    GetBlockByHash(BlockHash),
+    GetBlockByDepth(u64),
+    SendRawTransaction(Box<Transaction>),
+    GetMempool,
+    GetStats,
+    GetStatus,
+    Dump(DumpFormat),
+    ForceReorg(BlockHash),
+    ExportPeers,
+    ImportPeers(Vec<SocketAddr>),
+    DevMine(u64), // only answered by a dev node
+    PolicySet(RelayPolicy),
+    PolicyGet,
+}
+
+impl Method {
+    /// Whether answering the method changes the blockchain
+    pub(crate) fn writes(&self) -> bool {
+        matches!(self, Method::SendRawTransaction(_) | Method::ForceReorg(_) | Method::PolicySet(_))
+    }
+
+    fn parse(name: &str, params: &[Value]) -> std::result::Result<Self, RpcError> {
+        let invalid = |message: &str| RpcError::new(INVALID_PARAMS, message);
+        let string_param = || {
+            params
+                .first()
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid("expected a string parameter"))
+        };
+        let account_param = || {
+            let account = string_param()?;
+            account
+                .parse()
+                .or_else(|_| PublicKey::from_pem(account).map(|pk| AccountId::from(&pk)))
+                .map_err(|_| invalid("expected an address or a public key pem"))
+        };
+
+        let hash_param = || {
+            string_param()?
+                .parse::<BlockHash>()
+                .map_err(|_| invalid("expected a hex encoded 32 byte hash"))
+        };
+
+        match name {
+            "get_balance" => account_param().map(Method::GetBalance),
+            "get_nonce" => account_param().map(Method::GetNonce),
+            "get_tip" => Ok(Method::GetTip),
+            "get_block_by_hash" => hash_param().map(Method::GetBlockByHash),
+            "get_block_by_depth" => params
+                .first()
+                .and_then(Value::as_u64)
+                .map(Method::GetBlockByDepth)
+                .ok_or_else(|| invalid("expected a depth")),
+            "send_raw_transaction" => hex::decode(string_param()?)
+                .ok()
+                .and_then(|bytes| Transaction::from_bytes(&bytes).ok())
+                .map(|t| Method::SendRawTransaction(Box::new(t)))
+                .ok_or_else(|| invalid("expected a hex encoded transaction")),
+            "get_mempool" => Ok(Method::GetMempool),
+            "get_stats" => Ok(Method::GetStats),
+            "get_status" => Ok(Method::GetStatus),
+            "dump" => match params.first().and_then(Value::as_str) {
+                None | Some("json") => Ok(Method::Dump(DumpFormat::Json)),
+                Some("dot") => Ok(Method::Dump(DumpFormat::Dot)),
+                Some(_) => Err(invalid("expected json or dot")),
+            },
+            "force_reorg" => hash_param().map(Method::ForceReorg),
+            "export_peers" => Ok(Method::ExportPeers),
+            "import_peers" => crate::address_book::parse_list(string_param()?)
+                .map(Method::ImportPeers)
+                .map_err(|_| invalid("expected an address per line")),
+            "dev_mine" => params
+                .first()
+                .and_then(Value::as_u64)
+                .map(Method::DevMine)
+                .ok_or_else(|| invalid("expected a number of blocks")),
+            "policy_set" => params
+                .first()
+                .and_then(|policy| serde_json::from_value(policy.clone()).ok())
+                .map(Method::PolicySet)
+                .ok_or_else(|| invalid("expected an object with deny and allow lists of addresses")),
+            "policy_get" => Ok(Method::PolicyGet),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "method not found")),
+        }
+    }
+}
+
+/// A parsed request waiting for the client to answer it
+#[derive(Debug)]
+pub struct Call {
+    pub(crate) method: Method,
+    pub(crate) reply: oneshot::Sender<RpcResult>,
+}
+
+/// Answers a method that writes to the blockchain, called by the blockchain actor
+pub(crate) fn answer(blockchain: &mut Blockchain, method: Method) -> RpcResult {
+    match method {
+        Method::SendRawTransaction(transaction) => {
+            // the transaction is verified against the ledger when it is added
+            let hash = transaction.hash();
+            blockchain
+                .add_transaction(*transaction)
+                .map(|_| json!(hex::encode(hash)))
+                .map_err(RpcError::rejected)
+        }
+        Method::ForceReorg(hash) => {
+            blockchain
+                .force_reorg(hash)
+                .map_err(|e| RpcError::new(REJECTED, e.to_string()))?;
+            let TipRef { hash, depth } = blockchain.tip();
+            Ok(json!({ "hash": hex::encode(hash), "depth": depth }))
+        }
+        Method::PolicySet(policy) => {
+            // the transactions in the mempool it denies are dropped
+            blockchain.set_relay_policy(policy);
+            Ok(json!(blockchain.relay_policy()))
+        }
+        method => answer_read(blockchain, method),
+    }
+}
+
+/// Answers a method that only reads the blockchain, the handle of the actor does it without waiting for blocks
+/// that are being verified
+pub(crate) fn answer_read(blockchain: &Blockchain, method: Method) -> RpcResult {
+    match method {
+        Method::GetBalance(account) => Ok(json!(blockchain.get_balance(&account))),
+        Method::GetNonce(account) => Ok(json!(blockchain.next_nonce(&account))),
+        Method::GetTip => {
+            let TipRef { hash, depth } = blockchain.tip();
+            Ok(json!({ "hash": hex::encode(hash), "depth": depth }))
+        }
+        Method::GetBlockByHash(hash) => blockchain
+            .block_by_hash(&hash)
+            .map_or(Ok(Value::Null), full_block_to_json),
+        Method::GetBlockByDepth(depth) => blockchain
+            .best_path_block(depth)
+            .map_or(Ok(Value::Null), full_block_to_json),
+        Method::GetMempool => Ok(blockchain.mempool_iter().map(transaction_to_json).collect()),
+        Method::GetStats => Ok(stats_to_json(&blockchain.stats())),
+        Method::GetStatus | Method::ExportPeers | Method::ImportPeers(_) | Method::DevMine(_) | Method::PolicyGet => {
+            unreachable!("the client answers the methods of the node")
+        }
+        Method::Dump(DumpFormat::Json) => {
+            Ok(serde_json::from_str(&blockchain.dump(DumpFormat::Json)).expect("the dump is valid json"))
+        }
+        Method::Dump(DumpFormat::Dot) => Ok(Value::String(blockchain.dump(DumpFormat::Dot))),
+        Method::SendRawTransaction(_) | Method::ForceReorg(_) | Method::PolicySet(_) => {
+            unreachable!("the blockchain actor answers the methods that write")
+        }
+    }
+}
+
+pub(crate) fn transaction_to_json(transaction: &Transaction) -> Value {
+    json!({
+        "hash": hex::encode(transaction.hash()),
+        "from": transaction.from_id().to_string(),
+        "outputs": transaction
+            .outputs()
+            .iter()
+            .map(|(to, amount)| json!({ "to": AccountId::from(to).to_string(), "amount": amount }))
+            .collect::<Vec<_>>(),
+        "amount": transaction.amount(),
+        "fee": transaction.fee(),
+        "nonce": transaction.nonce(),
+        "valid_until": transaction.valid_until(),
+    })
+}
+
+/// The progress is null before the node has synced from a peer
+pub(crate) fn status_to_json(status: &NodeStatus) -> Value {
+    let metrics = &status.metrics;
+    let progress = metrics.sync.map(|progress| {
+        json!({
+            "current_depth": progress.current_depth,
+            "target_depth": progress.target_depth,
+            "percent": progress.percent(),
+        })
+    });
+    json!({
+        "uptime_secs": status.uptime.as_secs(),
+        "best_depth": metrics.chain_height,
+        "best_hash": hex::encode(metrics.best_hash),
+        "timeslot": metrics.timeslot,
+        "sync": {
+            "state": if status.syncing { "syncing" } else { "synced" },
+            "progress": progress,
+        },
+        "peers": metrics.peers_connected,
+        "mempool_size": metrics.mempool_size,
+        "orphans": metrics.orphan_pool_size,
+        "staking": status.staking,
+        "staking_address": status.staking_address.to_string(),
+    })
+}
+
+// the proposers are keyed by their address
+fn stats_to_json(stats: &ChainStats) -> Value {
+    let proposers: serde_json::Map<_, _> = stats
+        .proposers
+        .iter()
+        .map(|(account, blocks)| (account.to_string(), json!(blocks)))
+        .collect();
+    json!({
+        "best_height": stats.best_height,
+        "total_blocks": stats.total_blocks,
+        "forks": stats.forks,
+        "missed_slots": stats.missed_slots,
+        "proposers": proposers,
+        "average_transactions": stats.average_transactions,
+        "fees": stats.fees,
+        "rewards": stats.rewards,
+    })
+}
+
+/// The header of a block without its transactions
+pub(crate) fn header_to_json(block: &Block) -> Value {
+    json!({
+        "hash": hex::encode(block.hash),
+        "prev_hash": hex::encode(block.prev_hash),
+        "depth": block.depth(),
+        "timeslot": block.timeslot(),
+        "winner": block.winner_id().to_string(),
+        "transaction_count": block.transaction_count(),
+        "merkle_root": hex::encode(block.merkle_root()),
+        "state_root": hex::encode(block.state_root()),
+    })
+}
+
+// a block without its transactions is refused with its header, so it is not mistaken for an empty block
+fn full_block_to_json(block: &Arc<Block>) -> RpcResult {
+    if !block.has_body() {
+        let pruned = RpcError::new(PRUNED, BlockchainError::Pruned.to_string());
+        return Err(RpcError {
+            data: Some(header_to_json(block)),
+            ..pruned
+        });
+    }
+    Ok(block_to_json(block))
+}
+
+fn block_to_json(block: &Block) -> Value {
+    json!({
+        "hash": hex::encode(block.hash),
+        "prev_hash": hex::encode(block.prev_hash),
+        "depth": block.depth(),
+        "timeslot": block.timeslot(),
+        "winner": block.winner_id().to_string(),
+        "transactions": block.transactions().iter().map(transaction_to_json).collect::<Vec<_>>(),
+        "raw": hex::encode(block.to_bytes()),
+    })
+}
+
+/// Calls a method on the endpoint of a node, for wallets that submit transactions they signed elsewhere
+pub async fn call(addr: SocketAddr, method: &str, params: Value) -> crate::Result<RpcResult> {
+    let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let mut stream = TcpStream::connect(addr).await.map_err(|_| Error::NetworkError)?;
+    stream.write_all(request.as_bytes()).await.map_err(|_| Error::NetworkError)?;
+    // the node closes the connection after the response
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.map_err(|_| Error::NetworkError)?;
+    let response = String::from_utf8_lossy(&response);
+    let (_, body) = response.split_once("\r\n\r\n").ok_or(Error::NetworkError)?;
+    let response: Value = serde_json::from_str(body).map_err(|_| Error::InvalidEncoding)?;
+    if let Some(error) = response.get("error") {
+        let code = error["code"].as_i64().unwrap_or_default();
+        let message = error["message"].as_str().unwrap_or_default();
+        let data = error.get("data").cloned();
+        return Ok(Err(RpcError { data, ..RpcError::new(code, message) }));
+    }
+    Ok(Ok(response.get("result").cloned().unwrap_or(Value::Null)))
+}
+
+/// Starts serving requests on the address and returns the address that was bound
+pub async fn serve(
+    addr: SocketAddr,
+    client: mpsc::Sender<ClientMessage>,
+) -> crate::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|_| Error::NetworkError)?;
+    let addr = listener.local_addr().map_err(|_| Error::NetworkError)?;
+    tracing::info!(target: logging::RPC, %addr, "serving JSON-RPC");
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(error) = handle_connection(stream, client).await {
+                    tracing::debug!(target: logging::RPC, ?error, "RPC connection failed");
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    client: mpsc::Sender<ClientMessage>,
+) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    // we only need the request line and the content length
+    let mut head = (&mut stream).take(MAX_HEADER_BYTES);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADER_LINES {
+        let mut line = String::new();
+        head.read_line(&mut line).await?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    // a line cut short by the limit is not a request we can read
+    if head.limit() == 0 {
+        return reject(&mut stream, "431 Request Header Fields Too Large").await;
+    }
+    if !request_line.starts_with("POST ") {
+        return reject(&mut stream, "405 Method Not Allowed").await;
+    }
+    if content_length > MAX_BODY_SIZE {
+        return reject(&mut stream, "413 Payload Too Large").await;
+    }
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+    let body = respond(&body, &client).await.to_string();
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.get_mut().write_all(response.as_bytes()).await
+}
+
+async fn reject(stream: &mut BufReader<TcpStream>, status: &str) -> std::io::Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    stream.get_mut().write_all(response.as_bytes()).await
+}
+
+async fn respond(body: &[u8], client: &mpsc::Sender<ClientMessage>) -> Value {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(_) => return error_response(Value::Null, RpcError::new(PARSE_ERROR, "parse error")),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let (Some("2.0"), Some(name)) = (
+        request.get("jsonrpc").and_then(Value::as_str),
+        request.get("method").and_then(Value::as_str),
+    ) else {
+        return error_response(id, RpcError::new(INVALID_REQUEST, "invalid request"));
+    };
+    let params = match request.get("params") {
+        Some(Value::Array(params)) => params.as_slice(),
+        None => &[],
+        Some(_) => {
+            return error_response(id, RpcError::new(INVALID_PARAMS, "params must be an array"))
+        }
+    };
+
+    let method = match Method::parse(name, params) {
+        Ok(method) => method,
+        Err(e) => return error_response(id, e),
+    };
+
+    let (reply, rx) = oneshot::channel();
+    let result = match client
+        .send(ClientMessage::Rpc(Call { method, reply }))
+        .await
+    {
+        Ok(()) => rx.await.unwrap_or_else(|_| Err(RpcError::not_ready())),
+        Err(_) => Err(RpcError::not_ready()),
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_response(id, e),
+    }
+}
+
+pub(crate) fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": match error.data {
+            Some(data) => json!({ "code": error.code, "message": error.message, "data": data }),
+            None => json!({ "code": error.code, "message": error.message }),
+        },
+    })
+}
@@ -0,0 +1,160 @@
+// the signature of a transaction is checked when it is submitted, again when a block includes it and again
+// whenever a branch is replayed or the chain is verified. The id of a transaction is the hash of its encoding
+// with the signature, so an id that was verified once always verifies and only the ids are kept
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use thiserror::Error;
+
+use crate::{
+    transaction::{Transaction, TxId},
+    SIGNATURE_CACHE_SIZE,
+};
+
+lazy_static! {
+    /// The cache every check of a transaction signature goes through
+    pub static ref SIGNATURES: SignatureCache = SignatureCache::new(SIGNATURE_CACHE_SIZE);
+}
+
+/// Why the signature of a transaction does not hold
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigError {
+    #[error("the hash of the transaction is not the hash of its signature")]
+    HashMismatch,
+    #[error("the signature is not made with the key of the sender")]
+    BadSignature,
+}
+
+/// The ids of transactions whose signature holds, the least recently used are forgotten first.
+/// Signatures that do not hold are not kept, so invalid transactions can not push out the valid ones
+#[derive(Debug)]
+pub struct SignatureCache {
+    verified: Mutex<Lru>,
+    checks: AtomicU64, // signatures that were not in the cache and had to be checked
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    last_used: HashMap<TxId, u64>,
+    by_use: BTreeMap<u64, TxId>,
+    clock: u64, // counts the uses, so the smallest key of by_use is the least recently used
+    capacity: usize,
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            verified: Mutex::new(Lru {
+                capacity,
+                ..Default::default()
+            }),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks the signature unless the transaction has been verified before
+    pub fn verify(&self, transaction: &Transaction) -> Result<(), SigError> {
+        let id = transaction.id();
+        if self.verified.lock().unwrap().touch(&id) {
+            return Ok(());
+        }
+        // the lock is not held while the signature is checked, so blocks can be verified in parallel
+        self.checks.fetch_add(1, Ordering::Relaxed);
+        transaction.check_signature()?;
+        self.verified.lock().unwrap().insert(id);
+        Ok(())
+    }
+
+    pub fn contains(&self, id: &TxId) -> bool {
+        self.verified.lock().unwrap().last_used.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.verified.lock().unwrap().last_used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of signatures that had to be checked
+    pub fn checks(&self) -> u64 {
+        self.checks.load(Ordering::Relaxed)
+    }
+}
+
+impl Lru {
+    // marks the id as just used, false if it is not kept
+    fn touch(&mut self, id: &TxId) -> bool {
+        if !self.last_used.contains_key(id) {
+            return false;
+        }
+        self.insert(*id);
+        true
+    }
+
+    fn insert(&mut self, id: TxId) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(used) = self.last_used.remove(&id) {
+            self.by_use.remove(&used);
+        }
+        if self.last_used.len() >= self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.last_used.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.last_used.insert(id, self.clock);
+        self.by_use.insert(self.clock, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction() -> Transaction {
+        let (sk, from) = crate::generate_keypair();
+        let (_, to) = crate::generate_keypair();
+        Transaction::new(from, to, &sk, 10, 0).unwrap()
+    }
+
+    #[test]
+    fn a_signature_is_checked_once_and_the_least_recently_used_is_forgotten() {
+        let cache = SignatureCache::new(2);
+        let (a, b, c) = (transaction(), transaction(), transaction());
+        assert_eq!(cache.verify(&a), Ok(()));
+        assert_eq!(cache.verify(&a), Ok(()));
+        assert_eq!(cache.checks(), 1);
+
+        assert_eq!(cache.verify(&b), Ok(()));
+        // a is used again, so b is the one that makes room for c
+        assert_eq!(cache.verify(&a), Ok(()));
+        assert_eq!(cache.verify(&c), Ok(()));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(&a.id()) && cache.contains(&c.id()));
+        assert!(!cache.contains(&b.id()));
+        assert_eq!(cache.checks(), 3);
+    }
+
+    #[test]
+    fn signatures_that_do_not_hold_are_not_kept() {
+        let cache = SignatureCache::new(10);
+        let (_, other) = crate::generate_keypair();
+        let mut forged = transaction();
+        forged.from = other;
+        assert_eq!(cache.verify(&forged), Err(SigError::BadSignature));
+        assert_eq!(cache.verify(&forged), Err(SigError::BadSignature));
+        assert_eq!(cache.checks(), 2);
+        assert!(cache.is_empty());
+
+        let mut rehashed = transaction();
+        rehashed.hash = [0; 32];
+        assert_eq!(cache.verify(&rehashed), Err(SigError::HashMismatch));
+    }
+}
@@ -0,0 +1,327 @@
+// a network of chains in one process for consensus tests. The nodes pass blocks over an in-memory bus with
+// latency, losses and partitions, and one manual clock moves all of them through the timeslots, so a run
+// with the same seed is the same every time and nothing waits for a socket or the time of the machine
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    block::{Block, BlockHash},
+    blockchain::{Blockchain, BlockchainError, TipRef},
+    clock::ManualClock,
+    draw::DrawCache,
+    keys::SecretKey,
+    params::ChainParams,
+    Error, Timeslot,
+};
+
+/// How the bus treats the messages between the nodes
+#[derive(Debug, Clone, Default)]
+pub struct SimConfig {
+    pub latency: u64, // timeslots before a message arrives, with 0 it arrives in the timeslot it is sent in
+    pub jitter: u64,  // up to this many timeslots more, picked for every message
+    pub drop_probability: f64,
+    pub seed: u64, // of the jitter and the drops
+}
+
+// what the nodes send each other, the announces, block requests and blocks of the real network
+#[derive(Debug, Clone)]
+enum SimMessage {
+    Announce(BlockHash),
+    Request(BlockHash),
+    Block(Arc<Block>),
+}
+
+#[derive(Debug)]
+struct Envelope {
+    from: usize,
+    to: usize,
+    message: SimMessage,
+}
+
+#[derive(Debug)]
+struct SimNode {
+    blockchain: Blockchain,
+    sk: SecretKey,
+    draws: DrawCache,
+}
+
+/// Nodes that each stake with one key, on a chain whose root accounts are all the keys
+#[derive(Debug)]
+pub struct SimNetwork {
+    nodes: Vec<SimNode>,
+    clock: ManualClock,
+    config: SimConfig,
+    rng: ChaCha8Rng,
+    in_flight: BTreeMap<(Timeslot, u64), Envelope>, // by when it arrives and then by when it was sent
+    sent: u64,
+    sides: Option<Vec<u8>>, // the side of the partition of every node, they only reach the nodes on their side
+    dropped: u64,
+}
+
+impl SimNetwork {
+    pub fn new(keys: Vec<SecretKey>, params: ChainParams, config: SimConfig) -> Self {
+        let clock = ManualClock::default();
+        let root_accounts = keys.iter().map(SecretKey::to_public_key).collect();
+        let genesis = Blockchain::start_with_clock(root_accounts, params, Arc::new(clock.clone()));
+        let nodes = keys
+            .into_iter()
+            .map(|sk| SimNode {
+                blockchain: genesis.clone(),
+                sk,
+                draws: DrawCache::default(),
+            })
+            .collect();
+        Self {
+            nodes,
+            clock,
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            config,
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            sides: None,
+            dropped: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn blockchain(&self, node: usize) -> &Blockchain {
+        &self.nodes[node].blockchain
+    }
+
+    pub fn blockchain_mut(&mut self, node: usize) -> &mut Blockchain {
+        &mut self.nodes[node].blockchain
+    }
+
+    /// The tip of every node
+    pub fn tips(&self) -> Vec<TipRef> {
+        self.nodes.iter().map(|node| node.blockchain.tip()).collect()
+    }
+
+    /// Takes effect for the messages sent from now on, the randomness starts again from the seed
+    pub fn set_config(&mut self, config: SimConfig) {
+        self.rng = ChaCha8Rng::seed_from_u64(config.seed);
+        self.config = config;
+    }
+
+    /// The messages lost on the way or between the sides of a partition
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn now(&self) -> Timeslot {
+        self.nodes[0].blockchain.calculate_timeslot()
+    }
+
+    /// The nodes of `a` and of `b` only reach the nodes on their own side, the nodes in neither are a third side.
+    /// The messages in flight between the sides are lost
+    pub fn partition(&mut self, a: &[usize], b: &[usize]) {
+        let mut sides = vec![0; self.nodes.len()];
+        a.iter().for_each(|&node| sides[node] = 1);
+        b.iter().for_each(|&node| sides[node] = 2);
+        self.sides = Some(sides);
+    }
+
+    /// Every node reaches every other again, and they announce their tips to each other as on a new connection
+    pub fn heal(&mut self) {
+        self.sides = None;
+        for node in 0..self.nodes.len() {
+            let tip = self.nodes[node].blockchain.get_best_hash();
+            self.announce(node, tip);
+        }
+    }
+
+    /// Every node draws in each of the timeslots and announces the block when it wins, then the messages that
+    /// arrive in the timeslot are handled
+    pub fn run_slots(&mut self, slots: u64) {
+        for _ in 0..slots {
+            self.next_slot();
+            for i in 0..self.nodes.len() {
+                let node = &mut self.nodes[i];
+                let Some(block) = node.blockchain.try_produce(&node.sk, &mut node.draws) else {
+                    continue;
+                };
+                let hash = block.hash;
+                if node.blockchain.add_block(block).is_ok() {
+                    self.announce(i, hash);
+                }
+            }
+            self.deliver();
+        }
+    }
+
+    /// Lets the timeslots pass without anyone winning until every message has arrived or is lost
+    pub fn settle(&mut self) {
+        while !self.in_flight.is_empty() {
+            self.next_slot();
+            self.deliver();
+        }
+    }
+
+    fn next_slot(&mut self) {
+        let slot_length = self.nodes[0].blockchain.params().slot_length_ms;
+        self.clock.advance(slot_length);
+    }
+
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        self.sides.as_ref().is_none_or(|sides| sides[from] == sides[to])
+    }
+
+    fn announce(&mut self, from: usize, hash: BlockHash) {
+        for to in (0..self.nodes.len()).filter(|&to| to != from) {
+            self.send(from, to, SimMessage::Announce(hash));
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: SimMessage) {
+        if self.config.drop_probability > 0.0 && self.rng.gen_bool(self.config.drop_probability) {
+            self.dropped += 1;
+            return;
+        }
+        let jitter = self.rng.gen_range(0..=self.config.jitter);
+        let arrives = self.now() + self.config.latency + jitter;
+        self.in_flight.insert((arrives, self.sent), Envelope { from, to, message });
+        self.sent += 1;
+    }
+
+    fn deliver(&mut self) {
+        let now = self.now();
+        while let Some(entry) = self.in_flight.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let envelope = entry.remove();
+            if !self.reaches(envelope.from, envelope.to) {
+                self.dropped += 1;
+                continue;
+            }
+            self.receive(envelope);
+        }
+    }
+
+    // like the client handles the messages of its peers
+    fn receive(&mut self, Envelope { from, to, message }: Envelope) {
+        let blockchain = &mut self.nodes[to].blockchain;
+        match message {
+            SimMessage::Announce(hash) => {
+                if blockchain.block_by_hash(&hash).is_none() {
+                    self.send(to, from, SimMessage::Request(hash));
+                }
+            }
+            SimMessage::Request(hash) => {
+                if let Some(block) = blockchain.block_by_hash(&hash).cloned() {
+                    self.send(to, from, SimMessage::Block(block));
+                }
+            }
+            SimMessage::Block(block) => {
+                let hash = block.hash;
+                match blockchain.add_block(block) {
+                    // we walk back from the orphan until we reach our chain
+                    Err(Error::BlockchainError(BlockchainError::OrphanBlock(missing))) => {
+                        self.send(to, from, SimMessage::Request(missing));
+                    }
+                    Ok(()) => self.announce(to, hash),
+                    Err(_) => (),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::*;
+
+    // everyone wins every timeslot, so there is a fork in every timeslot for the nodes to agree on
+    fn winning_network(nodes: usize, config: SimConfig) -> SimNetwork {
+        let keys = (0..nodes).map(|_| crate::generate_keypair().0).collect();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        SimNetwork::new(keys, params, config)
+    }
+
+    fn assert_converged(sim: &SimNetwork) {
+        let tip = sim.blockchain(0).tip();
+        let state_root = sim.blockchain(0).ledger().state_root();
+        for node in 0..sim.len() {
+            let blockchain = sim.blockchain(node);
+            assert_eq!(blockchain.tip(), tip, "node {node}");
+            assert_eq!(blockchain.ledger().state_root(), state_root, "node {node}");
+            assert!(blockchain.check_best_path());
+            assert_eq!(blockchain.verify_chain(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn equal_depth_tips_converge_in_any_order() {
+        // the jitter of each seed lets the competing blocks of a timeslot arrive in another order
+        let mut sim = winning_network(3, SimConfig::default());
+        for seed in 0..6 {
+            sim.set_config(SimConfig {
+                jitter: 2,
+                seed,
+                ..Default::default()
+            });
+            sim.run_slots(1);
+            sim.settle();
+            assert_converged(&sim);
+        }
+        assert_eq!(sim.blockchain(0).tip().depth, 6);
+    }
+
+    #[test]
+    fn partitioned_nodes_converge_after_healing() {
+        let mut sim = winning_network(4, SimConfig::default());
+        sim.run_slots(2);
+        assert_converged(&sim);
+
+        sim.partition(&[0, 1], &[2, 3]);
+        sim.run_slots(5);
+        let tips = sim.tips();
+        assert_eq!(tips[0], tips[1]);
+        assert_eq!(tips[2], tips[3]);
+        assert_ne!(tips[0], tips[2]);
+        assert_eq!(tips[0].depth, 7);
+
+        sim.heal();
+        sim.run_slots(2);
+        assert_converged(&sim);
+        assert_eq!(sim.blockchain(0).tip().depth, 9);
+    }
+
+    #[test]
+    fn slow_lossy_links_converge_once_they_recover() {
+        let config = SimConfig {
+            latency: 1,
+            jitter: 2,
+            drop_probability: 0.3,
+            seed: 7,
+        };
+        let mut sim = winning_network(4, config.clone());
+        sim.run_slots(20);
+        assert!(sim.dropped() > 0);
+
+        sim.set_config(SimConfig {
+            drop_probability: 0.0,
+            ..config
+        });
+        sim.heal();
+        sim.settle();
+        assert_converged(&sim);
+        assert!(sim.blockchain(0).tip().depth > 0);
+    }
+}
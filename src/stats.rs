@@ -0,0 +1,127 @@
+// numbers about the block tree and the best path for watching a testnet.
+// they are worked out from the chain when asked for, unlike the metrics that count events as they happen
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{account::AccountId, blockchain::Blockchain};
+
+/// The shape of the chain at the time it was read
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChainStats {
+    pub best_height: u64, // the depth of the tip
+    pub total_blocks: usize, // in the block tree, on every fork
+    pub forks: usize, // blocks in the tree without children, the tip is one of them
+    pub missed_slots: u64, // timeslots between the genesis block and the tip without a block on the best path
+    pub proposers: BTreeMap<AccountId, u64>, // blocks on the best path by their winner, the genesis block is not won
+    pub average_transactions: f64, // per block on the best path above the genesis block
+    pub fees: u64, // paid by the transactions on the best path
+    pub rewards: u64, // given to the winners on the best path, without the root allocations
+}
+
+impl Blockchain {
+    /// Reads the stats of the block tree and the best path
+    pub fn stats(&self) -> ChainStats {
+        let best_height = self.tip().depth;
+        let parents: HashSet<_> = self.tree_iter().map(|block| block.prev_hash).collect();
+        let forks = self.tree_iter().filter(|block| !parents.contains(&block.hash)).count();
+
+        // from the tip down to the genesis block
+        let best_path: Vec<_> = self.best_path_iter().collect();
+        let missed_slots = best_path
+            .windows(2)
+            .map(|pair| pair[0].timeslot() - pair[1].timeslot() - 1)
+            .sum();
+        let won = &best_path[..best_path.len() - 1];
+        let mut proposers = BTreeMap::new();
+        for block in won {
+            *proposers.entry(block.winner_id()).or_default() += 1;
+        }
+        let transactions: u64 = won.iter().map(|block| block.transaction_count()).sum();
+        let average_transactions = match won.len() {
+            0 => 0.0,
+            blocks => transactions as f64 / blocks as f64,
+        };
+
+        let ledger = self.ledger();
+        ChainStats {
+            best_height,
+            total_blocks: self.block_count(),
+            forks,
+            missed_slots,
+            proposers,
+            average_transactions,
+            fees: ledger.fees_paid(),
+            rewards: ledger.rewarded_blocks() * self.params().block_reward,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        blockchain::BlockchainError, clock::ManualClock, draw::DrawCache, params::ChainParams, transaction::Transaction,
+        Timeslot, BLOCK_REWARD, TRANSACTION_FEE,
+    };
+
+    #[test]
+    fn stats_count_the_tree_and_the_best_path() {
+        let (a_sk, a) = crate::generate_keypair();
+        let (b_sk, b) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        // without hardness every draw wins
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain =
+            Blockchain::start_with_clock(vec![a.clone(), b.clone()], params, Arc::new(clock.clone()));
+        assert_eq!(blockchain.stats().best_height, 0);
+        assert_eq!(blockchain.stats().average_transactions, 0.0);
+
+        // timeslot 1 by a, then two empty timeslots and timeslot 4 by b
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &a_sk, 1), Ok(()));
+        clock.advance_slots(2);
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &b_sk, 1), Ok(()));
+        let second = Arc::clone(blockchain.best_path_block(2).unwrap());
+        assert_eq!(second.timeslot(), Timeslot(4));
+        // a fork is checked against the ledger of its branch, so it is built on the chain as it was here
+        let at_second = blockchain.clone();
+
+        // timeslot 5 by a with a payment
+        let payment = Transaction::new(a.clone(), b.clone(), &a_sk, 10, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment), Ok(false));
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &a_sk, 1), Ok(()));
+
+        // b builds on its own block in a later timeslot with a draw that loses the tiebreak
+        let third = Arc::clone(blockchain.best_path_block(3).unwrap());
+        let fork = std::iter::repeat_with(|| {
+            clock.advance_slots(1);
+            let draw = at_second.get_draw(&b_sk, &mut DrawCache::default());
+            at_second.get_new_block(second.hash, draw, &b_sk)
+        })
+            .find(|fork| fork.cmp_priority(&third).is_lt())
+            .unwrap();
+        assert_eq!(blockchain.add_block(fork), BlockchainError::BestPathNotUpdated.into());
+
+        let stats = blockchain.stats();
+        assert_eq!(stats.best_height, 3);
+        assert_eq!(stats.total_blocks, 5);
+        assert_eq!(stats.forks, 2);
+        assert_eq!(stats.missed_slots, 2);
+        let proposers = BTreeMap::from([(AccountId::from(&a), 2), (AccountId::from(&b), 1)]);
+        assert_eq!(stats.proposers, proposers);
+        assert_eq!(stats.average_transactions, 1.0 / 3.0);
+        assert_eq!(stats.fees, TRANSACTION_FEE);
+        assert_eq!(stats.rewards, 3 * BLOCK_REWARD);
+
+        let through_serde: ChainStats = bincode::deserialize(&bincode::serialize(&stats).unwrap()).unwrap();
+        assert_eq!(through_serde, stats);
+    }
+}
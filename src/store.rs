@@ -0,0 +1,307 @@
+// blocks are appended to a log as they are accepted, so a crash loses none of them without writing the whole
+// chain on every block. The whole chain is only written when the log is compacted
+
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    block::Block,
+    blockchain::{Blockchain, Genesis},
+    compression::{deflate, inflate},
+    logging, Error, Result,
+};
+
+const CHAIN_RECORD: u8 = 0; // the blockchain at the last compaction
+const BLOCK_RECORD: u8 = 1; // the canonical encoding of an accepted block
+const DEFLATED: u8 = 0x80; // set on the kind of a record whose bytes are deflated, logs written before are still read
+const RECORD_HEADER: usize = 9; // the kind, the length and the CRC-32 of the bytes
+
+/// Where a node keeps its chain between runs
+pub trait ChainStore: Send + Debug {
+    /// Keeps a block that was accepted, it is on disk when this returns
+    fn append(&mut self, block: &Block) -> Result<()>;
+
+    /// Replaces what is stored with the chain, the blocks appended after it are replayed on top of it
+    fn compact(&mut self, blockchain: &Blockchain) -> Result<()>;
+
+    /// The stored chain with the blocks appended since it replayed through `add_block`, none if nothing is stored
+    fn load(&mut self) -> Result<Option<Blockchain>>;
+}
+
+/// A store shared by the node and whoever made it
+pub type SharedStore = Arc<Mutex<dyn ChainStore>>;
+
+/// An append-only file of records, the chain at the last compaction and then the blocks accepted since.
+/// A record that was cut short by a crash or is corrupted ends the log, it and everything after it are dropped
+#[derive(Debug)]
+pub struct BlockLog {
+    path: PathBuf,
+    file: Option<File>, // opened for appending once it is known where the valid records end
+}
+
+impl BlockLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            file: None,
+        }
+    }
+
+    pub fn shared(self) -> SharedStore {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Every block in the log with the genesis they build on, the blocks of the chain at the last compaction
+    /// and those appended since. Only the blocks and the genesis are taken from the chain, not its ledger
+    pub fn blocks(&self) -> Result<Option<(Genesis, Vec<Block>)>> {
+        let Some(bytes) = self.read()? else {
+            return Ok(None);
+        };
+        let mut records = read_records(&bytes).into_iter();
+        let Some((CHAIN_RECORD, chain)) = records.next().and_then(|(_, kind, bytes)| unpack(kind, bytes)) else {
+            return Ok(None);
+        };
+        let chain: Blockchain = bincode::deserialize(&chain).map_err(|_| Error::InvalidEncoding)?;
+        let mut blocks: Vec<Block> = chain.tree_iter().chain(chain.orphans_iter()).map(|b| Block::clone(b)).collect();
+        blocks.extend(records.map_while(|(_, kind, bytes)| match unpack(kind, bytes)? {
+            (BLOCK_RECORD, bytes) => Block::from_bytes(&bytes).ok(),
+            _ => None,
+        }));
+        Ok(Some((chain.genesis(), blocks)))
+    }
+
+    /// Replaces the chain in the log with the one `Blockchain::reindex` rebuilds from the blocks in it
+    pub fn reindex(&mut self) -> Result<Option<Blockchain>> {
+        let Some((genesis, blocks)) = self.blocks()? else {
+            return Ok(None);
+        };
+        let blockchain = Blockchain::reindex(blocks.into_iter(), genesis.params, genesis.root_accounts, genesis.start_time)
+            .map_err(Error::Reindex)?;
+        self.compact(&blockchain)?;
+        Ok(Some(blockchain))
+    }
+
+    fn read(&self) -> Result<Option<Vec<u8>>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(_) => Err(Error::StorageError),
+        }
+    }
+
+    fn write(&mut self, kind: u8, bytes: &[u8]) -> Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path);
+            self.file = Some(file.map_err(|_| Error::StorageError)?);
+        }
+        let file = self.file.as_mut().expect("unreachable");
+        file.write_all(&deflated_record(kind, bytes))
+            .and_then(|()| file.sync_data())
+            .map_err(|_| Error::StorageError)
+    }
+}
+
+impl ChainStore for BlockLog {
+    fn append(&mut self, block: &Block) -> Result<()> {
+        self.write(BLOCK_RECORD, &block.to_bytes())
+    }
+
+    // the new log replaces the old one in one step, so a crash leaves one of them whole
+    fn compact(&mut self, blockchain: &Blockchain) -> Result<()> {
+        let bytes = bincode::serialize(blockchain).map_err(|_| Error::InvalidEncoding)?;
+        let partial = self.path.with_extension("partial");
+        std::fs::write(&partial, deflated_record(CHAIN_RECORD, &bytes))
+            .and_then(|()| std::fs::rename(&partial, &self.path))
+            .map_err(|_| Error::StorageError)?;
+        self.file = None;
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<Option<Blockchain>> {
+        let Some(bytes) = self.read()? else {
+            return Ok(None);
+        };
+        let mut records = read_records(&bytes).into_iter();
+        let Some((_, kind, stored)) = records.next() else {
+            return Ok(None);
+        };
+        let Some((CHAIN_RECORD, chain)) = unpack(kind, stored) else {
+            return Ok(None);
+        };
+        let mut blockchain: Blockchain = bincode::deserialize(&chain).map_err(|_| Error::InvalidEncoding)?;
+
+        let mut valid = RECORD_HEADER + stored.len();
+        for (at, kind, bytes) in records {
+            let block = match unpack(kind, bytes).map(|(kind, bytes)| (kind, Block::from_bytes(&bytes))) {
+                Some((BLOCK_RECORD, Ok(block))) => block,
+                _ => break,
+            };
+            // blocks that did not move the tip and orphans are kept like when they arrived
+            let _ = match block.has_body() {
+                true => blockchain.add_block(block),
+                false => blockchain.add_header(block.header().clone()),
+            };
+            valid = at + RECORD_HEADER + bytes.len();
+        }
+
+        // the blocks appended from now on follow the last valid record
+        if valid < bytes.len() {
+            let dropped = bytes.len() - valid;
+            tracing::warn!(target: logging::STORE, dropped, "dropped the bytes after the last valid record");
+            let file = OpenOptions::new().write(true).open(&self.path).map_err(|_| Error::StorageError)?;
+            file.set_len(valid as u64).map_err(|_| Error::StorageError)?;
+        }
+        self.file = None;
+        Ok(Some(blockchain))
+    }
+}
+
+fn record(kind: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER + bytes.len());
+    record.push(kind);
+    record.extend((bytes.len() as u32).to_be_bytes());
+    record.extend(crc32fast::hash(bytes).to_be_bytes());
+    record.extend_from_slice(bytes);
+    record
+}
+
+// the bytes are deflated after they are encoded, so a block is read back with the bytes it was hashed with
+fn deflated_record(kind: u8, bytes: &[u8]) -> Vec<u8> {
+    record(kind | DEFLATED, &deflate(bytes))
+}
+
+// the kind of a record without the deflated bit and its bytes, none if they do not inflate
+fn unpack(kind: u8, bytes: &[u8]) -> Option<(u8, Cow<'_, [u8]>)> {
+    match kind & DEFLATED {
+        0 => Some((kind, Cow::Borrowed(bytes))),
+        _ => Some((kind & !DEFLATED, Cow::Owned(inflate(bytes, usize::MAX)?))),
+    }
+}
+
+// the records with where they start, up to the first that is cut short or fails its CRC
+fn read_records(bytes: &[u8]) -> Vec<(usize, u8, &[u8])> {
+    let mut records = Vec::new();
+    let mut at = 0;
+    while let Some(header) = bytes.get(at..at + RECORD_HEADER) {
+        let len = u32::from_be_bytes(header[1..5].try_into().expect("unreachable")) as usize;
+        let crc = u32::from_be_bytes(header[5..9].try_into().expect("unreachable"));
+        let Some(body) = bytes.get(at + RECORD_HEADER..at + RECORD_HEADER + len) else {
+            break;
+        };
+        if crc32fast::hash(body) != crc {
+            break;
+        }
+        records.push((at, header[0], body));
+        at += RECORD_HEADER + len;
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{blockchain::TipRef, clock::ManualClock, params::ChainParams, transaction::Transaction};
+
+    #[test]
+    fn a_log_cut_short_by_a_crash_recovers_every_whole_block() {
+        let path = std::env::temp_dir().join(format!("lasagna-blocks-{}.log", uuid::Uuid::new_v4()));
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        // without hardness every draw wins
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        let mut log = BlockLog::new(&path);
+        assert_eq!(log.load().map(|chain| chain.is_none()), Ok(true));
+        log.compact(&blockchain).unwrap();
+        for nonce in 0..5 {
+            let payment = Transaction::new(pk.clone(), receiver.clone(), &sk, 10, nonce).unwrap();
+            assert_eq!(blockchain.add_transaction(payment), Ok(false));
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+            log.append(blockchain.best_path_block(blockchain.tip().depth).unwrap()).unwrap();
+        }
+        let whole = std::fs::metadata(&path).unwrap().len();
+
+        // the writer dies halfway through the next record
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        let sixth = Block::clone(blockchain.best_path_block(6).unwrap());
+        let cut = deflated_record(BLOCK_RECORD, &sixth.to_bytes());
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&cut[..cut.len() / 2]).unwrap();
+
+        let mut log = BlockLog::new(&path);
+        let recovered = log.load().unwrap().unwrap();
+        assert_eq!(recovered.tip(), TipRef { hash: blockchain.best_path_hash(5).unwrap(), depth: 5 });
+        assert_eq!(recovered.ledger().state_root(), *blockchain.best_path_block(5).unwrap().state_root());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), whole);
+        // and the log continues after the last whole record
+        log.append(&sixth).unwrap();
+        let recovered = BlockLog::new(&path).load().unwrap().unwrap();
+        assert_eq!(recovered.tip(), blockchain.tip());
+        assert_eq!(recovered.verify_chain(), Ok(()));
+
+        // a corrupted record ends the log there
+        let mut bytes = std::fs::read(&path).unwrap();
+        let third = read_records(&bytes)[3].0;
+        bytes[third + RECORD_HEADER + 10] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+        let recovered = BlockLog::new(&path).load().unwrap().unwrap();
+        assert_eq!(recovered.tip().depth, 2);
+        assert_eq!(read_records(&std::fs::read(&path).unwrap()).len(), 3);
+
+        // a compacted log is the chain alone
+        let mut log = BlockLog::new(&path);
+        log.compact(&blockchain).unwrap();
+        assert_eq!(read_records(&std::fs::read(&path).unwrap()).len(), 1);
+        assert_eq!(log.load().unwrap().unwrap().tip(), blockchain.tip());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn logs_written_before_records_were_deflated_are_read() {
+        let path = std::env::temp_dir().join(format!("lasagna-blocks-{}.log", uuid::Uuid::new_v4()));
+        let (sk, pk) = crate::generate_keypair();
+        let (_, receiver) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        let chain = bincode::serialize(&blockchain).unwrap();
+        let mut plain = record(CHAIN_RECORD, &chain);
+        let mut deflated = deflated_record(CHAIN_RECORD, &chain);
+        for nonce in 0..3 {
+            let payment = Transaction::new(pk.clone(), receiver.clone(), &sk, 10, nonce).unwrap();
+            assert_eq!(blockchain.add_transaction(payment), Ok(false));
+            assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+            let block = blockchain.best_path_block(blockchain.tip().depth).unwrap().to_bytes();
+            plain.extend(record(BLOCK_RECORD, &block));
+            deflated.extend(deflated_record(BLOCK_RECORD, &block));
+        }
+        assert!(deflated.len() < plain.len());
+
+        // both kinds of records may be in one log, the blocks appended after an upgrade are deflated
+        std::fs::write(&path, &plain).unwrap();
+        let mut log = BlockLog::new(&path);
+        assert_eq!(log.load().unwrap().unwrap().tip(), blockchain.tip());
+        assert_eq!(blockchain.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        log.append(blockchain.best_path_block(4).unwrap()).unwrap();
+        let kinds: Vec<_> = read_records(&std::fs::read(&path).unwrap()).iter().map(|(_, kind, _)| *kind).collect();
+        assert_eq!(kinds, [CHAIN_RECORD, BLOCK_RECORD, BLOCK_RECORD, BLOCK_RECORD, BLOCK_RECORD | DEFLATED]);
+        let recovered = BlockLog::new(&path).load().unwrap().unwrap();
+        assert_eq!(recovered.tip(), blockchain.tip());
+        assert_eq!(recovered.verify_chain(), Ok(()));
+        assert_eq!(BlockLog::new(&path).blocks().unwrap().unwrap().1.len(), 5);
+        let _ = std::fs::remove_file(&path);
+    }
+}
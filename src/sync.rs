@@ -0,0 +1,140 @@
+// a node that is behind asks a peer for the blocks of its best path above the last block they share.
+// the peer streams them in batches so no message has to carry the whole chain, and the node can ask again
+// from the last block it applied when the stream stops
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{block::{Block, BlockHash}, blockchain::Blockchain};
+
+/// How far a sync has come, it is sent with every batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncProgress {
+    pub current_depth: u64, // of the last block of the batch
+    pub target_depth: u64, // the tip of the peer when it sent the batch
+}
+
+impl SyncProgress {
+    pub fn is_done(&self) -> bool {
+        self.current_depth >= self.target_depth
+    }
+
+    pub fn percent(&self) -> f64 {
+        match self.target_depth {
+            0 => 100.0,
+            target => 100.0 * self.current_depth.min(target) as f64 / target as f64,
+        }
+    }
+}
+
+impl Blockchain {
+    /// Hashes of the best path from the tip down to the genesis block, the first ten one apart and then
+    /// twice as far apart each time, so a peer finds the last block we share in a short list
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut depth = self.tip().depth;
+        let mut step = 1;
+        let mut locator = Vec::new();
+        loop {
+            locator.extend(self.best_path_hash(depth));
+            if depth == 0 {
+                return locator;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            depth = depth.saturating_sub(step);
+        }
+    }
+
+    /// At most `max` blocks of the best path above the deepest block of the locator that is on it, and no more
+    /// than `max_bytes` of them unless the first is larger. None if none of them are on it, and the batch ends
+    /// early at a block we only have the header of
+    pub fn sync_batch(
+        &self,
+        locator: &[BlockHash],
+        max: usize,
+        max_bytes: u64,
+    ) -> Option<(SyncProgress, Vec<Block>)> {
+        let tip = self.tip().depth;
+        let locator: HashSet<_> = locator.iter().collect();
+        let shared = (0..=tip)
+            .rev()
+            .find(|depth| self.best_path_hash(*depth).is_some_and(|hash| locator.contains(&hash)))?;
+        let mut bytes = 0;
+        let blocks: Vec<Block> = (shared + 1..=tip)
+            .take(max)
+            .map_while(|depth| self.best_path_block(depth).filter(|block| block.has_body()))
+            .take_while(|block| {
+                let first = bytes == 0;
+                bytes += block.encoded_len() as u64;
+                first || bytes <= max_bytes
+            })
+            .map(|block| Block::clone(block))
+            .collect();
+        let progress = SyncProgress {
+            current_depth: shared + blocks.len() as u64,
+            target_depth: tip,
+        };
+        Some((progress, blocks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{clock::ManualClock, params::ChainParams};
+
+    #[test]
+    fn batches_continue_from_the_last_shared_block() {
+        let (sk, pk) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        // without hardness every draw wins
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut behind = Blockchain::start_with_clock(vec![pk], params, Arc::new(clock.clone()));
+        let mut ahead = behind.clone();
+        for _ in 0..25 {
+            assert_eq!(ahead.produce_new_block_on_best_path(&clock, &sk, 1), Ok(()));
+        }
+        let locator = ahead.locator();
+        assert_eq!(locator.len(), 14);
+        assert_eq!(locator.first(), Some(&ahead.tip().hash));
+        assert_eq!(locator.last(), Some(&ahead.genesis_hash()));
+
+        let mut seen = Vec::new();
+        loop {
+            let (progress, blocks) = ahead.sync_batch(&behind.locator(), 10, u64::MAX).unwrap();
+            seen.push(progress);
+            for block in blocks {
+                assert_eq!(behind.add_block(block), Ok(()));
+            }
+            assert_eq!(behind.tip().depth, progress.current_depth);
+            if progress.is_done() {
+                break;
+            }
+        }
+        let depths: Vec<_> = seen.iter().map(|progress| progress.current_depth).collect();
+        assert_eq!(depths, vec![10, 20, 25]);
+        assert_eq!(seen[0].percent(), 40.0);
+        assert_eq!(behind.tip(), ahead.tip());
+
+        // a chain that shares nothing gets nothing
+        let (_, other_pk) = crate::generate_keypair();
+        let other = Blockchain::start(vec![other_pk]);
+        assert!(ahead.sync_batch(&other.locator(), 10, u64::MAX).is_none());
+        assert_eq!(ahead.sync_batch(&[ahead.tip().hash], 10, u64::MAX).unwrap().1.len(), 0);
+
+        // large blocks are sent fewer at a time, but always at least one
+        let genesis = [ahead.genesis_hash()];
+        let size = ahead.best_path_block(1).unwrap().encoded_len() as u64;
+        assert_eq!(ahead.sync_batch(&genesis, 10, 3 * size).unwrap().1.len(), 3);
+        assert_eq!(ahead.sync_batch(&genesis, 10, 1).unwrap().1.len(), 1);
+    }
+}
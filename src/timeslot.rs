@@ -0,0 +1,121 @@
+// time is counted in slots of `slot_length_ms` since the start of the chain, a block is at most one per timeslot
+// on a path. Timeslots and depths were both plain numbers and easy to swap, so a timeslot has its own type
+
+use std::{
+    fmt,
+    num::ParseIntError,
+    ops::{Add, AddAssign, Sub},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A timeslot of a chain, the genesis block is in timeslot 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timeslot(pub u64);
+
+impl Timeslot {
+    pub const GENESIS: Self = Self(0);
+    pub const MAX: Self = Self(u64::MAX);
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn next(self) -> Self {
+        self + 1
+    }
+
+    /// The timeslots from `earlier` up to this one, 0 when `earlier` is not earlier
+    pub fn since(self, earlier: Timeslot) -> u64 {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// This many timeslots earlier, the genesis timeslot is as early as it gets
+    pub fn saturating_sub(self, slots: u64) -> Self {
+        Self(self.0.saturating_sub(slots))
+    }
+
+    pub fn checked_sub(self, slots: u64) -> Option<Self> {
+        self.0.checked_sub(slots).map(Self)
+    }
+
+    pub fn checked_add(self, slots: u64) -> Option<Self> {
+        self.0.checked_add(slots).map(Self)
+    }
+
+    pub fn saturating_add(self, slots: u64) -> Self {
+        Self(self.0.saturating_add(slots))
+    }
+
+    /// The millisecond the timeslot starts at, on a chain that started at `start_time`
+    pub fn start_millis(self, start_time: u128, slot_length_ms: u64) -> u128 {
+        start_time + self.0 as u128 * slot_length_ms as u128
+    }
+}
+
+impl Add<u64> for Timeslot {
+    type Output = Timeslot;
+
+    fn add(self, slots: u64) -> Timeslot {
+        Timeslot(self.0 + slots)
+    }
+}
+
+impl AddAssign<u64> for Timeslot {
+    fn add_assign(&mut self, slots: u64) {
+        self.0 += slots;
+    }
+}
+
+/// The number of timeslots between the two, it overflows like a `u64` when the right one is later
+impl Sub for Timeslot {
+    type Output = u64;
+
+    fn sub(self, earlier: Timeslot) -> u64 {
+        self.0 - earlier.0
+    }
+}
+
+impl From<u64> for Timeslot {
+    fn from(slot: u64) -> Self {
+        Self(slot)
+    }
+}
+
+// the plain number, it is also what the lottery hashes, see `Draw`
+impl fmt::Display for Timeslot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Timeslot {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeslots_count_and_print_like_numbers() {
+        let slot = Timeslot(41).next();
+        assert_eq!(slot, Timeslot(42));
+        assert_eq!(slot.to_string(), "42");
+        assert_eq!("42".parse::<Timeslot>(), Ok(slot));
+        assert!("-1".parse::<Timeslot>().is_err());
+        assert_eq!(slot - Timeslot(40), 2);
+        assert_eq!(slot.since(Timeslot(50)), 0);
+        assert_eq!(slot.saturating_sub(100), Timeslot::GENESIS);
+        assert_eq!(slot.checked_sub(43), None);
+        assert_eq!(slot.start_millis(1_000, 10), 1_420);
+        assert_eq!(serde_json::to_string(&slot).unwrap(), "42");
+        assert_eq!(bincode::serialize(&slot).unwrap(), bincode::serialize(&42u64).unwrap());
+    }
+}
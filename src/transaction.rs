@@ -1,38 +1,134 @@
 use std::hash::Hash;
 
-use rand::thread_rng;
-use rsa::pkcs1::EncodeRsaPublicKey;
-
-use rsa::RsaPrivateKey;
-use rsa::RsaPublicKey;
 use rsa::sha2::Sha256;
-use serde::{Deserialize, Serialize};
 use rsa::sha2::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountId;
+use crate::blockchain::BlockchainError;
+use crate::encoding::{serde_via_bytes, text_via_bytes, Reader, Writer};
+use crate::keys::{PublicKey, SecretKey, Signature};
+use crate::signatures::SigError;
+use crate::{Error, Result};
+use crate::Timeslot;
+use crate::TRANSACTION_FEE;
+
+const MIN_OUTPUT_BYTES: usize = 4 + 8; // the length of the key and the amount
+pub(crate) const MIN_TRANSACTION_BYTES: usize = 4 + 4 + 3 * 8 + 4 + 32; // with empty keys, outputs and signature
+
+/// Identifies a transaction by the SHA-256 of its canonical encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TxId(pub [u8; 32]);
 
-use crate::PssSignature;
+impl std::fmt::Display for TxId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Transaction {
-    pub(super) from: RsaPublicKey,
-    pub(super) to: RsaPublicKey,
-    pub(super) amount: u64,
-    pub(super) signature: PssSignature,
+    pub(super) from: PublicKey,
+    pub(super) outputs: Vec<(PublicKey, u64)>, // the receivers and what they are paid, all or none of them are paid
+    pub(super) fee: u64,
+    pub(super) nonce: u64, // the transactions of a sender are applied in nonce order from 0, a higher fee replaces one with the same nonce
+    pub(super) valid_until: Timeslot, // it can not be included in a block of a later timeslot
+    pub(super) signature: Signature,
     pub(super) hash: [u8; 32],
 }
 
 impl Transaction {
+    /// A transaction paying the minimum fee, it never expires.
+    /// The nonce is the number of transactions the sender has made before it
     pub fn new(
-        from: RsaPublicKey,
-        to: RsaPublicKey,
-        sk: &RsaPrivateKey,
+        from: PublicKey,
+        to: PublicKey,
+        sk: &SecretKey,
+        amount: u64,
+        nonce: u64,
+    ) -> Result<Self> {
+        Self::with_fee(from, to, sk, amount, TRANSACTION_FEE, nonce)
+    }
+
+    /// A transaction paying all of the balance to `to`, the minimum fee is taken from it
+    pub fn sweep(
+        from: PublicKey,
+        to: PublicKey,
+        sk: &SecretKey,
+        balance: u64,
+        nonce: u64,
+    ) -> Result<Self> {
+        Self::sweep_with_fee(from, to, sk, balance, TRANSACTION_FEE, nonce)
+    }
+
+    pub(crate) fn sweep_with_fee(
+        from: PublicKey,
+        to: PublicKey,
+        sk: &SecretKey,
+        balance: u64,
+        fee: u64,
+        nonce: u64,
+    ) -> Result<Self> {
+        // the amount can not be less than the fee either
+        match balance.checked_sub(fee) {
+            Some(amount) if amount >= fee => Self::with_fee(from, to, sk, amount, fee, nonce),
+            _ => BlockchainError::NothingToSweep.into(),
+        }
+    }
+
+    /// To replace a pending transaction sign a new one with its nonce and a higher fee
+    pub fn with_fee(
+        from: PublicKey,
+        to: PublicKey,
+        sk: &SecretKey,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+    ) -> Result<Self> {
+        Self::with_expiry(from, to, sk, amount, fee, nonce, Timeslot::MAX)
+    }
+
+    /// A transaction that can only be included in blocks up to and including the timeslot `valid_until`
+    pub fn with_expiry(
+        from: PublicKey,
+        to: PublicKey,
+        sk: &SecretKey,
         amount: u64,
+        fee: u64,
+        nonce: u64,
+        valid_until: Timeslot,
+    ) -> Result<Self> {
+        Self::with_outputs(from, vec![(to, amount)], sk, fee, nonce, valid_until)
+    }
+
+    /// Pays every output with one signature, the fee is paid once for all of them.
+    /// The sender can not pay itself and every output must pay something
+    pub fn with_outputs(
+        from: PublicKey,
+        outputs: Vec<(PublicKey, u64)>,
+        sk: &SecretKey,
+        fee: u64,
+        nonce: u64,
+        valid_until: Timeslot,
+    ) -> Result<Self> {
+        Self::validate_outputs(&from, &outputs)?;
+        Ok(Self::sign(from, outputs, sk, fee, nonce, valid_until))
+    }
+
+    // signs the outputs as they are, a transaction the constructors refuse can still arrive from the network
+    pub(crate) fn sign(
+        from: PublicKey,
+        outputs: Vec<(PublicKey, u64)>,
+        sk: &SecretKey,
+        fee: u64,
+        nonce: u64,
+        valid_until: Timeslot,
     ) -> Self {
-        let fields_string = Self::combine_fields_to_string(&from, &to, amount);
-        let _rng = thread_rng();
+        let fields_bytes = Self::combine_fields_to_bytes(&from, &outputs, fee, nonce, valid_until);
         let mut hasher = Sha256::new();
-        hasher.update(fields_string);
+        hasher.update(fields_bytes);
         let hash: [u8; 32] = hasher.finalize().into();
-        let signature = PssSignature::sign(sk, &hash).unwrap();
+        let signature = Signature::sign(sk, &hash).unwrap();
         let mut hasher = Sha256::new();
         hasher.update(signature.to_bytes()); 
         // we hash the signature as well, since we sign with RNG we have a unique hash 
@@ -40,27 +136,180 @@ impl Transaction {
 
         Self {
             from,
-            to,
-            amount,
+            outputs,
+            fee,
+            nonce,
+            valid_until,
             signature,
             hash,
         }
     }
 
-    fn combine_fields_to_string(
-        from: &RsaPublicKey,
-        to: &RsaPublicKey,
-        amount: u64,
-    ) -> String {
-        let hexify = |k: &RsaPublicKey| hex::encode(k.to_pkcs1_der().unwrap().as_bytes());
-        format!("{:?}{:?}{}", hexify(from), hexify(to), amount)
+    // a payment to the sender or of nothing only burns the fee and takes up room in a block
+    fn validate_outputs(from: &PublicKey, outputs: &[(PublicKey, u64)]) -> Result<()> {
+        if outputs.is_empty() || outputs.iter().any(|(_, amount)| *amount == 0) {
+            return BlockchainError::ZeroAmount.into();
+        }
+        let sender = AccountId::from(from);
+        if outputs.iter().any(|(to, _)| AccountId::from(to) == sender) {
+            return BlockchainError::SelfTransfer.into();
+        }
+        Ok(())
+    }
+
+    /// Whether the outputs are ones the constructors allow, transactions from the network are not built by them
+    pub fn check_outputs(&self) -> Result<()> {
+        Self::validate_outputs(&self.from, &self.outputs)
+    }
+
+    // the signed preimage, it is the start of the canonical encoding
+    fn combine_fields_to_bytes(
+        from: &PublicKey,
+        outputs: &[(PublicKey, u64)],
+        fee: u64,
+        nonce: u64,
+        valid_until: Timeslot,
+    ) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.key(from);
+        Self::encode_outputs(&mut writer, outputs);
+        writer.u64(fee).u64(nonce).timeslot(valid_until).finish()
+    }
+
+    fn encode_outputs(writer: &mut Writer, outputs: &[(PublicKey, u64)]) {
+        writer.len(outputs.len());
+        for (to, amount) in outputs {
+            writer.key(to).u64(*amount);
+        }
+    }
+
+    /// The canonical encoding, this is what is sent on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let transaction = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(transaction)
+    }
+
+    // the encoding without the version, used when transactions are part of a block
+    pub(crate) fn encode(&self, writer: &mut Writer) {
+        writer.key(&self.from);
+        Self::encode_outputs(writer, &self.outputs);
+        writer
+            .u64(self.fee)
+            .u64(self.nonce)
+            .timeslot(self.valid_until)
+            .bytes(self.signature.to_bytes())
+            .hash(&self.hash);
+    }
+
+    pub(crate) fn decode(reader: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            from: reader.key()?,
+            outputs: (0..reader.count(MIN_OUTPUT_BYTES)?)
+                .map(|_| Ok((reader.key()?, reader.u64()?)))
+                .collect::<Result<_>>()?,
+            fee: reader.u64()?,
+            nonce: reader.u64()?,
+            valid_until: reader.timeslot()?,
+            signature: Signature(reader.signature()?),
+            hash: reader.hash()?,
+        })
+    }
+
+    pub fn from(&self) -> &PublicKey {
+        &self.from
+    }
+
+    /// The transaction without its signature, as it was before it was signed
+    pub fn unsigned(&self) -> UnsignedTransaction {
+        UnsignedTransaction {
+            from: self.from.clone(),
+            outputs: self.outputs.clone(),
+            fee: self.fee,
+            nonce: self.nonce,
+            valid_until: self.valid_until,
+        }
+    }
+
+    pub fn outputs(&self) -> &[(PublicKey, u64)] {
+        &self.outputs
     }
 
+    pub fn from_id(&self) -> AccountId {
+        AccountId::from(&self.from)
+    }
+
+    /// The sum of the outputs, saturated if it does not fit in a u64
+    pub fn amount(&self) -> u64 {
+        self.outputs
+            .iter()
+            .fold(0u64, |sum, (_, amount)| sum.saturating_add(*amount))
+    }
+
+    /// What the sender is debited, the outputs and the fee, none if it does not fit in a u64
+    pub fn cost(&self) -> Option<u64> {
+        self.outputs
+            .iter()
+            .try_fold(self.fee, |sum, (_, amount)| sum.checked_add(*amount))
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn valid_until(&self) -> Timeslot {
+        self.valid_until
+    }
+
+    /// Whether the transaction is too old for a block in the timeslot
+    pub fn is_expired(&self, timeslot: Timeslot) -> bool {
+        self.valid_until < timeslot
+    }
+
+    /// The length of `to_bytes`, counted without allocating the encoding. Fees are compared per byte of it,
+    /// so a wallet estimates the fee of a transaction with this
+    pub fn encoded_len(&self) -> usize {
+        let mut writer = Writer::counting();
+        self.encode(&mut writer);
+        writer.written()
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+
+    pub fn id(&self) -> TxId {
+        TxId(Sha256::digest(self.to_bytes()).into())
+    }
+
+    /// Checks the signature every time, `signatures::SIGNATURES` keeps the ones that hold
     pub fn verify_signature(&self) -> bool {
-        let fields_string =
-            Self::combine_fields_to_string(&self.from, &self.to, self.amount);
+        self.check_signature().is_ok()
+    }
+
+    pub fn check_signature(&self) -> std::result::Result<(), SigError> {
+        #[cfg(test)]
+        SIGNATURE_CHECKS.with(|checks| checks.set(checks.get() + 1));
+        let fields_bytes = Self::combine_fields_to_bytes(
+            &self.from,
+            &self.outputs,
+            self.fee,
+            self.nonce,
+            self.valid_until,
+        );
         let mut hasher = Sha256::new();
-        hasher.update(fields_string);
+        hasher.update(fields_bytes);
         let fields_hash: [u8; 32] = hasher.finalize().into();
 
         let mut hasher = Sha256::new();
@@ -68,10 +317,99 @@ impl Transaction {
         // we hash the signature as well, since we sign with RNG we have a unique hash 
         let hash: [u8; 32] = hasher.finalize().into();
 
-        hash == self.hash && self.signature.verify(&self.from, &fields_hash).is_ok()
+        if hash != self.hash {
+            return Err(SigError::HashMismatch);
+        }
+        self.signature
+            .verify(&self.from, &fields_hash)
+            .map_err(|_| SigError::BadSignature)
     }
 }
 
+#[cfg(test)]
+thread_local! {
+    // the signatures checked on this thread, tests count them to see that the cache is used
+    pub(crate) static SIGNATURE_CHECKS: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+serde_via_bytes!(Transaction);
+text_via_bytes!(Transaction);
+
+/// A transaction that is built where the private key is not, and signed on the machine that holds it
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsignedTransaction {
+    from: PublicKey,
+    outputs: Vec<(PublicKey, u64)>,
+    fee: u64,
+    nonce: u64,
+    valid_until: Timeslot,
+}
+
+impl UnsignedTransaction {
+    /// The outputs are checked like `Transaction::with_outputs` checks them
+    pub fn new(
+        from: PublicKey,
+        outputs: Vec<(PublicKey, u64)>,
+        fee: u64,
+        nonce: u64,
+        valid_until: Timeslot,
+    ) -> Result<Self> {
+        Transaction::validate_outputs(&from, &outputs)?;
+        Ok(Self {
+            from,
+            outputs,
+            fee,
+            nonce,
+            valid_until,
+        })
+    }
+
+    /// The bytes whose hash is signed, they are also the canonical encoding of the unsigned transaction
+    pub fn to_signing_bytes(&self) -> Vec<u8> {
+        Transaction::combine_fields_to_bytes(&self.from, &self.outputs, self.fee, self.nonce, self.valid_until)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_signing_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes)?;
+        let from = reader.key()?;
+        let outputs = (0..reader.count(MIN_OUTPUT_BYTES)?)
+            .map(|_| Ok((reader.key()?, reader.u64()?)))
+            .collect::<Result<_>>()?;
+        let (fee, nonce, valid_until) = (reader.u64()?, reader.u64()?, reader.timeslot()?);
+        reader.finish()?;
+        Self::new(from, outputs, fee, nonce, valid_until)
+    }
+
+    /// Signs it the way the constructors of `Transaction` do, with the key of the sender
+    pub fn sign(&self, sk: &SecretKey) -> Result<Transaction> {
+        if sk.to_public_key() != self.from {
+            return Err(Error::WrongKey);
+        }
+        Ok(Transaction::sign(
+            self.from.clone(),
+            self.outputs.clone(),
+            sk,
+            self.fee,
+            self.nonce,
+            self.valid_until,
+        ))
+    }
+
+    pub fn from(&self) -> &PublicKey {
+        &self.from
+    }
+
+    pub fn outputs(&self) -> &[(PublicKey, u64)] {
+        &self.outputs
+    }
+}
+
+text_via_bytes!(UnsignedTransaction);
+
 impl PartialEq for Transaction {
     fn eq(&self, other: &Self) -> bool {
         self.hash == other.hash
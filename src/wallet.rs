@@ -0,0 +1,137 @@
+// a lightweight client for wallets, it talks to a single node instead of joining the network
+
+use std::{net::SocketAddr, path::Path, time::Duration};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    account::AccountId,
+    keys::{self, PublicKey, SecretKey},
+    keystore,
+    ledger::TxValidationError,
+    pippi::{handshake::Handshake, Message, MessageContent},
+    transaction::Transaction,
+    Error, ExternalMessage, Result,
+};
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct WalletClient {
+    address: SocketAddr,
+    stream: TcpStream,
+}
+
+impl WalletClient {
+    pub async fn connect(node: SocketAddr) -> Result<Self> {
+        let stream = TcpStream::connect(node)
+            .await
+            .map_err(|_| Error::NetworkError)?;
+        let address = stream.local_addr().map_err(|_| Error::NetworkError)?;
+        let mut client = Self { address, stream };
+        client
+            .send(Message::new_direct_message(
+                &address,
+                MessageContent::Contact(Handshake::new(None, 0)),
+            ))
+            .await?;
+        Ok(client)
+    }
+
+    /// Asks the node for the balance of the account on its best path
+    pub async fn get_balance(&mut self, account: AccountId) -> Result<u64> {
+        let request = ExternalMessage::BalanceReqFrom(self.address, account);
+        self.send(Message::new_direct_message(
+            &self.address,
+            MessageContent::App(request),
+        ))
+        .await?;
+
+        tokio::time::timeout(RESPONSE_TIMEOUT, self.read_balance(&account))
+            .await
+            .map_err(|_| Error::NetworkError)?
+    }
+
+    /// Hands the transaction to the node which floods it to the rest of the network
+    pub async fn submit_transaction(&mut self, transaction: Transaction) -> Result<()> {
+        let message = ExternalMessage::BroadcastTransaction(transaction);
+        self.send(Message::new_flood_message(
+            &self.address,
+            MessageContent::App(message),
+        ))
+        .await
+    }
+
+    async fn read_balance(&mut self, account: &AccountId) -> Result<u64> {
+        loop {
+            let message = Message::read_from(&mut self.stream, crate::MAX_FRAME_BYTES)
+                .await
+                .map_err(|_| Error::NetworkError)?;
+            // the node may send us other messages such as heartbeats, we skip those
+            if let MessageContent::App(ExternalMessage::Balance(of, balance)) = message.content() {
+                if of == account {
+                    return Ok(*balance);
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, message: Message) -> Result<()> {
+        message
+            .write_to(&mut self.stream)
+            .await
+            .map_err(|_| Error::NetworkError)
+    }
+}
+
+/// What went wrong with a refused transaction and what the holder of the key can do about it
+pub fn explain(reason: &TxValidationError) -> String {
+    let advice = match reason {
+        TxValidationError::BadSignature => "sign it again with the key of the sender",
+        TxValidationError::NoReceivers | TxValidationError::SelfTransfer => "send to another account",
+        TxValidationError::OutputTooSmall { .. } | TxValidationError::FeeTooLow { .. } => {
+            "raise the amounts to the minimum"
+        }
+        TxValidationError::Overflow => "lower the amounts",
+        TxValidationError::UnknownSender => "the sender has never received any las, fund it first",
+        TxValidationError::InsufficientFunds { .. } => "check the balance, transactions in the mempool are counted",
+        TxValidationError::AlreadyApplied | TxValidationError::NonceTooLow { .. } => {
+            "it is already on the chain, sign a new one with the next nonce"
+        }
+        TxValidationError::NonceGap { .. } => "submit the transactions with the missing nonces first",
+        TxValidationError::Expired { .. } => "sign it again with a later valid_until",
+        TxValidationError::KeyTooSmall { .. } => "send from an account with a larger key",
+    };
+    format!("{reason}, {advice}")
+}
+
+/// Writes the public key to `<name>.pem` and the private key to `<name>.key.pem` in the directory,
+/// the private key is encrypted if there is a passphrase
+pub fn write_keypair(dir: &Path, name: &str, sk: &SecretKey, passphrase: Option<&str>) -> Result<()> {
+    let public_pem = sk.to_public_key().to_pem()?;
+    std::fs::write(dir.join(format!("{name}.pem")), public_pem).map_err(|_| Error::InvalidPem)?;
+    let path = dir.join(format!("{name}.key.pem"));
+    match passphrase {
+        Some(passphrase) => keystore::save_encrypted(&path, sk, passphrase),
+        None => keystore::save_plaintext(&path, sk),
+    }
+}
+
+/// Reads a private key file, the passphrase is read from the environment variable or stdin if the file is encrypted
+pub fn read_private_key(path: &Path, passphrase_env: Option<&str>) -> Result<SecretKey> {
+    keystore::load(path, || keystore::read_passphrase(passphrase_env))
+}
+
+/// Reads a public key in any of the `KeyFormat`s, a private key is also accepted in which case its public key
+/// is returned. The error is that of the public key
+pub fn read_public_key(path: &Path) -> Result<PublicKey> {
+    let bytes = std::fs::read(path).map_err(|_| Error::InvalidPem)?;
+    keys::import_public(&bytes)
+        .or_else(|e| keys::import_private(&bytes).map(|sk| sk.to_public_key()).map_err(|_| e))
+}
+
+/// An address is taken as it is, anything else is read as the path of a key
+pub fn read_account(address_or_path: &str) -> Result<AccountId> {
+    address_or_path
+        .parse()
+        .or_else(|_| read_public_key(Path::new(address_or_path)).map(|pk| AccountId::from(&pk)))
+}
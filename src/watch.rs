@@ -0,0 +1,191 @@
+// the payments to and from the accounts a payment processor watches, with their confirmations as the best path
+// grows. Unlike the chain events they are worked out per account, so the watcher does not go through the blocks
+
+use tokio::sync::mpsc;
+
+use crate::{
+    account::AccountId,
+    block::Block,
+    events::EVENT_CHANNEL_CAPACITY,
+    history::Direction,
+    transaction::TxId,
+};
+
+/// What happened to a payment to or from a watched account, the events of an account come in the order of the best path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressEvent {
+    /// The transaction pays the account and its block is `confirmations` blocks deep in the best path.
+    /// It is sent again every time the depth changes until the block is final
+    IncomingConfirmed {
+        txid: TxId,
+        amount: u64,
+        confirmations: u64,
+    },
+    /// The same for a transaction from the account, `amount` is what it sends without the fee
+    OutgoingConfirmed {
+        txid: TxId,
+        amount: u64,
+        confirmations: u64,
+    },
+    /// The block of the transaction left the best path in a reorg, the transaction is back in the mempool
+    Unconfirmed { txid: TxId },
+}
+
+/// The events of a watched account. A watch that falls `EVENT_CHANNEL_CAPACITY` events behind is ended
+/// instead of the chain holding the events for it
+#[derive(Debug)]
+pub struct AddressWatch {
+    account: AccountId,
+    events: mpsc::Receiver<AddressEvent>,
+}
+
+impl AddressWatch {
+    pub fn account(&self) -> &AccountId {
+        &self.account
+    }
+
+    /// The next event, none once the watch has ended
+    pub async fn recv(&mut self) -> Option<AddressEvent> {
+        self.events.recv().await
+    }
+
+    /// The next event if it has already happened
+    pub fn try_recv(&mut self) -> Option<AddressEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+// futures-util comes with the rpc feature
+#[cfg(feature = "rpc")]
+impl futures_util::Stream for AddressWatch {
+    type Item = AddressEvent;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<AddressEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// The watches of a chain, it follows the best path block by block like the account history
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Watchers {
+    watchers: Vec<Watcher>,
+}
+
+#[derive(Debug, Clone)]
+struct Watcher {
+    account: AccountId,
+    sender: mpsc::Sender<AddressEvent>,
+    payments: Vec<Payment>, // the payments on the best path above the checkpoint, in the order of the path
+}
+
+#[derive(Debug, Clone)]
+struct Payment {
+    txid: TxId,
+    depth: u64,
+    direction: Direction,
+    amount: u64,
+    confirmations: u64, // the last that were sent, 0 until the tip moves with the block on the best path
+}
+
+impl Watchers {
+    pub(crate) fn watch(&mut self, account: AccountId) -> AddressWatch {
+        let (sender, events) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        self.watchers.push(Watcher {
+            account,
+            sender,
+            payments: Vec::new(),
+        });
+        AddressWatch { account, events }
+    }
+
+    /// Notes the payments of the block that joined the best path, they are sent when the tip moves
+    pub(crate) fn connect_block(&mut self, block: &Block) {
+        for watcher in self.watchers.iter_mut() {
+            let account = watcher.account;
+            for t in block.transactions() {
+                let mut directions = Vec::new();
+                if t.from_id() == account {
+                    let sent = t.outputs().iter().map(|(_, amount)| amount).sum::<u64>();
+                    directions.push((Direction::Outgoing, sent));
+                }
+                let incoming: Vec<_> = t
+                    .outputs()
+                    .iter()
+                    .filter(|(to, _)| AccountId::from(to) == account)
+                    .map(|(_, amount)| *amount)
+                    .collect();
+                if !incoming.is_empty() {
+                    directions.push((Direction::Incoming, incoming.iter().sum()));
+                }
+                for (direction, amount) in directions {
+                    watcher.payments.push(Payment {
+                        txid: t.id(),
+                        depth: block.depth(),
+                        direction,
+                        amount,
+                        confirmations: 0,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Unconfirms the payments of the block at the top of the best path when it is rolled back, the last first
+    pub(crate) fn disconnect_block(&mut self, block: &Block) {
+        self.watchers.retain_mut(|watcher| {
+            while watcher.payments.last().is_some_and(|payment| payment.depth == block.depth()) {
+                let payment = watcher.payments.pop().expect("invariant: there is a last payment");
+                if payment.confirmations > 0 && !watcher.send(AddressEvent::Unconfirmed { txid: payment.txid }) {
+                    return false;
+                }
+            }
+            true
+        });
+    }
+
+    /// Sends the confirmations of the payments below the new tip, those at or below the checkpoint are final
+    /// and not followed any further
+    pub(crate) fn new_tip(&mut self, tip: u64, checkpoint: u64) {
+        self.watchers.retain_mut(|watcher| {
+            if watcher.sender.is_closed() {
+                return false;
+            }
+            for i in 0..watcher.payments.len() {
+                let payment = &watcher.payments[i];
+                let confirmations = tip + 1 - payment.depth;
+                if confirmations == payment.confirmations {
+                    continue;
+                }
+                let (txid, amount) = (payment.txid, payment.amount);
+                let event = match payment.direction {
+                    Direction::Incoming => AddressEvent::IncomingConfirmed {
+                        txid,
+                        amount,
+                        confirmations,
+                    },
+                    Direction::Outgoing => AddressEvent::OutgoingConfirmed {
+                        txid,
+                        amount,
+                        confirmations,
+                    },
+                };
+                if !watcher.send(event) {
+                    return false;
+                }
+                watcher.payments[i].confirmations = confirmations;
+            }
+            watcher.payments.retain(|payment| payment.depth > checkpoint);
+            true
+        });
+    }
+}
+
+impl Watcher {
+    // false once the watch has ended or fallen too far behind
+    fn send(&self, event: AddressEvent) -> bool {
+        self.sender.try_send(event).is_ok()
+    }
+}
@@ -0,0 +1,365 @@
+// a WebSocket endpoint that pushes the changes of the chain to explorers and merchant backends as they happen.
+// a connection subscribes to topics with JSON-RPC requests and receives a notification per matching chain event
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+    time::timeout,
+};
+use tokio_tungstenite::tungstenite::{
+    self,
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+use crate::{
+    account::AccountId,
+    block::Block,
+    blockchain::BlockPtr,
+    events::ChainEvent,
+    logging,
+    rpc::{self, RpcError, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR},
+    Error,
+};
+
+/// A subscriber that takes longer than this to accept a notification is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Topic {
+    /// The header of every block that joins the best path
+    NewBlocks,
+    /// Every switch of the best path to another branch
+    Reorgs,
+    /// The transactions from or to the account that join or leave the best path
+    Address(AccountId),
+}
+
+impl FromStr for Topic {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new_blocks" => Ok(Topic::NewBlocks),
+            "reorgs" => Ok(Topic::Reorgs),
+            _ => s
+                .strip_prefix("address:")
+                .ok_or(Error::InvalidEncoding)?
+                .parse()
+                .map(Topic::Address),
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topic::NewBlocks => write!(f, "new_blocks"),
+            Topic::Reorgs => write!(f, "reorgs"),
+            Topic::Address(account) => write!(f, "address:{account}"),
+        }
+    }
+}
+
+/// Starts serving subscriptions on the address and returns the address that was bound
+pub async fn serve(
+    addr: SocketAddr,
+    events: broadcast::Sender<ChainEvent>,
+) -> crate::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|_| Error::NetworkError)?;
+    let addr = listener.local_addr().map_err(|_| Error::NetworkError)?;
+    tracing::info!(target: logging::RPC, url = %format!("ws://{addr}"), "serving subscriptions");
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            // subscribed before the handshake, so no event after the connection is missed
+            let events = events.subscribe();
+            tokio::spawn(async move {
+                if let Err(error) = handle_connection(stream, events).await {
+                    tracing::debug!(target: logging::RPC, ?error, "WebSocket connection failed");
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    mut events: broadcast::Receiver<ChainEvent>,
+) -> tungstenite::Result<()> {
+    let (mut write, mut read) = tokio_tungstenite::accept_async(stream).await?.split();
+    let mut topics = Vec::new();
+
+    loop {
+        let outgoing = tokio::select! {
+            message = read.next() => match message {
+                Some(Ok(Message::Text(text))) => vec![respond(&text, &mut topics)],
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => continue, // the pings are answered by tungstenite
+                Some(Err(e)) => return Err(e),
+            },
+            event = events.recv() => match event {
+                Ok(event) => notifications(&event, &topics),
+                // the channel holds the events a subscriber is behind on, we do not buffer more for it
+                Err(RecvError::Lagged(missed)) => {
+                    let close = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: format!("too slow, missed {missed} events").into(),
+                    };
+                    let _ = timeout(WRITE_TIMEOUT, write.send(Message::Close(Some(close)))).await;
+                    return Ok(());
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        };
+        for message in outgoing {
+            match timeout(WRITE_TIMEOUT, write.send(Message::Text(message.to_string()))).await {
+                Ok(sent) => sent?,
+                Err(_) => return Ok(()), // the subscriber stopped reading
+            }
+        }
+    }
+}
+
+// answers a subscribe or unsubscribe request
+fn respond(text: &str, topics: &mut Vec<Topic>) -> Value {
+    let request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(_) => return rpc::error_response(Value::Null, RpcError::new(PARSE_ERROR, "parse error")),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let (Some("2.0"), Some(name)) = (
+        request.get("jsonrpc").and_then(Value::as_str),
+        request.get("method").and_then(Value::as_str),
+    ) else {
+        return rpc::error_response(id, RpcError::new(INVALID_REQUEST, "invalid request"));
+    };
+    let Some(topic) = request
+        .get("params")
+        .and_then(|params| params.get(0))
+        .and_then(Value::as_str)
+        .and_then(|topic| topic.parse::<Topic>().ok())
+    else {
+        let message = "expected new_blocks, reorgs or address:<account>";
+        return rpc::error_response(id, RpcError::new(INVALID_PARAMS, message));
+    };
+
+    let result = match name {
+        "subscribe" => {
+            if !topics.contains(&topic) {
+                topics.push(topic);
+            }
+            true
+        }
+        // whether the connection was subscribed
+        "unsubscribe" => {
+            let before = topics.len();
+            topics.retain(|subscribed| *subscribed != topic);
+            topics.len() < before
+        }
+        _ => return rpc::error_response(id, RpcError::new(METHOD_NOT_FOUND, "method not found")),
+    };
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// The notifications of the event for a connection subscribed to the topics, in the order they are sent
+fn notifications(event: &ChainEvent, topics: &[Topic]) -> Vec<Value> {
+    let mut notifications = Vec::new();
+    for topic in topics {
+        match (topic, event) {
+            (Topic::NewBlocks, ChainEvent::BlockConnected(block)) => {
+                notifications.push(notification(topic, rpc::header_to_json(block)));
+            }
+            (
+                Topic::Reorgs,
+                ChainEvent::Reorg {
+                    old_tip,
+                    new_tip,
+                    depth,
+                },
+            ) => {
+                let result = json!({
+                    "old_tip": ptr_to_json(old_tip),
+                    "new_tip": ptr_to_json(new_tip),
+                    "depth": depth,
+                });
+                notifications.push(notification(topic, result));
+            }
+            (Topic::Address(account), ChainEvent::BlockConnected(block)) => {
+                notifications.extend(payments(topic, account, block, "confirmed"));
+            }
+            (Topic::Address(account), ChainEvent::BlockDisconnected(block)) => {
+                notifications.extend(payments(topic, account, block, "unconfirmed"));
+            }
+            _ => (),
+        }
+    }
+    notifications
+}
+
+// the transactions of the block from or to the account
+fn payments<'a>(
+    topic: &'a Topic,
+    account: &'a AccountId,
+    block: &'a Block,
+    status: &'a str,
+) -> impl Iterator<Item = Value> + 'a {
+    block
+        .transactions()
+        .iter()
+        .filter(|t| t.from_id() == *account || t.outputs().iter().any(|(to, _)| AccountId::from(to) == *account))
+        .map(move |t| {
+            let result = json!({
+                "status": status,
+                "block": hex::encode(block.hash),
+                "depth": block.depth(),
+                "transaction": rpc::transaction_to_json(t),
+            });
+            notification(topic, result)
+        })
+}
+
+fn notification(topic: &Topic, result: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": { "topic": topic.to_string(), "result": result },
+    })
+}
+
+fn ptr_to_json((hash, depth): &BlockPtr) -> Value {
+    json!({ "hash": hex::encode(hash), "depth": depth })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use num_bigint::BigUint;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    use super::*;
+    use crate::{
+        blockchain::Blockchain, clock::ManualClock, draw::DrawCache, keys::SecretKey, params::ChainParams,
+        transaction::Transaction,
+    };
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    // every draw wins, so a block is produced in every timeslot
+    fn produce(blockchain: &mut Blockchain, clock: &ManualClock, sk: &SecretKey) -> Block {
+        let mut draws = DrawCache::default();
+        loop {
+            clock.advance_slots(1);
+            if let Some(block) = blockchain.try_produce(sk, &mut draws) {
+                assert_eq!(blockchain.add_block(block.clone()), Ok(()));
+                return block;
+            }
+        }
+    }
+
+    async fn next(ws: &mut Client) -> Value {
+        loop {
+            let message = timeout(Duration::from_secs(10), ws.next())
+                .await
+                .expect("no message arrived")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    async fn request(ws: &mut Client, id: usize, method: &str, topic: &str) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": [topic] });
+        ws.send(Message::Text(request.to_string())).await.unwrap();
+        next(ws).await
+    }
+
+    #[tokio::test]
+    async fn subscribers_follow_blocks_reorgs_and_payments_in_order() {
+        let (sk, pk) = crate::generate_keypair();
+        let (_, bob) = crate::generate_keypair();
+        let clock = ManualClock::default();
+        let params = ChainParams {
+            hardness: BigUint::from(0u8),
+            ..Default::default()
+        };
+        let mut blockchain = Blockchain::start_with_clock(vec![pk.clone()], params, Arc::new(clock.clone()));
+        let mut fork = blockchain.clone();
+        let mut chain_events = blockchain.subscribe();
+
+        let events = crate::events::channel();
+        let addr = serve("127.0.0.1:0".parse().unwrap(), events.clone()).await.unwrap();
+        let (mut ws, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+        let bob_topic = Topic::Address(AccountId::from(&bob));
+        let topics = [Topic::NewBlocks, Topic::Reorgs, bob_topic.clone()];
+        for (id, topic) in topics.iter().enumerate() {
+            let subscribed = request(&mut ws, id, "subscribe", &topic.to_string()).await;
+            assert_eq!(subscribed, json!({ "jsonrpc": "2.0", "id": id, "result": true }));
+        }
+        let unknown = request(&mut ws, 3, "subscribe", "everything").await;
+        assert_eq!(unknown["error"]["code"], INVALID_PARAMS);
+
+        // two blocks, bob is paid in the second
+        let first = produce(&mut blockchain, &clock, &sk);
+        let payment = Transaction::new(pk.clone(), bob.clone(), &sk, 30, 0).unwrap();
+        assert_eq!(blockchain.add_transaction(payment.clone()), Ok(false));
+        let second = produce(&mut blockchain, &clock, &sk);
+        assert_eq!(second.transactions().len(), 1);
+
+        // a longer branch without the payment takes over, at its second block if the branch is heavier by then
+        let forked: Vec<_> = (0..3).map(|_| produce(&mut fork, &clock, &sk)).collect();
+        let heavier = fork.weight_of(&(forked[1].hash, 2)) > blockchain.weight_of(&(second.hash, 2));
+        for block in forked.iter() {
+            let _ = blockchain.add_block(block.clone());
+        }
+        assert_eq!(blockchain.tip(), fork.tip());
+        while let Ok(event) = chain_events.try_recv() {
+            events.send(event).unwrap();
+        }
+
+        let header = |block: &Block| notification(&Topic::NewBlocks, rpc::header_to_json(block));
+        let paid = |status: &str| {
+            let result = json!({
+                "status": status,
+                "block": hex::encode(second.hash),
+                "depth": 2,
+                "transaction": rpc::transaction_to_json(&payment),
+            });
+            notification(&bob_topic, result)
+        };
+        let switched = if heavier { 1 } else { 2 };
+        // the branches part at the genesis block, so both blocks of the old one are rolled back
+        let reorg = json!({
+            "old_tip": ptr_to_json(&(second.hash, 2)),
+            "new_tip": ptr_to_json(&(forked[switched].hash, switched as u64 + 1)),
+            "depth": 2,
+        });
+        let mut expected = vec![header(&first), header(&second), paid("confirmed"), paid("unconfirmed")];
+        expected.extend(forked[..=switched].iter().map(header));
+        expected.push(notification(&Topic::Reorgs, reorg));
+        expected.extend(forked[switched + 1..].iter().map(header));
+        for expected in expected {
+            assert_eq!(next(&mut ws).await, expected);
+        }
+
+        assert_eq!(request(&mut ws, 4, "unsubscribe", "new_blocks").await["result"], true);
+        assert_eq!(request(&mut ws, 5, "unsubscribe", "new_blocks").await["result"], false);
+    }
+}
@@ -0,0 +1,281 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use lasagna::{
+    account::AccountId,
+    client::{ClientActor, NodeConfig, DEV_KEY_FILE},
+    generate_keypair, keystore,
+    ledger::TxValidationError,
+    node::NodeBuilder,
+    rpc,
+    transaction::{Transaction, UnsignedTransaction},
+    wallet, Timeslot, ROOT_AMOUNT, TRANSACTION_FEE,
+};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn call(http: &reqwest::Client, addr: SocketAddr, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    http.post(format!("http://{addr}"))
+        .json(&request)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+// built without the key, signed offline and submitted as text, like the build-tx, sign-tx and broadcast-tx commands
+#[tokio::test(flavor = "multi_thread")]
+async fn offline_signed_transactions_are_accepted() {
+    let (sk, pk) = generate_keypair();
+    let (_, receiver) = generate_keypair();
+    let client = ClientActor::start_root(
+        "127.0.0.1:9512".parse().unwrap(),
+        vec![pk.clone()],
+        sk.clone(),
+        NodeConfig::default(),
+    )
+    .await;
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), client.sender().clone())
+        .await
+        .unwrap();
+
+    let built = UnsignedTransaction::new(pk, vec![(receiver, 10)], 1, 0, Timeslot::MAX).unwrap().to_hex();
+    let signed = UnsignedTransaction::from_hex(&built).unwrap().sign(&sk).unwrap().to_base64();
+    let signed = Transaction::from_base64(&signed).unwrap();
+    assert!(signed.verify_signature());
+
+    let sent = rpc::call(addr, "send_raw_transaction", json!([signed.to_hex()])).await.unwrap();
+    assert_eq!(sent, Ok(json!(hex::encode(signed.hash()))));
+    let resent = rpc::call(addr, "send_raw_transaction", json!([signed.to_hex()])).await.unwrap();
+    assert_eq!(resent.map_err(|e| e.code), Err(-32000));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_transactions_say_why() {
+    let (sk, pk) = generate_keypair();
+    let (_, receiver) = generate_keypair();
+    let client = ClientActor::start_root(
+        "127.0.0.1:9567".parse().unwrap(),
+        vec![pk.clone()],
+        sk.clone(),
+        NodeConfig::default(),
+    )
+    .await;
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), client.sender().clone())
+        .await
+        .unwrap();
+
+    let amount = u64::MAX / 2;
+    let transaction = Transaction::new(pk, receiver, &sk, amount, 0).unwrap();
+    let refused = rpc::call(addr, "send_raw_transaction", json!([transaction.to_hex()]))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(refused.code, rpc::REJECTED_TRANSACTION);
+    let data = refused.data.clone().unwrap();
+    assert_eq!(data["reason"], "insufficient_funds");
+    assert_eq!(data["cost"], amount + TRANSACTION_FEE);
+    assert!(matches!(
+        refused.transaction_error(),
+        Some(TxValidationError::InsufficientFunds { spendable, .. }) if spendable >= ROOT_AMOUNT
+    ));
+    assert!(wallet::explain(&refused.transaction_error().unwrap()).contains("balance"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rpc_endpoints() {
+    let (sk, pk) = generate_keypair();
+    let (_, receiver) = generate_keypair();
+    let client = ClientActor::start_root(
+        "127.0.0.1:9511".parse().unwrap(),
+        vec![pk.clone()],
+        sk.clone(),
+        NodeConfig::default(),
+    )
+    .await;
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), client.sender().clone())
+        .await
+        .unwrap();
+    let http = reqwest::Client::new();
+
+    let tip = call(&http, addr, "get_tip", json!([])).await["result"].clone();
+    assert!(tip["depth"].as_u64().is_some());
+
+    let genesis = call(&http, addr, "get_block_by_depth", json!([0])).await["result"].clone();
+    assert_eq!(genesis["depth"], 0);
+    let by_hash = call(&http, addr, "get_block_by_hash", json!([genesis["hash"]])).await;
+    assert_eq!(by_hash["result"], genesis);
+    let missing = call(
+        &http,
+        addr,
+        "get_block_by_hash",
+        json!([hex::encode([0; 32])]),
+    )
+    .await;
+    assert_eq!(missing["result"], Value::Null);
+
+    let pem = pk.to_pem().unwrap();
+    let balance = call(&http, addr, "get_balance", json!([pem])).await["result"].clone();
+    assert!(balance.as_u64().unwrap() >= ROOT_AMOUNT);
+    let address = AccountId::from(&pk).to_string();
+    let balance = call(&http, addr, "get_balance", json!([address])).await["result"].clone();
+    assert!(balance.as_u64().unwrap() >= ROOT_AMOUNT);
+
+    let nonce = call(&http, addr, "get_nonce", json!([address])).await["result"].clone();
+    assert_eq!(nonce, 0);
+    let transaction = Transaction::new(pk.clone(), receiver, &sk, 10, 0).unwrap();
+    let raw = hex::encode(transaction.to_bytes());
+    let hash = hex::encode(transaction.hash());
+    let sent = call(&http, addr, "send_raw_transaction", json!([raw])).await;
+    assert_eq!(sent["result"], json!(hash));
+    let nonce = call(&http, addr, "get_nonce", json!([address])).await["result"].clone();
+    assert_eq!(nonce, 1);
+
+    // the node may already have included the transaction in a block
+    let mempool = call(&http, addr, "get_mempool", json!([])).await["result"].clone();
+    let tip = call(&http, addr, "get_tip", json!([])).await["result"].clone();
+    let in_mempool = mempool
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|t| t["hash"] == hash);
+    assert!(in_mempool || tip["depth"].as_u64().unwrap() > 0);
+
+    let resent = call(&http, addr, "send_raw_transaction", json!([raw])).await;
+    assert_eq!(resent["error"]["code"], -32000);
+
+    let dump = call(&http, addr, "dump", json!([])).await["result"].clone();
+    assert_eq!(dump["genesis"], dump["best_path"][0]["hash"]);
+    let dot = call(&http, addr, "dump", json!(["dot"])).await["result"].clone();
+    assert!(dot.as_str().unwrap().starts_with("digraph forks {"));
+
+    let stats = call(&http, addr, "get_stats", json!([])).await["result"].clone();
+    assert_eq!(stats["total_blocks"].as_u64(), Some(stats["best_height"].as_u64().unwrap() + 1));
+    assert_eq!(stats["forks"], 1);
+
+    let unknown = call(&http, addr, "get_everything", json!([])).await;
+    assert_eq!(unknown["error"]["code"], -32601);
+    // only a dev node mines on demand
+    let not_dev = call(&http, addr, "dev_mine", json!([1])).await;
+    assert_eq!(not_dev["error"]["code"], -32601);
+
+    let invalid = call(&http, addr, "get_block_by_depth", json!(["zero"])).await;
+    assert_eq!(invalid["error"]["code"], -32602);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn status_reports_the_running_node() {
+    let (sk, pk) = generate_keypair();
+    let client = ClientActor::start_root(
+        "127.0.0.1:9561".parse().unwrap(),
+        vec![pk.clone()],
+        sk,
+        NodeConfig::default(),
+    )
+    .await;
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), client.sender().clone())
+        .await
+        .unwrap();
+    let http = reqwest::Client::new();
+
+    let status = call(&http, addr, "get_status", json!([])).await["result"].clone();
+    assert!(status["uptime_secs"].is_u64());
+    assert!(status["best_depth"].is_u64());
+    assert_eq!(status["best_hash"].as_str().unwrap().len(), 64);
+    assert!(status["timeslot"].is_u64());
+    assert_eq!(status["sync"]["state"], "synced");
+    assert!(status["sync"]["progress"].is_null());
+    assert_eq!(status["peers"], 0);
+    assert!(status["mempool_size"].is_u64());
+    assert!(status["orphans"].is_u64());
+    assert_eq!(status["staking"], true);
+    assert_eq!(status["staking_address"], AccountId::from(&pk).to_string());
+
+    // the tip only moves forward, so the reported depth is never ahead of it
+    let tip = call(&http, addr, "get_tip", json!([])).await["result"].clone();
+    assert!(status["best_depth"].as_u64() <= tip["depth"].as_u64());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peers_are_imported_and_exported_as_a_list() {
+    let (sk, pk) = generate_keypair();
+    let client = ClientActor::start_root(
+        "127.0.0.1:9566".parse().unwrap(),
+        vec![pk],
+        sk,
+        NodeConfig::default(),
+    )
+    .await;
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), client.sender().clone())
+        .await
+        .unwrap();
+    let http = reqwest::Client::new();
+
+    let exported = call(&http, addr, "export_peers", json!([])).await;
+    assert_eq!(exported["result"], "");
+    let list = "# two friends\n127.0.0.1:7001\n\n127.0.0.1:7002\n";
+    let imported = call(&http, addr, "import_peers", json!([list])).await;
+    assert_eq!(imported["result"], 2);
+    // the node does not add itself
+    let imported = call(&http, addr, "import_peers", json!(["127.0.0.1:7001\n127.0.0.1:9566"])).await;
+    assert_eq!(imported["result"], 0);
+    let exported = call(&http, addr, "export_peers", json!([])).await;
+    assert_eq!(exported["result"], "127.0.0.1:7001\n127.0.0.1:7002\n");
+
+    let invalid = call(&http, addr, "import_peers", json!(["127.0.0.1:7003\nnot an address"])).await;
+    assert_eq!(invalid["error"]["code"], -32602);
+}
+
+async fn raw_request(addr: SocketAddr, request: &[u8]) -> String {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn oversized_requests_are_refused() {
+    let (client, _) = tokio::sync::mpsc::channel(1);
+    let addr = rpc::serve("127.0.0.1:0".parse().unwrap(), client)
+        .await
+        .unwrap();
+
+    let body = "POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n";
+    let response = raw_request(addr, body.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    // a head without an end is read up to its 16 KB and no further
+    let response = raw_request(addr, &[b'a'; 16 * 1024]).await;
+    assert!(response.starts_with("HTTP/1.1 431 "), "{response}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_dev_node_confirms_a_transaction_on_demand() {
+    let dir = std::env::temp_dir().join(format!("lasagna-dev-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let addr: SocketAddr = "127.0.0.1:9569".parse().unwrap();
+    let node = NodeBuilder::dev("127.0.0.1:9568".parse().unwrap(), &dir)
+        .unwrap()
+        .rpc(addr)
+        .start()
+        .await
+        .unwrap();
+    let sk = keystore::load(&dir.join(DEV_KEY_FILE), || unreachable!("the dev key is not encrypted")).unwrap();
+    let (_, receiver) = generate_keypair();
+
+    let transaction = Transaction::new(sk.to_public_key(), receiver.clone(), &sk, 10, 0).unwrap();
+    let sent = rpc::call(addr, "send_raw_transaction", json!([transaction.to_hex()])).await.unwrap();
+    assert_eq!(sent, Ok(json!(hex::encode(transaction.hash()))));
+    let started = Instant::now();
+    let mined = rpc::call(addr, "dev_mine", json!([1])).await.unwrap().unwrap();
+    assert_eq!(mined.as_array().unwrap().len(), 1);
+    // the node may have mined the transaction on its own before, it is confirmed either way
+    assert_eq!(node.get_balance(AccountId::from(&receiver)).await.unwrap(), 10);
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    node.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}